@@ -0,0 +1,121 @@
+//! Compares a new HTTP client per request (the old pattern) against the shared SSO portal client,
+//! over both HTTP/1.1 and HTTP/2.
+//!
+//! Run with `cargo bench --bench sso_client`.
+//! Local keep-alive HTTP servers stand in for the portal, so this only measures connection reuse;
+//! against the real portal, every new connection also pays for a TLS handshake.
+//! The portal negotiates HTTP/2 through ALPN, which needs TLS, so the HTTP/2 clients speak it to the
+//! cleartext server with prior knowledge instead.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use eyre::Result;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use oktaws::aws::sso::{http_client, http_client_builder};
+use oktaws::tls;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const REQUESTS: usize = 200;
+
+const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}";
+
+async fn respond(mut stream: TcpStream) {
+    let mut buf = [0; 4096];
+    let mut pending = Vec::new();
+
+    while let Ok(n) = stream.read(&mut buf).await {
+        if n == 0 {
+            return;
+        }
+        pending.extend_from_slice(&buf[..n]);
+
+        while let Some(end) = pending.windows(4).position(|w| w == b"\r\n\r\n") {
+            pending.drain(..end + 4);
+            if stream.write_all(RESPONSE).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn respond_http2(stream: TcpStream) {
+    let service =
+        service_fn(|_| async { Ok::<_, Infallible>(hyper::Response::new(String::from("{}"))) });
+
+    let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+        .serve_connection(TokioIo::new(stream), service)
+        .await;
+}
+
+async fn serve(listener: TcpListener, connections: Arc<AtomicUsize>, http2: bool) {
+    while let Ok((stream, _)) = listener.accept().await {
+        connections.fetch_add(1, Ordering::Relaxed);
+        if http2 {
+            tokio::spawn(respond_http2(stream));
+        } else {
+            tokio::spawn(respond(stream));
+        }
+    }
+}
+
+async fn run<F>(name: &str, url: &str, connections: &AtomicUsize, client: F) -> Result<()>
+where
+    F: Fn() -> reqwest::Client,
+{
+    let connections_before = connections.load(Ordering::Relaxed);
+    let start = Instant::now();
+
+    for _ in 0..REQUESTS {
+        client().get(url).send().await?.bytes().await?;
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{name:>18}: {REQUESTS} requests in {elapsed:>10.2?} ({:>8.2?}/request), {} connections",
+        elapsed / u32::try_from(REQUESTS)?,
+        connections.load(Ordering::Relaxed) - connections_before
+    );
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/", listener.local_addr()?);
+    tokio::spawn(serve(listener, connections.clone(), false));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let http2_url = format!("http://{}/", listener.local_addr()?);
+    tokio::spawn(serve(listener, connections.clone(), true));
+
+    run("HTTP/1.1 per-request", &url, &connections, || {
+        tls::client_builder()
+            .build()
+            .expect("Failed to build HTTP client")
+    })
+    .await?;
+    run("HTTP/1.1 shared", &url, &connections, || {
+        http_client().clone()
+    })
+    .await?;
+
+    let shared = http_client_builder().http2_prior_knowledge().build()?;
+    run("HTTP/2 per-request", &http2_url, &connections, || {
+        tls::client_builder()
+            .http2_prior_knowledge()
+            .build()
+            .expect("Failed to build HTTP client")
+    })
+    .await?;
+    run("HTTP/2 shared", &http2_url, &connections, || shared.clone()).await?;
+
+    Ok(())
+}
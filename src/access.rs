@@ -0,0 +1,254 @@
+//! Checking whether a role in an account can be obtained right now, for `oktaws can-i` (e.g. while onboarding).
+//!
+//! The check does no more than it must: it discovers the account (only the account, where it is named rather than
+//! given by ID), then fetches credentials for the role for as short a time as AWS allows, and throws them away.
+
+use crate::okta::applications::{AppLinkAccountRoleMapping, Scope};
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// Whether the role can be obtained, and why (not)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Verdict {
+    /// The organization the account was found through, if it was
+    pub organization: Option<String>,
+    pub account: String,
+    pub account_id: Option<String>,
+    pub role: String,
+    pub allowed: bool,
+    pub reason: String,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            if self.allowed { "yes" } else { "no" },
+            self.reason
+        )
+    }
+}
+
+impl Verdict {
+    /// The role in `mapping`'s account could be obtained through `organization`
+    #[must_use]
+    pub fn allowed(organization: &str, mapping: &AppLinkAccountRoleMapping, role: &str) -> Self {
+        Self {
+            organization: Some(organization.to_string()),
+            account: mapping.account_name.clone(),
+            account_id: mapping.account_id.clone(),
+            role: role.to_string(),
+            allowed: true,
+            reason: format!(
+                "{organization} issued credentials for {role} in {} (which were thrown away)",
+                describe(mapping)
+            ),
+        }
+    }
+
+    /// The role in `mapping`'s account could not be obtained through `organization`, for `reason`
+    #[must_use]
+    pub fn denied(
+        organization: &str,
+        mapping: &AppLinkAccountRoleMapping,
+        role: &str,
+        reason: String,
+    ) -> Self {
+        Self {
+            allowed: false,
+            reason,
+            ..Self::allowed(organization, mapping, role)
+        }
+    }
+
+    /// The first of `denials` (from each organization which has the account), giving every one's reason
+    #[must_use]
+    pub fn denied_everywhere(denials: Vec<Self>) -> Option<Self> {
+        let reason = denials
+            .iter()
+            .map(|denial| denial.reason.as_str())
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        denials
+            .into_iter()
+            .next()
+            .map(|first| Self { reason, ..first })
+    }
+
+    /// No account named (or with the ID) `account` could be found through `organizations`
+    #[must_use]
+    pub fn not_found(account: &str, role: &str, organizations: &[String]) -> Self {
+        Self {
+            organization: None,
+            account: account.to_string(),
+            account_id: None,
+            role: role.to_string(),
+            allowed: false,
+            reason: if organizations.is_empty() {
+                String::from("no organizations are configured to look in")
+            } else {
+                format!(
+                    "{account} is not among the accounts reachable through {}",
+                    organizations.join(", ")
+                )
+            },
+        }
+    }
+}
+
+/// An account's name, with its ID if it is known
+#[must_use]
+pub fn describe(mapping: &AppLinkAccountRoleMapping) -> String {
+    mapping.account_id.as_ref().map_or_else(
+        || mapping.account_name.clone(),
+        |id| format!("{} ({id})", mapping.account_name),
+    )
+}
+
+/// Whether `account` is an account ID, rather than a name
+fn is_account_id(account: &str) -> bool {
+    account.len() == 12 && account.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// The discovery needed to find `account`: only the account itself, when it is named
+#[must_use]
+pub fn scope(account: &str) -> Scope {
+    Scope {
+        filter: (!is_account_id(account))
+            .then(|| glob::Pattern::new(&glob::Pattern::escape(account)).ok())
+            .flatten(),
+        limit: None,
+    }
+}
+
+/// What the discovered accounts say about a role
+#[derive(Debug, PartialEq, Eq)]
+pub enum Lookup<'a> {
+    /// The account grants the role (named as the account names it)
+    Granted {
+        mapping: &'a AppLinkAccountRoleMapping,
+        role: &'a str,
+    },
+    /// The account was found, but does not grant the role
+    NotGranted(&'a AppLinkAccountRoleMapping),
+    /// The account was not found
+    NoAccount,
+}
+
+/// Find `role` in `account` (by name or ID) among `mappings`, in any of the applications the account is reached through.
+/// Roles are matched ignoring case, but exact matches come first
+#[must_use]
+pub fn lookup<'a>(
+    mappings: &'a [AppLinkAccountRoleMapping],
+    account: &str,
+    role: &str,
+) -> Lookup<'a> {
+    let accounts = mappings
+        .iter()
+        .filter(|mapping| {
+            mapping.account_name == account || mapping.account_id.as_deref() == Some(account)
+        })
+        .collect::<Vec<_>>();
+    let Some(first) = accounts.first().copied() else {
+        return Lookup::NoAccount;
+    };
+
+    let granted = |matches: &dyn Fn(&str) -> bool| {
+        accounts.iter().copied().find_map(|mapping| {
+            mapping
+                .role_names
+                .iter()
+                .find(|name| matches(name))
+                .map(|role| Lookup::Granted { mapping, role })
+        })
+    };
+
+    granted(&|name| name == role)
+        .or_else(|| granted(&|name| name.eq_ignore_ascii_case(role)))
+        .unwrap_or(Lookup::NotGranted(first))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::okta::applications::IntegrationType;
+
+    #[test]
+    fn looks_up_roles() {
+        let mappings = vec![
+            AppLinkAccountRoleMapping {
+                account_name: String::from("production"),
+                account_id: Some(String::from("123456789012")),
+                account_email: None,
+                account_description: None,
+                role_names: vec![String::from("Admin"), String::from("ReadOnly")],
+                application_name: String::from("AWS SSO"),
+                integration_type: IntegrationType::IdentityCenter,
+            },
+            AppLinkAccountRoleMapping {
+                account_name: String::from("production"),
+                account_id: Some(String::from("123456789012")),
+                account_email: None,
+                account_description: None,
+                role_names: vec![String::from("Deploy")],
+                application_name: String::from("Amazon Web Services"),
+                integration_type: IntegrationType::Federated,
+            },
+        ];
+
+        assert_eq!(
+            lookup(&mappings, "123456789012", "readonly"),
+            Lookup::Granted {
+                mapping: &mappings[0],
+                role: "ReadOnly"
+            }
+        );
+        assert_eq!(
+            lookup(&mappings, "production", "deploy"),
+            Lookup::Granted {
+                mapping: &mappings[1],
+                role: "Deploy"
+            }
+        );
+        assert_eq!(
+            lookup(&mappings, "production", "Billing"),
+            Lookup::NotGranted(&mappings[0])
+        );
+        assert_eq!(lookup(&mappings, "staging", "Admin"), Lookup::NoAccount);
+
+        assert!(scope("123456789012").is_everything());
+        assert!(scope("prod[1]").matches("prod[1]"));
+        assert!(!scope("prod[1]").matches("prod1"));
+
+        let verdict = Verdict::denied(
+            "mycompany",
+            &mappings[0],
+            "Billing",
+            String::from("production does not grant Billing"),
+        );
+        assert_eq!(verdict.to_string(), "no: production does not grant Billing");
+        assert_eq!(verdict.account_id.as_deref(), Some("123456789012"));
+
+        let everywhere = Verdict::denied_everywhere(vec![
+            verdict,
+            Verdict::denied(
+                "contractors",
+                &mappings[1],
+                "Billing",
+                String::from("production does not grant Billing through contractors"),
+            ),
+        ])
+        .unwrap();
+        assert_eq!(everywhere.organization.as_deref(), Some("mycompany"));
+        assert_eq!(
+            everywhere.reason,
+            "production does not grant Billing; production does not grant Billing through contractors"
+        );
+        assert_eq!(Verdict::denied_everywhere(Vec::new()), None);
+    }
+}
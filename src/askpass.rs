@@ -0,0 +1,157 @@
+//! External prompt programs, for collecting passwords and MFA codes when there is no terminal.
+//!
+//! This follows OpenSSH's `SSH_ASKPASS` protocol: the program is run with the prompt as its only argument,
+//! and whatever it prints (up to the first newline) is the answer; exiting non-zero cancels.
+//! With `ssh -R` socket forwarding or an X11/Wayland askpass, that lets oktaws inside an SSH session
+//! ask on the local machine's desktop instead.
+
+use crate::theme;
+
+use std::env::var as env_var;
+use std::io::{IsTerminal, stdin};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use dialoguer::Password;
+use eyre::{Result, eyre};
+use tracing::debug;
+
+static PROGRAM: OnceLock<Option<String>> = OnceLock::new();
+
+/// When `SSH_ASKPASS` is used, from `SSH_ASKPASS_REQUIRE` as in OpenSSH
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Require {
+    /// Only when there is no terminal (the default)
+    Prefer,
+    /// Even when there is a terminal
+    Force,
+    /// Never
+    Never,
+}
+
+impl Require {
+    fn from_env(value: Option<&str>) -> Self {
+        match value {
+            Some("force") => Self::Force,
+            Some("never") => Self::Never,
+            _ => Self::Prefer,
+        }
+    }
+}
+
+/// The prompt program to use: `requested` (from `--askpass` or `OKTAWS_ASKPASS`) always,
+/// otherwise `ssh_askpass` when there is no terminal, unless `SSH_ASKPASS_REQUIRE` says otherwise
+fn choose(
+    requested: Option<String>,
+    ssh_askpass: Option<String>,
+    require: Require,
+    terminal: bool,
+) -> Option<String> {
+    requested.or_else(|| {
+        ssh_askpass.filter(|program| {
+            !program.is_empty()
+                && match require {
+                    Require::Force => true,
+                    Require::Never => false,
+                    Require::Prefer => !terminal,
+                }
+        })
+    })
+}
+
+/// Decide which prompt program (if any) to use, and install it for the whole process.
+/// Only the first call has any effect.
+pub fn install(requested: Option<String>) {
+    let program = choose(
+        requested,
+        env_var("SSH_ASKPASS").ok(),
+        Require::from_env(env_var("SSH_ASKPASS_REQUIRE").ok().as_deref()),
+        stdin().is_terminal(),
+    );
+
+    if let Some(program) = &program {
+        debug!("Prompting for secrets with {program}");
+    }
+
+    let _ = PROGRAM.set(program);
+}
+
+/// Whether secrets can be prompted for, on the terminal or with a prompt program
+#[must_use]
+pub fn interactive() -> bool {
+    PROGRAM.get().is_some_and(Option::is_some) || stdin().is_terminal()
+}
+
+/// Ask for a secret (a password or MFA code), with the prompt program if there is one,
+/// or else on the terminal. This blocks, so callers run it off the reactor.
+///
+/// # Errors
+///
+/// Will return `Err` if the prompt is cancelled, or the prompt program cannot be run
+pub fn secret(prompt: &str) -> Result<String> {
+    match PROGRAM.get().and_then(Option::as_deref) {
+        Some(program) => run(program, prompt),
+        None => Password::with_theme(theme::prompt())
+            .with_prompt(prompt)
+            .interact()
+            .map_err(Into::into),
+    }
+}
+
+fn run(program: &str, prompt: &str) -> Result<String> {
+    let output = Command::new(program)
+        .arg(prompt)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| eyre!("Unable to run the askpass program {program} ({e})"))?;
+
+    if !output.status.success() {
+        return Err(eyre!("The prompt ({prompt}) was cancelled"));
+    }
+
+    let answer = String::from_utf8(output.stdout)
+        .map_err(|_| eyre!("The askpass program {program} printed something other than UTF-8"))?;
+
+    Ok(answer.lines().next().unwrap_or_default().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_askpass_only_without_a_terminal() {
+        let ssh_askpass = || Some(String::from("ssh-askpass"));
+
+        assert_eq!(
+            choose(None, ssh_askpass(), Require::Prefer, false).as_deref(),
+            Some("ssh-askpass")
+        );
+        assert_eq!(choose(None, ssh_askpass(), Require::Prefer, true), None);
+        assert_eq!(
+            choose(None, ssh_askpass(), Require::Force, true).as_deref(),
+            Some("ssh-askpass")
+        );
+        assert_eq!(choose(None, ssh_askpass(), Require::Never, false), None);
+        assert_eq!(
+            choose(
+                Some(String::from("zenity-pass")),
+                None,
+                Require::Never,
+                true
+            )
+            .as_deref(),
+            Some("zenity-pass")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_first_line_of_output() -> Result<()> {
+        assert_eq!(run("echo", "123456")?, "123456");
+        assert!(run("false", "Password").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use crate::aws::ini::Document;
+use crate::lock::FileLock;
+use crate::transaction::{Staged, write_atomically};
+
+use dirs;
+use eyre::{Result, eyre};
+use std::env::var as env_var;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::instrument;
+
+/// The AWS CLI config file (`~/.aws/config`).
+/// oktaws only writes the settings it manages, keeping everything else in the file as it was.
+#[derive(Debug)]
+pub struct Store {
+    path: PathBuf,
+    config_file: Document,
+    /// The settings upserted, as (profile, key, value)
+    upserted: Vec<(String, String, String)>,
+    /// The settings removed, as (profile, key)
+    removed: Vec<(String, String)>,
+}
+
+impl Store {
+    /// Load the AWS config file from `path`,
+    /// falling back on `AWS_CONFIG_FILE` or `~/.aws/config`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if no path can be determined,
+    /// or if the file exists but cannot be read.
+    #[instrument]
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match (path, env_var("AWS_CONFIG_FILE")) {
+            (Some(path), _) => PathBuf::from(path),
+            (_, Ok(path)) => PathBuf::from(path),
+            _ => dirs::home_dir().map_or_else(
+                || Err(eyre!("The environment variable HOME must be set.")),
+                |home_dir| Ok(home_dir.join(".aws").join("config")),
+            )?,
+        };
+
+        let config_file = Self::read(&path)?;
+
+        Ok(Self {
+            path,
+            config_file,
+            upserted: Vec::new(),
+            removed: Vec::new(),
+        })
+    }
+
+    fn read(path: &Path) -> Result<Document> {
+        if path.exists() {
+            Ok(Document::parse(&fs::read_to_string(path)?))
+        } else {
+            Ok(Document::default())
+        }
+    }
+
+    /// The section name for a profile; only `default` is not prefixed with `profile`
+    pub(crate) fn section(profile_name: &str) -> String {
+        if profile_name == "default" {
+            profile_name.to_string()
+        } else {
+            format!("profile {profile_name}")
+        }
+    }
+
+    /// A setting of `profile_name`, e.g. its `region`
+    #[must_use]
+    pub fn get(&self, profile_name: &str, key: &str) -> Option<&str> {
+        self.config_file.get(&Self::section(profile_name), key)
+    }
+
+    /// Have the AWS SDKs assume `role_arn` for `profile_name`, using the credentials in `source_profile`
+    pub fn upsert_source_profile(
+        &mut self,
+        profile_name: &str,
+        role_arn: &str,
+        source_profile: &str,
+    ) {
+        self.upsert_settings(
+            profile_name,
+            &[("role_arn", role_arn), ("source_profile", source_profile)],
+        );
+    }
+
+    /// Set `settings` (keys and values, e.g. `output = json`) for `profile_name`,
+    /// leaving its other settings as they are
+    pub fn upsert_settings(&mut self, profile_name: &str, settings: &[(&str, &str)]) {
+        let section = Self::section(profile_name);
+
+        for (key, value) in settings {
+            // Only changes are written, so that an unchanged file is left alone
+            if self.config_file.get(&section, key) == Some(*value) {
+                continue;
+            }
+
+            self.config_file.set(&section, key, value);
+            self.upserted.push((
+                profile_name.to_string(),
+                (*key).to_string(),
+                (*value).to_string(),
+            ));
+        }
+    }
+
+    /// Remove the settings `keys` (e.g. `output`) of `profile_name`, leaving its other settings as they are
+    pub fn remove_settings(&mut self, profile_name: &str, keys: &[&str]) {
+        let section = Self::section(profile_name);
+
+        for key in keys {
+            if !self.config_file.remove(&section, key) {
+                continue;
+            }
+
+            self.upserted
+                .retain(|(name, upserted, _)| name != profile_name || upserted != key);
+            self.removed
+                .push((profile_name.to_string(), (*key).to_string()));
+        }
+    }
+
+    /// Whether anything was changed, so that an unchanged file is left alone
+    fn is_changed(&self) -> bool {
+        !self.upserted.is_empty() || !self.removed.is_empty()
+    }
+
+    /// Write the config back to disk, if anything was changed.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the lock cannot be acquired,
+    /// or if the file (or its parent directories) cannot be read or written.
+    #[instrument(skip_all)]
+    pub fn save(&self) -> Result<()> {
+        if !self.is_changed() {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let _lock = FileLock::acquire(&self.path)?;
+
+        write_atomically(&self.path, self.merged()?.to_string().as_bytes())
+    }
+
+    /// The file on disk, with this store's upserts and removals applied
+    fn merged(&self) -> Result<Document> {
+        Ok(self.merged_into(Self::read(&self.path)?))
+    }
+
+    /// `config_file`, with this store's upserts and removals applied
+    fn merged_into(&self, mut config_file: Document) -> Document {
+        for (profile_name, key) in &self.removed {
+            config_file.remove(&Self::section(profile_name), key);
+        }
+        for (profile_name, key, value) in &self.upserted {
+            config_file.set(&Self::section(profile_name), key, value);
+        }
+
+        config_file
+    }
+}
+
+impl Staged for Store {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn render(&self) -> Result<Option<String>> {
+        if !self.is_changed() {
+            return Ok(None);
+        }
+
+        self.merged()
+            .map(|config_file| Some(config_file.to_string()))
+    }
+
+    fn render_over(&self, contents: &str) -> Result<Option<String>> {
+        if !self.is_changed() {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            self.merged_into(Document::parse(contents)).to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn writes_source_profile() -> Result<()> {
+        let mut tempfile = NamedTempFile::new()?;
+
+        write!(tempfile, "[profile prod]\nregion = us-west-2\n")?;
+
+        let mut store = Store::load(Some(tempfile.path()))?;
+        store.upsert_source_profile(
+            "prod",
+            "arn:aws:iam::123456789012:role/Admin",
+            "prod-source",
+        );
+        store.save()?;
+
+        assert_eq!(
+            fs::read_to_string(tempfile.path())?,
+            "[profile prod]\nregion = us-west-2\nrole_arn = arn:aws:iam::123456789012:role/Admin\nsource_profile = prod-source\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn writes_only_changed_settings() -> Result<()> {
+        let mut tempfile = NamedTempFile::new()?;
+
+        write!(tempfile, "[default]\noutput = json\n")?;
+
+        let mut store = Store::load(Some(tempfile.path()))?;
+        store.upsert_settings("default", &[("output", "json")]);
+        assert_eq!(store.render()?, None);
+
+        store.upsert_settings("default", &[("output", "table"), ("cli_pager", "")]);
+        store.save()?;
+
+        assert_eq!(
+            fs::read_to_string(tempfile.path())?,
+            "[default]\noutput = table\ncli_pager = \n"
+        );
+
+        let mut store = Store::load(Some(tempfile.path()))?;
+        store.remove_settings("default", &["cli_auto_prompt"]);
+        assert_eq!(store.render()?, None);
+
+        store.remove_settings("default", &["cli_pager"]);
+        store.save()?;
+
+        assert_eq!(
+            fs::read_to_string(tempfile.path())?,
+            "[default]\noutput = table\n"
+        );
+
+        Ok(())
+    }
+}
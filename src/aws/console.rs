@@ -0,0 +1,220 @@
+use crate::aws::partition::Partition;
+use crate::aws::sso::http_client;
+use crate::retry;
+
+use aws_arn::ResourceName as ARN;
+use aws_credential_types::Credentials;
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use url::Url;
+
+/// A console URL which switches the current browser session into `role`,
+/// rather than starting a new federated session, in the role's partition.
+/// `color` is an RGB hex colour (e.g. `F2B0A9`) for the role in the console navigation bar.
+///
+/// # Errors
+///
+/// Will return `Err` if the ARN has no account ID or role name,
+/// or if `color` is not a 6 digit hex colour.
+pub fn switch_role_url(role: &ARN, display_name: Option<&str>, color: Option<&str>) -> Result<Url> {
+    let account = role
+        .account_id
+        .as_ref()
+        .ok_or_else(|| eyre!("No account ID found in {role}"))?;
+    // The console finds roles by their path and name, e.g. `path/Admin`
+    let role_name = role
+        .resource
+        .to_string()
+        .strip_prefix("role/")
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string)
+        .ok_or_else(|| eyre!("No role name found in {role}"))?;
+
+    let signin_url = Partition::of(role)?.signin_url();
+    let mut url = Url::parse(&format!("{signin_url}/switchrole"))?;
+    url.query_pairs_mut()
+        .append_pair("account", &account.to_string())
+        .append_pair("roleName", &role_name);
+
+    if let Some(display_name) = display_name {
+        url.query_pairs_mut()
+            .append_pair("displayName", display_name);
+    }
+
+    if let Some(color) = color {
+        let color = color.trim_start_matches('#');
+        if color.len() != 6 || !color.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(eyre!("Console colour must be 6 hex digits, got {color}"));
+        }
+        url.query_pairs_mut().append_pair("color", color);
+    }
+
+    Ok(url)
+}
+
+#[derive(Serialize)]
+struct Session<'a> {
+    #[serde(rename = "sessionId")]
+    id: &'a str,
+    #[serde(rename = "sessionKey")]
+    key: &'a str,
+    #[serde(rename = "sessionToken")]
+    token: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SigninToken {
+    signin_token: String,
+}
+
+/// A console page to land on after signing in, instead of the console home
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Page {
+    /// A service's console (e.g. `cloudwatch`), or a page within the console (e.g. `cloudwatch/home#logsV2:log-groups`)
+    pub service: String,
+    /// The region to open the page in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+impl Page {
+    /// The page's URL, in `partition`'s console
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the service does not make a valid URL
+    pub fn url(&self, partition: Partition) -> Result<Url> {
+        let service = self.service.trim_start_matches('/');
+        let path = if service.contains(['/', '#', '?']) {
+            service.to_string()
+        } else {
+            format!("{service}/home")
+        };
+
+        let mut url = Url::parse(partition.console_url())?.join(&path)?;
+        if let Some(region) = &self.region {
+            url.query_pairs_mut().append_pair("region", region);
+        }
+
+        Ok(url)
+    }
+}
+
+/// The console sign-in URL for a federation sign-in token, landing on `page` (or the console home)
+fn login_url(signin_token: &str, partition: Partition, page: Option<&Page>) -> Result<Url> {
+    let destination = match page {
+        Some(page) => page.url(partition)?,
+        None => Url::parse(partition.console_url())?,
+    };
+
+    let mut url = Url::parse(&format!("{}/federation", partition.signin_url()))?;
+    url.query_pairs_mut()
+        .append_pair("Action", "login")
+        .append_pair("Issuer", "oktaws")
+        .append_pair("Destination", destination.as_str())
+        .append_pair("SigninToken", signin_token);
+
+    Ok(url)
+}
+
+/// A console URL which signs in to a new session with `credentials`, which must be from `partition`,
+/// landing on `page` (or the console home)
+///
+/// # Errors
+///
+/// Will return `Err` if the federation endpoint rejects the credentials
+#[instrument(skip_all)]
+pub async fn federation_url(
+    credentials: &Credentials,
+    partition: Partition,
+    page: Option<&Page>,
+) -> Result<Url> {
+    let session = serde_json::to_string(&Session {
+        id: credentials.access_key_id(),
+        key: credentials.secret_access_key(),
+        token: credentials.session_token(),
+    })?;
+
+    let SigninToken { signin_token } = retry::send(
+        http_client()
+            .get(format!("{}/federation", partition.signin_url()))
+            .query(&[("Action", "getSigninToken"), ("Session", &session)]),
+    )
+    .await?
+    .error_for_status()
+    .map_err(|e| eyre!("Unable to get a console sign-in token ({e})"))?
+    .json()
+    .await?;
+
+    login_url(&signin_token, partition, page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switch_role() -> Result<()> {
+        let role: ARN = "arn:aws:iam::123456789012:role/path/Admin".parse()?;
+
+        assert_eq!(
+            switch_role_url(&role, Some("Prod Admin"), Some("#F2B0A9"))?.as_str(),
+            "https://signin.aws.amazon.com/switchrole?account=123456789012&roleName=path%2FAdmin&displayName=Prod+Admin&color=F2B0A9"
+        );
+
+        let role: ARN = "arn:aws:iam::123456789012:role/Admin".parse()?;
+        assert_eq!(
+            switch_role_url(&role, None, None)?.as_str(),
+            "https://signin.aws.amazon.com/switchrole?account=123456789012&roleName=Admin"
+        );
+
+        assert_eq!(
+            switch_role_url(&role, None, Some("red"))
+                .unwrap_err()
+                .to_string(),
+            "Console colour must be 6 hex digits, got red"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn federation_login() -> Result<()> {
+        assert_eq!(
+            login_url("TOKEN", Partition::Aws, None)?.as_str(),
+            "https://signin.aws.amazon.com/federation?Action=login&Issuer=oktaws&Destination=https%3A%2F%2Fconsole.aws.amazon.com%2F&SigninToken=TOKEN"
+        );
+
+        assert_eq!(
+            login_url("TOKEN", Partition::AwsUsGov, None)?.as_str(),
+            "https://signin.amazonaws-us-gov.com/federation?Action=login&Issuer=oktaws&Destination=https%3A%2F%2Fconsole.amazonaws-us-gov.com%2F&SigninToken=TOKEN"
+        );
+
+        let logs = Page {
+            service: String::from("cloudwatch/home#logsV2:log-groups"),
+            region: Some(String::from("eu-west-1")),
+        };
+        assert_eq!(
+            logs.url(Partition::Aws)?.as_str(),
+            "https://console.aws.amazon.com/cloudwatch/home?region=eu-west-1#logsV2:log-groups"
+        );
+        assert_eq!(
+            login_url("TOKEN", Partition::Aws, Some(&logs))?.as_str(),
+            "https://signin.aws.amazon.com/federation?Action=login&Issuer=oktaws&Destination=https%3A%2F%2Fconsole.aws.amazon.com%2Fcloudwatch%2Fhome%3Fregion%3Deu-west-1%23logsV2%3Alog-groups&SigninToken=TOKEN"
+        );
+
+        let s3 = Page {
+            service: String::from("s3"),
+            region: None,
+        };
+        assert_eq!(
+            s3.url(Partition::AwsCn)?.as_str(),
+            "https://console.amazonaws.cn/s3/home"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+//! Credentials in the JSON format the AWS SDKs expect from the ECS container credentials endpoint.
+//!
+//! See <https://docs.aws.amazon.com/sdkref/latest/guide/feature-container-credentials.html>
+
+use aws_credential_types::Credentials;
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
+use eyre::Result;
+use serde::Serialize;
+
+/// Credentials in the format served by the container credentials endpoint
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerCredentials<'a> {
+    access_key_id: &'a str,
+    secret_access_key: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<&'a str>,
+    /// Without an expiration, the SDKs treat the credentials as long-lived, and never ask again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration: Option<String>,
+}
+
+impl<'a> ContainerCredentials<'a> {
+    /// # Errors
+    ///
+    /// Will return `Err` if the expiry cannot be formatted as an RFC 3339 timestamp
+    pub fn new(credentials: &'a Credentials) -> Result<Self> {
+        Ok(Self {
+            access_key_id: credentials.access_key_id(),
+            secret_access_key: credentials.secret_access_key(),
+            token: credentials.session_token(),
+            expiration: credentials
+                .expiry()
+                .map(|expiry| DateTime::from(expiry).fmt(Format::DateTime))
+                .transpose()?,
+        })
+    }
+}
+
+/// An error, in the shape the endpoint reports them
+#[derive(Serialize, Debug)]
+pub struct ContainerError<'a> {
+    pub code: &'a str,
+    pub message: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn names_the_session_token_token() -> Result<()> {
+        let credentials = Credentials::new(
+            "ACCESS_KEY",
+            "SECRET_KEY",
+            Some(String::from("SESSION_TOKEN")),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            "test",
+        );
+
+        assert_eq!(
+            serde_json::to_string(&ContainerCredentials::new(&credentials)?)?,
+            r#"{"AccessKeyId":"ACCESS_KEY","SecretAccessKey":"SECRET_KEY","Token":"SESSION_TOKEN","Expiration":"2023-11-14T22:13:20Z"}"#
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! The HTTP client the AWS SDK clients send their requests with.
+//!
+//! The SDK's own client knows nothing of `settings.toml`'s `[network]` section (or `--ca-bundle` and `--insecure`),
+//! so behind a TLS-intercepting proxy STS, SSO and the OIDC device flow would fail where Okta succeeds.
+//! Instead, each request is sent through an HTTP client from [`tls::client_builder`], with the same proxy and roots.
+
+use crate::tls;
+
+use aws_smithy_runtime_api::client::http::{
+    HttpClient, HttpConnector, HttpConnectorFuture, HttpConnectorSettings, SharedHttpClient,
+    SharedHttpConnector,
+};
+use aws_smithy_runtime_api::client::orchestrator::{HttpRequest, HttpResponse};
+use aws_smithy_runtime_api::client::result::ConnectorError;
+use aws_smithy_runtime_api::client::runtime_components::RuntimeComponents;
+use aws_smithy_types::body::SdkBody;
+use eyre::eyre;
+
+/// Sends the SDK's requests with an oktaws HTTP client
+#[derive(Clone, Debug)]
+struct Client(reqwest::Client);
+
+impl Client {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, ConnectorError> {
+        let (parts, body) = request
+            .try_into_http1x()
+            .map_err(|e| ConnectorError::user(e.into()))?
+            .into_parts();
+        // The STS, SSO and OIDC APIs only send bodies which are already in memory
+        let body = body
+            .bytes()
+            .ok_or_else(|| ConnectorError::user(eyre!("Unable to send a streaming body").into()))?
+            .to_vec();
+
+        let request = reqwest::Request::try_from(http::Request::from_parts(parts, body))
+            .map_err(|e| ConnectorError::user(e.into()))?;
+
+        let response = self.0.execute(request).await.map_err(|e| {
+            if e.is_timeout() {
+                ConnectorError::timeout(e.into())
+            } else if e.is_connect() {
+                ConnectorError::io(e.into())
+            } else {
+                ConnectorError::other(e.into(), None)
+            }
+        })?;
+
+        let mut builder = http::Response::builder().status(response.status());
+        for (name, value) in response.headers() {
+            builder = builder.header(name, value);
+        }
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| ConnectorError::io(e.into()))?;
+
+        builder
+            .body(SdkBody::from(body))
+            .map_err(|e| ConnectorError::other(e.into(), None))
+            .and_then(|response| {
+                HttpResponse::try_from(response).map_err(|e| ConnectorError::other(e.into(), None))
+            })
+    }
+}
+
+impl HttpConnector for Client {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let client = self.clone();
+        HttpConnectorFuture::new(async move { client.send(request).await })
+    }
+}
+
+impl HttpClient for Client {
+    fn http_connector(
+        &self,
+        _settings: &HttpConnectorSettings,
+        _components: &RuntimeComponents,
+    ) -> SharedHttpConnector {
+        SharedHttpConnector::new(self.clone())
+    }
+}
+
+/// An HTTP client for the AWS SDK clients, with the installed proxy, CA bundle and verification settings
+///
+/// # Panics
+///
+/// Will panic if the TLS backend cannot be initialized, as building any other oktaws HTTP client would
+#[must_use]
+pub fn client() -> SharedHttpClient {
+    SharedHttpClient::new(Client(
+        tls::client_builder()
+            .build()
+            .expect("Unable to initialize the TLS backend"),
+    ))
+}
@@ -0,0 +1,532 @@
+//! IAM Identity Center, through its OIDC device authorization flow.
+//!
+//! Organizations which have moved to Identity Center may no longer expose the legacy portal endpoints
+//! in `aws::sso`. Instead, oktaws registers itself as a public OIDC client, has the user approve a device code
+//! in their browser (signing in through Okta there), and uses the resulting access token with the SSO API.
+//! The registration and token are cached, so approval is only needed when the token expires:
+//! the client secret and token with the other secrets (see [`crate::secrets`]), and only when they expire in a file.
+
+use crate::aws::{self, Identity, http};
+use crate::blocking;
+use crate::browser;
+use crate::lock::FileLock;
+use crate::request_id;
+use crate::secrets::{self, Secret};
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aws_credential_types::Credentials;
+use aws_sdk_sso::Client as SsoClient;
+use aws_sdk_sso::config::{Config as SsoConfig, Region as SsoRegion};
+use aws_sdk_ssooidc::Client as OidcClient;
+use aws_sdk_ssooidc::config::{Config as OidcConfig, Region as OidcRegion};
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+use tokio::time::sleep;
+use tracing::{debug, instrument};
+use url::Url;
+
+/// The directory (in the oktaws cache directory) client registrations and tokens are kept in
+pub const DIR: &str = "identity-center";
+
+const CLIENT_NAME: &str = "oktaws";
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Tokens this close to expiring are not reused, so that a refresh doesn't fail part way through
+const MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// How long other refreshes wait for the user to approve a device code before giving up, unless configured
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Where an organization's Identity Center is, from its `[identity_center]` config
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The AWS access portal URL, e.g. `https://d-1234567890.awsapps.com/start`
+    pub start_url: String,
+    /// The region Identity Center is enabled in
+    pub region: String,
+    /// How long other refreshes wait for the user to approve a device code, before giving up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval_timeout_seconds: Option<u64>,
+}
+
+impl Config {
+    /// The secrets the registration and token are kept in, for `oktaws lock` to forget
+    #[must_use]
+    pub fn secrets(&self) -> Vec<Secret> {
+        let name = self.cache_name();
+        [CLIENT_SECRET, ACCESS_TOKEN]
+            .into_iter()
+            .map(|kind| Secret::identity_center(&name, kind))
+            .collect()
+    }
+
+    /// How long other refreshes wait for the user to approve a device code
+    #[must_use]
+    pub fn approval_timeout(&self) -> Duration {
+        self.approval_timeout_seconds
+            .map_or(APPROVAL_TIMEOUT, Duration::from_secs)
+    }
+
+    /// The name the registration and token are cached under: the start URL's host,
+    /// so that organizations sharing an Identity Center share a sign-in
+    fn cache_name(&self) -> String {
+        self.start_url
+            .trim_start_matches("https://")
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .replace(
+                |c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.',
+                "_",
+            )
+    }
+}
+
+/// The secret store names of the client secret and the access token
+const CLIENT_SECRET: &str = "client_secret";
+const ACCESS_TOKEN: &str = "access_token";
+
+/// The client registration and token, as cached in the file: their secrets are kept in the secret store
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+struct Cached {
+    client_id: Option<String>,
+    client_expires_at: u64,
+    token_expires_at: u64,
+}
+
+/// The client secret and access token, from the secret store
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Secrets {
+    client_secret: Option<String>,
+    access_token: Option<String>,
+}
+
+/// An account the user has been assigned to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Account {
+    pub id: String,
+    pub name: String,
+    pub email: Option<String>,
+}
+
+/// A client for the SSO API, signed in with an Identity Center access token
+pub struct Client {
+    sso: SsoClient,
+    token: String,
+}
+
+impl Client {
+    /// Sign in to Identity Center, with the cached token if it is still valid,
+    /// or else by asking the user to approve a device code in their browser.
+    /// Simultaneous sign-ins to one Identity Center wait for the first, so the user is only asked once.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the client cannot be registered, the device code is not approved in time,
+    /// or the cache cannot be locked
+    #[instrument(skip(config, dir), fields(start_url=%config.start_url))]
+    pub async fn sign_in(config: &Config, dir: &Path) -> Result<Self> {
+        let name = config.cache_name();
+        let path = dir.join(format!("{name}.json"));
+
+        let lock_path = path.clone();
+        let timeout = config.approval_timeout();
+        let _lock = spawn_blocking(move || FileLock::acquire_with_timeout(&lock_path, timeout))
+            .await
+            .map_err(|e| eyre!("Lock task failed ({e})"))??;
+
+        let (mut cached, mut secrets) = {
+            let (path, name) = (path.clone(), name.clone());
+            blocking::run(move || Ok(read(&path, &name)))
+                .await?
+                .unwrap_or_default()
+        };
+        let now = epoch_seconds(SystemTime::now());
+
+        let token = match &secrets.access_token {
+            Some(token) if cached.token_expires_at > now + MARGIN.as_secs() => {
+                debug!("Reusing the cached Identity Center token");
+                token.clone()
+            }
+            _ => {
+                let oidc = OidcClient::from_conf(
+                    OidcConfig::builder()
+                        .region(OidcRegion::new(config.region.clone()))
+                        .http_client(http::client())
+                        .behavior_version_latest()
+                        .build(),
+                );
+
+                let (client_id, client_secret) =
+                    register(&oidc, &mut cached, &mut secrets, now).await?;
+                let (token, expires_in) =
+                    authorize(&oidc, &config.start_url, &client_id, &client_secret).await?;
+
+                secrets.access_token = Some(token.clone());
+                cached.token_expires_at = epoch_seconds(SystemTime::now()) + expires_in;
+                let written = {
+                    let (cached, secrets) = (cached.clone(), secrets.clone());
+                    blocking::run(move || write(&path, &name, &cached, &secrets)).await
+                };
+                if let Err(e) = written {
+                    debug!("Unable to cache the Identity Center token: {e}");
+                }
+
+                token
+            }
+        };
+
+        Ok(Self {
+            sso: SsoClient::from_conf(
+                SsoConfig::builder()
+                    .region(SsoRegion::new(config.region.clone()))
+                    .http_client(http::client())
+                    .behavior_version_latest()
+                    .build(),
+            ),
+            token,
+        })
+    }
+
+    /// The accounts the user is assigned to
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the accounts cannot be listed
+    pub async fn accounts(&self) -> Result<Vec<Account>> {
+        let pages = self
+            .sso
+            .list_accounts()
+            .access_token(&self.token)
+            .into_paginator()
+            .send()
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut accounts = Vec::new();
+        for page in pages {
+            request_id::observe_aws(&page);
+            accounts.extend(page?.account_list.unwrap_or_default());
+        }
+
+        Ok(accounts
+            .into_iter()
+            .filter_map(|account| {
+                Some(Account {
+                    id: account.account_id?,
+                    name: account.account_name?,
+                    email: account.email_address,
+                })
+            })
+            .collect())
+    }
+
+    /// The roles the user can use in an account
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the roles cannot be listed
+    pub async fn roles(&self, account_id: &str) -> Result<Vec<String>> {
+        let pages = self
+            .sso
+            .list_account_roles()
+            .access_token(&self.token)
+            .account_id(account_id)
+            .into_paginator()
+            .send()
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut roles = Vec::new();
+        for page in pages {
+            request_id::observe_aws(&page);
+            roles.extend(page?.role_list.unwrap_or_default());
+        }
+
+        Ok(roles
+            .into_iter()
+            .filter_map(|role| role.role_name)
+            .collect())
+    }
+
+    /// Fetch credentials for a role in an account.
+    /// Identity Center always issues them for the permission set's session duration.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the credentials cannot be fetched
+    pub async fn credentials(&self, account_id: &str, role_name: &str) -> Result<Credentials> {
+        debug!("Requesting credentials for account: {account_id}, role: {role_name}");
+
+        let output = self
+            .sso
+            .get_role_credentials()
+            .access_token(&self.token)
+            .account_id(account_id)
+            .role_name(role_name)
+            .send()
+            .await;
+        request_id::observe_aws(&output);
+        let output = output?;
+
+        let credentials = output
+            .role_credentials
+            .ok_or_else(|| eyre!("No credentials returned for {role_name} in {account_id}"))?;
+
+        let credentials = Credentials::new(
+            credentials
+                .access_key_id
+                .ok_or_else(|| eyre!("No access key returned for {role_name}"))?,
+            credentials
+                .secret_access_key
+                .ok_or_else(|| eyre!("No secret key returned for {role_name}"))?,
+            credentials.session_token,
+            u64::try_from(credentials.expiration)
+                .ok()
+                .map(|expiration| UNIX_EPOCH + Duration::from_millis(expiration)),
+            "oktaws",
+        );
+        aws::issued(
+            &credentials,
+            Identity {
+                account_id: account_id.to_string(),
+                arn: None,
+            },
+        );
+        Ok(credentials)
+    }
+}
+
+/// The cached client registration, or a new one if it has expired
+async fn register(
+    oidc: &OidcClient,
+    cached: &mut Cached,
+    secrets: &mut Secrets,
+    now: u64,
+) -> Result<(String, String)> {
+    if let (Some(client_id), Some(client_secret)) = (&cached.client_id, &secrets.client_secret) {
+        if cached.client_expires_at > now + MARGIN.as_secs() {
+            return Ok((client_id.clone(), client_secret.clone()));
+        }
+    }
+
+    debug!("Registering oktaws as an Identity Center client");
+    let registration = oidc
+        .register_client()
+        .client_name(CLIENT_NAME)
+        .client_type("public")
+        .send()
+        .await;
+    request_id::observe_aws(&registration);
+    let registration = registration?;
+
+    let client_id = registration
+        .client_id
+        .ok_or_else(|| eyre!("Identity Center did not return a client ID"))?;
+    let client_secret = registration
+        .client_secret
+        .ok_or_else(|| eyre!("Identity Center did not return a client secret"))?;
+
+    cached.client_id = Some(client_id.clone());
+    secrets.client_secret = Some(client_secret.clone());
+    cached.client_expires_at = u64::try_from(registration.client_secret_expires_at).unwrap_or(0);
+
+    Ok((client_id, client_secret))
+}
+
+/// Have the user approve a device code, returning the access token and how many seconds it lasts
+async fn authorize(
+    oidc: &OidcClient,
+    start_url: &str,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<(String, u64)> {
+    let authorization = oidc
+        .start_device_authorization()
+        .client_id(client_id)
+        .client_secret(client_secret)
+        .start_url(start_url)
+        .send()
+        .await;
+    request_id::observe_aws(&authorization);
+    let authorization = authorization?;
+
+    let device_code = authorization
+        .device_code
+        .ok_or_else(|| eyre!("Identity Center did not return a device code"))?;
+
+    let verification_url = Url::parse(
+        authorization
+            .verification_uri_complete
+            .as_deref()
+            .unwrap_or(start_url),
+    )?;
+    eprintln!(
+        "Check the code shown when signing in to IAM Identity Center is {}",
+        authorization.user_code.as_deref().unwrap_or("shown")
+    );
+    browser::show(&verification_url, "sign in to IAM Identity Center");
+
+    let mut interval =
+        Duration::from_secs(u64::try_from(authorization.interval).unwrap_or(5).max(1));
+    let deadline = SystemTime::now()
+        + Duration::from_secs(u64::try_from(authorization.expires_in).unwrap_or(600));
+
+    loop {
+        sleep(interval).await;
+
+        let token = oidc
+            .create_token()
+            .client_id(client_id)
+            .client_secret(client_secret)
+            .grant_type(DEVICE_CODE_GRANT)
+            .device_code(&device_code)
+            .send()
+            .await;
+        // Polling until the user has approved the code is expected to fail, so only the answer is kept
+        let waiting = token
+            .as_ref()
+            .err()
+            .and_then(|e| e.as_service_error())
+            .is_some_and(|e| e.is_authorization_pending_exception() || e.is_slow_down_exception());
+        if !waiting {
+            request_id::observe_aws(&token);
+        }
+
+        match token {
+            Ok(token) => {
+                let access_token = token
+                    .access_token
+                    .ok_or_else(|| eyre!("Identity Center did not return an access token"))?;
+                return Ok((access_token, u64::try_from(token.expires_in).unwrap_or(0)));
+            }
+            Err(e) => {
+                let e = e.into_service_error();
+                if e.is_slow_down_exception() {
+                    interval += Duration::from_secs(5);
+                } else if !e.is_authorization_pending_exception() {
+                    return Err(e.into());
+                }
+            }
+        }
+
+        if SystemTime::now() > deadline {
+            return Err(eyre!(
+                "The Identity Center device code was not approved in time"
+            ));
+        }
+    }
+}
+
+fn epoch_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Whether the client registration cached in `contents` has yet to expire, so is worth moving to a new machine
+pub(crate) fn unexpired(contents: &[u8]) -> bool {
+    serde_json::from_slice::<Cached>(contents)
+        .is_ok_and(|cached| cached.client_expires_at > epoch_seconds(SystemTime::now()))
+}
+
+/// The registration and token cached as `name`: the secrets from the secret store, and the rest from `path`
+fn read(path: &Path, name: &str) -> Option<(Cached, Secrets)> {
+    let cached = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    let secret = |kind| Secret::identity_center(name, kind).get().ok().flatten();
+
+    Some((
+        cached,
+        Secrets {
+            client_secret: secret(CLIENT_SECRET),
+            access_token: secret(ACCESS_TOKEN),
+        },
+    ))
+}
+
+/// Keep the registration and token as `name`: the secrets in the secret store (if secrets are kept at all),
+/// and the rest in `path`, readable only by the current user
+fn write(path: &Path, name: &str, cached: &Cached, secrets: &Secrets) -> Result<()> {
+    if secrets::kept() {
+        for (kind, value) in [
+            (CLIENT_SECRET, &secrets.client_secret),
+            (ACCESS_TOKEN, &secrets.access_token),
+        ] {
+            if let Some(value) = value {
+                Secret::identity_center(name, kind).set(value)?;
+            }
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    options
+        .open(path)?
+        .write_all(serde_json::to_string(cached)?.as_bytes())
+        .map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reuses_unexpired_registration() -> Result<()> {
+        let oidc = OidcClient::from_conf(
+            OidcConfig::builder()
+                .region(OidcRegion::new("us-east-1"))
+                .behavior_version_latest()
+                .build(),
+        );
+        let now = epoch_seconds(SystemTime::now());
+        let mut cached = Cached {
+            client_id: Some(String::from("CLIENT_ID")),
+            client_expires_at: now + 24 * 60 * 60,
+            ..Cached::default()
+        };
+        let mut secrets = Secrets {
+            client_secret: Some(String::from("CLIENT_SECRET")),
+            ..Secrets::default()
+        };
+
+        assert_eq!(
+            register(&oidc, &mut cached, &mut secrets, now).await?,
+            (String::from("CLIENT_ID"), String::from("CLIENT_SECRET"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cached_by_start_url_host() {
+        let config = Config {
+            start_url: String::from("https://d-1234567890.awsapps.com/start"),
+            region: String::from("eu-west-1"),
+            approval_timeout_seconds: None,
+        };
+
+        assert_eq!(config.cache_name(), "d-1234567890.awsapps.com");
+        assert_eq!(config.approval_timeout(), APPROVAL_TIMEOUT);
+        assert_eq!(
+            Config {
+                approval_timeout_seconds: Some(120),
+                ..config
+            }
+            .approval_timeout(),
+            Duration::from_secs(120)
+        );
+    }
+}
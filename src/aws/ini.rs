@@ -0,0 +1,243 @@
+//! A minimal, formatting-preserving editor for AWS INI files.
+//!
+//! Only the values which are set are rewritten.
+//! Every other byte (comments, ordering, spacing, quoting and line endings) is kept as it was.
+
+use std::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Document {
+    /// Lines, each including its line ending (if any)
+    lines: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Line<'a> {
+    Section(&'a str),
+    Setting { key: &'a str, value_start: usize },
+    Other,
+}
+
+impl<'a> Line<'a> {
+    fn parse(line: &'a str) -> Self {
+        let trimmed = line.trim();
+
+        if trimmed.starts_with('#') || trimmed.starts_with(';') {
+            return Self::Other;
+        }
+
+        if let Some(inner) = trimmed.strip_prefix('[') {
+            if let Some((name, _)) = inner.split_once(']') {
+                return Self::Section(name.trim());
+            }
+        }
+
+        line.find('=').map_or(Self::Other, |equals| {
+            let after = &line[equals + 1..];
+            Self::Setting {
+                key: line[..equals].trim(),
+                value_start: equals
+                    + 1
+                    + (after.len() - after.trim_start_matches([' ', '\t']).len()),
+            }
+        })
+    }
+}
+
+fn content(line: &str) -> &str {
+    line.trim_end_matches(['\r', '\n'])
+}
+
+impl Document {
+    #[must_use]
+    pub fn parse(contents: &str) -> Self {
+        Self {
+            lines: contents.split_inclusive('\n').map(String::from).collect(),
+        }
+    }
+
+    fn line_ending(&self) -> &'static str {
+        match self.lines.first() {
+            Some(line) if line.ends_with("\r\n") => "\r\n",
+            _ => "\n",
+        }
+    }
+
+    /// The range of line indices in the first section named `section`, excluding its header
+    fn section(&self, section: &str) -> Option<(usize, usize)> {
+        let header = self
+            .lines
+            .iter()
+            .position(|line| Line::parse(line) == Line::Section(section))?;
+
+        let end = self.lines[header + 1..]
+            .iter()
+            .position(|line| matches!(Line::parse(line), Line::Section(_)))
+            .map_or(self.lines.len(), |offset| header + 1 + offset);
+
+        Some((header + 1, end))
+    }
+
+    fn setting(&self, section: &str, key: &str) -> Option<(usize, usize)> {
+        let (start, end) = self.section(section)?;
+
+        (start..end).find_map(|index| match Line::parse(&self.lines[index]) {
+            Line::Setting {
+                key: found,
+                value_start,
+            } if found == key => Some((index, value_start)),
+            _ => None,
+        })
+    }
+
+    /// Whether `section` exists
+    #[must_use]
+    pub fn has_section(&self, section: &str) -> bool {
+        self.section(section).is_some()
+    }
+
+    /// The names of the sections, in the order they appear
+    #[must_use]
+    pub fn sections(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match Line::parse(line) {
+                Line::Section(name) => Some(name),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Remove the first section named `section`, with its settings and comments, returning whether it existed
+    pub fn remove_section(&mut self, section: &str) -> bool {
+        let Some((start, end)) = self.section(section) else {
+            return false;
+        };
+
+        self.lines.drain(start - 1..end);
+        true
+    }
+
+    /// Remove `key` from `section`, returning whether it was set
+    pub fn remove(&mut self, section: &str, key: &str) -> bool {
+        let Some((index, _)) = self.setting(section, key) else {
+            return false;
+        };
+
+        self.lines.remove(index);
+        true
+    }
+
+    /// The raw value of `key` in `section`
+    #[must_use]
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        let (index, value_start) = self.setting(section, key)?;
+        Some(content(&self.lines[index])[value_start..].trim_end())
+    }
+
+    /// Set `key` in `section`, creating either if they do not exist.
+    /// An existing value is replaced in place; a new key is added after the last setting in the section.
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        let line_ending = self.line_ending();
+
+        if let Some((index, value_start)) = self.setting(section, key) {
+            let line = &self.lines[index];
+            let ending = &line[content(line).len()..];
+            self.lines[index] = format!("{}{value}{ending}", &line[..value_start]);
+            return;
+        }
+
+        let new_line = format!("{key} = {value}{line_ending}");
+
+        if let Some((start, end)) = self.section(section) {
+            let after = (start..end)
+                .rev()
+                .find(|&index| matches!(Line::parse(&self.lines[index]), Line::Setting { .. }))
+                .map_or(start, |index| index + 1);
+
+            self.terminate(after);
+            self.lines.insert(after, new_line);
+        } else {
+            self.terminate(self.lines.len());
+            self.lines.push(format!("[{section}]{line_ending}"));
+            self.lines.push(new_line);
+        }
+    }
+
+    /// Make sure the line before `index` ends with a line ending, so that a line can be inserted at `index`
+    fn terminate(&mut self, index: usize) {
+        let line_ending = self.line_ending();
+
+        if let Some(line) = index.checked_sub(1).and_then(|i| self.lines.get_mut(i)) {
+            if !line.ends_with('\n') {
+                line.push_str(line_ending);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            f.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untouched_document_is_unchanged() {
+        let contents = "# top comment\r\n[foo]  \r\nkey =  'value' \r\n\r\n;other\r\n[bar]\r\nx=1";
+        assert_eq!(Document::parse(contents).to_string(), contents);
+    }
+
+    #[test]
+    fn set_preserves_surrounding_bytes() {
+        let mut document = Document::parse(
+            "[foo]\r\n# keep me  \r\naws_access_key_id =   'OLD'\r\nregion = us-east-1   \r\n\r\n[bar]\r\nx=1",
+        );
+
+        assert_eq!(document.get("foo", "aws_access_key_id"), Some("'OLD'"));
+
+        document.set("foo", "aws_access_key_id", "NEW");
+        document.set("foo", "aws_session_token", "TOKEN");
+        document.set("bar", "x", "2");
+        document.set("baz", "y", "3");
+
+        assert_eq!(
+            document.to_string(),
+            "[foo]\r\n# keep me  \r\naws_access_key_id =   NEW\r\nregion = us-east-1   \r\naws_session_token = TOKEN\r\n\r\n[bar]\r\nx=2\r\n[baz]\r\ny = 3\r\n"
+        );
+    }
+
+    #[test]
+    fn remove_section_keeps_the_rest() {
+        let mut document = Document::parse("[foo]\nx=1\n\n[bar]\ny=2\n[baz]\nz=3\n");
+
+        assert!(document.remove_section("bar"));
+        assert!(!document.remove_section("bar"));
+        assert_eq!(document.sections(), vec!["foo", "baz"]);
+        assert_eq!(document.to_string(), "[foo]\nx=1\n\n[baz]\nz=3\n");
+    }
+
+    #[test]
+    fn remove_keeps_the_section() {
+        let mut document = Document::parse("[profile foo]\nregion = eu-west-1\nx = 1\n");
+
+        assert!(document.remove("profile foo", "x"));
+        assert!(!document.remove("profile foo", "x"));
+        assert_eq!(document.to_string(), "[profile foo]\nregion = eu-west-1\n");
+    }
+
+    #[test]
+    fn comments_are_not_settings() {
+        let document = Document::parse("[foo]\n# key = commented\n;key=also\n");
+        assert_eq!(document.get("foo", "key"), None);
+        assert!(document.has_section("foo"));
+        assert!(!document.has_section("key = commented"));
+    }
+}
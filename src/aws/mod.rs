@@ -1,11 +1,28 @@
+pub mod config;
+pub mod console;
+pub mod container;
+pub mod http;
+pub mod identity_center;
+pub mod ini;
+pub mod partition;
+pub mod process;
 pub mod profile;
 pub mod role;
 pub mod saml;
+#[cfg(target_os = "linux")]
+pub mod sandbox;
 pub mod sso;
 
+use crate::aws::partition::Partition;
 use crate::aws::role::SamlRole;
 use crate::aws::saml::Response;
+use crate::request_id;
 
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+
+use aws_credential_types::Credentials;
+use aws_sdk_iam::config::Region as IamRegion;
 use aws_sdk_iam::{Client as IamClient, Config as IamConfig};
 use aws_sdk_sts::config::Region as StsRegion;
 use aws_sdk_sts::{Client as StsClient, Config as StsConfig};
@@ -19,13 +36,16 @@ use eyre::{Result, eyre};
 /// if the role does not have sufficient permissions to call `list_account_aliases`,
 /// or if there are an unexpected number of aliases returned.
 pub async fn get_account_alias(role: &SamlRole, response: &Response) -> Result<String> {
+    let partition = Partition::of(&role.role)?;
     let credentials = role
-        .assume(sts_client(), response.saml.clone(), None)
+        .assume(sts_client(partition), response.saml.clone(), None)
         .await
         .map_err(|e| eyre!("Error assuming role ({})", e))?;
 
     let config = IamConfig::builder()
+        .region(IamRegion::new(partition.region()))
         .credentials_provider(credentials)
+        .http_client(http::client())
         .behavior_version_latest()
         .build();
 
@@ -42,12 +62,82 @@ pub async fn get_account_alias(role: &SamlRole, response: &Response) -> Result<S
     }
 }
 
+/// Create an STS client for `partition`
 #[must_use]
-pub fn sts_client() -> StsClient {
-    let region = StsRegion::new("us-east-1");
+pub fn sts_client(partition: Partition) -> StsClient {
+    let region = StsRegion::new(partition.region());
     let config = StsConfig::builder()
         .region(region)
+        .http_client(http::client())
         .behavior_version_latest()
         .build();
     StsClient::from_conf(config)
 }
+
+/// Create an STS client for `partition` which signs its requests with `credentials`
+#[must_use]
+pub fn sts_client_with_credentials(credentials: Credentials, partition: Partition) -> StsClient {
+    let region = StsRegion::new(partition.region());
+    let config = StsConfig::builder()
+        .region(region)
+        .credentials_provider(credentials)
+        .http_client(http::client())
+        .behavior_version_latest()
+        .build();
+    StsClient::from_conf(config)
+}
+
+/// The account and ARN which `credentials` act as
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub account_id: String,
+    /// The assumed role's session ARN, e.g. `arn:aws:sts::123456789012:assumed-role/Admin/jdoe`,
+    /// where it is known (AWS SSO issues credentials without saying)
+    pub arn: Option<String>,
+}
+
+/// Who the credentials fetched by this process were issued to, by access key ID
+static ISSUED: Mutex<BTreeMap<String, Identity>> = Mutex::new(BTreeMap::new());
+
+/// Remember who `credentials` were issued to, as the request which fetched them said
+pub fn issued(credentials: &Credentials, identity: Identity) {
+    ISSUED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(credentials.access_key_id().to_string(), identity);
+}
+
+/// Who `credentials` were issued to, if this process fetched them (rather than reusing another run's)
+#[must_use]
+pub fn issued_to(credentials: &Credentials) -> Option<Identity> {
+    ISSUED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(credentials.access_key_id())
+        .cloned()
+}
+
+/// Ask STS who `credentials` (from `partition`) belong to
+///
+/// # Errors
+///
+/// Will return `Err` if STS cannot be reached, or rejects the credentials
+pub async fn caller_identity(credentials: Credentials, partition: Partition) -> Result<Identity> {
+    let identity = sts_client_with_credentials(credentials, partition)
+        .get_caller_identity()
+        .send()
+        .await;
+    request_id::observe_aws(&identity);
+    let identity = identity?;
+
+    Ok(Identity {
+        account_id: identity
+            .account
+            .ok_or_else(|| eyre!("STS did not say which account the credentials are for"))?,
+        arn: Some(
+            identity
+                .arn
+                .ok_or_else(|| eyre!("STS did not say which role the credentials are for"))?,
+        ),
+    })
+}
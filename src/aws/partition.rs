@@ -0,0 +1,103 @@
+use std::fmt;
+use std::str::FromStr;
+
+use aws_arn::ResourceName as ARN;
+use eyre::{Error, Result, eyre};
+
+/// An AWS partition, each of which has its own STS, sign-in and console endpoints.
+/// Credentials from one partition are not accepted by another.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Partition {
+    /// The commercial regions
+    #[default]
+    Aws,
+    /// AWS GovCloud (US)
+    AwsUsGov,
+    /// The China regions
+    AwsCn,
+}
+
+impl FromStr for Partition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "aws" => Ok(Self::Aws),
+            "aws-us-gov" => Ok(Self::AwsUsGov),
+            "aws-cn" => Ok(Self::AwsCn),
+            _ => Err(eyre!(
+                "Unknown AWS partition {s}, expected aws, aws-us-gov or aws-cn"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Aws => write!(f, "aws"),
+            Self::AwsUsGov => write!(f, "aws-us-gov"),
+            Self::AwsCn => write!(f, "aws-cn"),
+        }
+    }
+}
+
+impl Partition {
+    /// The partition an ARN belongs to
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the ARN names a partition oktaws does not know
+    pub fn of(arn: &ARN) -> Result<Self> {
+        arn.partition
+            .as_ref()
+            .map_or(Ok(Self::Aws), |partition| partition.to_string().parse())
+    }
+
+    /// The region to send STS (and other global service) requests to
+    #[must_use]
+    pub const fn region(self) -> &'static str {
+        match self {
+            Self::Aws => "us-east-1",
+            Self::AwsUsGov => "us-gov-west-1",
+            Self::AwsCn => "cn-north-1",
+        }
+    }
+
+    /// The console sign-in endpoint, used for federation and switching roles
+    #[must_use]
+    pub const fn signin_url(self) -> &'static str {
+        match self {
+            Self::Aws => "https://signin.aws.amazon.com",
+            Self::AwsUsGov => "https://signin.amazonaws-us-gov.com",
+            Self::AwsCn => "https://signin.amazonaws.cn",
+        }
+    }
+
+    /// The console home page
+    #[must_use]
+    pub const fn console_url(self) -> &'static str {
+        match self {
+            Self::Aws => "https://console.aws.amazon.com/",
+            Self::AwsUsGov => "https://console.amazonaws-us-gov.com/",
+            Self::AwsCn => "https://console.amazonaws.cn/",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_of_arn() -> Result<()> {
+        let commercial: ARN = "arn:aws:iam::123456789012:role/Admin".parse()?;
+        let govcloud: ARN = "arn:aws-us-gov:iam::123456789012:role/Admin".parse()?;
+
+        assert_eq!(Partition::of(&commercial)?, Partition::Aws);
+        assert_eq!(Partition::of(&govcloud)?, Partition::AwsUsGov);
+        assert_eq!(Partition::AwsUsGov.to_string(), "aws-us-gov");
+
+        Ok(())
+    }
+}
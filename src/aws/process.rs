@@ -0,0 +1,58 @@
+//! Credentials in the JSON format the AWS SDKs expect from a `credential_process`.
+//!
+//! See <https://docs.aws.amazon.com/sdkref/latest/guide/feature-process-credentials.html>
+
+use aws_credential_types::Credentials;
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
+use eyre::Result;
+use serde::Serialize;
+
+/// Credentials in the format expected from a `credential_process`
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProcessCredentials<'a> {
+    version: u8,
+    access_key_id: &'a str,
+    secret_access_key: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_token: Option<&'a str>,
+    /// Without an expiration, the SDKs treat the credentials as long-lived, and never run the process again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration: Option<String>,
+}
+
+impl<'a> ProcessCredentials<'a> {
+    /// # Errors
+    ///
+    /// Will return `Err` if the expiry cannot be formatted as an RFC 3339 timestamp
+    pub fn new(credentials: &'a Credentials) -> Result<Self> {
+        Ok(Self {
+            version: 1,
+            access_key_id: credentials.access_key_id(),
+            secret_access_key: credentials.secret_access_key(),
+            session_token: credentials.session_token(),
+            expiration: credentials
+                .expiry()
+                .map(|expiry| DateTime::from(expiry).fmt(Format::DateTime))
+                .transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn omits_missing_fields() -> Result<()> {
+        let credentials = Credentials::new("ACCESS_KEY", "SECRET_KEY", None, None, "test");
+
+        assert_eq!(
+            serde_json::to_string(&ProcessCredentials::new(&credentials)?)?,
+            r#"{"Version":1,"AccessKeyId":"ACCESS_KEY","SecretAccessKey":"SECRET_KEY"}"#
+        );
+
+        Ok(())
+    }
+}
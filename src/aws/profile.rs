@@ -1,88 +1,342 @@
-use aws_config_mod::{AwsCredentialsFile, Value};
+use crate::aws::config::Store as ConfigStore;
+use crate::aws::ini::Document;
+use crate::lock::FileLock;
+use crate::transaction::write_atomically;
+
+use aws_config_mod::AwsCredentialsFile;
 use aws_credential_types::Credentials;
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
 use dirs;
-use eyre::{Context, Result, eyre};
+use eyre::{Context, Error, Result, eyre};
+use serde::{Deserialize, Serialize};
 use std::env::var as env_var;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
 use tracing::instrument;
 
+/// The settings which hold a profile's credentials, in either layout
+const CREDENTIAL_KEYS: &[&str] = &[
+    "aws_access_key_id",
+    "aws_secret_access_key",
+    "aws_session_token",
+    "aws_security_token",
+    "aws_expiration",
+];
+
+/// How credentials are laid out in the file they are written to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// As in `~/.aws/credentials`: a section named after each profile
+    #[default]
+    Credentials,
+    /// As in `~/.aws/config`: in each profile's `[profile <name>]` section, beside its other settings
+    Config,
+}
+
+impl FromStr for Layout {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "credentials" => Ok(Self::Credentials),
+            "config" => Ok(Self::Config),
+            _ => Err(eyre!(
+                "Unknown credentials layout {s}, expected credentials or config"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Credentials => write!(f, "credentials"),
+            Self::Config => write!(f, "config"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Store {
     path: PathBuf,
-    credentials_file: AwsCredentialsFile,
+    layout: Layout,
+    credentials_file: Document,
+    upserted: Vec<(String, Credentials)>,
+    removed: Vec<String>,
 }
 
 impl Store {
-    #[instrument]
+    /// Load the AWS credentials file from `path`,
+    /// falling back on `AWS_SHARED_CREDENTIALS_FILE` or `~/.aws/credentials`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if no path can be determined,
+    /// or if the file exists but cannot be read or parsed.
     pub fn load(path: Option<&Path>) -> Result<Self> {
-        let path = match (path, env_var("AWS_SHARED_CREDENTIALS_FILE")) {
+        Self::load_with(path, Layout::Credentials)
+    }
+
+    /// Load the file credentials are written to in `layout` from `path`, falling back on the usual file for the layout:
+    /// `AWS_SHARED_CREDENTIALS_FILE` or `~/.aws/credentials`, or `AWS_CONFIG_FILE` or `~/.aws/config`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if no path can be determined,
+    /// or if the file exists but cannot be read or parsed.
+    #[instrument]
+    pub fn load_with(path: Option<&Path>, layout: Layout) -> Result<Self> {
+        let path = match (path, layout) {
             (Some(path), _) => PathBuf::from(path),
-            (_, Ok(path)) => PathBuf::from(path),
-            _ => dirs::home_dir().map_or_else(
-                || Err(eyre!("The environment variable HOME must be set.")),
-                |home_dir| Ok(home_dir.join(".aws").join("credentials")),
-            )?,
+            (None, Layout::Config) => ConfigStore::load(None)?.path().to_path_buf(),
+            (None, Layout::Credentials) => match env_var("AWS_SHARED_CREDENTIALS_FILE") {
+                Ok(path) => PathBuf::from(path),
+                _ => dirs::home_dir().map_or_else(
+                    || Err(eyre!("The environment variable HOME must be set.")),
+                    |home_dir| Ok(home_dir.join(".aws").join("credentials")),
+                )?,
+            },
         };
 
-        let credentials_file = if path.exists() {
-            fs::read_to_string(&path)?.parse().wrap_err_with(|| {
-                format!("Failed to parse AWS credentials file {}", &path.display())
-            })?
-        } else {
-            AwsCredentialsFile::default()
-        };
+        let credentials_file = Self::read(&path, layout)?;
 
         Ok(Self {
             path,
+            layout,
             credentials_file,
+            upserted: Vec::new(),
+            removed: Vec::new(),
         })
     }
 
+    /// Read the credentials file, keeping its formatting.
+    /// Credentials files are also fully parsed, so that malformed files are rejected rather than edited.
+    fn read(path: &Path, layout: Layout) -> Result<Document> {
+        if !path.exists() {
+            return Ok(Document::default());
+        }
+
+        let contents = fs::read_to_string(path)?;
+        if layout == Layout::Credentials {
+            contents.parse::<AwsCredentialsFile>().wrap_err_with(|| {
+                format!("Failed to parse AWS credentials file {}", path.display())
+            })?;
+        }
+        Ok(Document::parse(&contents))
+    }
+
+    /// The section holding `profile_name`'s credentials
+    fn section(&self, profile_name: &str) -> String {
+        match self.layout {
+            Layout::Credentials => profile_name.to_string(),
+            Layout::Config => ConfigStore::section(profile_name),
+        }
+    }
+
     /// # Errors
     ///
     /// Will return Err if the credentials provided are not STS.
     /// Will return Err if the current credentials for the profile are not STS.
     pub fn upsert_credential(&mut self, profile_name: &str, creds: &Credentials) -> Result<()> {
-        let profile = self.credentials_file.insert_profile(profile_name.parse()?);
+        let section = self.section(profile_name);
+        set_credential(&mut self.credentials_file, &section, profile_name, creds)?;
+        self.upserted
+            .push((profile_name.to_string(), creds.clone()));
 
-        let access_key_id = profile.get_setting(&"aws_access_key_id".parse()?);
-        let secret_access_key_name = profile.get_setting(&"aws_secret_access_key".parse()?);
-        let session_token_name = profile.get_setting(&"aws_session_token".parse()?);
+        Ok(())
+    }
+
+    /// Remove the temporary (STS) credentials for `profile_name`, returning whether there were any.
+    /// In the config layout, the profile's other settings are left as they are.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the profile holds long-lived credentials, which oktaws never removes
+    pub fn remove_credential(&mut self, profile_name: &str) -> Result<bool> {
+        let section = self.section(profile_name);
+        if !self.credentials_file.has_section(&section) {
+            return Ok(false);
+        }
 
-        if access_key_id.is_some()
-            && secret_access_key_name.is_some()
-            && session_token_name.is_none()
-        {
+        if !self.has_session(profile_name) {
             return Err(eyre!(
-                "The credentials for {profile_name} are not STS. Refusing to overwrite them"
+                "The credentials for {profile_name} are not STS. Refusing to remove them"
             ));
         }
 
-        profile.set(
-            "aws_access_key_id".parse()?,
-            Value::from(creds.access_key_id()),
-        );
-        profile.set(
-            "aws_secret_access_key".parse()?,
-            Value::from(creds.secret_access_key()),
-        );
-        if let Some(session_token) = creds.session_token() {
-            profile.set("aws_session_token".parse()?, Value::from(session_token));
-        } else {
-            return Err(eyre!("No session token found for {profile_name}"));
-        }
+        clear(&mut self.credentials_file, &section, self.layout);
+        self.upserted.retain(|(name, _)| name != profile_name);
+        self.removed.push(profile_name.to_string());
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// The profiles which hold temporary (STS) credentials
+    #[must_use]
+    pub fn sessions(&self) -> Vec<String> {
+        self.credentials_file
+            .sections()
+            .into_iter()
+            .filter_map(|section| match self.layout {
+                Layout::Credentials => Some(section),
+                Layout::Config if section == "default" => Some(section),
+                // Other sections of the config (e.g. `sso-session`) are not profiles
+                Layout::Config => section.strip_prefix("profile "),
+            })
+            .filter(|profile_name| self.has_session(profile_name))
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Whether the file already holds temporary (STS) credentials for `profile_name`.
+    /// Older versions of the AWS tooling wrote `aws_security_token` instead of `aws_session_token`.
+    #[must_use]
+    pub fn has_session(&self, profile_name: &str) -> bool {
+        let section = self.section(profile_name);
+
+        self.credentials_file
+            .get(&section, "aws_session_token")
+            .or_else(|| self.credentials_file.get(&section, "aws_security_token"))
+            .is_some()
+    }
+
+    /// When the credentials already in the file for `profile_name` expire,
+    /// if they were written by a version of oktaws which records it.
+    #[must_use]
+    pub fn expiry(&self, profile_name: &str) -> Option<SystemTime> {
+        let expiration = self
+            .credentials_file
+            .get(&self.section(profile_name), "aws_expiration")?
+            .trim_matches(['"', '\'']);
+
+        DateTime::from_str(expiration, Format::DateTime)
+            .ok()
+            .and_then(|expiry| SystemTime::try_from(expiry).ok())
+    }
+
+    /// The access key of the credentials already in the file for `profile_name`, if any
+    #[must_use]
+    pub fn access_key_id(&self, profile_name: &str) -> Option<&str> {
+        self.credentials_file
+            .get(&self.section(profile_name), "aws_access_key_id")
     }
 
+    /// Write the credentials back to disk.
+    ///
+    /// The file is locked and re-read before writing,
+    /// so that changes made by other oktaws processes since `load` are not lost,
+    /// then replaced in one step, so that nothing reading it ever sees it half written.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the lock cannot be acquired,
+    /// or if the file (or its parent directories) cannot be read or written.
     #[instrument(skip_all)]
     pub fn save(&self) -> Result<()> {
         if let Some(parent) = self.path.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        fs::write(&self.path, self.credentials_file.to_string()).map_err(Into::into)
+        let _lock = FileLock::acquire(&self.path)?;
+
+        write_atomically(&self.path, self.merged()?.to_string().as_bytes())
+    }
+
+    /// The file on disk, with this store's upserts and removals applied
+    fn merged(&self) -> Result<Document> {
+        self.merged_into(Self::read(&self.path, self.layout)?)
+    }
+
+    /// `credentials_file`, with this store's upserts and removals applied
+    fn merged_into(&self, mut credentials_file: Document) -> Result<Document> {
+        for profile_name in &self.removed {
+            clear(
+                &mut credentials_file,
+                &self.section(profile_name),
+                self.layout,
+            );
+        }
+        for (profile_name, creds) in &self.upserted {
+            set_credential(
+                &mut credentials_file,
+                &self.section(profile_name),
+                profile_name,
+                creds,
+            )?;
+        }
+
+        Ok(credentials_file)
+    }
+}
+
+/// Set `profile_name`'s credentials in `section`
+fn set_credential(
+    credentials_file: &mut Document,
+    section: &str,
+    profile_name: &str,
+    creds: &Credentials,
+) -> Result<()> {
+    let access_key_id = credentials_file.get(section, "aws_access_key_id");
+    let secret_access_key_name = credentials_file.get(section, "aws_secret_access_key");
+    let session_token_name = credentials_file.get(section, "aws_session_token");
+
+    if access_key_id.is_some() && secret_access_key_name.is_some() && session_token_name.is_none() {
+        return Err(eyre!(
+            "The credentials for {profile_name} are not STS. Refusing to overwrite them"
+        ));
+    }
+
+    let Some(session_token) = creds.session_token() else {
+        return Err(eyre!("No session token found for {profile_name}"));
+    };
+
+    credentials_file.set(section, "aws_access_key_id", creds.access_key_id());
+    credentials_file.set(section, "aws_secret_access_key", creds.secret_access_key());
+    credentials_file.set(section, "aws_session_token", session_token);
+
+    if let Some(expiry) = creds.expiry() {
+        credentials_file.set(
+            section,
+            "aws_expiration",
+            &DateTime::from(expiry).fmt(Format::DateTime)?,
+        );
+    }
+
+    Ok(())
+}
+
+/// Remove the credentials in `section`: the whole section in a credentials file, or just its credentials in the config
+fn clear(credentials_file: &mut Document, section: &str, layout: Layout) {
+    match layout {
+        Layout::Credentials => {
+            credentials_file.remove_section(section);
+        }
+        Layout::Config => {
+            for key in CREDENTIAL_KEYS {
+                credentials_file.remove(section, key);
+            }
+        }
+    }
+}
+
+impl crate::transaction::Staged for Store {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn render(&self) -> Result<Option<String>> {
+        Ok(Some(self.merged()?.to_string()))
+    }
+
+    fn render_over(&self, contents: &str) -> Result<Option<String>> {
+        Ok(Some(
+            self.merged_into(Document::parse(contents))?.to_string(),
+        ))
     }
 }
 
@@ -244,7 +498,7 @@ aws_session_token = NEW_EXAMPLE_SESSION_TOKEN",
         // Normalize line endings to avoid OS-specifics
         let contents = fs::read_to_string(tempfile.path())?.lines().join("\n");
 
-        // Whitespace is preserved (except trailing whitespace), quotes are removed
+        // Formatting is preserved, only the values themselves are replaced
         assert_eq!(
             contents,
             r"[foo]
@@ -268,6 +522,72 @@ aws_secret_access_key = STATIC_SECRET_ACCESS_KEY"
         Ok(())
     }
 
+    #[test]
+    fn save_keeps_concurrent_changes() -> Result<()> {
+        let tempfile = NamedTempFile::new()?;
+
+        let mut first = Store::load(Some(tempfile.path()))?;
+        let mut second = Store::load(Some(tempfile.path()))?;
+
+        first.upsert_credential(
+            "foo",
+            &Credentials::new(
+                "NEW_FOO_ACCESS_KEY",
+                "NEW_FOO_SECRET_ACCESS_KEY",
+                Some("NEW_FOO_SESSION_TOKEN".to_string()),
+                None,
+                "oktaws",
+            ),
+        )?;
+        second.upsert_credential(
+            "bar",
+            &Credentials::new(
+                "NEW_BAR_ACCESS_KEY",
+                "NEW_BAR_SECRET_ACCESS_KEY",
+                Some("NEW_BAR_SESSION_TOKEN".to_string()),
+                None,
+                "oktaws",
+            ),
+        )?;
+
+        first.save()?;
+        second.save()?;
+
+        let contents = fs::read_to_string(tempfile.path())?;
+        assert!(contents.contains("aws_access_key_id = NEW_FOO_ACCESS_KEY"));
+        assert!(contents.contains("aws_access_key_id = NEW_BAR_ACCESS_KEY"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn expiry_round_trip() -> Result<()> {
+        let tempfile = NamedTempFile::new()?;
+        let expiry = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+
+        let mut store = Store::load(Some(tempfile.path()))?;
+        store.upsert_credential(
+            "foo",
+            &Credentials::new(
+                "NEW_FOO_ACCESS_KEY",
+                "NEW_FOO_SECRET_ACCESS_KEY",
+                Some("NEW_FOO_SESSION_TOKEN".to_string()),
+                Some(expiry),
+                "oktaws",
+            ),
+        )?;
+        store.save()?;
+
+        let contents = fs::read_to_string(tempfile.path())?;
+        assert!(contents.contains("aws_expiration = 2023-11-14T22:13:20Z"));
+
+        let store = Store::load(Some(tempfile.path()))?;
+        assert_eq!(store.expiry("foo"), Some(expiry));
+        assert_eq!(store.expiry("bar"), None);
+
+        Ok(())
+    }
+
     #[test]
     fn not_update_creds_on_static_profile() -> Result<()> {
         let mut tempfile = NamedTempFile::new()?;
@@ -295,7 +615,7 @@ aws_secret_access_key = STATIC_SECRET_ACCESS_KEY"
                 "The credentials for static are not STS. Refusing to overwrite them
 
 Location:
-    {}:57:24",
+    {}:289:20",
                 PathBuf::from_iter(["src", "aws", "profile.rs"]).display()
             ),
         );
@@ -310,6 +630,51 @@ Location:
         Ok(())
     }
 
+    #[test]
+    fn config_layout() -> Result<()> {
+        use crate::transaction::{self, Staged};
+
+        let mut tempfile = NamedTempFile::new()?;
+        write!(
+            tempfile,
+            "[profile foo]\nregion = eu-west-1\n\n[sso-session corp]\nsso_region = us-east-1\n"
+        )?;
+
+        let mut store = Store::load_with(Some(tempfile.path()), Layout::Config)?;
+        store.upsert_credential(
+            "foo",
+            &Credentials::new(
+                "NEW_FOO_ACCESS_KEY",
+                "NEW_FOO_SECRET_ACCESS_KEY",
+                Some("NEW_FOO_SESSION_TOKEN".to_string()),
+                None,
+                "oktaws",
+            ),
+        )?;
+        let mut config = ConfigStore::load(Some(tempfile.path()))?;
+        config.upsert_settings("foo", &[("output", "json")]);
+
+        // Both write ~/.aws/config, without either losing the other's changes
+        let journal = tempfile::tempdir()?;
+        transaction::commit(journal.path(), &[&store as &dyn Staged, &config])?;
+
+        let contents = fs::read_to_string(tempfile.path())?;
+        assert!(contents.contains("aws_session_token = NEW_FOO_SESSION_TOKEN"));
+        assert!(contents.contains("output = json"));
+
+        let mut store = Store::load_with(Some(tempfile.path()), Layout::Config)?;
+        assert_eq!(store.sessions(), vec!["foo"]);
+        assert!(store.remove_credential("foo")?);
+        store.save()?;
+
+        let contents = fs::read_to_string(tempfile.path())?;
+        assert!(contents.contains("[profile foo]\nregion = eu-west-1\n"));
+        assert!(!contents.contains("aws_access_key_id"));
+        assert!(contents.contains("[sso-session corp]"));
+
+        Ok(())
+    }
+
     #[test]
     fn parse_bad_ini() -> Result<()> {
         let mut tempfile = NamedTempFile::new()?;
@@ -335,7 +700,7 @@ Caused by:
    1: Parsing Error: VerboseError {{ errors: [(\"foo\", Nom(Eof))] }}
 
 Location:
-    {}:29:48",
+    {}:126:52",
                 tempfile.path().display(),
                 PathBuf::from_iter(["src", "aws", "profile.rs"]).display()
             )
@@ -1,14 +1,117 @@
 #![allow(clippy::module_name_repetitions)]
 
+use crate::aws::{self, Identity};
+use crate::request_id;
+
 use std::str;
 use std::str::FromStr;
 
 use aws_arn::ResourceName as ARN;
 use aws_credential_types::Credentials;
 use aws_sdk_sts::Client as StsClient;
-use eyre::{Error, Result, eyre};
+use aws_sdk_sts::error::ProvideErrorMetadata;
+use aws_sdk_sts::types::AssumedRoleUser;
+use eyre::{Error, Report, Result, eyre};
 use tracing::instrument;
 
+/// The shortest session STS grants, in seconds
+pub const MIN_DURATION_SECONDS: i32 = 900;
+
+/// The longest session a role can allow, in seconds (12 hours).
+/// Roles allow 1 hour unless their maximum session duration is raised, and chained roles never allow more.
+pub const MAX_DURATION_SECONDS: i32 = 43_200;
+
+/// The longest session a chained role can have, in seconds (1 hour), whatever its maximum session duration
+pub const MAX_CHAINED_DURATION_SECONDS: i32 = 3_600;
+
+/// How a role is assumed, which bounds how long its sessions can last
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// With a SAML assertion, from Okta
+    Saml,
+    /// With the credentials of another role
+    Chained,
+}
+
+impl Kind {
+    /// The longest session a role assumed this way can have, in seconds
+    #[must_use]
+    pub const fn max_duration_seconds(self) -> i32 {
+        match self {
+            Self::Saml => MAX_DURATION_SECONDS,
+            Self::Chained => MAX_CHAINED_DURATION_SECONDS,
+        }
+    }
+}
+
+/// STS would not grant a session as long (or short) as `duration_seconds` asked for
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "{role} does not allow sessions of {duration_seconds} seconds ({message}). \
+     Lower `duration_seconds` (or `--expires-in`), or raise the role's maximum session duration in IAM"
+)]
+pub struct DurationRejected {
+    pub role: String,
+    pub duration_seconds: i32,
+    pub message: String,
+}
+
+/// Check `duration_seconds` is one STS could grant for `role` (assumed as `kind`), before asking for it
+///
+/// # Errors
+///
+/// Will return `Err` if the duration is shorter than STS allows, or longer than any role assumed that way can
+pub fn check_duration(
+    role: &ARN,
+    kind: Kind,
+    duration_seconds: Option<i32>,
+) -> Result<(), DurationRejected> {
+    let max_duration_seconds = kind.max_duration_seconds();
+
+    match duration_seconds {
+        Some(duration_seconds) if duration_seconds < MIN_DURATION_SECONDS => {
+            Err(DurationRejected {
+                role: role.to_string(),
+                duration_seconds,
+                message: format!("sessions last at least {MIN_DURATION_SECONDS} seconds"),
+            })
+        }
+        Some(duration_seconds) if duration_seconds > max_duration_seconds => {
+            Err(DurationRejected {
+                role: role.to_string(),
+                duration_seconds,
+                message: match kind {
+                    Kind::Saml => format!("sessions last at most {max_duration_seconds} seconds"),
+                    Kind::Chained => format!(
+                        "chained roles' sessions last at most {max_duration_seconds} seconds"
+                    ),
+                },
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The duration STS rejected in `error`, if that is why it failed
+fn duration_rejected(
+    role: &ARN,
+    duration_seconds: Option<i32>,
+    error: &impl ProvideErrorMetadata,
+) -> Option<DurationRejected> {
+    let (duration_seconds, message) = (duration_seconds?, error.message()?);
+    if error.code() != Some("ValidationError")
+        || !message.to_lowercase().contains("durationseconds")
+    {
+        return None;
+    }
+
+    Some(DurationRejected {
+        role: role.to_string(),
+        duration_seconds,
+        message: message.trim_end_matches('.').to_string(),
+    })
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct SamlRole {
     pub provider: ARN,
@@ -47,6 +150,12 @@ impl SamlRole {
             .map(ToString::to_string)
     }
 
+    /// Assume the role with a SAML assertion
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if STS rejects the request (with [`DurationRejected`] if it is for the session duration),
+    /// or returns no credentials
     #[instrument(level = "trace", skip(client))]
     pub async fn assume(
         &self,
@@ -54,24 +163,140 @@ impl SamlRole {
         saml_assertion: String,
         duration_seconds: Option<i32>,
     ) -> Result<Credentials> {
-        let credentials = client
+        check_duration(&self.role, Kind::Saml, duration_seconds)?;
+
+        let assumed = client
             .assume_role_with_saml()
             .set_duration_seconds(duration_seconds)
             .principal_arn(self.provider.to_string())
             .role_arn(self.role.to_string())
             .saml_assertion(saml_assertion)
             .send()
-            .await?
+            .await;
+        request_id::observe_aws(&assumed);
+
+        let assumed =
+            assumed.map_err(
+                |e| match duration_rejected(&self.role, duration_seconds, &e) {
+                    Some(rejected) => Report::from(rejected),
+                    None => Report::from(e),
+                },
+            )?;
+        let credentials = assumed
             .credentials
             .ok_or_else(|| eyre!("No credentials returned"))?;
 
-        Ok(Credentials::new(
+        let credentials = Credentials::new(
             credentials.access_key_id,
             credentials.secret_access_key,
             Some(credentials.session_token),
             credentials.expiration.try_into().ok(),
             "sts",
-        ))
+        );
+        remember(&credentials, assumed.assumed_role_user.as_ref());
+        Ok(credentials)
+    }
+}
+
+/// A role to assume using existing credentials (role chaining)
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainedRole {
+    pub role: ARN,
+    pub session_name: Option<String>,
+    pub external_id: Option<String>,
+    pub duration_seconds: Option<i32>,
+}
+
+impl ChainedRole {
+    #[must_use]
+    pub const fn new(role: ARN) -> Self {
+        Self {
+            role,
+            session_name: None,
+            external_id: None,
+            duration_seconds: None,
+        }
+    }
+
+    /// The session name to use, defaulting to `oktaws-<username>`.
+    /// Characters STS does not allow in session names are replaced.
+    #[must_use]
+    pub fn session_name(&self) -> String {
+        let session_name = self
+            .session_name
+            .clone()
+            .unwrap_or_else(|| format!("oktaws-{}", whoami::username()));
+
+        session_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "_+=,.@-".contains(c) {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .take(64)
+            .collect()
+    }
+
+    /// Assume this role, using the STS client (which holds the base credentials)
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if STS rejects the request (with [`DurationRejected`] if it is for the session duration),
+    /// or returns no credentials
+    #[instrument(level = "trace", skip(client))]
+    pub async fn assume(&self, client: StsClient) -> Result<Credentials> {
+        check_duration(&self.role, Kind::Chained, self.duration_seconds)?;
+
+        let assumed = client
+            .assume_role()
+            .role_arn(self.role.to_string())
+            .role_session_name(self.session_name())
+            .set_external_id(self.external_id.clone())
+            .set_duration_seconds(self.duration_seconds)
+            .send()
+            .await;
+        request_id::observe_aws(&assumed);
+
+        let assumed = assumed.map_err(|e| {
+            match duration_rejected(&self.role, self.duration_seconds, &e) {
+                Some(rejected) => Report::from(rejected),
+                None => eyre!("Error assuming role {} ({})", self.role, e),
+            }
+        })?;
+        let credentials = assumed
+            .credentials
+            .ok_or_else(|| eyre!("No credentials returned"))?;
+
+        let credentials = Credentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            Some(credentials.session_token),
+            credentials.expiration.try_into().ok(),
+            "sts",
+        );
+        remember(&credentials, assumed.assumed_role_user.as_ref());
+        Ok(credentials)
+    }
+}
+
+/// Remember who STS issued `credentials` to, from the session it said they are for
+fn remember(credentials: &Credentials, user: Option<&AssumedRoleUser>) {
+    let Some(user) = user else {
+        return;
+    };
+
+    // e.g. arn:aws:sts::123456789012:assumed-role/Admin/jdoe
+    if let Some(account_id) = user.arn().split(':').nth(4) {
+        aws::issued(
+            credentials,
+            Identity {
+                account_id: account_id.to_string(),
+                arn: Some(user.arn().to_string()),
+            },
+        );
     }
 }
 
@@ -180,4 +405,126 @@ mod tests {
 
         http_client.assert_requests_match(&[]);
     }
+
+    #[test]
+    fn rejects_durations() {
+        let http_client = StaticReplayClient::new(vec![ReplayEvent::new(
+            http::Request::builder()
+                .uri(http::Uri::from_static("https://sts.us-east-1.amazonaws.com/"))
+                .body(SdkBody::empty())
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(r"<ErrorResponse xmlns='https://sts.amazonaws.com/doc/2011-06-15/'>
+  <Error>
+    <Type>Sender</Type>
+    <Code>ValidationError</Code>
+    <Message>The requested DurationSeconds exceeds the MaxSessionDuration set for this role.</Message>
+  </Error>
+  <RequestId>mock-request</RequestId>
+</ErrorResponse>"))
+                .unwrap(),
+        )]);
+
+        let config: StsConfig = StsConfig::builder()
+            .region(StsRegion::new("us-east-1"))
+            .http_client(http_client)
+            .behavior_version_latest()
+            .build();
+
+        let role = SamlRole {
+            provider: "arn:aws:iam::123456789012:saml-provider/okta-idp"
+                .parse()
+                .unwrap(),
+            role: "arn:aws:iam::123456789012:role/mock-role".parse().unwrap(),
+        };
+
+        let error = block_on(role.assume(
+            StsClient::from_conf(config),
+            String::from("SAML_ASSERTION"),
+            Some(28_800),
+        ))
+        .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<DurationRejected>(),
+            Some(&DurationRejected {
+                role: String::from("arn:aws:iam::123456789012:role/mock-role"),
+                duration_seconds: 28_800,
+                message: String::from(
+                    "The requested DurationSeconds exceeds the MaxSessionDuration set for this role"
+                ),
+            })
+        );
+
+        // Durations no role allows are caught before asking STS
+        assert!(check_duration(&role.role, Kind::Saml, Some(300)).is_err());
+        assert!(check_duration(&role.role, Kind::Saml, Some(50_000)).is_err());
+        assert!(check_duration(&role.role, Kind::Saml, Some(28_800)).is_ok());
+        assert!(check_duration(&role.role, Kind::Saml, None).is_ok());
+
+        // Chained roles never last more than an hour
+        assert!(check_duration(&role.role, Kind::Chained, Some(3600)).is_ok());
+        assert!(check_duration(&role.role, Kind::Chained, Some(7200)).is_err());
+    }
+
+    #[test]
+    fn chained_session_name() {
+        let mut role = ChainedRole::new(
+            "arn:aws:iam::123456789012:role/Break-Glass"
+                .parse()
+                .unwrap(),
+        );
+        role.session_name = Some(String::from("first last/laptop"));
+
+        assert_eq!(role.session_name(), "first-last-laptop");
+    }
+
+    #[test]
+    fn assume_chained_role() {
+        let http_client = StaticReplayClient::new(vec![ReplayEvent::new(
+            http::Request::builder()
+                .uri(http::Uri::from_static("https://sts.us-east-1.amazonaws.com/"))
+                .body(SdkBody::from(r"Action=AssumeRole&Version=2011-06-15&RoleArn=arn%3Aaws%3Aiam%3A%3A123456789012%3Arole%2FBreak-Glass&RoleSessionName=oktaws-test")).unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r"<AssumeRoleResponse xmlns='https://sts.amazonaws.com/doc/2011-06-15/'>
+  <AssumeRoleResult>
+    <Credentials>
+      <AccessKeyId>CHAINED_ACCESS_KEY</AccessKeyId>
+      <SecretAccessKey>CHAINED_SECRET_ACCESS_KEY</SecretAccessKey>
+      <SessionToken>CHAINED_SESSION_TOKEN</SessionToken>
+      <Expiration>2030-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleResult>
+</AssumeRoleResponse>")).unwrap(),
+        )]);
+
+        let config: StsConfig = StsConfig::builder()
+            .region(StsRegion::new("us-east-1"))
+            .http_client(http_client.clone())
+            .credentials_provider(Credentials::new(
+                "BASE_ACCESS_KEY",
+                "BASE_SECRET_ACCESS_KEY",
+                Some(String::from("BASE_SESSION_TOKEN")),
+                None,
+                "test",
+            ))
+            .behavior_version_latest()
+            .build();
+
+        let mut role = ChainedRole::new(
+            "arn:aws:iam::123456789012:role/Break-Glass"
+                .parse()
+                .unwrap(),
+        );
+        role.session_name = Some(String::from("oktaws-test"));
+
+        let credentials = block_on(role.assume(StsClient::from_conf(config))).unwrap();
+
+        assert_eq!(credentials.access_key_id(), "CHAINED_ACCESS_KEY");
+        assert_eq!(credentials.session_token(), Some("CHAINED_SESSION_TOKEN"));
+
+        http_client.assert_requests_match(&[]);
+    }
 }
@@ -1,14 +1,152 @@
 use crate::aws::role::SamlRole;
+use crate::retry;
+use crate::tls;
 
+use std::collections::BTreeMap;
 use std::str::FromStr;
+use std::time::Duration;
 
 use base64::engine::{Engine, general_purpose::STANDARD as b64};
-use eyre::{Error, Result, eyre};
+use eyre::{Result, eyre};
 use kuchiki::traits::TendrilSink;
 use regex::Regex;
 use samuel::assertion::{Assertions, AttributeStatement};
 use url::Url;
 
+/// The attribute listing the roles (and their SAML providers) the user may assume
+pub const ROLE_ATTRIBUTE: &str = "https://aws.amazon.com/SAML/Attributes/Role";
+/// The attribute giving the session name for `AssumeRoleWithSAML`
+pub const ROLE_SESSION_NAME_ATTRIBUTE: &str =
+    "https://aws.amazon.com/SAML/Attributes/RoleSessionName";
+/// The attribute giving the longest session the identity provider allows, in seconds
+pub const SESSION_DURATION_ATTRIBUTE: &str =
+    "https://aws.amazon.com/SAML/Attributes/SessionDuration";
+
+/// The namespace of SAML assertions' elements
+const ASSERTION_NAMESPACE: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+
+/// The parts of a SAML response which matter to AWS: the attributes of its (plaintext) assertions,
+/// and the audiences they are restricted to.
+/// Signatures are not checked; AWS does that when the assertion is used.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Assertion {
+    pub audiences: Vec<String>,
+    /// Attribute values, by attribute name, in document order
+    pub attributes: BTreeMap<String, Vec<String>>,
+}
+
+impl Assertion {
+    /// Parse a SAML response document
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the document is not a SAML response, or its assertions are encrypted
+    pub fn parse(xml: &str) -> Result<Self> {
+        let response: samuel::response::Response =
+            xml.parse().map_err(|_| eyre!("Error parsing SAML"))?;
+
+        let assertions = match response.assertions {
+            Assertions::Plaintexts(assertions) => assertions,
+            Assertions::Encrypteds(_) => {
+                return Err(eyre!("Encrypted assertions are not currently supported"));
+            }
+            Assertions::None => vec![],
+        };
+
+        let mut attributes = BTreeMap::<String, Vec<String>>::new();
+        for statement in assertions
+            .into_iter()
+            .flat_map(|assertion| assertion.attribute_statement)
+        {
+            match statement {
+                AttributeStatement::PlaintextAttributes(statement) => {
+                    for attribute in statement {
+                        attributes
+                            .entry(attribute.name)
+                            .or_default()
+                            .extend(attribute.values);
+                    }
+                }
+                AttributeStatement::EncryptedAttributes(_) => {
+                    return Err(eyre!("Encrypted assertions are not currently supported"));
+                }
+                AttributeStatement::None => {}
+            }
+        }
+
+        Ok(Self {
+            audiences: audiences(xml)?,
+            attributes,
+        })
+    }
+
+    /// Parse a base64-encoded SAML response, as posted to AWS
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `saml` is not base64-encoded UTF-8, or see [`Assertion::parse`]
+    pub fn from_base64(saml: &str) -> Result<Self> {
+        Self::parse(&String::from_utf8(b64.decode(saml)?)?)
+    }
+
+    /// The values of an attribute, or none if the assertion doesn't have it
+    #[must_use]
+    pub fn attribute(&self, name: &str) -> &[String] {
+        self.attributes.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    /// The roles the user may assume
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a role value is not a provider ARN and a role ARN
+    pub fn roles(&self) -> Result<Vec<SamlRole>> {
+        self.attribute(ROLE_ATTRIBUTE)
+            .iter()
+            .map(|value| value.parse())
+            .collect()
+    }
+
+    /// The session name AWS will use
+    #[must_use]
+    pub fn role_session_name(&self) -> Option<&str> {
+        self.attribute(ROLE_SESSION_NAME_ATTRIBUTE)
+            .first()
+            .map(String::as_str)
+    }
+
+    /// The longest session the identity provider allows, if it says
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the duration is not a whole number of seconds
+    pub fn session_duration(&self) -> Result<Option<Duration>> {
+        self.attribute(SESSION_DURATION_ATTRIBUTE)
+            .first()
+            .map(|seconds| {
+                seconds
+                    .trim()
+                    .parse()
+                    .map(Duration::from_secs)
+                    .map_err(|_| eyre!("Invalid session duration {seconds}"))
+            })
+            .transpose()
+    }
+}
+
+/// The audiences the assertions in a SAML response document are restricted to, in document order
+fn audiences(xml: &str) -> Result<Vec<String>> {
+    let document =
+        roxmltree::Document::parse(xml).map_err(|e| eyre!("Error parsing SAML ({e})"))?;
+
+    Ok(document
+        .descendants()
+        .filter(|node| node.has_tag_name((ASSERTION_NAMESPACE, "Audience")))
+        .filter_map(|node| node.text())
+        .map(|audience| audience.trim().to_string())
+        .collect())
+}
+
 #[derive(Clone, Debug)]
 pub struct Response {
     pub url: Url,
@@ -39,41 +177,21 @@ impl Response {
             .map_err(|_| eyre!("Error parsing SAML"))
     }
 
+    /// # Errors
+    ///
+    /// The function will error if the `SamlResponse` object is not valid SAML,
+    /// or if it finds encrypted assertions
+    ///
+    pub fn assertion(&self) -> Result<Assertion> {
+        Assertion::from_base64(&self.saml)
+    }
+
     /// # Errors
     ///
     /// The function will error if it finds encrypted assertions
     ///
     pub fn roles(&self) -> Result<Vec<SamlRole>> {
-        let assertions = match self.saml()?.assertions {
-            Assertions::Plaintexts(assertions) => Ok(assertions),
-            Assertions::Encrypteds(_) => {
-                Err(eyre!("Encrypted assertions are not currently supported"))
-            }
-            Assertions::None => Ok(vec![]),
-        }?;
-
-        let role_attribute = assertions
-            .into_iter()
-            .flat_map(|assertion| assertion.attribute_statement)
-            .flat_map(|attribute| match attribute {
-                AttributeStatement::PlaintextAttributes(attributes) => Ok(attributes),
-                AttributeStatement::EncryptedAttributes(_) => {
-                    Err(eyre!("Encrypted assertions are not currently supported"))
-                }
-                AttributeStatement::None => Ok(vec![]),
-            })
-            .flatten()
-            .find(|attribute| attribute.name == "https://aws.amazon.com/SAML/Attributes/Role");
-
-        if let Some(role_attribute) = role_attribute {
-            role_attribute
-                .values
-                .into_iter()
-                .map(|arn| arn.parse())
-                .collect::<Result<Vec<SamlRole>, Error>>()
-        } else {
-            Ok(vec![])
-        }
+        self.assertion()?.roles()
     }
 
     /// Post the SAML document to AWS, imitating the browser-based login flow
@@ -82,15 +200,12 @@ impl Response {
     ///
     /// Will return `Err` if there are any errors encountered while sending the request
     pub async fn post(self) -> Result<reqwest::Response> {
-        reqwest::Client::new()
-            .post(self.url)
-            .form(&[
-                ("SAMLResponse", self.saml),
-                ("RelayState", self.relay_state),
-            ])
-            .send()
-            .await
-            .map_err(Into::into)
+        retry::send(tls::client_builder().build()?.post(self.url).form(&[
+            ("SAMLResponse", self.saml),
+            ("RelayState", self.relay_state),
+        ]))
+        .await
+        .map_err(Into::into)
     }
 }
 
@@ -162,6 +277,9 @@ mod tests {
     use std::fs::File;
     use std::io::Read;
 
+    use eyre::Error;
+    use proptest::prelude::*;
+
     #[test]
     fn parse_response_invalid_no_role() {
         let mut f =
@@ -181,4 +299,157 @@ mod tests {
             "Not enough elements in arn:aws:iam::123456789012:saml-provider/okta-idp"
         );
     }
+
+    #[test]
+    fn audiences_are_read_by_namespace() -> Result<()> {
+        let xml = r#"<Response xmlns="urn:oasis:names:tc:SAML:2.0:assertion" xmlns:x="urn:example">
+<Audience> urn:amazon:webservices </Audience><x:Audience>urn:example:other</x:Audience>
+<Audience><![CDATA[https://signin.aws.amazon.com/saml?a=1&b=2]]></Audience></Response>"#;
+
+        assert_eq!(
+            audiences(xml)?,
+            vec![
+                "urn:amazon:webservices",
+                "https://signin.aws.amazon.com/saml?a=1&b=2"
+            ]
+        );
+        assert!(audiences("<Audience>").is_err());
+
+        Ok(())
+    }
+
+    /// Every fixture response, with the role names, session duration and audiences it should parse to
+    #[test]
+    fn parse_fixture_corpus() -> Result<()> {
+        let corpus: [(&str, &[&str], Option<u64>, &[&str]); 3] = [
+            (
+                "tests/fixtures/saml_response.xml",
+                &["role1", "role2"],
+                Some(43200),
+                &["http://sp.example.com/demo1/metadata.php"],
+            ),
+            (
+                "tests/fixtures/saml_response_govcloud.xml",
+                &["ReadOnly"],
+                None,
+                &["urn:amazon:webservices:govcloud-us"],
+            ),
+            (
+                "tests/fixtures/saml_response_session_tags.xml",
+                &["Admin", "Deploy"],
+                Some(3600),
+                &[
+                    "urn:amazon:webservices",
+                    "https://signin.aws.amazon.com/saml?tenant=a&region=eu",
+                ],
+            ),
+        ];
+
+        for (path, roles, duration, audiences) in corpus {
+            let assertion = Assertion::parse(&std::fs::read_to_string(path)?)?;
+
+            assert_eq!(
+                assertion
+                    .roles()?
+                    .iter()
+                    .map(SamlRole::role_name)
+                    .collect::<Result<Vec<_>>>()?,
+                roles,
+                "{path}"
+            );
+            assert_eq!(
+                assertion.session_duration()?,
+                duration.map(Duration::from_secs),
+                "{path}"
+            );
+            assert_eq!(assertion.audiences, audiences, "{path}");
+            assert_eq!(
+                assertion.role_session_name(),
+                Some(if path.ends_with("saml_response.xml") {
+                    "test@example.com"
+                } else {
+                    "jane@example.com"
+                }),
+                "{path}"
+            );
+        }
+
+        let tagged = Assertion::parse(&std::fs::read_to_string(
+            "tests/fixtures/saml_response_session_tags.xml",
+        )?)?;
+        assert_eq!(
+            tagged.attribute("https://aws.amazon.com/SAML/Attributes/PrincipalTag:CostCenter"),
+            ["1234"]
+        );
+        assert!(
+            tagged
+                .attribute("https://aws.amazon.com/SAML/Attributes/Missing")
+                .is_empty()
+        );
+
+        Ok(())
+    }
+
+    /// A minimal response with `roles` (as provider and role ARN pairs) and an optional session duration
+    fn generated_response(roles: &[(String, String)], duration: Option<u64>) -> String {
+        let attribute = |name: &str, values: &[String]| {
+            format!(
+                r#"<saml:Attribute Name="{name}">{}</saml:Attribute>"#,
+                values
+                    .iter()
+                    .map(|value| format!("<saml:AttributeValue>{value}</saml:AttributeValue>"))
+                    .collect::<String>()
+            )
+        };
+
+        let mut attributes = attribute(
+            ROLE_ATTRIBUTE,
+            &roles
+                .iter()
+                .map(|(account, role)| {
+                    format!(
+                        "arn:aws:iam::{account}:saml-provider/okta-idp,arn:aws:iam::{account}:role/{role}"
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+        if let Some(duration) = duration {
+            attributes.push_str(&attribute(
+                SESSION_DURATION_ATTRIBUTE,
+                &[duration.to_string()],
+            ));
+        }
+
+        format!(
+            r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" ID="id-response" Version="2.0" IssueInstant="2024-03-01T09:00:00Z"><saml:Issuer>http://www.okta.com/exk1example</saml:Issuer><samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/></samlp:Status><saml:Assertion ID="id-assertion" Version="2.0" IssueInstant="2024-03-01T09:00:00Z"><saml:Issuer>http://www.okta.com/exk1example</saml:Issuer><saml:Conditions><saml:AudienceRestriction><saml:Audience>urn:amazon:webservices</saml:Audience></saml:AudienceRestriction></saml:Conditions><saml:AttributeStatement>{attributes}</saml:AttributeStatement></saml:Assertion></samlp:Response>"#
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn parses_generated_roles(
+            roles in prop::collection::vec(("[0-9]{12}", "[A-Za-z0-9_-]{1,64}"), 1..8),
+            duration in prop::option::of(900_u64..=43200),
+        ) {
+            let assertion = Assertion::parse(&generated_response(&roles, duration)).unwrap();
+
+            prop_assert_eq!(
+                assertion
+                    .roles()
+                    .unwrap()
+                    .iter()
+                    .map(|role| role.role_name().unwrap())
+                    .collect::<Vec<_>>(),
+                roles.iter().map(|(_, role)| role.clone()).collect::<Vec<_>>()
+            );
+            prop_assert_eq!(assertion.session_duration().unwrap(), duration.map(Duration::from_secs));
+            prop_assert_eq!(assertion.audiences, vec![String::from("urn:amazon:webservices")]);
+        }
+
+        #[test]
+        fn never_panics_on_arbitrary_input(xml in any::<String>()) {
+            let _ = Assertion::parse(&xml);
+            let _ = Assertion::from_base64(&xml);
+        }
+    }
 }
@@ -0,0 +1,160 @@
+//! Hand credentials to a child process without putting them in its environment, for `oktaws exec --memfd`.
+//!
+//! Environment variables of every process are readable through `/proc/<pid>/environ`
+//! by anyone running as the same user (and by root), which matters on shared machines.
+//! Instead, the credentials are written to an anonymous, sealed memory file which only the command inherits,
+//! and the command is pointed at it with `AWS_SHARED_CREDENTIALS_FILE=/proc/self/fd/<fd>`.
+
+use crate::exec::OVERRIDING_VARIABLES;
+
+use std::ffi::CString;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use aws_credential_types::Credentials;
+use eyre::{Result, eyre};
+use tokio::process::Command;
+
+/// A sealed, in-memory credentials file holding one profile.
+/// The descriptor is close-on-exec, so only commands from [`CredentialsFd::command`] inherit it,
+/// and it is closed in oktaws once dropped.
+#[derive(Debug)]
+pub struct CredentialsFd {
+    fd: OwnedFd,
+    profile: String,
+}
+
+impl CredentialsFd {
+    /// Write `credentials` for `profile` to a new memfd, and seal it against modification
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the memfd cannot be created, written or sealed
+    pub fn new(profile: &str, credentials: &Credentials) -> Result<Self> {
+        let mut contents = format!(
+            "[{profile}]\naws_access_key_id = {}\naws_secret_access_key = {}\n",
+            credentials.access_key_id(),
+            credentials.secret_access_key()
+        );
+        if let Some(session_token) = credentials.session_token() {
+            let _ = writeln!(contents, "aws_session_token = {session_token}");
+        }
+
+        let name = CString::new("oktaws-credentials")?;
+        // SAFETY: `name` is a valid C string, and the returned descriptor is checked before use
+        let raw = unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+        if raw < 0 {
+            return Err(eyre!(
+                "Unable to create memfd: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: `raw` is a newly created descriptor, owned by nothing else
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        let mut file = File::from(fd);
+        file.write_all(contents.as_bytes())?;
+        let fd = OwnedFd::from(file);
+
+        let seals =
+            libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+        // SAFETY: `fd` is a valid, open memfd
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+            return Err(eyre!(
+                "Unable to seal memfd: {}",
+                io::Error::last_os_error()
+            ));
+        }
+
+        Ok(Self {
+            fd,
+            profile: profile.to_string(),
+        })
+    }
+
+    /// The descriptor number, which is the same in the command
+    #[must_use]
+    pub fn raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    /// A command which inherits the memfd, and uses it as its credentials file.
+    /// The descriptor is only made inheritable in the command itself (after it has forked),
+    /// so that nothing else oktaws starts meanwhile gets it.
+    /// Drop this once the command has been spawned, so that oktaws does not keep the credentials open.
+    #[must_use]
+    pub fn command(&self, program: &str, args: &[String]) -> Command {
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env(
+                "AWS_SHARED_CREDENTIALS_FILE",
+                format!("/proc/self/fd/{}", self.raw_fd()),
+            )
+            .env("AWS_PROFILE", &self.profile);
+
+        for variable in OVERRIDING_VARIABLES {
+            command.env_remove(variable);
+        }
+
+        let fd = self.raw_fd();
+        // SAFETY: only fcntl, which is async-signal-safe, runs between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                if libc::fcntl(fd, libc::F_SETFD, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_the_command_inherits_credentials() -> Result<()> {
+        let credentials = Credentials::new(
+            "ACCESS_KEY",
+            "SECRET_KEY",
+            Some(String::from("SESSION_TOKEN")),
+            None,
+            "test",
+        );
+        let fd = CredentialsFd::new("prod", &credentials)?;
+        let path = format!("/proc/self/fd/{}", fd.raw_fd());
+
+        let mut child = fd
+            .command(
+                "sh",
+                &[
+                    String::from("-c"),
+                    String::from(
+                        "grep -q 'aws_session_token = SESSION_TOKEN' \"$AWS_SHARED_CREDENTIALS_FILE\" \
+                         && test \"$AWS_PROFILE\" = prod && test -z \"$AWS_ACCESS_KEY_ID\"",
+                    ),
+                ],
+            )
+            .spawn()?;
+
+        // Any other command started while the memfd is open doesn't get it
+        let other = Command::new("sh")
+            .args(["-c", &format!("test ! -e {path}")])
+            .status()
+            .await?;
+        assert!(other.success());
+
+        drop(fd);
+        assert!(child.wait().await?.success());
+
+        Ok(())
+    }
+}
@@ -1,17 +1,145 @@
+use crate::aws::{self, Identity};
+use crate::retry;
+use crate::tls;
+use crate::transport::Transport;
+
 use eyre::{Result, eyre};
 use regex::Regex;
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
-use reqwest_retry::{RetryTransientMiddleware, policies::ExponentialBackoff};
 use serde::Deserialize;
-use std::sync::LazyLock;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, LazyLock};
 use std::time::Duration;
 use std::time::SystemTime;
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
 const BASE_URL: &str = "https://portal.sso.us-east-1.amazonaws.com";
 
+/// A single HTTP client shared by every SSO client,
+/// so that connections to the portal are pooled and reused across profiles.
+static HTTP: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    http_client_builder()
+        .build()
+        .expect("Failed to build SSO portal HTTP client")
+});
+
+/// Return a builder for HTTP clients configured as the shared SSO portal client is
+#[must_use]
+pub fn http_client_builder() -> reqwest::ClientBuilder {
+    tls::client_builder()
+        .pool_idle_timeout(Duration::from_secs(90))
+        .pool_max_idle_per_host(32)
+        .tcp_keepalive(Duration::from_secs(60))
+        .http2_adaptive_window(true)
+        .http2_keep_alive_interval(Duration::from_secs(30))
+        .http2_keep_alive_while_idle(true)
+}
+
+/// Return the HTTP client used for talking to the SSO portal
+#[must_use]
+pub fn http_client() -> &'static reqwest::Client {
+    &HTTP
+}
+
+/// The transport clients use unless given another: the shared HTTP client
+fn default_transport() -> Arc<dyn Transport> {
+    Arc::new(http_client().clone())
+}
+
 pub struct Client {
     token: String,
+    transport: Arc<dyn Transport>,
+}
+
+/// The portal returned credentials which cannot be used as they are.
+/// This has been seen for roles restricted by SCPs or to particular regions,
+/// where the portal leaves out the session token rather than refusing outright.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "The AWS SSO portal returned incomplete credentials for {role_name} in {account_id} (missing {}); \
+     the role may be restricted by an SCP or to particular regions",
+    missing.join(", ")
+)]
+pub struct IncompleteCredentials {
+    pub account_id: String,
+    pub role_name: String,
+    pub missing: Vec<&'static str>,
+}
+
+/// Credentials as the portal returns them, with every field optional so that gaps can be explained
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PortalCredentials {
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    session_token: Option<String>,
+    expiration: Option<u64>,
+    #[serde(flatten)]
+    other: BTreeMap<String, Value>,
+}
+
+impl PortalCredentials {
+    /// Check every field is present (and not empty), noting any the portal added that oktaws doesn't know
+    fn validate(
+        self,
+        account_id: &str,
+        role_name: &str,
+    ) -> Result<aws_credential_types::Credentials, IncompleteCredentials> {
+        if !self.other.is_empty() {
+            warn!(
+                "The AWS SSO portal returned unexpected fields with the credentials for {role_name} in {account_id}: {}",
+                self.other.keys().cloned().collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        let present = |value: Option<String>| value.filter(|value| !value.is_empty());
+
+        match (
+            present(self.access_key_id),
+            present(self.secret_access_key),
+            present(self.session_token),
+            self.expiration.filter(|expiration| *expiration > 0),
+        ) {
+            (
+                Some(access_key_id),
+                Some(secret_access_key),
+                Some(session_token),
+                Some(expiration),
+            ) => {
+                let credentials = aws_credential_types::Credentials::new(
+                    access_key_id,
+                    secret_access_key,
+                    Some(session_token),
+                    Some(SystemTime::UNIX_EPOCH + Duration::from_millis(expiration)),
+                    "oktaws",
+                );
+                aws::issued(
+                    &credentials,
+                    Identity {
+                        account_id: account_id.to_string(),
+                        arn: None,
+                    },
+                );
+                Ok(credentials)
+            }
+            (access_key_id, secret_access_key, session_token, expiration) => {
+                Err(IncompleteCredentials {
+                    account_id: account_id.to_string(),
+                    role_name: role_name.to_string(),
+                    missing: [
+                        ("accessKeyId", access_key_id.is_none()),
+                        ("secretAccessKey", secret_access_key.is_none()),
+                        ("sessionToken", session_token.is_none()),
+                        ("expiration", expiration.is_none()),
+                    ]
+                    .into_iter()
+                    .filter_map(|(field, missing)| missing.then_some(field))
+                    .collect(),
+                })
+            }
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -21,6 +149,33 @@ pub struct Page<T> {
     pub result: Vec<T>,
 }
 
+/// Every item across the pages returned by `fetch`, which is given the previous page's `pagination_token` (none at first).
+/// A portal which hands back a token it has already given is an error, rather than an endless loop.
+async fn all_pages<T, F, Fut>(mut fetch: F) -> Result<Vec<T>>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<Page<T>>>,
+{
+    let mut items = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pagination_token = None;
+
+    loop {
+        let page = fetch(pagination_token.take()).await?;
+        items.extend(page.result);
+
+        match page.pagination_token {
+            Some(token) if !seen.insert(token.clone()) => {
+                return Err(eyre!(
+                    "The AWS SSO portal returned pagination token {token} twice"
+                ));
+            }
+            Some(token) => pagination_token = Some(token),
+            None => return Ok(items),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AppInstance {
@@ -30,6 +185,15 @@ pub struct AppInstance {
     pub application_id: String,
     pub application_name: String,
     pub icon: String,
+    pub search_metadata: Option<SearchMetadata>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct SearchMetadata {
+    pub account_id: Option<String>,
+    pub account_name: Option<String>,
+    pub account_email: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -49,46 +213,99 @@ impl Client {
     /// The function will error for network issues, or if the response is not parseable as expected
     ///
     pub async fn new(org_id: &str, auth_code: &str) -> Result<Self> {
+        Self::sign_in(default_transport(), org_id, auth_code).await
+    }
+
+    /// Sign in to the portal, sending every request (now and later) through `transport`
+    ///
+    /// # Errors
+    ///
+    /// The function will error for network issues, or if the response is not parseable as expected
+    ///
+    pub async fn sign_in(
+        transport: Arc<dyn Transport>,
+        org_id: &str,
+        auth_code: &str,
+    ) -> Result<Self> {
         #[derive(Deserialize)]
         struct SsoTokenResponse {
             token: String,
         }
 
         // Get SSO Token
-        let response = reqwest::Client::new()
+        let request = http_client()
             .post(format!("{BASE_URL}/auth/sso-token"))
             .form(&[("authCode", auth_code), ("orgId", org_id)])
-            .send()
-            .await?;
+            .build()?;
+        let response = retry::execute(&*transport, request).await?;
 
         let text = response.text().await?;
         trace!("Received {}", &text);
 
         let SsoTokenResponse { token } = serde_json::from_str(&text)?;
 
-        Ok(Self { token })
+        Ok(Self { token, transport })
+    }
+
+    /// A client using a token from an earlier sign-in, which may no longer be valid
+    #[must_use]
+    pub fn from_token(token: String) -> Self {
+        Self {
+            token,
+            transport: default_transport(),
+        }
+    }
+
+    /// Send requests through `transport`, instead of the shared HTTP client
+    #[must_use]
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Send `request` through the client's transport, retrying as [`retry::execute`] does
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        Ok(retry::execute(&*self.transport, request.build()?).await?)
     }
 
+    /// The portal token, to reuse in later runs
+    #[must_use]
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Every app instance, following the portal's pagination
+    ///
     /// # Errors
     ///
     /// The function will error for network issues, or if the response is not parseable as expected
     ///
     pub async fn app_instances(&self) -> Result<Vec<AppInstance>> {
-        let retry_policy = ExponentialBackoff::builder()
-            .retry_bounds(Duration::from_secs(1), Duration::from_secs(2))
-            .base(1)
-            .build_with_max_retries(5);
-
-        let client: ClientWithMiddleware = ClientBuilder::new(reqwest::Client::new())
-            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-            .build();
+        all_pages(|pagination_token| async move {
+            self.app_instances_page(pagination_token.as_deref()).await
+        })
+        .await
+    }
 
-        let response = client
+    /// A single page of app instances, starting from `pagination_token` (or the first page)
+    ///
+    /// # Errors
+    ///
+    /// The function will error for network issues, or if the response is not parseable as expected
+    ///
+    pub async fn app_instances_page(
+        &self,
+        pagination_token: Option<&str>,
+    ) -> Result<Page<AppInstance>> {
+        let mut request = http_client()
             .get(format!("{BASE_URL}/instance/appinstances"))
             .header("x-amz-sso_bearer_token", &self.token)
-            .header("x-amz-sso-bearer-token", &self.token)
-            .send()
-            .await?;
+            .header("x-amz-sso-bearer-token", &self.token);
+        if let Some(pagination_token) = pagination_token {
+            request = request.query(&[("paginationToken", pagination_token)]);
+        }
+
+        let response = self.send(request).await?;
 
         let status = response.status();
         let text = response.text().await?;
@@ -101,32 +318,45 @@ impl Client {
         }
 
         trace!("Received {}", &text);
-        let Page::<AppInstance> { result, .. } = serde_json::from_str(&text)?;
-        Ok(result)
+        Ok(serde_json::from_str(&text)?)
     }
 
+    /// Every profile (role) in an app instance, following the portal's pagination
+    ///
     /// # Errors
     ///
     /// The function will error for network issues, or if the response is not parseable as expected
     ///
     pub async fn profiles(&self, app_instance_id: &str) -> Result<Vec<Profile>> {
-        let retry_policy = ExponentialBackoff::builder()
-            .retry_bounds(Duration::from_secs(1), Duration::from_secs(2))
-            .base(2)
-            .build_with_max_retries(10);
-
-        let client: ClientWithMiddleware = ClientBuilder::new(reqwest::Client::new())
-            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-            .build();
+        all_pages(|pagination_token| async move {
+            self.profiles_page(app_instance_id, pagination_token.as_deref())
+                .await
+        })
+        .await
+    }
 
-        let response = client
+    /// A single page of an app instance's profiles, starting from `pagination_token` (or the first page)
+    ///
+    /// # Errors
+    ///
+    /// The function will error for network issues, or if the response is not parseable as expected
+    ///
+    pub async fn profiles_page(
+        &self,
+        app_instance_id: &str,
+        pagination_token: Option<&str>,
+    ) -> Result<Page<Profile>> {
+        let mut request = http_client()
             .get(format!(
                 "{BASE_URL}/instance/appinstance/{app_instance_id}/profiles"
             ))
             .header("x-amz-sso_bearer_token", &self.token)
-            .header("x-amz-sso-bearer-token", &self.token)
-            .send()
-            .await?;
+            .header("x-amz-sso-bearer-token", &self.token);
+        if let Some(pagination_token) = pagination_token {
+            request = request.query(&[("paginationToken", pagination_token)]);
+        }
+
+        let response = self.send(request).await?;
 
         let status = response.status();
         let text = response.text().await?;
@@ -139,80 +369,317 @@ impl Client {
         }
 
         trace!("Received {}", &text);
-        let Page::<Profile> { result, .. } = serde_json::from_str(&text)?;
-        Ok(result)
+        Ok(serde_json::from_str(&text)?)
     }
 
+    /// Fetch credentials for a role in an account.
+    /// If `duration_seconds` is set, a session of that length is requested instead of the role's default.
+    ///
     /// # Errors
     ///
-    /// The function will error for network issues, or if the response is not parseable as expected
+    /// The function will error for network issues, or if the response is not parseable as expected.
+    /// Credentials missing a field (see [`IncompleteCredentials`]) are an error, rather than being written out broken.
     ///
     pub async fn credentials(
         &self,
         account_id: &str,
         role_name: &str,
+        duration_seconds: Option<i32>,
     ) -> Result<aws_credential_types::Credentials> {
-        #[derive(Debug, Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct Credentials {
-            access_key_id: String,
-            secret_access_key: String,
-            session_token: String,
-            expiration: u64,
-        }
-
         #[derive(Debug, Deserialize)]
         #[serde(rename_all = "camelCase")]
         struct RoleCredentials {
-            role_credentials: Credentials,
+            #[serde(default)]
+            role_credentials: Option<PortalCredentials>,
         }
 
         debug!("Requesting credentials for account: {account_id}, role: {role_name}");
 
-        let RoleCredentials { role_credentials } = reqwest::Client::new()
-            .get(format!("{BASE_URL}/federation/credentials/"))
-            .query(&[
-                ("account_id", account_id),
-                ("role_name", role_name),
-                ("debug", "true"),
-            ])
-            .header("x-amz-sso_bearer_token", &self.token)
-            .header("x-amz-sso-bearer-token", &self.token)
-            .send()
+        let mut query = vec![
+            ("account_id", account_id.to_string()),
+            ("role_name", role_name.to_string()),
+            ("debug", "true".to_string()),
+        ];
+        if let Some(duration_seconds) = duration_seconds {
+            query.push(("duration_seconds", duration_seconds.to_string()));
+        }
+
+        let RoleCredentials { role_credentials } = self
+            .send(
+                http_client()
+                    .get(format!("{BASE_URL}/federation/credentials/"))
+                    .query(&query)
+                    .header("x-amz-sso_bearer_token", &self.token)
+                    .header("x-amz-sso-bearer-token", &self.token),
+            )
             .await?
             .json()
             .await?;
 
-        Ok(aws_credential_types::Credentials::new(
-            role_credentials.access_key_id,
-            role_credentials.secret_access_key,
-            Some(role_credentials.session_token),
-            Some(SystemTime::UNIX_EPOCH + Duration::from_millis(role_credentials.expiration)),
-            "oktaws",
-        ))
+        role_credentials
+            .unwrap_or_default()
+            .validate(account_id, role_name)
+            .map_err(Into::into)
+    }
+}
+
+const DEFAULT_ACCOUNT_NAME_PATTERN: &str = r"\((?P<name>.+)\)";
+const DEFAULT_ACCOUNT_ID_PATTERN: &str = r"^(?P<id>\d+)";
+
+static DEFAULT_ACCOUNT_NAMING: LazyLock<AccountNaming> = LazyLock::new(|| {
+    AccountNaming::new(None, None).expect("Failed to compile default account naming patterns")
+});
+
+/// How to extract account names and IDs from AWS SSO app instance names,
+/// e.g. `123456789012 (Production)`.
+/// The patterns are regexes, with the name in a `name` capture group and the ID in an `id` capture group.
+#[derive(Clone, Debug)]
+pub struct AccountNaming {
+    name: Regex,
+    id: Regex,
+}
+
+impl Default for AccountNaming {
+    fn default() -> Self {
+        DEFAULT_ACCOUNT_NAMING.clone()
     }
 }
 
-static ACCOUNT_NAME_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"\((.+)\)").expect("Failed to compile account name regex"));
+impl AccountNaming {
+    /// Compile account naming patterns, using the defaults for any which are not given
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a pattern is not a valid regex, or is missing its capture group
+    pub fn new(name: Option<&str>, id: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            name: Self::compile(name.unwrap_or(DEFAULT_ACCOUNT_NAME_PATTERN), "name")?,
+            id: Self::compile(id.unwrap_or(DEFAULT_ACCOUNT_ID_PATTERN), "id")?,
+        })
+    }
+
+    /// The pattern extracting account names
+    #[must_use]
+    pub fn name_pattern(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The pattern extracting account IDs
+    #[must_use]
+    pub fn id_pattern(&self) -> &str {
+        self.id.as_str()
+    }
 
-static ACCOUNT_ID_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"^(\d+)").expect("Failed to compile account ID regex"));
+    fn compile(pattern: &str, group: &str) -> Result<Regex> {
+        let regex = Regex::new(pattern)
+            .map_err(|e| eyre!("Invalid account {group} pattern {pattern} ({e})"))?;
+
+        if regex.capture_names().flatten().any(|name| name == group) {
+            Ok(regex)
+        } else {
+            Err(eyre!(
+                "Account {group} pattern {pattern} must have a (?P<{group}>...) capture group"
+            ))
+        }
+    }
+
+    fn capture<'a>(regex: &Regex, group: &str, text: &'a str) -> Option<&'a str> {
+        regex
+            .captures(text)
+            .and_then(|captures| captures.name(group))
+            .map(|mat| mat.as_str().trim())
+    }
+}
 
 impl AppInstance {
     #[must_use]
-    pub fn account_name(&self) -> Option<String> {
-        ACCOUNT_NAME_REGEX
-            .captures(&self.name)
-            .and_then(|captures| captures.get(1))
-            .map(|mat| mat.as_str().to_lowercase().replace([' ', '_'], "-"))
+    pub fn account_name(&self, naming: &AccountNaming) -> Option<String> {
+        AccountNaming::capture(&naming.name, "name", &self.name)
+            .map(|name| name.to_lowercase().replace([' ', '_'], "-"))
+    }
+
+    #[must_use]
+    pub fn account_email(&self) -> Option<&str> {
+        self.search_metadata
+            .as_ref()
+            .and_then(|metadata| metadata.account_email.as_deref())
     }
 
     #[must_use]
-    pub fn account_id(&self) -> Option<&str> {
-        ACCOUNT_ID_REGEX
-            .captures(&self.name)
-            .and_then(|captures| captures.get(1))
-            .map(|mat| mat.as_str())
+    pub fn account_id(&self, naming: &AccountNaming) -> Option<&str> {
+        AccountNaming::capture(&naming.id, "id", &self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::transport::Canned;
+
+    fn app_instance(name: &str) -> AppInstance {
+        AppInstance {
+            id: String::from("ins-1234"),
+            name: name.to_string(),
+            description: String::new(),
+            application_id: String::from("app-1234"),
+            application_name: String::from("AWS Account"),
+            icon: String::new(),
+            search_metadata: None,
+        }
+    }
+
+    #[test]
+    fn default_naming() {
+        let naming = AccountNaming::default();
+        let instance = app_instance("123456789012 (Production Account)");
+
+        assert_eq!(
+            instance.account_name(&naming),
+            Some(String::from("production-account"))
+        );
+        assert_eq!(instance.account_id(&naming), Some("123456789012"));
+    }
+
+    #[test]
+    fn custom_naming_conventions() -> Result<()> {
+        // `AWS - Prod (1234)`
+        let naming = AccountNaming::new(
+            Some(r"^AWS - (?P<name>.+) \(\d+\)$"),
+            Some(r"\((?P<id>\d+)\)$"),
+        )?;
+        let instance = app_instance("AWS - Prod (123456789012)");
+        assert_eq!(instance.account_name(&naming), Some(String::from("prod")));
+        assert_eq!(instance.account_id(&naming), Some("123456789012"));
+
+        // `prod_data | 123456789012`
+        let naming = AccountNaming::new(Some(r"^(?P<name>[^|]+)\|"), Some(r"\|\s*(?P<id>\d{12})"))?;
+        let instance = app_instance("prod_data | 123456789012");
+        assert_eq!(
+            instance.account_name(&naming),
+            Some(String::from("prod-data"))
+        );
+        assert_eq!(instance.account_id(&naming), Some("123456789012"));
+
+        // The name doesn't match the configured convention
+        assert_eq!(
+            app_instance("123456789012 (Production)").account_name(&naming),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn incomplete_credentials_are_explained() -> Result<()> {
+        let credentials: PortalCredentials = serde_json::from_str(
+            r#"{
+                "accessKeyId": "ASIAEXAMPLE",
+                "secretAccessKey": "SECRET",
+                "sessionToken": "",
+                "expiration": 1700000000000,
+                "regionRestriction": "eu-west-1"
+            }"#,
+        )?;
+
+        let error = credentials
+            .validate("123456789012", "ReadOnly")
+            .unwrap_err();
+        assert_eq!(error.missing, vec!["sessionToken"]);
+        assert_eq!(
+            error.to_string(),
+            "The AWS SSO portal returned incomplete credentials for ReadOnly in 123456789012 (missing sessionToken); \
+             the role may be restricted by an SCP or to particular regions"
+        );
+
+        let credentials: PortalCredentials = serde_json::from_str(
+            r#"{
+                "accessKeyId": "ASIAEXAMPLE",
+                "secretAccessKey": "SECRET",
+                "sessionToken": "TOKEN",
+                "expiration": 1700000000000
+            }"#,
+        )?;
+        assert!(credentials.validate("123456789012", "ReadOnly").is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn naming_requires_capture_group() {
+        assert_eq!(
+            AccountNaming::new(Some(r"\((.+)\)"), None)
+                .unwrap_err()
+                .to_string(),
+            r"Account name pattern \((.+)\) must have a (?P<name>...) capture group"
+        );
+        assert!(AccountNaming::new(None, Some("(")).is_err());
+    }
+
+    #[tokio::test]
+    async fn follows_every_page() -> Result<()> {
+        let pages = |token: Option<String>| async move {
+            Ok(match token.as_deref() {
+                None => Page {
+                    pagination_token: Some(String::from("second")),
+                    result: vec![1, 2],
+                },
+                Some("second") => Page {
+                    pagination_token: Some(String::from("third")),
+                    result: vec![3],
+                },
+                _ => Page {
+                    pagination_token: None,
+                    result: vec![4],
+                },
+            })
+        };
+        assert_eq!(all_pages(pages).await?, vec![1, 2, 3, 4]);
+
+        let looping = |_| async {
+            Ok(Page {
+                pagination_token: Some(String::from("again")),
+                result: vec![1],
+            })
+        };
+        assert!(all_pages::<i32, _, _>(looping).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetches_profiles_through_the_transport() -> Result<()> {
+        let profile = |name: &str| {
+            format!(
+                r#"{{"id": "p-{name}", "name": "{name}", "description": "", "url": "", "protocol": "SAML", "relayState": null}}"#
+            )
+        };
+        let transport = Arc::new(
+            Canned::new()
+                .respond(
+                    200,
+                    format!(
+                        r#"{{"paginationToken": "next", "result": [{}]}}"#,
+                        profile("Admin")
+                    ),
+                )
+                .respond(200, format!(r#"{{"result": [{}]}}"#, profile("ReadOnly"))),
+        );
+
+        let client = Client::from_token(String::from("token")).with_transport(transport.clone());
+        let profiles = client.profiles("ins-1234").await?;
+
+        assert_eq!(
+            profiles
+                .iter()
+                .map(|profile| profile.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Admin", "ReadOnly"]
+        );
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].1.query(), Some("paginationToken=next"));
+
+        Ok(())
     }
 }
@@ -0,0 +1,251 @@
+//! Benchmarking the refresh pipeline against a mock backend, so that performance regressions
+//! (in serialization, locking or concurrency) can be measured without an Okta organization.
+//!
+//! Each iteration discovers a synthetic set of accounts and roles, fetches credentials for every profile
+//! concurrently (through the coalescer and its locks, as a refresh does) from a mock which answers after a fixed latency,
+//! then writes them all in one transaction. Okta and AWS are never contacted.
+
+use crate::aws::profile::Store as ProfileStore;
+use crate::coalesce::Coalescer;
+use crate::discovery;
+use crate::okta::applications::{
+    AppLinkAccountRoleMapping, IntegrationType, Precedence, dedupe_account_mappings,
+};
+use crate::registry::{self, Registry};
+use crate::transaction::{self, Staged};
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use aws_credential_types::Credentials;
+use eyre::{Result, eyre};
+use futures::stream::{self, StreamExt};
+use tokio::time::sleep;
+
+/// The organization name the mock profiles are under
+const ORGANIZATION: &str = "bench";
+
+/// What to benchmark
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub iterations: usize,
+    /// How many profiles each iteration refreshes
+    pub profiles: usize,
+    /// How long the mock backend takes to answer each request for credentials
+    pub latency: Duration,
+    /// How many profiles are fetched at once
+    pub concurrency: usize,
+}
+
+/// Timings for one stage of the pipeline, across every iteration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stage {
+    pub name: &'static str,
+    samples: Vec<Duration>,
+}
+
+impl Stage {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            samples: Vec::new(),
+        }
+    }
+
+    /// The `p`th percentile (nearest rank) of the samples
+    #[must_use]
+    pub fn percentile(&self, p: usize) -> Duration {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+
+        let rank = (sorted.len() * p).div_ceil(100).max(1);
+        sorted.get(rank - 1).copied().unwrap_or_default()
+    }
+}
+
+/// The results of a benchmark
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub stages: Vec<Stage>,
+    /// How many profiles were refreshed, across every iteration
+    pub profiles: usize,
+    pub elapsed: Duration,
+}
+
+/// Run the benchmark, with its files in `dir`
+///
+/// # Errors
+///
+/// Will return `Err` if any stage of the pipeline fails
+pub async fn run(dir: &Path, options: &Options) -> Result<Report> {
+    if options.iterations == 0 || options.profiles == 0 {
+        return Err(eyre!("Nothing to benchmark"));
+    }
+
+    let mut discovery_stage = Stage::new("discovery");
+    let mut credentials_stage = Stage::new("credentials");
+    let mut write_stage = Stage::new("write");
+    let mut iteration_stage = Stage::new("iteration");
+
+    let started = Instant::now();
+    for iteration in 0..options.iterations {
+        // A fresh cache for each iteration, so that credentials are fetched rather than coalesced
+        let iteration_dir = dir.join(format!("iteration-{iteration}"));
+        let iteration_started = Instant::now();
+
+        let stage_started = Instant::now();
+        let mappings =
+            dedupe_account_mappings(mock_mappings(options.profiles), &Precedence::default());
+        discovery::announce(&dir.join(discovery::DIR), ORGANIZATION, &mappings)?;
+        discovery_stage.samples.push(stage_started.elapsed());
+
+        let stage_started = Instant::now();
+        let coalescer = Coalescer::new(iteration_dir.join("cache"));
+        let credentials = stream::iter(mappings.iter().map(|mapping| {
+            let coalescer = &coalescer;
+            async move {
+                let credentials = coalescer
+                    .credentials(ORGANIZATION, &mapping.account_name, || {
+                        mock_credentials(options.latency)
+                    })
+                    .await?;
+                Ok((mapping.account_name.clone(), credentials))
+            }
+        }))
+        .buffer_unordered(options.concurrency.max(1))
+        .collect::<Vec<Result<_>>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+        credentials_stage.samples.push(stage_started.elapsed());
+
+        let stage_started = Instant::now();
+        let mut store = ProfileStore::load(Some(&iteration_dir.join("credentials")))?;
+        let mut registry = Registry::load(&iteration_dir.join(registry::FILE_NAME))?;
+        for (profile, credentials) in &credentials {
+            store.upsert_credential(profile, credentials)?;
+            registry.record(profile, ORGANIZATION);
+        }
+        transaction::commit(&iteration_dir, &[&store as &dyn Staged, &registry])?;
+        write_stage.samples.push(stage_started.elapsed());
+
+        iteration_stage.samples.push(iteration_started.elapsed());
+        fs::remove_dir_all(&iteration_dir)?;
+    }
+
+    Ok(Report {
+        stages: vec![
+            discovery_stage,
+            credentials_stage,
+            write_stage,
+            iteration_stage,
+        ],
+        profiles: options.iterations * options.profiles,
+        elapsed: started.elapsed(),
+    })
+}
+
+fn mock_mappings(profiles: usize) -> Vec<AppLinkAccountRoleMapping> {
+    (0..profiles)
+        .map(|n| AppLinkAccountRoleMapping {
+            account_name: format!("account-{n}"),
+            account_id: Some(format!("{:012}", 100_000_000_000 + n)),
+            account_email: None,
+            account_description: None,
+            role_names: vec![String::from("Admin"), String::from("ReadOnly")],
+            application_name: String::from("AWS"),
+            integration_type: if n % 2 == 0 {
+                IntegrationType::Federated
+            } else {
+                IntegrationType::IdentityCenter
+            },
+        })
+        .collect()
+}
+
+async fn mock_credentials(latency: Duration) -> Result<Credentials> {
+    sleep(latency).await;
+
+    Ok(Credentials::new(
+        "ASIAMOCKACCESSKEY",
+        "mock-secret-access-key",
+        Some(String::from("mock-session-token")),
+        Some(SystemTime::now() + Duration::from_hours(1)),
+        "bench",
+    ))
+}
+
+/// A table of the percentiles for each stage, and the overall throughput
+#[must_use]
+pub fn render(report: &Report) -> String {
+    let mut table = format!(
+        "{:12}  {:>10}  {:>10}  {:>10}  {:>10}\n",
+        "Stage", "p50", "p90", "p99", "max"
+    );
+
+    for stage in &report.stages {
+        let _ = writeln!(
+            table,
+            "{:12}  {:>10.2?}  {:>10.2?}  {:>10.2?}  {:>10.2?}",
+            stage.name,
+            stage.percentile(50),
+            stage.percentile(90),
+            stage.percentile(99),
+            stage.percentile(100)
+        );
+    }
+
+    let _ = writeln!(
+        table,
+        "\n{} profiles in {:.2?} ({:.1} profiles/s)",
+        report.profiles,
+        report.elapsed,
+        f64::from(u32::try_from(report.profiles).unwrap_or(u32::MAX))
+            / report.elapsed.as_secs_f64()
+    );
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let stage = Stage {
+            name: "mock",
+            samples: (1..=10).rev().map(Duration::from_millis).collect(),
+        };
+
+        assert_eq!(stage.percentile(50), Duration::from_millis(5));
+        assert_eq!(stage.percentile(90), Duration::from_millis(9));
+        assert_eq!(stage.percentile(99), Duration::from_millis(10));
+        assert_eq!(Stage::new("empty").percentile(50), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn runs_against_mock() -> Result<()> {
+        let dir = tempdir()?;
+
+        let report = run(
+            dir.path(),
+            &Options {
+                iterations: 2,
+                profiles: 3,
+                latency: Duration::ZERO,
+                concurrency: 2,
+            },
+        )
+        .await?;
+
+        assert_eq!(report.profiles, 6);
+        assert!(report.stages.iter().all(|stage| stage.samples.len() == 2));
+
+        Ok(())
+    }
+}
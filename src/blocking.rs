@@ -0,0 +1,166 @@
+//! Running prompts and keyring accesses off the async reactor.
+//!
+//! Waiting for the user to type a password or MFA code can take minutes, and some keyrings
+//! block on a desktop unlock dialog. Done on a reactor thread, that stalls every other task
+//! (and every timeout) until it returns, and with `--single-thread` there is no other thread to take over.
+//!
+//! Profiles are fetched in parallel, so prompts take turns at the terminal (see [`prompt`]),
+//! and time spent at them doesn't count against any profile's timeout (see [`timeout`]).
+
+use std::future::Future;
+use std::sync::{Mutex as StdMutex, PoisonError};
+use std::time::Duration;
+
+use eyre::{Result, eyre};
+use tokio::sync::Mutex;
+use tokio::task::spawn_blocking;
+use tokio::time::error::Elapsed;
+use tokio::time::{Instant, timeout as within};
+
+/// The terminal, held by whichever prompt is on it
+static TERMINAL: Mutex<()> = Mutex::const_new(());
+
+/// How long prompts have been on the terminal, and since when the current one has been (if there is one)
+static PROMPTED: StdMutex<(Duration, Option<Instant>)> = StdMutex::new((Duration::ZERO, None));
+
+/// How long prompts have been on the terminal in total, and whether one is now
+fn prompted() -> (Duration, bool) {
+    let (finished, since) = *PROMPTED.lock().unwrap_or_else(PoisonError::into_inner);
+    (
+        finished + since.map_or(Duration::ZERO, |since| since.elapsed()),
+        since.is_some(),
+    )
+}
+
+/// Marks the terminal as prompting until dropped (even when the prompt's future is)
+struct Prompting;
+
+impl Prompting {
+    fn start() -> Self {
+        PROMPTED.lock().unwrap_or_else(PoisonError::into_inner).1 = Some(Instant::now());
+        Self
+    }
+}
+
+impl Drop for Prompting {
+    fn drop(&mut self) {
+        let mut prompted = PROMPTED.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(since) = prompted.1.take() {
+            prompted.0 += since.elapsed();
+        }
+    }
+}
+
+/// Run `f` on tokio's blocking thread pool, and wait for it without blocking the reactor
+///
+/// # Errors
+///
+/// Will return `Err` if `f` does, or if it panics
+pub async fn run<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_blocking(f)
+        .await
+        .map_err(|e| eyre!("Blocking task failed ({e})"))?
+}
+
+/// Run the prompt `f` off the reactor, once no other prompt is on the terminal
+///
+/// # Errors
+///
+/// Will return `Err` if `f` does, or if it panics
+pub async fn prompt<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let _terminal = TERMINAL.lock().await;
+    let _prompting = Prompting::start();
+
+    run(f).await
+}
+
+/// Wait for `future` for up to `limit`, not counting the time any prompt is on the terminal meanwhile,
+/// as the user answering one profile's prompt holds up the others' too
+///
+/// # Errors
+///
+/// Will return `Err` if `future` has not finished in time
+pub async fn timeout<F: Future>(limit: Duration, future: F) -> Result<F::Output, Elapsed> {
+    let started = Instant::now();
+    let (before, _) = prompted();
+    tokio::pin!(future);
+
+    let mut remaining = limit;
+    loop {
+        match within(remaining, &mut future).await {
+            Ok(output) => return Ok(output),
+            Err(elapsed) => {
+                let (now, prompting) = prompted();
+                let used = started.elapsed().saturating_sub(now.saturating_sub(before));
+                if used >= limit && !prompting {
+                    return Err(elapsed);
+                }
+                // While a prompt is up, check back now and then for when it has been answered
+                remaining = limit.saturating_sub(used).max(Duration::from_millis(100));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use tokio::time::{Instant, sleep};
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn reactor_keeps_running() -> Result<()> {
+        let started = Instant::now();
+
+        let (blocked, timer) = tokio::join!(
+            run(|| {
+                std::thread::sleep(Duration::from_millis(200));
+                Ok("typed")
+            }),
+            async {
+                sleep(Duration::from_millis(10)).await;
+                started.elapsed()
+            }
+        );
+
+        assert_eq!(blocked?, "typed");
+        assert!(timer < Duration::from_millis(200));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prompts_take_turns_and_stop_the_clock() -> Result<()> {
+        let (answered, timed) = tokio::join!(
+            prompt(|| {
+                std::thread::sleep(Duration::from_millis(300));
+                Ok("answered")
+            }),
+            timeout(Duration::from_millis(100), async {
+                sleep(Duration::from_millis(10)).await;
+                // Waits for the first prompt to be answered
+                prompt(|| Ok("second")).await
+            })
+        );
+
+        assert_eq!(answered?, "answered");
+        assert_eq!(timed.map_err(|e| eyre!(e))??, "second");
+        assert!(
+            timeout(Duration::from_millis(10), sleep(Duration::from_secs(1)))
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
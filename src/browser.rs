@@ -0,0 +1,265 @@
+//! Opening pages in the browser: console sessions, and the pages sign-in sends the user to.
+//!
+//! The AWS console only allows one session per browser profile,
+//! so working in several accounts at once needs a separate cookie jar for each.
+//!
+//! Over SSH, or without a display, there is no browser to open. Pages are then shown as their URL and a QR code
+//! (to open on a phone, or copy to another machine), as they always are with `--no-browser`.
+
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use eyre::{Error, Result, eyre};
+use qrcode::QrCode;
+use qrcode::render::unicode::Dense1x2;
+use tracing::{debug, info};
+use url::Url;
+
+/// The directory (in the oktaws state directory) separate browser profiles are kept in
+pub const DIR: &str = "browsers";
+
+/// How to isolate each console session
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Browser {
+    /// A Firefox Multi-Account Container per profile.
+    /// Needs the "Open external links in a container" extension, which handles `ext+container:` URLs.
+    #[default]
+    Firefox,
+    /// A separate Chrome profile (user data directory) per profile
+    Chrome,
+}
+
+impl FromStr for Browser {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "firefox" => Ok(Self::Firefox),
+            "chrome" | "chromium" => Ok(Self::Chrome),
+            _ => Err(eyre!("Unknown browser {s}, expected firefox or chrome")),
+        }
+    }
+}
+
+impl fmt::Display for Browser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Firefox => write!(f, "firefox"),
+            Self::Chrome => write!(f, "chrome"),
+        }
+    }
+}
+
+impl Browser {
+    /// The command which launches the browser, unless overridden
+    #[must_use]
+    pub const fn default_command(self) -> &'static str {
+        match self {
+            Self::Firefox => "firefox",
+            Self::Chrome => "google-chrome",
+        }
+    }
+
+    /// The arguments which open `url` in the container called `container`.
+    /// Chrome profiles are kept under `data_dir`.
+    #[must_use]
+    pub fn args(self, container: &str, url: &Url, data_dir: &Path) -> Vec<String> {
+        match self {
+            Self::Firefox => vec![container_url(container, url)],
+            Self::Chrome => vec![
+                format!(
+                    "--user-data-dir={}",
+                    data_dir.join(container).to_string_lossy()
+                ),
+                String::from("--no-first-run"),
+                url.to_string(),
+            ],
+        }
+    }
+
+    /// Open `url` in the container called `container`, without waiting for the browser to exit
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the browser cannot be started
+    pub fn open(
+        self,
+        command: Option<&str>,
+        container: &str,
+        url: &Url,
+        data_dir: &Path,
+    ) -> Result<()> {
+        let command = command.unwrap_or_else(|| self.default_command());
+        debug!("Opening {container} with {command}");
+
+        Command::new(command)
+            .args(self.args(container, url, data_dir))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|_| ())
+            .map_err(|e| eyre!("Unable to start {command} ({e})"))
+    }
+}
+
+static NO_BROWSER: OnceLock<bool> = OnceLock::new();
+
+/// Never open a browser, only showing URLs, for the whole process (`--no-browser`).
+/// Only the first call has any effect.
+pub fn install(no_browser: bool) {
+    let _ = NO_BROWSER.set(no_browser);
+}
+
+/// Whether there is no browser to open in a session whose environment variables `var` returns:
+/// over SSH, or (on Linux and the BSDs) without a display
+#[must_use]
+pub fn headless_with(var: impl Fn(&str) -> Option<String>) -> bool {
+    let set = |name: &str| var(name).is_some_and(|value| !value.is_empty());
+
+    if set("SSH_CONNECTION") || set("SSH_TTY") {
+        return true;
+    }
+
+    cfg!(all(unix, not(target_os = "macos"))) && !set("DISPLAY") && !set("WAYLAND_DISPLAY")
+}
+
+/// Whether URLs can be opened in a browser here
+#[must_use]
+pub fn can_open() -> bool {
+    !NO_BROWSER.get().copied().unwrap_or_default() && !headless_with(|name| env::var(name).ok())
+}
+
+/// Open `url` in the default browser, without waiting for it
+///
+/// # Errors
+///
+/// Will return `Err` if the browser cannot be started
+pub fn open_default(url: &Url) -> Result<()> {
+    let (command, args): (&str, &[&str]) = if cfg!(target_os = "macos") {
+        ("open", &[])
+    } else if cfg!(windows) {
+        // Not `cmd /c start`, which splits the URL at each `&`
+        ("rundll32", &["url.dll,FileProtocolHandler"])
+    } else {
+        ("xdg-open", &[])
+    };
+
+    Command::new(command)
+        .args(args)
+        .arg(url.as_str())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| eyre!("Unable to start {command} ({e})"))
+}
+
+/// `url` as a QR code, for a terminal
+///
+/// # Errors
+///
+/// Will return `Err` if the URL is too long for a QR code
+pub fn qr_code(url: &Url) -> Result<String> {
+    Ok(QrCode::new(url.as_str())?
+        .render::<Dense1x2>()
+        .quiet_zone(true)
+        .build())
+}
+
+/// Send the user to `url`, to `purpose` (e.g. `sign in to IAM Identity Center`): in the browser where there is one,
+/// and otherwise by showing the URL, with a QR code, on stderr
+pub fn show(url: &Url, purpose: &str) {
+    if can_open() {
+        match open_default(url) {
+            Ok(()) => {
+                info!("Opened the browser to {purpose}");
+                return;
+            }
+            Err(e) => debug!("Unable to open the browser ({e})"),
+        }
+    }
+
+    eprintln!("To {purpose}, open {url}");
+    match qr_code(url) {
+        Ok(code) => eprintln!("{code}"),
+        Err(e) => debug!("Unable to show {url} as a QR code ({e})"),
+    }
+}
+
+/// A URL which Firefox opens in the named Multi-Account Container, creating it if needed
+#[must_use]
+pub fn container_url(container: &str, url: &Url) -> String {
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("name", container)
+        .append_pair("url", url.as_str())
+        .finish();
+
+    format!("ext+container:{query}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firefox_container() -> Result<()> {
+        let url = Url::parse("https://signin.aws.amazon.com/federation?Action=login")?;
+
+        assert_eq!(
+            Browser::Firefox.args("prod", &url, Path::new("/tmp")),
+            vec![String::from(
+                "ext+container:name=prod&url=https%3A%2F%2Fsignin.aws.amazon.com%2Ffederation%3FAction%3Dlogin"
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn headless_over_ssh() -> Result<()> {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| (*value).to_string())
+            }
+        };
+
+        assert!(headless_with(env(&[
+            ("SSH_CONNECTION", "10.0.0.1 50000 10.0.0.2 22"),
+            ("DISPLAY", ":0"),
+        ])));
+        assert!(!headless_with(env(&[("DISPLAY", ":0")])));
+        assert_eq!(
+            headless_with(env(&[])),
+            cfg!(all(unix, not(target_os = "macos")))
+        );
+
+        let url = Url::parse("https://device.sso.us-east-1.amazonaws.com/?user_code=ABCD-EFGH")?;
+        assert!(qr_code(&url)?.lines().count() > 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn chrome_profile() -> Result<()> {
+        let url = Url::parse("https://signin.aws.amazon.com/federation")?;
+
+        assert_eq!(
+            Browser::Chrome.args("prod", &url, Path::new("/tmp/profiles")),
+            vec![
+                String::from("--user-data-dir=/tmp/profiles/prod"),
+                String::from("--no-first-run"),
+                String::from("https://signin.aws.amazon.com/federation"),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+//! Coalesces simultaneous requests for the same profile's credentials, across processes.
+//!
+//! When many processes ask for one profile at once (e.g. the AWS CLI fanning out to `credential_process`),
+//! the first takes a lock and fetches the credentials, while the rest wait on the lock
+//! and then reuse what it fetched, so the portal is only hit once.
+//!
+//! A coalescer which [reuses unexpired credentials](Coalescer::reusing) goes further, and answers with any
+//! credentials which are not about to expire, however long ago they were fetched. Repeated `oktaws exec` runs
+//! then skip Okta and STS altogether. Those are kept by [`key`], which changes whenever the account or role does.
+
+use crate::config::profile::Profile;
+use crate::events::{self, Event};
+use crate::lock::FileLock;
+use crate::schedule::MIN_MARGIN;
+
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aws_credential_types::Credentials;
+use eyre::{Result, eyre};
+use ring::digest::{SHA256, digest};
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+use tracing::{debug, instrument};
+
+/// The directory (in the oktaws cache directory) recently fetched credentials are kept in
+pub const DIR: &str = "credentials";
+
+/// Credentials fetched less than this long ago are shared with other requests for the same profile
+pub const WINDOW: Duration = Duration::from_secs(60);
+
+static COALESCED: AtomicU64 = AtomicU64::new(0);
+
+/// How many requests in this process were answered with credentials fetched by another request
+#[must_use]
+pub fn coalesced() -> u64 {
+    COALESCED.load(Ordering::Relaxed)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Cached {
+    fetched_at: u64,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    expires_at: Option<u64>,
+}
+
+impl Cached {
+    fn new(credentials: &Credentials, fetched_at: SystemTime) -> Self {
+        Self {
+            fetched_at: epoch_seconds(fetched_at),
+            access_key_id: credentials.access_key_id().to_string(),
+            secret_access_key: credentials.secret_access_key().to_string(),
+            session_token: credentials.session_token().map(ToString::to_string),
+            expires_at: credentials.expiry().map(epoch_seconds),
+        }
+    }
+
+    /// Whether these credentials were fetched recently enough to share (or may be reused however old),
+    /// and are not about to expire
+    fn is_fresh(&self, now: SystemTime, reuse: bool) -> bool {
+        let now = epoch_seconds(now);
+        let recent = now.saturating_sub(self.fetched_at) < WINDOW.as_secs();
+        let valid = self
+            .expires_at
+            .is_none_or(|expires_at| expires_at > now + MIN_MARGIN.as_secs());
+
+        (recent || reuse) && valid
+    }
+
+    fn into_credentials(self) -> Credentials {
+        Credentials::new(
+            self.access_key_id,
+            self.secret_access_key,
+            self.session_token,
+            self.expires_at
+                .map(|expires_at| UNIX_EPOCH + Duration::from_secs(expires_at)),
+            "oktaws",
+        )
+    }
+}
+
+fn epoch_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// The name `profile`'s credentials are kept under (with the role it is overridden with, if any).
+/// It covers the account, role (and any role chained into) and session duration,
+/// so that credentials are not reused, or shared, once any of them change.
+#[must_use]
+pub fn key(profile: &Profile, role_override: Option<&str>) -> String {
+    let identity = serde_json::json!([
+        profile.application_name,
+        profile.account,
+        profile.roles,
+        profile.chain,
+        profile.role_rules,
+        role_override,
+        profile.duration_seconds,
+    ]);
+    let fingerprint = digest(&SHA256, identity.to_string().as_bytes())
+        .as_ref()
+        .iter()
+        .take(8)
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        });
+
+    format!("{}.{fingerprint}", profile.name)
+}
+
+/// A directory of recently-fetched credentials, one file (and lock) per profile
+#[derive(Clone, Debug)]
+pub struct Coalescer {
+    dir: PathBuf,
+    reuse: bool,
+}
+
+impl Coalescer {
+    #[must_use]
+    pub const fn new(dir: PathBuf) -> Self {
+        Self { dir, reuse: false }
+    }
+
+    /// Whether to reuse any unexpired credentials, rather than only those fetched within the last `WINDOW`
+    #[must_use]
+    pub const fn reusing(mut self, reuse: bool) -> Self {
+        self.reuse = reuse;
+        self
+    }
+
+    fn path(&self, organization: &str, profile: &str) -> PathBuf {
+        self.dir.join(organization).join(format!("{profile}.json"))
+    }
+
+    /// Return credentials for the profile, fetching them with `fetch` unless
+    /// another request fetched them within the last `WINDOW` (or at all, when reusing unexpired credentials).
+    /// Requests for the same profile are serialized, so only one of them fetches.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the lock cannot be acquired, or if `fetch` fails
+    #[instrument(skip(self, fetch))]
+    pub async fn credentials<F, Fut>(
+        &self,
+        organization: &str,
+        profile: &str,
+        fetch: F,
+    ) -> Result<Credentials>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Credentials>>,
+    {
+        let path = self.path(organization, profile);
+
+        let lock_path = path.clone();
+        let _lock = spawn_blocking(move || FileLock::acquire(&lock_path))
+            .await
+            .map_err(|e| eyre!("Lock task failed ({e})"))??;
+
+        if let Some(cached) =
+            read(&path).filter(|cached| cached.is_fresh(SystemTime::now(), self.reuse))
+        {
+            let count = COALESCED.fetch_add(1, Ordering::Relaxed) + 1;
+            debug!(
+                "Reusing credentials for {profile} fetched by another request ({count} coalesced)"
+            );
+
+            events::emit(&Event::CredentialsCoalesced {
+                organization: organization.to_string(),
+                profile: profile.to_string(),
+            });
+
+            return Ok(cached.into_credentials());
+        }
+
+        let credentials = fetch().await?;
+
+        if let Err(e) = write(&path, &Cached::new(&credentials, SystemTime::now())) {
+            debug!("Unable to share credentials for {profile}: {e}");
+        }
+
+        Ok(credentials)
+    }
+}
+
+fn read(path: &Path) -> Option<Cached> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Write the cache file, readable only by the current user
+fn write(path: &Path, cached: &Cached) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(path)?;
+    file.write_all(serde_json::to_string(cached)?.as_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    fn credentials(access_key_id: &str) -> Credentials {
+        Credentials::new(
+            access_key_id,
+            "SECRET_ACCESS_KEY",
+            Some(String::from("SESSION_TOKEN")),
+            Some(SystemTime::now() + Duration::from_hours(1)),
+            "oktaws",
+        )
+    }
+
+    #[tokio::test]
+    async fn coalesces_recent_requests() -> Result<()> {
+        let dir = tempdir()?;
+        let coalescer = Coalescer::new(dir.path().to_path_buf());
+
+        let first = coalescer
+            .credentials("mock_org", "prod", || async { Ok(credentials("FIRST")) })
+            .await?;
+        let second = coalescer
+            .credentials("mock_org", "prod", || async { Ok(credentials("SECOND")) })
+            .await?;
+        let other = coalescer
+            .credentials("mock_org", "dev", || async { Ok(credentials("OTHER")) })
+            .await?;
+
+        assert_eq!(first.access_key_id(), "FIRST");
+        assert_eq!(second.access_key_id(), "FIRST");
+        assert_eq!(other.access_key_id(), "OTHER");
+        assert!(coalesced() >= 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn keys_cover_role_override_and_duration() {
+        let mut profile = Profile {
+            name: String::from("prod"),
+            application_name: String::from("AWS SSO"),
+            account: None,
+            roles: vec![String::from("ReadOnly")],
+            duration_seconds: None,
+            chain: None,
+            role_rules: Vec::new(),
+            saml_fallback: None,
+            console: None,
+            cli: crate::config::profile::CliSettings::default(),
+        };
+
+        let plain = key(&profile, None);
+        assert!(plain.starts_with("prod."));
+        assert_ne!(plain, key(&profile, Some("Admin")));
+
+        profile.duration_seconds = Some(900);
+        assert_ne!(plain, key(&profile, None));
+    }
+
+    #[test]
+    fn stale_credentials_are_not_shared() {
+        let now = SystemTime::now();
+
+        let recent = Cached::new(&credentials("KEY"), now);
+        assert!(recent.is_fresh(now, false));
+
+        let old = Cached::new(&credentials("KEY"), now - WINDOW);
+        assert!(!old.is_fresh(now, false));
+        assert!(old.is_fresh(now, true));
+
+        let expiring = Cached::new(
+            &Credentials::new("KEY", "SECRET", None, Some(now + MIN_MARGIN), "oktaws"),
+            now,
+        );
+        assert!(!expiring.is_fresh(now, false));
+        assert!(!expiring.is_fresh(now, true));
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::de::{self, DeserializeOwned, Visitor};
+use serde::{Deserialize, Deserializer};
+
+static UNKNOWN_FIELD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^unknown field `(?P<key>[^`]+)`, expected (?P<expected>.+)$").unwrap()
+});
+static QUOTED: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"`([^`]+)`").unwrap());
+
+/// A problem with a config file, located by line and column,
+/// e.g. `mock_org.toml:14:7 unknown key 'rolle', did you mean 'role'?`
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("{path}:{line}:{column} {message}")]
+pub struct ConfigError {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ConfigError {
+    /// Locate a deserializer's error in `contents`.
+    /// This is independent of the file format, so that any deserializer reporting byte spans can use it.
+    #[must_use]
+    pub fn new(path: &Path, contents: &str, span: Option<Range<usize>>, message: &str) -> Self {
+        let offset = span.map_or(0, |span| span.start).min(contents.len());
+        let before = contents.get(..offset).unwrap_or_default();
+
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit_once('\n')
+            .map_or(before, |(_, last_line)| last_line)
+            .chars()
+            .count()
+            + 1;
+
+        Self {
+            path: path.display().to_string(),
+            line,
+            column,
+            message: explain(message),
+        }
+    }
+}
+
+/// Parse a TOML config file, reporting errors with their location
+///
+/// # Errors
+///
+/// Will return `Err` if the contents are not valid TOML, or do not match the config structure
+pub fn from_toml<T: DeserializeOwned>(path: &Path, contents: &str) -> Result<T, ConfigError> {
+    toml::de::from_str(contents)
+        .map_err(|e| ConfigError::new(path, contents, e.span(), e.message()))
+}
+
+/// Reword serde's unknown field errors to suggest the key which was most likely meant
+fn explain(message: &str) -> String {
+    let message = message.trim();
+
+    let Some(captures) = UNKNOWN_FIELD.captures(message) else {
+        return message.to_string();
+    };
+
+    let key = &captures["key"];
+    let expected = QUOTED
+        .captures_iter(&captures["expected"])
+        .map(|quoted| quoted[1].to_string())
+        .collect::<Vec<_>>();
+
+    match closest(key, &expected) {
+        Some(suggestion) => format!("unknown key '{key}', did you mean '{suggestion}'?"),
+        None => format!(
+            "unknown key '{key}', expected one of {}",
+            expected.join(", ")
+        ),
+    }
+}
+
+/// The keys serde reads into the struct `T` (aliases included), so that lists of them needn't be kept by hand
+pub(crate) fn keys<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    T::deserialize(Keys)
+        .err()
+        .and_then(|found| found.0)
+        .unwrap_or_default()
+}
+
+/// A deserializer which only asks the struct it is given for its keys
+struct Keys;
+
+/// How [`Keys`] stops, with the keys if it was given a struct
+#[derive(Debug)]
+struct Found(Option<&'static [&'static str]>);
+
+impl fmt::Display for Found {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("only structs have keys")
+    }
+}
+
+impl std::error::Error for Found {}
+
+impl de::Error for Found {
+    fn custom<T: fmt::Display>(_: T) -> Self {
+        Self(None)
+    }
+}
+
+impl<'de> Deserializer<'de> for Keys {
+    type Error = Found;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Found> {
+        Err(Found(None))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Found> {
+        Err(Found(Some(fields)))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// The candidate closest to `key`, if any is close enough to be a likely typo
+pub(crate) fn closest<'a>(key: &str, candidates: &'a [String]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(key, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= candidate.len().max(key.len()) / 3 + 1)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Deserialize, Debug)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Example {
+        username: Option<String>,
+        role: Option<String>,
+    }
+
+    #[test]
+    fn suggests_misspelled_keys() {
+        let err = from_toml::<Example>(
+            Path::new("mock_org.toml"),
+            "username = \"mock_user\"\n\n  rolle = \"Admin\"\n",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "mock_org.toml:3:3 unknown key 'rolle', did you mean 'role'?"
+        );
+    }
+
+    #[test]
+    fn reads_keys_from_the_struct() {
+        assert_eq!(keys::<Example>(), ["username", "role"]);
+        assert!(keys::<String>().is_empty());
+    }
+
+    #[test]
+    fn lists_keys_when_nothing_is_close() {
+        assert_eq!(
+            explain("unknown field `colour`, expected `username` or `role`"),
+            "unknown key 'colour', expected one of username, role"
+        );
+    }
+}
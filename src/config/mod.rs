@@ -1,32 +1,303 @@
+pub mod error;
+pub mod naming;
 pub mod organization;
+pub mod overlap;
 pub mod profile;
+pub mod read_only;
+pub mod rename;
+pub mod rules;
+pub mod settings;
+pub mod shared;
 
-use std::env::var as env_var;
+use std::env::var_os;
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use eyre::{Result, eyre};
 
-/// Return the location for the Oktaws config directory.
+/// The single directory older versions kept everything in:
+/// `OKTAWS_HOME` if it is set, otherwise `~/.oktaws` if it already exists.
+/// When there is one, configs, state and caches all stay in it.
+fn single_home() -> Option<PathBuf> {
+    var_os("OKTAWS_HOME").map(PathBuf::from).or_else(|| {
+        dirs::home_dir()
+            .map(|home_dir| home_dir.join(".oktaws"))
+            .filter(|path| path.is_dir())
+    })
+}
+
+/// The `oktaws` directory under the base directory named by the XDG `variable`,
+/// falling back on the platform's equivalent (e.g. `~/Library/Application Support` on macOS, or `%APPDATA%` on Windows)
+fn xdg_dir(variable: &str, fallback: Option<PathBuf>) -> Result<PathBuf> {
+    var_os(variable)
+        .map(PathBuf::from)
+        // The spec says relative paths are invalid, and should be ignored
+        .filter(|path| path.is_absolute())
+        .or(fallback)
+        .map(|dir| dir.join("oktaws"))
+        .ok_or_else(|| {
+            eyre!("Unable to find a directory for oktaws. Set {variable}, or OKTAWS_HOME")
+        })
+}
+
+/// Return the location for the Oktaws config directory,
+/// which holds the organization configs and `settings.toml`.
 ///
 /// # Errors
 ///
-/// Will return `Err` if there are no `OKTAWS_HOME` or `HOME`
-/// environment variables set.
+/// Will return `Err` if no location can be determined from
+/// `OKTAWS_HOME`, `XDG_CONFIG_HOME` or the platform's config directory.
 pub fn oktaws_home() -> Result<PathBuf> {
-    env_var("OKTAWS_HOME").map_or_else(
-        |_| default_profile_location(),
-        |path| Ok(PathBuf::from(path)),
+    single_home().map_or_else(|| xdg_dir("XDG_CONFIG_HOME", dirs::config_dir()), Ok)
+}
+
+/// Return the location for the state oktaws builds up as it is used
+/// (usage, picks, the sections it manages, remembered sessions and the like).
+/// In [read-only mode](read_only), this is in the process's scratch directory, which starts as a copy of it.
+///
+/// # Errors
+///
+/// Will return `Err` if no location can be determined from
+/// `OKTAWS_HOME`, `XDG_STATE_HOME` or the platform's local data directory.
+pub fn state_dir() -> Result<PathBuf> {
+    read_only::scratch().map_or_else(home_state_dir, |scratch| Ok(scratch.join("state")))
+}
+
+/// The state directory, outside read-only mode
+fn home_state_dir() -> Result<PathBuf> {
+    single_home().map_or_else(
+        || {
+            xdg_dir(
+                "XDG_STATE_HOME",
+                dirs::state_dir().or_else(dirs::data_local_dir),
+            )
+        },
+        Ok,
     )
 }
 
-/// Return the default location for the Oktaws config directory.
+/// Return the location for files which can be thrown away at any time, such as recently fetched credentials.
+/// In [read-only mode](read_only), this is in the process's scratch directory.
 ///
 /// # Errors
 ///
-/// Will return `Err` if there is no `HOME` environment variable set.
-fn default_profile_location() -> Result<PathBuf> {
-    dirs::home_dir().map_or_else(
-        || Err(eyre!("The environment variable HOME must be set.")),
-        |home_dir| Ok(home_dir.join(".oktaws")),
+/// Will return `Err` if no location can be determined from
+/// `OKTAWS_HOME`, `XDG_CACHE_HOME` or the platform's cache directory.
+pub fn cache_dir() -> Result<PathBuf> {
+    if let Some(scratch) = read_only::scratch() {
+        return Ok(scratch.join("cache"));
+    }
+
+    single_home().map_or_else(
+        || xdg_dir("XDG_CACHE_HOME", dirs::cache_dir()),
+        |home| Ok(home.join("cache")),
     )
 }
+
+/// Create the Oktaws config directory if it does not exist yet,
+/// readable only by the current user.
+///
+/// # Errors
+///
+/// Will return `Err` if the location cannot be determined,
+/// if the directory cannot be created, or in [read-only mode](read_only).
+pub fn create_oktaws_home() -> Result<PathBuf> {
+    read_only::check("change its config directory")?;
+
+    let home = oktaws_home()?;
+
+    if !home.exists() {
+        fs::create_dir_all(&home)?;
+
+        #[cfg(unix)]
+        fs::set_permissions(&home, fs::Permissions::from_mode(0o700))?;
+    }
+
+    Ok(home)
+}
+
+/// Whether any organization has been configured yet
+///
+/// # Errors
+///
+/// Will return `Err` if the location cannot be determined,
+/// or if the directory exists but cannot be read.
+pub fn is_configured() -> Result<bool> {
+    let home = oktaws_home()?;
+
+    if !home.is_dir() {
+        return Ok(false);
+    }
+
+    Ok(fs::read_dir(home)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .any(|path| {
+            path.extension().is_some_and(|ext| ext == "toml")
+                && !path.ends_with(settings::FILE_NAME)
+        }))
+}
+
+/// Parse a human-friendly duration, such as `900`, `15m`, or `1h30m`.
+/// Bare numbers are treated as seconds.
+///
+/// # Errors
+///
+/// Will return `Err` if the duration is empty, zero, or has an unknown unit.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+
+    if let Ok(seconds) = s.parse::<u64>() {
+        return non_zero(Duration::from_secs(seconds), s);
+    }
+
+    let mut total = 0;
+    let mut digits = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+
+        let multiplier = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(eyre!("Unknown unit '{c}' in duration {s}")),
+        };
+        if digits.is_empty() {
+            return Err(eyre!("Missing value before '{c}' in duration {s}"));
+        }
+        total = digits
+            .parse::<u64>()
+            .ok()
+            .and_then(|value| value.checked_mul(multiplier))
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(|| eyre!("Duration {s} is too long"))?;
+        digits.clear();
+    }
+
+    if !digits.is_empty() {
+        return Err(eyre!("Missing unit after {digits} in duration {s}"));
+    }
+
+    non_zero(Duration::from_secs(total), s)
+}
+
+fn non_zero(duration: Duration, s: &str) -> Result<Duration> {
+    if duration.is_zero() {
+        Err(eyre!("Duration must be greater than zero, got {s}"))
+    } else {
+        Ok(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn first_use_is_not_configured() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let home = tempdir.path().join("oktaws");
+        std::env::set_var("OKTAWS_HOME", &home);
+
+        assert!(!is_configured().unwrap());
+
+        create_oktaws_home().unwrap();
+        assert!(!is_configured().unwrap());
+
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(&home).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+
+        fs::write(home.join("mock_org.toml"), "[profiles]").unwrap();
+        assert!(is_configured().unwrap());
+    }
+
+    #[test]
+    #[serial]
+    fn oktaws_home_keeps_everything() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::env::set_var("OKTAWS_HOME", tempdir.path());
+
+        assert_eq!(oktaws_home().unwrap(), tempdir.path());
+        assert_eq!(state_dir().unwrap(), tempdir.path());
+        assert_eq!(cache_dir().unwrap(), tempdir.path().join("cache"));
+    }
+
+    #[test]
+    #[serial]
+    fn xdg_dirs() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::env::set_var("OKTAWS_TEST_XDG_HOME", tempdir.path());
+        std::env::set_var("OKTAWS_TEST_RELATIVE_XDG_HOME", "relative");
+
+        assert_eq!(
+            xdg_dir("OKTAWS_TEST_XDG_HOME", None).unwrap(),
+            tempdir.path().join("oktaws")
+        );
+        assert_eq!(
+            xdg_dir(
+                "OKTAWS_TEST_RELATIVE_XDG_HOME",
+                Some(PathBuf::from("/fallback"))
+            )
+            .unwrap(),
+            PathBuf::from("/fallback/oktaws")
+        );
+        assert!(xdg_dir("OKTAWS_TEST_UNSET_XDG_HOME", None).is_err());
+    }
+
+    #[test]
+    fn parse_bare_seconds() {
+        assert_eq!(parse_duration("900").unwrap(), Duration::from_mins(15));
+    }
+
+    #[test]
+    fn parse_units() {
+        assert_eq!(parse_duration("15m").unwrap(), Duration::from_mins(15));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_mins(90));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_hours(24));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(
+            parse_duration("15x").unwrap_err().to_string(),
+            "Unknown unit 'x' in duration 15x"
+        );
+        assert_eq!(
+            parse_duration("1h30").unwrap_err().to_string(),
+            "Missing unit after 30 in duration 1h30"
+        );
+        assert_eq!(
+            parse_duration("0m").unwrap_err().to_string(),
+            "Duration must be greater than zero, got 0m"
+        );
+        assert_eq!(
+            parse_duration("h").unwrap_err().to_string(),
+            "Missing value before 'h' in duration h"
+        );
+        assert_eq!(
+            parse_duration("999999999999999999d")
+                .unwrap_err()
+                .to_string(),
+            "Duration 999999999999999999d is too long"
+        );
+        assert_eq!(
+            parse_duration("18446744073709551615s1s")
+                .unwrap_err()
+                .to_string(),
+            "Duration 18446744073709551615s1s is too long"
+        );
+    }
+}
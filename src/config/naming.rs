@@ -0,0 +1,172 @@
+//! How `oktaws init` names the profiles it generates.
+//!
+//! A template names each profile from its account, and optionally its role and Okta application,
+//! e.g. `{account}` (one profile per account) or `{account}-{role}` (one profile per account and role).
+//! The same templates name the profiles refreshes discover, with an organization's `profile_format`.
+
+use crate::okta::applications::AppLinkAccountRoleMapping;
+
+use std::fmt;
+use std::str::FromStr;
+
+use eyre::{Error, Result, eyre};
+
+/// The placeholders a template can use
+const PLACEHOLDERS: &[&str] = &[
+    "account",
+    "account_name",
+    "account_id",
+    "role",
+    "application",
+];
+
+/// The templates offered by `oktaws init`, with what they generate
+pub const PRESETS: &[(&str, &str)] = &[
+    (
+        "{account}",
+        "one profile per account, choosing a role for each",
+    ),
+    ("{account}-{role}", "one profile per account and role"),
+    (
+        "{application}-{account}",
+        "one profile per account, prefixed with the Okta application",
+    ),
+];
+
+/// A template for profile names
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template(String);
+
+impl Default for Template {
+    fn default() -> Self {
+        Self(String::from("{account}"))
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(template: &str) -> Result<Self> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| eyre!("Unclosed {{ in profile naming template {template}"))?;
+            let placeholder = &rest[start + 1..start + end];
+
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(eyre!(
+                    "Unknown placeholder {{{placeholder}}} in profile naming template {template} (expected one of {})",
+                    PLACEHOLDERS
+                        .iter()
+                        .map(|placeholder| format!("{{{placeholder}}}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+
+        if !["{account}", "{account_name}", "{account_id}"]
+            .iter()
+            .any(|account| template.contains(account))
+        {
+            return Err(eyre!(
+                "Profile naming template {template} must include {{account}} (or {{account_name}} or {{account_id}}), \
+                 so that profiles have different names"
+            ));
+        }
+
+        Ok(Self(template.to_string()))
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Template {
+    /// Whether every role gets its own profile
+    #[must_use]
+    pub fn per_role(&self) -> bool {
+        self.0.contains("{role}")
+    }
+
+    /// The name of the profile for `mapping`'s account (and `role`, for templates with one).
+    /// `{account}` and `{account_name}` are both the account's name.
+    /// Roles and applications are lowercased, with spaces and underscores replaced, as account names are.
+    /// `None` if the template has `{account_id}`, but the account's ID is not known.
+    #[must_use]
+    pub fn render(
+        &self,
+        mapping: &AppLinkAccountRoleMapping,
+        role: Option<&str>,
+    ) -> Option<String> {
+        let normalize = |name: &str| name.to_lowercase().replace([' ', '_'], "-");
+
+        let account_id = if self.0.contains("{account_id}") {
+            mapping.account_id.as_deref()?
+        } else {
+            ""
+        };
+
+        Some(
+            self.0
+                .replace("{account}", &mapping.account_name)
+                .replace("{account_name}", &mapping.account_name)
+                .replace("{account_id}", account_id)
+                .replace("{role}", &role.map(normalize).unwrap_or_default())
+                .replace("{application}", &normalize(&mapping.application_name)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::okta::applications::IntegrationType;
+
+    #[test]
+    fn renders_templates() -> Result<()> {
+        let mut mapping = AppLinkAccountRoleMapping {
+            account_name: String::from("production"),
+            account_id: None,
+            account_email: None,
+            account_description: None,
+            role_names: vec![String::from("Data_Admin")],
+            application_name: String::from("AWS SSO"),
+            integration_type: IntegrationType::IdentityCenter,
+        };
+
+        let template = "{account}-{role}".parse::<Template>()?;
+        assert!(template.per_role());
+        assert_eq!(
+            template.render(&mapping, Some("Data_Admin")).as_deref(),
+            Some("production-data-admin")
+        );
+
+        let template = "{application}.{account}".parse::<Template>()?;
+        assert!(!template.per_role());
+        assert_eq!(
+            template.render(&mapping, None).as_deref(),
+            Some("aws-sso.production")
+        );
+
+        let template = "{account_name}-{account_id}".parse::<Template>()?;
+        assert_eq!(template.render(&mapping, None), None);
+        mapping.account_id = Some(String::from("123456789012"));
+        assert_eq!(
+            template.render(&mapping, None).as_deref(),
+            Some("production-123456789012")
+        );
+
+        assert!("{role}".parse::<Template>().is_err());
+        assert!("{account}-{team}".parse::<Template>().is_err());
+        assert!("{account".parse::<Template>().is_err());
+
+        Ok(())
+    }
+}
@@ -1,15 +1,36 @@
-use crate::config::oktaws_home;
+use crate::aws::identity_center;
+use crate::aws::partition::Partition;
+use crate::aws::profile::Layout;
+use crate::aws::sso::AccountNaming;
+use crate::blocking;
+use crate::coalesce::{self, Coalescer};
+use crate::config::error::from_toml;
+use crate::config::naming::Template as NamingTemplate;
 use crate::config::profile::{self, Profile};
+use crate::config::rename::{self, RoleRenamed};
+use crate::config::rules::RoleRule;
+use crate::config::{oktaws_home, settings};
+use crate::events::{self, Event};
+use crate::okta::applications::{
+    AppLink, AppLinkAccountRoleMapping, Precedence, Scope, dedupe_account_mappings,
+};
 #[double]
 use crate::okta::client::Client as OktaClient;
+use crate::okta::portal::Portal;
+use crate::parallel;
+use crate::picker::pick_many;
+use crate::retry::Budget;
 use crate::select_multiple_opt;
+use crate::silent;
+use crate::sink;
+use crate::theme;
 use mockall_double::double;
 
 use indexmap::IndexMap;
 use std::convert::TryFrom;
 use std::fmt;
 use std::fs::read_to_string;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use aws_credential_types::Credentials;
@@ -18,38 +39,232 @@ use eyre::{Error, Result, eyre};
 use futures::stream::{self, StreamExt};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use toml;
-use tracing::{debug, error, instrument};
+use tracing::{debug, error, instrument, warn};
 use whoami::username;
 
 /// This is an intentionally 'loose' struct,
 /// representing the potential for overrides and later prompts
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub username: Option<String>,
     pub roles: Option<Vec<String>>,
     pub role: Option<String>,
+    /// Default roles by account name or email, tried in order before `role`/`roles`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub role_rules: Vec<RoleRule>,
     pub duration_seconds: Option<i32>,
+    /// Regex extracting the account name from AWS SSO app instance names, with a `name` capture group
+    pub account_name_pattern: Option<String>,
+    /// Regex extracting the account ID from AWS SSO app instance names, with an `id` capture group
+    pub account_id_pattern: Option<String>,
+    /// Sign in somewhere other than `https://<organization>.okta.com/`
+    pub portal_base_url: Option<String>,
+    /// Use an Okta API path other than `api/v1`
+    pub api_prefix: Option<String>,
+    /// Headers to send with every request to Okta (e.g. for a WAF); their values are kept in the keyring
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub okta_headers: Vec<String>,
+    /// Read the Okta password from HashiCorp Vault, ahead of the keyring
+    #[cfg(feature = "vault")]
+    pub password_vault: Option<crate::vault::PasswordSource>,
+    /// Reach AWS SSO accounts through IAM Identity Center's OIDC device flow, instead of the legacy portal
+    pub identity_center: Option<identity_center::Config>,
+    /// Which application to use for an account with several Okta tiles: `sso`, `saml` or `label:<regex>`
+    pub application_precedence: Option<String>,
+    /// The AWS partition the organization's accounts are in: `aws` (the default), `aws-us-gov` or `aws-cn`
+    pub partition: Option<String>,
+    /// Write this organization's credentials to this file, instead of the shared credentials file
+    pub credentials_file: Option<PathBuf>,
+    /// Write credentials `[profile <name>]` sections as in `~/.aws/config` (`config`), rather than as in `~/.aws/credentials`
+    pub credentials_layout: Option<String>,
+    /// Which organization refreshes a profile that others define too: the highest wins (0 by default)
+    pub priority: Option<i32>,
+    /// Prefix the organization's profile names, so that they cannot collide with another organization's
+    pub namespace: Option<Namespace>,
+    /// Add a profile for every account refreshes discover, named by this template (e.g. `{account_name}-{role}`)
+    pub profile_format: Option<String>,
+    /// When refreshes may reuse the Okta session from the last sign-in, without any interaction
+    pub silent_refresh: Option<silent::Policy>,
+    /// Extra places to send this organization's credentials, besides the credentials file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sinks: Vec<sink::Config>,
     pub profiles: IndexMap<String, profile::Config>,
+    /// Named sets of profiles (or profile globs), selected with `@<group>`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub groups: IndexMap<String, Vec<String>>,
+}
+
+/// A prefix for an organization's profile names, e.g. `acme-sandbox` rather than `sandbox`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Namespace {
+    /// The prefix (the organization's name by default)
+    pub prefix: Option<String>,
+    /// What separates the prefix from the name in the config (`-` by default)
+    pub separator: Option<String>,
+}
+
+impl Namespace {
+    /// Check the prefix and separator can be part of a file name, as profile names are
+    /// (in the credentials cache and `env` sinks, for example)
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if either holds a path separator, or `..`
+    pub fn validate(&self) -> Result<()> {
+        for (field, value) in [("prefix", &self.prefix), ("separator", &self.separator)] {
+            if let Some(value) = value
+                .as_deref()
+                .filter(|value| value.contains(['/', '\\']) || value.contains(".."))
+            {
+                return Err(eyre!(
+                    "The namespace {field} {value:?} can't hold a path separator or `..`"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// What `organization`'s profile names are prefixed with, separator included
+    #[must_use]
+    pub fn prefix(&self, organization: &str) -> String {
+        format!(
+            "{}{}",
+            self.prefix.as_deref().unwrap_or(organization),
+            self.separator.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+/// The name `naming` gives `mapping`'s account (and `role`), if the account has what the template needs
+fn profile_name(
+    naming: &NamingTemplate,
+    mapping: &AppLinkAccountRoleMapping,
+    role: Option<&str>,
+) -> Option<String> {
+    let name = naming.render(mapping, role);
+    if name.is_none() {
+        warn!(
+            "Leaving out {}, whose account ID is not known, from profiles named {naming}",
+            mapping.account_name
+        );
+    }
+    name
+}
+
+/// Profiles for every account discovered, as named by an organization's `profile_format`
+#[derive(Clone, Debug)]
+pub struct ProfileFormat {
+    pub template: NamingTemplate,
+    default_roles: Option<Vec<String>>,
+    role_rules: Vec<RoleRule>,
+    duration_seconds: Option<i32>,
+}
+
+impl ProfileFormat {
+    /// The profiles for `mappings`' accounts (or each of their roles, for templates with `{role}`), prefixed with `namespace`.
+    /// Without `{role}`, an account's profile uses the first default role it has, or its only role,
+    /// and otherwise is left to the role rules; accounts with none of those are left out.
+    #[must_use]
+    pub fn profiles(
+        &self,
+        mappings: &[AppLinkAccountRoleMapping],
+        namespace: Option<&str>,
+    ) -> Vec<Profile> {
+        let configs = mappings.iter().flat_map(|mapping| {
+            let roles = if self.template.per_role() {
+                mapping.role_names.iter().map(Some).collect()
+            } else {
+                vec![
+                    self.default_roles
+                        .iter()
+                        .flatten()
+                        .find(|role| mapping.role_names.contains(role))
+                        .or_else(|| {
+                            (mapping.role_names.len() == 1).then(|| &mapping.role_names[0])
+                        }),
+                ]
+            };
+
+            roles.into_iter().filter_map(move |role| {
+                let name = profile_name(&self.template, mapping, role.map(String::as_str))?;
+                let config = profile::Config::for_account(mapping, role.cloned());
+                Some((name, config))
+            })
+        });
+
+        let mut profiles: Vec<Profile> = Vec::new();
+        for (name, config) in configs {
+            let name = format!("{}{name}", namespace.unwrap_or_default());
+            if profiles.iter().any(|profile| profile.name == name) {
+                warn!("More than one discovered account would be named {name}; keeping the first");
+                continue;
+            }
+
+            // The default role (if the account has one) is already chosen
+            match Profile::try_from_spec(
+                &config,
+                name,
+                None,
+                &self.role_rules,
+                self.duration_seconds,
+            ) {
+                Ok(profile) => profiles.push(profile),
+                Err(e) => debug!("Not discovering a profile for {config:?}: {e}"),
+            }
+        }
+
+        profiles
+    }
+}
+
+/// The named profiles, keeping the first of any which would share a name (as [`ProfileFormat::profiles`] does)
+fn first_of_each_name(
+    profiles: impl IntoIterator<Item = (String, profile::Config)>,
+) -> IndexMap<String, profile::Config> {
+    let mut named = IndexMap::new();
+    for (name, config) in profiles {
+        if named.contains_key(&name) {
+            warn!("More than one account or role would be named {name}; keeping the first");
+            continue;
+        }
+        named.insert(name, config);
+    }
+
+    named
 }
 
 impl Config {
-    /// Create a config object from an Okta organization
+    /// Create a config object from an Okta organization.
+    /// With `pick_accounts`, only the accounts chosen from a fuzzy-searchable list get profiles.
+    /// Profiles are named by `naming`; a template with `{role}` gets a profile for each of an account's roles.
     ///
     /// # Errors
     ///
     /// Will return `Err` if there are any errors fetching the information
     /// from Okta to form the config,
     /// or if there are errors during prompting of a default role.
-    pub async fn from_organization(client: &OktaClient, username: String) -> Result<Self> {
+    pub async fn from_organization(
+        client: &OktaClient,
+        username: String,
+        pick_accounts: bool,
+        naming: &NamingTemplate,
+    ) -> Result<Self> {
         let app_links = client.app_links(None).await?;
         let aws_links = app_links
             .into_iter()
-            .filter(|link| link.app_name == "amazon_aws" || link.app_name == "amazon_aws_sso")
+            .filter(AppLink::is_aws)
             .collect::<Vec<_>>();
 
-        let mut all_account_role_mappings =
-            client.get_all_account_mappings(aws_links.clone()).await?;
+        let mut all_account_role_mappings = client
+            .get_all_account_mappings(
+                aws_links.clone(),
+                &AccountNaming::default(),
+                &Scope::default(),
+            )
+            .await?;
         all_account_role_mappings.sort_by(|a, b| a.account_name.cmp(&b.account_name));
 
         let mut role_names = all_account_role_mappings
@@ -72,8 +287,9 @@ impl Config {
             .map(|(_, x)| x)
             .collect::<Vec<_>>();
 
-        let default_roles = if default_role_names.is_empty() {
-            default_role_names
+        // With a profile for every role, there is no role to default to
+        let default_roles = if default_role_names.is_empty() || naming.per_role() {
+            Vec::new()
         } else {
             select_multiple_opt(
                 default_role_names,
@@ -84,37 +300,126 @@ impl Config {
 
         let all_account_role_mappings =
             client.remove_overlapped_account_mappings(all_account_role_mappings)?;
-
-        let profiles = all_account_role_mappings
-            .into_iter()
-            .map(|account_mapping| {
-                profile::Config::from_account_mapping(account_mapping, &default_roles)
+        let all_account_role_mappings =
+            dedupe_account_mappings(all_account_role_mappings, &Precedence::default());
+        // Picks are remembered under a lock, which is waited for off the reactor too
+        let all_account_role_mappings = if pick_accounts {
+            blocking::prompt(move || {
+                pick_many(
+                    all_account_role_mappings,
+                    "Choose Accounts to add profiles for",
+                    |mapping| format!("{} ({})", mapping.account_name, mapping.application_name),
+                )
             })
-            .collect::<Result<IndexMap<String, profile::Config>, Error>>()?;
+            .await?
+        } else {
+            all_account_role_mappings
+        };
+
+        let profiles = if naming.per_role() {
+            first_of_each_name(all_account_role_mappings.iter().flat_map(|mapping| {
+                mapping.role_names.iter().filter_map(move |role| {
+                    profile_name(naming, mapping, Some(role))
+                        .map(|name| (name, profile::Config::for_role(mapping, role)))
+                })
+            }))
+        } else {
+            let mut profiles = Vec::new();
+            for account_mapping in all_account_role_mappings {
+                let Some(name) = profile_name(naming, &account_mapping, None) else {
+                    continue;
+                };
+
+                let default_roles = default_roles.clone();
+                let (_, config) = blocking::prompt(move || {
+                    profile::Config::from_account_mapping(account_mapping, &default_roles)
+                })
+                .await?;
+                profiles.push((name, config));
+            }
+            first_of_each_name(profiles)
+        };
 
         if default_roles.is_empty() {
             Ok(Self {
                 username: Some(username),
                 duration_seconds: None,
+                account_name_pattern: None,
+                account_id_pattern: None,
+                portal_base_url: None,
+                api_prefix: None,
+                okta_headers: Vec::new(),
+                #[cfg(feature = "vault")]
+                password_vault: None,
+                identity_center: None,
+                application_precedence: None,
+                partition: None,
+                credentials_file: None,
+                credentials_layout: None,
+                priority: None,
+                namespace: None,
+                profile_format: None,
+                silent_refresh: None,
+                sinks: Vec::new(),
                 role: None,
                 roles: None,
+                role_rules: Vec::new(),
                 profiles,
+                groups: IndexMap::new(),
             })
         } else if default_roles.len() == 1 {
             Ok(Self {
                 username: Some(username),
                 duration_seconds: None,
+                account_name_pattern: None,
+                account_id_pattern: None,
+                portal_base_url: None,
+                api_prefix: None,
+                okta_headers: Vec::new(),
+                #[cfg(feature = "vault")]
+                password_vault: None,
+                identity_center: None,
+                application_precedence: None,
+                partition: None,
+                credentials_file: None,
+                credentials_layout: None,
+                priority: None,
+                namespace: None,
+                profile_format: None,
+                silent_refresh: None,
+                sinks: Vec::new(),
                 role: default_roles.first().cloned(),
                 roles: None,
+                role_rules: Vec::new(),
                 profiles,
+                groups: IndexMap::new(),
             })
         } else {
             Ok(Self {
                 username: Some(username),
                 duration_seconds: None,
+                account_name_pattern: None,
+                account_id_pattern: None,
+                portal_base_url: None,
+                api_prefix: None,
+                okta_headers: Vec::new(),
+                #[cfg(feature = "vault")]
+                password_vault: None,
+                identity_center: None,
+                application_precedence: None,
+                partition: None,
+                credentials_file: None,
+                credentials_layout: None,
+                priority: None,
+                namespace: None,
+                profile_format: None,
+                silent_refresh: None,
+                sinks: Vec::new(),
                 role: None,
                 roles: Some(default_roles),
+                role_rules: Vec::new(),
                 profiles,
+                groups: IndexMap::new(),
             })
         }
     }
@@ -126,14 +431,29 @@ impl Config {
 pub struct Organization {
     pub name: String,
     pub username: String,
+    pub portal: Portal,
+    pub account_naming: AccountNaming,
+    pub identity_center: Option<identity_center::Config>,
+    pub application_precedence: Precedence,
+    pub partition: Partition,
+    pub credentials_file: Option<PathBuf>,
+    pub credentials_layout: Layout,
+    pub priority: i32,
+    /// What the profile names are prefixed with (see [`Namespace`]), if anything
+    pub namespace: Option<String>,
+    /// How discovered accounts get profiles, besides those configured
+    pub profile_format: Option<ProfileFormat>,
+    pub silent_refresh: Option<silent::Policy>,
+    pub sinks: Vec<sink::Config>,
     pub profiles: Vec<Profile>,
+    pub groups: IndexMap<String, Vec<String>>,
 }
 
 impl TryFrom<&Path> for Organization {
     type Error = Error;
 
     fn try_from(path: &Path) -> Result<Self, Self::Error> {
-        let cfg: Config = toml::de::from_str(&read_to_string(path)?)?;
+        let cfg: Config = from_toml(path, &read_to_string(path)?)?;
 
         let filename = path
             .file_stem()
@@ -157,23 +477,106 @@ impl TryFrom<&Path> for Organization {
             cfg.roles
         };
 
+        for rule in &cfg.role_rules {
+            rule.validate()?;
+        }
+
+        if let Some(namespace) = &cfg.namespace {
+            namespace.validate()?;
+        }
+        let namespace = cfg
+            .namespace
+            .as_ref()
+            .map(|namespace| namespace.prefix(&filename));
+
+        let profile_format = cfg
+            .profile_format
+            .as_deref()
+            .map(str::parse)
+            .transpose()?
+            .map(|template| ProfileFormat {
+                template,
+                default_roles: default_roles.clone(),
+                role_rules: cfg.role_rules.clone(),
+                duration_seconds: cfg.duration_seconds,
+            });
+
         let profiles = cfg
             .profiles
             .iter()
             .map(|(name, profile_config)| {
                 Profile::try_from_spec(
                     profile_config,
-                    name.clone(),
+                    format!("{}{name}", namespace.as_deref().unwrap_or_default()),
                     default_roles.clone(),
+                    &cfg.role_rules,
                     cfg.duration_seconds,
                 )
             })
             .collect::<Result<Vec<Profile>, Error>>()?;
 
+        // Group members name profiles as they are in the config, so they are namespaced too
+        let groups = match &namespace {
+            Some(namespace) => cfg
+                .groups
+                .into_iter()
+                .map(|(group, members)| {
+                    let members = members
+                        .iter()
+                        .map(|member| format!("{}{member}", glob::Pattern::escape(namespace)))
+                        .collect();
+                    (group, members)
+                })
+                .collect(),
+            None => cfg.groups,
+        };
+
+        let account_naming = AccountNaming::new(
+            cfg.account_name_pattern.as_deref(),
+            cfg.account_id_pattern.as_deref(),
+        )?;
+
+        let portal = Portal::with_overrides(
+            &filename,
+            cfg.portal_base_url.as_deref(),
+            cfg.api_prefix.as_deref(),
+        )?
+        .with_headers(&cfg.okta_headers)?;
+        #[cfg(feature = "vault")]
+        let portal = portal.with_password_vault(cfg.password_vault);
+
         Ok(Self {
             name: filename,
             username,
+            portal,
+            account_naming,
+            identity_center: cfg.identity_center,
+            application_precedence: cfg
+                .application_precedence
+                .as_deref()
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or_default(),
+            partition: cfg
+                .partition
+                .as_deref()
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or_default(),
+            credentials_file: cfg.credentials_file,
+            credentials_layout: cfg
+                .credentials_layout
+                .as_deref()
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or_default(),
+            priority: cfg.priority.unwrap_or_default(),
+            namespace,
+            profile_format,
+            silent_refresh: cfg.silent_refresh,
+            sinks: cfg.sinks,
             profiles,
+            groups,
         })
     }
 }
@@ -184,43 +587,212 @@ impl TryFrom<&Path> for Organization {
 ///
 /// Will return `Err` if there are any IO errors during the prompt
 pub fn prompt_username(organization: &impl fmt::Display) -> Result<String> {
-    Input::<String>::new()
+    Input::<String>::with_theme(theme::prompt())
         .with_prompt(format!("Username for {organization}"))
         .default(username())
         .interact_text()
         .map_err(Into::into)
 }
 
+/// Selects profiles, either with a glob or by group (`@<group>`)
+#[derive(Clone, Debug)]
+pub enum Selector {
+    Pattern(glob::Pattern),
+    Group(String),
+}
+
+impl FromStr for Selector {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix('@') {
+            Some(group) => Ok(Self::Group(group.to_string())),
+            None => Ok(Self::Pattern(glob::Pattern::new(s)?)),
+        }
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Pattern(pattern) => write!(f, "{pattern}"),
+            Self::Group(group) => write!(f, "@{group}"),
+        }
+    }
+}
+
 impl Organization {
+    /// The names of the profiles picked out by `selector`, in the order they are configured.
+    /// Group members may themselves be globs.
+    #[must_use]
+    pub fn select(&self, selector: &Selector) -> Vec<String> {
+        let members = match selector {
+            Selector::Pattern(pattern) => vec![pattern.clone()],
+            Selector::Group(group) => self
+                .groups
+                .get(group)
+                .into_iter()
+                .flatten()
+                .filter_map(|member| glob::Pattern::new(member).ok())
+                .collect(),
+        };
+
+        self.profiles
+            .iter()
+            .filter(|profile| members.iter().any(|member| member.matches(&profile.name)))
+            .map(|profile| profile.name.clone())
+            .collect()
+    }
+
+    /// The name `profile` has in the organization's config, without the namespace
+    #[must_use]
+    pub fn config_name<'a>(&self, profile: &'a str) -> &'a str {
+        self.namespace
+            .as_deref()
+            .and_then(|namespace| profile.strip_prefix(namespace))
+            .unwrap_or(profile)
+    }
+
+    /// With `profile_format`, add a profile for each account in `mappings`, as discovered through Okta
+    /// (or kept in the catalog from the last discovery). See [`ProfileFormat::profiles`].
+    /// Profiles named in the config win over discovered ones of the same name.
+    pub fn add_discovered_profiles(&mut self, mappings: &[AppLinkAccountRoleMapping]) {
+        let Some(format) = &self.profile_format else {
+            return;
+        };
+
+        let discovered = format
+            .profiles(mappings, self.namespace.as_deref())
+            .into_iter()
+            .filter(|discovered| {
+                self.profiles
+                    .iter()
+                    .all(|profile| profile.name != discovered.name)
+            })
+            .collect::<Vec<_>>();
+
+        debug!(
+            "Discovered {} profiles named {}",
+            discovered.len(),
+            format.template
+        );
+        self.profiles.extend(discovered);
+    }
+
     pub fn into_profiles(self, filter: glob::Pattern) -> impl Iterator<Item = Profile> {
         self.profiles
             .into_iter()
             .filter(move |p| filter.matches(&p.name))
     }
 
-    #[instrument(skip_all, fields(organization=%self.name, profiles=%filter))]
+    #[instrument(skip_all, fields(organization=%self.name, partition=%self.partition, profiles=%filter))]
     pub async fn into_credentials(
         self,
         client: &OktaClient,
         filter: glob::Pattern,
         role_override: Option<&String>,
+        duration_override: Option<i32>,
+        budget: Budget,
+        coalescer: Option<&Coalescer>,
     ) -> impl Iterator<Item = (String, Credentials)> {
-        let futures = self.into_profiles(filter).map(|profile| async {
-            (
-                profile.name.clone(),
-                profile.into_credentials(client, role_override).await,
-            )
+        let organization = self.name.clone();
+        let namespace = self.namespace.clone();
+        let partition = self.partition;
+        let naming = self.account_naming.clone();
+        let identity_center = self.identity_center.clone();
+
+        let futures = self.into_profiles(filter).map(|mut profile| {
+            let naming = &naming;
+            let identity_center = identity_center.as_ref();
+            let organization = &organization;
+            let namespace = namespace.as_deref();
+            parallel::global().run(async move {
+                if duration_override.is_some() {
+                    profile.duration_seconds = duration_override;
+                }
+
+                // Each profile gets its own budget, so one flaky account can't hold up the others
+                let fetching = &profile;
+                let (attempts, result) = budget
+                    .run(|| async move {
+                        let fetch = || {
+                            fetching.clone().into_credentials(
+                                client,
+                                role_override,
+                                naming,
+                                identity_center,
+                            )
+                        };
+
+                        match coalescer {
+                            Some(coalescer) => {
+                                let key =
+                                    coalesce::key(fetching, role_override.map(String::as_str));
+                                coalescer.credentials(organization, &key, fetch).await
+                            }
+                            None => fetch().await,
+                        }
+                    })
+                    .await;
+
+                // With --auto-migrate, a renamed permission set is written to the config, and used straight away
+                let result = match result {
+                    Err(e) if rename::auto_migrate() => match e.downcast::<RoleRenamed>() {
+                        Ok(mut renamed) => {
+                            // The config names the profile without its namespace
+                            if let Some(name) = namespace
+                                .and_then(|namespace| renamed.profile.strip_prefix(namespace))
+                            {
+                                renamed.profile = name.to_string();
+                            }
+
+                            match rename::migrate(organization, &renamed) {
+                                Ok(()) => {
+                                    profile.roles = vec![renamed.suggestion];
+                                    profile.role_rules.clear();
+                                    profile
+                                        .clone()
+                                        .into_credentials(
+                                            client,
+                                            role_override,
+                                            naming,
+                                            identity_center,
+                                        )
+                                        .await
+                                }
+                                Err(e) => Err(e),
+                            }
+                        }
+                        Err(e) => Err(e),
+                    },
+                    result => result,
+                };
+
+                (profile.name, attempts, result)
+            })
         });
 
+        // The limit is shared with any other organizations being fetched from at the same time
         stream::iter(futures)
-            .buffer_unordered(10) // Only run 10 concurrently at a time
+            .buffer_unordered(parallel::global().permits())
             .collect::<Vec<_>>()
             .await
             .into_iter()
-            .filter_map(|cred_result| match cred_result {
-                (profile, Ok(creds)) => Some((profile, creds)),
-                (_, Err(e)) => {
-                    error!("{e}");
+            .filter_map(move |cred_result| match cred_result {
+                (profile, attempts, Ok(creds)) => {
+                    if attempts > 1 {
+                        warn!("{profile} refreshed after {attempts} attempts");
+                    }
+                    Some((profile, creds))
+                }
+                (profile, attempts, Err(e)) => {
+                    error!("{profile} ({partition}) failed after {attempts} attempt(s): {e}");
+                    events::emit(&Event::RefreshFailed {
+                        organization: organization.clone(),
+                        partition: partition.to_string(),
+                        profile,
+                        error: e.to_string(),
+                    });
                     None
                 }
             })
@@ -257,6 +829,7 @@ impl Pattern {
     pub fn organizations(&self) -> Result<Vec<Organization>> {
         let paths = glob::glob(self.0.as_str())?
             .map(|r| r.map_err(Into::into))
+            .filter(|r| !matches!(r, Ok(path) if path.ends_with(settings::FILE_NAME)))
             .collect::<Result<Vec<_>>>()?;
 
         debug!("Found organization paths: {paths:?}");
@@ -267,6 +840,7 @@ impl Pattern {
 
 #[cfg(test)]
 mod tests {
+    use crate::config::profile::{Chain, ChainMode, CliSettings};
     use crate::okta::applications::{AppLinkAccountRoleMapping, IntegrationType};
 
     use super::*;
@@ -336,7 +910,12 @@ baz = {{ application = "baz", role = "baz_role" }}
             application_name: String::from("foo"),
             account: None,
             roles: vec![String::from("my_role"), String::from("my_role_2")],
-            duration_seconds: Some(300)
+            duration_seconds: Some(300),
+            chain: None,
+            role_rules: Vec::new(),
+            saml_fallback: None,
+            console: None,
+            cli: CliSettings::default(),
         }));
 
         assert!(organization.profiles.contains(&Profile {
@@ -344,7 +923,12 @@ baz = {{ application = "baz", role = "baz_role" }}
             application_name: String::from("bar"),
             account: None,
             roles: vec![String::from("my_role"), String::from("my_role_2")],
-            duration_seconds: Some(600)
+            duration_seconds: Some(600),
+            chain: None,
+            role_rules: Vec::new(),
+            saml_fallback: None,
+            console: None,
+            cli: CliSettings::default(),
         }));
 
         assert!(organization.profiles.contains(&Profile {
@@ -352,10 +936,128 @@ baz = {{ application = "baz", role = "baz_role" }}
             application_name: String::from("baz"),
             account: None,
             roles: vec![String::from("baz_role")],
-            duration_seconds: Some(300)
+            duration_seconds: Some(300),
+            chain: None,
+            role_rules: Vec::new(),
+            saml_fallback: None,
+            console: None,
+            cli: CliSettings::default(),
         }));
     }
 
+    #[test]
+    fn parse_organization_with_namespace() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let filepath = tempdir.path().join("mock_org.toml");
+        let mut file = File::create(filepath.clone()).unwrap();
+
+        write!(
+            file,
+            r#"
+username = "mock_user"
+role = "my_role"
+[namespace]
+separator = "."
+[profiles]
+sandbox = "foo"
+prod = "foo"
+[groups]
+dev = ["sand*"]
+"#
+        )
+        .unwrap();
+
+        let organization = Organization::try_from(filepath.as_path()).unwrap();
+
+        assert_eq!(
+            organization
+                .profiles
+                .iter()
+                .map(|profile| profile.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["mock_org.sandbox", "mock_org.prod"]
+        );
+        assert_eq!(organization.config_name("mock_org.sandbox"), "sandbox");
+        assert_eq!(
+            organization.select(&Selector::Group(String::from("dev"))),
+            vec![String::from("mock_org.sandbox")]
+        );
+
+        let prefixed = Namespace {
+            prefix: Some(String::from("acme")),
+            separator: None,
+        };
+        assert_eq!(prefixed.prefix("mock_org"), "acme-");
+
+        for separator in ["/", "\\", ".."] {
+            let escaping = Namespace {
+                prefix: None,
+                separator: Some(separator.to_string()),
+            };
+            assert!(escaping.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn discovers_profiles_by_format() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let filepath = tempdir.path().join("mock_org.toml");
+        let mut file = File::create(filepath.clone()).unwrap();
+
+        write!(
+            file,
+            r#"
+username = "mock_user"
+role = "ReadOnly"
+profile_format = "{{account_name}}-{{account_id}}"
+[namespace]
+[profiles]
+"#
+        )
+        .unwrap();
+
+        let organization = Organization::try_from(filepath.as_path()).unwrap();
+        let format = organization.profile_format.unwrap();
+
+        let mapping = |name: &str, id: Option<&str>, roles: &[&str]| AppLinkAccountRoleMapping {
+            account_name: name.to_string(),
+            account_id: id.map(ToString::to_string),
+            account_email: None,
+            account_description: None,
+            role_names: roles.iter().map(ToString::to_string).collect(),
+            application_name: String::from("AWS SSO"),
+            integration_type: IntegrationType::IdentityCenter,
+        };
+        let mappings = vec![
+            mapping("prod", Some("111111111111"), &["Admin", "ReadOnly"]),
+            mapping("sandbox", Some("222222222222"), &["Admin"]),
+            mapping("legacy", None, &["ReadOnly"]),
+            mapping("audit", Some("333333333333"), &["Auditor", "Admin"]),
+        ];
+
+        let profiles = format.profiles(&mappings, organization.namespace.as_deref());
+        assert_eq!(
+            profiles
+                .iter()
+                .map(|profile| (profile.name.as_str(), profile.roles.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("mock_org-prod-111111111111", vec![String::from("ReadOnly")]),
+                ("mock_org-sandbox-222222222222", vec![String::from("Admin")]),
+            ]
+        );
+
+        let format = ProfileFormat {
+            template: "{account_name}-{role}".parse().unwrap(),
+            default_roles: None,
+            role_rules: Vec::new(),
+            duration_seconds: None,
+        };
+        assert_eq!(format.profiles(&mappings, None).len(), 6);
+    }
+
     #[test]
     fn parse_organization_with_single_role() {
         let tempdir = tempfile::tempdir().unwrap();
@@ -386,10 +1088,104 @@ foo = "foo"
             application_name: String::from("foo"),
             account: None,
             roles: vec![String::from("my_role")],
-            duration_seconds: Some(300)
+            duration_seconds: Some(300),
+            chain: None,
+            role_rules: Vec::new(),
+            saml_fallback: None,
+            console: None,
+            cli: CliSettings::default(),
         }));
     }
 
+    #[test]
+    fn parse_organization_with_chained_roles() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let filepath = tempdir.path().join("mock_org.toml");
+        let mut file = File::create(filepath.clone()).unwrap();
+
+        write!(
+            file,
+            r#"
+username = "mock_user"
+role = "my_role"
+[profiles]
+foo = {{ application = "foo", assume_role = "arn:aws:iam::123456789012:role/Admin" }}
+bar = {{ application = "bar", assume_role = "arn:aws:iam::123456789012:role/Admin", chain_mode = "sdk" }}
+baz = {{ application = "baz", chained_role_arn = "arn:aws:iam::210987654321:role/Workload", external_id = "landing", session_name = "ci", chain_mode = "sdk" }}
+"#
+        )
+        .unwrap();
+
+        let organization = Organization::try_from(filepath.as_path()).unwrap();
+
+        let foo = &organization.profiles[0];
+        assert_eq!(
+            foo.chain,
+            Some(Chain {
+                role_arn: String::from("arn:aws:iam::123456789012:role/Admin"),
+                mode: ChainMode::Oktaws,
+                external_id: None,
+                session_name: None,
+            })
+        );
+        assert_eq!(foo.credentials_profile(), "foo");
+
+        let bar = &organization.profiles[1];
+        assert_eq!(
+            bar.chain.as_ref().map(|chain| chain.mode),
+            Some(ChainMode::Sdk)
+        );
+        assert_eq!(bar.credentials_profile(), "bar-source");
+
+        let baz = organization.profiles[2].chain.as_ref().unwrap();
+        assert_eq!(baz.role().unwrap().external_id.as_deref(), Some("landing"));
+        assert_eq!(
+            baz.sdk_settings("baz-source"),
+            vec![
+                ("role_arn", "arn:aws:iam::210987654321:role/Workload"),
+                ("source_profile", "baz-source"),
+                ("external_id", "landing"),
+                ("role_session_name", "ci"),
+            ]
+        );
+    }
+
+    #[test]
+    fn select_profiles_by_group() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let filepath = tempdir.path().join("mock_org.toml");
+        let mut file = File::create(filepath.clone()).unwrap();
+
+        write!(
+            file,
+            r#"
+username = "mock_user"
+role = "my_role"
+[profiles]
+prod-app = "prod-app"
+prod-data = "prod-data"
+dev = "dev"
+[groups]
+oncall = ["prod-*", "dev"]
+"#
+        )
+        .unwrap();
+
+        let organization = Organization::try_from(filepath.as_path()).unwrap();
+
+        assert_eq!(
+            organization.select(&"@oncall".parse().unwrap()),
+            vec!["prod-app", "prod-data", "dev"]
+        );
+        assert_eq!(
+            organization.select(&"prod-d*".parse().unwrap()),
+            vec!["prod-data"]
+        );
+        assert!(organization.select(&"@missing".parse().unwrap()).is_empty());
+    }
+
     #[test]
     fn must_have_profiles() {
         let tempdir = tempfile::tempdir().unwrap();
@@ -409,7 +1205,56 @@ username = "mock_user"
 
         assert_eq!(
             err.to_string(),
-            "TOML parse error at line 1, column 1\n  |\n1 | \n  | ^\nmissing field `profiles`\n"
+            format!("{}:1:1 missing field `profiles`", filepath.display())
+        );
+    }
+
+    #[test]
+    fn unknown_profile_key() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let filepath = tempdir.path().join("mock_org.toml");
+        let mut file = File::create(filepath.clone()).unwrap();
+
+        write!(
+            file,
+            r#"username = "mock_user"
+role = "my_role"
+[profiles]
+foo = {{ application = "foo", rolle = "Admin" }}
+"#
+        )
+        .unwrap();
+
+        let err = Organization::try_from(filepath.as_path()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{}:4:30 unknown key 'rolle', did you mean 'role'?",
+                filepath.display()
+            )
+        );
+
+        // The AWS CLI settings' keys are suggested too
+        std::fs::write(
+            &filepath,
+            r#"username = "mock_user"
+role = "my_role"
+[profiles]
+foo = { application = "foo", cli_pagr = "less" }
+"#,
+        )
+        .unwrap();
+
+        let err = Organization::try_from(filepath.as_path()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{}:4:30 unknown key 'cli_pagr', did you mean 'cli_pager'?",
+                filepath.display()
+            )
         );
     }
 
@@ -536,33 +1381,121 @@ foo = "foo"
         client.expect_app_links().returning(|_| Ok(Vec::new()));
 
         // With two (different) roles
-        client.expect_get_all_account_mappings().returning(|_| {
-            Ok(vec![
-                AppLinkAccountRoleMapping {
-                    account_name: "foo".to_string(),
-                    role_names: vec!["mock-role".to_string()],
-                    application_name: "blah".to_string(),
-                    integration_type: IntegrationType::Federated,
-                },
-                AppLinkAccountRoleMapping {
-                    account_name: "bar".to_string(),
-                    role_names: vec!["mock-role-2".to_string()],
-                    application_name: "blah".to_string(),
-                    integration_type: IntegrationType::Federated,
-                },
-            ])
-        });
+        client
+            .expect_get_all_account_mappings()
+            .returning(|_, _, _| {
+                Ok(vec![
+                    AppLinkAccountRoleMapping {
+                        account_name: "foo".to_string(),
+                        account_id: None,
+                        account_email: None,
+                        account_description: None,
+                        role_names: vec!["mock-role".to_string()],
+                        application_name: "blah".to_string(),
+                        integration_type: IntegrationType::Federated,
+                    },
+                    AppLinkAccountRoleMapping {
+                        account_name: "bar".to_string(),
+                        account_id: None,
+                        account_email: None,
+                        account_description: None,
+                        role_names: vec!["mock-role-2".to_string()],
+                        application_name: "blah".to_string(),
+                        integration_type: IntegrationType::Federated,
+                    },
+                ])
+            });
 
         client
             .expect_remove_overlapped_account_mappings()
             .returning(Ok);
 
-        let config = Config::from_organization(&client, String::from("test_user"))
-            .await
-            .unwrap();
+        let config = Config::from_organization(
+            &client,
+            String::from("test_user"),
+            false,
+            &NamingTemplate::default(),
+        )
+        .await
+        .unwrap();
 
         assert_eq!(config.role, None);
         assert_eq!(config.roles, None);
         assert_eq!(config.profiles.len(), 2);
     }
+
+    #[tokio::test]
+    async fn init_with_a_profile_per_role() -> Result<()> {
+        let mut client = OktaClient::new();
+        client.expect_app_links().returning(|_| Ok(Vec::new()));
+
+        // Admin is in both accounts, which would otherwise prompt for default roles
+        client
+            .expect_get_all_account_mappings()
+            .returning(|_, _, _| {
+                Ok(vec![
+                    AppLinkAccountRoleMapping {
+                        account_name: "foo".to_string(),
+                        account_id: None,
+                        account_email: None,
+                        account_description: None,
+                        role_names: vec!["Admin".to_string(), "Read_Only".to_string()],
+                        application_name: "blah".to_string(),
+                        integration_type: IntegrationType::IdentityCenter,
+                    },
+                    AppLinkAccountRoleMapping {
+                        account_name: "bar".to_string(),
+                        account_id: None,
+                        account_email: None,
+                        account_description: None,
+                        role_names: vec!["Admin".to_string()],
+                        application_name: "blah".to_string(),
+                        integration_type: IntegrationType::IdentityCenter,
+                    },
+                ])
+            });
+
+        client
+            .expect_remove_overlapped_account_mappings()
+            .returning(Ok);
+
+        let config = Config::from_organization(
+            &client,
+            String::from("test_user"),
+            false,
+            &"{account}-{role}".parse()?,
+        )
+        .await?;
+
+        assert_eq!(config.role, None);
+        assert_eq!(
+            config.profiles.keys().collect::<Vec<_>>(),
+            vec!["bar-admin", "foo-admin", "foo-read-only"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_the_first_profile_of_each_name() {
+        let profiles = first_of_each_name([
+            (
+                String::from("prod"),
+                profile::Config::Name(String::from("aws")),
+            ),
+            (
+                String::from("prod"),
+                profile::Config::Name(String::from("sso")),
+            ),
+            (
+                String::from("dev"),
+                profile::Config::Name(String::from("aws")),
+            ),
+        ]);
+
+        assert_eq!(profiles.keys().collect::<Vec<_>>(), vec!["prod", "dev"]);
+        assert!(
+            matches!(&profiles["prod"], profile::Config::Name(application) if application == "aws")
+        );
+    }
 }
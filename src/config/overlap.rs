@@ -0,0 +1,288 @@
+//! Profiles which more than one organization defines, e.g. when a team's shared config and a personal one overlap.
+//!
+//! Organizations fetching the same account and role would each fetch credentials for it, and race to write them.
+//! Instead, each account and role is refreshed by a single organization: the one with the highest `priority`.
+//! Organizations writing different accounts (or roles) to the same credentials section are resolved the same way.
+//! When the highest priority is shared, neither organization is preferred, and refreshes fail rather than pick one.
+
+use crate::aws::profile::Layout;
+use crate::config::organization::Organization;
+use crate::config::profile::Profile;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use glob::Pattern;
+
+/// An account and role (or a credentials section) which more than one organization's profiles are for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Overlap {
+    /// What the profiles are for, e.g. `production as Admin`, or `[prod]` for a section
+    pub what: String,
+    /// Whether it is a section the profiles write different accounts (or roles) to, rather than an account and role
+    pub section: bool,
+    /// The organization refreshing it
+    pub kept: String,
+    /// The organizations which define it too, but leave it alone
+    pub dropped: Vec<String>,
+    /// Whether the highest priority was shared, so that `kept` (the first by name) is not really preferred
+    pub tied: bool,
+}
+
+/// The account (or, for a SAML app without one, the app) and roles `profile` asks for
+fn target(profile: &Profile) -> (String, Vec<String>) {
+    let mut roles = profile.roles.clone();
+    roles.sort();
+
+    (
+        profile
+            .account
+            .clone()
+            .unwrap_or_else(|| profile.application_name.clone()),
+        roles,
+    )
+}
+
+/// Remove the profiles matching `profiles` which another organization takes priority for,
+/// returning each account and role defined more than once, then each section still written more than once
+pub fn resolve(organizations: &mut [Organization], profiles: &Pattern) -> Vec<Overlap> {
+    let mut overlaps = resolve_by(
+        organizations,
+        profiles,
+        false,
+        target,
+        |(account, roles)| {
+            if roles.is_empty() {
+                account.clone()
+            } else {
+                format!("{account} as {}", roles.join(" or "))
+            }
+        },
+    );
+    overlaps.extend(resolve_by(
+        organizations,
+        profiles,
+        true,
+        Profile::credentials_profile,
+        |section| format!("[{section}]"),
+    ));
+
+    overlaps
+}
+
+/// Remove the profiles matching `profiles` which share their `key` (and credentials file)
+/// with a profile of an organization taking priority, describing each overlap with `describe`
+fn resolve_by<K: Ord>(
+    organizations: &mut [Organization],
+    profiles: &Pattern,
+    section: bool,
+    key: impl Fn(&Profile) -> K,
+    describe: impl Fn(&K) -> String,
+) -> Vec<Overlap> {
+    // Each key's organizations, by credentials file (and layout)
+    let mut keys: BTreeMap<(Option<PathBuf>, Layout, K), Vec<usize>> = BTreeMap::new();
+    for (index, organization) in organizations.iter().enumerate() {
+        for profile in &organization.profiles {
+            if profiles.matches(&profile.name) {
+                keys.entry((
+                    organization.credentials_file.clone(),
+                    organization.credentials_layout,
+                    key(profile),
+                ))
+                .or_default()
+                .push(index);
+            }
+        }
+    }
+
+    let mut overlaps = Vec::new();
+    for ((_, _, shared), mut indices) in keys {
+        indices.dedup();
+        if indices.len() < 2 {
+            continue;
+        }
+
+        // Highest priority first, then by name
+        indices.sort_by(|a, b| {
+            let (a, b) = (&organizations[*a], &organizations[*b]);
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        let kept = indices[0];
+        let tied = organizations[indices[1]].priority == organizations[kept].priority;
+
+        for index in &indices[1..] {
+            organizations[*index]
+                .profiles
+                .retain(|profile| !profiles.matches(&profile.name) || key(profile) != shared);
+        }
+
+        overlaps.push(Overlap {
+            what: describe(&shared),
+            section,
+            kept: organizations[kept].name.clone(),
+            dropped: indices[1..]
+                .iter()
+                .map(|index| organizations[*index].name.clone())
+                .collect(),
+            tied,
+        });
+    }
+
+    overlaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::aws::partition::Partition;
+    use crate::aws::sso::AccountNaming;
+    use crate::config::profile::{CliSettings, Profile};
+    use crate::okta::applications::Precedence;
+    use crate::okta::portal::Portal;
+
+    use indexmap::IndexMap;
+
+    fn organization(name: &str, priority: i32, profiles: &[&str]) -> eyre::Result<Organization> {
+        with_accounts(
+            name,
+            priority,
+            &profiles
+                .iter()
+                .map(|profile| (*profile, *profile))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// An organization with a profile named for each pair, for the account in each pair
+    fn with_accounts(
+        name: &str,
+        priority: i32,
+        profiles: &[(&str, &str)],
+    ) -> eyre::Result<Organization> {
+        Ok(Organization {
+            name: name.to_string(),
+            username: String::from("mock_user"),
+            portal: Portal::new(name)?,
+            account_naming: AccountNaming::default(),
+            application_precedence: Precedence::default(),
+            partition: Partition::default(),
+            credentials_file: None,
+            credentials_layout: Layout::default(),
+            silent_refresh: None,
+            identity_center: None,
+            sinks: Vec::new(),
+            priority,
+            namespace: None,
+            profile_format: None,
+            profiles: profiles
+                .iter()
+                .map(|(profile, account)| Profile {
+                    name: profile.to_string(),
+                    application_name: String::from("AWS SSO"),
+                    account: Some(account.to_string()),
+                    roles: vec![String::from("Admin")],
+                    duration_seconds: None,
+                    chain: None,
+                    role_rules: Vec::new(),
+                    saml_fallback: None,
+                    console: None,
+                    cli: CliSettings::default(),
+                })
+                .collect(),
+            groups: IndexMap::new(),
+        })
+    }
+
+    fn names(organization: &Organization) -> Vec<&str> {
+        organization
+            .profiles
+            .iter()
+            .map(|profile| profile.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn refreshes_each_section_once() -> eyre::Result<()> {
+        let mut organizations = vec![
+            organization("personal", 0, &["prod", "sandbox"])?,
+            organization("team", 10, &["prod", "staging"])?,
+            organization("vendor", 0, &["sandbox"])?,
+        ];
+
+        let overlaps = resolve(&mut organizations, &Pattern::new("*")?);
+        assert_eq!(
+            overlaps,
+            vec![
+                Overlap {
+                    what: String::from("prod as Admin"),
+                    section: false,
+                    kept: String::from("team"),
+                    dropped: vec![String::from("personal")],
+                    tied: false,
+                },
+                Overlap {
+                    what: String::from("sandbox as Admin"),
+                    section: false,
+                    kept: String::from("personal"),
+                    dropped: vec![String::from("vendor")],
+                    tied: true,
+                },
+            ]
+        );
+        assert_eq!(names(&organizations[0]), vec!["sandbox"]);
+        assert_eq!(names(&organizations[1]), vec!["prod", "staging"]);
+        assert!(names(&organizations[2]).is_empty());
+
+        // Profiles which are not being refreshed are left alone
+        let mut organizations = vec![
+            organization("personal", 0, &["prod"])?,
+            organization("team", 0, &["prod"])?,
+        ];
+        assert!(resolve(&mut organizations, &Pattern::new("staging")?).is_empty());
+        assert_eq!(names(&organizations[0]), vec!["prod"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn refreshes_each_account_and_role_once() -> eyre::Result<()> {
+        // The same account and role, under different names
+        let mut organizations = vec![
+            with_accounts("personal", 0, &[("my-prod", "production")])?,
+            with_accounts("team", 10, &[("prod", "production")])?,
+        ];
+        assert_eq!(
+            resolve(&mut organizations, &Pattern::new("*")?),
+            vec![Overlap {
+                what: String::from("production as Admin"),
+                section: false,
+                kept: String::from("team"),
+                dropped: vec![String::from("personal")],
+                tied: false,
+            }]
+        );
+        assert!(names(&organizations[0]).is_empty());
+        assert_eq!(names(&organizations[1]), vec!["prod"]);
+
+        // Different accounts, written to the same section
+        let mut organizations = vec![
+            with_accounts("personal", 0, &[("sandbox", "my-sandbox")])?,
+            with_accounts("vendor", 0, &[("sandbox", "vendor-sandbox")])?,
+        ];
+        assert_eq!(
+            resolve(&mut organizations, &Pattern::new("*")?),
+            vec![Overlap {
+                what: String::from("[sandbox]"),
+                section: true,
+                kept: String::from("personal"),
+                dropped: vec![String::from("vendor")],
+                tied: true,
+            }]
+        );
+
+        Ok(())
+    }
+}
@@ -3,19 +3,39 @@ use mockall_double::double;
 #[double]
 use crate::okta::client::Client as OktaClient;
 use crate::{
-    aws::{sso::Client as SsoClient, sts_client},
+    aws::{
+        console::Page as ConsolePage,
+        identity_center::{self, Client as IdentityCenterClient},
+        partition::Partition,
+        role::ChainedRole,
+        sso::{AccountNaming, AppInstance, Client as SsoClient, IncompleteCredentials},
+        sts_client, sts_client_with_credentials,
+    },
+    blocking,
+    config::cache_dir,
+    config::error::keys,
+    config::rename::{self, RoleRenamed},
+    config::rules::{self, RoleRule},
     okta::applications::{AppLink, AppLinkAccountRoleMapping, IntegrationType},
-    select,
+    picker::pick,
+    secrets::{self, Secret},
 };
 
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::LazyLock;
+
 use aws_credential_types::Credentials;
 use eyre::{Result, eyre};
-use serde::{Deserialize, Serialize};
-use tracing::{instrument, trace};
+use serde::de::{
+    self, DeserializeSeed, IntoDeserializer, MapAccess, Visitor, value::MapAccessDeserializer,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::{debug, instrument, trace, warn};
 
 /// This is an intentionally 'loose' struct,
 /// representing the potential various ways of providing a profile.
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 #[serde(untagged)]
 pub enum Config {
     Name(String),
@@ -24,10 +44,264 @@ pub enum Config {
         account: Option<String>,
         role: Option<String>,
         duration_seconds: Option<i32>,
+        /// A role to chain into after signing in
+        assume_role: Option<String>,
+        chain_mode: Option<ChainMode>,
+        /// The external ID the chained role's trust policy requires
+        #[serde(skip_serializing_if = "Option::is_none")]
+        external_id: Option<String>,
+        /// The session name to chain into the role with
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session_name: Option<String>,
+        /// A SAML application to sign in with instead, if AWS SSO returns incomplete credentials
+        #[serde(skip_serializing_if = "Option::is_none")]
+        saml_fallback: Option<String>,
+        /// The page `oktaws console` opens, instead of the console home
+        #[serde(skip_serializing_if = "Option::is_none")]
+        console: Option<ConsolePage>,
+        #[serde(flatten)]
+        cli: CliSettings,
     },
 }
 
+/// The table form of a profile, kept separate so that unknown keys are reported where they are,
+/// rather than as a failure to match either form. Its AWS CLI settings are read into [`CliSettings`] alongside it.
+#[derive(Deserialize)]
+struct DetailedConfig {
+    application: String,
+    account: Option<String>,
+    role: Option<String>,
+    duration_seconds: Option<i32>,
+    #[serde(alias = "chained_role_arn")]
+    assume_role: Option<String>,
+    chain_mode: Option<ChainMode>,
+    external_id: Option<String>,
+    session_name: Option<String>,
+    saml_fallback: Option<String>,
+    console: Option<ConsolePage>,
+}
+
+/// Every key a profile table may have, as serde reads them into [`DetailedConfig`] and [`CliSettings`]
+static PROFILE_KEYS: LazyLock<Vec<&'static str>> =
+    LazyLock::new(|| [keys::<DetailedConfig>(), keys::<CliSettings>()].concat());
+
+/// A profile table, with each key checked against [`PROFILE_KEYS`],
+/// and the AWS CLI settings set aside in `cli` rather than passed on to [`DetailedConfig`]
+struct ProfileTable<'a, A> {
+    map: A,
+    cli: &'a mut toml::Table,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for ProfileTable<'_, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> std::result::Result<Option<K::Value>, A::Error> {
+        while let Some(key) = self.map.next_key_seed(ProfileKey)? {
+            if keys::<CliSettings>().contains(&key.as_str()) {
+                let value = self.map.next_value()?;
+                self.cli.insert(key, value);
+            } else {
+                return seed
+                    .deserialize(IntoDeserializer::<A::Error>::into_deserializer(key))
+                    .map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> std::result::Result<V::Value, A::Error> {
+        self.map.next_value_seed(seed)
+    }
+}
+
+/// A key of a profile table, which must be one of [`PROFILE_KEYS`].
+/// It is checked as it is read, so that the error is located at the key.
+struct ProfileKey;
+
+impl<'de> DeserializeSeed<'de> for ProfileKey {
+    type Value = String;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<String, D::Error> {
+        let key = String::deserialize(deserializer)?;
+
+        if PROFILE_KEYS.contains(&key.as_str()) {
+            Ok(key)
+        } else {
+            Err(de::Error::unknown_field(&key, PROFILE_KEYS.as_slice()))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Config {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct ConfigVisitor;
+
+        impl<'de> Visitor<'de> for ConfigVisitor {
+            type Value = Config;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an application name, or a table with an `application` key")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> std::result::Result<Config, E> {
+                Ok(Config::Name(name.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> std::result::Result<Config, A::Error> {
+                let mut cli = toml::Table::new();
+                let detailed =
+                    DetailedConfig::deserialize(MapAccessDeserializer::new(ProfileTable {
+                        map,
+                        cli: &mut cli,
+                    }))?;
+                let cli =
+                    CliSettings::deserialize(toml::Value::Table(cli)).map_err(de::Error::custom)?;
+
+                Ok(Config::Detailed {
+                    application: detailed.application,
+                    account: detailed.account,
+                    role: detailed.role,
+                    duration_seconds: detailed.duration_seconds,
+                    assume_role: detailed.assume_role,
+                    chain_mode: detailed.chain_mode,
+                    external_id: detailed.external_id,
+                    session_name: detailed.session_name,
+                    saml_fallback: detailed.saml_fallback,
+                    console: detailed.console,
+                    cli,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(ConfigVisitor)
+    }
+}
+
+/// Who assumes a profile's `assume_role`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChainMode {
+    /// oktaws assumes the role, and writes the chained credentials
+    #[default]
+    Oktaws,
+    /// oktaws writes the base credentials to a source profile,
+    /// and `role_arn`/`source_profile` to `~/.aws/config` so that the AWS SDKs assume the role
+    Sdk,
+}
+
+/// AWS CLI settings for a profile, kept in sync in its `~/.aws/config` section
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct CliSettings {
+    /// `json`, `text`, `table`, `yaml` or `yaml-stream`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// The pager for the CLI's output; an empty string turns paging off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cli_pager: Option<String>,
+    /// `on`, `off` or `on-partial`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cli_auto_prompt: Option<String>,
+}
+
+impl CliSettings {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.output.is_none() && self.cli_pager.is_none() && self.cli_auto_prompt.is_none()
+    }
+
+    /// The settings which are set, as `~/.aws/config` keys and values
+    #[must_use]
+    pub fn entries(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("output", &self.output),
+            ("cli_pager", &self.cli_pager),
+            ("cli_auto_prompt", &self.cli_auto_prompt),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_deref().map(|value| (key, value)))
+        .collect()
+    }
+}
+
+/// A role which a profile chains into after signing in
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Chain {
+    pub role_arn: String,
+    pub mode: ChainMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    /// Defaults to `oktaws-<username>` (see [`ChainedRole::session_name`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_name: Option<String>,
+}
+
+impl Chain {
+    /// The role, as oktaws assumes it
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the role is not a valid ARN
+    pub fn role(&self) -> Result<ChainedRole> {
+        Ok(ChainedRole {
+            external_id: self.external_id.clone(),
+            session_name: self.session_name.clone(),
+            ..ChainedRole::new(self.role_arn.parse()?)
+        })
+    }
+
+    /// The `~/.aws/config` settings which have the AWS SDKs assume the role, from `source_profile`
+    #[must_use]
+    pub fn sdk_settings<'a>(&'a self, source_profile: &'a str) -> Vec<(&'static str, &'a str)> {
+        let mut settings = vec![
+            ("role_arn", self.role_arn.as_str()),
+            ("source_profile", source_profile),
+        ];
+        if let Some(external_id) = &self.external_id {
+            settings.push(("external_id", external_id));
+        }
+        if let Some(session_name) = &self.session_name {
+            settings.push(("role_session_name", session_name));
+        }
+
+        settings
+    }
+}
+
 impl Config {
+    /// Build a profile config for one of an account mapping's roles
+    #[must_use]
+    pub fn for_role(mapping: &AppLinkAccountRoleMapping, role: &str) -> Self {
+        Self::for_account(mapping, Some(role.to_string()))
+    }
+
+    /// Build a profile config for an account mapping, leaving the role to the organization's defaults without `role`
+    #[must_use]
+    pub fn for_account(mapping: &AppLinkAccountRoleMapping, role: Option<String>) -> Self {
+        Self::Detailed {
+            application: mapping.application_name.clone(),
+            account: Some(mapping.account_name.clone()),
+            role,
+            duration_seconds: None,
+            assume_role: None,
+            chain_mode: None,
+            external_id: None,
+            session_name: None,
+            saml_fallback: None,
+            console: None,
+            cli: CliSettings::default(),
+        }
+    }
+
     #[instrument(skip(mapping, default_roles))]
     pub fn from_account_mapping(
         mapping: AppLinkAccountRoleMapping,
@@ -49,12 +323,12 @@ impl Config {
             _ if default_roles_available.len() == 1 => {
                 Ok(default_roles_available.first().unwrap().clone())
             }
-            _ if default_roles_available.len() > 1 => Ok(select(
+            _ if default_roles_available.len() > 1 => Ok(pick(
                 default_roles_available.clone(),
                 format!("Choose Role for {}", mapping.account_name),
                 std::clone::Clone::clone,
             )?),
-            _ => Ok(select(
+            _ => Ok(pick(
                 mapping.role_names.clone(),
                 format!("Choose Role for {}", mapping.account_name),
                 std::clone::Clone::clone,
@@ -74,6 +348,13 @@ impl Config {
                 account: Some(mapping.account_name.clone()),
                 role: None,
                 duration_seconds: None,
+                assume_role: None,
+                chain_mode: None,
+                external_id: None,
+                session_name: None,
+                saml_fallback: None,
+                console: None,
+                cli: CliSettings::default(),
             }
         } else {
             Self::Detailed {
@@ -81,6 +362,13 @@ impl Config {
                 account: Some(mapping.account_name.clone()),
                 role: Some(role_name),
                 duration_seconds: None,
+                assume_role: None,
+                chain_mode: None,
+                external_id: None,
+                session_name: None,
+                saml_fallback: None,
+                console: None,
+                cli: CliSettings::default(),
             }
         };
 
@@ -97,6 +385,20 @@ pub struct Profile {
     pub account: Option<String>,
     pub roles: Vec<String>,
     pub duration_seconds: Option<i32>,
+    pub chain: Option<Chain>,
+    /// The organization's role rules, when the profile doesn't name a role,
+    /// to try again once the account's email is known
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub role_rules: Vec<RoleRule>,
+    /// The SAML application (by label) to fall back on when AWS SSO returns incomplete credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saml_fallback: Option<String>,
+    /// The page `oktaws console` opens, instead of the console home
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub console: Option<ConsolePage>,
+    /// AWS CLI settings to keep in the profile's `~/.aws/config` section
+    #[serde(default, skip_serializing_if = "CliSettings::is_empty")]
+    pub cli: CliSettings,
 }
 
 impl Profile {
@@ -109,25 +411,42 @@ impl Profile {
         profile_config: &Config,
         name: String,
         default_roles: Option<Vec<String>>,
+        role_rules: &[RoleRule],
         default_duration_seconds: Option<i32>,
     ) -> Result<Self> {
+        let account = match profile_config {
+            Config::Name(_) => None,
+            Config::Detailed { account, .. } => account.clone(),
+        };
+        let role = match profile_config {
+            Config::Name(_) => None,
+            Config::Detailed { role, .. } => role.clone(),
+        };
+        // Rules are only needed later if the profile doesn't name a role
+        let role_rules = if role.is_none() {
+            role_rules.to_vec()
+        } else {
+            Vec::new()
+        };
+
         Ok(Self {
-            name,
             application_name: match profile_config {
                 Config::Name(name) => name,
                 Config::Detailed { application, .. } => application,
             }
             .clone(),
-            account: match profile_config {
-                Config::Name(_) => None,
-                Config::Detailed { account, .. } => account.clone(),
-            },
-            roles: match profile_config {
-                Config::Name(_) => None,
-                Config::Detailed { role, .. } => role.clone().map(|r| vec![r]),
-            }
-            .or(default_roles)
-            .ok_or_else(|| eyre!("No role found"))?,
+            roles: role
+                .map(|r| vec![r])
+                .or_else(|| {
+                    rules::first_match(&role_rules, account.as_ref().unwrap_or(&name), None)
+                        .map(|rule| vec![rule.role.clone()])
+                })
+                .or(default_roles)
+                // A rule on the account's email may still match, once AWS SSO gives it
+                .or_else(|| (!role_rules.is_empty()).then(Vec::new))
+                .ok_or_else(|| eyre!("No role found"))?,
+            name,
+            account,
             duration_seconds: match profile_config {
                 Config::Name(_) => None,
                 Config::Detailed {
@@ -135,14 +454,98 @@ impl Profile {
                 } => *duration_seconds,
             }
             .or(default_duration_seconds),
+            chain: match profile_config {
+                Config::Name(_) => None,
+                Config::Detailed {
+                    assume_role,
+                    chain_mode,
+                    external_id,
+                    session_name,
+                    ..
+                } => assume_role.clone().map(|role_arn| Chain {
+                    role_arn,
+                    mode: chain_mode.unwrap_or_default(),
+                    external_id: external_id.clone(),
+                    session_name: session_name.clone(),
+                }),
+            },
+            role_rules,
+            saml_fallback: match profile_config {
+                Config::Name(_) => None,
+                Config::Detailed { saml_fallback, .. } => saml_fallback.clone(),
+            },
+            console: match profile_config {
+                Config::Name(_) => None,
+                Config::Detailed { console, .. } => console.clone(),
+            },
+            cli: match profile_config {
+                Config::Name(_) => CliSettings::default(),
+                Config::Detailed { cli, .. } => cli.clone(),
+            },
         })
     }
 
-    #[instrument(skip(self, client), fields(organization=%client.base_url(), profile=%self.name))]
+    /// The credentials file section which this profile's credentials are written to.
+    /// When the AWS SDKs perform the chaining, this is a separate source profile holding the base credentials.
+    #[must_use]
+    pub fn credentials_profile(&self) -> String {
+        match &self.chain {
+            Some(Chain {
+                mode: ChainMode::Sdk,
+                ..
+            }) => format!("{}-source", self.name),
+            _ => self.name.clone(),
+        }
+    }
+
+    /// Fetch credentials for this profile, chaining into `assume_role` if oktaws is responsible for that
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the base credentials cannot be fetched,
+    /// or if the chained role cannot be assumed.
     pub async fn into_credentials(
         self,
         client: &OktaClient,
         role_override: Option<&String>,
+        naming: &AccountNaming,
+        identity_center: Option<&identity_center::Config>,
+    ) -> Result<Credentials> {
+        let chain = self.chain.clone();
+        let credentials = match identity_center {
+            // Accounts in Identity Center are reached without their Okta tile
+            Some(identity_center) if self.account.is_some() => {
+                self.into_identity_center_credentials(role_override, identity_center)
+                    .await?
+            }
+            _ => {
+                self.into_base_credentials(client, role_override, naming)
+                    .await?
+            }
+        };
+
+        match chain {
+            Some(
+                chain @ Chain {
+                    mode: ChainMode::Oktaws,
+                    ..
+                },
+            ) => {
+                let role = chain.role()?;
+                let partition = Partition::of(&role.role)?;
+                role.assume(sts_client_with_credentials(credentials, partition))
+                    .await
+            }
+            _ => Ok(credentials),
+        }
+    }
+
+    #[instrument(skip(self, client), fields(organization=%client.base_url(), profile=%self.name))]
+    pub async fn into_base_credentials(
+        self,
+        client: &OktaClient,
+        role_override: Option<&String>,
+        naming: &AccountNaming,
     ) -> Result<Credentials> {
         let saml_app_link = client.app_links(None).await?.into_iter().find(|app_link| {
             app_link.app_name == "amazon_aws" && app_link.label == self.application_name
@@ -159,9 +562,24 @@ impl Profile {
         });
 
         if let Some(app_link) = sso_app_link {
-            return self
-                .into_sso_credentials(client, app_link, role_override)
-                .await;
+            let Some(fallback) = self.saml_fallback.clone() else {
+                return self
+                    .into_sso_credentials(client, app_link, role_override, naming)
+                    .await;
+            };
+
+            return match self
+                .clone()
+                .into_sso_credentials(client, app_link, role_override, naming)
+                .await
+            {
+                Err(e) if e.downcast_ref::<IncompleteCredentials>().is_some() => {
+                    warn!("{e}; signing in with {fallback} instead");
+                    self.into_saml_fallback_credentials(client, &fallback, role_override)
+                        .await
+                }
+                result => result,
+            };
         }
 
         Err(eyre!(
@@ -170,6 +588,97 @@ impl Profile {
         ))
     }
 
+    /// Fetch credentials for this profile through IAM Identity Center's OIDC device flow,
+    /// rather than the legacy portal behind its Okta tile
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if signing in to Identity Center fails,
+    /// if the account or role is not assigned to the user, or if the credentials cannot be fetched.
+    #[instrument(skip_all, fields(start_url=%identity_center.start_url, profile=%self.name))]
+    pub async fn into_identity_center_credentials(
+        self,
+        role_override: Option<&String>,
+        identity_center: &identity_center::Config,
+    ) -> Result<Credentials> {
+        let account_name = self
+            .account
+            .clone()
+            .ok_or_else(|| eyre!("Identity Center profiles must specify `account`"))?;
+        let sso = IdentityCenterClient::sign_in(
+            identity_center,
+            &cache_dir()?.join(identity_center::DIR),
+        )
+        .await?;
+
+        let account = sso
+            .accounts()
+            .await?
+            .into_iter()
+            .find(|account| account.name == account_name)
+            .ok_or_else(|| eyre!("Could not find account: {account_name}"))?;
+        trace!("Found account: {:?}", account);
+
+        let roles = match role_override {
+            Some(role_override) => vec![role_override.clone()],
+            None => rules::first_match(&self.role_rules, &account.name, account.email.as_deref())
+                .map_or_else(|| self.roles.clone(), |rule| vec![rule.role.clone()]),
+        };
+
+        let roles_available = sso
+            .roles(&account.id)
+            .await?
+            .into_iter()
+            .filter(|role| roles.contains(role))
+            .collect::<Vec<_>>();
+
+        let role = match roles_available.len() {
+            0 => Err(eyre!(
+                "None of the roles for profile {} ({}) are assigned in {}",
+                self.name,
+                roles.join(", "),
+                account.name
+            )),
+            1 => Ok(roles_available[0].clone()),
+            _ => {
+                let prompt = format!("Choose Role for profile {}", self.name);
+                blocking::prompt(move || pick(roles_available, prompt, Clone::clone)).await
+            }
+        }?;
+
+        if self.duration_seconds.is_some() {
+            debug!(
+                "Ignoring the duration for {}, as Identity Center uses the permission set's",
+                self.name
+            );
+        }
+
+        sso.credentials(&account.id, &role).await
+    }
+
+    /// Fetch credentials through the profile's `saml_fallback` application, when AWS SSO's are unusable
+    async fn into_saml_fallback_credentials(
+        self,
+        client: &OktaClient,
+        fallback: &str,
+        role_override: Option<&String>,
+    ) -> Result<Credentials> {
+        let app_link = client
+            .app_links(None)
+            .await?
+            .into_iter()
+            .find(|app_link| app_link.app_name == "amazon_aws" && app_link.label == fallback)
+            .ok_or_else(|| {
+                eyre!(
+                    "Could not find the SAML application {fallback} for profile {}",
+                    self.name
+                )
+            })?;
+
+        self.into_saml_credentials(client, app_link, role_override)
+            .await
+    }
+
     async fn into_saml_credentials(
         self,
         client: &OktaClient,
@@ -194,6 +703,9 @@ impl Profile {
                 .into_iter()
                 .filter(|r| r.role_name().unwrap() == *role_override)
                 .collect::<Vec<_>>()
+        } else if self.roles.is_empty() {
+            // No rule matched the account, so choose one of every role in it
+            saml_roles
         } else {
             saml_roles
                 .into_iter()
@@ -218,19 +730,22 @@ impl Profile {
             }
             1 => Ok(saml_roles_available[0].clone()),
             _ => {
-                let selected = select(
-                    saml_roles_available,
-                    format!("Choose Role for profile {}", self.name),
-                    |role| role.role_name().unwrap(),
-                )?;
-                Ok(selected)
+                let prompt = format!("Choose Role for profile {}", self.name);
+                blocking::prompt(move || {
+                    pick(saml_roles_available, prompt, |role| {
+                        role.role_name().unwrap()
+                    })
+                })
+                .await
             }
         }?;
 
         trace!("Found role: {} for profile {}", saml_role.role, &self.name);
 
+        // The role's own ARN says which partition's STS will accept it
+        let partition = Partition::of(&saml_role.role)?;
         let credentials = saml_role
-            .assume(sts_client(), response.saml, self.duration_seconds)
+            .assume(sts_client(partition), response.saml, self.duration_seconds)
             .await
             .map_err(|e| eyre!("Error assuming role for profile {} ({})", self.name, e))?;
 
@@ -244,40 +759,60 @@ impl Profile {
         client: &OktaClient,
         app_link: AppLink,
         role_override: Option<&String>,
+        naming: &AccountNaming,
     ) -> Result<Credentials> {
-        let org_auth = client
-            .get_org_id_and_auth_code_for_app_link(app_link)
-            .await?;
+        let (client, app_instances) = sso_sign_in(client, app_link).await?;
 
-        let client = SsoClient::new(&org_auth.org_id, &org_auth.auth_code).await?;
-
-        let app_instance = if let Some(account) = self.account {
-            client
-                .app_instances()
-                .await?
+        let app_instance = if let Some(account) = &self.account {
+            app_instances
                 .into_iter()
-                .find(|app| app.account_name() == Some(account.clone()))
+                .find(|app| app.account_name(naming).as_ref() == Some(account))
                 .ok_or_else(|| eyre!("Could not find account: {account}"))
         } else {
-            Err(eyre!("AWS SSO Applications must specify `account`"))
+            // Without an account configured, choose one of every account the portal lists
+            let prompt = format!("Choose Account for profile {}", self.name);
+            let naming = naming.clone();
+            blocking::prompt(move || {
+                pick(app_instances, prompt, |app| {
+                    app.account_name(&naming)
+                        .unwrap_or_else(|| app.name.clone())
+                })
+            })
+            .await
         }?;
         trace!("Found application: {:?}", app_instance);
 
+        // Now that the account's email is known, rules on it can match too
+        let roles = app_instance
+            .account_name(naming)
+            .and_then(|account| {
+                rules::first_match(&self.role_rules, &account, app_instance.account_email())
+                    .map(|rule| vec![rule.role.clone()])
+            })
+            .unwrap_or_else(|| self.roles.clone());
+
         let account_id = app_instance
-            .account_id()
+            .account_id(naming)
             .ok_or_else(|| eyre!("No account ID found"))?;
 
         let profiles = client.profiles(&app_instance.id).await?;
+        let names = profiles
+            .iter()
+            .map(|profile| profile.name.clone())
+            .collect::<Vec<_>>();
 
         let profiles_available = if let Some(role_override) = role_override {
             profiles
                 .into_iter()
                 .filter(|profile| profile.name == *role_override)
                 .collect::<Vec<_>>()
+        } else if roles.is_empty() {
+            // No rule matched the account, so choose one of every permission set in it
+            profiles
         } else {
             profiles
                 .into_iter()
-                .filter(|profile| self.roles.contains(&profile.name))
+                .filter(|profile| roles.contains(&profile.name))
                 .collect::<Vec<_>>()
         };
 
@@ -289,6 +824,14 @@ impl Profile {
                         role_override,
                         self.name
                     ))
+                } else if let Some((role, suggestion)) = rename::suggest(&roles, &names) {
+                    Err(RoleRenamed {
+                        profile: self.name.clone(),
+                        account: app_instance.name.clone(),
+                        role,
+                        suggestion,
+                    }
+                    .into())
                 } else {
                     Err(eyre!(
                         "No profiles found for application {}",
@@ -298,20 +841,71 @@ impl Profile {
             }
             1 => Ok(profiles_available[0].clone()),
             _ => {
-                let selected = select(
-                    profiles_available,
-                    format!("Choose Profile for application {}", app_instance.name),
-                    |profile| profile.name.clone(),
-                )?;
-                Ok(selected)
+                let prompt = format!("Choose Profile for application {}", app_instance.name);
+                blocking::prompt(move || {
+                    pick(profiles_available, prompt, |profile| profile.name.clone())
+                })
+                .await
             }
         }?;
 
         trace!("Found profile: {:?}", profile);
 
-        let credentials = client.credentials(account_id, &profile.name).await?;
+        let credentials = client
+            .credentials(account_id, &profile.name, self.duration_seconds)
+            .await?;
         trace!("Credentials: {:?}", credentials);
 
         Ok(credentials)
     }
 }
+
+/// Sign in to the AWS SSO portal behind `app_link`, listing its app instances.
+/// The portal token from an earlier run is reused while the portal still accepts it,
+/// so that Okta is only asked for a new auth code when it doesn't.
+async fn sso_sign_in(
+    client: &OktaClient,
+    app_link: AppLink,
+) -> Result<(SsoClient, Vec<AppInstance>)> {
+    let secret = Secret::sso_tokens(client.base_url());
+    let key = app_link.link_url.to_string();
+
+    let reading = secret.clone();
+    let mut tokens = blocking::run(move || reading.get())
+        .await
+        .inspect_err(|e| debug!("Unable to read AWS SSO tokens ({e})"))
+        .ok()
+        .flatten()
+        .and_then(|tokens| serde_json::from_str::<BTreeMap<String, String>>(&tokens).ok())
+        .unwrap_or_default();
+
+    if let Some(token) = tokens.get(&key) {
+        let sso = SsoClient::from_token(token.clone());
+        match sso.app_instances().await {
+            Ok(app_instances) => {
+                debug!("Reusing the AWS SSO token for {}", app_link.label);
+                return Ok((sso, app_instances));
+            }
+            Err(e) => debug!(
+                "The AWS SSO token for {} is no longer valid ({e})",
+                app_link.label
+            ),
+        }
+    }
+
+    let org_auth = client
+        .get_org_id_and_auth_code_for_app_link(app_link)
+        .await?;
+    let sso = SsoClient::new(&org_auth.org_id, &org_auth.auth_code).await?;
+    let app_instances = sso.app_instances().await?;
+
+    tokens.insert(key, sso.token().to_string());
+    let tokens = serde_json::to_string(&tokens)?;
+    if secrets::kept() {
+        if let Err(e) = blocking::run(move || secret.set(&tokens)).await {
+            warn!("Unable to keep the AWS SSO token for next time: {e}");
+        }
+    }
+
+    Ok((sso, app_instances))
+}
@@ -0,0 +1,267 @@
+//! Running where oktaws may not write to its own directories, such as jump hosts and kiosks
+//! whose home directories are read-only, or shared between users.
+//!
+//! In read-only mode, the state and caches oktaws builds up go to a scratch directory of the process's own instead,
+//! in memory where the platform has somewhere for it (`XDG_RUNTIME_DIR`, or `/dev/shm`), which is removed on exit.
+//! The scratch state starts as a copy of the state directory, so that what earlier runs learnt (remembered sessions,
+//! the sections oktaws manages, discoveries) is still read; only what this run learns is lost.
+//! The config directory is only read: anything which would change it (such as `oktaws init`) fails.
+//! Credentials are still written where the organization writes them.
+
+use crate::config::{cache_dir, home_state_dir};
+
+use std::env::{temp_dir, var_os};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::OnceLock;
+
+use eyre::{Result, eyre};
+use tracing::{debug, info, warn};
+
+/// Why oktaws is read-only
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// `--read-only` was given
+    Requested,
+    /// The state directory cannot be written to
+    Detected(PathBuf),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Requested => write!(f, "--read-only was given"),
+            Self::Detected(dir) => write!(f, "{} cannot be written to", dir.display()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Mode {
+    reason: Reason,
+    scratch: PathBuf,
+}
+
+static MODE: OnceLock<Option<Mode>> = OnceLock::new();
+
+/// Go read-only for the whole process when `requested`, or when the state directory cannot be written to.
+/// Only the first call has any effect.
+///
+/// # Errors
+///
+/// Will return `Err` if oktaws goes read-only, but its scratch directory cannot be created
+pub fn install(requested: bool) -> Result<()> {
+    if MODE.get().is_some() {
+        return Ok(());
+    }
+
+    let reason = if requested {
+        Some(Reason::Requested)
+    } else {
+        home_state_dir()
+            .ok()
+            .filter(|dir| !writable(dir))
+            .map(Reason::Detected)
+    };
+
+    let mode = match reason {
+        Some(reason) => {
+            let scratch = create_scratch(&scratch_base(var_os))?;
+            // Caches are rebuilt as they are needed (and, with OKTAWS_HOME, are inside the state directory)
+            if let Ok(state) = home_state_dir() {
+                let skip: Vec<PathBuf> = cache_dir().into_iter().collect();
+                if let Err(e) = seed(&state, &scratch.join("state"), &skip) {
+                    warn!(
+                        "Unable to copy the state in {} for read-only mode ({e}); starting without it",
+                        state.display()
+                    );
+                }
+            }
+            match &reason {
+                Reason::Requested => debug!("Keeping state and caches in {}", scratch.display()),
+                Reason::Detected(_) => info!(
+                    "Running read-only, as {reason}; state and caches are kept in {} until oktaws exits",
+                    scratch.display()
+                ),
+            }
+            Some(Mode { reason, scratch })
+        }
+        None => None,
+    };
+
+    let _ = MODE.set(mode);
+    Ok(())
+}
+
+/// Why oktaws is read-only, if it is
+#[must_use]
+pub fn reason() -> Option<&'static Reason> {
+    MODE.get()?.as_ref().map(|mode| &mode.reason)
+}
+
+/// The directory state and caches are kept in, in read-only mode
+#[must_use]
+pub fn scratch() -> Option<&'static Path> {
+    MODE.get()?.as_ref().map(|mode| mode.scratch.as_path())
+}
+
+/// Fail in read-only mode, rather than `action` (e.g. `write the config`)
+///
+/// # Errors
+///
+/// Will return `Err` in read-only mode
+pub fn check(action: &str) -> Result<()> {
+    reason().map_or(Ok(()), |reason| {
+        Err(eyre!(
+            "oktaws is read-only ({reason}), so it cannot {action}"
+        ))
+    })
+}
+
+/// Remove the scratch directory, and everything kept in it
+pub fn clean_up() {
+    if let Some(scratch) = scratch() {
+        if let Err(e) = fs::remove_dir_all(scratch) {
+            debug!("Unable to remove {}: {e}", scratch.display());
+        }
+    }
+}
+
+/// Whether files can be created in `dir`, or the nearest of its parents which exists
+fn writable(dir: &Path) -> bool {
+    let Some(existing) = dir.ancestors().find(|dir| dir.is_dir()) else {
+        return true;
+    };
+
+    let probe = existing.join(format!(".oktaws-probe-{}", process::id()));
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            true
+        }
+        // Anything else is left to fail (and be reported) where the state is written
+        Err(e) => !matches!(
+            e.kind(),
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem
+        ),
+    }
+}
+
+/// Where the scratch directory goes: somewhere in memory if there is one, otherwise the temporary directory
+fn scratch_base(var: impl Fn(&str) -> Option<std::ffi::OsString>) -> PathBuf {
+    var("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute() && dir.is_dir())
+        .or_else(|| {
+            let shm = PathBuf::from("/dev/shm");
+            (cfg!(target_os = "linux") && shm.is_dir()).then_some(shm)
+        })
+        .unwrap_or_else(temp_dir)
+}
+
+/// Create a scratch directory in `base`, readable only by the current user
+fn create_scratch(base: &Path) -> Result<PathBuf> {
+    let scratch = base.join(format!("oktaws-{}", process::id()));
+
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    // Not create_dir_all: a directory someone else left there must not be used
+    builder.create(&scratch).map_err(|e| {
+        eyre!(
+            "Unable to create a scratch directory for read-only mode at {}: {e}",
+            scratch.display()
+        )
+    })?;
+
+    Ok(scratch)
+}
+
+/// Copy the files in `from` to `to`, except those under `skip`, so that read-only runs start from the existing state.
+/// Anything but plain files and directories (such as sockets) is left behind.
+fn seed(from: &Path, to: &Path, skip: &[PathBuf]) -> Result<()> {
+    let entries = match fs::read_dir(from) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    fs::create_dir_all(to)?;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if skip.contains(&path) {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            seed(&path, &to.join(entry.file_name()), skip)?;
+        } else if file_type.is_file() {
+            // Permissions are copied too, so secrets stay readable only by the user
+            fs::copy(&path, to.join(entry.file_name()))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_scratch_apart() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+
+        let base = scratch_base(|variable| {
+            (variable == "XDG_RUNTIME_DIR").then(|| tempdir.path().as_os_str().to_owned())
+        });
+        assert_eq!(base, tempdir.path());
+        // A relative runtime directory is ignored
+        assert_ne!(scratch_base(|_| Some("run".into())), Path::new("run"));
+
+        let scratch = create_scratch(&base)?;
+        assert!(scratch.is_dir());
+        assert!(writable(&scratch.join("state").join("discovery")));
+        // Another run with the same PID doesn't reuse it
+        assert!(create_scratch(&base).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn seeds_scratch_with_state() -> Result<()> {
+        let home = tempfile::tempdir()?;
+        fs::create_dir_all(home.path().join("sessions"))?;
+        fs::write(home.path().join("sessions").join("mycompany.json"), "{}")?;
+        fs::create_dir_all(home.path().join("cache"))?;
+        fs::write(home.path().join("cache").join("discovery.json"), "[]")?;
+
+        let scratch = tempfile::tempdir()?;
+        let state = scratch.path().join("state");
+        seed(home.path(), &state, &[home.path().join("cache")])?;
+
+        assert_eq!(
+            fs::read_to_string(state.join("sessions").join("mycompany.json"))?,
+            "{}"
+        );
+        assert!(!state.join("cache").exists());
+        // A state directory which was never created leaves nothing to copy
+        seed(
+            &home.path().join("missing"),
+            &scratch.path().join("empty"),
+            &[],
+        )?;
+        assert!(!scratch.path().join("empty").exists());
+
+        Ok(())
+    }
+}
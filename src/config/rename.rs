@@ -0,0 +1,278 @@
+//! Noticing AWS SSO permission sets which have been renamed since a profile was configured.
+//!
+//! When none of a profile's roles exist in its account any more, the closest permission set that does
+//! (by edit distance, or by one name containing the other) is suggested in the error.
+//! With `--auto-migrate`, the suggestion is written to the organization's config instead, and used straight away.
+//! Only the profile's own role is rewritten, unless `--migrate-defaults` asks for the organization's default too.
+
+use crate::config::error::closest;
+use crate::config::{oktaws_home, read_only};
+use crate::lock::FileLock;
+use crate::transaction::write_atomically;
+
+use std::fs;
+use std::sync::OnceLock;
+
+use eyre::{Result, eyre};
+use regex::Regex;
+use tracing::warn;
+
+static AUTO_MIGRATE: OnceLock<(bool, bool)> = OnceLock::new();
+
+/// Choose whether renamed roles are migrated, and whether the organization's default role may be,
+/// for the whole process. Only the first call has any effect.
+pub fn install(auto_migrate: bool, defaults: bool) {
+    let _ = AUTO_MIGRATE.set((auto_migrate, defaults));
+}
+
+/// Whether renamed roles are migrated
+#[must_use]
+pub fn auto_migrate() -> bool {
+    AUTO_MIGRATE
+        .get()
+        .is_some_and(|(auto_migrate, _)| *auto_migrate)
+}
+
+/// Whether a renamed role which is the organization's default is migrated there, for every profile using it
+fn migrate_defaults() -> bool {
+    AUTO_MIGRATE.get().is_some_and(|(_, defaults)| *defaults)
+}
+
+/// A profile's role which no longer exists, with the permission set it was most likely renamed to
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "Role {role} no longer exists in {account} for profile {profile}, but {suggestion} does. \
+     If it was renamed, refresh with --auto-migrate to use {suggestion} from now on"
+)]
+pub struct RoleRenamed {
+    pub profile: String,
+    pub account: String,
+    pub role: String,
+    pub suggestion: String,
+}
+
+/// The permission set in `available` which one of `roles` was most likely renamed to, with that role
+#[must_use]
+pub fn suggest(roles: &[String], available: &[String]) -> Option<(String, String)> {
+    roles.iter().find_map(|role| {
+        closest(role, available)
+            .map(ToString::to_string)
+            .or_else(|| contained(role, available))
+            .map(|suggestion| (role.clone(), suggestion))
+    })
+}
+
+/// The only name in `available` which contains `role` (or is contained in it), ignoring case and punctuation
+fn contained(role: &str, available: &[String]) -> Option<String> {
+    let normalize = |name: &str| {
+        name.chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_lowercase()
+    };
+    let role = normalize(role);
+
+    let mut matches = available.iter().filter(|name| {
+        let name = normalize(name);
+        !name.is_empty() && !role.is_empty() && (name.contains(&role) || role.contains(&name))
+    });
+
+    match (matches.next(), matches.next()) {
+        (Some(name), None) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// `contents` (an organization's config) with `profile`'s role changed from `role` to `suggestion`,
+/// where it is set: in the profile itself, or (with `defaults`) in the organization's default `role`/`roles`.
+/// Only the quoted value is replaced, so comments and formatting are kept.
+#[must_use]
+pub fn rename_role(
+    contents: &str,
+    profile: &str,
+    role: &str,
+    suggestion: &str,
+    defaults: bool,
+) -> Option<String> {
+    let value = Regex::new(&format!(r#"(["']){}(["'])"#, regex::escape(role))).ok()?;
+    let inline = Regex::new(&format!(
+        r#"^\s*(?:{0}|"{0}"|'{0}')\s*=\s*\{{.*\brole\s*="#,
+        regex::escape(profile)
+    ))
+    .ok()?;
+    let table = Regex::new(&format!(
+        r#"^\s*\[\s*profiles\.(?:{0}|"{0}"|'{0}')\s*\]"#,
+        regex::escape(profile)
+    ))
+    .ok()?;
+    let default = Regex::new(r"^\s*roles?\s*=").ok()?;
+    let key = Regex::new(r"^\s*role\s*=").ok()?;
+
+    let lines = contents.split_inclusive('\n').collect::<Vec<_>>();
+
+    // The line setting the role: in the profile's inline table, then its own table, then the organization's defaults
+    let in_table = lines
+        .iter()
+        .position(|line| table.is_match(line))
+        .and_then(|start| {
+            lines[start + 1..]
+                .iter()
+                .take_while(|line| !line.trim_start().starts_with('['))
+                .position(|line| key.is_match(line) && value.is_match(line))
+                .map(|offset| start + 1 + offset)
+        });
+    let index = lines
+        .iter()
+        .position(|line| inline.is_match(line) && value.is_match(line))
+        .or(in_table)
+        .or_else(|| {
+            lines
+                .iter()
+                .take_while(|line| !line.trim_start().starts_with('['))
+                .position(|line| default.is_match(line) && value.is_match(line))
+                .filter(|_| defaults)
+        })?;
+
+    let mut renamed = lines.iter().map(ToString::to_string).collect::<Vec<_>>();
+    renamed[index] = value
+        .replace(&renamed[index], |captures: &regex::Captures| {
+            format!("{}{suggestion}{}", &captures[1], &captures[2])
+        })
+        .into_owned();
+
+    Some(renamed.concat())
+}
+
+/// Write the rename to `organization`'s config
+///
+/// # Errors
+///
+/// Will return `Err` if the config cannot be locked, read or written (as in [read-only mode](read_only)),
+/// or the role is not set in it (other than as the organization's default, without `--migrate-defaults`)
+pub fn migrate(organization: &str, renamed: &RoleRenamed) -> Result<()> {
+    read_only::check(&format!("migrate {organization}'s config"))?;
+
+    let path = oktaws_home()?.join(format!("{organization}.toml"));
+    let _lock = FileLock::acquire(&path)?;
+    let contents = fs::read_to_string(&path)?;
+
+    let rename = |defaults| {
+        rename_role(
+            &contents,
+            &renamed.profile,
+            &renamed.role,
+            &renamed.suggestion,
+            defaults,
+        )
+    };
+    let migrated = match rename(migrate_defaults()) {
+        Some(migrated) => migrated,
+        None if rename(true).is_some() => {
+            return Err(eyre!(
+                "{} is the default role for every profile in {}; refresh with --migrate-defaults as well \
+                 to change it to {} there, or set {}'s role by hand",
+                renamed.role,
+                path.display(),
+                renamed.suggestion,
+                renamed.profile
+            ));
+        }
+        None => {
+            return Err(eyre!(
+                "Unable to find where {}'s role {} is set in {}; change it to {} by hand",
+                renamed.profile,
+                renamed.role,
+                path.display(),
+                renamed.suggestion
+            ));
+        }
+    };
+    write_atomically(&path, migrated.as_bytes())?;
+
+    warn!(
+        "Migrated {} from {} to {} in {}",
+        renamed.profile,
+        renamed.role,
+        renamed.suggestion,
+        path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggests_similar_permission_sets() {
+        let available = vec![
+            String::from("AdministratorAccess-v2"),
+            String::from("ReadOnlyAcess"),
+            String::from("Billing"),
+        ];
+
+        assert_eq!(
+            suggest(&[String::from("ReadOnlyAccess")], &available),
+            Some((
+                String::from("ReadOnlyAccess"),
+                String::from("ReadOnlyAcess")
+            ))
+        );
+        assert_eq!(
+            suggest(&[String::from("administrator_access")], &available),
+            Some((
+                String::from("administrator_access"),
+                String::from("AdministratorAccess-v2")
+            ))
+        );
+        assert_eq!(suggest(&[String::from("DataScience")], &available), None);
+    }
+
+    #[test]
+    fn renames_where_the_role_is_set() {
+        let config = r#"username = "mock_user"
+role = "Admin"
+[profiles]
+prod = { application = "AWS SSO", account = "prod", role = 'ReadOnly' } # reviewed
+dev = "dev"
+
+[profiles.data]
+application = "AWS SSO"
+role = "ReadOnly"
+"#;
+
+        assert_eq!(
+            rename_role(config, "prod", "ReadOnly", "ReadOnly-v2", false).as_deref(),
+            Some(
+                r#"username = "mock_user"
+role = "Admin"
+[profiles]
+prod = { application = "AWS SSO", account = "prod", role = 'ReadOnly-v2' } # reviewed
+dev = "dev"
+
+[profiles.data]
+application = "AWS SSO"
+role = "ReadOnly"
+"#
+            )
+        );
+
+        let data = rename_role(config, "data", "ReadOnly", "ReadOnly-v2", false).unwrap();
+        assert!(data.ends_with("role = \"ReadOnly-v2\"\n"));
+        assert!(data.contains("role = 'ReadOnly' }"));
+
+        // The organization's default is for every profile, so is only renamed when asked
+        assert_eq!(
+            rename_role(config, "dev", "Admin", "AdministratorAccess", false),
+            None
+        );
+        let dev = rename_role(config, "dev", "Admin", "AdministratorAccess", true).unwrap();
+        assert!(dev.starts_with("username = \"mock_user\"\nrole = \"AdministratorAccess\"\n"));
+
+        assert_eq!(
+            rename_role(config, "dev", "Billing", "Billing-v2", true),
+            None
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Default roles by account classification.
+//!
+//! An organization's `[[role_rules]]` are tried in order, and the first whose patterns all match an account
+//! gives the role for profiles which don't name one, ahead of the organization's `role`/`roles`.
+//! Account names are known whenever a profile is resolved; account emails only once AWS SSO has been asked for them.
+
+use eyre::{Result, eyre};
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+/// A class of accounts, and the role their profiles use by default
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoleRule {
+    /// Glob matched against the account name (the profile's `account`, or else its name)
+    pub account: Option<String>,
+    /// Glob matched against the account's email, for AWS SSO accounts
+    pub email: Option<String>,
+    pub role: String,
+}
+
+impl RoleRule {
+    /// Check that the rule has something to match on, and that its patterns are valid globs
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the rule has no patterns, or a pattern is not a valid glob
+    pub fn validate(&self) -> Result<()> {
+        if self.account.is_none() && self.email.is_none() {
+            return Err(eyre!(
+                "The role rule for {} needs an `account` or `email` pattern",
+                self.role
+            ));
+        }
+
+        for pattern in self.account.iter().chain(&self.email) {
+            Pattern::new(pattern)
+                .map_err(|e| eyre!("Invalid pattern {pattern} in a role rule ({e})"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the rule matches an account. A rule with an `email` pattern never matches an account whose email is unknown.
+    #[must_use]
+    pub fn matches(&self, account: &str, email: Option<&str>) -> bool {
+        let matches = |pattern: &String, value: &str| {
+            Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(value))
+        };
+
+        self.account
+            .as_ref()
+            .is_none_or(|pattern| matches(pattern, account))
+            && self
+                .email
+                .as_ref()
+                .is_none_or(|pattern| email.is_some_and(|email| matches(pattern, email)))
+    }
+}
+
+/// The first of `rules` to match an account
+#[must_use]
+pub fn first_match<'a>(
+    rules: &'a [RoleRule],
+    account: &str,
+    email: Option<&str>,
+) -> Option<&'a RoleRule> {
+    rules.iter().find(|rule| rule.matches(account, email))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(account: Option<&str>, email: Option<&str>, role: &str) -> RoleRule {
+        RoleRule {
+            account: account.map(ToString::to_string),
+            email: email.map(ToString::to_string),
+            role: role.to_string(),
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = vec![
+            rule(None, Some("*@sandbox.example.com"), "Admin"),
+            rule(Some("*-prod-*"), None, "ReadOnly"),
+            rule(Some("*"), None, "Developer"),
+        ];
+
+        let role = |account, email| first_match(&rules, account, email).map(|rule| &rule.role);
+
+        assert_eq!(role("payments-prod-eu", None).unwrap(), "ReadOnly");
+        assert_eq!(
+            role("jane-dev", Some("jane@sandbox.example.com")).unwrap(),
+            "Admin"
+        );
+        assert_eq!(role("jane-dev", None).unwrap(), "Developer");
+        assert!(first_match(&rules[..1], "jane-dev", None).is_none());
+    }
+
+    #[test]
+    fn rules_need_a_pattern() {
+        assert!(rule(None, None, "Admin").validate().is_err());
+        assert!(rule(Some("[prod"), None, "Admin").validate().is_err());
+        assert!(rule(Some("*-prod-*"), None, "Admin").validate().is_ok());
+    }
+}
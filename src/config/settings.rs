@@ -0,0 +1,125 @@
+use crate::config::error::from_toml;
+use crate::config::oktaws_home;
+use crate::config::organization::Pattern as OrganizationPattern;
+use crate::secrets::Store as SecretStore;
+use crate::sink::WriteLimits;
+use crate::tls::Network;
+
+use std::fs::read_to_string;
+use std::path::Path;
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// The user-level settings file, in the oktaws home directory.
+/// It is not an organization, so it is skipped when looking for organization configs.
+pub const FILE_NAME: &str = "settings.toml";
+
+/// User-level defaults, used when a command is not told which organization or profile to use.
+/// Flags given on the command line always take precedence over these.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Organizations to use by default (a name or glob)
+    pub organization: Option<String>,
+    /// Profiles to use by default (a name or glob)
+    pub profile: Option<String>,
+    /// Limits on writing to sinks, e.g. for home directories on network filesystems
+    #[serde(default)]
+    pub writes: WriteLimits,
+    /// Where to keep the Okta password, sessions and tokens (see `--secret-store`)
+    #[serde(default)]
+    pub secret_store: SecretStore,
+    /// How to reach Okta and AWS: a proxy, and the CAs to trust
+    #[serde(default)]
+    pub network: Network,
+}
+
+impl Settings {
+    /// Load the settings from the oktaws home directory, if there are any
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the oktaws home cannot be found,
+    /// or if the settings file exists but cannot be read or parsed
+    pub fn load() -> Result<Self> {
+        Self::load_from(&oktaws_home()?.join(FILE_NAME))
+    }
+
+    fn load_from(path: &Path) -> Result<Self> {
+        if path.exists() {
+            Ok(from_toml(path, &read_to_string(path)?)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// The organizations to use: the `--organizations` flag if given, then the default organization, then all of them
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the default organization is not a valid pattern
+    pub fn organizations(&self, flag: Option<&OrganizationPattern>) -> Result<OrganizationPattern> {
+        match (flag, &self.organization) {
+            (Some(flag), _) => Ok(flag.clone()),
+            (None, Some(organization)) => {
+                debug!("Using default organization {organization} from {FILE_NAME}");
+                organization.parse()
+            }
+            (None, None) => "*".parse(),
+        }
+    }
+
+    /// The profiles to use: the profile argument if given, then the default profile, then all of them
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the default profile is not a valid pattern
+    pub fn profiles(&self, argument: Option<&glob::Pattern>) -> Result<glob::Pattern> {
+        match (argument, &self.profile) {
+            (Some(argument), _) => Ok(argument.clone()),
+            (None, Some(profile)) => {
+                debug!("Using default profile {profile} from {FILE_NAME}");
+                Ok(glob::Pattern::new(profile)?)
+            }
+            (None, None) => Ok(glob::Pattern::new("*")?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn flags_take_precedence() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join(FILE_NAME);
+
+        assert_eq!(Settings::load_from(&path)?, Settings::default());
+        assert_eq!(Settings::default().profiles(None)?.as_str(), "*");
+
+        fs::write(
+            &path,
+            "organization = \"client-a\"\nprofile = \"prod\"\nsecret_store = \"encrypted-file\"\n",
+        )?;
+        let settings = Settings::load_from(&path)?;
+        assert_eq!(settings.secret_store, SecretStore::EncryptedFile);
+
+        assert_eq!(settings.profiles(None)?.as_str(), "prod");
+        assert_eq!(settings.writes, WriteLimits::default());
+        assert_eq!(
+            settings
+                .profiles(Some(&glob::Pattern::new("dev")?))?
+                .as_str(),
+            "dev"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+//! Organization configs maintained by a team, installed from a URL with `oktaws init --from-url`.
+//!
+//! Where each one came from is recorded in the state directory, so that `oktaws config update` can fetch it again.
+//! A download can be pinned to its SHA-256 digest, or checked against an Ed25519 signature published next to it
+//! (at `<url>.sig`, in base64). A team's config cannot know each engineer's `username`, so the local one is kept.
+
+use crate::config::error::from_toml;
+use crate::config::organization::Config;
+use crate::retry;
+use crate::tls;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::engine::{Engine, general_purpose::STANDARD as b64};
+use eyre::{Result, eyre};
+use ring::digest::{SHA256, digest};
+use ring::signature::{ED25519, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// The record of installed shared configs, in the oktaws state directory
+pub const FILE_NAME: &str = "shared.json";
+
+/// Where a shared organization config is installed from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub url: Url,
+    /// The Ed25519 public key (in base64) which must have signed the config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+}
+
+impl Source {
+    /// Where the config's signature is published
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the URL has no path to add `.sig` to
+    pub fn signature_url(&self) -> Result<Url> {
+        let mut url = self.url.clone();
+        let path = format!("{}.sig", url.path());
+        if path == "/.sig" {
+            return Err(eyre!("{} does not name a file", self.url));
+        }
+        url.set_path(&path);
+
+        Ok(url)
+    }
+
+    /// Download the config, checking its signature if there is a public key,
+    /// and its digest against `sha256` (in hex) if given
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the config or its signature cannot be downloaded, or either check fails
+    pub async fn fetch(&self, sha256: Option<&str>) -> Result<String> {
+        let contents = download(&self.url).await?;
+
+        if let Some(expected) = sha256 {
+            verify_sha256(contents.as_bytes(), expected)?;
+        }
+        if let Some(public_key) = &self.public_key {
+            let signature = download(&self.signature_url()?).await?;
+            verify_signature(contents.as_bytes(), public_key, &signature)?;
+        }
+
+        Ok(contents)
+    }
+}
+
+async fn download(url: &Url) -> Result<String> {
+    let client = tls::client_builder().build()?;
+    let response = retry::send(client.get(url.clone())).await?;
+
+    Ok(response.error_for_status()?.text().await?)
+}
+
+/// The organization a config at `url` is for, from its file name (e.g. `mycompany` for `.../mycompany.toml`)
+#[must_use]
+pub fn organization_name(url: &Url) -> Option<String> {
+    url.path_segments()?
+        .next_back()
+        .map(|file| file.strip_suffix(".toml").unwrap_or(file))
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string)
+}
+
+/// Check that `contents` has the SHA-256 digest `expected` (in hex)
+///
+/// # Errors
+///
+/// Will return `Err` if the digests differ
+pub fn verify_sha256(contents: &[u8], expected: &str) -> Result<()> {
+    let actual = digest(&SHA256, contents)
+        .as_ref()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        });
+
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "The downloaded config's SHA-256 digest is {actual}, not the pinned {}",
+            expected.trim()
+        ))
+    }
+}
+
+/// Check that `signature` (in base64) is `public_key`'s (in base64) Ed25519 signature of `contents`
+///
+/// # Errors
+///
+/// Will return `Err` if the key or signature cannot be decoded, or the signature does not match
+pub fn verify_signature(contents: &[u8], public_key: &str, signature: &str) -> Result<()> {
+    let public_key = b64
+        .decode(public_key.trim())
+        .map_err(|e| eyre!("Unable to decode the public key ({e})"))?;
+    let signature = b64
+        .decode(signature.trim())
+        .map_err(|e| eyre!("Unable to decode the config's signature ({e})"))?;
+
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(contents, &signature)
+        .map_err(|_| eyre!("The config's signature does not match the public key"))
+}
+
+/// Check that `contents` is a valid organization config, naming `path` in any error
+///
+/// # Errors
+///
+/// Will return `Err` if the config cannot be parsed
+pub fn validate(path: &Path, contents: &str) -> Result<()> {
+    from_toml::<Config>(path, contents)?;
+
+    Ok(())
+}
+
+/// `contents` with `username` set, unless the config already sets one
+///
+/// # Errors
+///
+/// Will return `Err` if the config cannot be parsed as TOML
+pub fn with_username(contents: &str, username: &str) -> Result<String> {
+    let table = contents.parse::<toml::Table>()?;
+
+    if table.contains_key("username") {
+        Ok(contents.to_string())
+    } else {
+        Ok(format!(
+            "username = {}\n{contents}",
+            toml::Value::String(username.to_string())
+        ))
+    }
+}
+
+/// The username set in a config, if it sets one
+#[must_use]
+pub fn username(contents: &str) -> Option<String> {
+    contents
+        .parse::<toml::Table>()
+        .ok()?
+        .get("username")?
+        .as_str()
+        .map(ToString::to_string)
+}
+
+/// Where each shared config was installed from, by organization
+#[derive(Debug, Default)]
+pub struct Sources {
+    path: PathBuf,
+    sources: BTreeMap<String, Source>,
+}
+
+impl Sources {
+    /// Load the record from `path`, which need not exist yet
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file exists but cannot be read or parsed
+    pub fn load(path: &Path) -> Result<Self> {
+        let sources = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(path)?)?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            sources,
+        })
+    }
+
+    /// Every organization installed from a URL, with its source
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Source)> {
+        self.sources.iter()
+    }
+
+    /// Record that `organization`'s config was installed from `source`
+    pub fn record(&mut self, organization: &str, source: Source) {
+        self.sources.insert(organization.to_string(), source);
+    }
+
+    /// Write the record back
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file cannot be written
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.sources)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    #[test]
+    fn verifies_pins_and_signatures() -> Result<()> {
+        let contents = b"[profiles]\nprod = \"production\"\n";
+
+        let pin = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert!(verify_sha256(b"", pin).is_ok());
+        assert!(verify_sha256(contents, pin).is_err());
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| eyre!("Unable to generate a key"))?;
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+            .map_err(|_| eyre!("Unable to parse the key"))?;
+        let public_key = b64.encode(key_pair.public_key());
+        let signature = b64.encode(key_pair.sign(contents));
+
+        assert!(verify_signature(contents, &public_key, &signature).is_ok());
+        assert!(verify_signature(b"[profiles]\n", &public_key, &signature).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_the_local_username() -> Result<()> {
+        let shared = "[profiles]\nprod = \"production\"\n";
+
+        let installed = with_username(shared, "jdoe")?;
+        assert_eq!(
+            installed,
+            "username = \"jdoe\"\n[profiles]\nprod = \"production\"\n"
+        );
+        assert_eq!(username(&installed).as_deref(), Some("jdoe"));
+        assert_eq!(with_username(&installed, "someone-else")?, installed);
+
+        assert_eq!(
+            organization_name(&Url::parse("https://intranet/oktaws/mycompany.toml")?).as_deref(),
+            Some("mycompany")
+        );
+        assert_eq!(organization_name(&Url::parse("https://intranet/")?), None);
+
+        Ok(())
+    }
+}
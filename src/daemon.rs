@@ -0,0 +1,274 @@
+//! Keeping profiles' credentials fresh in the background, for `oktaws daemon`.
+//!
+//! The daemon signs in to each organization once, keeps the Okta session alive, and refreshes each profile
+//! shortly before its credentials expire (see [`crate::schedule`]), so long-running tools never see expired credentials.
+//! The [`Tracker`] holds when each profile is next due; the control socket (see [`crate::ipc`]) can read it,
+//! and ask for refreshes or sign-outs.
+
+#[cfg(unix)]
+use crate::events;
+use crate::exec::RETRY_INTERVAL;
+#[cfg(unix)]
+use crate::ipc::{Handler, ProfileStatus, Request, Response};
+use crate::schedule::Schedule;
+
+use std::collections::BTreeMap;
+#[cfg(unix)]
+use std::future::{Future, ready};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use tokio::sync::mpsc::UnboundedSender;
+
+/// How often the Okta sessions are extended, so that they last between refreshes
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_mins(10);
+
+/// Something the refresh loop is asked to do, from outside it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Refresh whatever is now due
+    Refresh,
+    /// End the Okta session for an organization (or every organization), signing in again when next needed
+    Logout { organization: Option<String> },
+}
+
+/// SIGTERM, which service managers stop the daemon with (never received, where there is no such signal)
+pub struct Terminate(#[cfg(unix)] tokio::signal::unix::Signal);
+
+impl Terminate {
+    /// Start listening for SIGTERM
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the signal handler cannot be installed
+    #[cfg(unix)]
+    pub fn new() -> std::io::Result<Self> {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).map(Self)
+    }
+
+    /// Start listening for SIGTERM
+    ///
+    /// # Errors
+    ///
+    /// Never returns `Err`, as there is no SIGTERM to listen for
+    #[cfg(not(unix))]
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self())
+    }
+
+    /// Wait for SIGTERM
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        self.0.recv().await;
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    organization: String,
+    /// `None` once the profile has credentials which never expire
+    refresh_at: Option<SystemTime>,
+    expires_at: Option<SystemTime>,
+}
+
+/// When each of the daemon's profiles is next due for a refresh
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tracker {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Tracker {
+    /// Track `profiles` (each with its organization), all due straight away
+    pub fn new(profiles: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            entries: profiles
+                .into_iter()
+                .map(|(organization, profile)| {
+                    (
+                        profile,
+                        Entry {
+                            organization,
+                            refresh_at: Some(UNIX_EPOCH),
+                            expires_at: None,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[must_use]
+    pub fn contains(&self, profile: &str) -> bool {
+        self.entries.contains_key(profile)
+    }
+
+    /// The profiles of `organization` which are due for a refresh at `now`
+    #[must_use]
+    pub fn due(&self, organization: &str, now: SystemTime) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.organization == organization
+                    && entry.refresh_at.is_some_and(|refresh_at| refresh_at <= now)
+            })
+            .map(|(profile, _)| profile.clone())
+            .collect()
+    }
+
+    /// When the next refresh is due, if ever
+    #[must_use]
+    pub fn next(&self) -> Option<SystemTime> {
+        self.entries
+            .values()
+            .filter_map(|entry| entry.refresh_at)
+            .min()
+    }
+
+    /// Reschedule `profile` after a successful refresh
+    pub fn refreshed(&mut self, profile: &str, schedule: Option<&Schedule>) {
+        if let Some(entry) = self.entries.get_mut(profile) {
+            entry.refresh_at = schedule.map(|schedule| schedule.refresh_at);
+            entry.expires_at = schedule.map(|schedule| schedule.expires_at);
+        }
+    }
+
+    /// Try `profile` again after [`RETRY_INTERVAL`], keeping its current credentials' expiry
+    pub fn failed(&mut self, profile: &str, now: SystemTime) {
+        if let Some(entry) = self.entries.get_mut(profile) {
+            entry.refresh_at = Some(now + RETRY_INTERVAL);
+        }
+    }
+
+    /// Make `profile` (or every profile) due straight away
+    pub fn force(&mut self, profile: Option<&str>) {
+        for (name, entry) in &mut self.entries {
+            if profile.is_none_or(|profile| profile == name) {
+                entry.refresh_at = Some(UNIX_EPOCH);
+            }
+        }
+    }
+
+    /// Each profile's expiry and next refresh, for the control socket
+    #[cfg(unix)]
+    #[must_use]
+    pub fn status(&self) -> Vec<ProfileStatus> {
+        self.entries
+            .iter()
+            .map(|(profile, entry)| ProfileStatus {
+                profile: profile.clone(),
+                expires_at: entry.expires_at.and_then(events::timestamp),
+                refresh_at: entry
+                    .refresh_at
+                    .filter(|refresh_at| *refresh_at != UNIX_EPOCH)
+                    .and_then(events::timestamp),
+            })
+            .collect()
+    }
+}
+
+/// A tracker shared between the daemon's refresh loop and its control socket
+#[derive(Clone, Debug, Default)]
+pub struct Shared(Arc<Mutex<Tracker>>);
+
+impl Shared {
+    #[must_use]
+    pub fn new(tracker: Tracker) -> Self {
+        Self(Arc::new(Mutex::new(tracker)))
+    }
+
+    /// Run `f` with the tracker. A panic elsewhere while holding it leaves it usable,
+    /// as every change to it is a single assignment.
+    pub fn with<T>(&self, f: impl FnOnce(&mut Tracker) -> T) -> T {
+        f(&mut self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// Answers the control socket for a running daemon.
+/// Refreshes and sign-outs are passed on to the refresh loop, which carries them out.
+#[cfg(unix)]
+pub struct Control {
+    tracker: Shared,
+    commands: UnboundedSender<Command>,
+}
+
+#[cfg(unix)]
+impl Control {
+    #[must_use]
+    pub const fn new(tracker: Shared, commands: UnboundedSender<Command>) -> Self {
+        Self { tracker, commands }
+    }
+
+    fn forward(&self, command: Command) -> Response {
+        match self.commands.send(command) {
+            Ok(()) => Response::ok(),
+            Err(_) => Response::error("The daemon is stopping"),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Handler for Control {
+    fn handle(&self, request: Request) -> impl Future<Output = Response> + Send {
+        let response = match request {
+            Request::Status => Response {
+                profiles: self.tracker.with(|tracker| tracker.status()),
+                ..Response::ok()
+            },
+            Request::RefreshNow {
+                profile: Some(profile),
+            } if !self.tracker.with(|tracker| tracker.contains(&profile)) => {
+                Response::error(format!("The daemon is not refreshing {profile}"))
+            }
+            Request::RefreshNow { profile } => {
+                self.tracker
+                    .with(|tracker| tracker.force(profile.as_deref()));
+                self.forward(Command::Refresh)
+            }
+            Request::Logout { organization } => self.forward(Command::Logout { organization }),
+        };
+
+        ready(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reschedules_profiles() {
+        let now = SystemTime::now();
+        let mut tracker = Tracker::new([
+            (String::from("mock_org"), String::from("prod")),
+            (String::from("mock_org"), String::from("dev")),
+            (String::from("other_org"), String::from("sandbox")),
+        ]);
+
+        assert_eq!(tracker.due("mock_org", now), vec!["dev", "prod"]);
+
+        let schedule = Schedule {
+            profile: String::from("prod"),
+            expires_at: now + Duration::from_hours(1),
+            refresh_at: now + Duration::from_mins(48),
+        };
+        tracker.refreshed("prod", Some(&schedule));
+        tracker.failed("dev", now);
+
+        assert!(tracker.due("mock_org", now).is_empty());
+        assert_eq!(tracker.due("other_org", now), vec!["sandbox"]);
+
+        tracker.refreshed("sandbox", None);
+        assert_eq!(tracker.next(), Some(now + RETRY_INTERVAL));
+
+        tracker.force(Some("prod"));
+        assert_eq!(tracker.due("mock_org", now), vec!["prod"]);
+    }
+}
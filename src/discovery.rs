@@ -0,0 +1,267 @@
+//! Noticing accounts and roles granted since the last discovery, and remembering what was discovered.
+//!
+//! Each discovery is compared with a snapshot of the previous one for the organization,
+//! so that newly granted access is announced (which also confirms that an access request went through).
+//! The accounts discovered are also kept in a catalog, with their IDs, emails and descriptions, for `oktaws search`.
+
+use crate::events::{self, Event};
+use crate::okta::applications::{AppLinkAccountRoleMapping, IntegrationType};
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// The directory (in the oktaws state directory) snapshots are kept in
+pub const DIR: &str = "discovery";
+
+/// The directory (in the oktaws cache directory) catalogs are kept in
+pub const CATALOG_DIR: &str = "catalog";
+
+/// A role in an account, as discovered through Okta
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Grant {
+    pub account: String,
+    pub role: String,
+}
+
+/// The grants found by the last discovery for an organization
+#[derive(Debug)]
+pub struct Snapshot {
+    path: PathBuf,
+    /// `None` if the organization has never been discovered
+    grants: Option<BTreeSet<Grant>>,
+}
+
+impl Snapshot {
+    /// The snapshot for `organization`, kept in `dir`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot exists but cannot be read or parsed
+    pub fn load(dir: &Path, organization: &str) -> Result<Self> {
+        let path = dir.join(format!("{organization}.json"));
+
+        let grants = if path.exists() {
+            Some(serde_json::from_str(&fs::read_to_string(&path)?)?)
+        } else {
+            None
+        };
+
+        Ok(Self { path, grants })
+    }
+
+    /// The grants in `mappings` which were not in the snapshot.
+    /// The first discovery has nothing to compare with, so finds nothing new.
+    #[must_use]
+    pub fn new_grants(&self, mappings: &[AppLinkAccountRoleMapping]) -> Vec<Grant> {
+        let Some(previous) = &self.grants else {
+            return Vec::new();
+        };
+
+        grants(mappings)
+            .into_iter()
+            .filter(|grant| !previous.contains(grant))
+            .collect()
+    }
+
+    /// Replace the snapshot with the grants in `mappings`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot cannot be written
+    pub fn save(&self, mappings: &[AppLinkAccountRoleMapping]) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&self.path, serde_json::to_string_pretty(&grants(mappings))?).map_err(Into::into)
+    }
+}
+
+/// An account, as discovered through Okta
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CatalogEntry {
+    pub account: String,
+    pub account_id: Option<String>,
+    pub account_email: Option<String>,
+    pub description: Option<String>,
+    /// The Okta application the account was discovered through
+    pub application: String,
+    pub federated: bool,
+    pub roles: Vec<String>,
+}
+
+impl From<&AppLinkAccountRoleMapping> for CatalogEntry {
+    fn from(mapping: &AppLinkAccountRoleMapping) -> Self {
+        Self {
+            account: mapping.account_name.clone(),
+            account_id: mapping.account_id.clone(),
+            account_email: mapping.account_email.clone(),
+            description: mapping.account_description.clone(),
+            application: mapping.application_name.clone(),
+            federated: mapping.integration_type == IntegrationType::Federated,
+            roles: mapping.role_names.clone(),
+        }
+    }
+}
+
+/// The account as discovery found it, for what was discovered to be used without discovering again
+impl From<&CatalogEntry> for AppLinkAccountRoleMapping {
+    fn from(entry: &CatalogEntry) -> Self {
+        Self {
+            account_name: entry.account.clone(),
+            account_id: entry.account_id.clone(),
+            account_email: entry.account_email.clone(),
+            account_description: entry.description.clone(),
+            role_names: entry.roles.clone(),
+            application_name: entry.application.clone(),
+            integration_type: if entry.federated {
+                IntegrationType::Federated
+            } else {
+                IntegrationType::IdentityCenter
+            },
+        }
+    }
+}
+
+/// The accounts found by the last discovery of an organization, kept in `dir`
+///
+/// # Errors
+///
+/// Will return `Err` if the catalog exists but cannot be read or parsed
+pub fn load_catalog(dir: &Path, organization: &str) -> Result<Option<Vec<CatalogEntry>>> {
+    let path = dir.join(format!("{organization}.json"));
+
+    if path.exists() {
+        Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Replace the organization's catalog with the accounts in `mappings`
+///
+/// # Errors
+///
+/// Will return `Err` if the catalog cannot be written
+pub fn save_catalog(
+    dir: &Path,
+    organization: &str,
+    mappings: &[AppLinkAccountRoleMapping],
+) -> Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let catalog = mappings.iter().map(CatalogEntry::from).collect::<Vec<_>>();
+    fs::write(
+        dir.join(format!("{organization}.json")),
+        serde_json::to_string_pretty(&catalog)?,
+    )
+    .map_err(Into::into)
+}
+
+fn grants(mappings: &[AppLinkAccountRoleMapping]) -> BTreeSet<Grant> {
+    mappings
+        .iter()
+        .flat_map(|mapping| {
+            mapping.role_names.iter().map(|role| Grant {
+                account: mapping.account_name.clone(),
+                role: role.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Compare `mappings` with the organization's last discovery, announcing anything new
+/// (as a warning, which is shown without `-v`, and an `access_granted` event), and remember them for next time
+///
+/// # Errors
+///
+/// Will return `Err` if the snapshot cannot be read or written
+pub fn announce(
+    dir: &Path,
+    organization: &str,
+    mappings: &[AppLinkAccountRoleMapping],
+) -> Result<Vec<Grant>> {
+    let snapshot = Snapshot::load(dir, organization)?;
+    let new_grants = snapshot.new_grants(mappings);
+
+    for grant in &new_grants {
+        warn!("You now have {} in {}", grant.role, grant.account);
+        events::emit(&Event::AccessGranted {
+            organization: organization.to_string(),
+            account: grant.account.clone(),
+            role: grant.role.clone(),
+        });
+    }
+
+    snapshot.save(mappings)?;
+
+    Ok(new_grants)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    fn mapping(account: &str, roles: &[&str]) -> AppLinkAccountRoleMapping {
+        AppLinkAccountRoleMapping {
+            account_name: account.to_string(),
+            account_id: None,
+            account_email: None,
+            account_description: None,
+            role_names: roles.iter().map(ToString::to_string).collect(),
+            application_name: String::from("aws"),
+            integration_type: IntegrationType::Federated,
+        }
+    }
+
+    #[test]
+    fn finds_new_grants() -> Result<()> {
+        let dir = tempdir()?;
+
+        let first = vec![mapping("data-lake-dev", &["Admin"])];
+        assert_eq!(announce(dir.path(), "mock_org", &first)?, Vec::new());
+
+        let second = vec![
+            mapping("data-lake-dev", &["Admin"]),
+            mapping("data-lake-prod", &["ReadOnly"]),
+        ];
+        assert_eq!(
+            announce(dir.path(), "mock_org", &second)?,
+            vec![Grant {
+                account: String::from("data-lake-prod"),
+                role: String::from("ReadOnly"),
+            }]
+        );
+
+        assert_eq!(announce(dir.path(), "mock_org", &second)?, Vec::new());
+
+        Ok(())
+    }
+
+    #[test]
+    fn catalog_keeps_what_was_discovered() -> Result<()> {
+        let dir = tempdir()?;
+        let mappings = vec![mapping("data-lake-dev", &["Admin", "ReadOnly"])];
+
+        save_catalog(dir.path(), "mock_org", &mappings)?;
+        let catalog = load_catalog(dir.path(), "mock_org")?.unwrap_or_default();
+
+        assert_eq!(
+            catalog
+                .iter()
+                .map(AppLinkAccountRoleMapping::from)
+                .collect::<Vec<_>>(),
+            mappings
+        );
+        assert_eq!(load_catalog(dir.path(), "other_org")?, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,337 @@
+//! Time-boxed elevated access, for `oktaws elevate`: credentials for a privileged profile which last only as long as
+//! they are needed, with why they were needed kept in a local audit log.
+//!
+//! Each elevation's window is kept in the state directory. The credentials are requested to last exactly as long as
+//! the window (so profiles whose sessions can't be given a duration, through Identity Center or a chained role,
+//! can't be elevated), and refreshes leave the section alone while the window is open.
+//! Once it has closed, the next `oktaws refresh`, `oktaws elevate` or daemon tick removes the section (and logs that too),
+//! unless something else has written other credentials to it since.
+
+use crate::aws::profile::{Layout, Store as ProfileStore};
+use crate::events::timestamp;
+use crate::lock::FileLock;
+use crate::request_id::RequestId;
+use crate::transaction::write_atomically;
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// The open windows, in the oktaws state directory
+pub const FILE_NAME: &str = "elevations.json";
+
+/// The audit log, as JSON lines, in the oktaws state directory
+pub const AUDIT_LOG: &str = "audit.log";
+
+/// An elevated profile, and when its window closes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    pub organization: String,
+    pub profile: String,
+    /// The credentials section the profile's credentials were written to
+    pub section: String,
+    /// The organization's own credentials file, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials_file: Option<PathBuf>,
+    #[serde(default)]
+    pub layout: Layout,
+    /// Seconds since the epoch when the window closes
+    pub until: u64,
+    /// The access key written for the elevation, so that other credentials written to the section since are kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+}
+
+impl Window {
+    /// When the window closes
+    #[must_use]
+    pub fn closes_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.until)
+    }
+
+    /// Whether `other` is for the same section of the same file
+    fn overlaps(&self, other: &Self) -> bool {
+        self.credentials_file == other.credentials_file
+            && self.layout == other.layout
+            && self.section == other.section
+    }
+}
+
+/// What happened to an elevated profile
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Elevated,
+    Expired,
+}
+
+/// A line of the audit log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub at: Option<String>,
+    pub action: Action,
+    pub organization: String,
+    pub profile: String,
+    /// Why the access was needed, as the user gave it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// When the window closes (or closed)
+    pub until: Option<String>,
+    /// The Okta and AWS requests made for the elevation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requests: Vec<RequestId>,
+}
+
+impl Entry {
+    fn new(
+        action: Action,
+        window: &Window,
+        reason: Option<&str>,
+        requests: Vec<RequestId>,
+    ) -> Self {
+        Self {
+            at: timestamp(SystemTime::now()),
+            action,
+            organization: window.organization.clone(),
+            profile: window.profile.clone(),
+            reason: reason.map(ToString::to_string),
+            until: timestamp(window.closes_at()),
+            requests,
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<Window>> {
+    if path.exists() {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// Append `entry` to the audit log in `state_dir`
+fn audit(state_dir: &Path, entry: &Entry) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+
+    // One write, so that lines from runs at the same time are never interleaved
+    options
+        .open(state_dir.join(AUDIT_LOG))?
+        .write_all(line.as_bytes())
+        .map_err(Into::into)
+}
+
+/// The sections of `credentials_file` (in `layout`) whose windows are still open at `now`, for refreshes to leave alone
+///
+/// # Errors
+///
+/// Will return `Err` if the windows cannot be read
+pub fn elevated(
+    state_dir: &Path,
+    credentials_file: Option<&Path>,
+    layout: Layout,
+    now: SystemTime,
+) -> Result<HashSet<String>> {
+    Ok(read(&state_dir.join(FILE_NAME))?
+        .into_iter()
+        .filter(|window| {
+            window.credentials_file.as_deref() == credentials_file
+                && window.layout == layout
+                && window.closes_at() > now
+        })
+        .map(|window| window.section)
+        .collect())
+}
+
+/// Record that `window` is open because of `reason`, with the `requests` which opened it,
+/// replacing any earlier window for the same section
+///
+/// # Errors
+///
+/// Will return `Err` if the lock cannot be acquired, or if the windows or the audit log cannot be read or written
+pub fn open(
+    state_dir: &Path,
+    window: &Window,
+    reason: &str,
+    requests: Vec<RequestId>,
+) -> Result<()> {
+    fs::create_dir_all(state_dir)?;
+    let path = state_dir.join(FILE_NAME);
+
+    {
+        let _lock = FileLock::acquire(&path)?;
+
+        let mut windows = read(&path)?;
+        windows.retain(|open| !open.overlaps(window));
+        windows.push(window.clone());
+
+        write_atomically(&path, serde_json::to_string_pretty(&windows)?.as_bytes())?;
+    }
+
+    audit(
+        state_dir,
+        &Entry::new(Action::Elevated, window, Some(reason), requests),
+    )
+}
+
+/// Remove the sections of elevated profiles whose windows have closed by `now`, returning those windows
+///
+/// # Errors
+///
+/// Will return `Err` if a lock cannot be acquired, or if the windows, credentials files or audit log
+/// cannot be read or written
+pub fn close_expired(state_dir: &Path, now: SystemTime) -> Result<Vec<Window>> {
+    let path = state_dir.join(FILE_NAME);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let _lock = FileLock::acquire(&path)?;
+
+    let (closed, open): (Vec<_>, Vec<_>) = read(&path)?
+        .into_iter()
+        .partition(|window| window.closes_at() <= now);
+    if closed.is_empty() {
+        return Ok(closed);
+    }
+
+    let mut by_file: BTreeMap<(Option<PathBuf>, Layout), Vec<&Window>> = BTreeMap::new();
+    for window in &closed {
+        by_file
+            .entry((window.credentials_file.clone(), window.layout))
+            .or_default()
+            .push(window);
+    }
+    for ((credentials_file, layout), windows) in by_file {
+        let mut store = ProfileStore::load_with(credentials_file.as_deref(), layout)?;
+        for window in windows {
+            // Credentials written there since (by hand, or another tool) aren't the elevation's to remove
+            if window
+                .access_key_id
+                .as_deref()
+                .is_some_and(|access_key_id| {
+                    store.access_key_id(&window.section) != Some(access_key_id)
+                })
+            {
+                info!(
+                    "Leaving {} as it is, as it has been rewritten since it was elevated",
+                    window.section
+                );
+                continue;
+            }
+
+            // Long-lived credentials written there since were not oktaws', so are left alone
+            if let Err(e) = store.remove_credential(&window.section) {
+                warn!("Leaving {} as it is: {e}", window.section);
+            }
+        }
+        store.save()?;
+    }
+
+    write_atomically(&path, serde_json::to_string_pretty(&open)?.as_bytes())?;
+
+    for window in &closed {
+        info!("Elevated access to {} has ended", window.profile);
+        audit(
+            state_dir,
+            &Entry::new(Action::Expired, window, None, Vec::new()),
+        )?;
+    }
+
+    Ok(closed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use aws_credential_types::Credentials;
+    use tempfile::tempdir;
+
+    #[test]
+    fn removes_sections_once_their_windows_close() -> Result<()> {
+        let dir = tempdir()?;
+        let credentials_file = dir.path().join("credentials");
+
+        let mut store = ProfileStore::load(Some(&credentials_file))?;
+        for section in ["prod-admin", "prod", "dev-admin"] {
+            store.upsert_credential(
+                section,
+                &Credentials::new("ASIA", "secret", Some(String::from("token")), None, "test"),
+            )?;
+        }
+        store.save()?;
+
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let window = Window {
+            organization: String::from("mycompany"),
+            profile: String::from("prod-admin"),
+            section: String::from("prod-admin"),
+            credentials_file: Some(credentials_file.clone()),
+            layout: Layout::Credentials,
+            until: 1_700_001_800,
+            access_key_id: Some(String::from("ASIA")),
+        };
+        open(dir.path(), &window, "INC-1234", Vec::new())?;
+
+        // Rewritten with other credentials since it was elevated
+        let rewritten = Window {
+            profile: String::from("dev-admin"),
+            section: String::from("dev-admin"),
+            access_key_id: Some(String::from("ASIAOLD")),
+            ..window.clone()
+        };
+        open(dir.path(), &rewritten, "INC-1235", Vec::new())?;
+
+        assert_eq!(
+            elevated(
+                dir.path(),
+                Some(&credentials_file),
+                Layout::Credentials,
+                now
+            )?,
+            HashSet::from([String::from("prod-admin"), String::from("dev-admin")])
+        );
+        assert!(close_expired(dir.path(), now)?.is_empty());
+        assert_eq!(
+            close_expired(dir.path(), window.closes_at())?,
+            vec![window, rewritten]
+        );
+
+        let store = ProfileStore::load(Some(&credentials_file))?;
+        assert_eq!(store.sessions(), vec!["prod", "dev-admin"]);
+        assert!(read(&dir.path().join(FILE_NAME))?.is_empty());
+
+        let entries = fs::read_to_string(dir.path().join(AUDIT_LOG))?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<Vec<Entry>>>()?;
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.action, entry.reason.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Action::Elevated, Some("INC-1234")),
+                (Action::Elevated, Some("INC-1235")),
+                (Action::Expired, None),
+                (Action::Expired, None)
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! An optional stream of lifecycle events, as JSON lines, for GUI wrappers and tray applets.
+
+use std::fs::OpenOptions;
+use std::io::{Write, stdout};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
+use eyre::{Result, eyre};
+use serde::Serialize;
+use tracing::debug;
+
+/// Something which happened during a run
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    AuthStarted {
+        organization: String,
+        username: String,
+    },
+    MfaRequired {
+        organization: String,
+        factor: String,
+    },
+    ProfileRefreshed {
+        organization: String,
+        partition: String,
+        profile: String,
+        expires_at: Option<String>,
+    },
+    RefreshFailed {
+        organization: String,
+        partition: String,
+        profile: String,
+        error: String,
+    },
+    CredentialsCoalesced {
+        organization: String,
+        profile: String,
+    },
+    AccessGranted {
+        organization: String,
+        account: String,
+        role: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+static SINK: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+
+/// Send events to `target` for the rest of the process.
+/// `-` means stdout; anything else is a path, which is appended to (so FIFOs work too).
+///
+/// # Errors
+///
+/// Will return `Err` if the file cannot be opened, or if events are already being sent elsewhere
+pub fn install(target: &Path) -> Result<()> {
+    let sink: Box<dyn Write + Send> = if target == Path::new("-") {
+        Box::new(stdout())
+    } else {
+        Box::new(OpenOptions::new().create(true).append(true).open(target)?)
+    };
+
+    SINK.set(Mutex::new(sink))
+        .map_err(|_| eyre!("Events are already being sent elsewhere"))
+}
+
+/// Format a timestamp the same way in every event
+#[must_use]
+pub fn timestamp(time: SystemTime) -> Option<String> {
+    DateTime::from(time).fmt(Format::DateTime).ok()
+}
+
+/// Emit an event, if events were requested.
+/// Failing to write an event never fails the run.
+pub fn emit(event: &Event) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+
+    let record = Record {
+        time: timestamp(SystemTime::now()).unwrap_or_default(),
+        event,
+    };
+
+    let result = serde_json::to_string(&record)
+        .map_err(eyre::Error::from)
+        .and_then(|line| {
+            let mut sink = sink.lock().map_err(|_| eyre!("Event sink poisoned"))?;
+            writeln!(sink, "{line}")?;
+            sink.flush().map_err(Into::into)
+        });
+
+    if let Err(e) = result {
+        debug!("Unable to emit event: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_event() {
+        let event = Event::ProfileRefreshed {
+            organization: String::from("mock_org"),
+            partition: String::from("aws"),
+            profile: String::from("prod"),
+            expires_at: Some(String::from("2023-11-14T22:13:20Z")),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&Record {
+                time: String::from("2023-11-14T21:13:20Z"),
+                event: &event
+            })
+            .unwrap(),
+            r#"{"time":"2023-11-14T21:13:20Z","event":"profile_refreshed","organization":"mock_org","partition":"aws","profile":"prod","expires_at":"2023-11-14T22:13:20Z"}"#
+        );
+    }
+}
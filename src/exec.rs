@@ -0,0 +1,322 @@
+//! Running a command with a profile's credentials, optionally keeping them fresh for as long as it runs.
+//!
+//! The credentials are written to a private credentials file for the child, rather than its environment,
+//! so that they can be replaced while it is running.
+//! Anything it starts which reads the file afresh (e.g. each `aws` command in a shell) sees the new credentials.
+//! With `--env`, the credentials are passed in the child's environment instead, and nothing is written at all.
+//! With `--memfd` (on Linux), they are passed in a memory file only the child inherits (see [`crate::aws::sandbox`]).
+
+use crate::aws::profile::Store as ProfileStore;
+use crate::daemon::Terminate;
+use crate::lock::FileLock;
+use crate::schedule::Schedule;
+use crate::timestamp;
+
+use std::fs::{self, OpenOptions};
+use std::future::Future;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::{Duration, SystemTime};
+
+use aws_credential_types::Credentials;
+use eyre::Result;
+use tokio::process::Command;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+/// The directory (in the oktaws cache directory) sessions' credentials files are kept in
+pub const DIR: &str = "exec";
+
+/// How long to wait before trying again, when a refresh fails
+pub const RETRY_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Environment variables which would take precedence over the credentials file
+pub(crate) const OVERRIDING_VARIABLES: &[&str] = &[
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "AWS_SECURITY_TOKEN",
+];
+
+/// A credentials file holding one profile, for the lifetime of a child process.
+/// The file is removed when the session is dropped.
+#[derive(Debug)]
+pub struct Session {
+    path: PathBuf,
+    profile: String,
+}
+
+impl Session {
+    /// Create an empty credentials file in `dir`, readable only by the current user
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file cannot be created
+    pub fn create(dir: &Path, profile: &str) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.credentials", std::process::id()));
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(&path)?;
+
+        Ok(Self {
+            path,
+            profile: profile.to_string(),
+        })
+    }
+
+    /// Replace the credentials in the file
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the credentials are not STS, or the file cannot be written
+    pub fn write(&self, credentials: &Credentials) -> Result<()> {
+        let mut store = ProfileStore::load(Some(&self.path))?;
+        store.upsert_credential(&self.profile, credentials)?;
+        store.save()
+    }
+
+    /// A command which uses this session's credentials file and profile
+    #[must_use]
+    pub fn command(&self, program: &str, args: &[String]) -> Command {
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env("AWS_SHARED_CREDENTIALS_FILE", &self.path)
+            .env("AWS_PROFILE", &self.profile);
+
+        for variable in OVERRIDING_VARIABLES {
+            command.env_remove(variable);
+        }
+
+        command
+    }
+
+    /// Run `command` until it exits.
+    /// With `refresh`, the credentials (issued at `issued`) are refreshed in the background before they expire.
+    /// Ctrl-C is left to the command (which the terminal interrupts as well), and SIGTERM stops it,
+    /// so that either way this returns, and the credentials file is removed once the session is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the command cannot be started, if the signal handlers cannot be installed,
+    /// or if refreshed credentials cannot be written.
+    pub async fn run<F, Fut>(
+        &self,
+        mut command: Command,
+        credentials: &Credentials,
+        issued: SystemTime,
+        refresh: Option<F>,
+    ) -> Result<ExitStatus>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Credentials>>,
+    {
+        // Should oktaws itself be stopped some other way, the command goes with it
+        let mut child = command.kill_on_drop(true).spawn()?;
+        let mut terminate = Terminate::new()?;
+
+        let mut refresh_at = refresh
+            .as_ref()
+            .and(Schedule::new(&self.profile, credentials, issued))
+            .map(|schedule| schedule.refresh_at);
+
+        loop {
+            let delay = refresh_at
+                .and_then(|refresh_at| refresh_at.duration_since(SystemTime::now()).ok())
+                .unwrap_or_default();
+
+            tokio::select! {
+                status = child.wait() => return Ok(status?),
+                interrupted = tokio::signal::ctrl_c() => {
+                    interrupted?;
+                    debug!("Interrupted, waiting for the command to exit");
+                }
+                () = terminate.recv() => {
+                    debug!("Terminated, stopping the command");
+                    child.start_kill()?;
+                    return Ok(child.wait().await?);
+                }
+                () = sleep(delay), if refresh_at.is_some() => {
+                    let Some(refresh) = &refresh else {
+                        continue;
+                    };
+                    debug!("Refreshing {} for the running command", self.profile);
+
+                    refresh_at = match refresh().await {
+                        Ok(credentials) => {
+                            self.write(&credentials)?;
+
+                            let schedule = Schedule::new(&self.profile, &credentials, SystemTime::now());
+                            if let Some(schedule) = &schedule {
+                                info!(
+                                    "Refreshed {}, now expiring at {}",
+                                    self.profile,
+                                    timestamp::render(schedule.expires_at)
+                                );
+                            }
+                            schedule.map(|schedule| schedule.refresh_at)
+                        }
+                        Err(e) => {
+                            warn!(
+                                "Unable to refresh {}, trying again in {}: {e}",
+                                self.profile,
+                                timestamp::duration(RETRY_INTERVAL)
+                            );
+                            Some(SystemTime::now() + RETRY_INTERVAL)
+                        }
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// A command with `credentials` (and `region`, if known) in its environment, for one-off commands.
+/// Any profile or credentials the command would otherwise have inherited are removed.
+#[must_use]
+pub fn command_with_environment(
+    program: &str,
+    args: &[String],
+    credentials: &Credentials,
+    region: Option<&str>,
+) -> Command {
+    let mut command = Command::new(program);
+    command.args(args).env_remove("AWS_PROFILE");
+
+    for variable in OVERRIDING_VARIABLES {
+        command.env_remove(variable);
+    }
+
+    command
+        .env("AWS_ACCESS_KEY_ID", credentials.access_key_id())
+        .env("AWS_SECRET_ACCESS_KEY", credentials.secret_access_key());
+    if let Some(session_token) = credentials.session_token() {
+        command.env("AWS_SESSION_TOKEN", session_token);
+    }
+    if let Some(region) = region {
+        command.env("AWS_REGION", region);
+    }
+
+    command
+}
+
+/// The code to exit with after the child exited with `status`: its own code, or as shells do,
+/// 128 plus the number of the signal which killed it
+#[must_use]
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = status.signal() {
+        return 128 + signal;
+    }
+
+    status.code().unwrap_or(1)
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        for path in [self.path.clone(), FileLock::lock_path(&self.path)] {
+            if let Err(e) = fs::remove_file(&path) {
+                debug!("Unable to remove {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[test]
+    fn exits_as_the_child_did() {
+        assert_eq!(exit_code(ExitStatus::from_raw(3 << 8)), 3);
+        // Killed by SIGKILL
+        assert_eq!(exit_code(ExitStatus::from_raw(9)), 137);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn child_sees_refreshed_credentials() -> Result<()> {
+        let dir = tempdir()?;
+        let session = Session::create(dir.path(), "prod")?;
+
+        let issued = SystemTime::now();
+        // Expiring within the minimum margin, so a refresh is due straight away
+        let credentials = Credentials::new(
+            "FIRST",
+            "SECRET",
+            Some(String::from("TOKEN")),
+            Some(issued + Duration::from_secs(1)),
+            "oktaws",
+        );
+        session.write(&credentials)?;
+
+        let refresh = || async {
+            Ok(Credentials::new(
+                "SECOND",
+                "SECRET",
+                Some(String::from("TOKEN")),
+                Some(SystemTime::now() + Duration::from_hours(1)),
+                "oktaws",
+            ))
+        };
+
+        let command = session.command(
+            "sh",
+            &[
+                String::from("-c"),
+                String::from("sleep 1; grep -q SECOND \"$AWS_SHARED_CREDENTIALS_FILE\""),
+            ],
+        );
+
+        let status = session
+            .run(command, &credentials, issued, Some(refresh))
+            .await?;
+        assert!(status.success());
+
+        let path = session.path.clone();
+        drop(session);
+        assert!(!path.exists());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn child_sees_credentials_in_environment() -> Result<()> {
+        let credentials = Credentials::new(
+            "ACCESS_KEY",
+            "SECRET",
+            Some(String::from("TOKEN")),
+            None,
+            "oktaws",
+        );
+
+        let status = command_with_environment(
+            "sh",
+            &[
+                String::from("-c"),
+                String::from(
+                    "test \"$AWS_ACCESS_KEY_ID:$AWS_SESSION_TOKEN:$AWS_REGION\" = ACCESS_KEY:TOKEN:eu-west-1 && test -z \"$AWS_PROFILE\"",
+                ),
+            ],
+            &credentials,
+            Some("eu-west-1"),
+        )
+        .status()
+        .await?;
+        assert!(status.success());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,532 @@
+//! Explaining how a profile resolves, step by step, without fetching any credentials.
+//!
+//! Each step says what was decided and why (which setting it came from, or which default applied),
+//! for debugging surprises in precedence and defaults.
+
+use crate::aws::config::Store as ConfigStore;
+use crate::aws::profile::Layout;
+use crate::aws::role::SamlRole;
+use crate::aws::sso::Client as SsoClient;
+use crate::config::organization::{Config as OrganizationConfig, Organization};
+use crate::config::profile::{self, Chain, ChainMode, Profile};
+use crate::config::rules::{self, RoleRule};
+use crate::okta::applications::AppLink;
+use crate::okta::client::Client as OktaClient;
+
+use std::env::var as env_var;
+use std::fmt;
+use std::path::Path;
+
+use eyre::Result;
+
+/// One decision in resolving a profile
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub subject: &'static str,
+    pub value: String,
+    pub reason: String,
+}
+
+/// The decisions made in resolving a profile, in the order they are made
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Explanation {
+    steps: Vec<Step>,
+}
+
+impl Explanation {
+    /// Record a decision
+    pub fn add(
+        &mut self,
+        subject: &'static str,
+        value: impl fmt::Display,
+        reason: impl Into<String>,
+    ) {
+        self.steps.push(Step {
+            subject,
+            value: value.to_string(),
+            reason: reason.into(),
+        });
+    }
+
+    /// The decisions recorded so far
+    #[must_use]
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Explain what the configuration alone says about `profile`
+    #[must_use]
+    pub fn from_config(
+        organization: &Organization,
+        config: &OrganizationConfig,
+        config_path: &Path,
+        profile: &Profile,
+        credentials_path: &Path,
+    ) -> Self {
+        let mut explanation = Self::default();
+        let profile_config = config.profiles.get(organization.config_name(&profile.name));
+
+        explanation.add(
+            "Organization",
+            &organization.name,
+            format!(
+                "{} is configured in {}",
+                profile.name,
+                config_path.display()
+            ),
+        );
+
+        explanation.add(
+            "Partition",
+            organization.partition,
+            if config.partition.is_some() {
+                "the organization's `partition`"
+            } else {
+                "the default partition"
+            },
+        );
+
+        explanation.add(
+            "Okta tile",
+            &profile.application_name,
+            "the profile's application; federated (SAML) tiles with this label are tried before AWS SSO tiles",
+        );
+
+        if let Some(account) = &profile.account {
+            explanation.add(
+                "Account",
+                account,
+                "the profile's `account`, matched against AWS SSO app instance names",
+            );
+        }
+
+        let account = profile.account.as_ref().unwrap_or(&profile.name);
+        explanation.add(
+            "Roles",
+            profile.roles.join(", "),
+            match (
+                profile_config,
+                rules::first_match(&profile.role_rules, account, None),
+                &config.role,
+                &config.roles,
+            ) {
+                (Some(profile::Config::Detailed { role: Some(_), .. }), _, _, _) => {
+                    String::from("the profile's `role`")
+                }
+                (_, Some(rule), _, _) => format!(
+                    "the first role rule to match {account} ({}), as the profile has no `role`",
+                    describe_rule(rule)
+                ),
+                (_, _, Some(_), _) => {
+                    String::from("the organization's default `role`, as the profile has none")
+                }
+                (_, _, _, Some(_)) => {
+                    String::from("the organization's default `roles`, as the profile has none")
+                }
+                _ => String::from("no role is configured"),
+            },
+        );
+
+        explanation.add(
+            "Duration",
+            profile
+                .duration_seconds
+                .map_or_else(|| String::from("unset"), |seconds| format!("{seconds}s")),
+            match (profile_config, config.duration_seconds) {
+                (
+                    Some(profile::Config::Detailed {
+                        duration_seconds: Some(_),
+                        ..
+                    }),
+                    _,
+                ) => "the profile's `duration_seconds`",
+                (_, Some(_)) => "the organization's default `duration_seconds`",
+                _ => "AWS's default for the role (usually 1 hour), as none is configured",
+            },
+        );
+
+        match &profile.chain {
+            Some(Chain {
+                role_arn,
+                mode: ChainMode::Oktaws,
+                ..
+            }) => explanation.add(
+                "Chains into",
+                role_arn,
+                "the profile's `assume_role`, assumed by oktaws",
+            ),
+            Some(Chain {
+                role_arn,
+                mode: ChainMode::Sdk,
+                ..
+            }) => explanation.add(
+                "Chains into",
+                role_arn,
+                "the profile's `assume_role`, assumed by the AWS SDKs (`chain_mode = 'sdk'`)",
+            ),
+            None => {}
+        }
+
+        let section = match organization.credentials_layout {
+            Layout::Credentials => profile.credentials_profile(),
+            Layout::Config => ConfigStore::section(&profile.credentials_profile()),
+        };
+        explanation.add(
+            "Section",
+            format!("[{section}]"),
+            if profile.credentials_profile() == profile.name {
+                "the profile's name"
+            } else {
+                "a source profile, which the SDKs chain from"
+            },
+        );
+
+        explanation.add(
+            "Written to",
+            credentials_path.display(),
+            if organization.credentials_file.is_some() {
+                "the organization's `credentials_file`"
+            } else if organization.credentials_layout == Layout::Config {
+                "the AWS config file, as the organization's `credentials_layout` is `config`"
+            } else if env_var("AWS_SHARED_CREDENTIALS_FILE").is_ok() {
+                "`AWS_SHARED_CREDENTIALS_FILE`"
+            } else {
+                "the shared credentials file"
+            },
+        );
+
+        explanation.add(
+            "STS region",
+            organization.partition.region(),
+            format!("the {} partition's STS endpoint", organization.partition),
+        );
+
+        explanation
+    }
+
+    /// Explain which Okta tile, account and role `profile` resolves to, as the user signed in to `client`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are any errors talking to Okta or AWS SSO
+    pub async fn add_matches(
+        &mut self,
+        client: &OktaClient,
+        organization: &Organization,
+        profile: &Profile,
+    ) -> Result<()> {
+        let app_links = client.app_links(None).await?;
+        let find = |app_name: &str| {
+            app_links
+                .iter()
+                .find(|app_link| {
+                    app_link.app_name == app_name && app_link.label == profile.application_name
+                })
+                .cloned()
+        };
+
+        if let Some(app_link) = find("amazon_aws") {
+            self.add(
+                "Matched tile",
+                format!("{} (federated)", app_link.label),
+                "the first federated (SAML) tile with the profile's application as its label",
+            );
+            self.add_saml_roles(client, app_link, profile).await
+        } else if let Some(app_link) = find("amazon_aws_sso") {
+            self.add(
+                "Matched tile",
+                format!("{} (AWS SSO)", app_link.label),
+                "the first AWS SSO tile with the profile's application as its label, as no federated tile has it",
+            );
+            self.add_sso_roles(client, app_link, organization, profile)
+                .await
+        } else {
+            self.add(
+                "Matched tile",
+                "none",
+                format!(
+                    "no AWS tile labelled {} is assigned to {}",
+                    profile.application_name, organization.username
+                ),
+            );
+            Ok(())
+        }
+    }
+
+    async fn add_saml_roles(
+        &mut self,
+        client: &OktaClient,
+        app_link: AppLink,
+        profile: &Profile,
+    ) -> Result<()> {
+        let granted = client
+            .get_saml_response(app_link.link_url)
+            .await?
+            .roles()?
+            .iter()
+            .map(SamlRole::role_name)
+            .collect::<Result<Vec<_>>>()?;
+
+        let (value, reason) = choose_role(&profile.roles, &granted);
+        self.add("Chosen role", value, reason);
+
+        Ok(())
+    }
+
+    async fn add_sso_roles(
+        &mut self,
+        client: &OktaClient,
+        app_link: AppLink,
+        organization: &Organization,
+        profile: &Profile,
+    ) -> Result<()> {
+        let naming = &organization.account_naming;
+
+        let Some(account) = &profile.account else {
+            self.add(
+                "App instance",
+                "none",
+                "Chosen when refreshing, as the profile has no `account`",
+            );
+            return Ok(());
+        };
+
+        let org_auth = client
+            .get_org_id_and_auth_code_for_app_link(app_link)
+            .await?;
+        let sso = SsoClient::new(&org_auth.org_id, &org_auth.auth_code).await?;
+
+        let Some(app_instance) = sso
+            .app_instances()
+            .await?
+            .into_iter()
+            .find(|app| app.account_name(naming).as_ref() == Some(account))
+        else {
+            self.add(
+                "App instance",
+                "none",
+                format!(
+                    "no app instance name gives {account} with the account name pattern `{}`",
+                    naming.name_pattern()
+                ),
+            );
+            return Ok(());
+        };
+
+        self.add(
+            "App instance",
+            &app_instance.name,
+            format!(
+                "the account name pattern `{}` gives {account}",
+                naming.name_pattern()
+            ),
+        );
+        self.add(
+            "Account ID",
+            app_instance.account_id(naming).unwrap_or("none"),
+            format!("the account ID pattern `{}`", naming.id_pattern()),
+        );
+
+        let configured = match rules::first_match(
+            &profile.role_rules,
+            account,
+            app_instance.account_email(),
+        ) {
+            Some(rule) if rule.email.is_some() => {
+                self.add(
+                    "Role rule",
+                    &rule.role,
+                    format!(
+                        "the first role rule to match, now that AWS SSO gives the account's email ({})",
+                        describe_rule(rule)
+                    ),
+                );
+                vec![rule.role.clone()]
+            }
+            _ => profile.roles.clone(),
+        };
+
+        let granted = sso
+            .profiles(&app_instance.id)
+            .await?
+            .into_iter()
+            .map(|profile| profile.name)
+            .collect::<Vec<_>>();
+
+        let (value, reason) = choose_role(&configured, &granted);
+        self.add("Chosen role", value, reason);
+
+        Ok(())
+    }
+}
+
+/// The patterns a role rule matches on
+fn describe_rule(rule: &RoleRule) -> String {
+    rule.account
+        .iter()
+        .map(|pattern| format!("account `{pattern}`"))
+        .chain(
+            rule.email
+                .iter()
+                .map(|pattern| format!("email `{pattern}`")),
+        )
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Which of the `granted` roles a profile configured with `configured` uses, and why
+fn choose_role(configured: &[String], granted: &[String]) -> (String, String) {
+    let available = granted
+        .iter()
+        .filter(|role| configured.contains(role))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    match available.as_slice() {
+        [] => (
+            String::from("none"),
+            format!(
+                "none of the configured roles are granted (granted: {})",
+                granted.join(", ")
+            ),
+        ),
+        [role] => (
+            role.clone(),
+            String::from("the only configured role which is granted"),
+        ),
+        _ => (
+            format!("one of {}", available.join(", ")),
+            String::from("several configured roles are granted, so refreshes prompt for one"),
+        ),
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .steps
+            .iter()
+            .map(|step| step.subject.len())
+            .max()
+            .unwrap_or_default();
+
+        for step in &self.steps {
+            writeln!(f, "{:<width$}  {}", step.subject, step.value)?;
+            writeln!(f, "{:<width$}  ({})", "", step.reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::error::from_toml;
+
+    fn roles(names: &[&str]) -> Vec<String> {
+        names.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn chooses_configured_roles() {
+        assert_eq!(
+            choose_role(&roles(&["Admin"]), &roles(&["ReadOnly", "Admin"])).0,
+            "Admin"
+        );
+        assert_eq!(
+            choose_role(
+                &roles(&["Admin", "ReadOnly"]),
+                &roles(&["ReadOnly", "Admin"])
+            )
+            .0,
+            "one of ReadOnly, Admin"
+        );
+        assert_eq!(
+            choose_role(&roles(&["Admin"]), &roles(&["ReadOnly"])),
+            (
+                String::from("none"),
+                String::from("none of the configured roles are granted (granted: ReadOnly)")
+            )
+        );
+    }
+
+    #[test]
+    fn explains_defaults() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("mock_org.toml");
+        let contents = r#"
+username = "mock_user"
+role = "ReadOnly"
+duration_seconds = 900
+
+[[role_rules]]
+account = "*-prod-*"
+role = "Auditor"
+
+[profiles]
+prod = { application = "AWS Prod", role = "Admin" }
+dev = "AWS Dev"
+payments-prod-eu = "AWS Payments"
+"#;
+        std::fs::write(&path, contents)?;
+
+        let config: OrganizationConfig = from_toml(&path, contents)?;
+        let organization = Organization::try_from(path.as_path())?;
+        let credentials = dir.path().join("credentials");
+
+        let reasons = |name: &str| {
+            let profile = organization
+                .profiles
+                .iter()
+                .find(|profile| profile.name == name)
+                .unwrap();
+
+            Explanation::from_config(&organization, &config, &path, profile, &credentials)
+                .steps()
+                .iter()
+                .map(|step| (step.subject, step.value.clone(), step.reason.clone()))
+                .filter(|(subject, _, _)| ["Roles", "Duration"].contains(subject))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            reasons("prod"),
+            vec![
+                (
+                    "Roles",
+                    String::from("Admin"),
+                    String::from("the profile's `role`")
+                ),
+                (
+                    "Duration",
+                    String::from("900s"),
+                    String::from("the organization's default `duration_seconds`")
+                ),
+            ]
+        );
+        assert_eq!(
+            reasons("dev")[0],
+            (
+                "Roles",
+                String::from("ReadOnly"),
+                String::from(
+                    "the organization's default `role`, as the profile has none and no role rule matches"
+                )
+            )
+        );
+        assert_eq!(
+            reasons("payments-prod-eu")[0],
+            (
+                "Roles",
+                String::from("Auditor"),
+                String::from(
+                    "the first role rule to match payments-prod-eu (account `*-prod-*`), as the profile has no `role`"
+                )
+            )
+        );
+
+        Ok(())
+    }
+}
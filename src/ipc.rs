@@ -0,0 +1,230 @@
+//! A small local control API, for tray applets and editor plugins.
+//!
+//! Each connection sends one JSON request on a line, and receives one JSON response on a line.
+//! Only Unix domain sockets are supported for now.
+
+use crate::config::state_dir;
+
+use std::fs::{self, Permissions};
+use std::future::Future;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, instrument};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Report the state of every managed profile
+    Status,
+    /// Refresh one profile (or all of them) straight away
+    RefreshNow { profile: Option<String> },
+    /// End the Okta session for one organization (or all of them), and forget it
+    Logout { organization: Option<String> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProfileStatus {
+    pub profile: String,
+    pub expires_at: Option<String>,
+    pub refresh_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<ProfileStatus>,
+}
+
+impl Response {
+    #[must_use]
+    pub fn ok() -> Self {
+        Self {
+            ok: true,
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn error(error: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error: Some(error.into()),
+            ..Self::default()
+        }
+    }
+}
+
+/// Carries out requests on behalf of the socket server
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: Request) -> impl Future<Output = Response> + Send;
+}
+
+/// The default location of the control socket
+///
+/// # Errors
+///
+/// Will return `Err` if the oktaws state directory cannot be determined
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(state_dir()?.join("oktaws.sock"))
+}
+
+/// Listen on `path`, replacing any socket left behind by a previous run.
+/// Only the current user may connect, as requests act on their sessions.
+///
+/// # Errors
+///
+/// Will return `Err` if something other than a socket is at `path`, or the socket cannot be bound
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(eyre!(
+                "Refusing to replace {}, as it is not a socket",
+                path.display()
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+
+    Ok(listener)
+}
+
+/// Answer requests on `listener` until it fails
+///
+/// # Errors
+///
+/// Will return `Err` if accepting a connection fails
+pub async fn serve<H: Handler>(listener: UnixListener, handler: Arc<H>) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let handler = handler.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = answer(stream, handler.as_ref()).await {
+                debug!("Control connection failed: {e}");
+            }
+        });
+    }
+}
+
+async fn answer<H: Handler>(stream: UnixStream, handler: &H) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    let response = match serde_json::from_str(&line) {
+        Ok(request) => handler.handle(request).await,
+        Err(e) => Response::error(format!("Invalid request: {e}")),
+    };
+
+    writer
+        .write_all(format!("{}\n", serde_json::to_string(&response)?).as_bytes())
+        .await?;
+
+    Ok(())
+}
+
+/// Send a single request to the server listening on `path`
+///
+/// # Errors
+///
+/// Will return `Err` if nothing is listening on `path`, or if the response is not understood
+#[instrument(level = "debug")]
+pub async fn send(path: &Path, request: &Request) -> Result<Response> {
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|e| eyre!("Unable to connect to {} ({e})", path.display()))?;
+    let (reader, mut writer) = stream.into_split();
+
+    writer
+        .write_all(format!("{}\n", serde_json::to_string(request)?).as_bytes())
+        .await?;
+
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    serde_json::from_str(&line).map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    struct Fixed;
+
+    impl Handler for Fixed {
+        fn handle(&self, request: Request) -> impl Future<Output = Response> + Send {
+            std::future::ready(match request {
+                Request::Status => Response {
+                    profiles: vec![ProfileStatus {
+                        profile: String::from("prod"),
+                        expires_at: Some(String::from("2023-11-14T22:13:20Z")),
+                        refresh_at: None,
+                    }],
+                    ..Response::ok()
+                },
+                Request::RefreshNow { .. } => Response::ok(),
+                Request::Logout { .. } => Response::error("not signed in"),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("oktaws.sock");
+
+        tokio::spawn(serve(bind(&path)?, Arc::new(Fixed)));
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+
+        let status = send(&path, &Request::Status).await?;
+        assert!(status.ok);
+        assert_eq!(status.profiles[0].profile, "prod");
+
+        let logout = send(&path, &Request::Logout { organization: None }).await?;
+        assert_eq!(logout, Response::error("not signed in"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_replaces_sockets() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("oktaws.sock");
+
+        // Left behind by a previous run
+        drop(bind(&path)?);
+        drop(bind(&path)?);
+
+        let file = dir.path().join("usage.json");
+        fs::write(&file, "{}")?;
+        assert!(bind(&file).is_err());
+        assert_eq!(fs::read_to_string(&file)?, "{}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn request_format() {
+        assert_eq!(
+            serde_json::to_string(&Request::RefreshNow {
+                profile: Some(String::from("prod"))
+            })
+            .unwrap(),
+            r#"{"command":"refresh_now","profile":"prod"}"#
+        );
+    }
+}
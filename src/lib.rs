@@ -2,32 +2,56 @@
 #![warn(clippy::cargo)]
 #![allow(clippy::multiple_crate_versions)]
 
+pub mod access;
+pub mod askpass;
 pub mod aws;
+pub mod bench;
+pub mod blocking;
+pub mod browser;
+pub mod coalesce;
 pub mod config;
+pub mod daemon;
+pub mod discovery;
+pub mod elevate;
+pub mod events;
+pub mod exec;
+pub mod explain;
+#[cfg(unix)]
+pub mod ipc;
+pub mod list;
+pub mod lock;
 pub mod okta;
+pub mod output;
+pub mod parallel;
+pub mod picker;
+pub mod ping;
+pub mod progress;
+pub mod redact;
+pub mod registry;
+pub mod report;
+pub mod request_id;
+pub mod retry;
+pub mod schedule;
+pub mod search;
+pub mod secrets;
+pub mod serve;
+pub mod shell;
+pub mod silent;
+pub mod sink;
+pub mod state;
+pub mod support;
+pub mod theme;
+pub mod timestamp;
+pub mod timing;
+pub mod tls;
+pub mod transaction;
+pub mod transport;
+pub mod usage;
+#[cfg(feature = "vault")]
+pub mod vault;
 
 use eyre::{Result, eyre};
 
-fn select<T, P, F, S>(mut items: Vec<T>, prompt: P, displayer: F) -> Result<T>
-where
-    P: Into<String>,
-    F: FnMut(&T) -> S,
-    S: ToString,
-{
-    let index = match items.len() {
-        0 => Err(eyre!("No items found")),
-        1 => Ok(0_usize),
-        _ => dialoguer::Select::new()
-            .with_prompt(prompt)
-            .items(&items.iter().map(displayer).collect::<Vec<_>>())
-            .default(0)
-            .interact()
-            .map_err(Into::into),
-    }?;
-
-    Ok(items.remove(index))
-}
-
 fn select_multiple_opt<T, P, F, S>(mut items: Vec<T>, prompt: P, displayer: F) -> Result<Vec<T>>
 where
     P: Into<String>,
@@ -36,7 +60,7 @@ where
 {
     let indices = match items.len() {
         0 => Err(eyre!("No items found")),
-        _ => dialoguer::MultiSelect::new()
+        _ => dialoguer::MultiSelect::with_theme(theme::prompt())
             .with_prompt(prompt)
             .items(&items.iter().map(displayer).collect::<Vec<_>>())
             .interact()
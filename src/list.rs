@@ -0,0 +1,330 @@
+//! Every configured profile, where its credentials come from, and whether those in the credentials file are still valid,
+//! for `oktaws list`.
+//!
+//! Nothing is fetched: expiry comes from the credentials file (where oktaws records it as `aws_expiration`),
+//! and accounts from the last discovery of each organization (see [`crate::discovery`]), where there has been one.
+
+use crate::aws::profile::Store as ProfileStore;
+use crate::config::profile::Profile;
+use crate::discovery::CatalogEntry;
+use crate::events;
+use crate::timestamp::{self, Format as TimestampFormat};
+
+use std::fmt::{self, Write};
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+/// The state of a profile's credentials in the credentials file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Temporary credentials which have not expired yet
+    Valid,
+    /// Temporary credentials which have expired
+    Expired,
+    /// Temporary credentials, written without their expiry (by an older version of oktaws, or another tool)
+    Unknown,
+    /// No credentials
+    Missing,
+}
+
+impl Status {
+    /// The state of the credentials for `section` in `store`, with when they expire, where that is known
+    #[must_use]
+    pub fn of(store: &ProfileStore, section: &str, now: SystemTime) -> (Self, Option<SystemTime>) {
+        if !store.has_session(section) {
+            return (Self::Missing, None);
+        }
+
+        match store.expiry(section) {
+            Some(expiry) if expiry > now => (Self::Valid, Some(expiry)),
+            Some(expiry) => (Self::Expired, Some(expiry)),
+            None => (Self::Unknown, None),
+        }
+    }
+}
+
+/// Where a profile's credentials come from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Source {
+    /// An account behind a federated (SAML) Okta tile
+    Saml {
+        application: String,
+        account: String,
+        account_id: Option<String>,
+    },
+    /// An account in AWS SSO
+    Sso {
+        application: String,
+        account: String,
+        account_id: Option<String>,
+    },
+    /// What the configuration says, as the account has not been discovered
+    Configured {
+        application: String,
+        account: Option<String>,
+    },
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let account = |account: &str, account_id: Option<&str>| match account_id {
+            Some(id) if id != account => format!("{account} ({id})"),
+            _ => account.to_string(),
+        };
+
+        match self {
+            Self::Saml {
+                application,
+                account: name,
+                account_id,
+            } => write!(
+                f,
+                "SAML app {application}, account {}",
+                account(name, account_id.as_deref())
+            ),
+            Self::Sso {
+                application,
+                account: name,
+                account_id,
+            } => write!(
+                f,
+                "AWS SSO account {} in {application}",
+                account(name, account_id.as_deref())
+            ),
+            Self::Configured {
+                application,
+                account: Some(account),
+            } => write!(f, "{application}, account {account} (not discovered)"),
+            Self::Configured {
+                application,
+                account: None,
+            } => write!(f, "{application} (not discovered)"),
+        }
+    }
+}
+
+/// A configured profile, as `oktaws list` shows it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    pub organization: String,
+    pub profile: String,
+    /// The credentials section the profile is written to
+    pub section: String,
+    pub source: Source,
+    /// The roles the profile asks for, or without any, those the account grants
+    pub roles: Vec<String>,
+    pub status: Status,
+    pub expires_at: Option<String>,
+    #[serde(skip)]
+    expiry: Option<SystemTime>,
+}
+
+impl Entry {
+    /// Describe `profile` of `organization`, with its account from `catalog` (the organization's last discovery, if any)
+    /// and its credentials from `store`
+    #[must_use]
+    pub fn new(
+        organization: &str,
+        profile: &Profile,
+        catalog: Option<&[CatalogEntry]>,
+        store: &ProfileStore,
+        now: SystemTime,
+    ) -> Self {
+        let discovered = catalog
+            .into_iter()
+            .flatten()
+            .filter(|entry| {
+                entry.application == profile.application_name
+                    && profile
+                        .account
+                        .as_ref()
+                        .map_or(entry.federated, |account| *account == entry.account)
+            })
+            .max_by_key(|entry| profile.roles.iter().any(|role| entry.roles.contains(role)));
+
+        let source = match discovered {
+            Some(entry) if entry.federated => Source::Saml {
+                application: entry.application.clone(),
+                account: entry.account.clone(),
+                account_id: entry.account_id.clone(),
+            },
+            Some(entry) => Source::Sso {
+                application: entry.application.clone(),
+                account: entry.account.clone(),
+                account_id: entry.account_id.clone(),
+            },
+            None => Source::Configured {
+                application: profile.application_name.clone(),
+                account: profile.account.clone(),
+            },
+        };
+
+        let roles = match discovered {
+            Some(entry) if profile.roles.is_empty() => entry.roles.clone(),
+            _ => profile.roles.clone(),
+        };
+
+        let section = profile.credentials_profile();
+        let (status, expiry) = Status::of(store, &section, now);
+
+        Self {
+            organization: organization.to_string(),
+            profile: profile.name.clone(),
+            section,
+            source,
+            roles,
+            status,
+            expires_at: expiry.and_then(events::timestamp),
+            expiry,
+        }
+    }
+}
+
+/// A description of each profile, with the state of its credentials
+#[must_use]
+pub fn render(entries: &[Entry], format: TimestampFormat, now: SystemTime) -> String {
+    let mut output = String::new();
+
+    for entry in entries {
+        let status = match (entry.status, entry.expiry) {
+            (Status::Valid, Some(expiry)) => format!("valid until {}", format.render(expiry, now)),
+            (Status::Expired, Some(expiry)) => {
+                format!("expired {}", timestamp::relative(expiry, now))
+            }
+            (Status::Missing, _) => String::from("no credentials"),
+            _ => String::from("credentials of unknown expiry"),
+        };
+
+        let _ = writeln!(
+            output,
+            "{} ({}): {status}",
+            entry.profile, entry.organization
+        );
+        let _ = write!(output, "  {}", entry.source);
+        if !entry.roles.is_empty() {
+            let _ = write!(output, ", as {}", entry.roles.join(" or "));
+        }
+        if entry.section != entry.profile {
+            let _ = write!(output, ", written to [{}]", entry.section);
+        }
+        output.push('\n');
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::profile::CliSettings;
+
+    use std::time::Duration;
+
+    use aws_credential_types::Credentials;
+    use tempfile::tempdir;
+
+    fn profile(name: &str, account: Option<&str>, roles: &[&str]) -> Profile {
+        Profile {
+            name: name.to_string(),
+            application_name: String::from("AWS SSO"),
+            account: account.map(ToString::to_string),
+            roles: roles.iter().map(ToString::to_string).collect(),
+            duration_seconds: None,
+            chain: None,
+            role_rules: Vec::new(),
+            saml_fallback: None,
+            console: None,
+            cli: CliSettings::default(),
+        }
+    }
+
+    #[test]
+    fn lists_source_and_expiry() -> eyre::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("credentials");
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut store = ProfileStore::load(Some(&path))?;
+        for (section, expiry) in [
+            ("prod", now + Duration::from_hours(1)),
+            ("dev", now - Duration::from_mins(5)),
+        ] {
+            store.upsert_credential(
+                section,
+                &Credentials::new(
+                    "ASIA",
+                    "secret",
+                    Some(String::from("token")),
+                    Some(expiry),
+                    "test",
+                ),
+            )?;
+        }
+        store.save()?;
+        let store = ProfileStore::load(Some(&path))?;
+
+        let catalog = vec![CatalogEntry {
+            account: String::from("production"),
+            account_id: Some(String::from("123456789012")),
+            account_email: None,
+            description: None,
+            application: String::from("AWS SSO"),
+            federated: false,
+            roles: vec![String::from("Admin"), String::from("ReadOnly")],
+        }];
+
+        let prod = Entry::new(
+            "mycompany",
+            &profile("prod", Some("production"), &[]),
+            Some(&catalog),
+            &store,
+            now,
+        );
+        assert_eq!(prod.status, Status::Valid);
+        assert_eq!(prod.roles, vec!["Admin", "ReadOnly"]);
+        assert_eq!(
+            prod.source.to_string(),
+            "AWS SSO account production (123456789012) in AWS SSO"
+        );
+
+        let dev = Entry::new(
+            "mycompany",
+            &profile("dev", Some("development"), &["Admin"]),
+            Some(&catalog),
+            &store,
+            now,
+        );
+        assert_eq!(dev.status, Status::Expired);
+        assert_eq!(
+            dev.source,
+            Source::Configured {
+                application: String::from("AWS SSO"),
+                account: Some(String::from("development")),
+            }
+        );
+
+        let staging = Entry::new(
+            "mycompany",
+            &profile("staging", None, &["Admin"]),
+            None,
+            &store,
+            now,
+        );
+        assert_eq!(staging.status, Status::Missing);
+
+        assert_eq!(
+            render(&[dev, staging], TimestampFormat::Utc, now),
+            "dev (mycompany): expired 5m ago
+  AWS SSO, account development (not discovered), as Admin
+staging (mycompany): no credentials
+  AWS SSO (not discovered), as Admin
+"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use eyre::{Result, eyre};
+use tracing::{debug, instrument, trace};
+
+/// How long to wait for a lock before giving up
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An advisory lock on a file, held until dropped.
+///
+/// The lock is taken on a sibling `<file>.lock` file rather than the file itself,
+/// so that the protected file can be replaced (e.g. by renaming a temporary file over it)
+/// while the lock is held.
+/// The lock file records the pid and time of the current holder, for diagnostics.
+/// Because the OS releases the lock when its holder exits,
+/// a lock file left behind by a crashed process is detected as stale and reclaimed.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    path: PathBuf,
+}
+
+/// The process recorded as holding a lock
+#[derive(Debug, PartialEq, Eq)]
+struct Holder {
+    pid: u32,
+    since: u64,
+}
+
+impl Holder {
+    fn current() -> Self {
+        Self {
+            pid: process::id(),
+            since: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+
+    fn parse(contents: &str) -> Option<Self> {
+        let (pid, since) = contents.trim().split_once(' ')?;
+
+        Some(Self {
+            pid: pid.parse().ok()?,
+            since: since.parse().ok()?,
+        })
+    }
+}
+
+impl FileLock {
+    /// Return the path of the lock file guarding `target`
+    #[must_use]
+    pub fn lock_path(target: &Path) -> PathBuf {
+        let mut file_name = target.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".lock");
+        target.with_file_name(file_name)
+    }
+
+    /// Acquire an exclusive lock on `target`, waiting up to `DEFAULT_TIMEOUT`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the lock file cannot be created,
+    /// or if the lock is still held by another process after the timeout.
+    pub fn acquire(target: &Path) -> Result<Self> {
+        Self::acquire_with_timeout(target, DEFAULT_TIMEOUT)
+    }
+
+    /// Acquire an exclusive lock on `target`, waiting up to `timeout`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the lock file cannot be created,
+    /// or if the lock is still held by another process after the timeout.
+    #[instrument(level = "debug")]
+    pub fn acquire_with_timeout(target: &Path, timeout: Duration) -> Result<Self> {
+        let path = Self::lock_path(target);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let started = SystemTime::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {
+                    if started.elapsed().unwrap_or_default() >= timeout {
+                        return Err(Self::read_holder(&mut file).map_or_else(
+                            || {
+                                eyre!(
+                                    "Timed out after {}s waiting for lock {}",
+                                    timeout.as_secs(),
+                                    path.display()
+                                )
+                            },
+                            |holder| {
+                                eyre!(
+                                    "Timed out after {}s waiting for lock {} (held by pid {} since {})",
+                                    timeout.as_secs(),
+                                    path.display(),
+                                    holder.pid,
+                                    holder.since
+                                )
+                            },
+                        ));
+                    }
+
+                    trace!("Waiting for lock {}", path.display());
+                    sleep(POLL_INTERVAL);
+                }
+                Err(TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+
+        if let Some(holder) = Self::read_holder(&mut file) {
+            debug!(
+                "Reclaiming stale lock {} left by pid {}",
+                path.display(),
+                holder.pid
+            );
+        }
+
+        let current = Holder::current();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{} {}", current.pid, current.since)?;
+        file.flush()?;
+
+        Ok(Self { file, path })
+    }
+
+    fn read_holder(file: &mut File) -> Option<Holder> {
+        let mut contents = String::new();
+        file.seek(SeekFrom::Start(0)).ok()?;
+        file.read_to_string(&mut contents).ok()?;
+        Holder::parse(&contents)
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Clear the holder, so the next process does not think this lock went stale
+        let _ = self.file.set_len(0);
+        if let Err(e) = self.file.unlock() {
+            debug!("Error releasing lock {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn lock_path_is_sibling() {
+        assert_eq!(
+            FileLock::lock_path(Path::new("/tmp/credentials")),
+            PathBuf::from("/tmp/credentials.lock")
+        );
+    }
+
+    #[test]
+    fn lock_is_exclusive() -> Result<()> {
+        let dir = tempdir()?;
+        let target = dir.path().join("credentials");
+
+        let lock = FileLock::acquire(&target)?;
+
+        let err = FileLock::acquire_with_timeout(&target, Duration::from_millis(200)).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Timed out after 0s waiting for lock"),
+            "{err}"
+        );
+        assert!(
+            err.to_string()
+                .contains(&format!("held by pid {}", process::id())),
+            "{err}"
+        );
+
+        drop(lock);
+
+        FileLock::acquire_with_timeout(&target, Duration::from_millis(200))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn reclaims_stale_lock() -> Result<()> {
+        let dir = tempdir()?;
+        let target = dir.path().join("credentials");
+
+        // A lock file left behind by a process that no longer holds the lock
+        fs::write(FileLock::lock_path(&target), "1 0")?;
+
+        let _lock = FileLock::acquire_with_timeout(&target, Duration::from_millis(200))?;
+
+        let holder = Holder::parse(&fs::read_to_string(FileLock::lock_path(&target))?);
+        assert_eq!(holder.map(|h| h.pid), Some(process::id()));
+
+        Ok(())
+    }
+}
@@ -2,22 +2,106 @@
 #![warn(clippy::cargo)]
 #![allow(clippy::multiple_crate_versions)]
 
-use oktaws::aws::profile::Store as ProfileStore;
-use oktaws::config::oktaws_home;
-use oktaws::config::organization::{Config as OrganizationConfig, Pattern as OrganizationPattern};
+use oktaws::access::{self, Lookup, Verdict};
+use oktaws::askpass;
+use oktaws::aws::config::Store as ConfigStore;
+use oktaws::aws::console::{self, Page as ConsolePage};
+use oktaws::aws::identity_center;
+use oktaws::aws::partition::Partition;
+use oktaws::aws::process::ProcessCredentials;
+use oktaws::aws::profile::{Layout, Store as ProfileStore};
+use oktaws::aws::role::{ChainedRole, MAX_DURATION_SECONDS, MIN_DURATION_SECONDS};
+#[cfg(target_os = "linux")]
+use oktaws::aws::sandbox::CredentialsFd;
+use oktaws::aws::{issued_to, sts_client_with_credentials};
+use oktaws::bench;
+use oktaws::blocking;
+use oktaws::browser::{self, Browser};
+use oktaws::coalesce::{self, Coalescer};
+use oktaws::config::error::from_toml;
+use oktaws::config::naming::{self, Template as NamingTemplate};
+use oktaws::config::organization::{
+    Config as OrganizationConfig, Organization, Pattern as OrganizationPattern, Selector,
+    prompt_username,
+};
+use oktaws::config::overlap;
+use oktaws::config::profile::{Chain, ChainMode, Config as ProfileConfig, Profile};
+use oktaws::config::read_only;
+use oktaws::config::rename;
+use oktaws::config::settings::{self, Settings};
+use oktaws::config::shared::{self, Source as SharedSource, Sources as SharedSources};
+use oktaws::config::{
+    cache_dir, create_oktaws_home, is_configured, oktaws_home, parse_duration, state_dir,
+};
+use oktaws::daemon;
+use oktaws::discovery::{self, CatalogEntry};
+use oktaws::elevate::{self, Window as ElevationWindow};
+use oktaws::events::{self, Event};
+use oktaws::exec::{self, Session as ExecSession};
+use oktaws::explain::Explanation;
+#[cfg(unix)]
+use oktaws::ipc;
+use oktaws::list::{self, Entry as ListEntry};
+use oktaws::okta::applications::{
+    AppLink, AppLinkAccountRoleMapping, Scope as DiscoveryScope, dedupe_account_mappings,
+};
+use oktaws::okta::capabilities;
 use oktaws::okta::client::Client as OktaClient;
+use oktaws::okta::cookies::Source as CookieSource;
+use oktaws::okta::lockout;
+use oktaws::okta::portal::Portal;
+use oktaws::okta::totp::Generator as TotpGenerator;
+use oktaws::okta::transactions;
+use oktaws::output::{self, Format as OutputFormat, Refreshed};
+use oktaws::parallel;
+use oktaws::picker;
+use oktaws::ping;
+use oktaws::redact::{MakeRedacted, Redactor};
+use oktaws::registry::{self, Registry as SectionRegistry};
+use oktaws::report::{self, Entry as ReportEntry, Format as ReportFormat};
+use oktaws::request_id;
+use oktaws::retry::{Budget, is_outage};
+use oktaws::schedule::Schedule;
+use oktaws::search;
+use oktaws::secrets::{self, Secret, Store as SecretStore};
+use oktaws::serve::{self, Server as CredentialsServer};
+use oktaws::shell;
+use oktaws::silent::{self, Remembered as SilentSession};
+use oktaws::sink;
+use oktaws::state::Bundle as StateBundle;
+use oktaws::support::Bundle as SupportBundle;
+use oktaws::theme::{self, Theme};
+use oktaws::timestamp::{self, Format as TimestampFormat};
+use oktaws::timing::FoldedLayer;
+use oktaws::tls::Network;
+use oktaws::transaction::{self, Staged};
+use oktaws::usage::{self, Usage};
 
+use std::collections::btree_map::Entry as BTreeEntry;
+use std::collections::hash_map::Entry as HashEntry;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
+use std::fmt::Display;
+use std::io::{IsTerminal, stdin, stdout};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
+use aws_credential_types::Credentials;
+use chrono::Utc;
 use clap::Parser;
 use clap_verbosity_flag::Verbosity;
+use color_eyre::Section;
 use color_eyre::eyre::{Result, eyre};
+use futures::future::join_all;
 use glob::Pattern;
-use tracing::instrument;
+use tokio::sync::OnceCell;
+use tracing::{Level, debug, info, instrument, warn};
 use tracing_log::AsTrace;
 use tracing_subscriber::filter::Targets;
 use tracing_subscriber::{Registry, prelude::*};
 use tracing_tree::HierarchicalLayer;
+use url::Url;
 use whoami::username;
 
 #[derive(Parser, Debug)]
@@ -26,6 +110,79 @@ struct Args {
     #[clap(flatten)]
     verbosity: Verbosity,
 
+    /// Terminal theme (default, dark or none). Also set by `OKTAWS_THEME`, and disabled by `NO_COLOR`
+    #[clap(long, global = true)]
+    theme: Option<Theme>,
+
+    /// Write lifecycle events as JSON lines to this file or FIFO (`-` for stdout)
+    #[clap(long, global = true)]
+    events: Option<PathBuf>,
+
+    /// How to print results: `text`, or `json` for scripts (for refresh, report, search, list and can-i)
+    #[clap(long, global = true, env = "OKTAWS_OUTPUT", default_value_t)]
+    output: OutputFormat,
+
+    /// When Okta is throttling sign-ins or has locked the account out, count down until it says to try again,
+    /// then sign in again (for up to an hour), instead of failing
+    #[clap(long, global = true, env = "OKTAWS_WAIT")]
+    wait: bool,
+
+    /// Never open a browser: print URLs (with a QR code) instead. Browsers are not opened over SSH or without a display either
+    #[clap(long = "no-browser", global = true, env = "OKTAWS_NO_BROWSER")]
+    no_browser: bool,
+
+    /// Never write to the oktaws config or state directories, keeping state and caches in a scratch directory
+    /// (in memory where possible) until oktaws exits. Automatic when the state directory cannot be written to
+    #[clap(long = "read-only", global = true, env = "OKTAWS_READ_ONLY")]
+    read_only: bool,
+
+    /// Show expiration times in UTC, instead of local time
+    #[clap(long, global = true)]
+    utc: bool,
+
+    /// Show expiration times as RFC 3339 timestamps, for scripts
+    #[clap(long, global = true, conflicts_with = "utc")]
+    rfc3339: bool,
+
+    /// Write a folded-stack timing profile (for inferno or speedscope) to this file
+    #[clap(long = "profile-timing", global = true)]
+    profile_timing: Option<PathBuf>,
+
+    /// Additional regex to mask in log output (tokens, cookies and credentials are always masked)
+    #[clap(long, global = true)]
+    redact: Vec<String>,
+
+    /// Ask for passwords and MFA codes with this program (as for `SSH_ASKPASS`), e.g. when oktaws runs over SSH.
+    /// Without it, `SSH_ASKPASS` is used when there is no terminal
+    #[clap(long, global = true, env = "OKTAWS_ASKPASS")]
+    askpass: Option<String>,
+
+    /// Keep Okta sessions and AWS SSO tokens in files (readable only by you) instead of the OS keyring
+    #[clap(long = "no-keyring", global = true, env = "OKTAWS_NO_KEYRING")]
+    no_keyring: bool,
+
+    /// Where to keep the Okta password, sessions and tokens: auto (the keyring, falling back to an encrypted file,
+    /// then nowhere), keyring, encrypted-file, file or none (prompting every time)
+    #[clap(
+        long = "secret-store",
+        global = true,
+        env = "OKTAWS_SECRET_STORE",
+        conflicts_with = "no_keyring"
+    )]
+    secret_store: Option<SecretStore>,
+
+    /// Trust the CA certificates in this PEM file as well as the usual roots, e.g. for a TLS-intercepting proxy
+    #[clap(long = "ca-bundle", global = true, env = "OKTAWS_CA_BUNDLE")]
+    ca_bundle: Option<PathBuf>,
+
+    /// Skip TLS certificate verification. Only for diagnosing proxies; anything on the network can read your credentials
+    #[clap(long, global = true, env = "OKTAWS_INSECURE")]
+    insecure: bool,
+
+    /// Run everything on one thread (prompts and keyring access still use a blocking thread)
+    #[clap(long = "single-thread", global = true)]
+    single_thread: bool,
+
     #[clap(subcommand)]
     cmd: Option<Command>,
 
@@ -40,38 +197,222 @@ enum Command {
 
     /// Generate an organization.toml configuration
     Init(InitArgs),
+
+    /// Print an inventory of the AWS accounts and roles available through Okta
+    Report(ReportArgs),
+
+    /// Search discovered accounts and roles, printing how to use each match
+    Search(SearchArgs),
+
+    /// List enrolled MFA factors, and the factors required by each organization's policy
+    Factors(FactorsArgs),
+
+    /// Print a URL which signs in to the AWS console for a profile
+    Console(ConsoleArgs),
+
+    /// Run a command with a profile's credentials, optionally refreshing them while it runs
+    Exec(ExecArgs),
+
+    /// Start $SHELL with a profile's credentials, and the profile in its prompt, until it exits
+    Shell(ShellArgs),
+
+    /// Print a profile's credentials as JSON, for the AWS SDKs' `credential_process`
+    CredentialProcess(CredentialProcessArgs),
+
+    /// Keep profiles' credentials fresh in the background, refreshing each shortly before it expires
+    Daemon(DaemonArgs),
+
+    /// Serve profiles' credentials over HTTP, as the ECS container credentials endpoint does
+    Serve(ServeArgs),
+
+    /// Check how quickly (and whether) Okta and AWS are responding
+    Ping(PingArgs),
+
+    /// Report what each Okta organization supports: its sign-in pipeline, enrolled factors and device trust
+    Doctor(DoctorArgs),
+
+    /// Measure the refresh pipeline's latency and throughput against a mock backend
+    Bench(BenchArgs),
+
+    /// Forget the cached Okta credentials (and the copies in sinks) for organizations, leaving other organizations signed in
+    Lock(LockArgs),
+
+    /// Manage MFA challenges
+    #[clap(subcommand)]
+    Mfa(MfaCommand),
+
+    /// Manage organization configs installed from a URL (with `init --from-url`)
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+
+    /// Move oktaws configuration to another machine
+    #[clap(subcommand)]
+    State(StateCommand),
+
+    /// List every configured profile, where its credentials come from, and whether they are valid (without refreshing any)
+    List(ListArgs),
+
+    /// Check whether a role in an account can be obtained now, without keeping any credentials for it
+    CanI(CanIArgs),
+
+    /// Get short-lived credentials for a privileged profile, for a reason kept in the audit log, removing them afterwards
+    Elevate(ElevateArgs),
+
+    /// Explain how a profile resolves: its organization, Okta tile, account, role, duration and where it is written
+    Explain(ExplainArgs),
+
+    /// Print every file and directory oktaws reads or writes
+    Paths,
+
+    /// Suggest profiles to remove, and roles to pin, based on how they have been used on this machine
+    Suggest(SuggestArgs),
+
+    /// Gather version details, sanitized configs, redacted logs and connectivity checks into a tarball for bug reports
+    SupportBundle(SupportBundleArgs),
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    color_eyre::install()?;
+impl Command {
+    /// Whether the command prints its results as JSON with --output json
+    const fn prints_json(&self) -> bool {
+        matches!(
+            self,
+            Self::Refresh(_) | Self::Report(_) | Self::Search(_) | Self::List(_) | Self::CanI(_)
+        )
+    }
+}
 
+fn main() -> Result<()> {
     let args = Args::parse();
 
+    let mut runtime = if args.single_thread {
+        tokio::runtime::Builder::new_current_thread()
+    } else {
+        tokio::runtime::Builder::new_multi_thread()
+    };
+
+    let result = runtime.enable_all().build()?.block_on(run(args));
+    read_only::clean_up();
+
+    // So that support tickets can point at the requests Okta or AWS failed
+    result.map_err(|error| {
+        let failed = request_id::failed();
+        if failed.is_empty() {
+            error
+        } else {
+            error.section(format!(
+                "Failed requests:\n{}",
+                failed
+                    .iter()
+                    .map(|request| format!("  {request}"))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ))
+        }
+    })
+}
+
+async fn run(args: Args) -> Result<()> {
+    let theme = Theme::resolve(args.theme)?;
+    theme.install();
+    askpass::install(args.askpass.clone());
+
+    // Commands load the settings again, and report them if they are broken
+    let configured = Settings::load().unwrap_or_default();
+    secrets::install(if args.no_keyring {
+        SecretStore::File
+    } else {
+        args.secret_store.unwrap_or(configured.secret_store)
+    });
+
+    TimestampFormat::from_flags(args.utc, args.rfc3339).install();
+    args.output.install();
+    lockout::install(args.wait);
+    browser::install(args.no_browser);
+
+    color_eyre::config::HookBuilder::default()
+        .theme(theme.eyre_theme())
+        .install()?;
+
     let filter =
         Targets::new().with_target(module_path!(), args.verbosity.log_level_filter().as_trace());
 
-    let subscriber = Registry::default()
-        .with(filter)
-        .with(HierarchicalLayer::new(2).with_targets(true));
+    let timing = args
+        .profile_timing
+        .as_deref()
+        .map(FoldedLayer::create)
+        .transpose()?
+        .map(|layer| layer.with_filter(Targets::new().with_target("oktaws", Level::TRACE)));
+
+    let redactor = Redactor::new(&args.redact)?;
+
+    let subscriber = Registry::default().with(timing).with(
+        HierarchicalLayer::new(2)
+            .with_writer(MakeRedacted::new(std::io::stderr, redactor.clone()))
+            .with_targets(true)
+            .with_ansi(theme.ansi())
+            .with_filter(filter),
+    );
     tracing::subscriber::set_global_default(subscriber)?;
 
+    Network {
+        ca_bundle: args.ca_bundle.clone().or(configured.network.ca_bundle),
+        insecure: args.insecure || configured.network.insecure,
+        ..configured.network
+    }
+    .install()?;
+    read_only::install(args.read_only)?;
+
+    if let Some(target) = &args.events {
+        events::install(target)?;
+    }
+
+    if output::json() && !args.cmd.as_ref().is_none_or(Command::prints_json) {
+        return Err(eyre!(
+            "--output json is only supported by refresh, report, search, list and can-i"
+        ));
+    }
+
     match args.cmd {
         Some(Command::Refresh(args)) => refresh(args).await,
+        Some(Command::Init(args)) if args.from_url.is_some() => init_from_url(args).await,
         Some(Command::Init(args)) => init(args.try_into()?).await,
+        Some(Command::Report(args)) => report(args).await,
+        Some(Command::Search(args)) => search(&args).await,
+        Some(Command::Factors(args)) => factors(args).await,
+        Some(Command::Console(args)) => console(args).await,
+        Some(Command::Exec(args)) => exec(args).await,
+        Some(Command::Shell(args)) => shell(args).await,
+        Some(Command::CredentialProcess(args)) => credential_process(&args).await,
+        Some(Command::Daemon(args)) => daemon(args).await,
+        Some(Command::Serve(args)) => serve(args).await,
+        Some(Command::Ping(args)) => ping(&args).await,
+        Some(Command::Doctor(args)) => doctor(&args).await,
+        Some(Command::Bench(args)) => bench(&args).await,
+        Some(Command::Lock(args)) => lock(&args).await,
+        Some(Command::Mfa(MfaCommand::Cancel(args))) => cancel_mfa(&args).await,
+        Some(Command::Mfa(MfaCommand::Totp(args))) => register_totp(&args).await,
+        Some(Command::Config(ConfigCommand::Update(args))) => update_config(&args).await,
+        Some(Command::State(StateCommand::Export(args))) => export_state(&args),
+        Some(Command::State(StateCommand::Import(args))) => import_state(&args),
+        Some(Command::List(args)) => list(&args).await,
+        Some(Command::CanI(args)) => can_i(&args).await,
+        Some(Command::Elevate(args)) => elevate(&args).await,
+        Some(Command::Explain(args)) => explain(&args).await,
+        Some(Command::Paths) => paths(),
+        Some(Command::Suggest(args)) => suggest(&args),
+        Some(Command::SupportBundle(args)) => support_bundle(&args, &redactor).await,
         None => refresh(args.default).await,
     }
 }
 
 #[derive(Parser, Debug)]
 struct RefreshArgs {
-    /// Okta organizations to use
-    #[clap(short, long, default_value = "*")]
-    pub organizations: OrganizationPattern,
+    /// Okta organizations to use (defaults to `organization` in settings.toml, then all)
+    #[clap(short, long)]
+    pub organizations: Option<OrganizationPattern>,
 
-    /// Profiles to update
-    #[clap(default_value = "*")]
-    pub profiles: Pattern,
+    /// Profiles to update (defaults to `profile` in settings.toml, then all)
+    pub profiles: Option<Pattern>,
 
     /// Role to override toml file with
     #[clap(short, long = "role-override")]
@@ -80,44 +421,723 @@ struct RefreshArgs {
     /// Forces new credentials
     #[clap(short, long = "force-new")]
     pub force_new: bool,
+
+    /// When a profile's AWS SSO role has been renamed, switch the profile's config to the new name
+    #[clap(long = "auto-migrate")]
+    pub auto_migrate: bool,
+
+    /// With --auto-migrate, also rename a role which is the organization's default, for every profile using it
+    #[clap(long = "migrate-defaults", requires = "auto_migrate")]
+    pub migrate_defaults: bool,
+
+    /// Request credentials which expire sooner (or later) than configured, e.g. `15m`
+    #[clap(long = "expires-in", value_parser = parse_duration)]
+    pub expires_in: Option<Duration>,
+
+    /// Sign in with the Okta session from a browser, instead of a password:
+    /// `firefox`, `chrome` or `chromium` for its profiles' cookie stores, or the path of one of those stores,
+    /// or of a cookies.txt (Netscape format) export
+    #[clap(long = "import-cookies", value_name = "BROWSER|PATH")]
+    pub import_cookies: Option<CookieSource>,
+
+    /// Sign in with an existing Okta session ID (the `sid` cookie), e.g. from another sign-in tool
+    #[clap(
+        long = "session-id",
+        env = "OKTA_SESSION_ID",
+        hide_env_values = true,
+        conflicts_with = "import_cookies"
+    )]
+    pub session_id: Option<String>,
+
+    /// The Okta organization which issued the session ID, e.g. `https://mycompany.okta.com`.
+    /// Other organizations are signed in to as usual. Needed when refreshing more than one organization
+    #[clap(
+        long = "session-url",
+        env = "OKTA_SESSION_URL",
+        requires = "session_id"
+    )]
+    pub session_url: Option<Url>,
+
+    /// Sign in with the system browser (for factors oktaws cannot verify, such as FastPass or device trust),
+    /// then paste the Okta session back
+    #[clap(long, conflicts_with_all = ["import_cookies", "session_id"])]
+    pub browser: bool,
+
+    /// How many times to try each profile, when Okta or AWS have transient errors
+    #[clap(long, default_value_t = 3)]
+    pub attempts: u32,
+
+    /// How long each attempt at a profile may take, e.g. `30s`
+    #[clap(long = "profile-timeout", default_value = "2m", value_parser = parse_duration)]
+    pub profile_timeout: Duration,
+
+    /// Fail if Okta cannot be reached, instead of keeping unexpired credentials (for CI)
+    #[clap(long)]
+    pub strict: bool,
+
+    /// How many profiles to fetch credentials for at once, across all organizations
+    #[clap(long, env = "OKTAWS_PARALLEL", default_value_t = parallel::DEFAULT)]
+    pub parallel: usize,
+}
+
+#[derive(Parser, Debug)]
+struct AssumeArgs {
+    /// Chain into this role ARN, using the profile's credentials
+    #[clap(long = "assume")]
+    pub role_arn: Option<String>,
+
+    /// Session name to use when chaining with --assume (defaults to oktaws-<username>)
+    #[clap(long = "session-name", requires = "role_arn")]
+    pub session_name: Option<String>,
+
+    /// Session duration to request when chaining with --assume, e.g. `1h`
+    #[clap(long = "assume-duration", requires = "role_arn", value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+}
+
+impl AssumeArgs {
+    fn chained_role(&self) -> Result<Option<ChainedRole>> {
+        self.role_arn
+            .as_ref()
+            .map(|role_arn| {
+                Ok(ChainedRole {
+                    role: role_arn.parse()?,
+                    session_name: self.session_name.clone(),
+                    external_id: None,
+                    duration_seconds: self
+                        .duration
+                        .map(|duration| i32::try_from(duration.as_secs()))
+                        .transpose()
+                        .map_err(|_| eyre!("--assume-duration is too long"))?,
+                })
+            })
+            .transpose()
+    }
 }
 
-#[instrument(skip_all, fields(organizations=%args.organizations,profiles=%args.profiles))]
+#[instrument(skip_all, fields(organizations=?args.organizations,profiles=?args.profiles))]
 async fn refresh(args: RefreshArgs) -> Result<()> {
-    // Set up a store for AWS profiles
-    let mut aws_credentials = ProfileStore::load(None)?;
+    transaction::recover(&state_dir()?)?;
+    close_elevations();
+    rename::install(args.auto_migrate, args.migrate_defaults);
+    parallel::install(args.parallel);
 
-    let organizations = args.organizations.organizations()?;
+    let settings = Settings::load()?;
+    settings.writes.install();
+    let organization_pattern = settings.organizations(args.organizations.as_ref())?;
+    let profiles = settings.profiles(args.profiles.as_ref())?;
 
-    if organizations.is_empty() {
+    // Set up stores for AWS profiles. Organizations can keep their credentials in a file of their own
+    // (e.g. to keep GovCloud credentials apart from commercial ones)
+    let mut credentials_stores: BTreeMap<(Option<PathBuf>, Layout), ProfileStore> = BTreeMap::new();
+    let mut aws_config = ConfigStore::load(None)?;
+
+    // Discovered profiles can overlap too, so organizations with a `profile_format` are signed in to and discovered first
+    let found = find_organizations(&organization_pattern).await?;
+    // A session only belongs to the organization which issued it, so it is never sent to the others
+    if args.session_id.is_some() && args.session_url.is_none() && found.len() > 1 {
         return Err(eyre!(
-            "No organizations found matching {}",
-            args.organizations
+            "The session ID can only be used with the organization which issued it. \
+             Pass --session-url (or set OKTA_SESSION_URL) to say which one, or refresh only that organization"
         ));
     }
 
+    let mut okta_clients = HashMap::new();
+    let mut organizations = Vec::new();
+    for mut organization in found {
+        if organization.profile_format.is_some() {
+            match discover_profiles(&mut organization, &args).await {
+                Ok(okta_client) => {
+                    okta_clients.insert(organization.name.clone(), okta_client);
+                }
+                Err(e) if !args.strict && is_outage(&e) => {
+                    // The profiles found by the last discovery keep their credentials too
+                    add_catalog_profiles(&mut organization)?;
+                    keep_cached_credentials(
+                        credentials_store(&mut credentials_stores, &organization)?,
+                        &organization,
+                        &profiles,
+                        e,
+                    )?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        organizations.push(organization);
+    }
+
+    // An account and role (or a section) defined by several organizations (e.g. a team's config and a personal one)
+    // is refreshed by one of them
+    let overlaps = overlap::resolve(&mut organizations, &profiles);
+    for overlap in &overlaps {
+        // Neither is preferred, so refreshing either would silently overwrite the other's credentials
+        if overlap.tied {
+            return Err(eyre!(
+                "{} is defined by {} and {}. {} in one of their configs to choose which refreshes it",
+                overlap.what,
+                overlap.kept,
+                overlap.dropped.join(", "),
+                if overlap.section {
+                    "Give their profiles a `[namespace]`, or set `priority`"
+                } else {
+                    "Set `priority`"
+                }
+            ));
+        } else {
+            info!(
+                "{} is defined by {} and {}; refreshing it with {}, which has the highest priority",
+                overlap.what,
+                overlap.kept,
+                overlap.dropped.join(", "),
+                overlap.kept
+            );
+        }
+    }
+    // Organizations left with nothing to refresh needn't be signed in to
+    organizations.retain(|organization| {
+        overlaps
+            .iter()
+            .all(|overlap| !overlap.dropped.contains(&organization.name))
+            || organization
+                .profiles
+                .iter()
+                .any(|profile| profiles.matches(&profile.name))
+    });
+
+    let budget = Budget {
+        attempts: args.attempts.max(1),
+        timeout: args.profile_timeout,
+    };
+
+    // Simultaneous refreshes of the same profile (e.g. from several shells) only hit the portal once
+    let coalescer = Coalescer::new(cache_dir()?.join(coalesce::DIR));
+
+    // Sections oktaws has written before, so that those from older versions can be adopted
+    let mut registry = SectionRegistry::load(&state_dir()?.join(registry::FILE_NAME))?;
+
+    // Copies for each organization's extra sinks, sent once the credentials file is written
+    let mut deliveries = Vec::new();
+    // What was refreshed, for --output json
+    let mut refreshed = Vec::new();
+
+    let duration_override = args
+        .expires_in
+        .map(|expires_in| i32::try_from(expires_in.as_secs()))
+        .transpose()
+        .map_err(|_| eyre!("--expires-in is too long"))?;
+
+    // Signing in can prompt (for passwords, MFA and adopting sections), so organizations are signed in to one at a time
+    let mut signed_in = Vec::new();
     for organization in organizations {
-        let okta_client = OktaClient::new(
-            organization.name.clone(),
-            organization.username.clone(),
-            args.force_new,
+        let aws_credentials = credentials_store(&mut credentials_stores, &organization)?;
+
+        let okta_client = match okta_clients.remove(&organization.name) {
+            Some(okta_client) => okta_client,
+            None => match okta_client(&organization, &args).await {
+                Ok(okta_client) => okta_client,
+                Err(e) if !args.strict && is_outage(&e) => {
+                    keep_cached_credentials(aws_credentials, &organization, &profiles, e)?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            },
+        };
+
+        let mut skipped = adopt_sections(aws_credentials, &mut registry, &organization, &profiles)?;
+        skipped.extend(elevated_sections(&organization)?);
+        signed_in.push((organization, okta_client, skipped));
+    }
+
+    // Then every organization's profiles are fetched at once, within the --parallel limit
+    let fetched = join_all(signed_in.iter().map(|(organization, okta_client, _)| {
+        organization.clone().into_credentials(
+            okta_client,
+            profiles.clone(),
+            args.role_override.as_ref(),
+            duration_override,
+            budget,
+            Some(&coalescer),
         )
-        .await?;
+    }))
+    .await;
+    let issued = SystemTime::now();
 
-        let credentials_map = organization
-            .into_credentials(
-                &okta_client,
-                args.profiles.clone(),
-                args.role_override.as_ref(),
-            )
-            .await;
+    // And the results merged into the files one organization at a time
+    for ((organization, _, skipped), credentials_map) in signed_in.iter().zip(fetched) {
+        let organization_name = organization.name.clone();
+        let partition = organization.partition;
+        let aws_credentials = credentials_stores
+            .get_mut(&(
+                organization.credentials_file.clone(),
+                organization.credentials_layout,
+            ))
+            .ok_or_else(|| eyre!("No credentials file loaded for {organization_name}"))?;
+
+        let by_name = organization
+            .profiles
+            .iter()
+            .map(|profile| (profile.name.clone(), profile.clone()))
+            .collect::<HashMap<_, _>>();
+
+        let sinks = organization.sinks.clone();
+        let mut delivered = Vec::new();
 
         for (name, creds) in credentials_map {
-            aws_credentials.upsert_credential(&name, &creds)?;
+            if let Some(schedule) = Schedule::new(&name, &creds, issued) {
+                info!(
+                    "{name} ({partition}) expires at {}, refresh after {}",
+                    timestamp::render(schedule.expires_at),
+                    timestamp::render(schedule.refresh_at)
+                );
+            }
+
+            let profile = by_name.get(&name);
+            let credentials_profile =
+                profile.map_or_else(|| name.clone(), Profile::credentials_profile);
+
+            if skipped.contains(&credentials_profile) {
+                info!("Leaving {credentials_profile} as it is");
+                continue;
+            }
+
+            aws_credentials.upsert_credential(&credentials_profile, &creds)?;
+            registry.record(&credentials_profile, &organization_name);
+            if !sinks.is_empty() {
+                delivered.push((credentials_profile.clone(), creds.clone()));
+            }
+
+            // The SDKs chain into the role themselves, from the source profile's credentials
+            let mut settings = Vec::new();
+            if let Some(
+                chain @ Chain {
+                    mode: ChainMode::Sdk,
+                    ..
+                },
+            ) = profile.and_then(|profile| profile.chain.as_ref())
+            {
+                settings.extend(chain.sdk_settings(&credentials_profile));
+            }
+            if let Some(profile) = profile {
+                settings.extend(profile.cli.entries());
+            }
+            aws_config.upsert_settings(&name, &settings);
+
+            // Settings oktaws wrote before, which the profile no longer declares
+            let declared = settings
+                .iter()
+                .map(|(key, _)| (*key).to_string())
+                .collect::<Vec<_>>();
+            let undeclared = registry
+                .record_settings(&credentials_profile, declared.clone())
+                .into_iter()
+                .filter(|key| !declared.contains(key))
+                .collect::<Vec<_>>();
+            aws_config.remove_settings(
+                &name,
+                &undeclared.iter().map(String::as_str).collect::<Vec<_>>(),
+            );
+
+            if output::json() {
+                refreshed.push(refreshed_profile(
+                    &organization_name,
+                    &name,
+                    &credentials_profile,
+                    &creds,
+                    partition,
+                ));
+            }
+
+            events::emit(&Event::ProfileRefreshed {
+                organization: organization_name.clone(),
+                partition: partition.to_string(),
+                profile: name,
+                expires_at: creds.expiry().and_then(events::timestamp),
+            });
+        }
+
+        deliveries.push((organization_name, sinks, delivered));
+    }
+
+    if coalesce::coalesced() > 0 {
+        debug!(
+            "{} profile(s) reused credentials fetched by another oktaws process",
+            coalesce::coalesced()
+        );
+    }
+
+    // The credentials, config and registry must agree on which profiles exist, so are written together
+    // Waiting for the files' locks can take a while, so is done off the reactor
+    let state_dir = state_dir()?;
+    blocking::run(move || {
+        let mut files = credentials_stores
+            .values()
+            .map(|store| store as &dyn Staged)
+            .collect::<Vec<_>>();
+        files.extend([&aws_config as &dyn Staged, &registry]);
+        transaction::commit(&state_dir, &files)
+    })
+    .await?;
+
+    sink::deliver_all(&deliveries).await;
+
+    if output::json() {
+        output::print_json(&refreshed)?;
+    }
+
+    Ok(())
+}
+
+/// The credentials file the organization's credentials are written to, loading it if no other organization has
+fn credentials_store<'a>(
+    stores: &'a mut BTreeMap<(Option<PathBuf>, Layout), ProfileStore>,
+    organization: &Organization,
+) -> Result<&'a mut ProfileStore> {
+    Ok(
+        match stores.entry((
+            organization.credentials_file.clone(),
+            organization.credentials_layout,
+        )) {
+            BTreeEntry::Occupied(entry) => entry.into_mut(),
+            BTreeEntry::Vacant(entry) => entry.insert(ProfileStore::load_with(
+                organization.credentials_file.as_deref(),
+                organization.credentials_layout,
+            )?),
+        },
+    )
+}
+
+/// Sign in to an organization with a `profile_format`, and add a profile for every account the user can reach
+async fn discover_profiles(
+    organization: &mut Organization,
+    args: &RefreshArgs,
+) -> Result<OktaClient> {
+    let okta_client = okta_client(organization, args).await?;
+
+    let mappings = discover_with(&okta_client, organization, &DiscoveryScope::default()).await?;
+    organization.add_discovered_profiles(&mappings);
+
+    Ok(okta_client)
+}
+
+/// Add the profiles found by the organization's last discovery (kept in the catalog), for its `profile_format`.
+/// Commands which don't sign in to discover accounts see the same profiles as the last refresh.
+fn add_catalog_profiles(organization: &mut Organization) -> Result<()> {
+    if organization.profile_format.is_none() {
+        return Ok(());
+    }
+
+    if let Some(catalog) = discovery::load_catalog(
+        &cache_dir()?.join(discovery::CATALOG_DIR),
+        &organization.name,
+    )? {
+        organization.add_discovered_profiles(
+            &catalog
+                .iter()
+                .map(AppLinkAccountRoleMapping::from)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    Ok(())
+}
+
+/// The organizations matching `pattern`, with the profiles their last discovery found (see [`add_catalog_profiles`])
+async fn find_organizations_with_catalog(
+    pattern: &OrganizationPattern,
+) -> Result<Vec<Organization>> {
+    let mut organizations = find_organizations(pattern).await?;
+    for organization in &mut organizations {
+        add_catalog_profiles(organization)?;
+    }
+
+    Ok(organizations)
+}
+
+/// Describe a refreshed profile for --output json, with the account and role its credentials were issued for
+/// where the request which fetched them said (credentials shared by another run's fetch leave them out)
+fn refreshed_profile(
+    organization: &str,
+    profile: &str,
+    section: &str,
+    credentials: &Credentials,
+    partition: Partition,
+) -> Refreshed {
+    let identity = issued_to(credentials);
+
+    Refreshed {
+        organization: organization.to_string(),
+        profile: profile.to_string(),
+        section: section.to_string(),
+        partition: partition.to_string(),
+        account_id: identity
+            .as_ref()
+            .map(|identity| identity.account_id.clone()),
+        arn: identity.and_then(|identity| identity.arn),
+        expires_at: credentials.expiry().and_then(events::timestamp),
+    }
+}
+
+/// Find credentials sections for the organization's profiles which were written by an older version of oktaws,
+/// and ask whether oktaws should take them over.
+/// Returns the sections the user chose to leave alone; when not interactive, every section is adopted.
+fn adopt_sections(
+    store: &ProfileStore,
+    registry: &mut SectionRegistry,
+    organization: &Organization,
+    profiles: &Pattern,
+) -> Result<HashSet<String>> {
+    let sections = organization
+        .profiles
+        .iter()
+        .filter(|profile| profiles.matches(&profile.name))
+        .map(Profile::credentials_profile)
+        .collect::<Vec<_>>();
+
+    // Those the user chose to leave alone before are not asked about again
+    let mut skipped = sections
+        .iter()
+        .filter(|section| registry.is_left_alone(section))
+        .cloned()
+        .collect::<HashSet<_>>();
+
+    let unadopted = registry.unadopted(store, sections.iter().map(String::as_str));
+    if unadopted.is_empty() {
+        return Ok(skipped);
+    }
+
+    warn!(
+        "{} credentials section(s) for {} were written by an older version of oktaws: {}",
+        unadopted.len(),
+        organization.name,
+        unadopted.join(", ")
+    );
+
+    if !stdin().is_terminal() {
+        warn!("Adopting them; they will be refreshed by this version from now on");
+        return Ok(skipped);
+    }
+
+    for section in unadopted {
+        let adopt = dialoguer::Confirm::with_theme(theme::prompt())
+            .with_prompt(format!("Let oktaws manage [{section}] from now on?"))
+            .default(true)
+            .interact()?;
+
+        if !adopt {
+            registry.leave_alone(&section, &organization.name);
+            skipped.insert(section);
+        }
+    }
+
+    Ok(skipped)
+}
+
+/// Sign in to the organization, with a password, an imported browser session, an existing session ID,
+/// or through the browser
+async fn okta_client(organization: &Organization, args: &RefreshArgs) -> Result<OktaClient> {
+    if args.browser {
+        let okta_client =
+            OktaClient::from_browser(&organization.portal, organization.username.clone()).await?;
+
+        // Later refreshes can reuse the session, rather than going back to the browser
+        if let Err(e) =
+            silent::remember(&okta_client, organization, &state_dir()?.join(silent::DIR)).await
+        {
+            warn!(
+                "Unable to remember the Okta session for {}: {e}",
+                organization.name
+            );
+        }
+
+        // A browser session is how a blocked password sign-in is remedied, so it refreshes the factors which blocked it
+        record_factors(organization, &okta_client).await;
+
+        return Ok(okta_client);
+    }
+
+    let session_id = args.session_id.as_ref().filter(|_| {
+        args.session_url
+            .as_ref()
+            .is_none_or(|url| issued_by(url, &organization.portal))
+    });
+
+    let okta_client = match (&args.import_cookies, session_id) {
+        (Some(source), _) => {
+            confirm_cookie_import(&organization.name, source)?;
+            OktaClient::from_cookies(&organization.portal, organization.username.clone(), source)
+                .await?
+        }
+        (None, Some(session_id)) => {
+            OktaClient::from_session_id(
+                &organization.portal,
+                organization.username.clone(),
+                session_id,
+            )
+            .await?
+        }
+        (None, None) => return sign_in(organization, args.force_new).await,
+    };
+
+    record_factors(organization, &okta_client).await;
+
+    Ok(okta_client)
+}
+
+/// Whether a session from `url` belongs to the organization at `portal`
+fn issued_by(url: &Url, portal: &Portal) -> bool {
+    url.host_str().is_some_and(|host| {
+        portal
+            .base_url()
+            .host_str()
+            .is_some_and(|base| base.eq_ignore_ascii_case(host))
+    })
+}
+
+/// Sign in to the organization with a password, unless the session from the last sign-in is still valid
+/// (and the organization's silent refresh policy, if any, allows reusing it).
+/// When a policy doesn't, and there is no terminal to prompt on, this fails rather than waiting for interaction.
+async fn sign_in(organization: &Organization, force_new: bool) -> Result<OktaClient> {
+    let sessions = state_dir()?.join(silent::DIR);
+    let policy = organization.silent_refresh.clone();
+
+    if !force_new {
+        match silent::sign_in(&policy.clone().unwrap_or_default(), organization, &sessions).await {
+            Ok(okta_client) => return Ok(okta_client),
+            Err(reason) if policy.is_some() && !askpass::interactive() => {
+                return Err(eyre!(
+                    "Unable to refresh {} without interaction, as {reason}",
+                    organization.name
+                ));
+            }
+            Err(reason) if policy.is_some() => {
+                info!("Signing in to {}, as {reason}", organization.name);
+            }
+            Err(reason) => debug!("Signing in to {}, as {reason}", organization.name),
+        }
+    }
+
+    // Capabilities only steer sign-in, so one which can't be probed doesn't stop it
+    let capabilities_dir = cache_dir()?.join(capabilities::DIR);
+    let known = match capabilities::cached(&organization.portal, &capabilities_dir, false).await {
+        Ok(capabilities) => Some(capabilities),
+        Err(e) => {
+            debug!("Unable to probe {}: {e}", organization.name);
+            None
+        }
+    };
+
+    if let Some(reason) = known
+        .as_ref()
+        .and_then(|capabilities| capabilities.password_sign_in_blocker(Utc::now()))
+    {
+        if !force_new {
+            return Err(eyre!(
+                "Unable to sign in to {} with a password, as {reason}. \
+                 Sign in with a browser session instead (--browser, --import-cookies or --session-id), \
+                 or with --force-new if the organization's policy has changed",
+                organization.name
+            ));
+        }
+    }
+
+    let okta_client = OktaClient::new(
+        &organization.portal,
+        organization.username.clone(),
+        force_new,
+    )
+    .await?;
+
+    // Without the session, the next refresh just signs in again
+    if let Err(e) = silent::remember(&okta_client, organization, &sessions).await {
+        warn!(
+            "Unable to remember the Okta session for {}: {e}",
+            organization.name
+        );
+    }
+
+    record_factors(organization, &okta_client).await;
+
+    Ok(okta_client)
+}
+
+/// Record the factors the user is enrolled in now, so that sign-in is never blocked by ones enrolled in since
+async fn record_factors(organization: &Organization, okta_client: &OktaClient) {
+    let recorded = match cache_dir() {
+        Ok(dir) => {
+            capabilities::record_factors(
+                okta_client,
+                &organization.portal,
+                &dir.join(capabilities::DIR),
+            )
+            .await
         }
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = recorded {
+        debug!(
+            "Unable to record the enrolled factors for {}: {e}",
+            organization.name
+        );
+    }
+}
+
+/// Keep the existing credentials for the profiles of an organization which is unreachable (or failing),
+/// as long as none have expired,
+/// warning how much longer each one is valid for
+fn keep_cached_credentials(
+    store: &ProfileStore,
+    organization: &Organization,
+    profiles: &Pattern,
+    error: eyre::Report,
+) -> Result<()> {
+    let now = SystemTime::now();
+
+    for profile in organization.clone().into_profiles(profiles.clone()) {
+        let Some(remaining) = store
+            .expiry(&profile.credentials_profile())
+            .and_then(|expiry| expiry.duration_since(now).ok())
+        else {
+            return Err(error.wrap_err(format!(
+                "Unable to sign in to {}, and there are no unexpired credentials for {}",
+                organization.name, profile.name
+            )));
+        };
+
+        warn!(
+            "Unable to sign in to {} ({error}), keeping stale credentials for {} (valid for another {})",
+            organization.name,
+            profile.name,
+            timestamp::duration(remaining)
+        );
     }
 
-    aws_credentials.save()
+    Ok(())
+}
+
+/// Make sure the user knows what importing a browser session means before doing it
+fn confirm_cookie_import(organization: &str, source: &CookieSource) -> Result<()> {
+    eprintln!("oktaws will read your Okta session for {organization} from {source}.");
+    match source {
+        CookieSource::Browser(_) => eprintln!(
+            "oktaws keeps using the session after that, until it expires or you sign out of Okta in the browser."
+        ),
+        CookieSource::File(_) => eprintln!(
+            "Anyone who can read that file can sign in as you. If it is an export, delete it once you are done."
+        ),
+    }
+
+    let proceed = dialoguer::Confirm::with_theme(theme::prompt())
+        .with_prompt("Use this browser session?")
+        .default(false)
+        .interact()?;
+
+    if proceed {
+        Ok(())
+    } else {
+        Err(eyre!("Cookie import cancelled"))
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -132,12 +1152,37 @@ struct InitArgs {
     /// Forces new credentials
     #[structopt(short, long = "force-new")]
     force_new: bool,
+
+    /// Choose which accounts get profiles, from a searchable list, rather than adding them all
+    #[structopt(long)]
+    pick: bool,
+
+    /// How to name profiles, from `{account}`, `{account_id}`, `{role}` and `{application}` (e.g. `{account}-{role}` for a profile per role).
+    /// Prompted for if not given
+    #[structopt(long)]
+    naming: Option<NamingTemplate>,
+
+    /// Install a team-maintained organization config from this URL, instead of generating one.
+    /// The organization is named after the file, unless it is given
+    #[structopt(long = "from-url")]
+    from_url: Option<Url>,
+
+    /// The SHA-256 digest (in hex) the config downloaded with --from-url must have
+    #[structopt(long, requires = "from_url")]
+    sha256: Option<String>,
+
+    /// An Ed25519 public key (in base64) which must have signed the config downloaded with --from-url.
+    /// The signature is fetched from the same URL, with `.sig` added. It is checked again by `oktaws config update`
+    #[structopt(long = "public-key", requires = "from_url")]
+    public_key: Option<String>,
 }
 
 struct Init {
     organization: String,
     username: String,
     force_new: bool,
+    pick: bool,
+    naming: NamingTemplate,
 }
 
 impl TryFrom<InitArgs> for Init {
@@ -146,7 +1191,7 @@ impl TryFrom<InitArgs> for Init {
     fn try_from(args: InitArgs) -> Result<Self, Self::Error> {
         let organization = args.organization.map_or_else(
             || {
-                dialoguer::Input::new()
+                dialoguer::Input::with_theme(theme::prompt())
                     .with_prompt("Okta Organization Name")
                     .interact_text()
             },
@@ -155,7 +1200,7 @@ impl TryFrom<InitArgs> for Init {
 
         let username = args.username.map_or_else(
             || {
-                dialoguer::Input::<String>::new()
+                dialoguer::Input::<String>::with_theme(theme::prompt())
                     .with_prompt(format!("Username for {organization}"))
                     .default(username())
                     .interact_text()
@@ -163,10 +1208,30 @@ impl TryFrom<InitArgs> for Init {
             Ok,
         )?;
 
+        let naming = match args.naming {
+            Some(naming) => naming,
+            None => {
+                let choice = dialoguer::Select::with_theme(theme::prompt())
+                    .with_prompt("Profile names")
+                    .items(
+                        &naming::PRESETS
+                            .iter()
+                            .map(|(template, description)| format!("{template}: {description}"))
+                            .collect::<Vec<_>>(),
+                    )
+                    .default(0)
+                    .interact()?;
+
+                naming::PRESETS[choice].0.parse()?
+            }
+        };
+
         Ok(Self {
             organization,
             username,
             force_new: args.force_new,
+            pick: args.pick,
+            naming,
         })
     }
 }
@@ -174,14 +1239,19 @@ impl TryFrom<InitArgs> for Init {
 /// Output a config toml for a given organization
 async fn init(options: Init) -> Result<()> {
     let okta_client = OktaClient::new(
-        options.organization.clone(),
+        &Portal::new(&options.organization)?,
         options.username.clone(),
         options.force_new,
     )
     .await?;
 
-    let organization_config =
-        OrganizationConfig::from_organization(&okta_client, options.username).await?;
+    let organization_config = OrganizationConfig::from_organization(
+        &okta_client,
+        options.username,
+        options.pick,
+        &options.naming,
+    )
+    .await?;
 
     let org_toml = toml::to_string_pretty(&organization_config)?;
 
@@ -190,14 +1260,2391 @@ async fn init(options: Init) -> Result<()> {
     let oktaws_home = oktaws_home()?;
     let oktaws_config_path = oktaws_home.join(format!("{}.toml", options.organization));
 
-    let write_to_file = dialoguer::Confirm::new()
+    let write_to_file = dialoguer::Confirm::with_theme(theme::prompt())
         .with_prompt(format!("Write config to {}?", oktaws_config_path.display()))
         .interact()?;
 
     if write_to_file {
-        std::fs::create_dir_all(oktaws_home)?;
+        create_oktaws_home()?;
         std::fs::write(oktaws_config_path, org_toml)?;
     }
 
     Ok(())
 }
+
+/// Install a team-maintained organization config, downloaded and verified
+async fn init_from_url(args: InitArgs) -> Result<()> {
+    let url = args
+        .from_url
+        .ok_or_else(|| eyre!("No URL to install an organization config from"))?;
+    let organization = args
+        .organization
+        .or_else(|| shared::organization_name(&url))
+        .ok_or_else(|| eyre!("Unable to name the organization from {url}; give its name too"))?;
+
+    if args.sha256.is_none() && args.public_key.is_none() {
+        warn!("Installing {url} without verifying it; pin it with --sha256 or --public-key");
+    }
+
+    let source = SharedSource {
+        url,
+        public_key: args.public_key,
+    };
+    let contents = source.fetch(args.sha256.as_deref()).await?;
+
+    let username = match args.username {
+        Some(username) => username,
+        None => prompt_username(&organization)?,
+    };
+    let contents = shared::with_username(&contents, &username)?;
+
+    let path = create_oktaws_home()?.join(format!("{organization}.toml"));
+    shared::validate(&path, &contents)?;
+
+    if path.exists() {
+        if !stdin().is_terminal() {
+            return Err(eyre!(
+                "{} already exists; move it aside first, or run `oktaws config update` if it was installed from a URL",
+                path.display()
+            ));
+        }
+
+        let replace = dialoguer::Confirm::with_theme(theme::prompt())
+            .with_prompt(format!("Replace {}?", path.display()))
+            .interact()?;
+        if !replace {
+            return Ok(());
+        }
+    }
+
+    std::fs::write(&path, contents)?;
+
+    let mut sources = SharedSources::load(&state_dir()?.join(shared::FILE_NAME))?;
+    sources.record(&organization, source);
+    sources.save()?;
+
+    eprintln!(
+        "Installed {organization} to {}; run `oktaws config update` to fetch it again",
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Find the organizations matching `pattern`.
+/// On first use (when no organization has been configured at all), offer to run `init` instead of failing.
+async fn find_organizations(pattern: &OrganizationPattern) -> Result<Vec<Organization>> {
+    if !is_configured()? {
+        first_use().await?;
+    }
+
+    let organizations = pattern.organizations()?;
+
+    if organizations.is_empty() {
+        Err(eyre!("No organizations found matching {pattern}"))
+    } else {
+        Ok(organizations)
+    }
+}
+
+/// Guide a new user through setting up their first organization,
+/// or tell them how to when there is no terminal to prompt on
+async fn first_use() -> Result<()> {
+    let oktaws_home = oktaws_home()?;
+
+    if !stdin().is_terminal() {
+        return Err(eyre!(
+            "No oktaws configuration found in {home}.
+To set up an Okta organization, run `oktaws init <organization>` in a terminal,
+or write {home}/<organization>.toml by hand (see https://github.com/jonathanmorley/oktaws#setup)",
+            home = oktaws_home.display()
+        ));
+    }
+
+    eprintln!(
+        "No oktaws configuration found in {}.",
+        oktaws_home.display()
+    );
+
+    let setup = dialoguer::Confirm::with_theme(theme::prompt())
+        .with_prompt("Set up an Okta organization now?")
+        .default(true)
+        .interact()?;
+
+    if !setup {
+        return Err(eyre!("Run `oktaws init` to set up an Okta organization"));
+    }
+
+    init(
+        InitArgs {
+            organization: None,
+            username: None,
+            force_new: false,
+            pick: false,
+            naming: None,
+            from_url: None,
+            sha256: None,
+            public_key: None,
+        }
+        .try_into()?,
+    )
+    .await
+}
+
+#[derive(Parser, Debug)]
+struct ReportArgs {
+    /// Okta organizations to use
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+
+    /// Output format (markdown or csv)
+    #[clap(short, long, default_value_t)]
+    pub format: ReportFormat,
+
+    /// Only discover accounts whose name matches this glob, e.g. `prod-*`
+    #[clap(long)]
+    pub filter: Option<Pattern>,
+
+    /// Stop discovering after this many accounts (per organization)
+    #[clap(long)]
+    pub limit: Option<usize>,
+}
+
+/// Output an inventory report of every account discovered through Okta
+#[instrument(skip_all, fields(organizations=%args.organizations))]
+async fn report(args: ReportArgs) -> Result<()> {
+    let organizations = find_organizations(&args.organizations).await?;
+    let scope = DiscoveryScope {
+        filter: args.filter,
+        limit: args.limit,
+    };
+
+    let mut entries = Vec::new();
+
+    for organization in organizations {
+        let mappings = discover(&organization, &scope).await?;
+
+        entries.extend(
+            mappings
+                .into_iter()
+                .map(|mapping| ReportEntry::from_mapping(&organization, mapping)),
+        );
+    }
+
+    if output::json() {
+        return output::print_json(&entries);
+    }
+    print!("{}", report::render(&entries, args.format));
+
+    Ok(())
+}
+
+/// Sign in, and discover the accounts and roles available through the organization's AWS tiles (see [`discover_with`])
+async fn discover(
+    organization: &Organization,
+    scope: &DiscoveryScope,
+) -> Result<Vec<AppLinkAccountRoleMapping>> {
+    let okta_client =
+        OktaClient::new(&organization.portal, organization.username.clone(), false).await?;
+
+    discover_with(&okta_client, organization, scope).await
+}
+
+/// Discover the accounts and roles available through the organization's AWS tiles, signed in as `okta_client`.
+/// A full discovery also announces new grants, and replaces the catalog `oktaws search`
+/// (and the commands which use `profile_format` profiles without discovering them) look in.
+async fn discover_with(
+    okta_client: &OktaClient,
+    organization: &Organization,
+    scope: &DiscoveryScope,
+) -> Result<Vec<AppLinkAccountRoleMapping>> {
+    let aws_links = okta_client
+        .app_links(None)
+        .await?
+        .into_iter()
+        .filter(AppLink::is_aws)
+        .collect();
+
+    let mappings = okta_client
+        .get_all_account_mappings(aws_links, &organization.account_naming, scope)
+        .await?;
+    let mut mappings = dedupe_account_mappings(mappings, &organization.application_precedence);
+    mappings.sort_by(|a, b| a.account_name.cmp(&b.account_name));
+
+    // A partial discovery would make every account outside it look newly granted (or gone) next time
+    if scope.is_everything() {
+        discovery::announce(
+            &state_dir()?.join(discovery::DIR),
+            &organization.name,
+            &mappings,
+        )?;
+        discovery::save_catalog(
+            &cache_dir()?.join(discovery::CATALOG_DIR),
+            &organization.name,
+            &mappings,
+        )?;
+    }
+
+    Ok(mappings)
+}
+
+#[derive(Parser, Debug)]
+struct SearchArgs {
+    /// Words to look for in account names, IDs, emails and descriptions, and role names, e.g. `payments prod`
+    pub query: String,
+
+    /// Okta organizations to search
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+
+    /// Discover accounts again, rather than searching those found by the last discovery
+    #[clap(long)]
+    pub refresh: bool,
+
+    /// Show at most this many matches
+    #[clap(long, default_value_t = 20)]
+    pub limit: usize,
+}
+
+/// Search the accounts and roles found by the last discovery of each organization,
+/// discovering any organization which has not been discovered yet
+#[instrument(skip_all, fields(organizations=%args.organizations, query=%args.query))]
+async fn search(args: &SearchArgs) -> Result<()> {
+    let catalog_dir = cache_dir()?.join(discovery::CATALOG_DIR);
+    let mut hits = Vec::new();
+
+    for organization in find_organizations(&args.organizations).await? {
+        let catalog = match discovery::load_catalog(&catalog_dir, &organization.name)? {
+            Some(catalog) if !args.refresh => catalog,
+            _ => discover(&organization, &DiscoveryScope::default())
+                .await?
+                .iter()
+                .map(CatalogEntry::from)
+                .collect(),
+        };
+
+        hits.extend(search::search(&organization, &catalog, &args.query));
+    }
+
+    if hits.is_empty() && !output::json() {
+        return Err(eyre!(
+            "No accounts or roles match {}; pass --refresh if they were granted recently",
+            args.query
+        ));
+    }
+
+    search::rank(&mut hits, args.limit);
+    if output::json() {
+        return output::print_json(&hits);
+    }
+    print!("{}", search::render(&hits));
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct FactorsArgs {
+    /// Okta organizations to use
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+}
+
+/// Output the enrolled factors (in the order oktaws will offer them) and the factor policy
+#[instrument(skip_all, fields(organizations=%args.organizations))]
+async fn factors(args: FactorsArgs) -> Result<()> {
+    let organizations = find_organizations(&args.organizations).await?;
+
+    for organization in organizations {
+        let okta_client =
+            OktaClient::new(&organization.portal, organization.username.clone(), false).await?;
+
+        println!("{}", organization.name);
+
+        println!("  Enrolled factors:");
+        for (index, factor) in okta_client.enrolled_factors().await?.iter().enumerate() {
+            let support = if factor.is_supported() {
+                ""
+            } else {
+                " (not supported by oktaws)"
+            };
+            println!("    {}. {factor}{support}", index + 1);
+        }
+
+        println!("  Policy:");
+        for factor in okta_client.factor_catalog().await? {
+            println!("    {factor}");
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct ConsoleArgs {
+    /// Okta organizations to use
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+
+    /// Profile to sign in as, or `@<group>` for a group of profiles
+    pub profile: Selector,
+
+    /// Open every selected profile's console in the browser, each in its own container
+    /// (firefox, using Multi-Account Containers, or chrome, using separate profiles)
+    #[clap(long, num_args = 0..=1, default_missing_value = "firefox")]
+    pub containers: Option<Browser>,
+
+    /// Command which starts the browser, with --containers
+    #[clap(long, requires = "containers")]
+    pub browser: Option<String>,
+
+    /// Role to override toml file with
+    #[clap(short, long = "role-override")]
+    pub role_override: Option<String>,
+
+    /// Switch the existing console session into the --assume role, instead of signing in again
+    #[clap(long = "switch-role", requires = "role_arn")]
+    pub switch_role: bool,
+
+    /// Name shown for the role in the console, with --switch-role (defaults to the profile name)
+    #[clap(long = "display-name", requires = "switch_role")]
+    pub display_name: Option<String>,
+
+    /// Colour for the role in the console, as 6 hex digits, with --switch-role
+    #[clap(long, requires = "switch_role")]
+    pub color: Option<String>,
+
+    /// Open this service's console (e.g. `cloudwatch`), instead of the profile's `console` page
+    #[clap(long, conflicts_with = "switch_role")]
+    pub service: Option<String>,
+
+    /// The region to open the service's console in, with --service
+    #[clap(long, requires = "service")]
+    pub region: Option<String>,
+
+    #[clap(flatten)]
+    pub assume: AssumeArgs,
+}
+
+/// Output a console sign-in URL for the first matching profile,
+/// or with `--containers`, open a console for every matching profile side by side
+#[instrument(skip_all, fields(organizations=%args.organizations,profile=%args.profile))]
+async fn console(args: ConsoleArgs) -> Result<()> {
+    let chained_role = args.assume.chained_role()?;
+
+    if args.switch_role {
+        let role = chained_role.ok_or_else(|| eyre!("--switch-role requires --assume"))?;
+        let display_name = args
+            .display_name
+            .unwrap_or_else(|| args.profile.to_string());
+
+        println!(
+            "{}",
+            console::switch_role_url(&role.role, Some(&display_name), args.color.as_deref())?
+        );
+        return Ok(());
+    }
+
+    let organizations = find_organizations_with_catalog(&args.organizations).await?;
+    if chained_role.is_some() {
+        single_match(
+            organizations
+                .iter()
+                .map(|organization| organization.select(&args.profile).len())
+                .sum(),
+            &args.profile,
+            "--assume",
+        )?;
+    }
+
+    let mut opened = 0;
+
+    for organization in organizations {
+        let mut names = organization.select(&args.profile);
+        if names.is_empty() {
+            continue;
+        }
+        if args.containers.is_none() {
+            names.truncate(1);
+        }
+
+        let okta_client =
+            OktaClient::new(&organization.portal, organization.username.clone(), false).await?;
+        let mut usage = Usage::load(&state_dir()?.join(usage::FILE_NAME))?;
+
+        for name in names {
+            let credentials = organization
+                .clone()
+                .into_credentials(
+                    &okta_client,
+                    Pattern::new(&Pattern::escape(&name))?,
+                    args.role_override.as_ref(),
+                    None,
+                    Budget::default(),
+                    None,
+                )
+                .await
+                .next();
+
+            // Failures have already been logged, so carry on with the other profiles
+            let Some((name, credentials)) = credentials else {
+                continue;
+            };
+            usage.record_profile(&organization.name, &name);
+
+            let (credentials, partition) = match &chained_role {
+                Some(role) => {
+                    usage.record_assumed(&role.role.to_string());
+                    let partition = Partition::of(&role.role)?;
+                    let credentials = role
+                        .assume(sts_client_with_credentials(credentials, partition))
+                        .await?;
+                    (credentials, partition)
+                }
+                None => (credentials, organization.partition),
+            };
+
+            let page = match &args.service {
+                Some(service) => Some(ConsolePage {
+                    service: service.clone(),
+                    region: args.region.clone(),
+                }),
+                None => organization
+                    .profiles
+                    .iter()
+                    .find(|profile| profile.name == name)
+                    .and_then(|profile| profile.console.clone()),
+            };
+
+            let url = console::federation_url(&credentials, partition, page.as_ref()).await?;
+            match args.containers {
+                Some(browser) => {
+                    browser.open(
+                        args.browser.as_deref(),
+                        &name,
+                        &url,
+                        &state_dir()?.join(browser::DIR),
+                    )?;
+                    info!("Opened {name} in its own {browser} container");
+                }
+                // Scripts reading the URL get it as before; people get the browser (or a QR code without one)
+                None if stdout().is_terminal() => {
+                    browser::show(&url, &format!("sign in to the console as {name}"));
+                }
+                None => println!("{url}"),
+            }
+            opened += 1;
+        }
+
+        blocking::run(move || usage.save()).await?;
+
+        if opened > 0 && args.containers.is_none() {
+            return Ok(());
+        }
+    }
+
+    if opened == 0 {
+        Err(eyre!("No profiles found matching {}", args.profile))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that `selection` matched exactly one profile, for what `needs` one
+/// (e.g. `--assume`, which chains a single profile into its role)
+fn single_match(matched: usize, selection: &dyn Display, needs: &str) -> Result<()> {
+    match matched {
+        1 => Ok(()),
+        0 => Err(eyre!("No profiles found matching {selection}")),
+        _ => Err(eyre!(
+            "{matched} profiles match {selection}, but {needs} needs exactly one"
+        )),
+    }
+}
+
+#[derive(Parser, Debug)]
+struct ExecArgs {
+    /// Okta organizations to use (defaults to `organization` in settings.toml, then all)
+    #[clap(short, long)]
+    pub organizations: Option<OrganizationPattern>,
+
+    /// Profile to run the command as (defaults to `profile` in settings.toml)
+    pub profile: Option<Pattern>,
+
+    /// Refresh the credentials in the background before they expire, for as long as the command runs
+    #[clap(long = "keep-fresh")]
+    pub keep_fresh: bool,
+
+    /// Pass the credentials in the command's environment, rather than a private credentials file
+    #[clap(long, conflicts_with = "keep_fresh")]
+    pub env: bool,
+
+    /// Pass the credentials in a memory file only the command inherits (Linux only),
+    /// rather than a private credentials file or the environment, which other processes can read
+    #[clap(long, conflicts_with_all = ["keep_fresh", "env"])]
+    pub memfd: bool,
+
+    /// The region to set as `AWS_REGION` (defaults to the profile's `region` in ~/.aws/config, if any)
+    #[clap(long)]
+    pub region: Option<String>,
+
+    /// Fetch new credentials, rather than reusing unexpired ones from an earlier run
+    #[clap(short, long)]
+    pub force: bool,
+
+    #[clap(flatten)]
+    pub assume: AssumeArgs,
+
+    /// The command to run, and its arguments
+    #[clap(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+/// Run a command with the first matching profile's credentials, exiting with its exit code
+#[instrument(skip_all, fields(organizations=?args.organizations, profile=?args.profile))]
+async fn exec(args: ExecArgs) -> Result<()> {
+    let settings = Settings::load()?;
+    let organization_pattern = settings.organizations(args.organizations.as_ref())?;
+    let profile = settings.profiles(args.profile.as_ref())?;
+
+    // Unexpired credentials from an earlier run are reused, without signing in
+    let coalescer = Coalescer::new(cache_dir()?.join(coalesce::DIR)).reusing(!args.force);
+
+    let organizations = find_organizations_with_catalog(&organization_pattern).await?;
+
+    // Without a profile (or a default one), every profile matches, and which ran the command would be down to chance
+    single_match(
+        organizations
+            .iter()
+            .flat_map(|organization| &organization.profiles)
+            .filter(|candidate| profile.matches(&candidate.name))
+            .count(),
+        &profile,
+        "exec",
+    )?;
+
+    let chained_role = args.assume.chained_role()?;
+
+    for organization in organizations {
+        let Some((name, key)) = organization
+            .profiles
+            .iter()
+            .find(|candidate| profile.matches(&candidate.name))
+            .map(|profile| (profile.name.clone(), coalesce::key(profile, None)))
+        else {
+            continue;
+        };
+
+        // Only signed in to once credentials need fetching
+        let okta_client = OnceCell::new();
+
+        let filter = Pattern::new(&Pattern::escape(&name))?;
+        let (organization, okta_client, filter, name) =
+            (&organization, &okta_client, &filter, &name);
+        let fetch = move || async move {
+            let okta_client = okta_client
+                .get_or_try_init(|| sign_in(organization, false))
+                .await?;
+
+            organization
+                .clone()
+                .into_credentials(
+                    okta_client,
+                    filter.clone(),
+                    None,
+                    None,
+                    Budget::default(),
+                    None,
+                )
+                .await
+                .next()
+                .map(|(_, credentials)| credentials)
+                .ok_or_else(|| eyre!("Unable to fetch credentials for {name}"))
+        };
+
+        // The profile's own credentials are the ones reused; the role is chained into afresh each time
+        let chained_role = chained_role.as_ref();
+        let chain = move |credentials: Credentials| async move {
+            match chained_role {
+                Some(role) => {
+                    role.assume(sts_client_with_credentials(
+                        credentials,
+                        Partition::of(&role.role)?,
+                    ))
+                    .await
+                }
+                None => Ok(credentials),
+            }
+        };
+        let refresh = move || async move { chain(fetch().await?).await };
+
+        let issued = SystemTime::now();
+        let credentials = chain(
+            coalescer
+                .credentials(&organization.name, &key, fetch)
+                .await?,
+        )
+        .await?;
+
+        let mut usage = Usage::load(&state_dir()?.join(usage::FILE_NAME))?;
+        usage.record_profile(&organization.name, name);
+        if let Some(role) = chained_role {
+            usage.record_assumed(&role.role.to_string());
+        }
+        blocking::run(move || usage.save()).await?;
+
+        let (program, program_args) = args
+            .command
+            .split_first()
+            .ok_or_else(|| eyre!("No command given"))?;
+        let region = match &args.region {
+            Some(region) => Some(region.clone()),
+            None => ConfigStore::load(None)?
+                .get(name, "region")
+                .map(ToString::to_string),
+        };
+
+        if args.env {
+            let status = exec::command_with_environment(
+                program,
+                program_args,
+                &credentials,
+                region.as_deref(),
+            )
+            .status()
+            .await?;
+
+            read_only::clean_up();
+            std::process::exit(exec::exit_code(status));
+        }
+
+        if args.memfd {
+            #[cfg(target_os = "linux")]
+            {
+                let fd = CredentialsFd::new(name, &credentials)?;
+                let mut command = fd.command(program, program_args);
+                if let Some(region) = &region {
+                    command.env("AWS_REGION", region);
+                }
+
+                let mut child = command.kill_on_drop(true).spawn()?;
+                // Only the command keeps the credentials open
+                drop(fd);
+                let status = child.wait().await?;
+
+                read_only::clean_up();
+                std::process::exit(exec::exit_code(status));
+            }
+
+            #[cfg(not(target_os = "linux"))]
+            return Err(eyre!("--memfd is only supported on Linux"));
+        }
+
+        let session = ExecSession::create(&cache_dir()?.join(exec::DIR), name)?;
+        session.write(&credentials)?;
+
+        let mut command = session.command(program, program_args);
+        if let Some(region) = &region {
+            command.env("AWS_REGION", region);
+        }
+
+        let status = session
+            .run(
+                command,
+                &credentials,
+                issued,
+                args.keep_fresh.then_some(refresh),
+            )
+            .await?;
+        drop(session);
+
+        read_only::clean_up();
+        std::process::exit(exec::exit_code(status));
+    }
+
+    Err(eyre!("No profiles found matching {profile}"))
+}
+
+#[derive(Parser, Debug)]
+struct ShellArgs {
+    /// Okta organizations to use (defaults to `organization` in settings.toml, then all)
+    #[clap(short, long)]
+    pub organizations: Option<OrganizationPattern>,
+
+    /// Profile to start the shell as (defaults to `profile` in settings.toml)
+    pub profile: Option<Pattern>,
+
+    /// Refresh the credentials in the background before they expire, for as long as the shell runs
+    #[clap(long = "keep-fresh")]
+    pub keep_fresh: bool,
+
+    /// The region to set as `AWS_REGION` (defaults to the profile's `region` in ~/.aws/config, if any)
+    #[clap(long)]
+    pub region: Option<String>,
+}
+
+/// Start the user's shell with the first matching profile's credentials, exiting with its exit code.
+/// The credentials file is removed when the shell exits.
+#[instrument(skip_all, fields(organizations=?args.organizations, profile=?args.profile))]
+async fn shell(args: ShellArgs) -> Result<()> {
+    let settings = Settings::load()?;
+    let organization_pattern = settings.organizations(args.organizations.as_ref())?;
+    let profile = settings.profiles(args.profile.as_ref())?;
+
+    if let Some(current) = shell::current() {
+        warn!("Already in an oktaws shell for {current}; this one will be nested inside it");
+    }
+
+    let organizations = find_organizations_with_catalog(&organization_pattern).await?;
+    single_match(
+        organizations
+            .iter()
+            .flat_map(|organization| &organization.profiles)
+            .filter(|candidate| profile.matches(&candidate.name))
+            .count(),
+        &profile,
+        "shell",
+    )?;
+
+    for organization in organizations {
+        let Some(name) = organization
+            .profiles
+            .iter()
+            .find(|candidate| profile.matches(&candidate.name))
+            .map(|profile| profile.name.clone())
+        else {
+            continue;
+        };
+
+        let okta_client = sign_in(&organization, false).await?;
+
+        let filter = Pattern::new(&Pattern::escape(&name))?;
+        let (organization, okta_client, filter, name) =
+            (&organization, &okta_client, &filter, &name);
+        let fetch = move || async move {
+            organization
+                .clone()
+                .into_credentials(
+                    okta_client,
+                    filter.clone(),
+                    None,
+                    None,
+                    Budget::default(),
+                    None,
+                )
+                .await
+                .next()
+                .map(|(_, credentials)| credentials)
+                .ok_or_else(|| eyre!("Unable to fetch credentials for {name}"))
+        };
+
+        let issued = SystemTime::now();
+        let credentials = fetch().await?;
+
+        let mut usage = Usage::load(&state_dir()?.join(usage::FILE_NAME))?;
+        usage.record_profile(&organization.name, name);
+        blocking::run(move || usage.save()).await?;
+
+        let region = match &args.region {
+            Some(region) => Some(region.clone()),
+            None => ConfigStore::load(None)?
+                .get(name, "region")
+                .map(ToString::to_string),
+        };
+
+        let session = ExecSession::create(&cache_dir()?.join(exec::DIR), name)?;
+        session.write(&credentials)?;
+
+        let mut command = session.command(&shell::program(), &[]);
+        if let Some(region) = &region {
+            command.env("AWS_REGION", region);
+        }
+        // Credentials kept fresh outlive their expiry, so the prompt leaves it out
+        shell::configure(
+            &mut command,
+            name,
+            credentials.expiry().filter(|_| !args.keep_fresh),
+        );
+
+        eprintln!("Starting a shell as {name}; exit it to remove its credentials");
+        let status = session
+            .run(
+                command,
+                &credentials,
+                issued,
+                args.keep_fresh.then_some(fetch),
+            )
+            .await?;
+        drop(session);
+
+        read_only::clean_up();
+        std::process::exit(exec::exit_code(status));
+    }
+
+    Err(eyre!("No profiles found matching {profile}"))
+}
+
+#[derive(Parser, Debug)]
+struct CredentialProcessArgs {
+    /// The profile to print credentials for
+    pub profile: String,
+
+    /// Okta organizations to look in (defaults to `organization` in settings.toml, then all)
+    #[clap(short, long)]
+    pub organizations: Option<OrganizationPattern>,
+
+    /// Fetch new credentials, rather than reusing unexpired ones from an earlier run
+    #[clap(short, long)]
+    pub force: bool,
+}
+
+/// Print a profile's credentials in the `credential_process` format, without writing them anywhere.
+/// Nothing but the JSON goes to stdout, as the SDKs parse all of it.
+#[instrument(skip_all, fields(profile=%args.profile))]
+async fn credential_process(args: &CredentialProcessArgs) -> Result<()> {
+    let settings = Settings::load()?;
+    let organization_pattern = settings.organizations(args.organizations.as_ref())?;
+    // The SDKs run the process for every client they build, so simultaneous runs share one fetch,
+    // and later runs reuse it until it is about to expire
+    let coalescer = Coalescer::new(cache_dir()?.join(coalesce::DIR)).reusing(!args.force);
+
+    for organization in find_organizations_with_catalog(&organization_pattern).await? {
+        let Some(profile) = organization
+            .profiles
+            .iter()
+            .find(|profile| profile.name == args.profile)
+            .cloned()
+        else {
+            continue;
+        };
+
+        let organization = &organization;
+        let credentials = coalescer
+            .credentials(
+                &organization.name,
+                &coalesce::key(&profile, None),
+                || async move {
+                    let okta_client = sign_in(organization, false).await?;
+                    profile
+                        .into_credentials(
+                            &okta_client,
+                            None,
+                            &organization.account_naming,
+                            organization.identity_center.as_ref(),
+                        )
+                        .await
+                },
+            )
+            .await?;
+
+        let mut usage = Usage::load(&state_dir()?.join(usage::FILE_NAME))?;
+        usage.record_profile(&organization.name, &args.profile);
+        blocking::run(move || usage.save()).await?;
+
+        println!(
+            "{}",
+            serde_json::to_string(&ProcessCredentials::new(&credentials)?)?
+        );
+        return Ok(());
+    }
+
+    Err(eyre!("No profile named {} found", args.profile))
+}
+
+#[derive(Parser, Debug)]
+struct ServeArgs {
+    /// Okta organizations to use (defaults to `organization` in settings.toml, then all)
+    #[clap(short, long)]
+    pub organizations: Option<OrganizationPattern>,
+
+    /// Profiles to serve (defaults to `profile` in settings.toml, then all)
+    pub profiles: Option<Pattern>,
+
+    /// The address to listen on; the SDKs only accept plain HTTP from loopback addresses
+    #[clap(long, default_value = "127.0.0.1:0")]
+    pub address: SocketAddr,
+
+    /// The token clients must send, instead of a random one
+    #[clap(long, env = "OKTAWS_SERVE_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+}
+
+/// Fetches credentials for the served profiles, as `credential_process` does
+struct ServedProfiles {
+    organizations: Vec<Organization>,
+    profiles: Selector,
+    coalescer: Coalescer,
+}
+
+impl serve::Source for ServedProfiles {
+    async fn credentials(&self, name: &str) -> Result<Option<aws_credential_types::Credentials>> {
+        for organization in &self.organizations {
+            if !organization
+                .select(&self.profiles)
+                .iter()
+                .any(|profile| profile == name)
+            {
+                continue;
+            }
+            let Some(profile) = organization
+                .profiles
+                .iter()
+                .find(|profile| profile.name == name)
+                .cloned()
+            else {
+                continue;
+            };
+
+            let key = coalesce::key(&profile, None);
+            let credentials = self
+                .coalescer
+                .credentials(&organization.name, &key, || async move {
+                    let okta_client = sign_in(organization, false).await?;
+                    profile
+                        .into_credentials(
+                            &okta_client,
+                            None,
+                            &organization.account_naming,
+                            organization.identity_center.as_ref(),
+                        )
+                        .await
+                })
+                .await?;
+
+            let mut usage = Usage::load(&state_dir()?.join(usage::FILE_NAME))?;
+            usage.record_profile(&organization.name, name);
+            blocking::run(move || usage.save()).await?;
+
+            return Ok(Some(credentials));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Serve credentials to the AWS SDKs over the ECS container credentials contract, until interrupted.
+/// Credentials are fetched when first asked for, and kept in memory until due for a refresh.
+#[instrument(skip_all, fields(organizations=?args.organizations,profiles=?args.profiles))]
+async fn serve(args: ServeArgs) -> Result<()> {
+    let settings = Settings::load()?;
+    settings.writes.install();
+    let organization_pattern = settings.organizations(args.organizations.as_ref())?;
+    let profiles = Selector::Pattern(settings.profiles(args.profiles.as_ref())?);
+    let organizations = find_organizations_with_catalog(&organization_pattern).await?;
+
+    let served = organizations
+        .iter()
+        .flat_map(|organization| organization.select(&profiles))
+        .collect::<Vec<_>>();
+    if served.is_empty() {
+        return Err(eyre!("No profiles found matching {profiles}"));
+    }
+
+    let listener = tokio::net::TcpListener::bind(args.address).await?;
+    let address = listener.local_addr()?;
+    let token = args.token.map_or_else(serve::generate_token, Ok)?;
+
+    println!("Serving credentials on http://{address} until interrupted");
+    for profile in &served {
+        println!(
+            "{profile}: {}={}",
+            serve::URI_VARIABLE,
+            serve::profile_url(&address, profile)
+        );
+    }
+    println!("Authorize with {}={token}", serve::TOKEN_VARIABLE);
+
+    let server = CredentialsServer::new(
+        token,
+        ServedProfiles {
+            organizations,
+            profiles,
+            coalescer: Coalescer::new(cache_dir()?.join(coalesce::DIR)),
+        },
+    );
+
+    tokio::select! {
+        result = server.serve(listener) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+#[derive(Parser, Debug)]
+struct DaemonArgs {
+    /// Okta organizations to use (defaults to `organization` in settings.toml, then all)
+    #[clap(short, long)]
+    pub organizations: Option<OrganizationPattern>,
+
+    /// Profiles to keep fresh (defaults to `profile` in settings.toml, then all)
+    pub profiles: Option<Pattern>,
+
+    /// How many times to try each profile, when Okta or AWS have transient errors
+    #[clap(long, default_value_t = 3)]
+    pub attempts: u32,
+
+    /// How long each attempt at a profile may take, e.g. `30s`
+    #[clap(long = "profile-timeout", default_value = "2m", value_parser = parse_duration)]
+    pub profile_timeout: Duration,
+
+    /// How often to discover the accounts and roles of the organizations signed in to, e.g. `30m`,
+    /// announcing new grants and keeping the catalog `oktaws search` and `oktaws list` use up to date
+    #[clap(long = "discover-every", default_value = "1h", value_parser = parse_duration)]
+    pub discover_every: Duration,
+}
+
+/// Refresh profiles as they come due, until interrupted.
+/// Each organization is signed in to once, and its Okta session kept alive in between;
+/// if the session ends anyway, the next refresh signs in again.
+/// The organizations signed in to are discovered again every so often, as `oktaws report` would.
+#[instrument(skip_all, fields(organizations=?args.organizations,profiles=?args.profiles))]
+async fn daemon(args: DaemonArgs) -> Result<()> {
+    transaction::recover(&state_dir()?)?;
+
+    let settings = Settings::load()?;
+    let organization_pattern = settings.organizations(args.organizations.as_ref())?;
+    let profiles = Selector::Pattern(settings.profiles(args.profiles.as_ref())?);
+    let organizations = find_organizations(&organization_pattern).await?;
+
+    let budget = Budget {
+        attempts: args.attempts.max(1),
+        timeout: args.profile_timeout,
+    };
+    let coalescer = Coalescer::new(cache_dir()?.join(coalesce::DIR));
+
+    let tracker = daemon::Shared::new(daemon::Tracker::new(organizations.iter().flat_map(
+        |organization| {
+            organization
+                .select(&profiles)
+                .into_iter()
+                .map(|profile| (organization.name.clone(), profile))
+        },
+    )));
+    if tracker.with(|tracker| tracker.is_empty()) {
+        return Err(eyre!("No profiles found matching {profiles}"));
+    }
+
+    // The control socket can ask for refreshes and sign-outs
+    let (commands_sender, mut commands) = tokio::sync::mpsc::unbounded_channel();
+    #[cfg(unix)]
+    {
+        let listener = ipc::bind(&ipc::socket_path()?)?;
+        let control = daemon::Control::new(tracker.clone(), commands_sender.clone());
+        tokio::spawn(ipc::serve(listener, std::sync::Arc::new(control)));
+    }
+    #[cfg(not(unix))]
+    drop(commands_sender);
+
+    let mut clients: HashMap<String, OktaClient> = HashMap::new();
+    let mut keepalive = tokio::time::interval(daemon::KEEPALIVE_INTERVAL);
+    keepalive.reset();
+    let discover_every = args.discover_every.max(Duration::from_mins(1));
+    let mut rediscover = tokio::time::interval(discover_every);
+    rediscover.reset();
+    let mut terminate = daemon::Terminate::new()?;
+
+    loop {
+        close_elevations();
+
+        for organization in &organizations {
+            let due = tracker.with(|tracker| tracker.due(&organization.name, SystemTime::now()));
+            if !due.is_empty() {
+                refresh_due(
+                    organization,
+                    &due,
+                    &mut clients,
+                    &tracker,
+                    budget,
+                    &coalescer,
+                )
+                .await;
+            }
+        }
+
+        let next = tracker.with(|tracker| tracker.next());
+        let delay = next
+            .and_then(|next| next.duration_since(SystemTime::now()).ok())
+            .unwrap_or_default();
+        if let Some(next) = next {
+            debug!("Next refresh at {}", timestamp::render(next));
+        }
+
+        tokio::select! {
+            () = tokio::time::sleep(delay), if next.is_some() => {}
+            _ = keepalive.tick() => {
+                let mut ended = Vec::new();
+                for (organization, client) in &clients {
+                    if let Err(e) = client.refresh_session().await {
+                        warn!("Unable to extend the Okta session for {organization}, signing in again when next needed: {e}");
+                        ended.push(organization.clone());
+                    }
+                }
+                clients.retain(|organization, _| !ended.contains(organization));
+            }
+            _ = rediscover.tick() => {
+                for organization in &organizations {
+                    if let Some(client) = clients.get(&organization.name) {
+                        let scope = DiscoveryScope::default();
+                        if let Err(e) = discover_with(client, organization, &scope).await {
+                            warn!(
+                                "Unable to discover the accounts of {}, trying again in {}: {e}",
+                                organization.name,
+                                timestamp::duration(discover_every)
+                            );
+                        }
+                    }
+                }
+            }
+            Some(command) = commands.recv() => {
+                if let daemon::Command::Logout { organization } = command {
+                    sign_out(&organizations, organization.as_deref(), &mut clients).await?;
+                }
+            }
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                info!("Stopping");
+                return Ok(());
+            }
+            () = terminate.recv() => {
+                // Stopped for good (e.g. by a service manager), so no session is left behind for anything to reuse
+                sign_out(&organizations, None, &mut clients).await?;
+                info!("Stopping");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Sign the daemon out of `organization` (or every organization): end its Okta session, and forget it,
+/// so that neither the daemon nor a later refresh reuses it
+async fn sign_out(
+    organizations: &[Organization],
+    organization: Option<&str>,
+    clients: &mut HashMap<String, OktaClient>,
+) -> Result<()> {
+    let sessions = state_dir()?.join(silent::DIR);
+
+    for name in organizations
+        .iter()
+        .map(|found| found.name.as_str())
+        .filter(|name| organization.is_none_or(|organization| organization == *name))
+    {
+        if let Some(client) = clients.remove(name) {
+            if let Err(e) = client.end_session().await {
+                warn!("Unable to end the Okta session for {name}: {e}");
+            }
+        }
+
+        let forgotten = {
+            let (sessions, name) = (sessions.clone(), name.to_string());
+            blocking::run(move || SilentSession::forget(&sessions, &name)).await
+        };
+        if let Err(e) = forgotten {
+            warn!("Unable to forget the Okta session for {name}: {e}");
+        }
+
+        info!("Signed out of {name}");
+    }
+
+    Ok(())
+}
+
+/// Refresh an organization's due profiles, writing them to its credentials file (and sinks) together.
+/// Profiles which fail are tried again later, rather than stopping the daemon.
+async fn refresh_due(
+    organization: &Organization,
+    due: &[String],
+    clients: &mut HashMap<String, OktaClient>,
+    tracker: &daemon::Shared,
+    budget: Budget,
+    coalescer: &Coalescer,
+) {
+    let written = async {
+        let okta_client = match clients.entry(organization.name.clone()) {
+            HashEntry::Occupied(entry) => entry.into_mut(),
+            HashEntry::Vacant(entry) => entry.insert(sign_in(organization, false).await?),
+        };
+
+        let mut store = ProfileStore::load_with(
+            organization.credentials_file.as_deref(),
+            organization.credentials_layout,
+        )?;
+        let mut registry = SectionRegistry::load(&state_dir()?.join(registry::FILE_NAME))?;
+        let mut delivered = Vec::new();
+        let elevated = elevated_sections(organization)?;
+
+        for name in due {
+            let issued = SystemTime::now();
+            let fetched = organization
+                .clone()
+                .into_credentials(
+                    okta_client,
+                    Pattern::new(&Pattern::escape(name))?,
+                    None,
+                    None,
+                    budget,
+                    Some(coalescer),
+                )
+                .await
+                .next();
+
+            // Failures have already been logged
+            let Some((name, credentials)) = fetched else {
+                tracker.with(|tracker| tracker.failed(name, SystemTime::now()));
+                continue;
+            };
+
+            let schedule = Schedule::new(&name, &credentials, issued);
+            if let Some(schedule) = &schedule {
+                info!(
+                    "Refreshed {name}, expiring at {}, next refresh after {}",
+                    timestamp::render(schedule.expires_at),
+                    timestamp::render(schedule.refresh_at)
+                );
+            }
+            tracker.with(|tracker| tracker.refreshed(&name, schedule.as_ref()));
+
+            let credentials_profile = organization
+                .profiles
+                .iter()
+                .find(|profile| profile.name == name)
+                .map_or_else(|| name.clone(), Profile::credentials_profile);
+            if elevated.contains(&credentials_profile)
+                || registry.is_left_alone(&credentials_profile)
+            {
+                continue;
+            }
+
+            store.upsert_credential(&credentials_profile, &credentials)?;
+            registry.record(&credentials_profile, &organization.name);
+            if !organization.sinks.is_empty() {
+                delivered.push((credentials_profile, credentials.clone()));
+            }
+
+            events::emit(&Event::ProfileRefreshed {
+                organization: organization.name.clone(),
+                partition: organization.partition.to_string(),
+                profile: name,
+                expires_at: credentials.expiry().and_then(events::timestamp),
+            });
+        }
+
+        let state_dir = state_dir()?;
+        blocking::run(move || transaction::commit(&state_dir, &[&store as &dyn Staged, &registry]))
+            .await?;
+        sink::deliver(&organization.name, &organization.sinks, &delivered).await;
+
+        Ok::<_, eyre::Report>(())
+    }
+    .await;
+
+    if let Err(e) = written {
+        warn!(
+            "Unable to refresh {} for {}, trying again in {}: {e}",
+            due.join(", "),
+            organization.name,
+            timestamp::duration(exec::RETRY_INTERVAL)
+        );
+        clients.remove(&organization.name);
+        tracker.with(|tracker| {
+            for name in due {
+                tracker.failed(name, SystemTime::now());
+            }
+        });
+    }
+}
+
+#[derive(Parser, Debug)]
+struct ListArgs {
+    /// Profiles to list (defaults to all)
+    pub profiles: Option<Pattern>,
+
+    /// Okta organizations to list (defaults to `organization` in settings.toml, then all)
+    #[clap(short, long)]
+    pub organizations: Option<OrganizationPattern>,
+
+    /// Discover accounts again (signing in), rather than using those found by the last discovery
+    #[clap(long)]
+    pub discover: bool,
+}
+
+/// Print every configured profile, where its credentials come from, and the state of those in the credentials file.
+/// Without `--discover`, nothing is signed in to.
+#[instrument(skip_all, fields(organizations=?args.organizations,profiles=?args.profiles))]
+async fn list(args: &ListArgs) -> Result<()> {
+    let settings = Settings::load()?;
+    let organization_pattern = settings.organizations(args.organizations.as_ref())?;
+    let catalog_dir = cache_dir()?.join(discovery::CATALOG_DIR);
+    let now = SystemTime::now();
+
+    let mut credentials_stores: BTreeMap<(Option<PathBuf>, Layout), ProfileStore> = BTreeMap::new();
+    let mut entries = Vec::new();
+
+    for mut organization in find_organizations(&organization_pattern).await? {
+        let catalog: Option<Vec<CatalogEntry>> = if args.discover {
+            Some(
+                discover(&organization, &DiscoveryScope::default())
+                    .await?
+                    .iter()
+                    .map(CatalogEntry::from)
+                    .collect(),
+            )
+        } else {
+            discovery::load_catalog(&catalog_dir, &organization.name)?
+        };
+
+        // Including the profiles `profile_format` names for the accounts discovered
+        if let Some(catalog) = &catalog {
+            organization.add_discovered_profiles(
+                &catalog
+                    .iter()
+                    .map(AppLinkAccountRoleMapping::from)
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        let aws_credentials = credentials_store(&mut credentials_stores, &organization)?;
+
+        entries.extend(
+            organization
+                .profiles
+                .iter()
+                .filter(|profile| {
+                    args.profiles
+                        .as_ref()
+                        .is_none_or(|pattern| pattern.matches(&profile.name))
+                })
+                .map(|profile| {
+                    ListEntry::new(
+                        &organization.name,
+                        profile,
+                        catalog.as_deref(),
+                        aws_credentials,
+                        now,
+                    )
+                }),
+        );
+    }
+
+    if output::json() {
+        return output::print_json(&entries);
+    }
+    if entries.is_empty() {
+        return Err(eyre!("No profiles are configured"));
+    }
+    print!(
+        "{}",
+        list::render(&entries, TimestampFormat::current(), now)
+    );
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct CanIArgs {
+    /// The account, by name or ID
+    account: String,
+
+    /// The role (or permission set)
+    role: String,
+
+    /// Okta organizations to look for the account in
+    #[clap(short, long, default_value = "*")]
+    organizations: OrganizationPattern,
+}
+
+/// Print whether the role can be obtained, and why (not), exiting with a failure when it cannot
+#[instrument(skip_all, fields(account=%args.account, role=%args.role))]
+async fn can_i(args: &CanIArgs) -> Result<()> {
+    let verdict = check_access(args).await?;
+
+    if output::json() {
+        output::print_json(&verdict)?;
+    } else {
+        println!("{verdict}");
+    }
+
+    if !verdict.allowed {
+        read_only::clean_up();
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Discover the account in each organization until the role is granted, fetching (and dropping) the shortest credentials for it.
+/// The role is only reported as not granted once every organization with the account has been tried
+async fn check_access(args: &CanIArgs) -> Result<Verdict> {
+    let scope = access::scope(&args.account);
+    let mut looked_in = Vec::new();
+    let mut denials = Vec::new();
+
+    for organization in find_organizations(&args.organizations).await? {
+        let okta_client =
+            OktaClient::new(&organization.portal, organization.username.clone(), false).await?;
+        let mappings = discover_with(&okta_client, &organization, &scope).await?;
+
+        let (mapping, role) = match access::lookup(&mappings, &args.account, &args.role) {
+            Lookup::Granted { mapping, role } => (mapping, role),
+            Lookup::NotGranted(mapping) => {
+                denials.push(Verdict::denied(
+                    &organization.name,
+                    mapping,
+                    &args.role,
+                    format!(
+                        "{} does not grant {} through {}; it grants {}",
+                        access::describe(mapping),
+                        args.role,
+                        organization.name,
+                        mapping.role_names.join(", ")
+                    ),
+                ));
+                continue;
+            }
+            Lookup::NoAccount => {
+                looked_in.push(organization.name);
+                continue;
+            }
+        };
+
+        let profile = Profile::try_from_spec(
+            &ProfileConfig::for_role(mapping, role),
+            format!("{}/{role}", mapping.account_name),
+            None,
+            &[],
+            Some(MIN_DURATION_SECONDS),
+        )?;
+
+        match profile
+            .into_credentials(
+                &okta_client,
+                None,
+                &organization.account_naming,
+                organization.identity_center.as_ref(),
+            )
+            .await
+        {
+            Ok(_) => return Ok(Verdict::allowed(&organization.name, mapping, role)),
+            Err(e) => denials.push(Verdict::denied(
+                &organization.name,
+                mapping,
+                role,
+                format!(
+                    "{} grants {role} through {}, but getting credentials for it failed: {e}",
+                    access::describe(mapping),
+                    organization.name
+                ),
+            )),
+        }
+    }
+
+    Ok(Verdict::denied_everywhere(denials)
+        .unwrap_or_else(|| Verdict::not_found(&args.account, &args.role, &looked_in)))
+}
+
+#[derive(Parser, Debug)]
+struct ElevateArgs {
+    /// The privileged profile
+    pub profile: String,
+
+    /// How long the access is needed for, e.g. `30m` (from 15m to 12h, as AWS sessions last)
+    #[clap(long = "for", value_parser = parse_duration)]
+    pub window: Duration,
+
+    /// Why the access is needed, for the audit log (asked for when not given)
+    #[clap(long)]
+    pub reason: Option<String>,
+
+    /// Okta organizations to look in (defaults to `organization` in settings.toml, then all)
+    #[clap(short, long)]
+    pub organizations: Option<OrganizationPattern>,
+}
+
+/// Write credentials for a profile which last only for the window, recording why in the audit log.
+/// The profile's section is removed once the window has closed (see [`elevate`]).
+#[instrument(skip_all, fields(profile=%args.profile))]
+async fn elevate(args: &ElevateArgs) -> Result<()> {
+    transaction::recover(&state_dir()?)?;
+    close_elevations();
+
+    let duration_seconds = i32::try_from(args.window.as_secs())
+        .ok()
+        .filter(|seconds| (MIN_DURATION_SECONDS..=MAX_DURATION_SECONDS).contains(seconds))
+        .ok_or_else(|| eyre!("--for must be from 15m to 12h, as AWS sessions last"))?;
+
+    let settings = Settings::load()?;
+    let organization_pattern = settings.organizations(args.organizations.as_ref())?;
+
+    for organization in find_organizations_with_catalog(&organization_pattern).await? {
+        let Some(mut profile) = organization
+            .profiles
+            .iter()
+            .find(|profile| profile.name == args.profile)
+            .cloned()
+        else {
+            continue;
+        };
+        // The credentials must expire with the window, so their duration has to be one STS honours
+        if profile.chain.is_some() {
+            return Err(eyre!(
+                "{} chains into another role, whose session can't be made to last for the window",
+                args.profile
+            ));
+        }
+        if organization.identity_center.is_some() && profile.account.is_some() {
+            return Err(eyre!(
+                "{} is fetched through Identity Center, whose sessions last as long as the permission set says",
+                args.profile
+            ));
+        }
+        profile.duration_seconds = Some(duration_seconds);
+
+        let reason = match &args.reason {
+            Some(reason) => reason.clone(),
+            None if stdin().is_terminal() => {
+                dialoguer::Input::<String>::with_theme(theme::prompt())
+                    .with_prompt(format!("Why do you need {}?", args.profile))
+                    .interact_text()?
+            }
+            None => {
+                return Err(eyre!(
+                    "--reason must be given when oktaws cannot ask for it"
+                ));
+            }
+        };
+        let reason = reason.trim();
+        if reason.is_empty() {
+            return Err(eyre!("A reason is needed for the audit log"));
+        }
+
+        // The requests behind the elevation are kept in the audit log with it
+        let mark = request_id::mark();
+        let okta_client = sign_in(&organization, false).await?;
+        let section = profile.credentials_profile();
+        let credentials = profile
+            .into_credentials(
+                &okta_client,
+                None,
+                &organization.account_naming,
+                organization.identity_center.as_ref(),
+            )
+            .await?;
+        let closes_at = SystemTime::now() + args.window;
+        if credentials
+            .expiry()
+            .is_none_or(|expiry| expiry > closes_at + Duration::from_mins(1))
+        {
+            return Err(eyre!(
+                "AWS gave {} credentials which outlast the window, so they were not written",
+                args.profile
+            ));
+        }
+
+        let mut aws_credentials = ProfileStore::load_with(
+            organization.credentials_file.as_deref(),
+            organization.credentials_layout,
+        )?;
+        aws_credentials.upsert_credential(&section, &credentials)?;
+        let mut registry = SectionRegistry::load(&state_dir()?.join(registry::FILE_NAME))?;
+        registry.record(&section, &organization.name);
+        transaction::commit(&state_dir()?, &[&aws_credentials as &dyn Staged, &registry])?;
+
+        elevate::open(
+            &state_dir()?,
+            &ElevationWindow {
+                organization: organization.name.clone(),
+                profile: args.profile.clone(),
+                section,
+                credentials_file: organization.credentials_file.clone(),
+                layout: organization.credentials_layout,
+                until: closes_at.duration_since(std::time::UNIX_EPOCH)?.as_secs(),
+                access_key_id: Some(credentials.access_key_id().to_string()),
+            },
+            reason,
+            request_id::since(mark),
+        )?;
+
+        info!(
+            "{} is elevated until {}, when its credentials expire and are removed",
+            args.profile,
+            timestamp::render(closes_at)
+        );
+        return Ok(());
+    }
+
+    Err(eyre!("No profile named {} found", args.profile))
+}
+
+/// The organization's sections which are elevated (see [`elevate`]), which refreshes leave alone until their windows close
+fn elevated_sections(organization: &Organization) -> Result<HashSet<String>> {
+    elevate::elevated(
+        &state_dir()?,
+        organization.credentials_file.as_deref(),
+        organization.credentials_layout,
+        SystemTime::now(),
+    )
+}
+
+/// Remove the sections of elevated profiles whose windows have closed.
+/// Failing to only warns: their credentials have expired already
+fn close_elevations() {
+    if let Err(e) =
+        state_dir().and_then(|state_dir| elevate::close_expired(&state_dir, SystemTime::now()))
+    {
+        warn!("Unable to remove elevated profiles whose time is up: {e}");
+    }
+}
+
+#[derive(Parser, Debug)]
+struct ExplainArgs {
+    /// The profile to explain
+    pub profile: String,
+
+    /// Okta organizations to look in (defaults to `organization` in settings.toml, then all)
+    #[clap(short, long)]
+    pub organizations: Option<OrganizationPattern>,
+
+    /// Only explain what the configuration says, without signing in to match the tile, account and role
+    #[clap(long)]
+    pub offline: bool,
+}
+
+/// Print how a profile resolves, step by step, without fetching or writing any credentials
+#[instrument(skip_all, fields(profile=%args.profile))]
+async fn explain(args: &ExplainArgs) -> Result<()> {
+    let settings = Settings::load()?;
+    let organization_pattern = settings.organizations(args.organizations.as_ref())?;
+
+    let mut found = false;
+    for organization in find_organizations(&organization_pattern).await? {
+        let Some(profile) = organization
+            .profiles
+            .iter()
+            .find(|profile| profile.name == args.profile)
+        else {
+            continue;
+        };
+        found = true;
+
+        let config_path = oktaws_home()?.join(format!("{}.toml", organization.name));
+        let config: OrganizationConfig =
+            from_toml(&config_path, &std::fs::read_to_string(&config_path)?)?;
+        let credentials = ProfileStore::load_with(
+            organization.credentials_file.as_deref(),
+            organization.credentials_layout,
+        )?;
+
+        let mut explanation = Explanation::from_config(
+            &organization,
+            &config,
+            &config_path,
+            profile,
+            credentials.path(),
+        );
+
+        if !args.offline {
+            let okta_client =
+                OktaClient::new(&organization.portal, organization.username.clone(), false).await?;
+            explanation
+                .add_matches(&okta_client, &organization, profile)
+                .await?;
+        }
+
+        print!("{explanation}");
+    }
+
+    if found {
+        Ok(())
+    } else {
+        Err(eyre!("No profile named {} found", args.profile))
+    }
+}
+
+/// Print what each path oktaws uses is for, and where it is
+fn paths() -> Result<()> {
+    let home = oktaws_home()?;
+    let state = state_dir()?;
+    let cache = cache_dir()?;
+
+    let mut paths = vec![
+        (String::from("config"), home.clone()),
+        (String::from("settings"), home.join(settings::FILE_NAME)),
+    ];
+
+    // A broken config shouldn't stop the rest of the paths from being printed
+    let organizations = "*"
+        .parse::<OrganizationPattern>()?
+        .organizations()
+        .unwrap_or_else(|e| {
+            warn!("Unable to load the organizations ({e})");
+            Vec::new()
+        });
+    for organization in &organizations {
+        paths.push((
+            format!("organization {}", organization.name),
+            home.join(format!("{}.toml", organization.name)),
+        ));
+    }
+
+    paths.extend([
+        (String::from("state"), state.clone()),
+        (String::from("usage"), state.join(usage::FILE_NAME)),
+        (String::from("registry"), state.join(registry::FILE_NAME)),
+        (String::from("elevations"), state.join(elevate::FILE_NAME)),
+        (String::from("audit log"), state.join(elevate::AUDIT_LOG)),
+        (
+            String::from("shared configs"),
+            state.join(shared::FILE_NAME),
+        ),
+        (String::from("picks"), state.join(picker::FILE_NAME)),
+        (String::from("discovery"), state.join(discovery::DIR)),
+        (String::from("sessions"), state.join(silent::DIR)),
+        (
+            String::from("pending pushes"),
+            state.join(transactions::DIR),
+        ),
+        (
+            String::from("secrets (with --no-keyring)"),
+            state.join(secrets::DIR),
+        ),
+        (String::from("browsers"), state.join(browser::DIR)),
+        (String::from("journal"), state.join(transaction::JOURNAL)),
+        #[cfg(unix)]
+        (String::from("socket"), ipc::socket_path()?),
+        (String::from("cache"), cache.clone()),
+        (String::from("credentials cache"), cache.join(coalesce::DIR)),
+        (String::from("capabilities"), cache.join(capabilities::DIR)),
+        (String::from("catalog"), cache.join(discovery::CATALOG_DIR)),
+        (String::from("exec"), cache.join(exec::DIR)),
+        (
+            String::from("identity center"),
+            cache.join(identity_center::DIR),
+        ),
+        (
+            String::from("aws config"),
+            ConfigStore::load(None)?.path().to_path_buf(),
+        ),
+        (
+            String::from("aws credentials"),
+            ProfileStore::load(None)?.path().to_path_buf(),
+        ),
+    ]);
+
+    for organization in &organizations {
+        if organization.credentials_file.is_some()
+            || organization.credentials_layout != Layout::default()
+        {
+            paths.push((
+                format!("aws credentials ({})", organization.name),
+                ProfileStore::load_with(
+                    organization.credentials_file.as_deref(),
+                    organization.credentials_layout,
+                )?
+                .path()
+                .to_path_buf(),
+            ));
+        }
+    }
+
+    let width = paths
+        .iter()
+        .map(|(label, _)| label.len())
+        .max()
+        .unwrap_or_default();
+    for (label, path) in paths {
+        println!("{label:<width$}  {}", path.display());
+    }
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct SuggestArgs {
+    /// Okta organizations to use
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+
+    /// How many times a role must have been chained into before suggesting it is pinned
+    #[clap(long = "min-uses", default_value_t = 5)]
+    pub min_uses: u64,
+}
+
+/// Print the profiles which have never been used on this machine,
+/// and the `--assume` roles used often enough to be worth pinning in a profile.
+/// This only reads local files, so works offline.
+#[instrument(skip_all, fields(organizations=%args.organizations))]
+fn suggest(args: &SuggestArgs) -> Result<()> {
+    let organizations = args.organizations.organizations()?;
+    let usage = Usage::load(&state_dir()?.join(usage::FILE_NAME))?;
+
+    let suggestions = usage.suggest(
+        organizations.iter().map(|organization| {
+            (
+                organization.name.as_str(),
+                organization
+                    .profiles
+                    .iter()
+                    .map(|profile| {
+                        (
+                            profile.name.as_str(),
+                            profile.chain.as_ref().map(|chain| chain.role_arn.as_str()),
+                        )
+                    })
+                    .collect(),
+            )
+        }),
+        args.min_uses,
+    );
+
+    if suggestions.unused.is_empty() && suggestions.unpinned.is_empty() {
+        println!("Nothing to suggest");
+        return Ok(());
+    }
+
+    if !suggestions.unused.is_empty() {
+        println!("Never used on this machine (consider removing):");
+        for (organization, profile) in &suggestions.unused {
+            println!("  {organization}: {profile}");
+        }
+    }
+
+    if !suggestions.unpinned.is_empty() {
+        println!("Often chained into with --assume (consider adding as a profile's assume_role):");
+        for (role_arn, hits) in &suggestions.unpinned {
+            println!(
+                "  {role_arn} ({} uses, last {})",
+                hits.count,
+                timestamp::render(SystemTime::UNIX_EPOCH + Duration::from_secs(hits.last_used))
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct BenchArgs {
+    /// How many times to run the pipeline
+    #[clap(long, default_value_t = 20)]
+    pub iterations: usize,
+
+    /// How many profiles each iteration refreshes
+    #[clap(long, default_value_t = 50)]
+    pub profiles: usize,
+
+    /// How long the mock backend takes to answer each request for credentials, in milliseconds
+    #[clap(long = "latency-ms", default_value_t = 50)]
+    pub latency_ms: u64,
+
+    /// How many profiles to fetch at once
+    #[clap(long, default_value_t = 10)]
+    pub concurrency: usize,
+
+    /// Use the mock backend (the only one supported, so that benchmarks never hit Okta's rate limits)
+    #[clap(long)]
+    pub mock: bool,
+}
+
+/// Run the discovery and credential pipeline against a mock backend, and print latency percentiles for each stage
+async fn bench(args: &BenchArgs) -> Result<()> {
+    if !args.mock {
+        return Err(eyre!(
+            "Only the mock backend is supported (--mock), as benchmarking a real organization would count against its rate limits"
+        ));
+    }
+
+    // Removed when dropped, and named unpredictably, so that nothing else can be there first
+    let dir = tempfile::Builder::new().prefix("oktaws-bench-").tempdir()?;
+    let report = bench::run(
+        dir.path(),
+        &bench::Options {
+            iterations: args.iterations,
+            profiles: args.profiles,
+            latency: Duration::from_millis(args.latency_ms),
+            concurrency: args.concurrency,
+        },
+    )
+    .await;
+
+    print!("{}", bench::render(&report?));
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct PingArgs {
+    /// Okta organizations to check
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+
+    /// AWS regions whose SSO portal to check
+    #[clap(long = "region", default_value = "us-east-1")]
+    pub regions: Vec<String>,
+
+    /// How long to wait for each endpoint, e.g. `5s`
+    #[clap(long, default_value = "10s", value_parser = parse_duration)]
+    pub timeout: Duration,
+}
+
+/// Print the latency and result of a request to each Okta and AWS endpoint.
+/// Nothing is signed in to, so this works even when credentials are the problem.
+#[instrument(skip_all, fields(organizations=%args.organizations))]
+async fn ping(args: &PingArgs) -> Result<()> {
+    let organizations = args.organizations.organizations()?;
+    let targets = ping::targets(&organizations, &args.regions)?;
+
+    let probes = ping::probe_all(targets, args.timeout).await?;
+    print!("{}", ping::render(&probes));
+
+    let health = probes.iter().map(ping::Probe::health).collect::<Vec<_>>();
+    if health.contains(&ping::Health::Unreachable) {
+        Err(eyre!("Some endpoints could not be reached"))
+    } else if health.contains(&ping::Health::Degraded) {
+        Err(eyre!("Some endpoints are answering with server errors"))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Parser, Debug)]
+struct DoctorArgs {
+    /// Okta organizations to check
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+
+    /// Probe each organization again, rather than reporting what was cached
+    #[clap(long)]
+    pub refresh: bool,
+}
+
+/// Print what each organization supports, probing any not seen (or not probed recently).
+/// Enrolled factors are only known after a password sign-in, which this does not do.
+#[instrument(skip_all, fields(organizations=%args.organizations))]
+async fn doctor(args: &DoctorArgs) -> Result<()> {
+    let dir = cache_dir()?.join(capabilities::DIR);
+
+    for organization in args.organizations.organizations()? {
+        match capabilities::cached(&organization.portal, &dir, args.refresh).await {
+            Ok(capabilities) => print!(
+                "{}",
+                capabilities::render(&organization.name, &capabilities)
+            ),
+            Err(e) => println!("{}\n  Unable to probe: {e}", organization.name),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct LockArgs {
+    /// Okta organizations to lock
+    pub organizations: OrganizationPattern,
+}
+
+/// Remove the cached secrets for each matching organization, and its profiles' copies in its sinks.
+/// Secrets which cannot be removed are reported, and fail the command once every organization has been locked.
+#[instrument(skip_all, fields(organizations=%args.organizations))]
+async fn lock(args: &LockArgs) -> Result<()> {
+    let organizations = find_organizations_with_catalog(&args.organizations).await?;
+    let mut failures = 0;
+
+    for organization in organizations {
+        let mut secrets = vec![
+            Secret::sso_tokens(organization.portal.base_url()),
+            Secret::okta_password(&organization.name, &organization.username),
+            Secret::okta_totp(&organization.name, &organization.username),
+            Secret::okta_device(&organization.name),
+        ];
+        secrets.extend(
+            organization
+                .portal
+                .headers()
+                .iter()
+                .map(|name| Secret::okta_header(&organization.name, name.as_str())),
+        );
+        if let Some(identity_center) = &organization.identity_center {
+            secrets.extend(identity_center.secrets());
+        }
+
+        let mut forgot =
+            match SilentSession::forget(&state_dir()?.join(silent::DIR), &organization.name) {
+                Ok(forgot) => forgot,
+                Err(e) => {
+                    warn!(
+                        "Unable to forget the Okta session for {}: {e}",
+                        organization.name
+                    );
+                    failures += 1;
+                    false
+                }
+            };
+        for secret in secrets {
+            match secret.delete() {
+                Ok(deleted) => forgot |= deleted,
+                Err(e) => {
+                    warn!("Unable to forget {secret}: {e}");
+                    failures += 1;
+                }
+            }
+        }
+
+        if forgot {
+            println!("{}: removed cached credentials", organization.name);
+        } else {
+            println!("{}: no cached credentials", organization.name);
+        }
+
+        // Copies of the organization's AWS credentials sent elsewhere go too
+        let sections = organization
+            .profiles
+            .iter()
+            .map(Profile::credentials_profile)
+            .collect::<Vec<_>>();
+        let removed = sink::forget(&organization.name, &organization.sinks, &sections).await;
+        if removed > 0 {
+            println!(
+                "{}: removed {removed} profile(s) from its sinks",
+                organization.name
+            );
+        }
+    }
+
+    if failures > 0 {
+        return Err(eyre!(
+            "Unable to forget {failures} secret(s), which are still kept"
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+enum MfaCommand {
+    /// Cancel Okta Verify pushes left pending by runs which were interrupted
+    Cancel(MfaCancelArgs),
+
+    /// Register a TOTP secret (or `otpauth://` URI), so that oktaws generates TOTP codes itself
+    Totp(MfaTotpArgs),
+}
+
+#[derive(Parser, Debug)]
+struct MfaTotpArgs {
+    /// Okta organizations to register the secret for
+    pub organizations: OrganizationPattern,
+
+    /// Forget the registered secret, prompting for codes again
+    #[clap(long)]
+    pub forget: bool,
+}
+
+/// Keep (or forget) the TOTP secret for each matching organization
+#[instrument(skip_all, fields(organizations=%args.organizations))]
+async fn register_totp(args: &MfaTotpArgs) -> Result<()> {
+    let organizations = find_organizations(&args.organizations).await?;
+
+    for organization in organizations {
+        let secret = Secret::okta_totp(&organization.name, &organization.username);
+
+        if args.forget {
+            if secret.delete()? {
+                println!("{}: forgot the TOTP secret", organization.name);
+            } else {
+                println!("{}: no TOTP secret registered", organization.name);
+            }
+            continue;
+        }
+
+        let value = askpass::secret(&format!(
+            "TOTP secret or otpauth:// URI for {} in {}",
+            organization.username, organization.name
+        ))?;
+        let generator = value.parse::<TotpGenerator>()?;
+        secret.set(value.trim())?;
+
+        println!(
+            "{}: registered; the current code is {}, which should match your authenticator app",
+            organization.name,
+            generator.code()
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct MfaCancelArgs {
+    /// Okta organizations to cancel pushes for
+    pub organizations: OrganizationPattern,
+
+    /// Also cancel pushes which other runs are still waiting for
+    #[clap(long)]
+    pub force: bool,
+}
+
+/// Cancel the pending pushes for each matching organization
+#[instrument(skip_all, fields(organizations=%args.organizations))]
+async fn cancel_mfa(args: &MfaCancelArgs) -> Result<()> {
+    let organizations = find_organizations(&args.organizations).await?;
+
+    for organization in organizations {
+        match transactions::cancel_orphaned(
+            &organization.portal,
+            &organization.username,
+            args.force,
+        )
+        .await?
+        {
+            0 => println!("{}: no pending pushes", organization.name),
+            1 => println!("{}: cancelled 1 pending push", organization.name),
+            cancelled => println!(
+                "{}: cancelled {cancelled} pending pushes",
+                organization.name
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+enum ConfigCommand {
+    /// Download organization configs installed with `init --from-url` again, keeping the local username
+    Update(ConfigUpdateArgs),
+}
+
+#[derive(Parser, Debug)]
+struct ConfigUpdateArgs {
+    /// Organizations to update (defaults to every one installed from a URL)
+    organizations: Vec<String>,
+
+    /// The SHA-256 digest (in hex) the new config must have, when updating a single organization
+    #[clap(long)]
+    sha256: Option<String>,
+}
+
+/// Download the shared organization configs again, replacing the installed ones
+async fn update_config(args: &ConfigUpdateArgs) -> Result<()> {
+    read_only::check("update configs")?;
+
+    let sources = SharedSources::load(&state_dir()?.join(shared::FILE_NAME))?;
+
+    if args.sha256.is_some() && args.organizations.len() != 1 {
+        return Err(eyre!(
+            "--sha256 pins a single organization's config; name it"
+        ));
+    }
+    if let Some(unknown) = args
+        .organizations
+        .iter()
+        .find(|organization| !sources.entries().any(|(name, _)| name == *organization))
+    {
+        return Err(eyre!(
+            "{unknown} was not installed from a URL; install it with `oktaws init --from-url`"
+        ));
+    }
+
+    let home = oktaws_home()?;
+    let mut updated = 0;
+
+    for (organization, source) in sources
+        .entries()
+        .filter(|(name, _)| args.organizations.is_empty() || args.organizations.contains(name))
+    {
+        let path = home.join(format!("{organization}.toml"));
+        let installed = std::fs::read_to_string(&path).unwrap_or_default();
+
+        let mut contents = source.fetch(args.sha256.as_deref()).await?;
+        if let Some(username) = shared::username(&installed) {
+            contents = shared::with_username(&contents, &username)?;
+        }
+        shared::validate(&path, &contents)?;
+
+        if contents == installed {
+            eprintln!("{organization} is up to date");
+        } else {
+            std::fs::write(&path, contents)?;
+            eprintln!("Updated {organization} from {}", source.url);
+            updated += 1;
+        }
+    }
+
+    if sources.entries().next().is_none() {
+        eprintln!("No organization configs were installed from a URL");
+    } else {
+        eprintln!("{updated} updated");
+    }
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+enum StateCommand {
+    /// Write the oktaws configuration to a passphrase-encrypted file (AWS credentials are not included)
+    Export(ExportArgs),
+
+    /// Restore the oktaws configuration from a file written by `state export`
+    Import(ImportArgs),
+}
+
+#[derive(Parser, Debug)]
+struct ExportArgs {
+    /// File to write the encrypted state to
+    #[clap(long)]
+    pub out: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct ImportArgs {
+    /// File written by `oktaws state export`
+    pub path: PathBuf,
+
+    /// Replace existing configuration files
+    #[clap(short, long)]
+    pub force: bool,
+}
+
+/// Encrypt the oktaws config and state directories into a single file
+fn export_state(args: &ExportArgs) -> Result<()> {
+    let mut bundle = StateBundle::collect(&oktaws_home()?, &state_dir()?, &cache_dir()?)?;
+    // A broken config is still worth moving, without its organizations' sessions
+    let organizations = "*"
+        .parse::<OrganizationPattern>()?
+        .organizations()
+        .unwrap_or_else(|e| {
+            warn!("Exporting no Okta sessions, as the organizations could not be loaded ({e})");
+            Vec::new()
+        });
+    bundle.collect_secrets(
+        organizations
+            .iter()
+            .map(|organization| organization.name.as_str()),
+    )?;
+
+    for path in bundle.paths().chain(bundle.secrets()) {
+        eprintln!("Exporting {path}");
+    }
+
+    let passphrase = dialoguer::Password::with_theme(theme::prompt())
+        .with_prompt("Passphrase for the export")
+        .with_confirmation("Confirm passphrase", "Passphrases do not match")
+        .interact()?;
+
+    std::fs::write(&args.out, bundle.encrypt(&passphrase)?)?;
+
+    Ok(())
+}
+
+/// Decrypt an export into the oktaws config and state directories
+fn import_state(args: &ImportArgs) -> Result<()> {
+    let data = std::fs::read(&args.path)?;
+
+    let passphrase = dialoguer::Password::with_theme(theme::prompt())
+        .with_prompt(format!("Passphrase for {}", args.path.display()))
+        .interact()?;
+
+    let bundle = StateBundle::decrypt(&data, &passphrase)?;
+
+    for path in bundle.restore(
+        &create_oktaws_home()?,
+        &state_dir()?,
+        &cache_dir()?,
+        args.force,
+    )? {
+        eprintln!("Imported {}", path.display());
+    }
+    for secret in bundle.restore_secrets()? {
+        eprintln!("Imported {secret}");
+    }
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct SupportBundleArgs {
+    /// File to write the tarball to
+    #[clap(long, default_value = "oktaws-support.tar")]
+    pub out: PathBuf,
+
+    /// Log files to include (redacted), e.g. from `oktaws refresh -vvvv 2> refresh.log`
+    #[clap(long = "log")]
+    pub logs: Vec<PathBuf>,
+
+    /// Don't check whether Okta and AWS can be reached
+    #[clap(long = "skip-checks")]
+    pub skip_checks: bool,
+
+    /// Write the bundle without reviewing what is in it
+    #[clap(short, long)]
+    pub yes: bool,
+}
+
+/// Collect a support bundle, and let the user review it before writing it
+#[instrument(skip_all, fields(out=%args.out.display()))]
+async fn support_bundle(args: &SupportBundleArgs, redactor: &Redactor) -> Result<()> {
+    let mut bundle = SupportBundle::new();
+    bundle.add_configs(&oktaws_home()?, redactor)?;
+    for log in &args.logs {
+        bundle.add_log(log, redactor)?;
+    }
+
+    if !args.skip_checks {
+        // A broken config is as likely to be the problem as anything else, so it doesn't stop the checks
+        let organizations = "*"
+            .parse::<OrganizationPattern>()?
+            .organizations()
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Checking AWS endpoints only, as the organizations could not be loaded ({e})"
+                );
+                Vec::new()
+            });
+        let targets = ping::targets(&organizations, &[String::from("us-east-1")])?;
+        let probes = ping::probe_all(targets, Duration::from_secs(10)).await?;
+        bundle.add("doctor.txt", redactor.redact(&ping::render(&probes)));
+    }
+
+    if !args.yes {
+        if !stdin().is_terminal() {
+            return Err(eyre!(
+                "Reviewing the bundle needs a terminal. Pass --yes to write it without reviewing"
+            ));
+        }
+
+        let names = bundle.names().map(ToString::to_string).collect::<Vec<_>>();
+        let included = dialoguer::MultiSelect::with_theme(theme::prompt())
+            .with_prompt("Files to include")
+            .items(&names)
+            .defaults(&vec![true; names.len()])
+            .interact()?
+            .into_iter()
+            .map(|index| names[index].clone())
+            .collect::<Vec<_>>();
+        bundle.retain(&included);
+
+        let show = dialoguer::Confirm::with_theme(theme::prompt())
+            .with_prompt("Show the contents before writing?")
+            .default(true)
+            .interact()?;
+        if show {
+            for name in &included {
+                eprintln!("--- {name}\n{}", bundle.get(name).unwrap_or_default());
+            }
+        }
+
+        let write = dialoguer::Confirm::with_theme(theme::prompt())
+            .with_prompt(format!(
+                "Write {} file(s) to {}?",
+                included.len(),
+                args.out.display()
+            ))
+            .default(true)
+            .interact()?;
+        if !write {
+            return Err(eyre!("Support bundle not written"));
+        }
+    }
+
+    bundle.write(&args.out)?;
+    println!("Wrote {}", args.out.display());
+
+    Ok(())
+}
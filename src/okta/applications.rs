@@ -1,15 +1,22 @@
 use crate::{
     aws::{get_account_alias, saml::extract_account_name},
     okta::client::Client,
+    progress::Progress,
+    theme,
 };
 
-use eyre::{Result, eyre};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use eyre::{Error, Result, eyre};
 use futures::future::join_all;
+use regex::Regex;
 use serde::Deserialize;
 use tracing::warn;
 use url::Url;
 
-use crate::aws::sso::{AppInstance, Client as SsoClient};
+use crate::aws::sso::{AccountNaming, AppInstance, Client as SsoClient};
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -19,20 +26,166 @@ pub struct AppLink {
     pub app_name: String,
 }
 
+impl AppLink {
+    /// Whether this links to an AWS application (federated or Identity Center)
+    #[must_use]
+    pub fn is_aws(&self) -> bool {
+        self.app_name == "amazon_aws" || self.app_name == "amazon_aws_sso"
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum IntegrationType {
     Federated,
     IdentityCenter,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct AppLinkAccountRoleMapping {
     pub account_name: String,
+    pub account_id: Option<String>,
+    pub account_email: Option<String>,
+    /// The account's description in AWS SSO, if it has one
+    pub account_description: Option<String>,
     pub role_names: Vec<String>,
     pub application_name: String,
     pub integration_type: IntegrationType,
 }
 
+/// Which accounts a discovery covers, so that organizations with hundreds of accounts need not be discovered in full
+#[derive(Clone, Debug, Default)]
+pub struct Scope {
+    /// Only accounts whose name matches
+    pub filter: Option<glob::Pattern>,
+    /// At most this many accounts
+    pub limit: Option<usize>,
+}
+
+impl Scope {
+    /// Whether every account is covered
+    #[must_use]
+    pub const fn is_everything(&self) -> bool {
+        self.filter.is_none() && self.limit.is_none()
+    }
+
+    /// Whether the account named `account_name` is covered
+    #[must_use]
+    pub fn matches(&self, account_name: &str) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(account_name))
+    }
+
+    /// Whether `count` accounts are as many as are wanted
+    #[must_use]
+    pub fn is_full(&self, count: usize) -> bool {
+        self.limit.is_some_and(|limit| count >= limit)
+    }
+
+    /// Drop mappings beyond the limit
+    pub fn truncate(&self, mappings: &mut Vec<AppLinkAccountRoleMapping>) {
+        if let Some(limit) = self.limit {
+            mappings.truncate(limit);
+        }
+    }
+}
+
+/// Which Okta application to use for an account reachable through more than one
+#[derive(Clone, Debug, Default)]
+pub enum Precedence {
+    /// Prefer Identity Center (AWS SSO) applications
+    #[default]
+    Sso,
+    /// Prefer federated (SAML) applications
+    Saml,
+    /// Prefer applications whose label matches the regex
+    Label(Regex),
+}
+
+impl FromStr for Precedence {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            _ if s.eq_ignore_ascii_case("sso") => Ok(Self::Sso),
+            _ if s.eq_ignore_ascii_case("saml") => Ok(Self::Saml),
+            Some(("label", pattern)) => Ok(Self::Label(Regex::new(pattern)?)),
+            _ => Err(eyre!(
+                "Unknown application precedence {s}, expected sso, saml or label:<regex>"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Precedence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sso => write!(f, "sso"),
+            Self::Saml => write!(f, "saml"),
+            Self::Label(pattern) => write!(f, "label:{pattern}"),
+        }
+    }
+}
+
+impl Precedence {
+    /// Sort key for a mapping; lower is preferred.
+    /// Ties are broken by application name, so the choice never depends on the order Okta lists tiles in.
+    fn rank(&self, mapping: &AppLinkAccountRoleMapping) -> (bool, String) {
+        let preferred = match self {
+            Self::Sso => mapping.integration_type == IntegrationType::IdentityCenter,
+            Self::Saml => mapping.integration_type == IntegrationType::Federated,
+            Self::Label(pattern) => pattern.is_match(&mapping.application_name),
+        };
+
+        (!preferred, mapping.application_name.clone())
+    }
+}
+
+/// Keep a single mapping for each account (by ID, or by name when the ID is unknown),
+/// choosing between duplicates by `precedence`, and warning about each account which had them.
+#[must_use]
+pub fn dedupe_account_mappings(
+    account_mappings: Vec<AppLinkAccountRoleMapping>,
+    precedence: &Precedence,
+) -> Vec<AppLinkAccountRoleMapping> {
+    let mut accounts = BTreeMap::<String, Vec<AppLinkAccountRoleMapping>>::new();
+    let mut order = Vec::new();
+
+    for mapping in account_mappings {
+        let key = mapping
+            .account_id
+            .clone()
+            .unwrap_or_else(|| mapping.account_name.clone());
+        if !accounts.contains_key(&key) {
+            order.push(key.clone());
+        }
+        accounts.entry(key).or_default().push(mapping);
+    }
+
+    order
+        .into_iter()
+        .filter_map(|key| {
+            let mut duplicates = accounts.remove(&key)?;
+            duplicates.sort_by_key(|mapping| precedence.rank(mapping));
+
+            if duplicates.len() > 1 {
+                warn!(
+                    "Account {key} is available through {} Okta applications ({}), using {} (precedence: {precedence})",
+                    duplicates.len(),
+                    duplicates
+                        .iter()
+                        .map(|mapping| mapping.application_name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    duplicates[0].application_name
+                );
+            }
+
+            duplicates.into_iter().next()
+        })
+        .collect()
+}
+
 pub struct SsoOrgAuth {
     pub org_id: String,
     pub auth_code: String,
@@ -92,11 +245,8 @@ impl Client {
     ///
     /// Will return `Err` if there are any network errors encountered
     pub async fn app_links(&self, user_id: Option<&str>) -> Result<Vec<AppLink>> {
-        self.get(&format!(
-            "api/v1/users/{}/appLinks",
-            user_id.unwrap_or("me")
-        ))
-        .await
+        self.get(&self.api(&format!("users/{}/appLinks", user_id.unwrap_or("me"))))
+            .await
     }
 
     /// Given an `amazon_aws` federated `AppLink`, visit it to get the account name and roles that can be assumed
@@ -146,8 +296,13 @@ impl Client {
 
         let application_name = link.label.clone();
 
+        let account_id = roles[0].role.account_id.as_ref().map(ToString::to_string);
+
         Ok(AppLinkAccountRoleMapping {
             account_name,
+            account_id,
+            account_email: None,
+            account_description: None,
             role_names,
             application_name,
             integration_type: IntegrationType::Federated,
@@ -164,6 +319,7 @@ impl Client {
         app_instance: &AppInstance,
         application_name: String,
         sso_client: &SsoClient,
+        naming: &AccountNaming,
     ) -> Result<AppLinkAccountRoleMapping> {
         let profiles = sso_client.profiles(&app_instance.id).await?;
 
@@ -176,7 +332,7 @@ impl Client {
 
         let mut role_names = profiles.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
         role_names.sort();
-        let account_name = app_instance.account_name().ok_or_else(|| {
+        let account_name = app_instance.account_name(naming).ok_or_else(|| {
             eyre!(
                 "No account name found for app instance: {}",
                 app_instance.name
@@ -185,13 +341,20 @@ impl Client {
 
         Ok(AppLinkAccountRoleMapping {
             account_name,
+            account_id: app_instance.account_id(naming).map(ToString::to_string),
+            account_email: app_instance.account_email().map(ToString::to_string),
+            account_description: Some(app_instance.description.clone())
+                .filter(|description| !description.is_empty()),
             role_names,
             application_name,
             integration_type: IntegrationType::IdentityCenter,
         })
     }
 
-    /// Given an `amazon_aws_sso` identity center `AppLink`, iterate through all app instances to get a list of all account names and roles that can be assumed
+    /// Given an `amazon_aws_sso` identity center `AppLink`, iterate through the app instances in `scope`
+    /// to get a list of their account names and roles that can be assumed.
+    /// Each page of app instances is done with before the next is listed, so that only one is held at a time,
+    /// and no more pages are listed once `scope`'s limit is reached.
     ///
     /// # Errors
     ///
@@ -199,38 +362,65 @@ impl Client {
     pub async fn get_sso_applink_accounts_and_roles(
         &self,
         app_link: AppLink,
+        naming: &AccountNaming,
+        scope: &Scope,
     ) -> Result<Vec<AppLinkAccountRoleMapping>> {
         let app_name = app_link.clone().label;
         let org_auth = self.get_org_id_and_auth_code_for_app_link(app_link).await?;
         let sso_client = SsoClient::new(&org_auth.org_id, &org_auth.auth_code).await?;
 
-        let app_instances = sso_client.app_instances().await?;
-        let app_aws_accounts = app_instances
-            .iter()
-            .filter(|app_instance| app_instance.application_name == "AWS Account")
-            .collect::<Vec<_>>();
-
+        let mut progress = Progress::new(&app_name);
         let mut all_account_role_mappings = Vec::new();
+        let mut pagination_token = None;
         let batch_size = 5;
-        for chunk in app_aws_accounts.chunks(batch_size) {
-            let mut futures = Vec::new();
-            for app_aws_account in chunk {
-                futures.push(self.get_sso_account_role_mapping(
-                    app_aws_account,
-                    app_name.clone(),
-                    &sso_client,
-                ));
+
+        loop {
+            let page = sso_client
+                .app_instances_page(pagination_token.as_deref())
+                .await?;
+            progress.listed(page.result.len(), page.pagination_token.is_none());
+
+            let (app_aws_accounts, skipped): (Vec<_>, Vec<_>) =
+                page.result.iter().partition(|app_instance| {
+                    app_instance.application_name == "AWS Account"
+                        && app_instance
+                            .account_name(naming)
+                            .is_none_or(|account_name| scope.matches(&account_name))
+                });
+            progress.skipped(skipped.len());
+
+            for chunk in app_aws_accounts.chunks(batch_size) {
+                let mut futures = Vec::new();
+                for app_aws_account in chunk {
+                    futures.push(self.get_sso_account_role_mapping(
+                        app_aws_account,
+                        app_name.clone(),
+                        &sso_client,
+                        naming,
+                    ));
+                }
+                let nested_account_role_mappings = futures::future::join_all(futures).await;
+                let account_role_mappings = nested_account_role_mappings
+                    .into_iter()
+                    .collect::<Result<Vec<AppLinkAccountRoleMapping>>>()?;
+                all_account_role_mappings.extend(account_role_mappings);
+                progress.done(chunk.len());
+
+                if scope.is_full(all_account_role_mappings.len()) {
+                    scope.truncate(&mut all_account_role_mappings);
+                    return Ok(all_account_role_mappings);
+                }
+            }
+
+            match page.pagination_token {
+                Some(token) => pagination_token = Some(token),
+                None => return Ok(all_account_role_mappings),
             }
-            let nested_account_role_mappings = futures::future::join_all(futures).await;
-            let account_role_mappings = nested_account_role_mappings
-                .into_iter()
-                .collect::<Result<Vec<AppLinkAccountRoleMapping>>>()?;
-            all_account_role_mappings.extend(account_role_mappings);
         }
-        Ok(all_account_role_mappings)
     }
 
-    /// Given a list of `AppLink`s, visit each of them to get a list of all account names and roles that can be assumed
+    /// Given a list of `AppLink`s, visit each of them to get a list of the account names (in `scope`)
+    /// and roles that can be assumed
     ///
     /// # Errors
     ///
@@ -238,6 +428,8 @@ impl Client {
     pub async fn get_all_account_mappings(
         &self,
         links: Vec<AppLink>,
+        naming: &AccountNaming,
+        scope: &Scope,
     ) -> Result<Vec<AppLinkAccountRoleMapping>> {
         let mut saml_role_futures = Vec::new();
         let mut all_role_names = Vec::new(); // We don't want to run sso app links concurrently due to rate limiting
@@ -245,7 +437,12 @@ impl Client {
             if link.app_name == "amazon_aws" {
                 saml_role_futures.push(self.get_saml_account_role_mapping(link));
             } else if link.app_name == "amazon_aws_sso" {
-                all_role_names.extend(self.get_sso_applink_accounts_and_roles(link).await?);
+                if !scope.is_full(all_role_names.len()) {
+                    all_role_names.extend(
+                        self.get_sso_applink_accounts_and_roles(link, naming, scope)
+                            .await?,
+                    );
+                }
             } else {
                 return Err(eyre!("Unsupported app name: {}", link.app_name));
             }
@@ -255,7 +452,14 @@ impl Client {
             .into_iter()
             .collect::<Result<Vec<AppLinkAccountRoleMapping>>>()?;
 
-        Ok([all_role_names, saml_roles].concat())
+        let mut mappings = [all_role_names, saml_roles]
+            .concat()
+            .into_iter()
+            .filter(|mapping| scope.matches(&mapping.account_name))
+            .collect::<Vec<_>>();
+        scope.truncate(&mut mappings);
+
+        Ok(mappings)
     }
 
     /// Given an identity center `AppLink`, return all app instances
@@ -303,7 +507,7 @@ impl Client {
         } else {
             let options = &["Identity Center", "Account Federation"];
 
-            let favored_integration = dialoguer::Select::new()
+            let favored_integration = dialoguer::Select::with_theme(theme::prompt())
                 .with_prompt(
                     "Overlapping accounts found in Identity Center and Federated AWS Account tiles. Which integration type do you want to favor?"
                 )
@@ -339,3 +543,43 @@ impl Client {
         Ok(filtered_account_role_mappings)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(
+        application_name: &str,
+        integration_type: IntegrationType,
+    ) -> AppLinkAccountRoleMapping {
+        AppLinkAccountRoleMapping {
+            account_name: String::from("prod"),
+            account_id: Some(String::from("123456789012")),
+            account_email: None,
+            account_description: None,
+            role_names: vec![String::from("Admin")],
+            application_name: application_name.to_string(),
+            integration_type,
+        }
+    }
+
+    fn chosen(precedence: &str) -> String {
+        let mappings = vec![
+            mapping("Legacy AWS", IntegrationType::Federated),
+            mapping("AWS SSO", IntegrationType::IdentityCenter),
+            mapping("AWS Break Glass", IntegrationType::Federated),
+        ];
+
+        let deduped = dedupe_account_mappings(mappings, &precedence.parse().unwrap());
+        assert_eq!(deduped.len(), 1);
+        deduped[0].application_name.clone()
+    }
+
+    #[test]
+    fn dedupes_by_precedence() {
+        assert_eq!(chosen("sso"), "AWS SSO");
+        assert_eq!(chosen("saml"), "AWS Break Glass");
+        assert_eq!(chosen("label:^Legacy"), "Legacy AWS");
+        assert!("fastest".parse::<Precedence>().is_err());
+    }
+}
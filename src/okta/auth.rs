@@ -1,5 +1,11 @@
+use crate::blocking;
+use crate::events::{self, Event};
 use crate::okta::client::Client;
-use crate::okta::factors::{Factor, FactorResult};
+use crate::okta::factors::FactorResult;
+use crate::okta::lockout::Blocked;
+use crate::okta::mfa::{self, MfaFactor};
+use crate::okta::password::{PasswordPolicy, User};
+use crate::theme;
 
 use dialoguer;
 use eyre::{Result, eyre};
@@ -73,17 +79,27 @@ struct Context {
 pub struct LoginResponse {
     pub state_token: Option<String>,
     pub session_token: Option<String>,
-    status: LoginState,
+    pub status: LoginState,
     pub factor_result: Option<FactorResult>,
     #[serde(rename = "_embedded")]
-    embedded: Option<LoginEmbedded>,
+    pub embedded: Option<LoginEmbedded>,
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct LoginEmbedded {
     #[serde(default)]
-    factors: Vec<Factor>,
+    factors: Vec<serde_json::Value>,
+    pub policy: Option<PasswordPolicy>,
+    pub user: Option<User>,
+}
+
+/// The result of a completed login
+#[derive(Debug)]
+pub struct Authenticated {
+    pub session_token: String,
+    /// The new password, if the old one had expired and was changed during login
+    pub new_password: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -121,7 +137,7 @@ impl Client {
             self.base_url()
         );
 
-        self.post("api/v1/authn", req).await
+        self.post(&self.api("authn"), req).await
     }
 
     /// Get a session token
@@ -133,61 +149,117 @@ impl Client {
     /// if a state token cannot be found in the response,
     /// or if there are MFA verification errors.
     pub async fn get_session_token(&self, req: &LoginRequest) -> Result<String> {
-        let response = self.login(req).await?;
-
-        trace!("Login response: {:?}", response);
-
-        match response.status {
-            LoginState::Success => response
-                .session_token
-                .ok_or_else(|| eyre!("Session token not found")),
-            LoginState::MfaRequired => {
-                let factors = response
-                    .embedded
-                    .map(|e| e.factors)
-                    .ok_or_else(|| eyre!("MFA required, but no factors found"))?
-                    .into_iter()
-                    .filter(Factor::is_supported)
-                    .collect::<Vec<_>>();
-
-                let factor = match factors.len() {
-                    0 => Err(eyre!(
-                        "MFA is required, but the user has no supported factors"
-                    )),
-                    1 => {
-                        info!(
-                            "Only one MFA option is supported ({}), using it",
-                            factors[0]
-                        );
-                        Ok(&factors[0])
-                    }
-                    _ => {
-                        let selection = dialoguer::Select::new()
-                            .with_prompt("Choose MFA Option")
-                            .items(&factors)
-                            .default(0)
-                            .interact()?;
-
-                        Ok(&factors[selection])
-                    }
-                }?;
-
-                debug!("Factor: {:?}", factor);
-
-                let state_token = response
-                    .state_token
-                    .ok_or_else(|| eyre!("No state token found in response"))?;
-
-                let factor_provided_response = self.verify(factor, state_token).await?;
-
-                trace!("Factor Provided Response: {:?}", factor_provided_response);
-
-                factor_provided_response
-                    .session_token
-                    .ok_or_else(|| eyre!("Session token not found"))
+        self.authenticate(req)
+            .await
+            .map(|authenticated| authenticated.session_token)
+    }
+
+    /// Log in, completing any MFA or password change steps Okta asks for along the way
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are any unrecoverable issues during login,
+    /// if there are IO problems while prompting for MFA or a new password,
+    /// if a state token cannot be found in the response,
+    /// or if there are MFA verification errors.
+    pub async fn authenticate(&self, req: &LoginRequest) -> Result<Authenticated> {
+        let mut response = self.login(req).await?;
+        let mut new_password = None;
+
+        loop {
+            trace!("Login response: {:?}", response);
+
+            response = match response.status {
+                LoginState::Success => {
+                    return Ok(Authenticated {
+                        session_token: response
+                            .session_token
+                            .ok_or_else(|| eyre!("Session token not found"))?,
+                        new_password,
+                    });
+                }
+                LoginState::MfaRequired => self.verify_mfa(response).await?,
+                LoginState::LockedOut => return Err(Blocked::LockedOut { until: None }.into()),
+                LoginState::PasswordExpired => {
+                    let (response, password) = self
+                        .change_expired_password(response, req.password.as_deref())
+                        .await?;
+                    new_password = Some(password);
+                    response
+                }
+                _ => return Err(eyre!("Unknown error encountered during login")),
+            };
+        }
+    }
+
+    async fn verify_mfa(&self, response: LoginResponse) -> Result<LoginResponse> {
+        let factors = response
+            .embedded
+            .map(|e| mfa::offered(e.factors))
+            .ok_or_else(|| eyre!("MFA required, but no factors found"))?
+            .into_iter()
+            .filter(|factor| factor.is_supported())
+            .collect::<Vec<_>>();
+
+        // A registered TOTP secret answers without interaction, so its factor is preferred
+        let mut registered_totp = None;
+        if let Some(index) = factors
+            .iter()
+            .position(|factor| factor.metadata().factor_type == "token:software:totp")
+        {
+            if self.totp_generator().await?.is_some() {
+                registered_totp = Some(index);
             }
-            _ => Err(eyre!("Unknown error encountered during login")),
         }
+
+        let factor = match (factors.len(), registered_totp) {
+            (0, _) => Err(eyre!(
+                "MFA is required, but the user has no supported factors"
+            )),
+            (_, Some(index)) => {
+                info!("Using {}, with the registered TOTP secret", factors[index]);
+                Ok(&factors[index])
+            }
+            (1, None) => {
+                info!(
+                    "Only one MFA option is supported ({}), using it",
+                    factors[0]
+                );
+                Ok(&factors[0])
+            }
+            _ => {
+                let items = factors.iter().map(ToString::to_string).collect::<Vec<_>>();
+                let selection = blocking::prompt(move || {
+                    dialoguer::Select::with_theme(theme::prompt())
+                        .with_prompt("Choose MFA Option")
+                        .items(&items)
+                        .default(0)
+                        .interact()
+                        .map_err(Into::into)
+                })
+                .await?;
+
+                Ok(&factors[selection])
+            }
+        }?;
+
+        debug!("Factor: {:?}", factor);
+
+        // As configured, as portals with their own base URL have hosts which aren't named after the organization
+        events::emit(&Event::MfaRequired {
+            organization: self.organization().to_string(),
+            factor: factor.to_string(),
+        });
+
+        let state_token = response
+            .state_token
+            .ok_or_else(|| eyre!("No state token found in response"))?;
+
+        let factor_provided_response = self.verify(factor.as_ref(), state_token).await?;
+
+        trace!("Factor Provided Response: {:?}", factor_provided_response);
+
+        Ok(factor_provided_response)
     }
 
     /// Check whether the page is asking for extra verification.
@@ -0,0 +1,362 @@
+//! What an Okta organization supports, probed once and cached.
+//!
+//! Organizations differ in the sign-in pipeline they run (Classic, or Identity Engine),
+//! in the factors their users are enrolled in, and in whether they trust devices through Okta Verify (FastPass).
+//! The pipeline is public, at `/.well-known/okta-organization`; the factors are recorded after every sign-in, and only
+//! block password sign-ins while that record is recent.
+//! Sign-in uses them to avoid paths which cannot work for the organization, and `oktaws doctor` reports them.
+
+use crate::okta::client::Client;
+use crate::okta::portal::Portal;
+use crate::retry;
+use crate::tls;
+use crate::transport::Transport;
+
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use eyre::Result;
+use reqwest::{Method, Request};
+use serde::{Deserialize, Serialize};
+
+/// The directory (in the oktaws cache directory) capabilities are kept in
+pub const DIR: &str = "capabilities";
+
+/// How long probed capabilities are trusted for, before probing again
+pub const MAX_AGE: TimeDelta = TimeDelta::days(7);
+
+/// The factor type of Okta Verify's FastPass, which proves the device is trusted
+pub const DEVICE_TRUST_FACTOR: &str = "signed_nonce";
+
+/// The sign-in pipeline an organization runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Pipeline {
+    /// Okta Classic, whose authentication API oktaws uses
+    #[serde(rename = "v1")]
+    Classic,
+    /// Okta Identity Engine, which only allows the Classic authentication API for some policies
+    #[serde(rename = "idx")]
+    IdentityEngine,
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Classic => write!(f, "Classic"),
+            Self::IdentityEngine => write!(f, "Identity Engine"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct WellKnown {
+    pipeline: Pipeline,
+}
+
+/// What an organization supports
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub pipeline: Pipeline,
+    /// The types of the factors the user is enrolled in, as of the last sign-in (empty before one)
+    #[serde(default)]
+    pub factor_types: Vec<String>,
+    /// Those of `factor_types` which oktaws can verify
+    #[serde(default)]
+    pub supported_factor_types: Vec<String>,
+    /// When the factors were last recorded
+    #[serde(default)]
+    pub factors_recorded_at: Option<DateTime<Utc>>,
+    pub probed_at: DateTime<Utc>,
+}
+
+impl Capabilities {
+    fn path(dir: &Path, organization: &str) -> PathBuf {
+        dir.join(format!("{organization}.json"))
+    }
+
+    /// The capabilities cached for `organization` in `dir`, if any
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file exists but cannot be read or parsed
+    pub fn load(dir: &Path, organization: &str) -> Result<Option<Self>> {
+        let path = Self::path(dir, organization);
+
+        if path.exists() {
+            Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Cache the capabilities for `organization` in `dir`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file cannot be written
+    pub fn save(&self, dir: &Path, organization: &str) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(
+            Self::path(dir, organization),
+            serde_json::to_string_pretty(self)?,
+        )
+        .map_err(Into::into)
+    }
+
+    /// Whether the capabilities should be probed again
+    #[must_use]
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        now - self.probed_at > MAX_AGE
+    }
+
+    /// Whether the user is enrolled in Okta Verify's FastPass
+    #[must_use]
+    pub fn device_trust(&self) -> bool {
+        self.factor_types
+            .iter()
+            .any(|factor_type| factor_type == DEVICE_TRUST_FACTOR)
+    }
+
+    /// Why signing in with a password cannot work for the organization, if it cannot.
+    /// Identity Engine only accepts the Classic authentication API when a factor it can verify is enrolled.
+    /// Factors recorded more than [`MAX_AGE`] before `now` may have changed since (e.g. a TOTP enrolled),
+    /// so they never block sign-in.
+    #[must_use]
+    pub fn password_sign_in_blocker(&self, now: DateTime<Utc>) -> Option<&'static str> {
+        let recent = self
+            .factors_recorded_at
+            .is_some_and(|recorded_at| now - recorded_at <= MAX_AGE);
+        let unverifiable =
+            recent && !self.factor_types.is_empty() && self.supported_factor_types.is_empty();
+
+        match self.pipeline {
+            Pipeline::IdentityEngine if unverifiable && self.device_trust() => Some(
+                "it runs Okta Identity Engine, and only Okta Verify FastPass can verify your sign-in",
+            ),
+            Pipeline::IdentityEngine if unverifiable => Some(
+                "it runs Okta Identity Engine, and none of your enrolled factors can be verified by oktaws",
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// The pipeline the organization at `portal` runs. Nothing is signed in to.
+///
+/// # Errors
+///
+/// Will return `Err` if Okta cannot be reached, or does not describe the organization
+pub async fn probe(portal: &Portal) -> Result<Pipeline> {
+    probe_with(&tls::client_builder().build()?, portal).await
+}
+
+/// The pipeline the organization at `portal` runs, asking through `transport`. Nothing is signed in to.
+///
+/// # Errors
+///
+/// Will return `Err` if Okta cannot be reached, or does not describe the organization
+pub async fn probe_with(transport: &dyn Transport, portal: &Portal) -> Result<Pipeline> {
+    let request = Request::new(
+        Method::GET,
+        portal.base_url().join(".well-known/okta-organization")?,
+    );
+    let well_known: WellKnown = retry::execute(transport, request)
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(well_known.pipeline)
+}
+
+/// The capabilities cached for the organization at `portal`,
+/// probing its pipeline again if they are missing, stale, or `refresh` is set
+///
+/// # Errors
+///
+/// Will return `Err` if the cache cannot be read or written, or the organization cannot be probed
+pub async fn cached(portal: &Portal, dir: &Path, refresh: bool) -> Result<Capabilities> {
+    let now = Utc::now();
+    let cached = Capabilities::load(dir, &portal.organization)?;
+
+    if let Some(capabilities) = &cached {
+        if !refresh && !capabilities.is_stale(now) {
+            return Ok(capabilities.clone());
+        }
+    }
+
+    let capabilities = match cached {
+        Some(cached) => Capabilities {
+            pipeline: probe(portal).await?,
+            probed_at: now,
+            ..cached
+        },
+        None => Capabilities {
+            pipeline: probe(portal).await?,
+            factor_types: Vec::new(),
+            supported_factor_types: Vec::new(),
+            factors_recorded_at: None,
+            probed_at: now,
+        },
+    };
+    capabilities.save(dir, &portal.organization)?;
+
+    Ok(capabilities)
+}
+
+/// Record the factors the signed-in user is enrolled in, alongside the organization's pipeline
+///
+/// # Errors
+///
+/// Will return `Err` if the factors cannot be listed, or the cache cannot be written
+pub async fn record_factors(client: &Client, portal: &Portal, dir: &Path) -> Result<Capabilities> {
+    let mut capabilities = cached(portal, dir, false).await?;
+
+    let enrolled = client.enrolled_factor_types().await?;
+    capabilities.factor_types = enrolled
+        .iter()
+        .map(|(factor_type, _)| factor_type.clone())
+        .collect();
+    capabilities.supported_factor_types = enrolled
+        .into_iter()
+        .filter_map(|(factor_type, supported)| supported.then_some(factor_type))
+        .collect();
+    capabilities.factors_recorded_at = Some(Utc::now());
+    capabilities.save(dir, &portal.organization)?;
+
+    Ok(capabilities)
+}
+
+/// A description of an organization's capabilities, for `oktaws doctor`
+#[must_use]
+pub fn render(organization: &str, capabilities: &Capabilities) -> String {
+    let mut report = format!("{organization}\n  Pipeline: {}\n", capabilities.pipeline);
+
+    if capabilities.factor_types.is_empty() {
+        report.push_str("  Enrolled factors: unknown until the next password sign-in\n");
+    } else {
+        let factor_types = capabilities
+            .factor_types
+            .iter()
+            .map(|factor_type| {
+                if capabilities.supported_factor_types.contains(factor_type) {
+                    factor_type.clone()
+                } else {
+                    format!("{factor_type} (not supported by oktaws)")
+                }
+            })
+            .collect::<Vec<_>>();
+        let _ = writeln!(report, "  Enrolled factors: {}", factor_types.join(", "));
+    }
+
+    let _ = writeln!(
+        report,
+        "  Device trust: {}",
+        if capabilities.device_trust() {
+            "Okta Verify FastPass"
+        } else {
+            "none"
+        }
+    );
+
+    let _ = writeln!(
+        report,
+        "  Password sign-in: {}",
+        capabilities
+            .password_sign_in_blocker(Utc::now())
+            .map_or_else(
+                || String::from("possible"),
+                |reason| format!("not possible, as {reason}")
+            )
+    );
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::transport::Canned;
+
+    #[test]
+    fn identity_engine_with_only_fastpass_blocks_passwords() -> Result<()> {
+        let now = Utc::now();
+        let mut capabilities = Capabilities {
+            pipeline: serde_json::from_str::<WellKnown>(
+                r#"{ "id": "00o1", "pipeline": "idx", "_links": {} }"#,
+            )?
+            .pipeline,
+            factor_types: Vec::new(),
+            supported_factor_types: Vec::new(),
+            factors_recorded_at: Some(now),
+            probed_at: now,
+        };
+
+        assert_eq!(capabilities.pipeline, Pipeline::IdentityEngine);
+        assert_eq!(capabilities.password_sign_in_blocker(now), None);
+
+        capabilities.factor_types = vec![String::from(DEVICE_TRUST_FACTOR)];
+        assert!(capabilities.device_trust());
+        assert!(capabilities.password_sign_in_blocker(now).is_some());
+
+        // Until the factors are recorded again
+        assert_eq!(
+            capabilities.password_sign_in_blocker(now + MAX_AGE + TimeDelta::days(1)),
+            None
+        );
+
+        capabilities
+            .factor_types
+            .push(String::from("token:software:totp"));
+        capabilities.supported_factor_types = vec![String::from("token:software:totp")];
+        assert_eq!(capabilities.password_sign_in_blocker(now), None);
+
+        capabilities.pipeline = Pipeline::Classic;
+        capabilities.factor_types = vec![String::from(DEVICE_TRUST_FACTOR)];
+        capabilities.supported_factor_types = Vec::new();
+        assert_eq!(capabilities.password_sign_in_blocker(now), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn probes_through_the_transport() -> Result<()> {
+        let transport = Canned::new().respond(200, r#"{ "id": "00o1", "pipeline": "v1" }"#);
+        let portal = Portal::new("mock_org")?;
+
+        assert_eq!(probe_with(&transport, &portal).await?, Pipeline::Classic);
+        assert_eq!(
+            transport.requests(),
+            vec![(
+                Method::GET,
+                portal.base_url().join(".well-known/okta-organization")?
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn renders_capabilities() {
+        let capabilities = Capabilities {
+            pipeline: Pipeline::IdentityEngine,
+            factor_types: vec![String::from("push"), String::from(DEVICE_TRUST_FACTOR)],
+            supported_factor_types: vec![String::from("push")],
+            factors_recorded_at: Some(Utc::now()),
+            probed_at: Utc::now(),
+        };
+
+        assert_eq!(
+            render("mock_org", &capabilities),
+            "mock_org
+  Pipeline: Identity Engine
+  Enrolled factors: push, signed_nonce (not supported by oktaws)
+  Device trust: Okta Verify FastPass
+  Password sign-in: possible
+"
+        );
+    }
+}
@@ -1,16 +1,23 @@
+use crate::askpass;
+use crate::blocking;
+use crate::events::{self, Event};
 use crate::okta::auth::LoginRequest;
+use crate::okta::lockout::Blocked;
+use crate::okta::portal::Portal;
+use crate::retry;
+use crate::secrets::{self, Secret};
+use crate::tls;
+use crate::transport::Transport;
 
 use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::SystemTime;
 
-use backoff::ExponentialBackoff;
-use backoff::future::retry;
-use dialoguer::Password;
-use eyre::{Result, eyre};
-use reqwest::Response;
-use reqwest::cookie::Jar;
-use reqwest::header::{ACCEPT, HeaderValue};
-use reqwest::{Client as HttpClient, StatusCode};
+use eyre::{Report, Result, eyre};
+use reqwest::Client as HttpClient;
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{ACCEPT, HeaderMap, HeaderValue};
+use reqwest::{RequestBuilder, Response};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, warn};
@@ -19,7 +26,12 @@ use url::Url;
 #[derive(Debug)]
 pub struct Client {
     http: HttpClient,
+    /// What sends the requests built with `http` (by default, `http` itself)
+    transport: Arc<dyn Transport>,
     base_url: Url,
+    portal: Portal,
+    /// The organization's extra headers, with their values
+    headers: HeaderMap,
     pub cookies: Arc<Jar>,
 }
 
@@ -29,6 +41,8 @@ pub enum OktaError {
     AuthenticationException(String),
     #[error("Too many requests")]
     TooManyRequestsException(String),
+    #[error("User locked out")]
+    LockedOutException(String),
     #[error("{0}")]
     Unknown(RawOktaError),
 }
@@ -38,6 +52,7 @@ impl From<RawOktaError> for OktaError {
         match &*error.code {
             "E0000004" => Self::AuthenticationException(error.id),
             "E0000047" => Self::TooManyRequestsException(error.id),
+            "E0000069" => Self::LockedOutException(error.id),
             _ => Self::Unknown(error),
         }
     }
@@ -57,61 +72,92 @@ pub struct RawOktaError {
 }
 
 impl Client {
-    /// Create a new client for an Okta organization
+    /// Create a new client for an Okta organization's portal
     ///
     /// # Errors
     ///
     /// Will return `Err` if a URL cannot be constructed for the organization,
     /// or if there are underlying HTTP client creation issues.
-    pub async fn new(organization: String, username: String, force_prompt: bool) -> Result<Self> {
-        let mut base_url = Url::parse(&format!("https://{organization}.okta.com/"))?;
-        base_url
-            .set_username(&username)
-            .map_err(|()| eyre!("Cannot set username for URL"))?;
-
-        let cookies = Arc::from(Jar::default());
+    pub async fn new(portal: &Portal, username: String, force_prompt: bool) -> Result<Self> {
+        Self::sign_in(portal, username, force_prompt, None).await
+    }
 
-        let mut client = Self {
-            http: HttpClient::builder()
-                .cookie_store(true)
-                .cookie_provider(cookies.clone())
-                .build()?,
-            base_url: base_url.clone(),
-            cookies,
-        };
+    /// Create a new client for an Okta organization's portal, sending every request through `transport`.
+    /// Cookies are only kept by transports which keep them, so the transport should usually wrap a reqwest client with a cookie store.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a URL cannot be constructed for the organization,
+    /// or if there are underlying HTTP client creation issues.
+    pub async fn with_transport(
+        portal: &Portal,
+        username: String,
+        force_prompt: bool,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Self> {
+        Self::sign_in(portal, username, force_prompt, Some(transport)).await
+    }
 
-        // Visit the homepage to get a DeviceToken (DT) cookie (used for persisting MFA information).
-        client.get_response(base_url).await?;
+    async fn sign_in(
+        portal: &Portal,
+        username: String,
+        force_prompt: bool,
+        transport: Option<Arc<dyn Transport>>,
+    ) -> Result<Self> {
+        let organization = portal.organization.clone();
+        let mut client = Self::unauthenticated_with(portal, &username, transport).await?;
+        client.restore_device_token().await;
+
+        let secret = Secret::okta_password(&organization, &username);
+
+        // A push left waiting by an interrupted run would keep prompting alongside this run's
+        match client.cancel_orphaned_transactions(false).await {
+            Ok(0) => {}
+            Ok(cancelled) => debug!("Cancelled {cancelled} orphaned transactions"),
+            Err(e) => warn!("Unable to cancel orphaned Okta transactions ({e})"),
+        }
 
-        let service = format!("oktaws::okta::{organization}");
-        let keyring = keyring::Entry::new(&service, &username)?;
+        events::emit(&Event::AuthStarted {
+            organization: organization.clone(),
+            username: username.clone(),
+        });
 
         // get password
-        let password = client.get_password(&keyring, force_prompt)?;
+        let password = client.get_password(&secret, force_prompt).await?;
         let login_request = LoginRequest::from_credentials(username.clone(), password.clone());
 
         // Do the login
-        let session_token = match client.get_session_token(&login_request).await {
-            Ok(session_token) => {
-                // Save the password.
-                client.set_cached_password(&keyring, &password);
-
-                Ok(session_token)
+        let session_token = match client.authenticate_patiently(&login_request).await {
+            Ok(authenticated) => {
+                // Save the password (or its replacement, if it had expired).
+                client
+                    .set_cached_password(
+                        &secret,
+                        authenticated.new_password.as_deref().unwrap_or(&password),
+                    )
+                    .await;
+
+                Ok(authenticated.session_token)
             }
             Err(wrapped_error) => {
                 if let Some(OktaError::AuthenticationException(_)) = wrapped_error.downcast_ref() {
                     warn!("Authentication failed, re-prompting for Okta credentials");
 
-                    let password = client.prompt_password()?;
+                    let password = client.prompt_password().await?;
                     let login_request =
                         LoginRequest::from_credentials(username.clone(), password.clone());
 
-                    let session_token = client.get_session_token(&login_request).await?;
+                    let authenticated = client.authenticate_patiently(&login_request).await?;
 
-                    // Save the password.
-                    client.set_cached_password(&keyring, &password);
+                    // Save the password (or its replacement, if it had expired).
+                    client
+                        .set_cached_password(
+                            &secret,
+                            authenticated.new_password.as_deref().unwrap_or(&password),
+                        )
+                        .await;
 
-                    Ok(session_token)
+                    Ok(authenticated.session_token)
                 } else {
                     Err(wrapped_error)
                 }
@@ -119,6 +165,58 @@ impl Client {
         }?;
 
         client.new_session(session_token, &HashSet::new()).await?;
+        client.keep_device_token().await;
+
+        Ok(client)
+    }
+
+    /// Create a client for an Okta organization's portal, without signing in
+    pub(crate) async fn unauthenticated(portal: &Portal, username: &str) -> Result<Self> {
+        Self::unauthenticated_with(portal, username, None).await
+    }
+
+    /// Create a client without signing in, sending requests through `transport` (or its own HTTP client)
+    async fn unauthenticated_with(
+        portal: &Portal,
+        username: &str,
+        transport: Option<Arc<dyn Transport>>,
+    ) -> Result<Self> {
+        let mut base_url = portal.base_url().clone();
+        base_url
+            .set_username(username)
+            .map_err(|()| eyre!("Cannot set username for URL"))?;
+
+        let cookies = Arc::from(Jar::default());
+
+        let http = tls::client_builder()
+            .cookie_store(true)
+            .cookie_provider(cookies.clone())
+            .build()?;
+
+        let mut client = Self {
+            transport: transport.unwrap_or_else(|| Arc::new(http.clone())),
+            http,
+            base_url: base_url.clone(),
+            portal: portal.clone(),
+            headers: Self::extra_headers(portal, false).await?,
+            cookies,
+        };
+
+        // Visit the homepage to get a DeviceToken (DT) cookie (used for persisting MFA information).
+        match client.get_response(base_url.clone()).await {
+            // The headers' values kept from before may have been replaced since
+            Err(e) if !portal.headers().is_empty() && is_forbidden(&e) => {
+                warn!(
+                    "{} refused the extra headers' values, so asking for them again",
+                    portal.organization
+                );
+                client.headers = Self::extra_headers(portal, true).await?;
+                client.get_response(base_url).await?;
+            }
+            response => {
+                response?;
+            }
+        }
 
         Ok(client)
     }
@@ -128,35 +226,91 @@ impl Client {
         &self.base_url
     }
 
+    /// The Okta organization the client is for
+    #[must_use]
+    pub fn organization(&self) -> &str {
+        &self.portal.organization
+    }
+
+    /// The path (relative to the base URL) of an Okta API endpoint, e.g. `authn`
+    #[must_use]
+    pub fn api(&self, path: &str) -> String {
+        self.portal.api(path)
+    }
+
+    /// The extra headers to send with a request to `url`.
+    /// They are only sent to the organization's own host, never to AWS or anywhere else Okta links to.
+    fn headers_for(&self, url: &Url) -> HeaderMap {
+        if url.host_str() == self.base_url.host_str() {
+            self.headers.clone()
+        } else {
+            HeaderMap::new()
+        }
+    }
+
+    /// The DeviceToken (`DT`) cookie Okta gave the client
+    fn device_token(&self) -> Option<String> {
+        self.cookies
+            .cookies(&self.base_url)?
+            .to_str()
+            .ok()?
+            .split("; ")
+            .find_map(|cookie| cookie.strip_prefix("DT="))
+            .map(ToString::to_string)
+    }
+
+    /// Send the device token kept from the last sign-in (in place of the one the homepage gave),
+    /// so that Okta recognizes the device, and doesn't ask for an MFA it remembers
+    async fn restore_device_token(&self) {
+        let secret = Secret::okta_device(self.organization());
+
+        match blocking::run(move || secret.get()).await {
+            Ok(Some(token)) => self
+                .cookies
+                .add_cookie_str(&format!("DT={token}; Path=/"), &self.base_url),
+            Ok(None) => {}
+            Err(e) => debug!("Unable to read the Okta device token: {e}"),
+        }
+    }
+
+    /// Keep the device token Okta signed in with, for the next sign-in to send
+    async fn keep_device_token(&self) {
+        let Some(token) = self.device_token().filter(|_| secrets::kept()) else {
+            return;
+        };
+        let secret = Secret::okta_device(self.organization());
+
+        if let Err(e) = blocking::run(move || secret.set(&token)).await {
+            warn!("Unable to keep the Okta device token: {e}");
+        }
+    }
+
     pub fn set_session_id(&mut self, session_id: &str) {
         self.cookies
             .add_cookie_str(&format!("sid={session_id}"), &self.base_url);
     }
 
+    /// Send `request` through the client's transport, retrying as [`retry::execute`] does
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        Ok(retry::execute(&*self.transport, request.build()?).await?)
+    }
+
     /// Given an absolute URL (not just a path), perform a GET request against it
-    /// This method attempts to retry if the response indicates rate-limiting.
+    /// This method retries if the response indicates rate-limiting or a server error (see [`retry::execute`]).
     ///
     /// # Errors
     ///
     /// Will return `Err` if there are any errors performing the GET operation.
     pub async fn get_response(&self, url: Url) -> Result<Response> {
-        retry(ExponentialBackoff::default(), || async {
-            let resp = self.http.get(url.clone()).send().await?;
+        let resp = self
+            .send(self.http.get(url.clone()).headers(self.headers_for(&url)))
+            .await?;
 
-            if resp.status() == StatusCode::TOO_MANY_REQUESTS || resp.status().is_server_error() {
-                resp.error_for_status().map_err(backoff::Error::transient)
-            } else if resp.status().is_client_error() {
-                resp.error_for_status().map_err(backoff::Error::permanent)
-            } else {
-                Ok(resp)
-            }
-        })
-        .await
-        .map_err(Into::into)
+        resp.error_for_status().map_err(Into::into)
     }
 
     /// Given a relative path, perform a GET request against it (using the client's base url)
-    /// This method attempts to retry if the response indicates rate-limiting.
+    /// This method retries if the response indicates rate-limiting or a server error (see [`retry::execute`]).
     ///
     /// # Errors
     ///
@@ -166,43 +320,22 @@ impl Client {
     where
         O: DeserializeOwned,
     {
-        retry(ExponentialBackoff::default(), || async {
-            let url = self
-                .base_url
-                .join(path)
-                .map_err(eyre::Error::from)
-                .map_err(backoff::Error::Permanent)?;
-
-            let resp = self
-                .http
-                .get(url)
-                .header(ACCEPT, HeaderValue::from_static("application/json"))
-                .send()
-                .await
-                .map_err(eyre::Error::from)
-                .map_err(backoff::Error::Permanent)?;
-
-            if resp.status().is_success() {
-                resp.json()
-                    .await
-                    .map_err(eyre::Error::from)
-                    .map_err(backoff::Error::Permanent)
-            } else {
-                let error: OktaError = resp
-                    .json::<RawOktaError>()
-                    .await
-                    .map_err(eyre::Error::from)
-                    .map_err(backoff::Error::Permanent)?
-                    .into();
-
-                if let OktaError::TooManyRequestsException(_) = error {
-                    Err(backoff::Error::transient(eyre::Error::from(error)))
-                } else {
-                    Err(backoff::Error::permanent(eyre::Error::from(error)))
-                }
-            }
-        })
-        .await
+        let url = self.base_url.join(path)?;
+
+        let resp = self
+            .send(
+                self.http
+                    .get(url.clone())
+                    .headers(self.headers_for(&url))
+                    .header(ACCEPT, HeaderValue::from_static("application/json")),
+            )
+            .await?;
+
+        if resp.status().is_success() {
+            resp.json().await.map_err(Into::into)
+        } else {
+            Err(Self::error_from(resp).await?)
+        }
     }
 
     /// Given a relative path, POST the body to it (using the client's base url)
@@ -220,6 +353,8 @@ impl Client {
     }
 
     /// Given an absolute URL (not just a path), POST the body to it.
+    /// This method retries if the response indicates rate-limiting, but not on server errors,
+    /// as Okta may already have acted on the request.
     ///
     /// # Errors
     ///
@@ -231,25 +366,67 @@ impl Client {
         O: DeserializeOwned,
     {
         let resp = self
-            .http
-            .post(url)
-            .json(body)
-            .header(ACCEPT, HeaderValue::from_static("application/json"))
-            .send()
+            .send(
+                self.http
+                    .post(url.clone())
+                    .headers(self.headers_for(&url))
+                    .json(body)
+                    .header(ACCEPT, HeaderValue::from_static("application/json")),
+            )
             .await?;
 
         if resp.status().is_success() {
             resp.json().await.map_err(Into::into)
         } else {
-            Err(resp.json::<RawOktaError>().await?.into())
+            Err(Self::error_from(resp).await?)
         }
     }
 
-    fn prompt_password(&self) -> Result<String> {
-        Password::new()
-            .with_prompt(format!("Password for {}", self.base_url))
-            .interact()
-            .map_err(Into::into)
+    /// Given a relative path, perform a DELETE request against it (using the client's base url).
+    /// This method only retries if the response indicates rate-limiting (see [`retry::execute`]).
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are any errors performing the DELETE operation.
+    pub async fn delete(&self, path: &str) -> Result<()> {
+        let url = self.base_url.join(path)?;
+
+        let resp = self
+            .send(
+                self.http
+                    .delete(url.clone())
+                    .headers(self.headers_for(&url))
+                    .header(ACCEPT, HeaderValue::from_static("application/json")),
+            )
+            .await?;
+
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(Self::error_from(resp).await?)
+        }
+    }
+
+    /// The error in an unsuccessful response, saying when to try again if Okta is throttling or has locked the user out
+    async fn error_from(resp: Response) -> Result<Report> {
+        let delay = retry::retry_after(&resp);
+        let error = OktaError::from(resp.json::<RawOktaError>().await?);
+
+        Ok(match error {
+            OktaError::TooManyRequestsException(_) => {
+                Report::new(error).wrap_err(Blocked::throttled_for(delay))
+            }
+            OktaError::LockedOutException(_) => Report::new(error).wrap_err(Blocked::LockedOut {
+                until: delay.map(|delay| SystemTime::now() + delay),
+            }),
+            error => error.into(),
+        })
+    }
+
+    async fn prompt_password(&self) -> Result<String> {
+        let prompt = format!("Password for {}", self.base_url);
+
+        blocking::prompt(move || askpass::secret(&prompt)).await
     }
 
     /// Return the password for authenticating with this client
@@ -258,35 +435,120 @@ impl Client {
     ///
     /// Will return `Err` if there are any IO errors during password prompting,
     /// or if there were errors encountered while retrieving the password from the cache.
-    pub fn get_password(&self, keyring: &keyring::Entry, force_prompt: bool) -> Result<String> {
+    pub async fn get_password(&self, secret: &Secret, force_prompt: bool) -> Result<String> {
         // If the user chooses to force new credentials, prompt them for them
         if force_prompt {
-            self.prompt_password()
-        } else {
-            Self::get_cached_password(keyring).or_else(|_| self.prompt_password())
+            return self.prompt_password().await;
+        }
+
+        // Vault is the source of truth when configured; the secret store's copy is only a fallback
+        #[cfg(feature = "vault")]
+        if let Some(source) = self.portal.password_vault().cloned() {
+            match blocking::run(move || source.read()).await {
+                Ok(password) => return Ok(password),
+                Err(e) => warn!("Unable to read the Okta password from Vault ({e})"),
+            }
+        }
+
+        match Self::get_cached_password(secret).await {
+            Ok(password) => Ok(password),
+            Err(_) => self.prompt_password().await,
         }
     }
 
-    fn get_cached_password(keyring: &keyring::Entry) -> Result<String> {
-        keyring.get_password().map_err(Into::into)
+    /// The values of the portal's extra headers, from the secret store.
+    /// Any which are not there yet (or all of them, if `force_prompt` is set) are prompted for, and saved.
+    async fn extra_headers(portal: &Portal, force_prompt: bool) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+
+        for name in portal.headers() {
+            let secret = Secret::okta_header(&portal.organization, name.as_str());
+
+            let kept = if force_prompt {
+                None
+            } else {
+                let secret = secret.clone();
+                blocking::run(move || secret.get()).await.map_err(|e| {
+                    e.wrap_err(format!("Unable to read the value of the {name} header"))
+                })?
+            };
+
+            let value = match kept {
+                Some(value) => value,
+                None => {
+                    let prompt = format!("Value of the {name} header for {}", portal.organization);
+                    let value = blocking::prompt(move || askpass::secret(&prompt)).await?;
+
+                    if secrets::kept() {
+                        let saved = value.clone();
+                        blocking::run(move || secret.set(&saved)).await?;
+                    }
+
+                    value
+                }
+            };
+
+            let mut value = HeaderValue::from_str(&value)
+                .map_err(|_| eyre!("The value of the {name} header is not valid in a header"))?;
+            value.set_sensitive(true);
+            headers.insert(name.clone(), value);
+        }
+
+        Ok(headers)
+    }
+
+    /// Remove the cached password for `username` in `organization`,
+    /// returning whether there was one to remove
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the secret store cannot be accessed
+    pub fn forget_password(organization: &str, username: &str) -> Result<bool> {
+        Secret::okta_password(organization, username).delete()
     }
 
-    pub fn set_cached_password(&self, keyring: &keyring::Entry, password: &str) {
+    async fn get_cached_password(secret: &Secret) -> Result<String> {
+        let secret = secret.clone();
+        blocking::run(move || {
+            secret
+                .get()?
+                .ok_or_else(|| eyre!("No password has been saved"))
+        })
+        .await
+    }
+
+    pub async fn set_cached_password(&self, secret: &Secret, password: &str) {
+        if !secrets::kept() {
+            return;
+        }
         debug!("Saving Okta credentials for {}", self.base_url);
 
+        let (secret, password) = (secret.clone(), password.to_string());
+        let saved = blocking::run(move || secret.set(&password)).await;
+
         // Don't treat this as a failure, as it is not a hard requirement
-        if let Err(e) = keyring.set_password(password) {
+        if let Err(e) = saved {
             warn!("Error while saving credentials: {}", e);
         }
     }
 }
 
+/// Whether `error` is Okta (or something in front of it) refusing a request as forbidden
+fn is_forbidden(error: &Report) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status)
+            == Some(reqwest::StatusCode::FORBIDDEN)
+    })
+}
+
 #[cfg(test)]
 mockall::mock! {
     pub Client {
         pub fn base_url(&self) -> &Url;
         pub async fn app_links(&self, user_id: Option<()>) -> Result<Vec<crate::okta::applications::AppLink>>;
-        pub async fn get_all_account_mappings(&self, links: Vec<crate::okta::applications::AppLink>) -> Result<Vec<crate::okta::applications::AppLinkAccountRoleMapping>>;
+        pub async fn get_all_account_mappings(&self, links: Vec<crate::okta::applications::AppLink>, naming: &crate::aws::sso::AccountNaming, scope: &crate::okta::applications::Scope) -> Result<Vec<crate::okta::applications::AppLinkAccountRoleMapping>>;
         pub fn remove_overlapped_account_mappings(&self, account_mappings: Vec<crate::okta::applications::AppLinkAccountRoleMapping>) -> Result<Vec<crate::okta::applications::AppLinkAccountRoleMapping>>;
         pub async fn get_saml_response(&self, url: Url) -> Result<crate::aws::saml::Response>;
         pub async fn get_response(&self, url: Url) -> Result<Response>;
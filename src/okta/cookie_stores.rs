@@ -0,0 +1,405 @@
+//! Reading the Okta session cookie straight from a browser's own cookie store, for `--import-cookies firefox` (or `chrome`).
+//!
+//! The stores are SQLite databases, which the browser keeps locked while it runs,
+//! so they are copied (with their write-ahead logs) to a private temporary directory and read from there.
+//! Firefox keeps cookie values in the clear. Chrome encrypts them (as `v10` or `v11` values) with AES-128-CBC,
+//! under a key derived from a password it keeps in the OS keyring: the Secret Service on Linux, the Keychain on macOS.
+//! Chrome on Windows binds its key to the browser itself, so only exports and Firefox are read there.
+
+use crate::okta::cookies::domain_matches;
+
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use aes::Aes128;
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use eyre::{Error, Result, eyre};
+use ring::digest::SHA256_OUTPUT_LEN;
+use ring::pbkdf2::{self, PBKDF2_HMAC_SHA1};
+use rusqlite::{Connection, OptionalExtension};
+use tracing::debug;
+
+/// The table Firefox keeps cookies in
+const FIREFOX_TABLE: &str = "moz_cookies";
+
+/// Chrome's key derivation salt, and its initialization vector (16 spaces)
+const CHROME_SALT: &[u8] = b"saltysalt";
+const CHROME_IV: [u8; 16] = [b' '; 16];
+
+/// The password Chrome on Linux uses for `v10` values, when no keyring is available
+const CHROME_FALLBACK_PASSWORD: &[u8] = b"peanuts";
+
+/// From this version of Chrome's database, each decrypted value starts with the SHA-256 of its host
+const CHROME_HASHED_HOST_VERSION: i64 = 24;
+
+/// A browser whose cookie store can be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Browser {
+    Firefox,
+    Chrome,
+    Chromium,
+}
+
+impl FromStr for Browser {
+    type Err = Error;
+
+    fn from_str(browser: &str) -> Result<Self> {
+        match browser.to_ascii_lowercase().as_str() {
+            "firefox" => Ok(Self::Firefox),
+            "chrome" => Ok(Self::Chrome),
+            "chromium" => Ok(Self::Chromium),
+            _ => Err(eyre!("Unknown browser {browser}")),
+        }
+    }
+}
+
+impl Browser {
+    /// The cookie stores of each of the browser's profiles, most recently written first
+    #[must_use]
+    pub fn stores(self) -> Vec<PathBuf> {
+        let mut stores = self
+            .patterns()
+            .iter()
+            .filter_map(|pattern| glob::glob(&pattern.to_string_lossy()).ok())
+            .flatten()
+            .filter_map(Result::ok)
+            .collect::<Vec<_>>();
+
+        stores.sort_by_key(|store| {
+            std::cmp::Reverse(
+                fs::metadata(store)
+                    .and_then(|metadata| metadata.modified())
+                    .ok(),
+            )
+        });
+        stores
+    }
+
+    fn patterns(self) -> Vec<PathBuf> {
+        let home = dirs::home_dir().unwrap_or_default();
+        let config = dirs::config_dir().unwrap_or_default();
+
+        match self {
+            Self::Firefox if cfg!(target_os = "linux") => vec![
+                home.join(".mozilla/firefox/*/cookies.sqlite"),
+                // Firefox as a snap
+                home.join("snap/firefox/common/.mozilla/firefox/*/cookies.sqlite"),
+            ],
+            Self::Firefox if cfg!(windows) => {
+                vec![config.join("Mozilla/Firefox/Profiles/*/cookies.sqlite")]
+            }
+            Self::Firefox => vec![config.join("Firefox/Profiles/*/cookies.sqlite")],
+            Self::Chrome | Self::Chromium => {
+                let dir = config.join(match (self, cfg!(target_os = "macos")) {
+                    (Self::Chrome, true) => "Google/Chrome",
+                    (Self::Chrome, false) => "google-chrome",
+                    (_, true) => "Chromium",
+                    (_, false) => "chromium",
+                });
+                vec![dir.join("*/Cookies"), dir.join("*/Network/Cookies")]
+            }
+        }
+    }
+
+    /// Which browser wrote the Chrome-format store at `path`, as Chrome and Chromium keep their keys apart
+    fn of_chrome_store(path: &Path) -> Self {
+        if path.to_string_lossy().to_lowercase().contains("chromium") {
+            Self::Chromium
+        } else {
+            Self::Chrome
+        }
+    }
+
+    /// The password Chrome derives its key from, from the Secret Service
+    #[cfg(target_os = "linux")]
+    fn safe_storage_password(self) -> Result<Option<Vec<u8>>> {
+        use dbus_secret_service::{EncryptionType, SecretService};
+        use std::collections::HashMap;
+
+        let application = if self == Self::Chromium {
+            "chromium"
+        } else {
+            "chrome"
+        };
+
+        let service = SecretService::connect(EncryptionType::Dh)?;
+        let found = service.search_items(HashMap::from([("application", application)]))?;
+        let Some(item) = found.unlocked.first().or_else(|| found.locked.first()) else {
+            return Ok(None);
+        };
+
+        item.unlock()?;
+        Ok(Some(item.get_secret()?))
+    }
+
+    /// The password Chrome derives its key from, from the Keychain
+    #[cfg(target_os = "macos")]
+    fn safe_storage_password(self) -> Result<Option<Vec<u8>>> {
+        let (service, account) = if self == Self::Chromium {
+            ("Chromium Safe Storage", "Chromium")
+        } else {
+            ("Chrome Safe Storage", "Chrome")
+        };
+
+        match keyring::Entry::new(service, account)?.get_password() {
+            Ok(password) => Ok(Some(password.into_bytes())),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn safe_storage_password(self) -> Result<Option<Vec<u8>>> {
+        Err(eyre!(
+            "{self:?} cookies can only be read on Linux and macOS; export them in cookies.txt format instead"
+        ))
+    }
+
+    /// The key Chrome encrypts `version` (`v10` or `v11`) values with
+    fn key(self, version: &[u8]) -> Result<[u8; 16]> {
+        let iterations = if cfg!(target_os = "macos") { 1003 } else { 1 };
+
+        let password = match self.safe_storage_password()? {
+            Some(password) => password,
+            // Chrome on Linux only falls back to a fixed password without a keyring, for `v10` values
+            None if cfg!(target_os = "linux") && version == b"v10" => {
+                CHROME_FALLBACK_PASSWORD.to_vec()
+            }
+            None => {
+                return Err(eyre!(
+                    "No {self:?} Safe Storage password found in the OS keyring"
+                ));
+            }
+        };
+
+        Ok(derive_key(&password, iterations))
+    }
+}
+
+/// The AES-128 key Chrome derives from its Safe Storage `password`
+fn derive_key(password: &[u8], iterations: u32) -> [u8; 16] {
+    let mut key = [0; 16];
+    pbkdf2::derive(
+        PBKDF2_HMAC_SHA1,
+        NonZeroU32::new(iterations).unwrap_or(NonZeroU32::MIN),
+        CHROME_SALT,
+        password,
+        &mut key,
+    );
+    key
+}
+
+/// Decrypt Chrome's `encrypted` value with `key`, leaving out the host's hash newer databases prepend
+fn decrypt(encrypted: &[u8], key: &[u8; 16], hashed_host: bool) -> Result<String> {
+    let ciphertext = encrypted
+        .get(3..)
+        .ok_or_else(|| eyre!("Unable to decrypt the session cookie"))?;
+
+    let mut plaintext = cbc::Decryptor::<Aes128>::new(key.into(), &CHROME_IV.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .map_err(|_| {
+            eyre!("Unable to decrypt the session cookie (is the keyring's password Chrome's?)")
+        })?;
+    if hashed_host {
+        plaintext.drain(..SHA256_OUTPUT_LEN.min(plaintext.len()));
+    }
+
+    String::from_utf8(plaintext).map_err(|_| eyre!("Unable to decrypt the session cookie"))
+}
+
+/// Copy the store at `path` (with its write-ahead log and journal) to `dir`, and open the copy.
+/// The browser keeps the store itself locked while it runs, and it is never written to.
+fn snapshot(path: &Path, dir: &Path) -> Result<Connection> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| eyre!("{} is not a cookie store", path.display()))?;
+
+    for suffix in ["", "-wal", "-journal"] {
+        let mut file_name = name.to_os_string();
+        file_name.push(suffix);
+
+        let source = path.with_file_name(&file_name);
+        if source.exists() {
+            fs::copy(&source, dir.join(&file_name))?;
+        }
+    }
+
+    Connection::open(dir.join(name)).map_err(Into::into)
+}
+
+/// Find the Okta session cookie (`sid`) for `host` in the browser cookie store at `path`,
+/// which may be Firefox's `cookies.sqlite` or Chrome's `Cookies`
+///
+/// # Errors
+///
+/// Will return `Err` if the store cannot be copied or read, or if Chrome's cookie cannot be decrypted
+pub fn find_session_cookie(path: &Path, host: &str) -> Result<Option<String>> {
+    let dir = tempfile::tempdir()?;
+    let connection = snapshot(path, dir.path())?;
+
+    let firefox = connection
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [FIREFOX_TABLE],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+
+    if firefox {
+        find_firefox_cookie(&connection, host)
+    } else {
+        find_chrome_cookie(&connection, host, |version| {
+            Browser::of_chrome_store(path).key(version)
+        })
+    }
+}
+
+fn find_firefox_cookie(connection: &Connection, host: &str) -> Result<Option<String>> {
+    let mut statement = connection.prepare(
+        "SELECT host, value FROM moz_cookies WHERE name = 'sid' ORDER BY lastAccessed DESC",
+    )?;
+    let cookies = statement
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(cookies
+        .into_iter()
+        .find(|(domain, value)| domain_matches(domain, host) && !value.is_empty())
+        .map(|(_, value)| value))
+}
+
+fn find_chrome_cookie<K>(connection: &Connection, host: &str, key: K) -> Result<Option<String>>
+where
+    K: Fn(&[u8]) -> Result<[u8; 16]>,
+{
+    let version = connection
+        .query_row(
+            "SELECT CAST(value AS INTEGER) FROM meta WHERE key = 'version'",
+            [],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?
+        .unwrap_or_default();
+
+    let mut statement = connection.prepare(
+        "SELECT host_key, value, encrypted_value FROM cookies WHERE name = 'sid' ORDER BY last_access_utc DESC",
+    )?;
+    let cookies = statement
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Vec<u8>>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let Some((_, value, encrypted)) = cookies
+        .into_iter()
+        .find(|(domain, _, _)| domain_matches(domain, host))
+    else {
+        return Ok(None);
+    };
+
+    // Values from before Chrome encrypted them are kept as they are
+    if !value.is_empty() {
+        return Ok(Some(value));
+    }
+
+    let prefix = encrypted.get(..3).unwrap_or_default();
+    if prefix != b"v10" && prefix != b"v11" {
+        return Err(eyre!(
+            "The session cookie is encrypted in a way oktaws cannot read; export it in cookies.txt format instead"
+        ));
+    }
+    debug!(
+        "Decrypting the {} session cookie",
+        String::from_utf8_lossy(prefix)
+    );
+
+    decrypt(
+        &encrypted,
+        &key(prefix)?,
+        version >= CHROME_HASHED_HOST_VERSION,
+    )
+    .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use aes::cipher::BlockEncryptMut;
+    use ring::digest::{SHA256, digest};
+
+    fn encrypt(value: &[u8], key: &[u8; 16]) -> Vec<u8> {
+        let ciphertext = cbc::Encryptor::<Aes128>::new(key.into(), &CHROME_IV.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(value);
+        [b"v10".as_slice(), &ciphertext].concat()
+    }
+
+    #[test]
+    fn reads_firefox_stores_through_a_copy() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("cookies.sqlite");
+
+        let connection = Connection::open(&path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE moz_cookies (name TEXT, value TEXT, host TEXT, lastAccessed INTEGER);
+             INSERT INTO moz_cookies VALUES ('sid', 'WRONG_DOMAIN', '.example.com', 2);
+             INSERT INTO moz_cookies VALUES ('sid', 'SESSION_ID', 'mock_org.okta.com', 1);",
+        )?;
+
+        // Still open (and with its changes only in the write-ahead log), as while Firefox runs
+        assert_eq!(
+            find_session_cookie(&path, "mock_org.okta.com")?,
+            Some(String::from("SESSION_ID"))
+        );
+        assert_eq!(find_session_cookie(&path, "other_org.okta.com")?, None);
+        drop(connection);
+
+        Ok(())
+    }
+
+    #[test]
+    fn decrypts_chrome_cookies() -> Result<()> {
+        let key = derive_key(CHROME_FALLBACK_PASSWORD, 1);
+
+        for (version, value) in [
+            (23, b"SESSION_ID".to_vec()),
+            (
+                24,
+                [
+                    digest(&SHA256, b"mock_org.okta.com").as_ref(),
+                    b"SESSION_ID",
+                ]
+                .concat(),
+            ),
+        ] {
+            let connection = Connection::open_in_memory()?;
+            connection.execute_batch(&format!(
+                "CREATE TABLE meta (key TEXT, value TEXT);
+                 INSERT INTO meta VALUES ('version', '{version}');
+                 CREATE TABLE cookies (name TEXT, value TEXT, encrypted_value BLOB, host_key TEXT, last_access_utc INTEGER);"
+            ))?;
+            connection.execute(
+                "INSERT INTO cookies VALUES ('sid', '', ?1, 'mock_org.okta.com', 1)",
+                [encrypt(&value, &key)],
+            )?;
+
+            assert_eq!(
+                find_chrome_cookie(&connection, "mock_org.okta.com", |_| Ok(key))?,
+                Some(String::from("SESSION_ID"))
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,328 @@
+use crate::askpass;
+use crate::blocking;
+use crate::browser;
+use crate::okta::client::Client;
+use crate::okta::cookie_stores::{self, Browser};
+use crate::okta::portal::Portal;
+
+use std::convert::Infallible;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use eyre::{Result, eyre};
+use tracing::{debug, instrument};
+
+/// How the browsers' own cookie stores (Firefox's `cookies.sqlite`, Chrome's `Cookies`) begin
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// Where to import the Okta session cookie from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The cookie stores of a browser's profiles, most recently used first
+    Browser(Browser),
+    /// A browser's cookie store, or a `cookies.txt` export
+    File(PathBuf),
+}
+
+impl FromStr for Source {
+    type Err = Infallible;
+
+    fn from_str(source: &str) -> Result<Self, Infallible> {
+        Ok(source
+            .parse()
+            .map_or_else(|_| Self::File(PathBuf::from(source)), Self::Browser))
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Browser(browser) => write!(f, "{browser:?}'s cookies"),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Source {
+    /// Find the Okta session cookie (`sid`) for `host`.
+    /// This can block, on copying a cookie store, or on the keyring for Chrome's key.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the browser has no profiles, or a cookie store or export cannot be read
+    pub fn find_session_cookie(&self, host: &str) -> Result<Option<String>> {
+        match self {
+            Self::Browser(browser) => {
+                let stores = browser.stores();
+                if stores.is_empty() {
+                    return Err(eyre!("No {browser:?} profiles found"));
+                }
+
+                for store in stores {
+                    debug!("Looking for the session cookie in {}", store.display());
+                    if let Some(session_id) = cookie_stores::find_session_cookie(&store, host)? {
+                        return Ok(Some(session_id));
+                    }
+                }
+                Ok(None)
+            }
+            Self::File(path) => {
+                let contents = fs::read(path)?;
+                if contents.starts_with(SQLITE_HEADER) {
+                    return cookie_stores::find_session_cookie(path, host);
+                }
+
+                let contents = String::from_utf8(contents).map_err(|_| {
+                    eyre!(
+                        "{} is neither a browser's cookie store nor a cookies.txt (Netscape format) export",
+                        path.display()
+                    )
+                })?;
+                Ok(find_session_cookie(&contents, host))
+            }
+        }
+    }
+}
+
+/// Find the Okta session cookie (`sid`) for `host` in a Netscape-format `cookies.txt` export,
+/// as produced by browser extensions and by `curl -c`.
+#[must_use]
+pub fn find_session_cookie(contents: &str, host: &str) -> Option<String> {
+    contents
+        .lines()
+        // curl marks HttpOnly cookies with a prefix, rather than a column
+        .map(|line| line.strip_prefix("#HttpOnly_").unwrap_or(line))
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            match fields.as_slice() {
+                [domain, _, _, _, _, "sid", value] => Some((*domain, *value)),
+                _ => None,
+            }
+        })
+        .filter(|(domain, _)| domain_matches(domain, host))
+        .map(|(_, value)| value.trim().to_string())
+        .find(|value| !value.is_empty())
+}
+
+/// Find the Okta session ID in what the user pasted: the `sid` cookie's value, a `Cookie` header containing it,
+/// or the JSON of `/api/v1/sessions/me` (whose `id` it is)
+#[must_use]
+pub fn find_pasted_session(pasted: &str) -> Option<String> {
+    let pasted = pasted.trim();
+
+    if pasted.starts_with('{') {
+        return serde_json::from_str::<serde_json::Value>(pasted)
+            .ok()?
+            .get("id")?
+            .as_str()
+            .map(ToString::to_string);
+    }
+
+    if pasted.contains('=') {
+        return pasted
+            .trim_start_matches("Cookie:")
+            .split(';')
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == "sid")
+            .map(|(_, value)| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+    }
+
+    (!pasted.is_empty() && !pasted.contains(char::is_whitespace)).then(|| pasted.to_string())
+}
+
+impl Client {
+    /// Create a client for an Okta organization by signing in with the system browser,
+    /// where Okta can verify factors oktaws cannot (e.g. FastPass, or device trust).
+    /// Okta's session cookie belongs to its own domain, so the session is pasted back once the user has signed in.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the session cannot be read from the terminal,
+    /// if no session ID can be found in what was pasted,
+    /// or if Okta does not accept the session.
+    #[instrument(skip(portal, username), fields(organization=%portal.organization))]
+    pub async fn from_browser(portal: &Portal, username: String) -> Result<Self> {
+        let sign_in = portal.base_url().clone();
+        let session = sign_in.join(&portal.api("sessions/me"))?;
+
+        browser::show(&sign_in, &format!("sign in to {}", portal.organization));
+        eprintln!(
+            "Once signed in, open {session} in the same browser, and paste the page (or its \"id\", or the sid cookie) here"
+        );
+
+        let prompt = format!("Okta session for {}", portal.organization);
+        let pasted = blocking::prompt(move || askpass::secret(&prompt)).await?;
+        let session_id = find_pasted_session(&pasted)
+            .ok_or_else(|| eyre!("No Okta session ID found in what was pasted"))?;
+
+        Self::from_session_id(portal, username, &session_id).await
+    }
+
+    /// Create a client for an Okta organization,
+    /// reusing the browser session found in `source`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the cookies cannot be read,
+    /// if they have no session cookie for the organization,
+    /// or if Okta no longer accepts the session.
+    #[instrument(skip(portal, username), fields(organization=%portal.organization))]
+    pub async fn from_cookies(portal: &Portal, username: String, source: &Source) -> Result<Self> {
+        let host = host(portal)?;
+
+        let session_id = blocking::run({
+            let (source, host) = (source.clone(), host.clone());
+            move || source.find_session_cookie(&host)
+        })
+        .await?
+        .ok_or_else(|| eyre!("No Okta session cookie for {host} found in {source}"))?;
+
+        Self::from_session_id(portal, username, &session_id)
+            .await
+            .map_err(|e| {
+                eyre!("The browser session for {host} is no longer valid, sign in again ({e})")
+            })
+    }
+
+    /// Create a client for an Okta organization, reusing an existing session (the `sid` cookie),
+    /// e.g. one handed over by another sign-in tool. Primary authentication and MFA are skipped entirely.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if Okta does not accept the session, or it is another user's.
+    #[instrument(skip(portal, username, session_id), fields(organization=%portal.organization))]
+    pub async fn from_session_id(
+        portal: &Portal,
+        username: String,
+        session_id: &str,
+    ) -> Result<Self> {
+        let mut client = Self::unauthenticated(portal, &username).await?;
+        client.set_session_id(session_id);
+
+        let session = client.current_session().await.map_err(|e| {
+            eyre!(
+                "Okta did not accept the session for {} ({e})",
+                portal.organization
+            )
+        })?;
+
+        // A session handed over (or pasted) for the wrong account would otherwise be used as this one
+        if !same_user(&session.login, &username) {
+            return Err(eyre!(
+                "The Okta session for {} is {}'s, not {username}'s",
+                portal.organization,
+                session.login
+            ));
+        }
+
+        debug!("Reusing existing session for {}", session.login);
+
+        Ok(client)
+    }
+}
+
+/// Whether the session of `login` is for the configured `username`, which may leave out the login's domain
+fn same_user(login: &str, username: &str) -> bool {
+    login.eq_ignore_ascii_case(username)
+        || (!username.contains('@')
+            && login
+                .split_once('@')
+                .is_some_and(|(name, _)| name.eq_ignore_ascii_case(username)))
+}
+
+/// Whether a cookie for `domain` (with or without its leading `.`) is sent to `host`
+pub(crate) fn domain_matches(domain: &str, host: &str) -> bool {
+    let domain = domain.trim_start_matches('.');
+    host == domain || host.ends_with(&format!(".{domain}"))
+}
+
+fn host(portal: &Portal) -> Result<String> {
+    portal
+        .base_url()
+        .host_str()
+        .map(ToString::to_string)
+        .ok_or_else(|| eyre!("No host for organization {}", portal.organization))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static COOKIES: &str = "# Netscape HTTP Cookie File
+.example.com\tTRUE\t/\tTRUE\t0\tsid\tWRONG_DOMAIN
+#HttpOnly_mock_org.okta.com\tFALSE\t/\tTRUE\t0\tsid\tSESSION_ID
+mock_org.okta.com\tFALSE\t/\tTRUE\t0\tDT\tDEVICE_TOKEN
+";
+
+    #[test]
+    fn finds_session_cookie() {
+        assert_eq!(
+            find_session_cookie(COOKIES, "mock_org.okta.com"),
+            Some(String::from("SESSION_ID"))
+        );
+        assert_eq!(find_session_cookie(COOKIES, "other_org.okta.com"), None);
+    }
+
+    #[test]
+    fn sessions_belong_to_the_configured_user() {
+        assert!(same_user("Mock_User@example.com", "mock_user@example.com"));
+        assert!(same_user("mock_user@example.com", "mock_user"));
+        assert!(!same_user("other_user@example.com", "mock_user"));
+        assert!(!same_user("mock_user@other.com", "mock_user@example.com"));
+    }
+
+    #[test]
+    fn finds_pasted_session() {
+        assert_eq!(
+            find_pasted_session(" SESSION_ID\n"),
+            Some(String::from("SESSION_ID"))
+        );
+        assert_eq!(
+            find_pasted_session("Cookie: DT=DEVICE_TOKEN; sid=SESSION_ID; JSESSIONID=X"),
+            Some(String::from("SESSION_ID"))
+        );
+        assert_eq!(
+            find_pasted_session(r#"{"id": "SESSION_ID", "login": "user@example.com"}"#),
+            Some(String::from("SESSION_ID"))
+        );
+        assert_eq!(find_pasted_session("DT=DEVICE_TOKEN"), None);
+        assert_eq!(find_pasted_session("not a session"), None);
+    }
+
+    #[test]
+    fn reads_exports_and_names_browsers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("cookies.txt");
+        fs::write(&path, COOKIES)?;
+
+        assert_eq!(
+            Source::File(path).find_session_cookie("mock_org.okta.com")?,
+            Some(String::from("SESSION_ID"))
+        );
+        assert_eq!(
+            "Firefox".parse::<Source>(),
+            Ok(Source::Browser(Browser::Firefox))
+        );
+        assert_eq!(
+            "Cookies".parse::<Source>(),
+            Ok(Source::File(PathBuf::from("Cookies")))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn matches_parent_domains() {
+        assert_eq!(
+            find_session_cookie(
+                ".okta.com\tTRUE\t/\tTRUE\t0\tsid\tPARENT",
+                "mock_org.okta.com"
+            ),
+            Some(String::from("PARENT"))
+        );
+    }
+}
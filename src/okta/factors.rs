@@ -1,17 +1,25 @@
+use crate::askpass;
+use crate::blocking;
 use crate::okta::Links;
 use crate::okta::Links::Multi;
 use crate::okta::Links::Single;
 use crate::okta::auth::LoginResponse;
 use crate::okta::client::Client;
+use crate::okta::mfa::{Challenge, Metadata, MfaFactor};
+use crate::okta::totp::{self, Generator};
+use crate::okta::transactions::Pending;
+use crate::okta::webauthn;
 
 use std::collections::HashMap;
 use std::fmt;
-use std::thread::sleep;
 use std::time::Duration;
 
-use dialoguer::Password;
 use eyre::{Result, eyre};
-use serde::{Deserialize, Serialize};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::time::sleep;
+use tracing::debug;
+use url::Url;
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
@@ -115,6 +123,75 @@ pub enum Factor {
     },
 }
 
+/// A factor, along with the priority Okta's policy evaluation assigned to it (if any).
+/// Lower priorities are preferred.
+#[derive(Deserialize, Debug)]
+pub struct RankedFactor {
+    #[serde(flatten)]
+    pub factor: Factor,
+    #[serde(default)]
+    pub priority: Option<u32>,
+}
+
+/// Parse the factors Okta offers, skipping any of types (or from providers) that oktaws does not know about.
+/// Okta Verify enrolls several factors at once (e.g. push, TOTP and FastPass),
+/// and an unknown one should not stop the others from being used.
+#[must_use]
+pub fn known(factors: Vec<serde_json::Value>) -> Vec<RankedFactor> {
+    factors
+        .into_iter()
+        .filter_map(|factor| {
+            serde_json::from_value::<RankedFactor>(factor.clone())
+                .map_err(|e| debug!("Skipping unknown factor {factor}: {e}"))
+                .ok()
+        })
+        .collect()
+}
+
+/// Deserialize a list of factors with [`known`]
+///
+/// # Errors
+///
+/// Will return `Err` if the value is not a list
+pub fn deserialize_known<'de, D>(deserializer: D) -> Result<Vec<RankedFactor>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<serde_json::Value>::deserialize(deserializer).map(known)
+}
+
+/// Order factors by their policy-evaluated priority,
+/// keeping the order Okta returned them in for factors without a priority
+#[must_use]
+pub fn prioritize(mut factors: Vec<RankedFactor>) -> Vec<Factor> {
+    factors.sort_by_key(|factor| factor.priority.unwrap_or(u32::MAX));
+    factors.into_iter().map(|factor| factor.factor).collect()
+}
+
+/// An entry in the user's factor catalog,
+/// describing whether the org's policy requires enrollment in that factor
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogFactor {
+    pub factor_type: String,
+    pub provider: String,
+    pub enrollment: Option<String>,
+    pub status: Option<String>,
+}
+
+impl fmt::Display for CatalogFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (from {})", self.factor_type, self.provider)?;
+        if let Some(enrollment) = &self.enrollment {
+            write!(f, ": {enrollment}")?;
+        }
+        if let Some(status) = &self.status {
+            write!(f, " [{status}]")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum FactorProvider {
@@ -222,15 +299,23 @@ pub enum FactorVerificationRequest {
     Token { pass_code: String },
     #[serde(rename_all = "camelCase")]
     WebAuthn { state_token: String },
+    #[serde(rename_all = "camelCase")]
+    WebAuthnAssertion {
+        state_token: String,
+        client_data: String,
+        authenticator_data: String,
+        signature_data: String,
+    },
 }
 
 impl Factor {
     #[must_use]
     pub const fn is_supported(&self) -> bool {
-        !matches!(
-            self,
-            Self::Hotp { .. } | Self::Web { .. } | Self::WebAuthn { .. }
-        )
+        match self {
+            Self::Hotp { .. } | Self::Web { .. } => false,
+            Self::WebAuthn { .. } => cfg!(feature = "webauthn"),
+            _ => true,
+        }
     }
 }
 
@@ -254,6 +339,17 @@ impl fmt::Display for Factor {
             Self::Sms { ref profile, .. } => write!(f, "SMS (to {})", profile.phone_number),
             Self::Call { ref profile, .. } => write!(f, "Call (to {})", profile.phone_number),
             Self::Token { ref provider, .. } => write!(f, "One-time Password (from {provider:?})"),
+            Self::Totp {
+                provider: FactorProvider::Okta,
+                ..
+            } => write!(f, "Time-based One-time Password (from Okta Verify)"),
+            Self::Totp {
+                provider: FactorProvider::Google,
+                ..
+            } => write!(
+                f,
+                "Time-based One-time Password (from Google Authenticator)"
+            ),
             Self::Totp { ref provider, .. } => {
                 write!(f, "Time-based One-time Password (from {provider:?})")
             }
@@ -276,87 +372,331 @@ impl fmt::Display for Factor {
 }
 
 impl Client {
-    /// Given an MFA factor, follow the verification procedure until the MFA is accepted
+    /// Return the factors the current user is enrolled in, in priority order.
+    /// Factors of types that oktaws does not know about are skipped.
     ///
     /// # Errors
     ///
-    /// Will return `Err` if there are any errors during validation
-    pub async fn verify(&self, factor: &Factor, state_token: String) -> Result<LoginResponse> {
-        match factor {
-            Factor::Push { links, .. } => {
-                let url = links
-                    .get("verify")
-                    .and_then(|link| match link {
-                        Single(ref link) => Some(link.href.clone()),
-                        Multi(ref links) => links.first().map(|link| link.href.clone()),
-                    })
-                    .ok_or_else(|| eyre!("No verify link found"))?;
-
-                let request = FactorVerificationRequest::Push { state_token };
-
-                // Trigger sending of Push
-                let mut response: LoginResponse = self.post_absolute(url.clone(), &request).await?;
-
-                while Some(FactorResult::Waiting) == response.factor_result {
-                    sleep(Duration::from_millis(100));
-                    response = self.post_absolute(url.clone(), &request).await?;
+    /// Will return `Err` if there are any network errors encountered
+    pub async fn enrolled_factors(&self) -> Result<Vec<Factor>> {
+        let factors: Vec<serde_json::Value> = self.get(&self.api("users/me/factors")).await?;
+
+        Ok(prioritize(known(factors)))
+    }
+
+    /// Return the types of every factor the current user is enrolled in, including those oktaws does not know about,
+    /// with whether oktaws can verify each
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are any network errors encountered
+    pub async fn enrolled_factor_types(&self) -> Result<Vec<(String, bool)>> {
+        let factors: Vec<serde_json::Value> = self.get(&self.api("users/me/factors")).await?;
+
+        Ok(factors
+            .into_iter()
+            .filter_map(|factor| {
+                let factor_type = factor["factorType"].as_str()?.to_string();
+                // Factors of types (or from providers) oktaws doesn't know can't be verified either
+                let supported = serde_json::from_value::<Factor>(factor)
+                    .is_ok_and(|factor| factor.is_supported());
+                Some((factor_type, supported))
+            })
+            .collect())
+    }
+
+    /// Return the catalog of factors the org's policy allows (or requires) the current user to enroll in
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are any network errors encountered
+    pub async fn factor_catalog(&self) -> Result<Vec<CatalogFactor>> {
+        self.get(&self.api("users/me/factors/catalog")).await
+    }
+
+    /// The TOTP generator registered (with `oktaws mfa totp`) for the signed-in user, if there is one
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the secret store cannot be read, or the registered secret cannot be parsed
+    pub async fn totp_generator(&self) -> Result<Option<Generator>> {
+        let organization = self.organization().to_string();
+        let username = self.base_url().username().replace("%40", "@");
+
+        blocking::run(move || totp::registered(&organization, &username)).await
+    }
+}
+
+impl Factor {
+    /// The factor's verify link
+    fn verify_url(&self) -> Result<Url> {
+        let links = match self {
+            Self::Push { links, .. }
+            | Self::Sms { links, .. }
+            | Self::Call { links, .. }
+            | Self::Token { links, .. }
+            | Self::Totp { links, .. }
+            | Self::Hotp { links, .. }
+            | Self::Question { links, .. }
+            | Self::Web { links, .. }
+            | Self::WebAuthn { links, .. } => links,
+        };
+
+        links
+            .get("verify")
+            .and_then(|link| match link {
+                Single(ref link) => Some(link.href.clone()),
+                Multi(ref links) => links.first().map(|link| link.href.clone()),
+            })
+            .ok_or_else(|| eyre!("No verify link found"))
+    }
+
+    /// Okta's name for the factor's type
+    const fn factor_type(&self) -> &'static str {
+        match self {
+            Self::Push { .. } => "push",
+            Self::Sms { .. } => "sms",
+            Self::Call { .. } => "call",
+            Self::Token { .. } => "token",
+            Self::Totp { .. } => "token:software:totp",
+            Self::Hotp { .. } => "token:hardware",
+            Self::Question { .. } => "question",
+            Self::Web { .. } => "web",
+            Self::WebAuthn { .. } => "webauthn",
+        }
+    }
+}
+
+impl MfaFactor for Factor {
+    fn metadata(&self) -> Metadata {
+        let (id, provider) = match self {
+            Self::Push { id, provider, .. }
+            | Self::Sms { id, provider, .. }
+            | Self::Call { id, provider, .. }
+            | Self::Token { id, provider, .. }
+            | Self::Totp { id, provider, .. }
+            | Self::Hotp { id, provider, .. }
+            | Self::Question { id, provider, .. }
+            | Self::Web { id, provider, .. }
+            | Self::WebAuthn { id, provider, .. } => (id, provider),
+        };
+
+        Metadata {
+            id: id.clone(),
+            factor_type: self.factor_type().to_string(),
+            provider: format!("{provider:?}").to_uppercase(),
+        }
+    }
+
+    fn is_supported(&self) -> bool {
+        Self::is_supported(self)
+    }
+
+    fn challenge<'a>(
+        &'a self,
+        client: &'a Client,
+        state_token: String,
+    ) -> BoxFuture<'a, Result<Challenge>> {
+        Box::pin(async move {
+            let mut url = self.verify_url()?;
+
+            match self {
+                Self::Push { .. } => {
+                    // Record the transaction until the push is answered, so that it is cancelled if this run is interrupted
+                    let organization = client.organization().to_string();
+                    let token = state_token.clone();
+                    let pending =
+                        blocking::run(move || Pending::start(&organization, &token)).await?;
+
+                    // Trigger sending of Push
+                    let request = FactorVerificationRequest::Push {
+                        state_token: state_token.clone(),
+                    };
+                    let response = client.post_absolute(url.clone(), &request).await?;
+
+                    Ok(Challenge::new(url, state_token, Some(response)).pending(pending))
                 }
+                Self::Sms { .. } => {
+                    let request = FactorVerificationRequest::Sms {
+                        state_token,
+                        pass_code: None,
+                    };
+
+                    // Trigger sending of SMS
+                    let response: LoginResponse =
+                        client.post_absolute(url.clone(), &request).await?;
 
-                match response.factor_result {
-                    None | Some(FactorResult::Success) => Ok(response),
-                    Some(result) => Err(eyre!("Failed to verify with Push MFA ({:?})", result)),
+                    let state_token = response
+                        .state_token
+                        .ok_or_else(|| eyre!("No state token found in factor prompt response"))?;
+
+                    Ok(Challenge::new(url, state_token, None))
+                }
+                Self::Totp { .. } => {
+                    url.set_query(Some("rememberDevice"));
+
+                    Ok(Challenge::new(url, state_token, None))
+                }
+                Self::WebAuthn { .. } => {
+                    // Ask Okta for a challenge
+                    let request = FactorVerificationRequest::WebAuthn { state_token };
+                    let response: serde_json::Value =
+                        client.post_absolute(url.clone(), &request).await?;
+                    let state_token =
+                        serde_json::from_value::<webauthn::ChallengeResponse>(response.clone())?
+                            .state_token;
+
+                    Ok(Challenge::new(url, state_token, Some(response)))
+                }
+                _ => {
+                    // TODO
+                    Err(eyre!("Unsupported MFA method ({})", self))
                 }
             }
-            Factor::Sms { links, .. } => {
-                let url = links
-                    .get("verify")
-                    .and_then(|link| match link {
-                        Single(ref link) => Some(link.href.clone()),
-                        Multi(ref links) => links.first().map(|link| link.href.clone()),
-                    })
-                    .ok_or_else(|| eyre!("No verify link found"))?;
-
-                let request = FactorVerificationRequest::Sms {
-                    state_token,
-                    pass_code: None,
-                };
-
-                // Trigger sending of SMS
-                let response: LoginResponse = self.post_absolute(url.clone(), &request).await?;
-
-                let state_token = response
-                    .state_token
-                    .ok_or_else(|| eyre!("No state token found in factor prompt response"))?;
-
-                let request = FactorVerificationRequest::Sms {
-                    state_token,
-                    pass_code: Some(Password::new().with_prompt(factor.to_string()).interact()?),
-                };
-
-                self.post_absolute(url, &request).await
-            }
-            Factor::Totp { links, .. } => {
-                let mut url = links
-                    .get("verify")
-                    .and_then(|link| match link {
-                        Single(ref link) => Some(link.href.clone()),
-                        Multi(ref links) => links.first().map(|link| link.href.clone()),
-                    })
-                    .ok_or_else(|| eyre!("No verify link found"))?;
-
-                url.set_query(Some("rememberDevice"));
-
-                let request = FactorVerificationRequest::Totp {
-                    state_token,
-                    pass_code: Password::new().with_prompt(factor.to_string()).interact()?,
-                };
-
-                self.post_absolute(url, &request).await
-            }
-            _ => {
-                // TODO
-                Err(eyre!("Unsupported MFA method ({})", factor))
+        })
+    }
+
+    fn verify<'a>(
+        &'a self,
+        client: &'a Client,
+        challenge: Challenge,
+    ) -> BoxFuture<'a, Result<LoginResponse>> {
+        Box::pin(async move {
+            match self {
+                Self::Push { .. } => {
+                    let request = FactorVerificationRequest::Push {
+                        state_token: challenge.state_token.clone(),
+                    };
+
+                    let mut response: LoginResponse = serde_json::from_value(
+                        challenge
+                            .response
+                            .clone()
+                            .ok_or_else(|| eyre!("The push was not sent"))?,
+                    )?;
+
+                    while Some(FactorResult::Waiting) == response.factor_result {
+                        sleep(Duration::from_millis(100)).await;
+                        response = client
+                            .post_absolute(challenge.url.clone(), &request)
+                            .await?;
+                    }
+
+                    match response.factor_result {
+                        None | Some(FactorResult::Success) => Ok(response),
+                        Some(result) => Err(eyre!("Failed to verify with Push MFA ({:?})", result)),
+                    }
+                }
+                Self::Sms { .. } => {
+                    let request = FactorVerificationRequest::Sms {
+                        state_token: challenge.state_token,
+                        pass_code: Some(prompt_pass_code(self.to_string()).await?),
+                    };
+
+                    client.post_absolute(challenge.url, &request).await
+                }
+                Self::Totp { .. } => {
+                    let pass_code = match client.totp_generator().await? {
+                        Some(generator) => {
+                            debug!("Generating the code for {self} from the registered secret");
+                            generator.code()
+                        }
+                        None => prompt_pass_code(self.to_string()).await?,
+                    };
+
+                    let request = FactorVerificationRequest::Totp {
+                        state_token: challenge.state_token,
+                        pass_code,
+                    };
+
+                    client.post_absolute(challenge.url, &request).await
+                }
+                Self::WebAuthn { .. } => {
+                    let response: webauthn::ChallengeResponse = serde_json::from_value(
+                        challenge
+                            .response
+                            .ok_or_else(|| eyre!("No WebAuthn challenge was issued"))?,
+                    )?;
+
+                    let base_url = client.base_url().clone();
+                    let assertion =
+                        blocking::prompt(move || webauthn::sign(&base_url, &response)).await?;
+
+                    let request = FactorVerificationRequest::WebAuthnAssertion {
+                        state_token: challenge.state_token,
+                        client_data: assertion.client_data,
+                        authenticator_data: assertion.authenticator_data,
+                        signature_data: assertion.signature_data,
+                    };
+
+                    client.post_absolute(challenge.url, &request).await
+                }
+                _ => {
+                    // TODO
+                    Err(eyre!("Unsupported MFA method ({})", self))
+                }
             }
-        }
+        })
+    }
+}
+
+/// Prompt for an MFA pass code, without blocking the reactor while the user finds it
+async fn prompt_pass_code(prompt: String) -> Result<String> {
+    blocking::prompt(move || askpass::secret(&prompt)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prioritize_factors() {
+        let factors: Vec<RankedFactor> = serde_json::from_str(
+            r#"[
+                {"id": "sms", "factorType": "sms", "provider": "OKTA", "profile": {"phoneNumber": "+1 XXX-XXX-1234"}, "_links": {}},
+                {"id": "totp", "factorType": "token:software:totp", "provider": "GOOGLE", "profile": {}, "_links": {}},
+                {"id": "push", "factorType": "push", "provider": "OKTA", "profile": {}, "_links": {}, "priority": 1}
+            ]"#,
+        )
+        .unwrap();
+
+        let ids = prioritize(factors)
+            .iter()
+            .map(|factor| match factor {
+                Factor::Push { id, .. } | Factor::Sms { id, .. } | Factor::Totp { id, .. } => {
+                    id.clone()
+                }
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, vec!["push", "sms", "totp"]);
+    }
+
+    #[test]
+    fn distinguish_okta_verify_and_google_totp() {
+        let factors = known(
+            serde_json::from_str(
+                r#"[
+                    {"id": "google", "factorType": "token:software:totp", "provider": "GOOGLE", "profile": {"credentialId": "user@example.com"}, "_links": {}},
+                    {"id": "fastpass", "factorType": "signed_nonce", "provider": "OKTA", "profile": {}, "_links": {}},
+                    {"id": "okta", "factorType": "token:software:totp", "provider": "OKTA", "profile": {"credentialId": "user@example.com"}, "_links": {}}
+                ]"#,
+            )
+            .unwrap(),
+        );
+
+        let names = prioritize(factors)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            vec![
+                "Time-based One-time Password (from Google Authenticator)",
+                "Time-based One-time Password (from Okta Verify)"
+            ]
+        );
     }
 }
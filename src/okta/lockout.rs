@@ -0,0 +1,199 @@
+//! When Okta will not let the user sign in for a while: because it is throttling sign-ins (429),
+//! or because the account has been locked out after too many failed attempts.
+//!
+//! Retrying straight away only makes either worse (a lockout may even be extended by it), so these fail with
+//! when to try again, where Okta says. With `--wait`, sign-in instead counts down to then, and tries again by itself.
+
+use crate::okta::auth::{Authenticated, LoginRequest};
+use crate::okta::client::Client;
+
+use std::fmt;
+use std::io::{IsTerminal, Write, stderr};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use eyre::Result;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+/// The longest oktaws waits with `--wait`, beyond which it fails as it would without
+pub const MAX_WAIT: Duration = Duration::from_hours(1);
+
+static WAIT: OnceLock<bool> = OnceLock::new();
+
+/// Wait out throttling and lockouts (whose end Okta gives) instead of failing, for the whole process.
+/// Only the first call has any effect.
+pub fn install(wait: bool) {
+    let _ = WAIT.set(wait);
+}
+
+/// Whether `--wait` was given
+#[must_use]
+pub fn waiting() -> bool {
+    WAIT.get().copied().unwrap_or_default()
+}
+
+/// Why Okta will not let the user sign in yet, and until when, if it says
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Blocked {
+    /// Okta is rate limiting sign-ins
+    Throttled { until: Option<SystemTime> },
+    /// The account is locked out, after too many failed sign-ins
+    LockedOut { until: Option<SystemTime> },
+}
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let now = SystemTime::now();
+
+        match self {
+            Self::Throttled { until: Some(until) } => write!(
+                f,
+                "Okta is throttling sign-ins; try again in {} (or pass --wait to wait for it)",
+                clock(remaining(*until, now))
+            ),
+            Self::Throttled { until: None } => write!(
+                f,
+                "Okta is throttling sign-ins; wait a minute or two before trying again"
+            ),
+            Self::LockedOut { until: Some(until) } => write!(
+                f,
+                "The Okta account is locked out; it unlocks in {} (or pass --wait to wait for it)",
+                clock(remaining(*until, now))
+            ),
+            Self::LockedOut { until: None } => write!(
+                f,
+                "The Okta account is locked out. Signing in again may extend the lockout: \
+                 wait for it to unlock, or ask an Okta administrator to unlock it"
+            ),
+        }
+    }
+}
+
+impl Blocked {
+    /// When signing in may work again, if Okta said
+    #[must_use]
+    pub const fn until(&self) -> Option<SystemTime> {
+        match self {
+            Self::Throttled { until } | Self::LockedOut { until } => *until,
+        }
+    }
+
+    /// Blocked until `delay` from now, if there is one
+    #[must_use]
+    pub fn throttled_for(delay: Option<Duration>) -> Self {
+        Self::Throttled {
+            until: delay.map(|delay| SystemTime::now() + delay),
+        }
+    }
+
+    const fn reason(&self) -> &'static str {
+        match self {
+            Self::Throttled { .. } => "Okta is throttling sign-ins",
+            Self::LockedOut { .. } => "The Okta account is locked out",
+        }
+    }
+}
+
+/// The time left until `until`, from `now`
+#[must_use]
+pub fn remaining(until: SystemTime, now: SystemTime) -> Duration {
+    until.duration_since(now).unwrap_or_default()
+}
+
+/// A countdown rendering of `duration`, e.g. `4:05`, or `1:02:03` over an hour
+#[must_use]
+pub fn clock(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+/// Count down to `until`, on one line of the terminal (or with a single log line, without one)
+async fn countdown(reason: &str, until: SystemTime) {
+    if !stderr().is_terminal() {
+        info!(
+            "{reason}, trying again in {}",
+            clock(remaining(until, SystemTime::now()))
+        );
+        sleep(remaining(until, SystemTime::now())).await;
+        return;
+    }
+
+    loop {
+        let left = remaining(until, SystemTime::now());
+        eprint!("\r{reason}, trying again in {}  ", clock(left));
+        let _ = stderr().flush();
+
+        if left.is_zero() {
+            break;
+        }
+        sleep(left.min(Duration::from_secs(1))).await;
+    }
+    eprintln!();
+}
+
+impl Client {
+    /// [Authenticate](Client::authenticate), waiting out throttling and lockouts with `--wait` where Okta says how long for
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` as [`Client::authenticate`] does, including with [`Blocked`]
+    /// when Okta will not let the user sign in and oktaws is not waiting for it
+    pub async fn authenticate_patiently(&self, req: &LoginRequest) -> Result<Authenticated> {
+        loop {
+            let error = match self.authenticate(req).await {
+                Ok(authenticated) => return Ok(authenticated),
+                Err(error) => error,
+            };
+
+            let Some(blocked) = error.downcast_ref::<Blocked>().copied() else {
+                return Err(error);
+            };
+
+            match blocked.until() {
+                Some(until) if waiting() && remaining(until, SystemTime::now()) <= MAX_WAIT => {
+                    countdown(blocked.reason(), until).await;
+                }
+                Some(_) if waiting() => {
+                    warn!("Not waiting more than {} for Okta", clock(MAX_WAIT));
+                    return Err(error);
+                }
+                _ => return Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn counts_down() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            clock(remaining(now + Duration::from_secs(245), now)),
+            "4:05"
+        );
+        assert_eq!(clock(Duration::from_secs(3723)), "1:02:03");
+        assert_eq!(clock(remaining(now, now + Duration::from_secs(5))), "0:00");
+
+        let blocked = Blocked::LockedOut { until: None };
+        assert_eq!(blocked.until(), None);
+        assert!(blocked.to_string().contains("may extend the lockout"));
+        assert!(
+            Blocked::throttled_for(Some(Duration::from_mins(2)))
+                .to_string()
+                .contains("--wait")
+        );
+    }
+}
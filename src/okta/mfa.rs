@@ -0,0 +1,250 @@
+//! MFA factors, as the sign-in state machine sees them.
+//!
+//! Verifying any factor is two steps: issuing its challenge (sending a push or SMS, or asking for a WebAuthn challenge),
+//! then answering it. Okta's own factors ([`Factor`]) implement [`MfaFactor`] that way, and with the `custom-factors`
+//! feature, so can a company's own (e.g. an internal push app): [`register`] a constructor for its factor type,
+//! and it is offered alongside the built-in factors, without changes to sign-in itself.
+
+use crate::okta::auth::LoginResponse;
+use crate::okta::client::Client;
+use crate::okta::factors::{Factor, RankedFactor};
+use crate::okta::transactions::Pending;
+
+use std::fmt;
+
+use eyre::Result;
+use futures::future::BoxFuture;
+use tracing::debug;
+use url::Url;
+
+/// What a factor is, as Okta describes it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub id: String,
+    /// Okta's factor type, e.g. `push` or `token:software:totp`
+    pub factor_type: String,
+    pub provider: String,
+}
+
+/// An issued challenge, for a factor to verify
+#[derive(Debug)]
+pub struct Challenge {
+    /// The factor's verify link
+    pub url: Url,
+    /// The state token to answer the challenge with
+    pub state_token: String,
+    /// Okta's response to issuing the challenge, for factors which issue one
+    pub response: Option<serde_json::Value>,
+    /// The record of a pending push, kept until the challenge is answered
+    _pending: Option<Pending>,
+}
+
+impl Challenge {
+    #[must_use]
+    pub const fn new(url: Url, state_token: String, response: Option<serde_json::Value>) -> Self {
+        Self {
+            url,
+            state_token,
+            response,
+            _pending: None,
+        }
+    }
+
+    /// Keep `pending` until the challenge is answered, so that its push is cancelled if this run is interrupted
+    #[must_use]
+    pub(crate) fn pending(mut self, pending: Pending) -> Self {
+        self._pending = Some(pending);
+        self
+    }
+}
+
+/// A factor oktaws can verify. Its `Display` is how it is offered to the user
+pub trait MfaFactor: fmt::Debug + fmt::Display + Send + Sync {
+    /// What the factor is
+    fn metadata(&self) -> Metadata;
+
+    /// Whether oktaws can verify the factor (in this build)
+    fn is_supported(&self) -> bool {
+        true
+    }
+
+    /// Issue the factor's challenge, for the transaction with `state_token`
+    fn challenge<'a>(
+        &'a self,
+        client: &'a Client,
+        state_token: String,
+    ) -> BoxFuture<'a, Result<Challenge>>;
+
+    /// Answer `challenge`, until Okta accepts (or rejects) it
+    fn verify<'a>(
+        &'a self,
+        client: &'a Client,
+        challenge: Challenge,
+    ) -> BoxFuture<'a, Result<LoginResponse>>;
+}
+
+/// Builds a custom factor from the factor Okta offers (as JSON)
+pub type Constructor = fn(serde_json::Value) -> Result<Box<dyn MfaFactor>>;
+
+#[cfg(feature = "custom-factors")]
+static CUSTOM: std::sync::Mutex<std::collections::BTreeMap<String, Constructor>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+/// Verify factors of `factor_type` (as Okta names it) with the factors `constructor` builds,
+/// instead of skipping them (or verifying them as a built-in factor would)
+#[cfg(feature = "custom-factors")]
+pub fn register(factor_type: &str, constructor: Constructor) {
+    CUSTOM
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .insert(factor_type.to_string(), constructor);
+}
+
+/// The constructor registered for `factor_type`, if there is one
+#[allow(clippy::missing_const_for_fn)]
+fn custom(factor_type: Option<&str>) -> Option<Constructor> {
+    #[cfg(feature = "custom-factors")]
+    {
+        factor_type.and_then(|factor_type| {
+            CUSTOM
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .get(factor_type)
+                .copied()
+        })
+    }
+    #[cfg(not(feature = "custom-factors"))]
+    {
+        let _ = factor_type;
+        None
+    }
+}
+
+/// The factors Okta offers, in priority order (see [`prioritize`](crate::okta::factors::prioritize)).
+/// Factors of types which are neither built in nor registered are skipped.
+#[must_use]
+pub fn offered(factors: Vec<serde_json::Value>) -> Vec<Box<dyn MfaFactor>> {
+    let mut offered = factors
+        .into_iter()
+        .filter_map(|factor| {
+            let priority = factor["priority"]
+                .as_u64()
+                .and_then(|priority| u32::try_from(priority).ok());
+
+            let built = match custom(factor["factorType"].as_str()) {
+                Some(constructor) => constructor(factor.clone()),
+                None => serde_json::from_value::<RankedFactor>(factor.clone())
+                    .map(|ranked| Box::new(ranked.factor) as Box<dyn MfaFactor>)
+                    .map_err(Into::into),
+            };
+
+            built
+                .map_err(|e| debug!("Skipping unknown factor {factor}: {e}"))
+                .ok()
+                .map(|built| (priority, built))
+        })
+        .collect::<Vec<_>>();
+
+    offered.sort_by_key(|(priority, _)| priority.unwrap_or(u32::MAX));
+    offered.into_iter().map(|(_, factor)| factor).collect()
+}
+
+impl Client {
+    /// Given an MFA factor, follow the verification procedure until the MFA is accepted
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are any errors during validation
+    pub async fn verify(
+        &self,
+        factor: &dyn MfaFactor,
+        state_token: String,
+    ) -> Result<LoginResponse> {
+        let challenge = factor.challenge(self, state_token).await?;
+
+        factor.verify(self, challenge).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offers_known_factors_by_priority() {
+        let factors = offered(
+            serde_json::from_str(
+                r#"[
+                    {"id": "sms", "factorType": "sms", "provider": "OKTA", "profile": {"phoneNumber": "+1 XXX-XXX-1234"}, "_links": {}},
+                    {"id": "fastpass", "factorType": "signed_nonce", "provider": "OKTA", "profile": {}, "_links": {}},
+                    {"id": "push", "factorType": "push", "provider": "OKTA", "profile": {}, "_links": {}, "priority": 1}
+                ]"#,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(
+            factors
+                .iter()
+                .map(|factor| factor.metadata().id)
+                .collect::<Vec<_>>(),
+            vec!["push", "sms"]
+        );
+        assert_eq!(factors[0].metadata().factor_type, "push");
+    }
+
+    #[cfg(feature = "custom-factors")]
+    #[test]
+    fn offers_registered_factors() {
+        #[derive(Debug)]
+        struct InternalPush(String);
+
+        impl fmt::Display for InternalPush {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "Push (to the internal app)")
+            }
+        }
+
+        impl MfaFactor for InternalPush {
+            fn metadata(&self) -> Metadata {
+                Metadata {
+                    id: self.0.clone(),
+                    factor_type: String::from("custom:push"),
+                    provider: String::from("CUSTOM"),
+                }
+            }
+
+            fn challenge<'a>(
+                &'a self,
+                _client: &'a Client,
+                _state_token: String,
+            ) -> BoxFuture<'a, Result<Challenge>> {
+                Box::pin(async { Err(eyre::eyre!("Not in tests")) })
+            }
+
+            fn verify<'a>(
+                &'a self,
+                _client: &'a Client,
+                _challenge: Challenge,
+            ) -> BoxFuture<'a, Result<LoginResponse>> {
+                Box::pin(async { Err(eyre::eyre!("Not in tests")) })
+            }
+        }
+
+        register("custom:push", |factor| {
+            Ok(Box::new(InternalPush(
+                factor["id"].as_str().unwrap_or_default().to_string(),
+            )))
+        });
+
+        let factors = offered(
+            serde_json::from_str(
+                r#"[{"id": "internal", "factorType": "custom:push", "provider": "CUSTOM", "profile": {}, "_links": {}}]"#,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(factors.len(), 1);
+        assert_eq!(factors[0].to_string(), "Push (to the internal app)");
+    }
+}
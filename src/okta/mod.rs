@@ -1,9 +1,19 @@
 pub mod applications;
 pub mod auth;
+pub mod capabilities;
 pub mod client;
+pub mod cookie_stores;
+pub mod cookies;
 pub mod factors;
+pub mod lockout;
+pub mod mfa;
+pub mod password;
+pub mod portal;
 pub mod saml;
 pub mod sessions;
+pub mod totp;
+pub mod transactions;
+pub mod webauthn;
 
 use serde::Deserialize;
 use url::Url;
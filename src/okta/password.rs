@@ -0,0 +1,416 @@
+use crate::blocking;
+use crate::okta::auth::LoginResponse;
+use crate::okta::client::Client;
+use crate::theme;
+
+use dialoguer::{Confirm, Password};
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument, warn};
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordPolicy {
+    pub complexity: Option<Complexity>,
+    pub age: Option<Age>,
+}
+
+/// How often Okta lets a password be changed, and how many earlier passwords it remembers
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Age {
+    pub min_age_minutes: Option<u64>,
+    #[serde(default)]
+    pub history_count: usize,
+}
+
+/// The user signing in, as Okta describes them while changing a password
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct User {
+    #[serde(default)]
+    pub profile: UserProfile,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UserProfile {
+    pub login: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+}
+
+impl PasswordPolicy {
+    /// Describe every rule `password` (replacing `old_password`) breaks, so that it can be fixed before Okta sees it
+    #[must_use]
+    pub fn violations(
+        &self,
+        password: &str,
+        old_password: &str,
+        user: &UserProfile,
+    ) -> Vec<String> {
+        let mut violations = self
+            .complexity
+            .as_ref()
+            .map(|complexity| complexity.violations(password, user))
+            .unwrap_or_default();
+
+        if self.age.as_ref().is_some_and(|age| age.history_count > 0) && password == old_password {
+            violations.push(String::from("not being your current password"));
+        }
+
+        violations
+    }
+
+    /// Describe what a new password needs, before asking for one
+    #[must_use]
+    pub fn requirements(&self) -> Vec<String> {
+        let mut requirements = self
+            .complexity
+            .as_ref()
+            .map(Complexity::requirements)
+            .unwrap_or_default();
+
+        if let Some(age) = self.age.as_ref().filter(|age| age.history_count > 0) {
+            requirements.push(format!(
+                "not being any of your last {} passwords",
+                age.history_count
+            ));
+        }
+
+        requirements
+    }
+}
+
+/// The password complexity rules Okta reports for the organization
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Complexity {
+    pub min_length: Option<usize>,
+    pub min_lower_case: Option<usize>,
+    pub min_upper_case: Option<usize>,
+    pub min_number: Option<usize>,
+    pub min_symbol: Option<usize>,
+    #[serde(default)]
+    pub exclude_username: bool,
+    /// Profile attributes (`firstName`, `lastName`) which must not appear in the password
+    #[serde(default)]
+    pub exclude_attributes: Vec<String>,
+}
+
+impl Complexity {
+    /// The minimum counts, with what they count
+    const fn minimums(&self) -> [(Option<usize>, &'static str); 5] {
+        [
+            (self.min_length, "characters"),
+            (self.min_lower_case, "lowercase letters"),
+            (self.min_upper_case, "uppercase letters"),
+            (self.min_number, "numbers"),
+            (self.min_symbol, "symbols"),
+        ]
+    }
+
+    /// Describe every rule, e.g. `characters (at least 8)`
+    #[must_use]
+    pub fn requirements(&self) -> Vec<String> {
+        let mut requirements = self
+            .minimums()
+            .into_iter()
+            .filter_map(|(minimum, what)| {
+                minimum
+                    .filter(|minimum| *minimum > 0)
+                    .map(|minimum| format!("{what} (at least {minimum})"))
+            })
+            .collect::<Vec<_>>();
+
+        if self.exclude_username {
+            requirements.push(String::from("not containing your username"));
+        }
+        for attribute in &self.exclude_attributes {
+            requirements.push(format!("not containing your {}", describe(attribute)));
+        }
+
+        requirements
+    }
+
+    /// Describe every rule `password` breaks
+    #[must_use]
+    pub fn violations(&self, password: &str, user: &UserProfile) -> Vec<String> {
+        let count = |predicate: fn(&char) -> bool| password.chars().filter(predicate).count();
+
+        let counts = [
+            password.chars().count(),
+            count(char::is_ascii_lowercase),
+            count(char::is_ascii_uppercase),
+            count(char::is_ascii_digit),
+            count(|c| !c.is_alphanumeric() && !c.is_whitespace()),
+        ];
+
+        let mut violations = self
+            .minimums()
+            .into_iter()
+            .zip(counts)
+            .filter_map(|((minimum, what), actual)| match minimum {
+                Some(minimum) if actual < minimum => Some(format!("{what} (at least {minimum})")),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let contains =
+            |part: &str| !part.is_empty() && password.to_lowercase().contains(&part.to_lowercase());
+
+        let username = user.login.as_deref().unwrap_or_default();
+        let local_part = username.split('@').next().unwrap_or(username);
+        if self.exclude_username && contains(local_part) {
+            violations.push(String::from("not containing your username"));
+        }
+
+        for attribute in &self.exclude_attributes {
+            let value = match attribute.as_str() {
+                "firstName" => user.first_name.as_deref(),
+                "lastName" => user.last_name.as_deref(),
+                _ => None,
+            };
+            if value.is_some_and(contains) {
+                violations.push(format!("not containing your {}", describe(attribute)));
+            }
+        }
+
+        violations
+    }
+}
+
+/// A profile attribute's name, as the user knows it
+fn describe(attribute: &str) -> &str {
+    match attribute {
+        "firstName" => "first name",
+        "lastName" => "last name",
+        attribute => attribute,
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangePasswordRequest<'a> {
+    state_token: &'a str,
+    old_password: &'a str,
+    new_password: &'a str,
+}
+
+impl Client {
+    /// Drive the change-password transaction for an expired password,
+    /// returning the next login response and the new password
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the user declines to change their password,
+    /// if there are IO problems while prompting,
+    /// or if Okta rejects the new password.
+    #[instrument(skip_all)]
+    pub async fn change_expired_password(
+        &self,
+        response: LoginResponse,
+        old_password: Option<&str>,
+    ) -> Result<(LoginResponse, String)> {
+        let state_token = response
+            .state_token
+            .ok_or_else(|| eyre!("No state token found in response"))?;
+
+        let prompt = format!(
+            "Your Okta password for {} has expired. Change it now?",
+            self.base_url()
+        );
+        let change = blocking::prompt(move || {
+            Confirm::with_theme(theme::prompt())
+                .with_prompt(prompt)
+                .default(true)
+                .interact()
+                .map_err(Into::into)
+        })
+        .await?;
+
+        if !change {
+            return Err(eyre!(
+                "Your Okta password has expired. Change it at {}",
+                self.base_url()
+            ));
+        }
+
+        let old_password = match old_password {
+            Some(password) => password.to_string(),
+            None => {
+                blocking::prompt(|| {
+                    Password::with_theme(theme::prompt())
+                        .with_prompt("Current password")
+                        .interact()
+                        .map_err(Into::into)
+                })
+                .await?
+            }
+        };
+
+        let (policy, user) = response
+            .embedded
+            .map(|embedded| (embedded.policy, embedded.user))
+            .unwrap_or_default();
+        let policy = policy.unwrap_or_else(|| {
+            debug!("Okta sent no password policy, so the new password is only checked by Okta");
+            PasswordPolicy::default()
+        });
+
+        // The username is percent-encoded in the URL, and Okta may leave it out of the user's profile
+        let mut user = user.unwrap_or_default().profile;
+        user.login = user
+            .login
+            .or_else(|| Some(self.base_url().username().replace("%40", "@")));
+
+        let prompt = new_password_prompt(&policy.requirements());
+
+        let new_password = loop {
+            let prompt = prompt.clone();
+            let new_password = blocking::prompt(move || {
+                Password::with_theme(theme::prompt())
+                    .with_prompt(prompt)
+                    .with_confirmation("Confirm new password", "Passwords do not match")
+                    .interact()
+                    .map_err(Into::into)
+            })
+            .await?;
+
+            let violations = policy.violations(&new_password, &old_password, &user);
+            if violations.is_empty() {
+                break new_password;
+            }
+
+            warn!(
+                "The new password does not meet the password policy, it needs {}",
+                violations.join(", ")
+            );
+        };
+
+        let response = self
+            .post(
+                &self.api("authn/credentials/change_password"),
+                &ChangePasswordRequest {
+                    state_token: &state_token,
+                    old_password: &old_password,
+                    new_password: &new_password,
+                },
+            )
+            .await?;
+
+        Ok((response, new_password))
+    }
+}
+
+/// The prompt for a new password, saying what it needs (so that it is on screen while it is typed)
+fn new_password_prompt(requirements: &[String]) -> String {
+    if requirements.is_empty() {
+        String::from("New password")
+    } else {
+        format!("New password (needs {})", requirements.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_policy() -> Result<()> {
+        let policy: PasswordPolicy = serde_json::from_str(
+            r#"{
+                "expiration": { "passwordExpireDays": 90 },
+                "complexity": {
+                    "minLength": 8,
+                    "minLowerCase": 1,
+                    "minUpperCase": 1,
+                    "minNumber": 1,
+                    "minSymbol": 0,
+                    "excludeUsername": true,
+                    "excludeAttributes": ["firstName", "lastName"]
+                },
+                "age": { "minAgeMinutes": 0, "historyCount": 4 }
+            }"#,
+        )?;
+
+        assert_eq!(
+            policy.complexity,
+            Some(Complexity {
+                min_length: Some(8),
+                min_lower_case: Some(1),
+                min_upper_case: Some(1),
+                min_number: Some(1),
+                min_symbol: Some(0),
+                exclude_username: true,
+                exclude_attributes: vec![String::from("firstName"), String::from("lastName")],
+            })
+        );
+        assert_eq!(
+            policy.requirements(),
+            vec![
+                "characters (at least 8)",
+                "lowercase letters (at least 1)",
+                "uppercase letters (at least 1)",
+                "numbers (at least 1)",
+                "not containing your username",
+                "not containing your first name",
+                "not containing your last name",
+                "not being any of your last 4 passwords"
+            ]
+        );
+        assert_eq!(
+            new_password_prompt(&policy.requirements()[..2]),
+            "New password (needs characters (at least 8), lowercase letters (at least 1))"
+        );
+        assert_eq!(new_password_prompt(&[]), "New password");
+
+        Ok(())
+    }
+
+    #[test]
+    fn password_violations() {
+        let policy = PasswordPolicy {
+            complexity: Some(Complexity {
+                min_length: Some(8),
+                min_lower_case: Some(1),
+                min_upper_case: Some(1),
+                min_number: Some(1),
+                min_symbol: Some(1),
+                exclude_username: true,
+                exclude_attributes: vec![String::from("lastName")],
+            }),
+            age: Some(Age {
+                min_age_minutes: None,
+                history_count: 1,
+            }),
+        };
+        let user = UserProfile {
+            login: Some(String::from("jdoe@example.com")),
+            first_name: Some(String::from("Jane")),
+            last_name: Some(String::from("Doe")),
+        };
+
+        assert_eq!(
+            policy.violations("jdoe", "Old-Password-1", &user),
+            vec![
+                "characters (at least 8)",
+                "uppercase letters (at least 1)",
+                "numbers (at least 1)",
+                "symbols (at least 1)",
+                "not containing your username",
+                "not containing your last name"
+            ]
+        );
+        assert_eq!(
+            policy.violations("Correct-Horse-1", "Old-Password-1", &user),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            policy.violations("Correct-Doe-1", "Correct-Doe-1", &user),
+            vec![
+                "not containing your last name",
+                "not being your current password"
+            ]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+#[cfg(feature = "vault")]
+use crate::vault::PasswordSource;
+
+use eyre::{Result, eyre};
+use reqwest::header::HeaderName;
+use url::Url;
+
+/// The path prefix of the Okta API, unless an organization overrides it
+pub const DEFAULT_API_PREFIX: &str = "api/v1";
+
+/// Where an Okta organization's portal and API are served from.
+/// Normally `https://<organization>.okta.com/api/v1`, but both parts can be overridden,
+/// for private deployments or if Okta changes its API version before oktaws catches up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Portal {
+    pub organization: String,
+    base_url: Url,
+    api_prefix: String,
+    /// Extra headers sent with every request to Okta, e.g. a pre-shared header for a WAF.
+    /// Only the names are configured; their values are kept in the keyring.
+    headers: Vec<HeaderName>,
+    /// Where to read the Okta password from, ahead of the keyring
+    #[cfg(feature = "vault")]
+    password_vault: Option<PasswordSource>,
+}
+
+impl Portal {
+    /// The default portal for `organization`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the organization name does not make a valid URL
+    pub fn new(organization: &str) -> Result<Self> {
+        Self::with_overrides(organization, None, None)
+    }
+
+    /// The portal for `organization`, with an optional base URL and API prefix in place of the defaults
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the base URL is not a valid `https` URL
+    pub fn with_overrides(
+        organization: &str,
+        base_url: Option<&str>,
+        api_prefix: Option<&str>,
+    ) -> Result<Self> {
+        let mut base_url = match base_url {
+            Some(base_url) => Url::parse(base_url)?,
+            None => Url::parse(&format!("https://{organization}.okta.com/"))?,
+        };
+
+        if base_url.scheme() != "https" {
+            return Err(eyre!("portal_base_url must use https, not {base_url}"));
+        }
+
+        // Paths are joined onto the base URL, so it must be treated as a directory
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+
+        Ok(Self {
+            organization: organization.to_string(),
+            base_url,
+            api_prefix: api_prefix
+                .unwrap_or(DEFAULT_API_PREFIX)
+                .trim_matches('/')
+                .to_string(),
+            headers: Vec::new(),
+            #[cfg(feature = "vault")]
+            password_vault: None,
+        })
+    }
+
+    /// The portal, sending the named headers with every request to Okta
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if any name is not a valid header name
+    pub fn with_headers(mut self, names: &[String]) -> Result<Self> {
+        self.headers = names
+            .iter()
+            .map(|name| {
+                HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| eyre!("okta_headers has an invalid header name: {name}"))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(self)
+    }
+
+    /// The portal, reading the Okta password from Vault
+    #[cfg(feature = "vault")]
+    #[must_use]
+    pub fn with_password_vault(mut self, source: Option<PasswordSource>) -> Self {
+        self.password_vault = source;
+        self
+    }
+
+    /// Where the Okta password is read from in Vault, if anywhere
+    #[cfg(feature = "vault")]
+    #[must_use]
+    pub const fn password_vault(&self) -> Option<&PasswordSource> {
+        self.password_vault.as_ref()
+    }
+
+    /// The names of the extra headers sent with every request to Okta
+    #[must_use]
+    pub fn headers(&self) -> &[HeaderName] {
+        &self.headers
+    }
+
+    #[must_use]
+    pub const fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// The path (relative to the base URL) of an API endpoint, e.g. `authn`
+    #[must_use]
+    pub fn api(&self, path: &str) -> String {
+        format!("{}/{path}", self.api_prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_portal() -> Result<()> {
+        let portal = Portal::new("mock_org")?;
+
+        assert_eq!(portal.base_url().as_str(), "https://mock_org.okta.com/");
+        assert_eq!(portal.api("authn"), "api/v1/authn");
+
+        Ok(())
+    }
+
+    #[test]
+    fn overridden_portal() -> Result<()> {
+        let portal = Portal::with_overrides(
+            "mock_org",
+            Some("https://sso.example.gov/okta"),
+            Some("/api/v2/"),
+        )?;
+
+        assert_eq!(
+            portal.base_url().join(&portal.api("authn"))?.as_str(),
+            "https://sso.example.gov/okta/api/v2/authn"
+        );
+        assert!(Portal::with_overrides("mock_org", Some("http://sso.example.gov"), None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn portal_headers() -> Result<()> {
+        let portal = Portal::new("mock_org")?.with_headers(&[String::from("X-WAF-Key")])?;
+
+        assert_eq!(portal.headers(), &[HeaderName::from_static("x-waf-key")]);
+        assert!(
+            Portal::new("mock_org")?
+                .with_headers(&[String::from("X WAF Key")])
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -101,10 +101,10 @@ impl Client {
     ) -> Result<()> {
         let session: Session = self
             .post(
-                &format!(
-                    "api/v1/sessions?additionalFields={}",
+                &self.api(&format!(
+                    "sessions?additionalFields={}",
                     additional_fields.iter().join(",")
-                ),
+                )),
                 &SessionRequest {
                     session_token: Some(session_token),
                 },
@@ -115,4 +115,35 @@ impl Client {
 
         Ok(())
     }
+
+    /// The Okta session the client is signed in with
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the client is not signed in, or the session has ended
+    pub async fn current_session(&self) -> Result<Session> {
+        self.get(&self.api("sessions/me")).await
+    }
+
+    /// End the Okta session the client is signed in with, as signing out in the browser would
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the client is not signed in, or the session cannot be ended
+    pub async fn end_session(&self) -> Result<()> {
+        self.delete(&self.api("sessions/me")).await
+    }
+
+    /// Extend the Okta session the client is signed in with, as any activity in the browser would
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the client is not signed in, or the session has already ended
+    pub async fn refresh_session(&self) -> Result<Session> {
+        self.post(
+            &self.api("sessions/me/lifecycle/refresh"),
+            &serde_json::json!({}),
+        )
+        .await
+    }
 }
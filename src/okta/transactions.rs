@@ -0,0 +1,291 @@
+//! Okta authentication transactions left waiting for MFA, by runs which were interrupted.
+//!
+//! Okta Verify keeps offering a push until its transaction ends, so a run killed while waiting for a push
+//! leaves prompts behind which the user never asked for. While a push is pending, its state token is kept in the
+//! secret store, and a record of it in the `transactions` directory of the state directory is locked.
+//! Records are named by the process and a count of the pushes it has started, as one run can wait for several at once.
+//! A record whose lock is no longer held belongs to a run which has gone, so its transaction is cancelled
+//! before the next sign-in starts one, or by `oktaws mfa cancel`.
+
+use crate::config::state_dir;
+use crate::lock::FileLock;
+use crate::okta::client::{Client, OktaError};
+use crate::okta::portal::Portal;
+use crate::secrets::{self, Secret};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use eyre::Result;
+use serde::Serialize;
+use tracing::{debug, info};
+
+/// The directory of pending transactions, in the oktaws state directory
+pub const DIR: &str = "transactions";
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CancelRequest<'a> {
+    state_token: &'a str,
+}
+
+/// How many pushes this process has started, so that each of its records has an ID of its own
+static STARTED: AtomicU32 = AtomicU32::new(0);
+
+/// A transaction recorded as `id`: the recording process's ID, then a count of the pushes it had started
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    organization: String,
+    id: String,
+    path: PathBuf,
+}
+
+impl Record {
+    fn new(dir: &Path, organization: &str, id: &str) -> Self {
+        Self {
+            organization: organization.to_string(),
+            id: id.to_string(),
+            path: dir.join(format!("{organization}.{id}")),
+        }
+    }
+
+    /// A record for the next push this process starts
+    fn next(dir: &Path, organization: &str) -> Self {
+        let started = STARTED.fetch_add(1, Ordering::Relaxed);
+        Self::new(dir, organization, &format!("{}-{started}", process::id()))
+    }
+
+    /// The ID of the process which recorded the transaction
+    fn process(&self) -> &str {
+        self.id.split('-').next().unwrap_or(&self.id)
+    }
+
+    fn secret(&self) -> Secret {
+        Secret::okta_transaction(&self.organization, &self.id)
+    }
+
+    /// Remove the record, and its lock file
+    fn remove(&self) {
+        for path in [self.path.clone(), FileLock::lock_path(&self.path)] {
+            if let Err(e) = fs::remove_file(&path) {
+                debug!("Unable to remove {} ({e})", path.display());
+            }
+        }
+    }
+}
+
+/// The transactions recorded for `organization` in `dir` whose runs have gone, with their locks held,
+/// or every transaction recorded for it (locking those whose runs have gone) if `force`
+fn orphaned(
+    dir: &Path,
+    organization: &str,
+    force: bool,
+) -> Result<Vec<(Record, Option<FileLock>)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let prefix = format!("{organization}.");
+    let mut orphans = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name();
+        // Lock files are skipped, as are other organizations whose names start with this one's
+        let Some(id) = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit() || c == '-'))
+        else {
+            continue;
+        };
+
+        let record = Record::new(dir, organization, id);
+        match FileLock::acquire_with_timeout(&record.path, Duration::ZERO) {
+            Ok(lock) => orphans.push((record, Some(lock))),
+            Err(_) if force => orphans.push((record, None)),
+            Err(e) => debug!(
+                "Transaction {} is still pending ({e})",
+                record.path.display()
+            ),
+        }
+    }
+
+    orphans.sort_by(|(a, _), (b, _)| a.id.cmp(&b.id));
+
+    Ok(orphans)
+}
+
+/// A push waiting to be answered in this run, recorded until it is dropped
+#[derive(Debug)]
+pub struct Pending {
+    record: Record,
+    _lock: FileLock,
+}
+
+impl Pending {
+    /// Record that a transaction for `organization`, with `state_token`, is waiting for a push in this run
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the record cannot be written, or the state token cannot be kept
+    pub fn start(organization: &str, state_token: &str) -> Result<Self> {
+        Self::start_in(&state_dir()?.join(DIR), organization, state_token)
+    }
+
+    fn start_in(dir: &Path, organization: &str, state_token: &str) -> Result<Self> {
+        let record = Record::next(dir, organization);
+
+        // Lock before writing the record, so that no other run takes it for an orphan
+        let lock = FileLock::acquire(&record.path)?;
+        // Without a secret store, the push can't be cancelled by a later run, but is still waited for
+        if secrets::kept() {
+            record.secret().set(state_token)?;
+        }
+        fs::write(&record.path, "")?;
+
+        Ok(Self {
+            record,
+            _lock: lock,
+        })
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if let Err(e) = self.record.secret().delete() {
+            debug!("Unable to forget transaction {} ({e})", self.record.id);
+        }
+        self.record.remove();
+    }
+}
+
+impl Client {
+    /// Cancel the authentication transaction with `state_token`, returning whether it was still pending
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if Okta cannot be reached
+    pub async fn cancel_transaction(&self, state_token: &str) -> Result<bool> {
+        match self
+            .post::<_, serde_json::Value>(&self.api("authn/cancel"), &CancelRequest { state_token })
+            .await
+        {
+            Ok(_) => Ok(true),
+            // Okta rejects tokens for transactions which have already ended
+            Err(e) if e.downcast_ref::<OktaError>().is_some() => {
+                debug!("Transaction had already ended ({e})");
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Cancel the transactions left pending by runs which have gone (or every pending transaction, if `force`),
+    /// returning how many were cancelled
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the records cannot be read, or Okta cannot be reached
+    pub async fn cancel_orphaned_transactions(&self, force: bool) -> Result<usize> {
+        let orphans = orphaned(&state_dir()?.join(DIR), self.organization(), force)?;
+        let mut cancelled = 0;
+
+        for (record, _lock) in orphans {
+            if let Some(state_token) = record.secret().get()? {
+                if self.cancel_transaction(&state_token).await? {
+                    info!(
+                        "Cancelled a pending Okta Verify push for {}, left by process {}",
+                        record.organization,
+                        record.process()
+                    );
+                    cancelled += 1;
+                }
+                record.secret().delete()?;
+            }
+            record.remove();
+        }
+
+        Ok(cancelled)
+    }
+}
+
+/// Cancel the transactions left pending for `portal`'s organization by runs which have gone
+/// (or every pending transaction, if `force`), returning how many were cancelled
+///
+/// # Errors
+///
+/// Will return `Err` if the records cannot be read, or Okta cannot be reached
+pub async fn cancel_orphaned(portal: &Portal, username: &str, force: bool) -> Result<usize> {
+    let dir = state_dir()?.join(DIR);
+    if orphaned(&dir, &portal.organization, force)?.is_empty() {
+        return Ok(0);
+    }
+
+    Client::unauthenticated(portal, username)
+        .await?
+        .cancel_orphaned_transactions(force)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn finds_records_whose_runs_have_gone() -> Result<()> {
+        let dir = tempdir()?;
+
+        // A run which is still waiting holds its record's lock
+        let live = Record::new(dir.path(), "mock_org", "1-0");
+        let _lock = FileLock::acquire(&live.path)?;
+        fs::write(&live.path, "")?;
+
+        // A run which was killed left its record unlocked
+        let gone = Record::new(dir.path(), "mock_org", "2-0");
+        fs::write(&gone.path, "")?;
+        assert_eq!(gone.process(), "2");
+
+        fs::write(dir.path().join("other_org.3"), "")?;
+
+        let orphans = orphaned(dir.path(), "mock_org", false)?;
+        assert_eq!(
+            orphans.iter().map(|(record, _)| record).collect::<Vec<_>>(),
+            vec![&gone]
+        );
+        drop(orphans);
+
+        let orphans = orphaned(dir.path(), "mock_org", true)?;
+        assert_eq!(
+            orphans
+                .iter()
+                .map(|(record, lock)| (record.id.as_str(), lock.is_some()))
+                .collect::<Vec<_>>(),
+            vec![("1-0", false), ("2-0", true)]
+        );
+
+        assert!(orphaned(&dir.path().join("missing"), "mock_org", false)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pushes_in_one_run_are_recorded_apart() -> Result<()> {
+        let dir = tempdir()?;
+
+        let first = Record::next(dir.path(), "mock_org");
+        let second = Record::next(dir.path(), "mock_org");
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.process(), second.process());
+
+        let _first = FileLock::acquire(&first.path)?;
+        // Doesn't wait for the first push's lock
+        let _second = FileLock::acquire_with_timeout(&second.path, Duration::ZERO)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! WebAuthn (FIDO2) verification, with a security key over USB HID.
+//!
+//! Okta sends a challenge for the factor's credential; oktaws builds the `clientData` a browser would
+//! (with the Okta organization as the origin), has the key sign its hash after the user touches it,
+//! and returns the assertion for Okta to check. Only builds with the `webauthn` feature can talk to keys.
+
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use eyre::{Result, eyre};
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+/// Okta's response to starting a WebAuthn verification
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChallengeResponse {
+    pub state_token: String,
+    #[serde(rename = "_embedded")]
+    embedded: ChallengeEmbedded,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChallengeEmbedded {
+    factor: ChallengeFactor,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ChallengeFactor {
+    profile: ChallengeProfile,
+    #[serde(rename = "_embedded")]
+    embedded: ChallengeFactorEmbedded,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ChallengeProfile {
+    credential_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChallengeFactorEmbedded {
+    challenge: Challenge,
+}
+
+#[derive(Deserialize, Debug)]
+struct Challenge {
+    challenge: String,
+}
+
+impl ChallengeResponse {
+    /// The challenge to sign, as Okta sent it (base64url)
+    #[must_use]
+    pub fn challenge(&self) -> &str {
+        &self.embedded.factor.embedded.challenge.challenge
+    }
+
+    /// The ID of the credential the key must sign with
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the ID is not valid base64url
+    pub fn credential_id(&self) -> Result<Vec<u8>> {
+        URL_SAFE_NO_PAD
+            .decode(
+                self.embedded
+                    .factor
+                    .profile
+                    .credential_id
+                    .trim_end_matches('='),
+            )
+            .map_err(|e| eyre!("Okta sent an invalid WebAuthn credential ID ({e})"))
+    }
+}
+
+/// A signed assertion, encoded as Okta expects it
+#[derive(Debug, PartialEq, Eq)]
+pub struct Assertion {
+    pub client_data: String,
+    pub authenticator_data: String,
+    pub signature_data: String,
+}
+
+/// The origin a browser signing in to `base_url` would report
+///
+/// # Errors
+///
+/// Will return `Err` if the URL has no host
+pub fn origin(base_url: &Url) -> Result<String> {
+    let host = base_url
+        .host_str()
+        .ok_or_else(|| eyre!("{base_url} has no host to verify WebAuthn for"))?;
+
+    Ok(match base_url.port() {
+        Some(port) => format!("{}://{host}:{port}", base_url.scheme()),
+        None => format!("{}://{host}", base_url.scheme()),
+    })
+}
+
+/// The `clientData` JSON a browser would build for the challenge, for `webauthn.get`
+#[must_use]
+pub fn client_data(challenge: &str, origin: &str) -> String {
+    json!({
+        "type": "webauthn.get",
+        "challenge": challenge,
+        "origin": origin,
+        "crossOrigin": false,
+    })
+    .to_string()
+}
+
+/// Have a security key sign the challenge, asking the user to touch it.
+/// This blocks until they do (or the key times out), so callers run it off the reactor.
+///
+/// # Errors
+///
+/// Will return `Err` if no key is plugged in, or the key does not hold the credential
+#[cfg(feature = "webauthn")]
+pub fn sign(base_url: &Url, challenge: &ChallengeResponse) -> Result<Assertion> {
+    use ctap_hid_fido2::fidokey::GetAssertionArgsBuilder;
+    use ctap_hid_fido2::{Cfg, FidoKeyHidFactory};
+
+    let rp_id = base_url
+        .host_str()
+        .ok_or_else(|| eyre!("{base_url} has no host to verify WebAuthn for"))?;
+    let client_data = client_data(challenge.challenge(), &origin(base_url)?);
+    let credential_id = challenge.credential_id()?;
+
+    let key = FidoKeyHidFactory::create(&Cfg::init())
+        .map_err(|e| eyre!("No security key found ({e})"))?;
+
+    eprintln!("Touch your security key to verify with Okta");
+
+    // The key is given the clientData itself, and signs its SHA-256 hash
+    let args = GetAssertionArgsBuilder::new(rp_id, client_data.as_bytes())
+        .credential_id(&credential_id)
+        .build();
+    let assertion = key
+        .get_assertion_with_args(&args)
+        .map_err(|e| eyre!("The security key did not sign the challenge ({e})"))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| eyre!("The security key returned no assertion"))?;
+
+    Ok(Assertion {
+        client_data: STANDARD.encode(&client_data),
+        authenticator_data: STANDARD.encode(&assertion.auth_data),
+        signature_data: STANDARD.encode(&assertion.signature),
+    })
+}
+
+/// # Errors
+///
+/// Always, as this build cannot talk to security keys
+#[cfg(not(feature = "webauthn"))]
+pub fn sign(_base_url: &Url, _challenge: &ChallengeResponse) -> Result<Assertion> {
+    Err(eyre!(
+        "This build of oktaws does not support security keys; install it with `--features webauthn`"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn browser_client_data() -> Result<()> {
+        let challenge: ChallengeResponse = serde_json::from_str(
+            r#"{
+                "stateToken": "STATE_TOKEN",
+                "status": "MFA_CHALLENGE",
+                "_embedded": {
+                    "factor": {
+                        "id": "fwf1",
+                        "factorType": "webauthn",
+                        "profile": { "credentialId": "3Ld1ZqY-Zm9v", "authenticatorName": "YubiKey 5" },
+                        "_embedded": { "challenge": { "challenge": "cdsZ1XjTqF9mLbX0", "extensions": {} } }
+                    }
+                }
+            }"#,
+        )?;
+
+        assert_eq!(
+            challenge.credential_id()?,
+            b"\xdc\xb7\x75\x66\xa6\x3e\x66\x6f\x6f"
+        );
+
+        let origin = origin(&Url::parse("https://mock_org.okta.com/")?)?;
+        assert_eq!(origin, "https://mock_org.okta.com");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&client_data(
+                challenge.challenge(),
+                &origin
+            ))?,
+            json!({
+                "type": "webauthn.get",
+                "challenge": "cdsZ1XjTqF9mLbX0",
+                "origin": "https://mock_org.okta.com",
+                "crossOrigin": false
+            })
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! How commands print their results: for people (the default), or as JSON for scripts, with `--output json`.
+//!
+//! Logs go to stderr either way, so in JSON mode stdout carries nothing but one JSON document per command.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use eyre::{Error, Result, eyre};
+use serde::Serialize;
+
+/// How results are printed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Formatted for people
+    #[default]
+    Text,
+    /// As JSON, for scripts and wrapper tools
+    Json,
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(eyre!("Unknown output format {s}, expected text or json")),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Text => write!(f, "text"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl Format {
+    /// Install this format for the whole process.
+    /// Only the first call has any effect.
+    pub fn install(self) {
+        let _ = FORMAT.set(self);
+    }
+
+    /// The format installed for this process
+    #[must_use]
+    pub fn current() -> Self {
+        FORMAT.get().copied().unwrap_or_default()
+    }
+}
+
+/// A profile whose credentials were refreshed, as `oktaws refresh --output json` lists it
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Refreshed {
+    pub organization: String,
+    pub profile: String,
+    /// The credentials file section the credentials were written to
+    pub section: String,
+    pub partition: String,
+    pub account_id: Option<String>,
+    /// The ARN the credentials act as, e.g. `arn:aws:sts::123456789012:assumed-role/Admin/jdoe`
+    pub arn: Option<String>,
+    /// When the credentials expire, in RFC 3339
+    pub expires_at: Option<String>,
+}
+
+/// Whether results are printed as JSON
+#[must_use]
+pub fn json() -> bool {
+    Format::current() == Format::Json
+}
+
+/// Print `value` to stdout as JSON
+///
+/// # Errors
+///
+/// Will return `Err` if `value` cannot be serialized
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_formats() -> Result<()> {
+        assert_eq!("JSON".parse::<Format>()?, Format::Json);
+        assert_eq!("text".parse::<Format>()?.to_string(), "text");
+        assert!("yaml".parse::<Format>().is_err());
+
+        Ok(())
+    }
+}
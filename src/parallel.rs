@@ -0,0 +1,90 @@
+//! How many profiles' credentials are fetched at once, across every organization being refreshed.
+//!
+//! Organizations are fetched from at the same time, so the limit is shared between them rather than applied to each,
+//! and `--parallel` bounds the requests in flight to Okta and AWS as a whole.
+
+use std::future::Future;
+use std::sync::OnceLock;
+
+use tokio::sync::Semaphore;
+
+/// How many fetches run at once, unless `--parallel` says otherwise
+pub const DEFAULT: usize = 10;
+
+static GLOBAL: OnceLock<Limit> = OnceLock::new();
+
+/// Choose how many fetches run at once, for the whole process. Only the first call has any effect.
+pub fn install(parallel: usize) {
+    let _ = GLOBAL.set(Limit::new(parallel));
+}
+
+/// The limit installed for this process
+#[must_use]
+pub fn global() -> &'static Limit {
+    GLOBAL.get_or_init(|| Limit::new(DEFAULT))
+}
+
+/// A bound on how many futures run at once
+#[derive(Debug)]
+pub struct Limit {
+    permits: usize,
+    semaphore: Semaphore,
+}
+
+impl Limit {
+    /// A limit of `permits` at once (at least one)
+    #[must_use]
+    pub fn new(permits: usize) -> Self {
+        let permits = permits.max(1);
+
+        Self {
+            permits,
+            semaphore: Semaphore::new(permits),
+        }
+    }
+
+    /// How many futures run at once
+    #[must_use]
+    pub const fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Run `future` once fewer than the limit are running
+    pub async fn run<F: Future>(&self, future: F) -> F::Output {
+        // The semaphore is never closed, so acquiring only waits
+        let _permit = self.semaphore.acquire().await.ok();
+
+        future.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::future::join_all;
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn runs_at_most_the_limit_at_once() {
+        let limit = Limit::new(2);
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+
+        join_all((0..6).map(|_| {
+            limit.run(async {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                sleep(Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        }))
+        .await;
+
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert_eq!(Limit::new(0).permits(), 1);
+    }
+}
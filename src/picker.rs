@@ -0,0 +1,498 @@
+//! Interactive account and role pickers which remember what the user picks.
+//!
+//! Favorites are listed first, then the most recently picked, then everything else alphabetically,
+//! so that the few roles used day to day are at the top of a list of hundreds.
+//! Pressing `f` toggles the highlighted item as a favorite.
+//! Pressing `/` narrows the list as you type, fzf-style: items containing the typed characters in order,
+//! closest together first. Pickers of several items mark each with space (or tab, while typing).
+
+use crate::config::state_dir;
+use crate::lock::FileLock;
+use crate::theme;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use console::{Key, Term};
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// The picks file, in the oktaws state directory
+pub const FILE_NAME: &str = "picks.json";
+
+/// The most items shown at once; the list scrolls beyond this
+const PAGE_SIZE: usize = 15;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+struct Picks {
+    #[serde(default)]
+    favorites: BTreeSet<String>,
+    /// Seconds since the epoch when each item was last picked
+    #[serde(default)]
+    recent: BTreeMap<String, u64>,
+}
+
+/// Favorites and recently picked items, as saved by previous runs, plus this run's changes
+#[derive(Debug, Default)]
+pub struct History {
+    path: PathBuf,
+    picks: Picks,
+    /// Favorites toggled in this run, to be applied to the file when saving
+    toggled: BTreeSet<String>,
+    /// Items picked in this run
+    picked: BTreeMap<String, u64>,
+}
+
+impl History {
+    /// Load the history from `path`, which need not exist yet
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file exists but cannot be read or parsed
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            picks: Self::read(path)?,
+            ..Self::default()
+        })
+    }
+
+    fn read(path: &Path) -> Result<Picks> {
+        if path.exists() {
+            Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+        } else {
+            Ok(Picks::default())
+        }
+    }
+
+    #[must_use]
+    pub fn is_favorite(&self, label: &str) -> bool {
+        self.picks.favorites.contains(label)
+    }
+
+    pub fn toggle_favorite(&mut self, label: &str) {
+        for set in [&mut self.picks.favorites, &mut self.toggled] {
+            if !set.remove(label) {
+                set.insert(label.to_string());
+            }
+        }
+    }
+
+    /// Record that `label` was picked
+    pub fn record(&mut self, label: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        self.picks.recent.insert(label.to_string(), now);
+        self.picked.insert(label.to_string(), now);
+    }
+
+    /// The indices of `labels`, in the order they should be listed:
+    /// favorites, then most recently picked, then alphabetically
+    #[must_use]
+    pub fn order(&self, labels: &[String]) -> Vec<usize> {
+        let mut order = (0..labels.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| {
+            let label = &labels[i];
+            (
+                !self.is_favorite(label),
+                std::cmp::Reverse(self.picks.recent.get(label).copied().unwrap_or_default()),
+                label.to_lowercase(),
+            )
+        });
+        order
+    }
+
+    /// Add this run's picks and favorites to the file, merging with any changes made by other processes
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the lock cannot be acquired, or if the file cannot be read or written
+    pub fn save(&self) -> Result<()> {
+        if self.toggled.is_empty() && self.picked.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let _lock = FileLock::acquire(&self.path)?;
+
+        let mut picks = Self::read(&self.path)?;
+        for label in &self.toggled {
+            if !picks.favorites.remove(label) {
+                picks.favorites.insert(label.clone());
+            }
+        }
+        for (label, picked_at) in &self.picked {
+            let recent = picks.recent.entry(label.clone()).or_default();
+            *recent = (*recent).max(*picked_at);
+        }
+
+        fs::write(&self.path, serde_json::to_string_pretty(&picks)?).map_err(Into::into)
+    }
+}
+
+/// How closely `label` matches the typed `query`, if it does at all: lower is closer.
+/// Every character of the query must appear in the label, in order (ignoring case);
+/// matches that start earlier, and have fewer characters between them, come first.
+#[must_use]
+pub fn fuzzy_score(query: &str, label: &str) -> Option<usize> {
+    let mut label_chars = label.chars().flat_map(char::to_lowercase).enumerate();
+    let mut first = None;
+    let mut last = 0;
+    let mut matched = 0;
+
+    for query_char in query.chars().flat_map(char::to_lowercase) {
+        let (position, _) = label_chars.find(|(_, label_char)| *label_char == query_char)?;
+        first.get_or_insert(position);
+        last = position;
+        matched += 1;
+    }
+
+    // Where the match starts, plus the characters skipped within it
+    Some(first.map_or(0, |first| first + (last + 1 - first - matched)))
+}
+
+/// The indices of `labels` matching `query`, closest first; ties keep their place in `order`
+fn filter(order: &[usize], labels: &[String], query: &str) -> Vec<usize> {
+    let mut matches = order
+        .iter()
+        .filter_map(|&index| fuzzy_score(query, &labels[index]).map(|score| (score, index)))
+        .collect::<Vec<_>>();
+    matches.sort_by_key(|(score, _)| *score);
+
+    matches.into_iter().map(|(_, index)| index).collect()
+}
+
+/// Choose one of `items`, listing favorites and recent picks first.
+/// With a single item, it is chosen without prompting.
+///
+/// # Errors
+///
+/// Will return `Err` if there are no items, if nothing is chosen,
+/// or if there are IO errors while prompting
+pub fn pick<T, P, F, S>(mut items: Vec<T>, prompt: P, displayer: F) -> Result<T>
+where
+    P: Into<String>,
+    F: FnMut(&T) -> S,
+    S: ToString,
+{
+    match items.len() {
+        0 => return Err(eyre!("No items found")),
+        1 => return Ok(items.remove(0)),
+        _ => {}
+    }
+
+    let index = choose(&items, &prompt.into(), displayer, false)?[0];
+
+    Ok(items.remove(index))
+}
+
+/// Choose any number of `items` (at least one), listing favorites and recent picks first
+///
+/// # Errors
+///
+/// Will return `Err` if there are no items, if nothing is chosen,
+/// or if there are IO errors while prompting
+pub fn pick_many<T, P, F, S>(items: Vec<T>, prompt: P, displayer: F) -> Result<Vec<T>>
+where
+    P: Into<String>,
+    F: FnMut(&T) -> S,
+    S: ToString,
+{
+    if items.is_empty() {
+        return Err(eyre!("No items found"));
+    }
+
+    let chosen = choose(&items, &prompt.into(), displayer, true)?;
+
+    Ok(items
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| chosen.contains(index))
+        .map(|(_, item)| item)
+        .collect())
+}
+
+/// Prompt for one (or with `multiple`, several) of `items`, remembering what was chosen
+fn choose<T, F, S>(items: &[T], prompt: &str, displayer: F, multiple: bool) -> Result<Vec<usize>>
+where
+    F: FnMut(&T) -> S,
+    S: ToString,
+{
+    let labels = items
+        .iter()
+        .map(displayer)
+        .map(|label| label.to_string())
+        .collect::<Vec<_>>();
+
+    let path = state_dir()?.join(FILE_NAME);
+    let mut history = History::load(&path)?;
+
+    let chosen = interact(&mut history, prompt, &labels, multiple)?;
+    for &index in &chosen {
+        history.record(&labels[index]);
+    }
+
+    // Remembering picks is a convenience, so failing to is not an error
+    if let Err(e) = history.save() {
+        warn!("Unable to save picks to {}: {e}", path.display());
+    }
+
+    Ok(chosen)
+}
+
+/// What the picker is showing, between key presses
+struct View<'a> {
+    prompt: &'a str,
+    labels: &'a [String],
+    multiple: bool,
+    /// The typed query, while searching
+    query: Option<String>,
+    /// The indices of the labels listed, in order
+    visible: Vec<usize>,
+    cursor: usize,
+    marked: BTreeSet<usize>,
+}
+
+impl View<'_> {
+    fn refilter(&mut self, history: &History) {
+        let highlighted = self.visible.get(self.cursor).copied();
+        let order = history.order(self.labels);
+
+        self.visible = match &self.query {
+            Some(query) => filter(&order, self.labels, query),
+            None => order,
+        };
+        // Keep the same item highlighted, wherever it has moved to
+        self.cursor = highlighted
+            .and_then(|index| self.visible.iter().position(|&i| i == index))
+            .unwrap_or_default();
+    }
+
+    fn toggle_mark(&mut self) {
+        if let Some(&index) = self.visible.get(self.cursor) {
+            if !self.marked.remove(&index) {
+                self.marked.insert(index);
+            }
+        }
+    }
+
+    fn chosen(&self) -> Option<Vec<usize>> {
+        if self.multiple && !self.marked.is_empty() {
+            return Some(self.marked.iter().copied().collect());
+        }
+
+        self.visible.get(self.cursor).map(|&index| vec![index])
+    }
+}
+
+fn interact(
+    history: &mut History,
+    prompt: &str,
+    labels: &[String],
+    multiple: bool,
+) -> Result<Vec<usize>> {
+    let term = Term::stderr();
+    if !term.is_term() {
+        return Err(eyre!(
+            "Unable to prompt for {prompt}, as there is no terminal"
+        ));
+    }
+
+    let mut view = View {
+        prompt,
+        labels,
+        multiple,
+        query: None,
+        visible: history.order(labels),
+        cursor: 0,
+        marked: BTreeSet::new(),
+    };
+    let page_size = PAGE_SIZE.min(labels.len());
+    let mut drawn = 0;
+
+    term.hide_cursor()?;
+    let chosen = loop {
+        if drawn > 0 {
+            term.clear_last_lines(drawn)?;
+        }
+        drawn = render(&term, history, &view, page_size)?;
+
+        let count = view.visible.len().max(1);
+        match (term.read_key()?, view.query.as_mut()) {
+            (Key::ArrowUp, _) | (Key::Char('k'), None) => {
+                view.cursor = view.cursor.checked_sub(1).unwrap_or(count - 1);
+            }
+            (Key::ArrowDown, _) | (Key::Char('j'), None) => view.cursor = (view.cursor + 1) % count,
+            (Key::Tab, _) | (Key::Char(' '), None) if multiple => view.toggle_mark(),
+            (Key::Char('/'), None) => {
+                view.query = Some(String::new());
+                view.refilter(history);
+            }
+            (Key::Char('f'), None) => {
+                if let Some(&index) = view.visible.get(view.cursor) {
+                    history.toggle_favorite(&labels[index]);
+                    view.refilter(history);
+                }
+            }
+            (Key::Char(c), Some(query)) if !c.is_control() => {
+                query.push(c);
+                view.refilter(history);
+            }
+            (Key::Backspace, Some(query)) => {
+                query.pop();
+                view.refilter(history);
+            }
+            // Escape stops searching, showing everything again
+            (Key::Escape, Some(_)) => {
+                view.query = None;
+                view.refilter(history);
+            }
+            (Key::Enter, _) => {
+                if let Some(chosen) = view.chosen() {
+                    break Ok(chosen);
+                }
+            }
+            (Key::Escape | Key::Char('q'), None) => {
+                break Err(eyre!("Nothing chosen for {prompt}"));
+            }
+            _ => {}
+        }
+    };
+
+    term.clear_last_lines(drawn)?;
+    term.show_cursor()?;
+
+    if let Ok(indices) = &chosen {
+        let selection = indices
+            .iter()
+            .map(|&index| labels[index].as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut line = String::new();
+        theme::prompt().format_select_prompt_selection(&mut line, prompt, &selection)?;
+        term.write_line(&line)?;
+    }
+
+    chosen
+}
+
+/// Draw the prompt and the visible page of items, returning how many lines were drawn
+fn render(term: &Term, history: &History, view: &View, page_size: usize) -> Result<usize> {
+    let hint = match (&view.query, view.multiple) {
+        (Some(query), true) => format!("/{query} (tab to mark, esc to stop searching)"),
+        (Some(query), false) => format!("/{query} (esc to stop searching)"),
+        (None, true) => {
+            String::from("(/ to search, space to mark, f to favorite, enter to choose)")
+        }
+        (None, false) => String::from("(/ to search, f to favorite, enter to choose)"),
+    };
+
+    let mut line = String::new();
+    theme::prompt().format_select_prompt(&mut line, &format!("{} {hint}", view.prompt))?;
+    term.write_line(&line)?;
+
+    // The page keeps its height as the list narrows, so that redrawing clears the same lines
+    let first = view.cursor.saturating_sub(page_size - 1);
+    for position in first..first + page_size {
+        let mut line = String::new();
+
+        if let Some(&index) = view.visible.get(position) {
+            let label = &view.labels[index];
+            let mark = match (view.multiple, view.marked.contains(&index)) {
+                (true, true) => "[x] ",
+                (true, false) => "[ ] ",
+                (false, _) => "",
+            };
+            let favorite = if history.is_favorite(label) { '*' } else { ' ' };
+
+            theme::prompt().format_select_prompt_item(
+                &mut line,
+                &format!("{favorite} {mark}{label}"),
+                position == view.cursor,
+            )?;
+        } else if position == 0 {
+            line.push_str("  No matches");
+        }
+
+        term.write_line(&line)?;
+    }
+
+    Ok(page_size + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    fn labels(names: &[&str]) -> Vec<String> {
+        names.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn favorites_then_recent_then_alphabetical() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join(FILE_NAME);
+        let names = labels(&["dev", "Audit", "prod", "billing"]);
+
+        let mut history = History::load(&path)?;
+        assert_eq!(history.order(&names), vec![1, 3, 0, 2]);
+
+        history.picks.recent.insert(String::from("dev"), 100);
+        history.picks.recent.insert(String::from("billing"), 200);
+        history.toggle_favorite("prod");
+        assert_eq!(history.order(&names), vec![2, 3, 0, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fuzzy_matches_closest_first() {
+        let names = labels(&["prod-data-admin", "Production", "dev", "sandbox-prod"]);
+
+        assert_eq!(fuzzy_score("pd", "prod"), Some(2));
+        assert_eq!(fuzzy_score("od", "prod"), Some(2));
+        assert_eq!(fuzzy_score("", "prod"), Some(0));
+        assert_eq!(fuzzy_score("dp", "prod"), None);
+
+        // Ties keep their order (e.g. favorites first)
+        assert_eq!(filter(&[0, 1, 2, 3], &names, "PROD"), vec![0, 1, 3]);
+        assert_eq!(filter(&[0, 1, 2, 3], &names, "pda"), vec![0]);
+        assert_eq!(filter(&[0, 1, 2, 3], &names, "sbp"), vec![3]);
+    }
+
+    #[test]
+    fn saves_merge_with_other_runs() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join(FILE_NAME);
+
+        let mut first = History::load(&path)?;
+        let mut second = History::load(&path)?;
+
+        first.toggle_favorite("prod");
+        first.record("prod");
+        first.save()?;
+
+        second.toggle_favorite("dev");
+        second.record("audit");
+        second.save()?;
+
+        let history = History::load(&path)?;
+        assert!(history.is_favorite("prod"));
+        assert!(history.is_favorite("dev"));
+        assert!(history.picks.recent.contains_key("prod"));
+        assert!(history.picks.recent.contains_key("audit"));
+
+        Ok(())
+    }
+}
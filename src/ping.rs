@@ -0,0 +1,200 @@
+//! Probing the endpoints oktaws depends on, to tell slowness in oktaws apart from an Okta or AWS incident.
+
+use crate::config::organization::Organization;
+use crate::timestamp;
+use crate::tls;
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use eyre::Result;
+use futures::future::join_all;
+use url::Url;
+
+/// An endpoint to probe
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub name: String,
+    pub url: Url,
+}
+
+/// The outcome of probing a target
+#[derive(Debug)]
+pub struct Probe {
+    pub target: Target,
+    pub latency: Duration,
+    /// The HTTP status, or why no response was received
+    pub outcome: Result<u16, String>,
+}
+
+/// How an endpoint fared
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Health {
+    /// It answered, with anything but a server error
+    Reachable,
+    /// It answered, but with a server error, as during an incident
+    Degraded,
+    /// It didn't answer at all
+    Unreachable,
+}
+
+impl Probe {
+    #[must_use]
+    pub fn health(&self) -> Health {
+        match self.outcome {
+            Ok(status) if (500..600).contains(&status) => Health::Degraded,
+            Ok(_) => Health::Reachable,
+            Err(_) => Health::Unreachable,
+        }
+    }
+}
+
+/// The endpoints used by `organizations`, and the AWS endpoints in `regions`
+///
+/// # Errors
+///
+/// Will return `Err` if a region does not make a valid URL
+pub fn targets(organizations: &[Organization], regions: &[String]) -> Result<Vec<Target>> {
+    let mut targets = organizations
+        .iter()
+        .map(|organization| Target {
+            name: format!("Okta ({})", organization.name),
+            url: organization.portal.base_url().clone(),
+        })
+        .collect::<Vec<_>>();
+
+    for region in regions {
+        targets.push(Target {
+            name: format!("AWS SSO portal ({region})"),
+            url: Url::parse(&format!("https://portal.sso.{region}.amazonaws.com/"))?,
+        });
+    }
+
+    targets.push(Target {
+        name: String::from("AWS STS"),
+        url: Url::parse("https://sts.amazonaws.com/")?,
+    });
+    targets.push(Target {
+        name: String::from("AWS console sign-in"),
+        url: Url::parse("https://signin.aws.amazon.com/")?,
+    });
+
+    Ok(targets)
+}
+
+/// Probe every target at once, each with its own `timeout`.
+/// Any HTTP response but a server error counts as reachable (see [`Probe::health`]); the status is reported as is.
+///
+/// # Errors
+///
+/// Will return `Err` if the HTTP client cannot be created
+pub async fn probe_all(targets: Vec<Target>, timeout: Duration) -> Result<Vec<Probe>> {
+    let client = tls::client_builder().build()?;
+
+    Ok(join_all(targets.into_iter().map(|target| {
+        let client = &client;
+        async move {
+            let started = Instant::now();
+            let outcome = client
+                .get(target.url.clone())
+                .timeout(timeout)
+                .send()
+                .await
+                .map(|response| response.status().as_u16())
+                .map_err(|e| {
+                    if e.is_timeout() {
+                        format!("timed out after {}", timestamp::duration(timeout))
+                    } else if e.is_connect() {
+                        String::from("unable to connect")
+                    } else {
+                        e.to_string()
+                    }
+                });
+
+            Probe {
+                target,
+                latency: started.elapsed(),
+                outcome,
+            }
+        }
+    }))
+    .await)
+}
+
+/// A table of probe results
+#[must_use]
+pub fn render(probes: &[Probe]) -> String {
+    let width = probes
+        .iter()
+        .map(|probe| probe.target.name.len())
+        .max()
+        .unwrap_or_default()
+        .max("Endpoint".len());
+
+    let mut table = format!("{:width$}  {:>8}  Result\n", "Endpoint", "Latency");
+    for probe in probes {
+        let outcome = match (&probe.outcome, probe.health()) {
+            (Ok(status), Health::Degraded) => format!("DEGRADED: HTTP {status}"),
+            (Ok(status), _) => format!("HTTP {status}"),
+            (Err(e), _) => format!("FAILED: {e}"),
+        };
+        let _ = writeln!(
+            table,
+            "{:width$}  {:>6}ms  {outcome}",
+            probe.target.name,
+            probe.latency.as_millis()
+        );
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_table() -> Result<()> {
+        let probes = vec![
+            Probe {
+                target: Target {
+                    name: String::from("Okta (mock_org)"),
+                    url: Url::parse("https://mock_org.okta.com/")?,
+                },
+                latency: Duration::from_millis(120),
+                outcome: Ok(200),
+            },
+            Probe {
+                target: Target {
+                    name: String::from("AWS SSO portal (us-east-1)"),
+                    url: Url::parse("https://portal.sso.us-east-1.amazonaws.com/")?,
+                },
+                latency: Duration::from_millis(80),
+                outcome: Ok(503),
+            },
+            Probe {
+                target: Target {
+                    name: String::from("AWS STS"),
+                    url: Url::parse("https://sts.amazonaws.com/")?,
+                },
+                latency: Duration::from_secs(10),
+                outcome: Err(String::from("timed out after 10s")),
+            },
+        ];
+
+        assert_eq!(
+            probes.iter().map(Probe::health).collect::<Vec<_>>(),
+            vec![Health::Reachable, Health::Degraded, Health::Unreachable]
+        );
+        assert_eq!(
+            render(&probes),
+            "Endpoint                     Latency  Result
+Okta (mock_org)                120ms  HTTP 200
+AWS SSO portal (us-east-1)      80ms  DEGRADED: HTTP 503
+AWS STS                      10000ms  FAILED: timed out after 10s
+"
+        );
+
+        Ok(())
+    }
+}
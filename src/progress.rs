@@ -0,0 +1,165 @@
+//! Progress through discoveries over hundreds of accounts, shown as it goes rather than only at the end.
+//!
+//! Accounts are listed a page at a time, and each one's roles fetched after; the count so far is shown
+//! with an estimate of the time left once the last page has been listed. On a terminal, it is redrawn on one line
+//! of stderr (as the lockout countdown is); otherwise it is logged at most once every [`INTERVAL`].
+
+use crate::timestamp;
+
+use std::io::{IsTerminal, Write, stderr};
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+/// The least time between progress lines, and before the first
+pub const INTERVAL: Duration = Duration::from_secs(2);
+
+/// The least time between redraws on a terminal
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Progress through discovering the accounts of one application
+#[derive(Debug)]
+pub struct Progress {
+    label: String,
+    started: Instant,
+    logged: Option<Instant>,
+    /// Accounts listed so far, and whether that is all of them
+    listed: usize,
+    listed_all: bool,
+    /// Accounts whose roles have been fetched, or which were skipped
+    done: usize,
+    /// Accounts skipped, as outside the discovery's scope, which take no time
+    skipped: usize,
+    /// Whether the progress line is drawn on the terminal, rather than logged
+    terminal: bool,
+    drawn: bool,
+}
+
+impl Progress {
+    #[must_use]
+    pub fn new(label: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            started: Instant::now(),
+            logged: None,
+            listed: 0,
+            listed_all: false,
+            done: 0,
+            skipped: 0,
+            terminal: stderr().is_terminal(),
+            drawn: false,
+        }
+    }
+
+    /// Another page of `count` accounts has been listed, which is the last one if `last`
+    pub fn listed(&mut self, count: usize, last: bool) {
+        self.listed += count;
+        self.listed_all = last;
+    }
+
+    /// `count` more accounts were skipped, as outside the discovery's scope
+    pub fn skipped(&mut self, count: usize) {
+        self.skipped += count;
+        self.done(count);
+    }
+
+    /// `count` more accounts are done with, showing progress if it has not been shown recently
+    pub fn done(&mut self, count: usize) {
+        self.done += count;
+
+        let now = Instant::now();
+        let interval = if self.terminal {
+            REDRAW_INTERVAL
+        } else {
+            INTERVAL
+        };
+        if self
+            .logged
+            .is_none_or(|logged| now.duration_since(logged) >= interval)
+            && now.duration_since(self.started) >= INTERVAL
+        {
+            self.logged = Some(now);
+            let message = self.message(now.duration_since(self.started));
+            if self.terminal {
+                eprint!("\r{message}  ");
+                let _ = stderr().flush();
+                self.drawn = true;
+            } else {
+                info!("{message}");
+            }
+        }
+    }
+
+    /// The time left, if every account has been listed and the roles of some have been fetched.
+    /// Skipped accounts take no time, so are left out of the rate.
+    #[must_use]
+    pub fn remaining(&self, elapsed: Duration) -> Option<Duration> {
+        let fetched = self.done.saturating_sub(self.skipped);
+        if !self.listed_all || fetched == 0 {
+            return None;
+        }
+
+        let left = u32::try_from(self.listed.saturating_sub(self.done)).ok()?;
+        let fetched = u32::try_from(fetched).ok()?;
+
+        Some(elapsed / fetched * left)
+    }
+
+    /// A line describing the progress after `elapsed`
+    #[must_use]
+    pub fn message(&self, elapsed: Duration) -> String {
+        match self.remaining(elapsed) {
+            Some(remaining) => format!(
+                "{}: discovered {} of {} accounts (about {} left)",
+                self.label,
+                self.done,
+                self.listed,
+                timestamp::duration(remaining)
+            ),
+            None => format!(
+                "{}: discovered {} of {} accounts listed so far",
+                self.label, self.done, self.listed
+            ),
+        }
+    }
+}
+
+impl Drop for Progress {
+    /// Finish the progress line, so that what is printed next starts on its own
+    fn drop(&mut self) {
+        if self.drawn {
+            eprintln!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_once_every_page_is_listed() {
+        let mut progress = Progress::new("AWS SSO");
+
+        progress.listed(100, false);
+        progress.done(50);
+        assert_eq!(progress.remaining(Duration::from_secs(10)), None);
+        assert_eq!(
+            progress.message(Duration::from_secs(10)),
+            "AWS SSO: discovered 50 of 100 accounts listed so far"
+        );
+
+        progress.listed(50, true);
+        assert_eq!(
+            progress.remaining(Duration::from_secs(10)),
+            Some(Duration::from_secs(20))
+        );
+
+        // Skipped accounts are done with, but took none of the time
+        progress.skipped(25);
+        assert_eq!(
+            progress.remaining(Duration::from_secs(10)),
+            Some(Duration::from_secs(15))
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Masks tokens, cookies, passwords and credentials in log output,
+//! so that verbose logs can be shared when debugging.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use eyre::Result;
+use regex::{Captures, Regex};
+use tracing_subscriber::fmt::MakeWriter;
+
+const MASK: &str = "[REDACTED]";
+
+/// Patterns which are always redacted.
+/// Where a pattern has a `secret` group, only that group is masked, so the surrounding key stays readable.
+const DEFAULT_PATTERNS: &[&str] = &[
+    // JSON fields, e.g. `"sessionToken":"..."`
+    r#"(?i)"(?:session_?token|state_?token|password|new_?password|old_?password|passcode|token|access_?token|id_?token|refresh_?token|secret_?access_?key|access_?key_?id|auth_?code|SAMLResponse)"\s*:\s*"(?P<secret>[^"]*)""#,
+    // Debug output, e.g. `session_token: Some("...")`
+    r#"(?i)\b(?:session_token|state_token|password|new_password|old_password|passcode|token|access_token|secret_access_key|access_key_id|auth_code|saml)\s*:\s*(?:Some\()?"(?P<secret>[^"]*)""#,
+    // Form and query parameters, e.g. `SAMLResponse=...&RelayState=...`
+    r"(?i)\b(?:SAMLResponse|sessionToken|stateToken|token|authCode|password)=(?P<secret>[^&\s]+)",
+    // Cookies, e.g. `sid=...; DT=...`
+    r"\b(?:sid|DT|JSESSIONID|idx|xids|proximity_[0-9a-f]+)=(?P<secret>[^;\s]+)",
+    // Bearer tokens
+    r"(?i)(?:bearer[-_]token|authorization)\W+(?:bearer\s+)?(?P<secret>[A-Za-z0-9._~+/=-]{8,})",
+    // JavaScript state tokens on the extra verification page
+    r"var stateToken = '(?P<secret>[^']*)'",
+];
+
+/// A set of patterns whose matches are masked
+#[derive(Clone, Debug)]
+pub struct Redactor {
+    patterns: Arc<Vec<Regex>>,
+}
+
+impl Redactor {
+    /// Redact the default patterns, and any `extra` patterns.
+    /// Extra patterns are masked entirely, unless they have a `secret` group.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if any of the extra patterns are not valid regexes
+    pub fn new<S: AsRef<str>>(extra: &[S]) -> Result<Self> {
+        let patterns = DEFAULT_PATTERNS
+            .iter()
+            .copied()
+            .chain(extra.iter().map(AsRef::as_ref))
+            .map(Regex::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            patterns: Arc::new(patterns),
+        })
+    }
+
+    /// Mask every match in `text`
+    #[must_use]
+    pub fn redact(&self, text: &str) -> String {
+        self.patterns
+            .iter()
+            .fold(text.to_string(), |text, pattern| {
+                pattern
+                    .replace_all(&text, |caps: &Captures| match caps.name("secret") {
+                        Some(secret) => {
+                            let offset = caps.get(0).map_or(0, |whole| whole.start());
+                            let matched = &caps[0];
+                            format!(
+                                "{}{MASK}{}",
+                                &matched[..secret.start() - offset],
+                                &matched[secret.end() - offset..]
+                            )
+                        }
+                        None => MASK.to_string(),
+                    })
+                    .into_owned()
+            })
+    }
+}
+
+/// Makes writers which redact everything written through them before passing it on
+#[derive(Clone, Debug)]
+pub struct MakeRedacted<M> {
+    inner: M,
+    redactor: Redactor,
+}
+
+impl<M> MakeRedacted<M> {
+    pub const fn new(inner: M, redactor: Redactor) -> Self {
+        Self { inner, redactor }
+    }
+}
+
+impl<'a, M> MakeWriter<'a> for MakeRedacted<M>
+where
+    M: MakeWriter<'a>,
+{
+    type Writer = RedactedWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactedWriter::new(self.inner.make_writer(), self.redactor.clone())
+    }
+}
+
+/// A writer which holds back partial lines, and redacts each line once it is complete.
+/// Secrets split across several writes are still masked, as long as they do not span lines.
+#[derive(Debug)]
+pub struct RedactedWriter<W: Write> {
+    inner: W,
+    redactor: Redactor,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> RedactedWriter<W> {
+    pub const fn new(inner: W, redactor: Redactor) -> Self {
+        Self {
+            inner,
+            redactor,
+            pending: Vec::new(),
+        }
+    }
+
+    fn write_redacted(&mut self, line: &[u8]) -> io::Result<()> {
+        let redacted = self.redactor.redact(&String::from_utf8_lossy(line));
+        self.inner.write_all(redacted.as_bytes())
+    }
+}
+
+impl<W: Write> Write for RedactedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+
+        if let Some(last_newline) = self.pending.iter().rposition(|&b| b == b'\n') {
+            let rest = self.pending.split_off(last_newline + 1);
+            let complete = std::mem::replace(&mut self.pending, rest);
+            self.write_redacted(&complete)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.write_redacted(&pending)?;
+        }
+
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for RedactedWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_json_fields() {
+        let redactor = Redactor::new::<&str>(&[]).unwrap();
+
+        assert_eq!(
+            redactor.redact(r#"{"status":"SUCCESS","sessionToken":"20111abc"}"#),
+            r#"{"status":"SUCCESS","sessionToken":"[REDACTED]"}"#
+        );
+        assert_eq!(
+            redactor.redact(r#"{"roleCredentials":{"accessKeyId":"ASIA","secretAccessKey":"abc","sessionToken":"def"}}"#),
+            r#"{"roleCredentials":{"accessKeyId":"[REDACTED]","secretAccessKey":"[REDACTED]","sessionToken":"[REDACTED]"}}"#
+        );
+    }
+
+    #[test]
+    fn redact_debug_output() {
+        let redactor = Redactor::new::<&str>(&[]).unwrap();
+
+        assert_eq!(
+            redactor.redact(r#"LoginResponse { state_token: Some("00abc"), session_token: None }"#),
+            r#"LoginResponse { state_token: Some("[REDACTED]"), session_token: None }"#
+        );
+    }
+
+    #[test]
+    fn redact_cookies() {
+        let redactor = Redactor::new::<&str>(&[]).unwrap();
+
+        assert_eq!(
+            redactor.redact("Cookie: sid=102abc; DT=DI0xyz"),
+            "Cookie: sid=[REDACTED]; DT=[REDACTED]"
+        );
+    }
+
+    #[test]
+    fn redact_extra_patterns() {
+        let redactor = Redactor::new(&[r"\d{12}", r"email=(?P<secret>\S+)"]).unwrap();
+
+        assert_eq!(
+            redactor.redact("account 123456789012 email=user@example.com"),
+            "account [REDACTED] email=[REDACTED]"
+        );
+    }
+
+    #[test]
+    fn redact_across_writes() {
+        let redactor = Redactor::new::<&str>(&[]).unwrap();
+        let mut output = Vec::new();
+
+        {
+            let mut writer = RedactedWriter::new(&mut output, redactor);
+            write!(writer, "Received {{\"sessionTo").unwrap();
+            write!(writer, "ken\":\"secret\"}}\nnext").unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Received {\"sessionToken\":\"[REDACTED]\"}\nnext"
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! A record of which credentials file sections oktaws manages, and for which organization.
+//!
+//! Sections written before the registry existed (by older oktaws versions) are not in it.
+//! These are detected, so that they can be adopted rather than orphaned or duplicated.
+//! Those the user chose not to adopt are recorded as left alone, so that they are not asked about again.
+
+use crate::aws::profile::Store as ProfileStore;
+use crate::lock::FileLock;
+use crate::transaction::{Staged, write_atomically};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+/// The registry file, in the oktaws state directory
+pub const FILE_NAME: &str = "registry.json";
+
+/// A section of the credentials file written by oktaws
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub organization: String,
+    /// The oktaws version which last wrote the section
+    pub version: String,
+    /// Seconds since the epoch when the section was last written
+    pub written_at: u64,
+    /// Whether the user chose to leave the section as it is, rather than have oktaws manage it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub left_alone: bool,
+    /// The `~/.aws/config` settings oktaws last wrote for the section's profile,
+    /// so that those the profile no longer declares can be removed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub settings: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    path: PathBuf,
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Registry {
+    /// Load the registry from `path`, which need not exist yet
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file exists but cannot be read or parsed
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries: Self::read(path)?,
+        })
+    }
+
+    fn read(path: &Path) -> Result<BTreeMap<String, Entry>> {
+        if path.exists() {
+            Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+        } else {
+            Ok(BTreeMap::new())
+        }
+    }
+
+    /// The entry for a credentials file section, if oktaws manages it
+    #[must_use]
+    pub fn get(&self, section: &str) -> Option<&Entry> {
+        self.entries.get(section)
+    }
+
+    /// Record that oktaws wrote `section` for `organization`
+    pub fn record(&mut self, section: &str, organization: &str) {
+        self.insert(section, organization, false);
+    }
+
+    /// Record that the user chose not to let oktaws manage `section` (for `organization`), so it is left as it is
+    pub fn leave_alone(&mut self, section: &str, organization: &str) {
+        self.insert(section, organization, true);
+    }
+
+    /// Record that oktaws wrote `settings` (the keys, e.g. `output`) to `~/.aws/config`
+    /// for the profile whose credentials are in `section`, returning those it had written before
+    pub fn record_settings(&mut self, section: &str, settings: Vec<String>) -> Vec<String> {
+        self.entries
+            .get_mut(section)
+            .map(|entry| std::mem::replace(&mut entry.settings, settings))
+            .unwrap_or_default()
+    }
+
+    fn insert(&mut self, section: &str, organization: &str, left_alone: bool) {
+        let settings = self
+            .entries
+            .get(section)
+            .map(|entry| entry.settings.clone())
+            .unwrap_or_default();
+
+        self.entries.insert(
+            section.to_string(),
+            Entry {
+                organization: organization.to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                written_at: SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+                left_alone,
+                settings,
+            },
+        );
+    }
+
+    /// Whether the user chose to leave `section` as it is
+    #[must_use]
+    pub fn is_left_alone(&self, section: &str) -> bool {
+        self.entries
+            .get(section)
+            .is_some_and(|entry| entry.left_alone)
+    }
+
+    /// The sections which already hold STS credentials, but which oktaws has no record of writing.
+    /// These were most likely written by an older version of oktaws.
+    #[must_use]
+    pub fn unadopted<'a>(
+        &self,
+        store: &ProfileStore,
+        sections: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<String> {
+        sections
+            .into_iter()
+            .filter(|section| store.has_session(section) && !self.entries.contains_key(*section))
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Write the registry back to disk, merging with any changes made by other processes
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the lock cannot be acquired, or if the file cannot be read or written
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let _lock = FileLock::acquire(&self.path)?;
+
+        write_atomically(&self.path, self.merged()?.as_bytes())
+    }
+
+    /// The registry on disk, with this registry's entries added
+    fn merged(&self) -> Result<String> {
+        let mut entries = Self::read(&self.path)?;
+        entries.extend(self.entries.clone());
+
+        Ok(serde_json::to_string_pretty(&entries)?)
+    }
+}
+
+impl Staged for Registry {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn render(&self) -> Result<Option<String>> {
+        self.merged().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use tempfile::{NamedTempFile, tempdir};
+
+    #[test]
+    fn detects_unadopted_sections() -> Result<()> {
+        let mut credentials = NamedTempFile::new()?;
+        write!(
+            credentials,
+            "[old]\naws_access_key_id = A\naws_secret_access_key = B\naws_session_token = C\n\n[static]\naws_access_key_id = A\naws_secret_access_key = B\n"
+        )?;
+        let store = ProfileStore::load(Some(credentials.path()))?;
+
+        let dir = tempdir()?;
+        let mut registry = Registry::load(&dir.path().join("registry.json"))?;
+
+        assert_eq!(
+            registry.unadopted(&store, ["old", "static", "new"]),
+            vec![String::from("old")]
+        );
+
+        registry.record("old", "mock_org");
+        assert!(registry.unadopted(&store, ["old"]).is_empty());
+        assert!(!registry.is_left_alone("old"));
+
+        // The settings written before are kept until they are replaced
+        assert!(
+            registry
+                .record_settings("old", vec![String::from("output")])
+                .is_empty()
+        );
+        registry.record("old", "mock_org");
+        assert_eq!(
+            registry.record_settings("old", Vec::new()),
+            vec![String::from("output")]
+        );
+
+        // Not adopted, and not asked about again
+        registry.leave_alone("static", "mock_org");
+        assert!(registry.is_left_alone("static"));
+        assert!(registry.unadopted(&store, ["static"]).is_empty());
+
+        registry.save()?;
+        let registry = Registry::load(&dir.path().join("registry.json"))?;
+        assert_eq!(
+            registry.get("old").map(|entry| entry.organization.as_str()),
+            Some("mock_org")
+        );
+        assert!(registry.is_left_alone("static"));
+
+        Ok(())
+    }
+}
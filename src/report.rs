@@ -0,0 +1,263 @@
+use crate::config::organization::Organization;
+use crate::okta::applications::{AppLinkAccountRoleMapping, IntegrationType};
+
+use std::fmt;
+use std::str::FromStr;
+
+use eyre::{Error, Result, eyre};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Markdown,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "csv" => Ok(Self::Csv),
+            _ => Err(eyre!("Unknown report format {s}, expected markdown or csv")),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Markdown => write!(f, "markdown"),
+            Self::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// A single account in an inventory report
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    pub organization: String,
+    pub partition: String,
+    pub account_id: Option<String>,
+    pub account_name: String,
+    pub account_email: Option<String>,
+    pub roles: Vec<String>,
+    pub profile: Option<String>,
+}
+
+impl Entry {
+    /// Build a report entry from a discovered account,
+    /// looking up which configured profile (if any) it is mapped to.
+    #[must_use]
+    pub fn from_mapping(organization: &Organization, mapping: AppLinkAccountRoleMapping) -> Self {
+        let profile = organization
+            .profiles
+            .iter()
+            .find(|profile| {
+                profile.application_name == mapping.application_name
+                    && profile.account.as_ref().map_or(
+                        mapping.integration_type == IntegrationType::Federated,
+                        |account| *account == mapping.account_name,
+                    )
+            })
+            .map(|profile| profile.name.clone());
+
+        Self {
+            organization: organization.name.clone(),
+            partition: organization.partition.to_string(),
+            account_id: mapping.account_id,
+            account_name: mapping.account_name,
+            account_email: mapping.account_email,
+            roles: mapping.role_names,
+            profile,
+        }
+    }
+
+    fn columns(&self, role_separator: &str) -> [String; 7] {
+        [
+            self.organization.clone(),
+            self.partition.clone(),
+            self.account_id.clone().unwrap_or_default(),
+            self.account_name.clone(),
+            self.account_email.clone().unwrap_or_default(),
+            self.roles.join(role_separator),
+            self.profile.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+const HEADERS: [&str; 7] = [
+    "Organization",
+    "Partition",
+    "Account ID",
+    "Account Name",
+    "Account Email",
+    "Roles",
+    "Profile",
+];
+
+/// Render the entries as a report in the given format
+#[must_use]
+pub fn render(entries: &[Entry], format: Format) -> String {
+    match format {
+        Format::Markdown => render_markdown(entries),
+        Format::Csv => render_csv(entries),
+    }
+}
+
+fn render_markdown(entries: &[Entry]) -> String {
+    let row = |columns: &[String]| {
+        format!(
+            "| {} |\n",
+            columns
+                .iter()
+                .map(|column| column.replace('|', "\\|"))
+                .collect::<Vec<_>>()
+                .join(" | ")
+        )
+    };
+
+    let mut output = row(&HEADERS.map(String::from));
+    output.push_str(&row(&HEADERS.map(|_| String::from("---"))));
+    for entry in entries {
+        output.push_str(&row(&entry.columns(", ")));
+    }
+
+    output
+}
+
+fn render_csv(entries: &[Entry]) -> String {
+    let row = |columns: &[String]| {
+        format!(
+            "{}\n",
+            columns
+                .iter()
+                .map(|column| {
+                    if column.contains([',', '"', '\n']) {
+                        format!("\"{}\"", column.replace('"', "\"\""))
+                    } else {
+                        column.clone()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    };
+
+    let mut output = row(&HEADERS.map(String::from));
+    for entry in entries {
+        output.push_str(&row(&entry.columns(";")));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::aws::partition::Partition;
+    use crate::aws::profile::Layout;
+    use crate::aws::sso::AccountNaming;
+    use crate::config::profile::{CliSettings, Profile};
+    use crate::okta::applications::Precedence;
+    use crate::okta::portal::Portal;
+
+    use indexmap::IndexMap;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                organization: String::from("mock_org"),
+                partition: String::from("aws"),
+                account_id: Some(String::from("123456789012")),
+                account_name: String::from("prod"),
+                account_email: Some(String::from("prod@example.com")),
+                roles: vec![String::from("Admin"), String::from("ReadOnly")],
+                profile: Some(String::from("prod")),
+            },
+            Entry {
+                organization: String::from("mock_org"),
+                partition: String::from("aws"),
+                account_id: None,
+                account_name: String::from("sandbox, old"),
+                account_email: None,
+                roles: vec![String::from("Admin")],
+                profile: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn render_markdown_report() {
+        assert_eq!(
+            render(&entries(), Format::Markdown),
+            "| Organization | Partition | Account ID | Account Name | Account Email | Roles | Profile |
+| --- | --- | --- | --- | --- | --- | --- |
+| mock_org | aws | 123456789012 | prod | prod@example.com | Admin, ReadOnly | prod |
+| mock_org | aws |  | sandbox, old |  | Admin |  |
+"
+        );
+    }
+
+    #[test]
+    fn render_csv_report() {
+        assert_eq!(
+            render(&entries(), Format::Csv),
+            "Organization,Partition,Account ID,Account Name,Account Email,Roles,Profile
+mock_org,aws,123456789012,prod,prod@example.com,Admin;ReadOnly,prod
+mock_org,aws,,\"sandbox, old\",,Admin,
+"
+        );
+    }
+
+    #[test]
+    fn maps_entry_to_profile() {
+        let organization = Organization {
+            name: String::from("mock_org"),
+            username: String::from("mock_user"),
+            portal: Portal::new("mock_org").unwrap(),
+            account_naming: AccountNaming::default(),
+            application_precedence: Precedence::default(),
+            partition: Partition::default(),
+            credentials_file: None,
+            credentials_layout: Layout::default(),
+            priority: 0,
+            namespace: None,
+            profile_format: None,
+            silent_refresh: None,
+            identity_center: None,
+            sinks: Vec::new(),
+            profiles: vec![Profile {
+                name: String::from("production"),
+                application_name: String::from("AWS SSO"),
+                account: Some(String::from("prod")),
+                roles: vec![String::from("Admin")],
+                duration_seconds: None,
+                chain: None,
+                role_rules: Vec::new(),
+                saml_fallback: None,
+                console: None,
+                cli: CliSettings::default(),
+            }],
+            groups: IndexMap::new(),
+        };
+
+        let entry = Entry::from_mapping(
+            &organization,
+            AppLinkAccountRoleMapping {
+                account_name: String::from("prod"),
+                account_id: Some(String::from("123456789012")),
+                account_email: None,
+                account_description: None,
+                role_names: vec![String::from("Admin")],
+                application_name: String::from("AWS SSO"),
+                integration_type: IntegrationType::IdentityCenter,
+            },
+        );
+
+        assert_eq!(entry.profile, Some(String::from("production")));
+    }
+}
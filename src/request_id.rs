@@ -0,0 +1,282 @@
+//! The IDs Okta and AWS give each request, so that support tickets (e.g. about the portal's undocumented behavior)
+//! can point at the exact requests which failed.
+//!
+//! Okta returns `X-Okta-Request-Id` with every response, AWS `x-amzn-RequestId`, and the AWS SDKs keep it on their
+//! outputs and errors. The most recent requests are kept for the whole process: the IDs of the ones which failed are
+//! added to the error report when a command fails, and `oktaws elevate` records the IDs of its requests in the audit log.
+//! Failures which a retry got past (of the request, or of the whole attempt it was part of) are left out of the report,
+//! as they are not why the command failed.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+
+use aws_sdk_sts::operation::RequestId as AwsRequestId;
+use reqwest::Response;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// How many requests are kept
+const RECENT: usize = 64;
+
+/// Okta's request ID header
+const OKTA_HEADER: &str = "x-okta-request-id";
+
+/// AWS's request ID headers (the SSO portal's, and S3-style ones)
+const AWS_HEADERS: &[&str] = &["x-amzn-requestid", "x-amz-request-id"];
+
+/// Who handled a request
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    Okta,
+    Aws,
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Okta => write!(f, "Okta"),
+            Self::Aws => write!(f, "AWS"),
+        }
+    }
+}
+
+/// A request, as Okta or AWS identify it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RequestId {
+    pub service: Service,
+    pub id: String,
+    /// Whether the request failed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub failed: bool,
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} request {}", self.service, self.id)
+    }
+}
+
+impl RequestId {
+    /// The request ID in `headers`, if there is one
+    #[must_use]
+    pub fn from_headers(headers: &HeaderMap, failed: bool) -> Option<Self> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToString::to_string)
+        };
+
+        header(OKTA_HEADER)
+            .map(|id| (Service::Okta, id))
+            .or_else(|| {
+                AWS_HEADERS
+                    .iter()
+                    .find_map(|name| header(name))
+                    .map(|id| (Service::Aws, id))
+            })
+            .map(|(service, id)| Self {
+                service,
+                id,
+                failed,
+            })
+    }
+}
+
+struct Seen {
+    /// How many requests were seen before this one
+    sequence: usize,
+    request: RequestId,
+    /// Whether a retry got past the request's failure
+    recovered: bool,
+}
+
+static SEEN: Mutex<(usize, VecDeque<Seen>)> = Mutex::new((0, VecDeque::new()));
+
+tokio::task_local! {
+    /// The requests seen during the current attempt (see [`during`])
+    static ATTEMPT: RefCell<Vec<usize>>;
+}
+
+fn record(request: RequestId) -> usize {
+    if request.failed {
+        debug!("{request} failed");
+    }
+
+    let mut seen = SEEN
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let (count, recent) = &mut *seen;
+    if recent.len() == RECENT {
+        recent.pop_front();
+    }
+    let sequence = *count;
+    recent.push_back(Seen {
+        sequence,
+        request,
+        recovered: false,
+    });
+    *count += 1;
+
+    let _ = ATTEMPT.try_with(|attempt| attempt.borrow_mut().push(sequence));
+    sequence
+}
+
+/// Keep the ID of the request `response` answers, if it has one
+pub fn observe(response: &Response) {
+    observed(response);
+}
+
+/// Keep the ID of the request `response` answers, if it has one, returning its place among those seen
+pub(crate) fn observed(response: &Response) -> Option<usize> {
+    let failed = !response.status().is_success();
+    RequestId::from_headers(response.headers(), failed).map(record)
+}
+
+/// Run `attempt`, returning its output with the requests it made, for [`recovered`] once a retry has succeeded
+pub async fn during<F: Future>(attempt: F) -> (F::Output, Vec<usize>) {
+    ATTEMPT
+        .scope(RefCell::new(Vec::new()), async move {
+            let output = attempt.await;
+            (output, ATTEMPT.with(RefCell::take))
+        })
+        .await
+}
+
+/// Leave `requests` (from [`during`] or [`observed`]) out of [`failed`], as a retry got past them
+pub fn recovered(requests: &[usize]) {
+    if requests.is_empty() {
+        return;
+    }
+
+    let mut seen = SEEN
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    for seen in seen.1.iter_mut() {
+        if requests.contains(&seen.sequence) {
+            seen.recovered = true;
+        }
+    }
+}
+
+/// Keep the ID of an AWS SDK call, from its output or its error
+pub fn observe_aws<T: AwsRequestId, E: AwsRequestId>(result: &Result<T, E>) {
+    let (id, failed) = match result {
+        Ok(output) => (output.request_id(), false),
+        Err(error) => (error.request_id(), true),
+    };
+
+    if let Some(id) = id {
+        record(RequestId {
+            service: Service::Aws,
+            id: id.to_string(),
+            failed,
+        });
+    }
+}
+
+/// A mark, for [`since`] to return the requests made after it
+#[must_use]
+pub fn mark() -> usize {
+    SEEN.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .0
+}
+
+/// The requests made since `mark` (of those which are still kept)
+#[must_use]
+pub fn since(mark: usize) -> Vec<RequestId> {
+    SEEN.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .1
+        .iter()
+        .filter(|seen| seen.sequence >= mark)
+        .map(|seen| seen.request.clone())
+        .collect()
+}
+
+/// The recent requests which failed, and were not got past by a retry, oldest first
+#[must_use]
+pub fn failed() -> Vec<RequestId> {
+    SEEN.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .1
+        .iter()
+        .filter(|seen| seen.request.failed && !seen.recovered)
+        .map(|seen| seen.request.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn keeps_request_ids() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-amzn-RequestId", HeaderValue::from_static("aws-1"));
+        assert_eq!(
+            RequestId::from_headers(&headers, true),
+            Some(RequestId {
+                service: Service::Aws,
+                id: String::from("aws-1"),
+                failed: true,
+            })
+        );
+        headers.insert("X-Okta-Request-Id", HeaderValue::from_static("okta-1"));
+        assert_eq!(
+            RequestId::from_headers(&headers, false).map(|request| request.to_string()),
+            Some(String::from("Okta request okta-1"))
+        );
+        assert_eq!(RequestId::from_headers(&HeaderMap::new(), true), None);
+
+        let mark = mark();
+        record(RequestId::from_headers(&headers, false).unwrap());
+        record(RequestId {
+            service: Service::Aws,
+            id: String::from("aws-2"),
+            failed: true,
+        });
+
+        let requests = since(mark);
+        assert_eq!(
+            requests
+                .iter()
+                .map(|request| request.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["okta-1", "aws-2"]
+        );
+        assert!(failed().iter().any(|request| request.id == "aws-2"));
+        assert!(!failed().iter().any(|request| request.id == "okta-1"));
+    }
+
+    #[tokio::test]
+    async fn leaves_out_failures_a_retry_got_past() {
+        let failure = |id: &str| RequestId {
+            service: Service::Okta,
+            id: id.to_string(),
+            failed: true,
+        };
+
+        let ((), retried) = during(async {
+            record(failure("okta-retried"));
+        })
+        .await;
+        record(failure("okta-unrelated"));
+        recovered(&retried);
+
+        assert!(!failed().iter().any(|request| request.id == "okta-retried"));
+        assert!(
+            failed()
+                .iter()
+                .any(|request| request.id == "okta-unrelated")
+        );
+    }
+}
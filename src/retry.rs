@@ -0,0 +1,445 @@
+//! Retry budgets for work done in parallel,
+//! so that one flaky profile cannot stall a batch, or use up the retries of the others.
+//!
+//! Single requests to Okta and AWS are retried too (see [`execute`]), so that one throttled request
+//! is waited out rather than failing the profile: for as long as the server asks, or with jittered exponential backoff.
+
+use crate::blocking;
+use crate::request_id;
+use crate::transport::Transport;
+
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::DateTime;
+use eyre::{Report, Result};
+use reqwest::header::{DATE, HeaderMap, HeaderName, RETRY_AFTER};
+use reqwest::{Method, Request, RequestBuilder, Response, StatusCode};
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::time::error::Elapsed;
+use tokio::time::sleep;
+use tracing::debug;
+
+/// How many times a single HTTP request is sent before its last response (or error) is returned
+const HTTP_ATTEMPTS: u32 = 6;
+
+/// The backoff before the first retry of an HTTP request, doubling for each one after
+const HTTP_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// The longest wait between attempts, however long the server asks for
+const HTTP_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// The backoff before a budget's second attempt at a piece of work, doubling for each one after
+const BUDGET_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// The longest wait between a budget's attempts, however many it allows
+const BUDGET_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Okta's header for when its rate limit resets, in seconds since the epoch
+const RATE_LIMIT_RESET: &str = "x-rate-limit-reset";
+
+/// How many times, and for how long, a single piece of work may be tried
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Budget {
+    pub attempts: u32,
+    pub timeout: Duration,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            timeout: Duration::from_mins(2),
+        }
+    }
+}
+
+impl Budget {
+    /// Run `operation` until it succeeds, fails permanently, or the budget runs out.
+    /// Each attempt is given `timeout` to finish, not counting time the user spends at prompts.
+    /// Returns the number of attempts made, with the last result.
+    pub async fn run<F, Fut, T>(&self, mut operation: F) -> (u32, Result<T>)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        // The requests of the attempts which failed, for the error report to leave out once one succeeds
+        let mut retried = Vec::new();
+
+        loop {
+            attempt += 1;
+
+            let (result, requests) =
+                request_id::during(blocking::timeout(self.timeout, operation())).await;
+            let result =
+                match result {
+                    Ok(result) => result,
+                    Err(elapsed) => Err(Report::from(elapsed)
+                        .wrap_err(format!("Timed out after {:?}", self.timeout))),
+                };
+
+            match result {
+                Err(e) if attempt < self.attempts && is_transient(&e) => {
+                    debug!("Attempt {attempt} failed, retrying: {e}");
+                    retried.extend(requests);
+                    sleep(Self::backoff(attempt)).await;
+                }
+                result => {
+                    if result.is_ok() {
+                        request_id::recovered(&retried);
+                    }
+                    return (attempt, result);
+                }
+            }
+        }
+    }
+
+    /// The wait after `attempt` failed, doubling from [`BUDGET_BASE_DELAY`] up to [`BUDGET_MAX_DELAY`]
+    fn backoff(attempt: u32) -> Duration {
+        BUDGET_BASE_DELAY
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(BUDGET_MAX_DELAY)
+    }
+}
+
+/// Whether the error was caused by being unable to reach Okta or AWS at all
+#[must_use]
+pub fn is_network_error(error: &Report) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<Elapsed>()
+            || cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(|e| e.is_connect() || e.is_timeout())
+    })
+}
+
+/// Whether Okta or AWS is out of service: unreachable, or answering with server errors
+#[must_use]
+pub fn is_outage(error: &Report) -> bool {
+    is_network_error(error) || has_status(error, |status| status.is_server_error())
+}
+
+/// Whether trying again might succeed: network errors, throttling, and server errors
+#[must_use]
+pub fn is_transient(error: &Report) -> bool {
+    is_outage(error) || has_status(error, |status| status.as_u16() == 429)
+}
+
+/// Whether the error was caused by a response whose status matches `matches`
+fn has_status(error: &Report, matches: impl Fn(StatusCode) -> bool) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status)
+            .is_some_and(&matches)
+    })
+}
+
+/// Send `request` with the client it was built from, retrying as [`execute`] does
+///
+/// # Errors
+///
+/// Will return `Err` if the request cannot be built, or cannot be sent even after retrying
+pub async fn send(request: RequestBuilder) -> reqwest::Result<Response> {
+    let (client, request) = request.build_split();
+
+    execute(&client, request?).await
+}
+
+/// Send `request` through `transport`, retrying when throttled (429), on server errors (5xx), and when the server cannot be reached.
+/// Every response's request ID is kept (see [`request_id`]).
+/// Requests other than GETs may not be safe to repeat once the server has seen them,
+/// so they are only retried when throttled, or when they could not connect at all.
+/// After the last attempt, the response is returned as it is, for the caller to report.
+///
+/// # Errors
+///
+/// Will return `Err` if the request cannot be sent, even after retrying
+pub async fn execute(transport: &dyn Transport, request: Request) -> reqwest::Result<Response> {
+    let idempotent = *request.method() == Method::GET;
+    let mut attempt = 0;
+    // The attempts so far, which are left out of the error report if a later one succeeds
+    let mut attempts = Vec::new();
+    let mut observe = |response: &Response| {
+        attempts.extend(request_id::observed(response));
+        if response.status().is_success() {
+            request_id::recovered(&attempts);
+        }
+    };
+
+    loop {
+        attempt += 1;
+
+        // Streaming bodies cannot be sent twice, so those requests only get the one attempt
+        let Some(this) = request.try_clone().filter(|_| attempt < HTTP_ATTEMPTS) else {
+            return transport.execute(request).await.inspect(&mut observe);
+        };
+
+        let delay = match transport.execute(this).await.inspect(&mut observe) {
+            Ok(response) if should_retry(response.status(), idempotent) => {
+                let delay = server_delay(response.status(), response.headers())
+                    .map_or_else(|| backoff(attempt), |delay| delay.min(HTTP_MAX_DELAY));
+                debug!(
+                    "{} returned {}, retrying in {delay:?}",
+                    request.url(),
+                    response.status()
+                );
+                delay
+            }
+            Err(e) if e.is_connect() || (idempotent && e.is_timeout()) => {
+                let delay = backoff(attempt);
+                debug!("Request failed, retrying in {delay:?}: {e}");
+                delay
+            }
+            result => return result,
+        };
+
+        sleep(delay).await;
+    }
+}
+
+/// How long `response` asks to be left alone for, as for a retry (see [`execute`])
+#[must_use]
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    server_delay(response.status(), response.headers())
+}
+
+/// Whether a response with `status` is worth asking for again
+fn should_retry(status: StatusCode, idempotent: bool) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || (idempotent && status.is_server_error())
+}
+
+/// How long the server asked to be left alone for, from `Retry-After` (in seconds, or as a date),
+/// or when throttled by Okta, until its rate limit resets.
+/// Dates are compared with the response's own `Date` where it has one, so that clock skew does not matter.
+fn server_delay(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: HeaderName| headers.get(name).and_then(|value| value.to_str().ok());
+    let date = |value: &str| {
+        DateTime::parse_from_rfc2822(value)
+            .ok()
+            .map(SystemTime::from)
+    };
+    let now = header(DATE).and_then(date).unwrap_or_else(SystemTime::now);
+    let until = |then: SystemTime| then.duration_since(now).unwrap_or_default();
+
+    if let Some(retry_after) = header(RETRY_AFTER) {
+        return retry_after
+            .trim()
+            .parse()
+            .ok()
+            .map(Duration::from_secs)
+            .or_else(|| date(retry_after).map(until));
+    }
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return header(HeaderName::from_static(RATE_LIMIT_RESET))
+            .and_then(|reset| reset.trim().parse().ok())
+            .map(|reset| until(UNIX_EPOCH + Duration::from_secs(reset)));
+    }
+
+    None
+}
+
+/// The wait before retrying after `attempt`: half of the exponential backoff, plus a random part of the other half,
+/// so that requests throttled together do not all retry together
+fn backoff(attempt: u32) -> Duration {
+    let mut random = [0; 4];
+    let jitter = SystemRandom::new().fill(&mut random).map_or(0.5, |()| {
+        f64::from(u32::from_le_bytes(random)) / f64::from(u32::MAX)
+    });
+
+    jittered(attempt, jitter)
+}
+
+/// The wait before retrying after `attempt`, with `jitter` (between 0 and 1) of the random half
+fn jittered(attempt: u32, jitter: f64) -> Duration {
+    let ceiling = HTTP_BASE_DELAY
+        .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(HTTP_MAX_DELAY);
+
+    ceiling / 2 + (ceiling / 2).mul_f64(jitter.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::transport::Canned;
+
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use eyre::eyre;
+
+    #[tokio::test]
+    async fn retries_timeouts() {
+        let calls = AtomicU32::new(0);
+        let budget = Budget {
+            attempts: 3,
+            timeout: Duration::from_millis(50),
+        };
+
+        let (attempts, result) = budget
+            .run(|| async {
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    sleep(Duration::from_secs(1)).await;
+                }
+                Ok(())
+            })
+            .await;
+
+        assert_eq!(attempts, 2);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_permanent_errors() {
+        let (attempts, result) = Budget::default()
+            .run(|| async { Err::<(), _>(eyre!("No role found")) })
+            .await;
+
+        assert_eq!(attempts, 1);
+        assert_eq!(result.unwrap_err().to_string(), "No role found");
+    }
+
+    #[tokio::test]
+    async fn stops_when_budget_is_spent() {
+        let budget = Budget {
+            attempts: 2,
+            timeout: Duration::from_millis(10),
+        };
+
+        let (attempts, result) = budget
+            .run(|| async {
+                sleep(Duration::from_secs(1)).await;
+                Ok(())
+            })
+            .await;
+
+        assert_eq!(attempts, 2);
+        assert_eq!(result.unwrap_err().to_string(), "Timed out after 10ms");
+    }
+
+    #[test]
+    fn waits_as_long_as_the_server_asks() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        HeaderName::from_static(name),
+                        reqwest::header::HeaderValue::from_static(value),
+                    )
+                })
+                .collect::<HeaderMap>()
+        };
+
+        assert_eq!(
+            server_delay(
+                StatusCode::SERVICE_UNAVAILABLE,
+                &headers(&[("retry-after", "7")])
+            ),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            server_delay(
+                StatusCode::TOO_MANY_REQUESTS,
+                &headers(&[
+                    ("date", "Wed, 21 Oct 2015 07:28:00 GMT"),
+                    ("retry-after", "Wed, 21 Oct 2015 07:28:05 GMT"),
+                ])
+            ),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            server_delay(
+                StatusCode::TOO_MANY_REQUESTS,
+                &headers(&[
+                    ("date", "Wed, 21 Oct 2015 07:28:00 GMT"),
+                    ("x-rate-limit-reset", "1445412492"),
+                ])
+            ),
+            Some(Duration::from_secs(12))
+        );
+        // Okta sends its rate limit on every response, but it only matters once throttled
+        assert_eq!(
+            server_delay(
+                StatusCode::BAD_GATEWAY,
+                &headers(&[("x-rate-limit-reset", "1445412492")])
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn budget_backoff_is_capped() {
+        assert_eq!(Budget::backoff(1), Duration::from_millis(500));
+        assert_eq!(Budget::backoff(3), Duration::from_secs(2));
+        // However many attempts the daemon is allowed (--attempts)
+        assert_eq!(Budget::backoff(40), BUDGET_MAX_DELAY);
+        assert_eq!(Budget::backoff(u32::MAX), BUDGET_MAX_DELAY);
+    }
+
+    #[test]
+    fn server_errors_are_outages() {
+        let failed = |status: u16| {
+            Report::new(
+                Response::from(
+                    http::Response::builder()
+                        .status(status)
+                        .body(String::new())
+                        .unwrap(),
+                )
+                .error_for_status()
+                .unwrap_err(),
+            )
+        };
+
+        assert!(is_outage(&failed(503)));
+        assert!(!is_outage(&failed(429)));
+        assert!(is_transient(&failed(429)));
+        assert!(!is_outage(&failed(401)));
+        assert!(!is_outage(&eyre!("Invalid password")));
+    }
+
+    #[test]
+    fn backs_off_exponentially_with_jitter() {
+        assert_eq!(jittered(1, 0.0), Duration::from_millis(250));
+        assert_eq!(jittered(1, 1.0), Duration::from_millis(500));
+        assert_eq!(jittered(3, 0.5), Duration::from_millis(1500));
+        assert_eq!(jittered(20, 1.0), HTTP_MAX_DELAY);
+
+        assert!(should_retry(StatusCode::TOO_MANY_REQUESTS, false));
+        assert!(should_retry(StatusCode::BAD_GATEWAY, true));
+        assert!(!should_retry(StatusCode::BAD_GATEWAY, false));
+        assert!(!should_retry(StatusCode::NOT_FOUND, true));
+    }
+
+    #[tokio::test]
+    async fn retries_throttled_requests() -> Result<()> {
+        let throttled = http::Response::builder()
+            .status(429)
+            .header("retry-after", "0")
+            .body(String::new())?;
+        let transport = Canned::new().respond_with(throttled).respond(200, "{}");
+
+        let request = Request::new(
+            Method::GET,
+            "https://mock.okta.com/api/v1/users/me".parse()?,
+        );
+        let response = execute(&transport, request).await?;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(transport.requests().len(), 2);
+
+        // A POST may already have been acted on, so a server error is returned as it is
+        let transport = Canned::new().respond(502, "");
+        let request = Request::new(Method::POST, "https://mock.okta.com/api/v1/authn".parse()?);
+        let response = execute(&transport, request).await?;
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(transport.requests().len(), 1);
+
+        Ok(())
+    }
+}
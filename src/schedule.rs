@@ -0,0 +1,118 @@
+use std::time::{Duration, SystemTime};
+
+use aws_credential_types::Credentials;
+
+/// Never leave less than this long between a refresh and the expiry of the credentials it replaces
+pub const MIN_MARGIN: Duration = Duration::from_mins(5);
+
+/// When a profile's credentials expire, and when they should next be refreshed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    pub profile: String,
+    pub expires_at: SystemTime,
+    pub refresh_at: SystemTime,
+}
+
+impl Schedule {
+    /// Work out a refresh schedule for credentials issued at `issued`.
+    /// The session length is inferred from the returned expiration,
+    /// so roles with a long maximum session are refreshed less often than short-lived ones.
+    /// Returns `None` for credentials which do not expire.
+    #[must_use]
+    pub fn new(profile: &str, credentials: &Credentials, issued: SystemTime) -> Option<Self> {
+        let expires_at = credentials.expiry()?;
+
+        Some(Self {
+            profile: profile.to_string(),
+            expires_at,
+            refresh_at: refresh_at(issued, expires_at),
+        })
+    }
+
+    /// Whether the credentials are due for a refresh at `now`
+    #[must_use]
+    pub fn is_due(&self, now: SystemTime) -> bool {
+        now >= self.refresh_at
+    }
+}
+
+/// Refresh once 80% of the session has elapsed, keeping at least `MIN_MARGIN` in hand
+#[must_use]
+pub fn refresh_at(issued: SystemTime, expires_at: SystemTime) -> SystemTime {
+    let lifetime = expires_at.duration_since(issued).unwrap_or_default();
+    let margin = (lifetime / 5).max(MIN_MARGIN).min(lifetime);
+
+    expires_at - margin
+}
+
+/// The schedule which is due soonest
+#[must_use]
+pub fn next(schedules: &[Schedule]) -> Option<&Schedule> {
+    schedules.iter().min_by_key(|schedule| schedule.refresh_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn refresh_scales_with_session_length() {
+        let issued = UNIX_EPOCH;
+
+        // 12 hour role: refresh after 9.6 hours
+        assert_eq!(
+            refresh_at(issued, issued + Duration::from_hours(12)),
+            issued + Duration::from_mins(576)
+        );
+
+        // 1 hour role: refresh after 48 minutes
+        assert_eq!(
+            refresh_at(issued, issued + Duration::from_hours(1)),
+            issued + Duration::from_mins(48)
+        );
+
+        // 15 minute session: keep the minimum margin
+        assert_eq!(
+            refresh_at(issued, issued + Duration::from_mins(15)),
+            issued + Duration::from_mins(10)
+        );
+
+        // Shorter than the margin: refresh straight away
+        assert_eq!(refresh_at(issued, issued + Duration::from_mins(2)), issued);
+    }
+
+    #[test]
+    fn next_schedule() {
+        let issued = UNIX_EPOCH;
+        let credentials = |lifetime| {
+            Credentials::new(
+                "ACCESS_KEY",
+                "SECRET_KEY",
+                None,
+                Some(issued + lifetime),
+                "test",
+            )
+        };
+
+        let schedules = vec![
+            Schedule::new("long", &credentials(Duration::from_hours(12)), issued).unwrap(),
+            Schedule::new("short", &credentials(Duration::from_hours(1)), issued).unwrap(),
+        ];
+
+        let next = next(&schedules).unwrap();
+        assert_eq!(next.profile, "short");
+        assert!(!next.is_due(issued + Duration::from_mins(47)));
+        assert!(next.is_due(issued + Duration::from_mins(48)));
+
+        assert_eq!(
+            Schedule::new(
+                "static",
+                &Credentials::new("ACCESS_KEY", "SECRET_KEY", None, None, "test"),
+                issued
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Finding accounts and roles by a free-text query, for `oktaws search`.
+//!
+//! The query is split into words, and each must fuzzily match (see [`picker::fuzzy_score`]) one of a role's
+//! account name, ID, email or description, its Okta application, or the role's own name.
+//! Matches are listed closest first, with the profile (or config) needed to use them.
+
+use crate::config::organization::Organization;
+use crate::discovery::CatalogEntry;
+use crate::picker;
+
+use std::fmt::Write;
+
+use serde::Serialize;
+
+/// A role matching the query
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Hit {
+    pub organization: String,
+    pub entry: CatalogEntry,
+    pub role: String,
+    /// The configured profile for the account and role, if there is one
+    pub profile: Option<String>,
+    #[serde(skip)]
+    score: usize,
+}
+
+/// How closely a role matches every word of `query`, if it matches them all: lower is closer
+fn score(query: &str, entry: &CatalogEntry, role: &str) -> Option<usize> {
+    let fields = [
+        Some(entry.account.as_str()),
+        entry.account_id.as_deref(),
+        entry.account_email.as_deref(),
+        entry.description.as_deref(),
+        Some(entry.application.as_str()),
+        Some(role),
+    ];
+
+    query.split_whitespace().try_fold(0, |total, word| {
+        fields
+            .iter()
+            .flatten()
+            .filter_map(|field| picker::fuzzy_score(word, field))
+            .min()
+            .map(|score| total + score)
+    })
+}
+
+/// The configured profile which uses `role` in the catalog entry's account
+fn profile(organization: &Organization, entry: &CatalogEntry, role: &str) -> Option<String> {
+    organization
+        .profiles
+        .iter()
+        .find(|profile| {
+            profile.application_name == entry.application
+                && profile
+                    .account
+                    .as_ref()
+                    .map_or(entry.federated, |account| *account == entry.account)
+                && (profile.roles.is_empty() || profile.roles.iter().any(|r| r == role))
+        })
+        .map(|profile| profile.name.clone())
+}
+
+/// The roles in `catalog` (an organization's discovered accounts) matching `query`
+#[must_use]
+pub fn search(organization: &Organization, catalog: &[CatalogEntry], query: &str) -> Vec<Hit> {
+    catalog
+        .iter()
+        .flat_map(|entry| {
+            entry.roles.iter().filter_map(move |role| {
+                score(query, entry, role).map(|score| Hit {
+                    organization: organization.name.clone(),
+                    entry: entry.clone(),
+                    role: role.clone(),
+                    profile: profile(organization, entry, role),
+                    score,
+                })
+            })
+        })
+        .collect()
+}
+
+/// Sort `hits` closest first, and keep at most `limit`
+pub fn rank(hits: &mut Vec<Hit>, limit: usize) {
+    hits.sort_by(|a, b| {
+        a.score
+            .cmp(&b.score)
+            .then_with(|| a.entry.account.cmp(&b.entry.account))
+            .then_with(|| a.role.cmp(&b.role))
+    });
+    hits.truncate(limit);
+}
+
+/// A description of each hit, with how to use it
+#[must_use]
+pub fn render(hits: &[Hit]) -> String {
+    let mut output = String::new();
+
+    for hit in hits {
+        let details = [
+            hit.entry.account_id.as_deref(),
+            hit.entry.account_email.as_deref(),
+            hit.entry.description.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        let _ = write!(output, "{} / {}", hit.entry.account, hit.role);
+        if !details.is_empty() {
+            let _ = write!(output, " ({})", details.join(", "));
+        }
+        let _ = writeln!(output, " in {}", hit.organization);
+
+        let _ = match &hit.profile {
+            Some(profile) => writeln!(output, "  oktaws exec {profile} -- <command>"),
+            None => writeln!(
+                output,
+                "  Not configured; add to {}.toml: {} = {{ application = '{}', account = '{}', role = '{}' }}",
+                hit.organization,
+                hit.entry.account,
+                hit.entry.application,
+                hit.entry.account,
+                hit.role
+            ),
+        };
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::aws::partition::Partition;
+    use crate::aws::profile::Layout;
+    use crate::aws::sso::AccountNaming;
+    use crate::config::profile::{CliSettings, Profile};
+    use crate::okta::applications::Precedence;
+    use crate::okta::portal::Portal;
+
+    use indexmap::IndexMap;
+
+    fn entry(account: &str, email: &str, roles: &[&str]) -> CatalogEntry {
+        CatalogEntry {
+            account: account.to_string(),
+            account_id: Some(String::from("123456789012")),
+            account_email: Some(email.to_string()),
+            description: None,
+            application: String::from("AWS SSO"),
+            federated: false,
+            roles: roles.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn finds_roles_matching_every_word() -> eyre::Result<()> {
+        let organization = Organization {
+            name: String::from("mock_org"),
+            username: String::from("mock_user"),
+            portal: Portal::new("mock_org")?,
+            account_naming: AccountNaming::default(),
+            application_precedence: Precedence::default(),
+            partition: Partition::default(),
+            credentials_file: None,
+            credentials_layout: Layout::default(),
+            priority: 0,
+            namespace: None,
+            profile_format: None,
+            silent_refresh: None,
+            identity_center: None,
+            sinks: Vec::new(),
+            profiles: vec![Profile {
+                name: String::from("payments-prod-admin"),
+                application_name: String::from("AWS SSO"),
+                account: Some(String::from("payments-prod")),
+                roles: vec![String::from("Admin")],
+                duration_seconds: None,
+                chain: None,
+                role_rules: Vec::new(),
+                saml_fallback: None,
+                console: None,
+                cli: CliSettings::default(),
+            }],
+            groups: IndexMap::new(),
+        };
+        let catalog = vec![
+            entry(
+                "payments-prod",
+                "payments+prod@example.com",
+                &["Admin", "ReadOnly"],
+            ),
+            entry("payments-dev", "payments+dev@example.com", &["Admin"]),
+            entry("data-prod", "data@example.com", &["Admin"]),
+        ];
+
+        let mut hits = search(&organization, &catalog, "payments prod");
+        rank(&mut hits, 10);
+        assert_eq!(
+            hits.iter()
+                .map(|hit| (hit.entry.account.as_str(), hit.role.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("payments-prod", "Admin"), ("payments-prod", "ReadOnly")]
+        );
+        assert_eq!(hits[0].profile.as_deref(), Some("payments-prod-admin"));
+        assert_eq!(hits[1].profile, None);
+
+        let rendered = render(&hits[..1]);
+        assert!(rendered.contains("oktaws exec payments-prod-admin -- <command>"));
+
+        assert!(search(&organization, &catalog, "payments staging").is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,566 @@
+//! Secrets oktaws keeps between runs: the Okta password and header values, the Okta session from the last sign-in,
+//! and AWS SSO portal tokens.
+//!
+//! They are kept in the first store which works, from:
+//! - the OS keyring (Keychain, Credential Manager, Secret Service)
+//! - a file in the oktaws state directory, encrypted with a passphrase (from `OKTAWS_SECRETS_PASSPHRASE`, or prompted for once)
+//! - nowhere, so that the password is prompted for every time
+//!
+//! `--secret-store` (or `secret_store` in `settings.toml`) chooses one instead, and `--no-keyring` keeps them
+//! in plain files, readable only by the current user. The store in use is logged.
+//! Each access can block (e.g. on a keyring unlock dialog, or the passphrase prompt), so callers run them off the reactor.
+
+use crate::askpass;
+use crate::config::state_dir;
+use crate::state;
+
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use eyre::{Error, Result, eyre};
+use keyring::{Entry, Error as KeyringError};
+use ring::aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::hkdf::{HKDF_SHA256, Salt};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use url::Url;
+
+/// The directory (in the oktaws state directory) secrets are kept in, when they are kept in files
+pub const DIR: &str = "secrets";
+
+/// The variable holding the passphrase for the encrypted file store
+pub const PASSPHRASE_VARIABLE: &str = "OKTAWS_SECRETS_PASSPHRASE";
+
+/// The salt for the encrypted file store's master key, in [`DIR`]
+const SALT_FILE: &str = "salt";
+
+/// The start of each encrypted file, before its own salt: each file's key is expanded from the master key with it,
+/// so that no two files are encrypted with the same key.
+const FORMAT: &[u8] = b"oktaws-secret-v2\n";
+
+/// The length of each encrypted file's salt
+const FILE_SALT_LEN: usize = 16;
+
+/// A known value encrypted with the encrypted file store's key, in [`DIR`],
+/// so that a mistyped passphrase is caught before anything is encrypted with it
+const VERIFIER_FILE: &str = "verifier";
+
+/// What the verifier holds
+const VERIFIER: &str = "oktaws";
+
+/// Where secrets are kept
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Store {
+    /// The OS keyring if it works, otherwise an encrypted file if there is a passphrase, otherwise nowhere
+    #[default]
+    Auto,
+    /// The OS keyring
+    Keyring,
+    /// Files encrypted with a passphrase
+    EncryptedFile,
+    /// Plain files, readable only by the current user
+    File,
+    /// Nowhere: the password is prompted for every time, and sessions are not remembered
+    None,
+}
+
+impl FromStr for Store {
+    type Err = Error;
+
+    fn from_str(store: &str) -> Result<Self> {
+        match store {
+            "auto" => Ok(Self::Auto),
+            "keyring" => Ok(Self::Keyring),
+            "encrypted-file" => Ok(Self::EncryptedFile),
+            "file" => Ok(Self::File),
+            "none" => Ok(Self::None),
+            _ => Err(eyre!(
+                "Unknown secret store {store} (expected auto, keyring, encrypted-file, file or none)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Store {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Keyring => write!(f, "keyring"),
+            Self::EncryptedFile => write!(f, "encrypted-file"),
+            Self::File => write!(f, "file"),
+            Self::None => write!(f, "none"),
+        }
+    }
+}
+
+static REQUESTED: OnceLock<Store> = OnceLock::new();
+static ACTIVE: OnceLock<Store> = OnceLock::new();
+static MASTER_KEY: OnceLock<[u8; 32]> = OnceLock::new();
+
+/// Choose where secrets are kept, for the whole process. Only the first call has any effect.
+pub fn install(store: Store) {
+    let _ = REQUESTED.set(store);
+}
+
+/// The store secrets are kept in, choosing one (and logging it) on first use
+#[must_use]
+pub fn active() -> Store {
+    *ACTIVE.get_or_init(|| {
+        let requested = REQUESTED.get().copied().unwrap_or_default();
+        let store = resolve(requested, keyring_error, passphrase_available);
+
+        if requested == Store::Auto && store != Store::Keyring {
+            warn!(
+                "The OS keyring is unavailable, so secrets are kept in: {store}. \
+                 Choose another store with --secret-store, or secret_store in settings.toml"
+            );
+        } else {
+            info!("Keeping secrets in: {store}");
+        }
+
+        store
+    })
+}
+
+/// The store to use for `requested`: for `Auto`, the first in the chain which is available
+fn resolve(
+    requested: Store,
+    keyring_error: impl FnOnce() -> Option<KeyringError>,
+    passphrase_available: impl FnOnce() -> bool,
+) -> Store {
+    if requested != Store::Auto {
+        return requested;
+    }
+
+    if let Some(e) = keyring_error() {
+        warn!("Unable to use the OS keyring ({e})");
+    } else {
+        return Store::Keyring;
+    }
+
+    if passphrase_available() {
+        Store::EncryptedFile
+    } else {
+        Store::None
+    }
+}
+
+/// Why the OS keyring cannot be used, if it cannot
+fn keyring_error() -> Option<KeyringError> {
+    match Entry::new("oktaws::probe", "probe").and_then(|entry| entry.get_password()) {
+        Ok(_) | Err(KeyringError::NoEntry) => None,
+        Err(e) => Some(e),
+    }
+}
+
+/// Whether secrets are kept at all, for callers which can do without keeping one
+#[must_use]
+pub fn kept() -> bool {
+    active() != Store::None
+}
+
+/// Whether there is a passphrase for the encrypted file store, or a way to ask for one
+fn passphrase_available() -> bool {
+    env::var_os(PASSPHRASE_VARIABLE).is_some() || askpass::interactive()
+}
+
+/// The master key for the encrypted file store, derived from its passphrase on first use
+fn key(dir: &Path) -> Result<&'static [u8; 32]> {
+    if let Some(key) = MASTER_KEY.get() {
+        return Ok(key);
+    }
+
+    let salt_path = dir.join(SALT_FILE);
+    let salt = if salt_path.exists() {
+        fs::read(&salt_path)?
+    } else {
+        let mut salt = vec![0; 16];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| eyre!("Unable to generate a salt"))?;
+        write_private(&salt_path, &salt)?;
+        salt
+    };
+
+    let verifier_path = dir.join(VERIFIER_FILE);
+    let passphrase = match env::var(PASSPHRASE_VARIABLE) {
+        Ok(passphrase) => passphrase,
+        // A new passphrase is typed twice, as a typo would lock every secret away
+        Err(_) if !verifier_path.exists() => {
+            let passphrase = askpass::secret("New passphrase for oktaws secrets")?;
+            if askpass::secret("Confirm the passphrase for oktaws secrets")? != passphrase {
+                return Err(eyre!("The passphrases for oktaws secrets do not match"));
+            }
+            passphrase
+        }
+        Err(_) => askpass::secret("Passphrase for oktaws secrets")?,
+    };
+
+    let key = state::derive(&passphrase, &salt, state::ITERATIONS)?;
+    verify(&key, &verifier_path)?;
+    let _ = MASTER_KEY.set(key);
+
+    MASTER_KEY
+        .get()
+        .ok_or_else(|| eyre!("Unable to derive the key for oktaws secrets"))
+}
+
+/// Check `key` against the verifier at `path`, or make one with it if there is none yet
+fn verify(key: &[u8; 32], path: &Path) -> Result<()> {
+    if !path.exists() {
+        return write_private(path, &seal(key, VERIFIER.as_bytes())?);
+    }
+
+    match open(key, &fs::read(path)?) {
+        Ok(verifier) if verifier == VERIFIER => Ok(()),
+        _ => Err(eyre!(
+            "The passphrase for oktaws secrets is wrong; check {PASSPHRASE_VARIABLE} (or the passphrase given)"
+        )),
+    }
+}
+
+/// The key for a file with `salt`, expanded from the master key with HKDF
+fn file_key(master_key: &[u8; 32], salt: &[u8]) -> Result<LessSafeKey> {
+    let okm = Salt::new(HKDF_SHA256, salt)
+        .extract(master_key)
+        .expand(&[FORMAT], &CHACHA20_POLY1305)
+        .map_err(|_| eyre!("Unable to derive the key for the secret"))?;
+
+    Ok(LessSafeKey::new(UnboundKey::from(okm)))
+}
+
+/// `plaintext` encrypted with a key of its own (from `master_key` and a random salt), after the salt and a random nonce
+fn seal(master_key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let rng = SystemRandom::new();
+    let mut salt = [0; FILE_SALT_LEN];
+    rng.fill(&mut salt)
+        .map_err(|_| eyre!("Unable to generate a salt"))?;
+    let mut nonce = [0; NONCE_LEN];
+    rng.fill(&mut nonce)
+        .map_err(|_| eyre!("Unable to generate a nonce"))?;
+
+    let mut sealed = plaintext.to_vec();
+    file_key(master_key, &salt)?
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut sealed,
+        )
+        .map_err(|_| eyre!("Unable to encrypt the secret"))?;
+
+    Ok([FORMAT, salt.as_slice(), nonce.as_slice(), &sealed].concat())
+}
+
+/// The plaintext of `sealed`, written by [`seal`] with `master_key`
+fn open(master_key: &[u8; 32], sealed: &[u8]) -> Result<String> {
+    let (salt, sealed) = sealed
+        .strip_prefix(FORMAT)
+        .filter(|salted| salted.len() >= FILE_SALT_LEN)
+        .map(|salted| salted.split_at(FILE_SALT_LEN))
+        .ok_or_else(|| eyre!("The secret is corrupted"))?;
+    let key = file_key(master_key, salt)?;
+
+    if sealed.len() < NONCE_LEN {
+        return Err(eyre!("The secret is corrupted"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce =
+        Nonce::try_assume_unique_for_key(nonce).map_err(|_| eyre!("The secret is corrupted"))?;
+
+    let mut ciphertext = ciphertext.to_vec();
+    let plaintext = key
+        .open_in_place(nonce, Aad::empty(), &mut ciphertext)
+        .map_err(|_| {
+            eyre!("Unable to decrypt the secret; is {PASSPHRASE_VARIABLE} (or the passphrase given) right?")
+        })?;
+
+    Ok(String::from_utf8(plaintext.to_vec())?)
+}
+
+/// Write `contents` to `path`, readable only by the current user
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    options.open(path)?.write_all(contents).map_err(Into::into)
+}
+
+/// A secret kept between runs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Secret {
+    service: String,
+    name: String,
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.service, self.name)
+    }
+}
+
+impl Secret {
+    /// The Okta session (`sid`) from the last sign-in to `organization`
+    #[must_use]
+    pub fn okta_session(organization: &str) -> Self {
+        Self {
+            service: format!("oktaws::okta::{organization}::session"),
+            name: String::from("sid"),
+        }
+    }
+
+    /// The AWS SSO portal tokens for the Okta organization at `base_url`, as JSON keyed by application link
+    #[must_use]
+    pub fn sso_tokens(base_url: &Url) -> Self {
+        Self {
+            service: format!(
+                "oktaws::aws::{}::sso",
+                base_url.host_str().unwrap_or_default()
+            ),
+            name: String::from("tokens"),
+        }
+    }
+
+    /// The Okta password for `username` in `organization`
+    #[must_use]
+    pub fn okta_password(organization: &str, username: &str) -> Self {
+        Self {
+            service: format!("oktaws::okta::{organization}"),
+            name: username.to_string(),
+        }
+    }
+
+    /// The TOTP shared secret (or `otpauth://` URI) for `username` in `organization`
+    #[must_use]
+    pub fn okta_totp(organization: &str, username: &str) -> Self {
+        Self {
+            service: format!("oktaws::okta::{organization}::totp"),
+            name: username.to_string(),
+        }
+    }
+
+    /// The state token of a pending Okta authentication transaction, recorded as `id`
+    #[must_use]
+    pub fn okta_transaction(organization: &str, id: &str) -> Self {
+        Self {
+            service: format!("oktaws::okta::{organization}::transactions"),
+            name: id.to_string(),
+        }
+    }
+
+    /// The `kind` of secret (client secret, or access token) for the IAM Identity Center cached as `name`
+    #[must_use]
+    pub fn identity_center(name: &str, kind: &str) -> Self {
+        Self {
+            service: format!("oktaws::aws::{name}::identity-center"),
+            name: kind.to_string(),
+        }
+    }
+
+    /// The DeviceToken (`DT`) cookie `organization` gave oktaws, by which Okta recognizes it (e.g. to remember MFA)
+    #[must_use]
+    pub fn okta_device(organization: &str) -> Self {
+        Self {
+            service: format!("oktaws::okta::{organization}::device"),
+            name: String::from("DT"),
+        }
+    }
+
+    /// The value of the extra header `name`, sent with every request to `organization`
+    #[must_use]
+    pub fn okta_header(organization: &str, name: &str) -> Self {
+        Self {
+            service: format!("oktaws::okta::{organization}::headers"),
+            name: name.to_string(),
+        }
+    }
+
+    /// The file the secret is kept in, when it is kept in a file
+    fn path(&self) -> Result<PathBuf> {
+        let file_name = format!("{}.{}", self.service, self.name).replace(
+            |c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.',
+            "_",
+        );
+
+        Ok(state_dir()?.join(DIR).join(file_name))
+    }
+
+    /// The file the secret is kept in, when it is kept in an encrypted file
+    fn encrypted_path(&self) -> Result<PathBuf> {
+        let mut path = self.path()?.into_os_string();
+        path.push(".enc");
+
+        Ok(path.into())
+    }
+
+    fn entry(&self) -> Result<Entry> {
+        Entry::new(&self.service, &self.name).map_err(Into::into)
+    }
+
+    /// The secret, if it has been kept
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the keyring (or file) cannot be read, or the file cannot be decrypted
+    pub fn get(&self) -> Result<Option<String>> {
+        match active() {
+            Store::Auto | Store::Keyring => match self.entry()?.get_password() {
+                Ok(value) => Ok(Some(value)),
+                Err(KeyringError::NoEntry) => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            Store::EncryptedFile => {
+                let path = self.encrypted_path()?;
+                if path.exists() {
+                    let dir = state_dir()?.join(DIR);
+                    open(key(&dir)?, &fs::read(path)?).map(Some)
+                } else {
+                    Ok(None)
+                }
+            }
+            Store::File => {
+                let path = self.path()?;
+                if path.exists() {
+                    Ok(Some(fs::read_to_string(path)?))
+                } else {
+                    Ok(None)
+                }
+            }
+            Store::None => Ok(None),
+        }
+    }
+
+    /// Keep the secret, replacing any kept before
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the keyring (or file) cannot be written, or secrets are not kept (see [`kept`])
+    pub fn set(&self, value: &str) -> Result<()> {
+        match active() {
+            Store::Auto | Store::Keyring => self.entry()?.set_password(value).map_err(Into::into),
+            Store::EncryptedFile => {
+                let dir = state_dir()?.join(DIR);
+                let sealed = seal(key(&dir)?, value.as_bytes())?;
+                write_private(&self.encrypted_path()?, &sealed)
+            }
+            Store::File => write_private(&self.path()?, value.as_bytes()),
+            Store::None => Err(eyre!(
+                "Unable to keep the secret, as the secret store is none. \
+                 Choose another store with --secret-store, or secret_store in settings.toml"
+            )),
+        }
+    }
+
+    /// Forget the secret, returning whether there was one
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the keyring (or file) cannot be written
+    pub fn delete(&self) -> Result<bool> {
+        let path = match active() {
+            Store::Auto | Store::Keyring => {
+                return match self.entry()?.delete_credential() {
+                    Ok(()) => Ok(true),
+                    Err(KeyringError::NoEntry) => Ok(false),
+                    Err(e) => Err(e.into()),
+                };
+            }
+            Store::EncryptedFile => self.encrypted_path()?,
+            Store::File => self.path()?,
+            Store::None => return Ok(false),
+        };
+
+        if path.exists() {
+            fs::remove_file(path)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_are_safe() -> Result<()> {
+        let secret = Secret::sso_tokens(&Url::parse("https://mock_org.okta.com/")?);
+
+        assert_eq!(
+            secret.path()?.file_name().and_then(|name| name.to_str()),
+            Some("oktaws__aws__mock_org.okta.com__sso.tokens")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn falls_back_along_the_chain() {
+        let unavailable = || Some(KeyringError::NoStorageAccess("locked".into()));
+
+        assert_eq!(resolve(Store::Auto, || None, || false), Store::Keyring);
+        assert_eq!(
+            resolve(Store::Auto, unavailable, || true),
+            Store::EncryptedFile
+        );
+        assert_eq!(resolve(Store::Auto, unavailable, || false), Store::None);
+        assert_eq!(resolve(Store::File, unavailable, || true), Store::File);
+
+        assert_eq!(
+            "encrypted-file".parse::<Store>().ok(),
+            Some(Store::EncryptedFile)
+        );
+        assert!("vault".parse::<Store>().is_err());
+    }
+
+    #[test]
+    fn encrypted_secrets_round_trip() -> Result<()> {
+        let key = state::derive("passphrase", b"0123456789abcdef", 1)?;
+        let other = state::derive("other", b"0123456789abcdef", 1)?;
+
+        let sealed = seal(&key, b"sid-1234")?;
+        assert_eq!(open(&key, &sealed)?, "sid-1234");
+        assert!(open(&other, &sealed).is_err());
+        assert!(open(&key, b"short").is_err());
+        assert!(open(&key, &sealed[FORMAT.len()..]).is_err());
+
+        // Each file has a salt (and so a key) of its own
+        let resealed = seal(&key, b"sid-1234")?;
+        assert_ne!(
+            sealed[FORMAT.len()..FORMAT.len() + FILE_SALT_LEN],
+            resealed[FORMAT.len()..FORMAT.len() + FILE_SALT_LEN]
+        );
+        assert_eq!(open(&key, &resealed)?, "sid-1234");
+
+        Ok(())
+    }
+
+    #[test]
+    fn verifies_the_passphrase() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(VERIFIER_FILE);
+        let key = state::derive("passphrase", b"0123456789abcdef", 1)?;
+
+        verify(&key, &path)?;
+        assert!(path.exists());
+        verify(&key, &path)?;
+        assert!(verify(&state::derive("passphrose", b"0123456789abcdef", 1)?, &path).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+//! A local stand-in for the ECS container credentials endpoint, for `oktaws serve`.
+//!
+//! The AWS SDKs fetch credentials from `AWS_CONTAINER_CREDENTIALS_FULL_URI` with a GET,
+//! sending `AWS_CONTAINER_AUTHORIZATION_TOKEN` as the `Authorization` header.
+//! Each profile is served at `/profiles/<profile>`; credentials are fetched when first asked for,
+//! and fetched again once they are due for a refresh (see [`crate::schedule`]), so nothing is written to disk.
+//! Each connection is answered by a task of its own, so a slow client or fetch doesn't hold up the others,
+//! while requests for the same profile wait on a single fetch, so containers starting together only cause one per profile.
+
+use crate::aws::container::{ContainerCredentials, ContainerError};
+use crate::schedule::Schedule;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::{Duration, SystemTime};
+
+use aws_credential_types::Credentials;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use eyre::{Result, eyre};
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::{LocalSet, spawn_local};
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+/// The variable the SDKs read the endpoint's URL from
+pub const URI_VARIABLE: &str = "AWS_CONTAINER_CREDENTIALS_FULL_URI";
+
+/// The variable the SDKs read the `Authorization` header from
+pub const TOKEN_VARIABLE: &str = "AWS_CONTAINER_AUTHORIZATION_TOKEN";
+
+const PATH_PREFIX: &str = "/profiles/";
+
+/// Clients have this long to send their request, so that a stalled one doesn't hold up the others
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests larger than this are refused
+const MAX_REQUEST: u64 = 16 * 1024;
+
+/// Where the credentials served come from
+pub trait Source {
+    /// Fresh credentials for `profile`, or `None` if it is not being served
+    fn credentials(&self, profile: &str) -> impl Future<Output = Result<Option<Credentials>>>;
+}
+
+/// A random token for clients to authorize with
+///
+/// # Errors
+///
+/// Will return `Err` if the system's random number generator fails
+pub fn generate_token() -> Result<String> {
+    let mut bytes = [0; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| eyre!("Unable to generate an authorization token"))?;
+
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// The URL to set as `AWS_CONTAINER_CREDENTIALS_FULL_URI` for `profile`
+#[must_use]
+pub fn profile_url(address: &SocketAddr, profile: &str) -> String {
+    format!("http://{address}{PATH_PREFIX}{profile}")
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+}
+
+impl Request {
+    /// Parse the request line and headers; any body is ignored
+    fn parse(head: &str) -> Option<Self> {
+        let mut lines = head.lines();
+        let mut request_line = lines.next()?.split_whitespace();
+        let method = request_line.next()?.to_string();
+        let path = request_line.next()?.to_string();
+
+        let authorization = lines
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+            .map(|(_, value)| value.trim().to_string());
+
+        Some(Self {
+            method,
+            path,
+            authorization,
+        })
+    }
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn error(status: u16, reason: &'static str, message: &str) -> Self {
+        Self {
+            status,
+            reason,
+            body: serde_json::to_string(&ContainerError {
+                code: reason,
+                message,
+            })
+            .unwrap_or_default(),
+        }
+    }
+
+    fn render(&self) -> String {
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.reason,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+/// A profile's credentials, once fetched, and when they are due for a refresh
+type Cached = Option<(Credentials, Option<Schedule>)>;
+
+/// Serves credentials from a [`Source`], keeping them until they are due for a refresh
+pub struct Server<S> {
+    token: String,
+    source: S,
+    /// Each profile's credentials, behind a lock of their own which is held while they are fetched
+    cached: SyncMutex<HashMap<String, Arc<Mutex<Cached>>>>,
+}
+
+impl<S: Source> Server<S> {
+    #[must_use]
+    pub fn new(token: String, source: S) -> Self {
+        Self {
+            token,
+            source,
+            cached: SyncMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Answer requests on `listener` until it fails, each connection in a task of its own.
+    /// The tasks run on the current thread, as a [`Source`]'s fetches needn't be `Send`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if accepting a connection fails
+    pub async fn serve(self, listener: TcpListener) -> Result<()>
+    where
+        S: 'static,
+    {
+        let server = Rc::new(self);
+
+        LocalSet::new()
+            .run_until(async move {
+                loop {
+                    let (stream, peer) = listener.accept().await?;
+                    let server = server.clone();
+
+                    spawn_local(async move {
+                        if let Err(e) = server.answer(stream).await {
+                            debug!("Credentials request from {peer} failed: {e}");
+                        }
+                    });
+                }
+            })
+            .await
+    }
+
+    async fn answer(&self, stream: TcpStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader.take(MAX_REQUEST));
+
+        let mut head = String::new();
+        let read = timeout(REQUEST_TIMEOUT, async {
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+                    return Ok::<_, std::io::Error>(());
+                }
+                head.push_str(&line);
+            }
+        })
+        .await;
+
+        let response = match (read, Request::parse(&head)) {
+            (Ok(Ok(())), Some(request)) => self.respond(&request).await,
+            (Err(_), _) => Response::error(408, "Request Timeout", "No request was received"),
+            _ => Response::error(400, "Bad Request", "The request could not be read"),
+        };
+
+        writer.write_all(response.render().as_bytes()).await?;
+        writer.shutdown().await?;
+
+        Ok(())
+    }
+
+    async fn respond(&self, request: &Request) -> Response {
+        if !self.authorized(request.authorization.as_deref()) {
+            return Response::error(401, "Unauthorized", "The authorization token is not valid");
+        }
+
+        if request.method != "GET" {
+            return Response::error(405, "Method Not Allowed", "Only GET is supported");
+        }
+
+        let Some(profile) = request.path.strip_prefix(PATH_PREFIX) else {
+            return Response::error(
+                404,
+                "Not Found",
+                "Credentials are served at /profiles/<profile>",
+            );
+        };
+
+        match self.credentials(profile).await {
+            Ok(Some(credentials)) => match ContainerCredentials::new(&credentials)
+                .and_then(|credentials| serde_json::to_string(&credentials).map_err(Into::into))
+            {
+                Ok(body) => Response {
+                    status: 200,
+                    reason: "OK",
+                    body,
+                },
+                Err(e) => Response::error(500, "Internal Server Error", &e.to_string()),
+            },
+            Ok(None) => Response::error(
+                404,
+                "Not Found",
+                &format!("No profile named {profile} is being served"),
+            ),
+            Err(e) => {
+                warn!("Unable to fetch credentials for {profile}: {e}");
+                Response::error(500, "Internal Server Error", &e.to_string())
+            }
+        }
+    }
+
+    /// The cached credentials for `profile`, or fresh ones once they are due for a refresh.
+    /// Requests for a profile which is being fetched wait for that fetch, rather than making their own.
+    async fn credentials(&self, profile: &str) -> Result<Option<Credentials>> {
+        let entry = self
+            .cached
+            .lock()
+            .map_err(|_| eyre!("The credentials cache is poisoned"))?
+            .entry(profile.to_string())
+            .or_default()
+            .clone();
+        let mut cached = entry.lock().await;
+        let now = SystemTime::now();
+
+        if let Some((credentials, schedule)) = cached.as_ref() {
+            if schedule
+                .as_ref()
+                .is_none_or(|schedule| !schedule.is_due(now))
+            {
+                return Ok(Some(credentials.clone()));
+            }
+        }
+
+        let Some(credentials) = self.source.credentials(profile).await? else {
+            return Ok(None);
+        };
+
+        let schedule = Schedule::new(profile, &credentials, now);
+        *cached = Some((credentials.clone(), schedule));
+
+        Ok(Some(credentials))
+    }
+
+    /// Whether `authorization` matches the token, compared in constant time
+    fn authorized(&self, authorization: Option<&str>) -> bool {
+        authorization.is_some_and(|authorization| {
+            authorization.len() == self.token.len()
+                && authorization
+                    .bytes()
+                    .zip(self.token.bytes())
+                    .fold(0, |difference, (a, b)| difference | (a ^ b))
+                    == 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::time::UNIX_EPOCH;
+
+    struct Counting {
+        fetches: Cell<u32>,
+    }
+
+    impl Source for Counting {
+        async fn credentials(&self, profile: &str) -> Result<Option<Credentials>> {
+            self.fetches.set(self.fetches.get() + 1);
+            // As a real fetch would, letting other requests in meanwhile
+            tokio::task::yield_now().await;
+
+            Ok((profile == "prod").then(|| {
+                Credentials::new(
+                    "ACCESS_KEY",
+                    "SECRET_KEY",
+                    Some(String::from("SESSION_TOKEN")),
+                    Some(UNIX_EPOCH + Duration::from_secs(4_000_000_000)),
+                    "test",
+                )
+            }))
+        }
+    }
+
+    #[test]
+    fn parses_requests() {
+        assert_eq!(
+            Request::parse(
+                "GET /profiles/prod HTTP/1.1\r\nHost: 127.0.0.1\r\nauthorization: TOKEN\r\n"
+            ),
+            Some(Request {
+                method: String::from("GET"),
+                path: String::from("/profiles/prod"),
+                authorization: Some(String::from("TOKEN")),
+            })
+        );
+        assert_eq!(Request::parse(""), None);
+    }
+
+    #[tokio::test]
+    async fn serves_cached_credentials_to_authorized_clients() {
+        let server = Server::new(
+            String::from("TOKEN"),
+            Counting {
+                fetches: Cell::new(0),
+            },
+        );
+        let request = |path: &str, authorization: Option<&str>| Request {
+            method: String::from("GET"),
+            path: path.to_string(),
+            authorization: authorization.map(ToString::to_string),
+        };
+
+        assert_eq!(
+            server
+                .respond(&request("/profiles/prod", Some("WRONG")))
+                .await
+                .status,
+            401
+        );
+        assert_eq!(
+            server
+                .respond(&request("/profiles/dev", Some("TOKEN")))
+                .await
+                .status,
+            404
+        );
+
+        // Requests at the same time share a fetch
+        let prod = request("/profiles/prod", Some("TOKEN"));
+        let (first, second) = tokio::join!(server.respond(&prod), server.respond(&prod));
+        for response in [first, second, server.respond(&prod).await] {
+            assert_eq!(response.status, 200);
+            assert!(response.body.contains(r#""Token":"SESSION_TOKEN""#));
+        }
+
+        // One fetch for dev, and only one for prod
+        assert_eq!(server.source.fetches.get(), 2);
+    }
+}
@@ -0,0 +1,111 @@
+//! An interactive shell with a profile's credentials, for `oktaws shell`.
+//!
+//! The shell gets a private credentials file, like `oktaws exec`, which is removed when it exits.
+//! It is told which profile it has, and until when, through `OKTAWS_PROFILE` and `OKTAWS_EXPIRATION`;
+//! `OKTAWS_PROMPT` holds a short indicator for prompts to include (e.g. `PS1='$OKTAWS_PROMPT '$PS1`).
+//! Most shells set their prompt in their startup files, so `PS1` is only changed when it was exported to oktaws.
+
+use std::env;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use tokio::process::Command;
+
+/// The variable naming the profile the shell has credentials for
+pub const PROFILE_VARIABLE: &str = "OKTAWS_PROFILE";
+
+/// The variable holding when the shell's credentials expire, in RFC 3339
+pub const EXPIRATION_VARIABLE: &str = "OKTAWS_EXPIRATION";
+
+/// The variable holding the indicator for prompts
+pub const PROMPT_VARIABLE: &str = "OKTAWS_PROMPT";
+
+/// The shell to run: `$SHELL`, falling back to the system's default
+#[must_use]
+pub fn program() -> String {
+    env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| {
+            if cfg!(windows) {
+                env::var("COMSPEC").unwrap_or_else(|_| String::from("cmd.exe"))
+            } else {
+                String::from("/bin/sh")
+            }
+        })
+}
+
+/// The profile of the `oktaws shell` this is running in, if any
+#[must_use]
+pub fn current() -> Option<String> {
+    env::var(PROFILE_VARIABLE).ok()
+}
+
+/// A short indicator of the profile, and when its credentials expire (unless they are kept fresh)
+#[must_use]
+pub fn indicator(profile: &str, expiration: Option<SystemTime>) -> String {
+    match expiration {
+        Some(expiration) => format!(
+            "(oktaws:{profile} until {})",
+            DateTime::<Local>::from(expiration).format("%H:%M")
+        ),
+        None => format!("(oktaws:{profile})"),
+    }
+}
+
+/// Tell `command` (the shell) which profile it has, and until when
+pub fn configure(command: &mut Command, profile: &str, expiration: Option<SystemTime>) {
+    let indicator = indicator(profile, expiration);
+
+    command
+        .env(PROFILE_VARIABLE, profile)
+        .env(PROMPT_VARIABLE, &indicator);
+
+    match expiration {
+        Some(expiration) => command.env(
+            EXPIRATION_VARIABLE,
+            DateTime::<Utc>::from(expiration).to_rfc3339_opts(SecondsFormat::Secs, true),
+        ),
+        None => command.env_remove(EXPIRATION_VARIABLE),
+    };
+
+    if let Ok(prompt) = env::var("PS1") {
+        command.env("PS1", format!("{indicator} {prompt}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn indicates_profile_and_expiry() {
+        assert_eq!(indicator("prod", None), "(oktaws:prod)");
+
+        let expiration = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            indicator("prod", Some(expiration)),
+            format!(
+                "(oktaws:prod until {})",
+                DateTime::<Local>::from(expiration).format("%H:%M")
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn shell_sees_profile() -> eyre::Result<()> {
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "test \"$OKTAWS_PROFILE:$OKTAWS_PROMPT:$OKTAWS_EXPIRATION\" = 'prod:(oktaws:prod):'",
+        ]);
+        configure(&mut command, "prod", None);
+
+        assert!(command.status().await?.success());
+
+        Ok(())
+    }
+}
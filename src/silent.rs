@@ -0,0 +1,311 @@
+//! Policy-gated silent refreshes.
+//!
+//! Refreshes reuse the Okta session from the last interactive sign-in, without any prompts, while Okta still accepts it.
+//! An organization can restrict that with the conditions in its `[silent_refresh]` policy
+//! (mirroring the IdP's own session policy, to cut down on MFA prompts).
+//! When they don't hold, the refresh signs in interactively, or fails if there is nobody to interact with.
+//! The session itself is kept with the other secrets (see [`crate::secrets`]); only its network is kept in a file.
+
+use crate::blocking;
+use crate::config::organization::Organization;
+use crate::okta::client::Client as OktaClient;
+use crate::secrets::{self, Secret};
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument};
+
+/// The directory (in the oktaws state directory) remembered sessions are kept in
+pub const DIR: &str = "sessions";
+
+/// The conditions under which an organization's profiles may be refreshed without interaction.
+/// The Okta session from the last interactive sign-in must always still be valid.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Only refresh silently from the network the session was created on
+    #[serde(default)]
+    pub same_network: bool,
+    /// Only refresh silently within this many hours of the session's last MFA
+    pub max_hours_since_mfa: Option<u32>,
+}
+
+/// The Okta session from the last interactive sign-in to an organization
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Remembered {
+    /// Kept with the other secrets, rather than in the file
+    #[serde(skip)]
+    pub session_id: String,
+    /// The network the session was created on
+    pub network: Option<String>,
+}
+
+impl Remembered {
+    fn path(dir: &Path, organization: &str) -> PathBuf {
+        dir.join(format!("{organization}.json"))
+    }
+
+    /// The session remembered for `organization` in `dir`, if any
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file exists but cannot be read or parsed,
+    /// or if the keyring cannot be read
+    pub fn load(dir: &Path, organization: &str) -> Result<Option<Self>> {
+        let path = Self::path(dir, organization);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let Some(session_id) = Secret::okta_session(organization).get()? else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            session_id,
+            ..serde_json::from_str::<Self>(&fs::read_to_string(path)?)?
+        }))
+    }
+
+    /// Remember the session for `organization`: the session in the keyring,
+    /// and the rest in `dir`, readable only by the current user
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the keyring or the file cannot be written
+    pub fn save(&self, dir: &Path, organization: &str) -> Result<()> {
+        Secret::okta_session(organization).set(&self.session_id)?;
+        fs::create_dir_all(dir)?;
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        options
+            .open(Self::path(dir, organization))?
+            .write_all(serde_json::to_string_pretty(self)?.as_bytes())
+            .map_err(Into::into)
+    }
+
+    /// Forget the session remembered for `organization` in `dir`, returning whether there was one
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file exists but cannot be removed
+    pub fn forget(dir: &Path, organization: &str) -> Result<bool> {
+        let path = Self::path(dir, organization);
+        let forgot_session = Secret::okta_session(organization).delete()?;
+
+        if path.exists() {
+            fs::remove_file(path)?;
+            Ok(true)
+        } else {
+            Ok(forgot_session)
+        }
+    }
+}
+
+impl Policy {
+    /// Why the policy does not allow a silent refresh, or `None` if it does
+    #[must_use]
+    pub fn refusal(
+        &self,
+        remembered: &Remembered,
+        network: Option<&str>,
+        last_mfa: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Option<String> {
+        if self.same_network && (network.is_none() || network != remembered.network.as_deref()) {
+            return Some(String::from(
+                "the network has changed since the last sign-in",
+            ));
+        }
+
+        if let Some(hours) = self.max_hours_since_mfa {
+            let Some(last_mfa) = last_mfa else {
+                return Some(String::from("the Okta session has no MFA"));
+            };
+
+            if now - last_mfa > TimeDelta::hours(hours.into()) {
+                return Some(format!("the last MFA was more than {hours} hour(s) ago"));
+            }
+        }
+
+        None
+    }
+}
+
+/// The network this machine reaches `host` from: the /24 (IPv4) or /64 (IPv6) of the local address it uses.
+/// No packets are sent to work this out.
+///
+/// # Errors
+///
+/// Will return `Err` if there is no route to `host`
+pub fn network(host: &str) -> Result<String> {
+    let address = (host, 443)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| eyre!("No addresses found for {host}"))?;
+
+    let socket = UdpSocket::bind(if address.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })?;
+    socket.connect(address)?;
+
+    Ok(match socket.local_addr()?.ip() {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{a}.{b}.{c}.0/24")
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, c, d, ..] = ip.segments();
+            format!("{a:x}:{b:x}:{c:x}:{d:x}::/64")
+        }
+    })
+}
+
+async fn current_network(organization: &Organization) -> Option<String> {
+    let host = organization.portal.base_url().host_str()?.to_string();
+
+    blocking::run(move || network(&host))
+        .await
+        .inspect_err(|e| debug!("Unable to work out the current network ({e})"))
+        .ok()
+}
+
+/// Sign in to `organization` with its remembered session, if its policy allows it
+///
+/// # Errors
+///
+/// Will return `Err` (saying why) if there is no remembered session,
+/// if Okta no longer accepts it, or if the policy does not allow a silent refresh
+#[instrument(skip_all, fields(organization=%organization.name))]
+pub async fn sign_in(
+    policy: &Policy,
+    organization: &Organization,
+    dir: &Path,
+) -> Result<OktaClient> {
+    let (loading, name) = (dir.to_path_buf(), organization.name.clone());
+    let remembered = blocking::run(move || Remembered::load(&loading, &name))
+        .await?
+        .ok_or_else(|| eyre!("there is no session from an earlier sign-in"))?;
+
+    let client = OktaClient::from_session_id(
+        &organization.portal,
+        organization.username.clone(),
+        &remembered.session_id,
+    )
+    .await
+    .map_err(|_| eyre!("the Okta session has ended"))?;
+
+    let session = client.current_session().await?;
+    let last_mfa = session
+        .last_factor_verification
+        .as_deref()
+        .map(DateTime::parse_from_rfc3339)
+        .transpose()?
+        .map(|last_mfa| last_mfa.with_timezone(&Utc));
+
+    let network = if policy.same_network {
+        current_network(organization).await
+    } else {
+        None
+    };
+
+    match policy.refusal(&remembered, network.as_deref(), last_mfa, Utc::now()) {
+        Some(refusal) => Err(eyre!(refusal)),
+        None => {
+            debug!("Refreshing silently, with the session from an earlier sign-in");
+            Ok(client)
+        }
+    }
+}
+
+/// Remember the session `client` signed in to `organization` with, for later silent refreshes
+///
+/// # Errors
+///
+/// Will return `Err` if the session cannot be looked up, or cannot be saved
+pub async fn remember(client: &OktaClient, organization: &Organization, dir: &Path) -> Result<()> {
+    // Without a secret store, there is nowhere to keep the session
+    if !secrets::kept() {
+        return Ok(());
+    }
+
+    let session = client.current_session().await?;
+
+    let remembered = Remembered {
+        session_id: session.id,
+        network: current_network(organization).await,
+    };
+    let (saving, name) = (dir.to_path_buf(), organization.name.clone());
+
+    blocking::run(move || remembered.save(&saving, &name)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remembered() -> Remembered {
+        Remembered {
+            session_id: String::from("SESSION_ID"),
+            network: Some(String::from("10.0.1.0/24")),
+        }
+    }
+
+    #[test]
+    fn policy_conditions() {
+        let policy = Policy {
+            same_network: true,
+            max_hours_since_mfa: Some(8),
+        };
+        let now = Utc::now();
+
+        assert_eq!(
+            policy.refusal(
+                &remembered(),
+                Some("10.0.1.0/24"),
+                Some(now - TimeDelta::hours(2)),
+                now
+            ),
+            None
+        );
+        assert_eq!(
+            policy.refusal(
+                &remembered(),
+                Some("192.168.0.0/24"),
+                Some(now - TimeDelta::hours(2)),
+                now
+            ),
+            Some(String::from(
+                "the network has changed since the last sign-in"
+            ))
+        );
+        assert_eq!(
+            policy.refusal(
+                &remembered(),
+                Some("10.0.1.0/24"),
+                Some(now - TimeDelta::hours(9)),
+                now
+            ),
+            Some(String::from("the last MFA was more than 8 hour(s) ago"))
+        );
+        assert_eq!(
+            Policy::default().refusal(&remembered(), None, None, now),
+            None
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Credentials as `<profile>.env` files of `AWS_*` variables, for tools which read dotenv files
+//! (or shells, with `set -a; . <profile>.env`).
+//!
+//! Writes are kept until the sink is flushed, then each file is written beside its target and renamed over it,
+//! so that readers (and other machines sharing a network home directory) never see a half-written file.
+
+use crate::sink::CredentialSink;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+use aws_credential_types::Credentials;
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
+use eyre::Result;
+
+const EXTENSION: &str = "env";
+
+/// A directory of env files, one per profile
+#[derive(Clone, Debug)]
+pub struct EnvFiles {
+    dir: PathBuf,
+    /// The contents of files written since the last flush, by profile
+    pending: BTreeMap<String, String>,
+}
+
+impl EnvFiles {
+    #[must_use]
+    pub const fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn path(&self, profile: &str) -> PathBuf {
+        self.dir.join(format!("{profile}.{EXTENSION}"))
+    }
+}
+
+impl CredentialSink for EnvFiles {
+    fn write(&mut self, profile: &str, credentials: &Credentials) -> Result<()> {
+        let mut contents = format!(
+            "AWS_ACCESS_KEY_ID={}\nAWS_SECRET_ACCESS_KEY={}\n",
+            credentials.access_key_id(),
+            credentials.secret_access_key()
+        );
+        if let Some(session_token) = credentials.session_token() {
+            let _ = writeln!(contents, "AWS_SESSION_TOKEN={session_token}");
+        }
+        if let Some(expiry) = credentials.expiry() {
+            let _ = writeln!(
+                contents,
+                "AWS_CREDENTIAL_EXPIRATION={}",
+                DateTime::from(expiry).fmt(Format::DateTime)?
+            );
+        }
+
+        self.pending.insert(profile.to_string(), contents);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, profile: &str) -> Result<bool> {
+        let pending = self.pending.remove(profile).is_some();
+        let path = self.path(profile);
+
+        if path.exists() {
+            fs::remove_file(path)?;
+            Ok(true)
+        } else {
+            Ok(pending)
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+
+        for (profile, contents) in std::mem::take(&mut self.pending) {
+            let path = self.path(&profile);
+            let staged = path.with_extension(format!("{EXTENSION}.oktaws-new"));
+
+            let mut options = OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            options.mode(0o600);
+
+            let mut file = options.open(&staged)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&staged, &path)?;
+        }
+
+        Ok(())
+    }
+
+    fn managed(&self) -> Result<Vec<String>> {
+        let mut profiles = self.pending.keys().cloned().collect::<Vec<_>>();
+        if !self.dir.is_dir() {
+            return Ok(profiles);
+        }
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                if let Some(profile) = path.file_stem() {
+                    profiles.push(profile.to_string_lossy().into_owned());
+                }
+            }
+        }
+        profiles.sort();
+        profiles.dedup();
+
+        Ok(profiles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn write_list_and_remove() -> Result<()> {
+        let dir = tempdir()?;
+        let mut sink = EnvFiles::new(dir.path().join("env"));
+
+        let credentials = Credentials::new(
+            "ACCESS_KEY",
+            "SECRET_KEY",
+            Some(String::from("SESSION_TOKEN")),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            "test",
+        );
+        sink.write("production", &credentials)?;
+        assert!(!dir.path().join("env").join("production.env").exists());
+        sink.flush()?;
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("env").join("production.env"))?,
+            "AWS_ACCESS_KEY_ID=ACCESS_KEY\nAWS_SECRET_ACCESS_KEY=SECRET_KEY\nAWS_SESSION_TOKEN=SESSION_TOKEN\nAWS_CREDENTIAL_EXPIRATION=2023-11-14T22:13:20Z\n"
+        );
+        assert_eq!(sink.managed()?, vec!["production"]);
+
+        assert!(sink.remove("production")?);
+        assert!(!sink.remove("production")?);
+        assert!(sink.managed()?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+//! Credentials in the OS keyring, as `credential_process` JSON.
+//!
+//! Each profile is kept under the service `oktaws::aws::<organization>`.
+//! Keyrings can't list their entries, so the profiles written are also recorded in an index entry.
+
+use crate::aws::process::ProcessCredentials;
+use crate::sink::CredentialSink;
+
+use aws_credential_types::Credentials;
+use eyre::Result;
+use keyring::{Entry, Error as KeyringError};
+
+/// The keyring entries for an organization's profiles
+#[derive(Clone, Debug)]
+pub struct Keychain {
+    organization: String,
+}
+
+impl Keychain {
+    #[must_use]
+    pub fn new(organization: &str) -> Self {
+        Self {
+            organization: organization.to_string(),
+        }
+    }
+
+    fn entry(&self, profile: &str) -> Result<Entry> {
+        Entry::new(&format!("oktaws::aws::{}", self.organization), profile).map_err(Into::into)
+    }
+
+    fn index(&self) -> Result<Entry> {
+        Entry::new(
+            &format!("oktaws::aws::{}::index", self.organization),
+            "profiles",
+        )
+        .map_err(Into::into)
+    }
+
+    fn save_index(&self, profiles: &[String]) -> Result<()> {
+        self.index()?
+            .set_password(&serde_json::to_string(profiles)?)
+            .map_err(Into::into)
+    }
+}
+
+impl CredentialSink for Keychain {
+    fn write(&mut self, profile: &str, credentials: &Credentials) -> Result<()> {
+        self.entry(profile)?
+            .set_password(&serde_json::to_string(&ProcessCredentials::new(
+                credentials,
+            )?)?)?;
+
+        let mut profiles = self.managed()?;
+        if !profiles.iter().any(|managed| managed == profile) {
+            profiles.push(profile.to_string());
+            profiles.sort();
+            self.save_index(&profiles)?;
+        }
+
+        Ok(())
+    }
+
+    fn remove(&mut self, profile: &str) -> Result<bool> {
+        let removed = match self.entry(profile)?.delete_credential() {
+            Ok(()) => true,
+            Err(KeyringError::NoEntry) => false,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut profiles = self.managed()?;
+        profiles.retain(|managed| managed != profile);
+        self.save_index(&profiles)?;
+
+        Ok(removed)
+    }
+
+    fn managed(&self) -> Result<Vec<String>> {
+        match self.index()?.get_password() {
+            Ok(index) => Ok(serde_json::from_str(&index)?),
+            Err(KeyringError::NoEntry) => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
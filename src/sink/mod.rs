@@ -0,0 +1,259 @@
+//! Where refreshed credentials are sent.
+//!
+//! The shared credentials file is always written. Each organization can list extra `[[sinks]]`
+//! (env files, the OS keyring, or with the `vault` feature a HashiCorp Vault KV store),
+//! which get a copy of every profile it refreshes. Anything implementing [`CredentialSink`] can be plugged in the same way.
+//!
+//! On home directories mounted over the network (NFS, SSHFS), many writes at once contend for locks,
+//! so how many sinks are written to at once, and how many profiles each takes before it is flushed,
+//! are limited by [`WriteLimits`] (the `[writes]` table of `settings.toml`).
+
+pub mod env;
+pub mod keychain;
+#[cfg(feature = "vault")]
+pub mod vault;
+
+use crate::aws::profile::Store as ProfileStore;
+use crate::blocking;
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use aws_credential_types::Credentials;
+use eyre::Result;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument, warn};
+
+/// Limits on writing to sinks, so that slow (e.g. network) filesystems see a steady trickle of writes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WriteLimits {
+    /// How many sinks are written to at once
+    pub max_concurrent: usize,
+    /// How many profiles are written to a sink between flushes
+    pub batch_size: usize,
+}
+
+impl Default for WriteLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 4,
+            batch_size: 16,
+        }
+    }
+}
+
+static LIMITS: OnceLock<WriteLimits> = OnceLock::new();
+
+impl WriteLimits {
+    /// Install these limits for the whole process. Only the first call has any effect.
+    pub fn install(self) {
+        let _ = LIMITS.set(self);
+    }
+
+    /// The limits installed for this process
+    #[must_use]
+    pub fn current() -> Self {
+        LIMITS.get().copied().unwrap_or_default()
+    }
+}
+
+/// Somewhere credentials can be kept, keyed by profile name.
+/// Sinks may block (on disk, keyring or network access), so callers run them off the reactor.
+pub trait CredentialSink: Send {
+    /// Store `credentials` for `profile`, replacing any already there
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the credentials cannot be stored
+    fn write(&mut self, profile: &str, credentials: &Credentials) -> Result<()>;
+
+    /// Remove the credentials for `profile`, returning whether there were any
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the credentials exist but cannot be removed
+    fn remove(&mut self, profile: &str) -> Result<bool>;
+
+    /// The profiles this sink holds credentials for
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the sink cannot be read
+    fn managed(&self) -> Result<Vec<String>>;
+
+    /// Make everything written so far durable, for sinks which buffer writes
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the buffered credentials cannot be stored
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The shared credentials file. Changes are kept in the store until it is saved
+/// (which a refresh does in one transaction, with the AWS config and registry).
+impl CredentialSink for ProfileStore {
+    fn write(&mut self, profile: &str, credentials: &Credentials) -> Result<()> {
+        self.upsert_credential(profile, credentials)
+    }
+
+    fn remove(&mut self, profile: &str) -> Result<bool> {
+        self.remove_credential(profile)
+    }
+
+    fn managed(&self) -> Result<Vec<String>> {
+        Ok(self.sessions())
+    }
+}
+
+/// An extra sink, as configured for an organization
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Config {
+    /// A `<profile>.env` file of `AWS_*` variables per profile, in `dir`
+    Env { dir: PathBuf },
+    /// The OS keyring, as `credential_process` JSON
+    Keyring,
+    /// A HashiCorp Vault KV (version 2) secrets engine
+    #[cfg(feature = "vault")]
+    Vault {
+        /// Defaults to `VAULT_ADDR`
+        address: Option<String>,
+        #[serde(default)]
+        auth: crate::vault::Auth,
+        /// Where the secrets engine is mounted, e.g. `secret`
+        mount: String,
+        /// The path under the mount each profile's secret is kept under
+        path: String,
+    },
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Env { dir } => write!(f, "env ({})", dir.display()),
+            Self::Keyring => write!(f, "keyring"),
+            #[cfg(feature = "vault")]
+            Self::Vault { mount, path, .. } => write!(f, "vault ({mount}/{path})"),
+        }
+    }
+}
+
+impl Config {
+    /// The sink this config describes, for `organization`'s profiles
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the sink is missing settings it needs (e.g. a Vault token)
+    pub fn build(&self, organization: &str) -> Result<Box<dyn CredentialSink>> {
+        Ok(match self {
+            Self::Env { dir } => Box::new(env::EnvFiles::new(dir.clone())),
+            Self::Keyring => Box::new(keychain::Keychain::new(organization)),
+            #[cfg(feature = "vault")]
+            Self::Vault {
+                address,
+                auth,
+                mount,
+                path,
+            } => Box::new(vault::Vault::new(address.as_deref(), auth, mount, path)?),
+        })
+    }
+}
+
+/// Copy `credentials` (keyed by profile) to each of `organization`'s extra sinks.
+/// A sink which fails is reported, without stopping the others, or the credentials file, from being written.
+#[instrument(skip_all, fields(organization=%organization))]
+pub async fn deliver(organization: &str, sinks: &[Config], credentials: &[(String, Credentials)]) {
+    deliver_all(&[(
+        organization.to_string(),
+        sinks.to_vec(),
+        credentials.to_vec(),
+    )])
+    .await;
+}
+
+/// Copy each organization's credentials (keyed by profile) to its extra sinks,
+/// writing to at most [`WriteLimits::max_concurrent`] sinks at once,
+/// and flushing each after every [`WriteLimits::batch_size`] profiles.
+/// A sink which fails is reported, without stopping the others, or the credentials file, from being written.
+#[instrument(skip_all)]
+pub async fn deliver_all(deliveries: &[(String, Vec<Config>, Vec<(String, Credentials)>)]) {
+    let limits = WriteLimits::current();
+
+    let writes = deliveries
+        .iter()
+        .flat_map(|(organization, sinks, credentials)| {
+            sinks.iter().map(move |config| {
+                let (organization, sink, credentials) =
+                    (organization.clone(), config.clone(), credentials.clone());
+
+                async move {
+                    let delivered = blocking::run(move || {
+                        let mut sink = sink.build(&organization)?;
+                        for batch in credentials.chunks(limits.batch_size.max(1)) {
+                            for (profile, credentials) in batch {
+                                sink.write(profile, credentials)?;
+                            }
+                            sink.flush()?;
+                        }
+                        Ok(credentials.len())
+                    })
+                    .await;
+
+                    match delivered {
+                        Ok(count) => debug!("Wrote {count} profile(s) to the {config} sink"),
+                        Err(e) => warn!("Unable to write credentials to the {config} sink ({e})"),
+                    }
+                }
+            })
+        });
+
+    stream::iter(writes)
+        .buffer_unordered(limits.max_concurrent.max(1))
+        .collect::<Vec<()>>()
+        .await;
+}
+
+/// Remove `profiles` (an organization's credentials sections) from each of `organization`'s extra sinks,
+/// returning how many copies were removed. Whatever else a sink holds is left alone.
+/// A sink which fails is reported, without stopping the others.
+#[instrument(skip_all, fields(organization=%organization))]
+pub async fn forget(organization: &str, sinks: &[Config], profiles: &[String]) -> usize {
+    let mut removed = 0;
+
+    for config in sinks {
+        let (organization, sink, profiles) =
+            (organization.to_string(), config.clone(), profiles.to_vec());
+
+        let forgotten = blocking::run(move || {
+            let mut sink = sink.build(&organization)?;
+            let mut removed = 0;
+            for profile in sink
+                .managed()?
+                .iter()
+                .filter(|managed| profiles.contains(managed))
+            {
+                if sink.remove(profile)? {
+                    removed += 1;
+                }
+            }
+            sink.flush()?;
+            Ok(removed)
+        })
+        .await;
+
+        match forgotten {
+            Ok(count) => {
+                debug!("Removed {count} profile(s) from the {config} sink");
+                removed += count;
+            }
+            Err(e) => warn!("Unable to remove credentials from the {config} sink ({e})"),
+        }
+    }
+
+    removed
+}
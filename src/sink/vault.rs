@@ -0,0 +1,103 @@
+//! Credentials in a HashiCorp Vault KV (version 2) secrets engine, for infrastructure which reads them from there.
+//!
+//! Each profile is a secret at `<mount>/<path>/<profile>`, holding the `credential_process` fields.
+//! Vault is signed in to as for `[password_vault]` (see [`crate::vault`]),
+//! and the token needs create, update, delete and list on those paths.
+
+use crate::aws::process::ProcessCredentials;
+use crate::sink::CredentialSink;
+use crate::vault::{Auth, Connection};
+
+use aws_credential_types::Credentials;
+use eyre::Result;
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+#[derive(Deserialize)]
+struct List {
+    data: ListData,
+}
+
+#[derive(Deserialize)]
+struct ListData {
+    keys: Vec<String>,
+}
+
+/// A path in a KV secrets engine
+pub struct Vault {
+    connection: Connection,
+    mount: String,
+    path: String,
+}
+
+impl Vault {
+    /// # Errors
+    ///
+    /// Will return `Err` if there is no address (or `VAULT_ADDR`), or Vault cannot be signed in to
+    pub fn new(address: Option<&str>, auth: &Auth, mount: &str, path: &str) -> Result<Self> {
+        Ok(Self {
+            connection: Connection::open(address, auth)?,
+            mount: mount.trim_matches('/').to_string(),
+            path: path.trim_matches('/').to_string(),
+        })
+    }
+
+    /// The API URL for `kind` (`data` or `metadata`) under the sink's path
+    fn url(&self, kind: &str, profile: Option<&str>) -> Result<Url> {
+        self.connection.url(&profile.map_or_else(
+            || format!("{}/{kind}/{}", self.mount, self.path),
+            |profile| format!("{}/{kind}/{}/{profile}", self.mount, self.path),
+        ))
+    }
+}
+
+impl CredentialSink for Vault {
+    fn write(&mut self, profile: &str, credentials: &Credentials) -> Result<()> {
+        let secret = json!({ "data": ProcessCredentials::new(credentials)? });
+        self.connection.send(
+            self.connection
+                .client()
+                .post(self.url("data", Some(profile))?)
+                .json(&secret),
+        )?;
+
+        Ok(())
+    }
+
+    fn remove(&mut self, profile: &str) -> Result<bool> {
+        // Deleting the metadata removes every version of the secret
+        let url = self.url("metadata", Some(profile))?;
+        if self
+            .connection
+            .fetch(self.connection.client().get(url.clone()))?
+            .is_none()
+        {
+            return Ok(false);
+        }
+
+        self.connection.send(self.connection.client().delete(url))?;
+        Ok(true)
+    }
+
+    fn managed(&self) -> Result<Vec<String>> {
+        let Some(response) = self.connection.fetch(
+            self.connection
+                .client()
+                .get(self.url("metadata", None)?)
+                .query(&[("list", "true")]),
+        )?
+        else {
+            return Ok(Vec::new());
+        };
+
+        Ok(response
+            .json::<List>()?
+            .data
+            .keys
+            .into_iter()
+            // Keys ending in `/` are paths, not secrets
+            .filter(|key| !key.ends_with('/'))
+            .collect())
+    }
+}
@@ -0,0 +1,614 @@
+//! Passphrase-encrypted export and import of oktaws state, for moving to a new machine.
+//!
+//! Everything in the oktaws config and state directories is included,
+//! except for the cache, the file secret store (whose key is the machine's own) and lock files.
+//! Of the cache, only the discovered catalogs and unexpired Identity Center client registrations are included.
+//! The Okta sessions and device tokens are exported from the secret store instead, so that the new machine
+//! signs in as the old one did. AWS secrets are never exported; they are cheap to fetch again on the new machine.
+//!
+//! Files are kept by their role, so that they can be imported into either layout: with `OKTAWS_HOME`
+//! (where configs and state share one directory), or with separate config and state directories.
+
+use crate::aws::identity_center;
+use crate::discovery;
+use crate::secrets::Secret;
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::num::NonZeroU32;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path, PathBuf};
+
+use base64::engine::{Engine, general_purpose::STANDARD as b64};
+use eyre::{Result, eyre};
+use ring::aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+const MAGIC: &[u8] = b"oktaws-state-v1\n";
+const SALT_LEN: usize = 16;
+
+/// PBKDF2 rounds used for new exports (imports read the count from the file)
+pub const ITERATIONS: u32 = 600_000;
+
+/// The most PBKDF2 rounds an import will run, so that a corrupted (or hostile) file can't keep it busy for hours
+pub const MAX_ITERATIONS: u32 = 10 * ITERATIONS;
+
+/// Where configs are kept in a bundle
+const CONFIG_PREFIX: &str = "config/";
+
+/// Where state is kept in a bundle
+const STATE_PREFIX: &str = "state/";
+
+/// Where cached files are kept in a bundle
+const CACHE_PREFIX: &str = "cache/";
+
+/// Directories (relative to the oktaws home) which hold the cache (exported separately),
+/// or secrets only this machine can decrypt, and so are not exported as they are
+const EXCLUDED_DIRS: &[&str] = &["cache", "secrets"];
+
+/// Directories (relative to the cache directory) which are exported, with whether each of their files is worth it.
+/// The rest of the cache, such as AWS credentials, is never exported.
+/// Identity Center client secrets and tokens are in the secret store, not in its cache.
+const EXPORTED_CACHES: &[(&str, fn(&[u8]) -> bool)] = &[
+    (discovery::CATALOG_DIR, |_| true),
+    (identity_center::DIR, identity_center::unexpired),
+];
+
+/// The secrets exported for each organization, by the name they are kept under in a bundle
+const SECRETS: &[(&str, fn(&str) -> Secret)] = &[
+    ("session", Secret::okta_session),
+    ("device", Secret::okta_device),
+];
+
+/// The files making up oktaws state, keyed by their path relative to the config directory (under `config/`),
+/// the state directory (under `state/`) or the cache directory (under `cache/`).
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Bundle {
+    files: BTreeMap<String, String>,
+    /// Secrets from the secret store, keyed by their name in [`SECRETS`] and organization, e.g. `session/mycompany`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    secrets: BTreeMap<String, String>,
+}
+
+impl Bundle {
+    /// Collect the configs in `home`, the state in `state` (which may be the same directory),
+    /// and what is worth keeping of the cache in `cache`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if any of the files cannot be read
+    pub fn collect(home: &Path, state: &Path, cache: &Path) -> Result<Self> {
+        let mut bundle = Self::default();
+        if state == home {
+            bundle.collect_dir(home, home, None)?;
+        } else {
+            bundle.collect_dir(home, home, Some(CONFIG_PREFIX))?;
+            if state.is_dir() {
+                bundle.collect_dir(state, state, Some(STATE_PREFIX))?;
+            }
+        }
+        bundle.collect_cache(cache)?;
+        Ok(bundle)
+    }
+
+    /// Collect the files in the [`EXPORTED_CACHES`] directories of `cache` which are worth exporting
+    fn collect_cache(&mut self, cache: &Path) -> Result<()> {
+        for (dir, exported) in EXPORTED_CACHES {
+            let Ok(entries) = fs::read_dir(cache.join(dir)) else {
+                continue;
+            };
+
+            for entry in entries {
+                let path = entry?.path();
+                let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let relative = format!("{CACHE_PREFIX}{dir}/{name}");
+
+                if !path.is_file() || path.extension().is_some_and(|ext| ext == "lock") {
+                    debug!("Not exporting {relative}");
+                    continue;
+                }
+
+                let contents = fs::read(&path)?;
+                if exported(&contents) {
+                    self.files.insert(relative, b64.encode(contents));
+                } else {
+                    debug!("Not exporting {relative}, as it has expired");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collect the files in `dir` (under `home`), under `prefix`, or where the directory holds both configs
+    /// and state, under the prefix for each file's role
+    fn collect_dir(&mut self, home: &Path, dir: &Path, prefix: Option<&str>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let relative = path
+                .strip_prefix(home)?
+                .to_string_lossy()
+                .replace('\\', "/");
+
+            if path.is_dir() {
+                if EXCLUDED_DIRS.contains(&relative.as_str()) {
+                    debug!("Not exporting {relative}");
+                } else {
+                    self.collect_dir(home, &path, prefix)?;
+                }
+            } else if path.extension().is_some_and(|ext| ext == "lock") {
+                debug!("Not exporting {relative}");
+            } else {
+                let prefix = prefix.unwrap_or_else(|| role(&relative));
+                self.files
+                    .insert(format!("{prefix}{relative}"), b64.encode(fs::read(&path)?));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collect the Okta sessions and device tokens kept for `organizations`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the secret store cannot be read
+    pub fn collect_secrets<'a>(
+        &mut self,
+        organizations: impl IntoIterator<Item = &'a str>,
+    ) -> Result<()> {
+        for organization in organizations {
+            for (kind, secret) in SECRETS {
+                if let Some(value) = secret(organization).get()? {
+                    self.secrets.insert(format!("{kind}/{organization}"), value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The paths of the files in the bundle, under the prefix for their role
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    /// The secrets in the bundle, e.g. `session/mycompany`
+    pub fn secrets(&self) -> impl Iterator<Item = &str> {
+        self.secrets.keys().map(String::as_str)
+    }
+
+    /// Keep the bundle's secrets in the secret store, returning their names
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a secret is not one oktaws exports, or the secret store cannot be written
+    pub fn restore_secrets(&self) -> Result<Vec<&str>> {
+        for (key, value) in &self.secrets {
+            let (kind, organization) = key
+                .split_once('/')
+                .ok_or_else(|| eyre!("Refusing to import secret {key}"))?;
+            let (_, secret) = SECRETS
+                .iter()
+                .find(|(name, _)| *name == kind)
+                .ok_or_else(|| eyre!("Refusing to import secret {key}"))?;
+
+            secret(organization).set(value)?;
+        }
+
+        Ok(self.secrets().collect())
+    }
+
+    /// Write the configs into `home`, the state into `state` and the cached files into `cache`,
+    /// readable only by the current user, returning the paths written.
+    /// Existing files are only replaced if `overwrite` is set.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a file would be written outside of `home` (or `state`, or `cache`),
+    /// if a file already exists and `overwrite` is not set,
+    /// or if any of the files cannot be written.
+    pub fn restore(
+        &self,
+        home: &Path,
+        state: &Path,
+        cache: &Path,
+        overwrite: bool,
+    ) -> Result<Vec<PathBuf>> {
+        let targets = self
+            .files
+            .iter()
+            .map(|(relative, contents)| {
+                let (root, relative) = if let Some(relative) = relative.strip_prefix(STATE_PREFIX) {
+                    (state, relative)
+                } else if let Some(relative) = relative.strip_prefix(CONFIG_PREFIX) {
+                    (home, relative)
+                } else if let Some(relative) =
+                    relative.strip_prefix(CACHE_PREFIX).filter(|relative| {
+                        relative.split_once('/').is_some_and(|(dir, _)| {
+                            EXPORTED_CACHES.iter().any(|(exported, _)| *exported == dir)
+                        })
+                    })
+                {
+                    (cache, relative)
+                } else {
+                    return Err(eyre!("Refusing to import {relative}"));
+                };
+                let relative = Path::new(relative);
+                if !relative
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+                {
+                    return Err(eyre!("Refusing to import {}", relative.display()));
+                }
+
+                let target = root.join(relative);
+                if target.exists() && !overwrite {
+                    return Err(eyre!(
+                        "{} already exists. Use --force to replace it",
+                        target.display()
+                    ));
+                }
+
+                Ok((target, b64.decode(contents)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for (target, contents) in &targets {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let mut options = OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            options.mode(0o600);
+
+            let mut file = options.open(target)?;
+            // The mode only applies to new files, and these may hold tokens
+            #[cfg(unix)]
+            file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+            file.write_all(contents)?;
+        }
+
+        Ok(targets.into_iter().map(|(target, _)| target).collect())
+    }
+
+    /// Encrypt the bundle with a key derived from `passphrase`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if random numbers cannot be generated, or encryption fails
+    pub fn encrypt(&self, passphrase: &str) -> Result<Vec<u8>> {
+        self.encrypt_with_iterations(passphrase, ITERATIONS)
+    }
+
+    fn encrypt_with_iterations(&self, passphrase: &str, iterations: u32) -> Result<Vec<u8>> {
+        let rng = SystemRandom::new();
+
+        let mut salt = [0; SALT_LEN];
+        rng.fill(&mut salt)
+            .map_err(|_| eyre!("Unable to generate a salt"))?;
+        let mut nonce = [0; NONCE_LEN];
+        rng.fill(&mut nonce)
+            .map_err(|_| eyre!("Unable to generate a nonce"))?;
+
+        let mut contents = serde_json::to_vec(self)?;
+        key(passphrase, &salt, iterations)?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(MAGIC),
+                &mut contents,
+            )
+            .map_err(|_| eyre!("Unable to encrypt state"))?;
+
+        Ok([
+            MAGIC,
+            &iterations.to_be_bytes()[..],
+            &salt[..],
+            &nonce[..],
+            &contents[..],
+        ]
+        .concat())
+    }
+
+    /// Decrypt a bundle made by [`Bundle::encrypt`]
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the data is not an oktaws state export,
+    /// or if the passphrase is wrong.
+    pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Self> {
+        let data = data
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| eyre!("Not an oktaws state export"))?;
+
+        let header_len = 4 + SALT_LEN + NONCE_LEN;
+        if data.len() < header_len {
+            return Err(eyre!("Truncated oktaws state export"));
+        }
+
+        let (header, contents) = data.split_at(header_len);
+        let (iterations, rest) = header.split_at(4);
+        let (salt, nonce) = rest.split_at(SALT_LEN);
+
+        let iterations = u32::from_be_bytes(iterations.try_into()?);
+        if iterations > MAX_ITERATIONS {
+            return Err(eyre!(
+                "Refusing to import an oktaws state export with {iterations} key derivation rounds (at most {MAX_ITERATIONS})"
+            ));
+        }
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| eyre!("Invalid nonce in oktaws state export"))?;
+
+        let mut contents = contents.to_vec();
+        let plaintext = key(passphrase, salt, iterations)?
+            .open_in_place(nonce, Aad::from(MAGIC), &mut contents)
+            .map_err(|_| eyre!("Wrong passphrase, or the export is corrupted"))?;
+
+        serde_json::from_slice(plaintext).map_err(Into::into)
+    }
+}
+
+/// The prefix for the role of the file at `relative` in a directory holding both configs and state:
+/// configs are the TOML files at the top (the organizations' and `settings.toml`), and everything else is state
+fn role(relative: &str) -> &'static str {
+    if !relative.contains('/') && relative.ends_with(".toml") {
+        CONFIG_PREFIX
+    } else {
+        STATE_PREFIX
+    }
+}
+
+/// The key for `passphrase`, derived with PBKDF2
+fn key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<LessSafeKey> {
+    UnboundKey::new(&CHACHA20_POLY1305, &derive(passphrase, salt, iterations)?)
+        .map(LessSafeKey::new)
+        .map_err(|_| eyre!("Unable to derive key"))
+}
+
+/// The key material for `passphrase`, derived with PBKDF2
+pub(crate) fn derive(passphrase: &str, salt: &[u8], iterations: u32) -> Result<[u8; 32]> {
+    let iterations = NonZeroU32::new(iterations).ok_or_else(|| eyre!("Invalid iteration count"))?;
+
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn excludes_aws_secrets() -> Result<()> {
+        let home = tempdir()?;
+        fs::write(home.path().join("mock_org.toml"), "[profiles]")?;
+        fs::write(home.path().join("mock_org.toml.lock"), "")?;
+        fs::create_dir_all(home.path().join("cache").join("mock_org"))?;
+        fs::write(
+            home.path().join("cache").join("mock_org").join("prod.json"),
+            "{}",
+        )?;
+
+        let bundle = Bundle::collect(home.path(), home.path(), &home.path().join("cache"))?;
+
+        assert_eq!(
+            bundle.paths().collect::<Vec<_>>(),
+            vec!["config/mock_org.toml"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let old_home = tempdir()?;
+        fs::write(old_home.path().join("mock_org.toml"), "[profiles]")?;
+
+        let exported = Bundle::collect(
+            old_home.path(),
+            old_home.path(),
+            &old_home.path().join("cache"),
+        )?
+        .encrypt_with_iterations("hunter2", 1)?;
+
+        assert_eq!(
+            Bundle::decrypt(&exported, "hunter3")
+                .unwrap_err()
+                .to_string(),
+            "Wrong passphrase, or the export is corrupted"
+        );
+
+        let new_home = tempdir()?;
+        let bundle = Bundle::decrypt(&exported, "hunter2")?;
+        bundle.restore(
+            new_home.path(),
+            new_home.path(),
+            &new_home.path().join("cache"),
+            false,
+        )?;
+
+        assert_eq!(
+            fs::read_to_string(new_home.path().join("mock_org.toml"))?,
+            "[profiles]"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(
+                &fs::metadata(new_home.path().join("mock_org.toml"))?.permissions()
+            ) & 0o777,
+            0o600
+        );
+        assert!(
+            bundle
+                .restore(
+                    new_home.path(),
+                    new_home.path(),
+                    &new_home.path().join("cache"),
+                    false
+                )
+                .is_err()
+        );
+        bundle.restore(
+            new_home.path(),
+            new_home.path(),
+            &new_home.path().join("cache"),
+            true,
+        )?;
+
+        // The round count is read from the file, so is bounded
+        let mut hostile = exported.clone();
+        hostile[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(
+            Bundle::decrypt(&hostile, "hunter2")
+                .unwrap_err()
+                .to_string()
+                .starts_with("Refusing to import")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_state_separate() -> Result<()> {
+        let (old_home, old_state) = (tempdir()?, tempdir()?);
+        fs::write(old_home.path().join("mock_org.toml"), "[profiles]")?;
+        fs::write(old_state.path().join("usage.json"), "{}")?;
+
+        let bundle = Bundle::collect(
+            old_home.path(),
+            old_state.path(),
+            &old_state.path().join("cache"),
+        )?;
+        assert_eq!(
+            bundle.paths().collect::<Vec<_>>(),
+            vec!["config/mock_org.toml", "state/usage.json"]
+        );
+
+        let (new_home, new_state) = (tempdir()?, tempdir()?);
+        bundle.restore(
+            new_home.path(),
+            new_state.path(),
+            &new_state.path().join("cache"),
+            false,
+        )?;
+
+        assert!(new_home.path().join("mock_org.toml").exists());
+        assert!(new_state.path().join("usage.json").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn separates_state_from_a_single_home() -> Result<()> {
+        let old_home = tempdir()?;
+        fs::write(old_home.path().join("mock_org.toml"), "[profiles]")?;
+        fs::write(old_home.path().join("usage.json"), "{}")?;
+        fs::create_dir_all(old_home.path().join("discovery"))?;
+        fs::write(old_home.path().join("discovery").join("mock_org.toml"), "")?;
+
+        let bundle = Bundle::collect(
+            old_home.path(),
+            old_home.path(),
+            &old_home.path().join("cache"),
+        )?;
+        assert_eq!(
+            bundle.paths().collect::<Vec<_>>(),
+            vec![
+                "config/mock_org.toml",
+                "state/discovery/mock_org.toml",
+                "state/usage.json"
+            ]
+        );
+
+        let (new_home, new_state) = (tempdir()?, tempdir()?);
+        bundle.restore(
+            new_home.path(),
+            new_state.path(),
+            &new_state.path().join("cache"),
+            false,
+        )?;
+        assert!(new_home.path().join("mock_org.toml").exists());
+        assert!(new_state.path().join("usage.json").exists());
+        assert!(!new_home.path().join("usage.json").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_unexpired_caches() -> Result<()> {
+        let old_home = tempdir()?;
+        let cache = old_home.path().join("cache");
+        fs::create_dir_all(cache.join(discovery::CATALOG_DIR))?;
+        fs::write(
+            cache.join(discovery::CATALOG_DIR).join("mock_org.json"),
+            "[]",
+        )?;
+        fs::create_dir_all(cache.join(identity_center::DIR))?;
+        fs::write(
+            cache.join(identity_center::DIR).join("expired.json"),
+            r#"{"client_id":"CLIENT_ID","client_expires_at":0,"token_expires_at":0}"#,
+        )?;
+
+        let bundle = Bundle::collect(old_home.path(), old_home.path(), &cache)?;
+        assert_eq!(
+            bundle.paths().collect::<Vec<_>>(),
+            vec!["cache/catalog/mock_org.json"]
+        );
+
+        let (new_home, new_cache) = (tempdir()?, tempdir()?);
+        bundle.restore(new_home.path(), new_home.path(), new_cache.path(), false)?;
+        assert_eq!(
+            fs::read_to_string(
+                new_cache
+                    .path()
+                    .join(discovery::CATALOG_DIR)
+                    .join("mock_org.json")
+            )?,
+            "[]"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_paths_outside_home() {
+        let home = tempdir().unwrap();
+
+        for path in [
+            "config/../escape.toml",
+            "mock_org.toml",
+            "cache/credentials/prod.json",
+        ] {
+            let bundle = Bundle {
+                files: BTreeMap::from([(String::from(path), String::new())]),
+                ..Bundle::default()
+            };
+
+            assert!(
+                bundle
+                    .restore(home.path(), home.path(), &home.path().join("cache"), true)
+                    .unwrap_err()
+                    .to_string()
+                    .starts_with("Refusing to import")
+            );
+        }
+    }
+}
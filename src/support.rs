@@ -0,0 +1,270 @@
+//! Support bundles: everything useful for a bug report, in one tarball.
+//!
+//! Configs are sanitized and logs are redacted before anything is added,
+//! and the user reviews (and can drop) each file before the bundle is written.
+
+use crate::redact::Redactor;
+
+use std::collections::BTreeMap;
+use std::env::var as env_var;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eyre::{Result, eyre};
+use tracing::debug;
+
+const MASK: &str = "[REDACTED]";
+
+/// The directory every file in the tarball is under
+const ROOT: &str = "oktaws-support";
+
+/// Config keys which identify the user, rather than describing the setup
+const PERSONAL_KEYS: &[&str] = &["username"];
+
+/// Environment variables which affect oktaws or the AWS SDKs, and whose values are safe to share
+const ENVIRONMENT: &[&str] = &[
+    "OKTAWS_HOME",
+    "OKTAWS_THEME",
+    "NO_COLOR",
+    "AWS_PROFILE",
+    "AWS_REGION",
+    "AWS_DEFAULT_REGION",
+    "AWS_CONFIG_FILE",
+    "AWS_SHARED_CREDENTIALS_FILE",
+    "OKTA_SESSION_URL",
+];
+
+/// Environment variables which are secret (or may contain secrets), so only whether they are set is recorded
+const SECRET_ENVIRONMENT: &[&str] = &[
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "OKTA_SESSION_ID",
+    "HTTPS_PROXY",
+    "HTTP_PROXY",
+    "NO_PROXY",
+];
+
+/// The files making up a support bundle, keyed by their name in the tarball
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Bundle {
+    files: BTreeMap<String, String>,
+}
+
+impl Bundle {
+    /// Start a bundle with version and environment details
+    #[must_use]
+    pub fn new() -> Self {
+        let mut bundle = Self::default();
+
+        bundle.add(
+            "version.txt",
+            format!(
+                "oktaws {}\nos: {}\narch: {}\n",
+                env!("CARGO_PKG_VERSION"),
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            ),
+        );
+
+        let mut environment = String::new();
+        for name in ENVIRONMENT {
+            let _ = writeln!(
+                environment,
+                "{name}={}",
+                env_var(name).unwrap_or_else(|_| String::from("(unset)"))
+            );
+        }
+        for name in SECRET_ENVIRONMENT {
+            let state = if env_var(name).is_ok() {
+                "(set)"
+            } else {
+                "(unset)"
+            };
+            let _ = writeln!(environment, "{name}={state}");
+        }
+        bundle.add("environment.txt", environment);
+
+        bundle
+    }
+
+    /// Add a file to the bundle
+    pub fn add(&mut self, name: &str, contents: String) {
+        self.files.insert(name.to_string(), contents);
+    }
+
+    /// Add the TOML configs in `home`, with personal details masked
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the directory or any config cannot be read
+    pub fn add_configs(&mut self, home: &Path, redactor: &Redactor) -> Result<()> {
+        if !home.is_dir() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(home)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "toml") {
+                continue;
+            }
+
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or_else(|| eyre!("No file name for {}", path.display()))?;
+
+            self.add(
+                &format!("config/{name}"),
+                sanitize_config(&fs::read_to_string(&path)?, redactor),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Add a log file, redacted
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the log cannot be read
+    pub fn add_log(&mut self, path: &Path, redactor: &Redactor) -> Result<()> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| eyre!("No file name for {}", path.display()))?;
+
+        let contents = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+        self.add(&format!("logs/{name}"), redactor.redact(&contents));
+
+        Ok(())
+    }
+
+    /// The names of the files in the bundle
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    /// The contents of a file in the bundle
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.files.get(name).map(String::as_str)
+    }
+
+    /// Drop every file not in `names`
+    pub fn retain(&mut self, names: &[String]) {
+        self.files.retain(|name, _| names.contains(name));
+    }
+
+    /// Write the bundle as a tarball at `path`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the tarball cannot be written
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        let mut tarball = tar::Builder::new(File::create(path)?);
+        for (name, contents) in &self.files {
+            debug!("Adding {name} to the support bundle");
+
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o600);
+            header.set_mtime(mtime);
+            header.set_cksum();
+
+            tarball.append_data(&mut header, format!("{ROOT}/{name}"), contents.as_bytes())?;
+        }
+        tarball.finish()?;
+
+        Ok(())
+    }
+}
+
+/// Mask personal details in a TOML config, and redact anything secret.
+/// A config which cannot be parsed is redacted as it is, since its syntax may be what the bug is about.
+fn sanitize_config(contents: &str, redactor: &Redactor) -> String {
+    let sanitized = match contents.parse::<toml::Table>() {
+        Ok(mut table) => {
+            mask_keys(&mut table);
+            toml::to_string_pretty(&table).unwrap_or_else(|_| contents.to_string())
+        }
+        Err(_) => contents.to_string(),
+    };
+
+    redactor.redact(&sanitized)
+}
+
+fn mask_keys(table: &mut toml::Table) {
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(table) => mask_keys(table),
+            _ if PERSONAL_KEYS.contains(&key.as_str()) => {
+                *value = toml::Value::String(MASK.to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn masks_usernames() -> Result<()> {
+        let redactor = Redactor::new::<&str>(&[])?;
+
+        let sanitized = sanitize_config(
+            "username = \"jane@example.com\"\nrole = \"Admin\"\n\n[profiles]\nprod = \"AWS Prod\"\n",
+            &redactor,
+        );
+
+        assert!(sanitized.contains("username = \"[REDACTED]\""));
+        assert!(sanitized.contains("role = \"Admin\""));
+        assert!(sanitized.contains("prod = \"AWS Prod\""));
+        assert!(!sanitized.contains("jane@example.com"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn writes_tarball() -> Result<()> {
+        let dir = tempdir()?;
+        let redactor = Redactor::new::<&str>(&[])?;
+
+        let log = dir.path().join("refresh.log");
+        fs::write(&log, "cookie: sid=SESSION_ID; DT=DEVICE\n")?;
+
+        let mut bundle = Bundle::new();
+        bundle.add_log(&log, &redactor)?;
+        bundle.retain(&[String::from("logs/refresh.log")]);
+
+        let path = dir.path().join("support.tar");
+        bundle.write(&path)?;
+
+        let mut archive = tar::Archive::new(File::open(&path)?);
+        let mut entries = archive.entries()?;
+        let mut entry = entries.next().ok_or_else(|| eyre!("Empty tarball"))??;
+
+        assert_eq!(
+            entry.path()?.to_string_lossy(),
+            "oktaws-support/logs/refresh.log"
+        );
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents)?;
+        assert_eq!(contents, "cookie: sid=[REDACTED]; DT=[REDACTED]\n");
+        assert!(entries.next().is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::env::var as env_var;
+use std::fmt;
+use std::io::{IsTerminal, stderr};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use console::Style;
+use dialoguer::theme::{ColorfulTheme, SimpleTheme, Theme as PromptTheme};
+use eyre::{Error, Result, eyre};
+
+/// Terminal styling for prompts, logs and error reports
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Theme {
+    /// Colours which read well on light and dark backgrounds
+    #[default]
+    Default,
+    /// Brighter colours for dark backgrounds
+    Dark,
+    /// No colours, and plain ASCII prompts (for logs and screen readers)
+    None,
+}
+
+impl FromStr for Theme {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "default" => Ok(Self::Default),
+            "dark" => Ok(Self::Dark),
+            "none" => Ok(Self::None),
+            _ => Err(eyre!("Unknown theme {s}, expected default, dark or none")),
+        }
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::Dark => write!(f, "dark"),
+            Self::None => write!(f, "none"),
+        }
+    }
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+static PROMPT_THEME: OnceLock<Box<dyn PromptTheme + Send + Sync>> = OnceLock::new();
+
+impl Theme {
+    /// Decide which theme to use.
+    /// `NO_COLOR` (see <https://no-color.org>) and non-terminal output always disable styling,
+    /// otherwise `requested` is used, falling back on `OKTAWS_THEME`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `OKTAWS_THEME` is not a known theme
+    pub fn resolve(requested: Option<Self>) -> Result<Self> {
+        let no_color = env_var("NO_COLOR").is_ok_and(|value| !value.is_empty());
+
+        if no_color || !stderr().is_terminal() {
+            return Ok(Self::None);
+        }
+
+        requested.map_or_else(
+            || env_var("OKTAWS_THEME").map_or(Ok(Self::Default), |theme| theme.parse()),
+            Ok,
+        )
+    }
+
+    /// Install this theme for the whole process.
+    /// Only the first call has any effect.
+    pub fn install(self) {
+        if THEME.set(self).is_ok() {
+            let colors = self != Self::None;
+            console::set_colors_enabled(colors);
+            console::set_colors_enabled_stderr(colors);
+        }
+    }
+
+    /// The theme installed for this process
+    #[must_use]
+    pub fn current() -> Self {
+        THEME.get().copied().unwrap_or_default()
+    }
+
+    /// Whether logs should include ANSI colours
+    #[must_use]
+    pub fn ansi(self) -> bool {
+        self != Self::None
+    }
+
+    /// The matching theme for error reports
+    #[must_use]
+    pub fn eyre_theme(self) -> color_eyre::config::Theme {
+        match self {
+            Self::Default => color_eyre::config::Theme::light(),
+            Self::Dark => color_eyre::config::Theme::dark(),
+            Self::None => color_eyre::config::Theme::new(),
+        }
+    }
+
+    fn prompt_theme(self) -> Box<dyn PromptTheme + Send + Sync> {
+        match self {
+            Self::Default => Box::new(ColorfulTheme::default()),
+            Self::Dark => Box::new(ColorfulTheme {
+                values_style: Style::new().for_stderr().cyan().bright(),
+                active_item_style: Style::new().for_stderr().cyan().bright(),
+                hint_style: Style::new().for_stderr().white(),
+                ..ColorfulTheme::default()
+            }),
+            Self::None => Box::new(SimpleTheme),
+        }
+    }
+}
+
+/// The theme to use for interactive prompts
+#[must_use]
+pub fn prompt() -> &'static dyn PromptTheme {
+    PROMPT_THEME
+        .get_or_init(|| Theme::current().prompt_theme())
+        .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_theme() {
+        assert_eq!("Dark".parse::<Theme>().unwrap(), Theme::Dark);
+        assert_eq!(
+            "neon".parse::<Theme>().unwrap_err().to_string(),
+            "Unknown theme neon, expected default, dark or none"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Human-friendly rendering of expiration times.
+
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+
+/// How expiration times are shown
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Local time, with the time remaining (e.g. `2024-01-02 09:30 -08:00 (in 42m)`)
+    #[default]
+    Local,
+    /// UTC, with the time remaining
+    Utc,
+    /// RFC 3339 in UTC, for scripts (e.g. `2024-01-02T17:30:00Z`)
+    Rfc3339,
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+impl Format {
+    /// Choose the format from the `--utc` and `--rfc3339` flags
+    #[must_use]
+    pub const fn from_flags(utc: bool, rfc3339: bool) -> Self {
+        match (utc, rfc3339) {
+            (_, true) => Self::Rfc3339,
+            (true, false) => Self::Utc,
+            (false, false) => Self::Local,
+        }
+    }
+
+    /// Install this format for the whole process.
+    /// Only the first call has any effect.
+    pub fn install(self) {
+        let _ = FORMAT.set(self);
+    }
+
+    /// The format installed for this process
+    #[must_use]
+    pub fn current() -> Self {
+        FORMAT.get().copied().unwrap_or_default()
+    }
+
+    /// Render `time`, relative to `now`
+    #[must_use]
+    pub fn render(self, time: SystemTime, now: SystemTime) -> String {
+        match self {
+            Self::Local => format!(
+                "{} ({})",
+                DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M %:z"),
+                relative(time, now)
+            ),
+            Self::Utc => format!(
+                "{} ({})",
+                DateTime::<Utc>::from(time).format("%Y-%m-%d %H:%M UTC"),
+                relative(time, now)
+            ),
+            Self::Rfc3339 => DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+}
+
+/// Render `time` in the installed format
+#[must_use]
+pub fn render(time: SystemTime) -> String {
+    Format::current().render(time, SystemTime::now())
+}
+
+/// How far `time` is from `now`, e.g. `in 1h 5m` or `3m ago`
+#[must_use]
+pub fn relative(time: SystemTime, now: SystemTime) -> String {
+    match time.duration_since(now) {
+        Ok(remaining) => format!("in {}", duration(remaining)),
+        Err(e) => format!("{} ago", duration(e.duration())),
+    }
+}
+
+/// A short rendering of a duration, to the minute (or second, when under a minute)
+#[must_use]
+pub fn duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{seconds}s"),
+        (0, 0, _) => format!("{minutes}m"),
+        (0, _, 0) => format!("{hours}h"),
+        (0, _, _) => format!("{hours}h {minutes}m"),
+        (_, 0, _) => format!("{days}d"),
+        (_, _, _) => format!("{days}d {hours}h"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn render_relative() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(relative(now + Duration::from_mins(42), now), "in 42m");
+        assert_eq!(relative(now + Duration::from_mins(65), now), "in 1h 5m");
+        assert_eq!(relative(now + Duration::from_hours(12), now), "in 12h");
+        assert_eq!(relative(now + Duration::from_secs(30), now), "in 30s");
+        assert_eq!(relative(now - Duration::from_mins(3), now), "3m ago");
+        assert_eq!(relative(now + Duration::from_hours(26), now), "in 1d 2h");
+    }
+
+    #[test]
+    fn render_utc() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let expiry = now + Duration::from_hours(1);
+
+        assert_eq!(
+            Format::Utc.render(expiry, now),
+            "2023-11-14 23:13 UTC (in 1h)"
+        );
+        assert_eq!(Format::Rfc3339.render(expiry, now), "2023-11-14T23:13:20Z");
+    }
+}
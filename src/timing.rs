@@ -0,0 +1,165 @@
+//! Records where time is spent across the auth and refresh pipeline,
+//! as folded stacks for `inferno-flamegraph` or speedscope.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use eyre::Result;
+use tracing::span::{Attributes, Id};
+use tracing::{Subscriber, debug};
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// A tracing layer which writes one `root;child;grandchild <microseconds>` line per closed span,
+/// counting only the time spent in that span itself (not in its children).
+#[derive(Debug)]
+pub struct FoldedLayer<W> {
+    out: Mutex<W>,
+}
+
+#[derive(Debug, Default)]
+struct Timing {
+    busy: Duration,
+    children: Duration,
+    entered: Option<Instant>,
+}
+
+impl FoldedLayer<BufWriter<File>> {
+    /// Write folded stacks to the file at `path`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file cannot be created
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> FoldedLayer<W> {
+    pub const fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+        }
+    }
+}
+
+impl<S, W> Layer<S> for FoldedLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: Write + Send + 'static,
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Timing::default());
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                timing.entered = Some(Instant::now());
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                if let Some(entered) = timing.entered.take() {
+                    timing.busy += entered.elapsed();
+                }
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+
+        let Some((busy, children)) = span
+            .extensions()
+            .get::<Timing>()
+            .map(|timing| (timing.busy, timing.children))
+        else {
+            return;
+        };
+
+        if let Some(parent) = span.parent() {
+            if let Some(timing) = parent.extensions_mut().get_mut::<Timing>() {
+                timing.children += busy;
+            }
+        }
+
+        let stack = span
+            .scope()
+            .from_root()
+            .map(|span| span.name())
+            .collect::<Vec<_>>()
+            .join(";");
+        let own = busy.saturating_sub(children).as_micros();
+
+        if let Ok(mut out) = self.out.lock() {
+            if let Err(e) = writeln!(out, "{stack} {own}").and_then(|()| out.flush()) {
+                debug!("Unable to write timing: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::thread::sleep;
+
+    use tracing::info_span;
+    use tracing_subscriber::Registry;
+    use tracing_subscriber::prelude::*;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_folded_stacks() {
+        let output = Shared::default();
+        let subscriber = Registry::default().with(FoldedLayer::new(output.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _refresh = info_span!("refresh").entered();
+            {
+                let _login = info_span!("login").entered();
+                sleep(Duration::from_millis(10));
+            }
+        });
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2);
+
+        let (stack, micros) = lines[0].rsplit_once(' ').unwrap();
+        assert_eq!(stack, "refresh;login");
+        assert!(micros.parse::<u128>().unwrap() >= 10_000);
+
+        // The parent only counts its own time, not the time spent logging in
+        let (stack, micros) = lines[1].rsplit_once(' ').unwrap();
+        assert_eq!(stack, "refresh");
+        assert!(micros.parse::<u128>().unwrap() < 10_000);
+    }
+}
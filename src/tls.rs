@@ -0,0 +1,193 @@
+//! The TLS implementation used by oktaws' HTTP clients, chosen at build time.
+//!
+//! `rustls` (the default) is pure Rust, so static musl and cross-compiled ARM builds don't need OpenSSL.
+//! `native-tls` uses the platform's TLS library instead, for hosts whose trust store only it understands.
+//! The AWS SDK clients send their requests through these clients too (see [`crate::aws::http`]).
+//!
+//! Every client goes through a proxy from `HTTPS_PROXY`/`HTTP_PROXY` (skipping hosts in `NO_PROXY`),
+//! or the one in `settings.toml`'s `[network]` section. For TLS-intercepting proxies, the section (or `--ca-bundle`)
+//! can add a corporate CA bundle to the trusted roots, or `--insecure` can skip certificate verification altogether.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use eyre::{Result, eyre};
+use reqwest::{Certificate, Client, ClientBuilder, NoProxy, Proxy};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// How oktaws' HTTP clients reach Okta and AWS, from `settings.toml`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    /// The proxy for every request, instead of `HTTPS_PROXY`/`HTTP_PROXY`
+    pub proxy: Option<String>,
+    /// Hosts (comma-separated, as for `NO_PROXY`) which are reached without the proxy
+    pub no_proxy: Option<String>,
+    /// A PEM file of CA certificates to trust, as well as the usual roots
+    pub ca_bundle: Option<PathBuf>,
+    /// Skip TLS certificate verification. Only for diagnosing proxies; anything on the network can read your credentials
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+/// The network settings, with the CA bundle read
+#[derive(Debug, Default)]
+struct Installed {
+    proxy: Option<Proxy>,
+    certificates: Vec<Certificate>,
+    insecure: bool,
+}
+
+static INSTALLED: OnceLock<Installed> = OnceLock::new();
+
+impl Network {
+    /// Use these settings for every HTTP client, for the whole process. Only the first call has any effect.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the proxy is not a valid URL, or the CA bundle cannot be read
+    pub fn install(&self) -> Result<()> {
+        let proxy = self
+            .proxy
+            .as_deref()
+            .map(|proxy| {
+                debug!("Sending requests through {proxy}");
+                Proxy::all(proxy)
+                    .map(|all| {
+                        all.no_proxy(
+                            self.no_proxy
+                                .as_deref()
+                                .and_then(NoProxy::from_string)
+                                .or_else(NoProxy::from_env),
+                        )
+                    })
+                    .map_err(|e| eyre!("Invalid proxy {proxy} ({e})"))
+            })
+            .transpose()?;
+
+        let certificates = match &self.ca_bundle {
+            Some(path) => {
+                let pem = fs::read(path)
+                    .map_err(|e| eyre!("Unable to read the CA bundle {} ({e})", path.display()))?;
+                Certificate::from_pem_bundle(&pem)
+                    .map_err(|e| eyre!("Unable to parse the CA bundle {} ({e})", path.display()))?
+            }
+            None => Vec::new(),
+        };
+
+        if self.insecure {
+            warn!(
+                "TLS certificates are not being verified; anything on the network can read your credentials"
+            );
+        }
+
+        let _ = INSTALLED.set(Installed {
+            proxy,
+            certificates,
+            insecure: self.insecure,
+        });
+
+        Ok(())
+    }
+}
+
+/// `builder`, with the installed proxy, CA bundle and verification settings
+fn configure(mut builder: ClientBuilder) -> ClientBuilder {
+    let Some(installed) = INSTALLED.get() else {
+        return builder;
+    };
+
+    if let Some(proxy) = &installed.proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+    for certificate in &installed.certificates {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+
+    builder.danger_accept_invalid_certs(installed.insecure)
+}
+
+/// `builder`, with the installed proxy, CA bundle and verification settings
+#[cfg(feature = "vault")]
+fn configure_blocking(
+    mut builder: reqwest::blocking::ClientBuilder,
+) -> reqwest::blocking::ClientBuilder {
+    let Some(installed) = INSTALLED.get() else {
+        return builder;
+    };
+
+    if let Some(proxy) = &installed.proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+    for certificate in &installed.certificates {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+
+    builder.danger_accept_invalid_certs(installed.insecure)
+}
+
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("Either the `rustls` or the `native-tls` feature must be enabled");
+
+/// An HTTP client builder using the TLS implementation oktaws was built with
+#[cfg(feature = "rustls")]
+#[must_use]
+pub fn client_builder() -> ClientBuilder {
+    configure(Client::builder().use_rustls_tls())
+}
+
+/// An HTTP client builder using the TLS implementation oktaws was built with
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+#[must_use]
+pub fn client_builder() -> ClientBuilder {
+    configure(Client::builder().use_native_tls())
+}
+
+/// A blocking HTTP client builder using the TLS implementation oktaws was built with,
+/// for code which already runs off the reactor
+#[cfg(all(feature = "vault", feature = "rustls"))]
+#[must_use]
+pub fn blocking_client_builder() -> reqwest::blocking::ClientBuilder {
+    configure_blocking(reqwest::blocking::Client::builder().use_rustls_tls())
+}
+
+/// A blocking HTTP client builder using the TLS implementation oktaws was built with,
+/// for code which already runs off the reactor
+#[cfg(all(feature = "vault", feature = "native-tls", not(feature = "rustls")))]
+#[must_use]
+pub fn blocking_client_builder() -> reqwest::blocking::ClientBuilder {
+    configure_blocking(reqwest::blocking::Client::builder().use_native_tls())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_network_settings() -> Result<()> {
+        let network: Network = toml::from_str(
+            "proxy = 'http://proxy.example.com:3128'\nno_proxy = 'localhost,.internal'\n",
+        )?;
+        assert_eq!(
+            network.proxy.as_deref(),
+            Some("http://proxy.example.com:3128")
+        );
+        assert!(!network.insecure);
+
+        let missing = Network {
+            ca_bundle: Some(PathBuf::from("/nonexistent/ca.pem")),
+            ..Network::default()
+        };
+        assert!(
+            missing
+                .install()
+                .unwrap_err()
+                .to_string()
+                .starts_with("Unable to read the CA bundle /nonexistent/ca.pem")
+        );
+
+        Ok(())
+    }
+}
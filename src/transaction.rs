@@ -0,0 +1,381 @@
+//! All-or-nothing writes across several files.
+//!
+//! A refresh updates `~/.aws/credentials`, `~/.aws/config` and the section registry together.
+//! Each new file is written in full beside its target before any target is replaced,
+//! and a journal of the replacements is kept until they are all done,
+//! so a crash part way through is finished off by the next run instead of leaving the files disagreeing.
+//! The journal is locked for the whole of a transaction, and while it is recovered, so that a run never finishes off
+//! a transaction another run is still in the middle of.
+
+use crate::lock::FileLock;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument, warn};
+
+/// The journal file, in the directory given to [`commit`] and [`recover`]
+pub const JOURNAL: &str = "transaction.json";
+
+/// A file which can take part in a transaction
+pub trait Staged {
+    /// The file to be replaced
+    fn path(&self) -> &Path;
+
+    /// The new contents of the file, merged with whatever is on disk now,
+    /// or `None` if there is nothing to write.
+    /// This is called while the file is locked.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file on disk cannot be read
+    fn render(&self) -> Result<Option<String>>;
+
+    /// The new contents of the file, merged with `contents` (as another file of the transaction rendered the same path)
+    /// rather than whatever is on disk, or `None` if there is nothing to write.
+    /// By default, files cannot share their path.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `contents` cannot be merged with
+    fn render_over(&self, contents: &str) -> Result<Option<String>> {
+        let _ = contents;
+        Err(eyre!(
+            "{} cannot be written twice in one transaction",
+            self.path().display()
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct Replacement {
+    staged: PathBuf,
+    target: PathBuf,
+}
+
+fn staged_path(target: &Path) -> PathBuf {
+    let mut staged = target.as_os_str().to_owned();
+    staged.push(".oktaws-new");
+    PathBuf::from(staged)
+}
+
+/// Write the file, and make sure it has reached the disk
+fn write_synced(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// Write `contents` beside `target`, to replace it with.
+/// A target which is a symlink (e.g. into a dotfiles repository) stays one: the file it points to is replaced instead.
+fn stage(target: &Path, contents: &[u8]) -> Result<Replacement> {
+    let target = if target.exists() {
+        fs::canonicalize(target)?
+    } else {
+        target.to_path_buf()
+    };
+    let replacement = Replacement {
+        staged: staged_path(&target),
+        target,
+    };
+    write_synced(&replacement.staged, contents)?;
+
+    // Keep the permissions of the file being replaced (e.g. a 0600 credentials file)
+    if let Ok(metadata) = fs::metadata(&replacement.target) {
+        if let Err(e) = fs::set_permissions(&replacement.staged, metadata.permissions()) {
+            let _ = fs::remove_file(&replacement.staged);
+            return Err(e.into());
+        }
+    }
+
+    Ok(replacement)
+}
+
+/// Replace a single file with `contents`, so that readers (and other writers) see either the old file or the new one,
+/// never a partly written one. The caller holds the file's [`FileLock`].
+///
+/// # Errors
+///
+/// Will return `Err` if the new file cannot be written, or cannot replace the old one.
+/// The old file is left as it was.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let replacement = stage(path, contents)?;
+
+    replace(std::slice::from_ref(&replacement)).inspect_err(|_| {
+        let _ = fs::remove_file(&replacement.staged);
+    })
+}
+
+/// Replace every file with its rendered contents, or none of them.
+/// The journal, then the files, are locked (in a fixed order, so that concurrent transactions can't deadlock)
+/// for the whole transaction. Files sharing a path (e.g. credentials kept in `~/.aws/config`)
+/// are rendered one over the other, in the order given.
+///
+/// # Errors
+///
+/// Will return `Err` if a lock cannot be acquired, or if any file cannot be rendered or written.
+/// If this happens before the journal is written, none of the files have been changed.
+#[instrument(skip_all)]
+pub fn commit(journal_dir: &Path, files: &[&dyn Staged]) -> Result<()> {
+    let mut files = files.to_vec();
+    // A stable sort, so that files sharing a path stay in the order given
+    files.sort_by(|a, b| a.path().cmp(b.path()));
+    let targets = files
+        .chunk_by(|a, b| a.path() == b.path())
+        .collect::<Vec<_>>();
+
+    for target in &targets {
+        if let Some(parent) = target[0].path().parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let journal = journal_dir.join(JOURNAL);
+    fs::create_dir_all(journal_dir)?;
+    let _journal_lock = FileLock::acquire(&journal)?;
+
+    let _locks = targets
+        .iter()
+        .map(|target| FileLock::acquire(target[0].path()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut replacements = Vec::new();
+    let staged: Result<()> = targets.iter().try_for_each(|target| {
+        let mut rendered: Option<String> = None;
+        for file in *target {
+            let contents = match &rendered {
+                Some(previous) => file.render_over(previous)?,
+                None => file.render()?,
+            };
+            if contents.is_some() {
+                rendered = contents;
+            }
+        }
+        let Some(contents) = rendered else {
+            return Ok(());
+        };
+
+        replacements.push(stage(target[0].path(), contents.as_bytes())?);
+        Ok(())
+    });
+
+    let journaled =
+        staged.and_then(|()| write_synced(&journal, &serde_json::to_vec(&replacements)?));
+
+    if let Err(e) = journaled {
+        debug!("Rolling back {} staged file(s)", replacements.len());
+        for replacement in &replacements {
+            let _ = fs::remove_file(&replacement.staged);
+        }
+        return Err(e);
+    }
+
+    replace(&replacements)?;
+    fs::remove_file(&journal)?;
+
+    Ok(())
+}
+
+fn replace(replacements: &[Replacement]) -> Result<()> {
+    for replacement in replacements {
+        if replacement.staged.exists() {
+            fs::rename(&replacement.staged, &replacement.target)?;
+        }
+
+        // Make the rename itself durable
+        if let Some(Ok(dir)) = replacement.target.parent().map(File::open) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    Ok(())
+}
+
+/// Finish any transaction which was interrupted after it was journaled.
+/// The journal and then the files are locked first, as [`commit`] locks them.
+///
+/// # Errors
+///
+/// Will return `Err` if a lock cannot be acquired, the journal cannot be read, or the files cannot be replaced
+pub fn recover(journal_dir: &Path) -> Result<()> {
+    let journal = journal_dir.join(JOURNAL);
+    if !journal.exists() {
+        return Ok(());
+    }
+
+    let _journal_lock = FileLock::acquire(&journal)?;
+    // The journal was another run's, which has finished (or recovered) it while this one waited
+    if !journal.exists() {
+        return Ok(());
+    }
+
+    let mut replacements: Vec<Replacement> = serde_json::from_slice(&fs::read(&journal)?)?;
+    warn!(
+        "Finishing an interrupted update of {}",
+        replacements
+            .iter()
+            .map(|replacement| replacement.target.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    replacements.sort_by(|a, b| a.target.cmp(&b.target));
+    let _locks = replacements
+        .iter()
+        .map(|replacement| FileLock::acquire(&replacement.target))
+        .collect::<Result<Vec<_>>>()?;
+
+    replace(&replacements)?;
+    fs::remove_file(&journal).map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    struct Fake {
+        path: PathBuf,
+        contents: Option<&'static str>,
+        fail: bool,
+    }
+
+    impl Staged for Fake {
+        fn path(&self) -> &Path {
+            &self.path
+        }
+
+        fn render(&self) -> Result<Option<String>> {
+            if self.fail {
+                Err(eyre::eyre!("Unable to render {}", self.path.display()))
+            } else {
+                Ok(self.contents.map(ToString::to_string))
+            }
+        }
+    }
+
+    #[test]
+    fn all_or_nothing() -> Result<()> {
+        let dir = tempdir()?;
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        fs::write(&a, "old a")?;
+        fs::write(&b, "old b")?;
+
+        let fake = |path: &Path, contents, fail| Fake {
+            path: path.to_path_buf(),
+            contents,
+            fail,
+        };
+
+        assert!(
+            commit(
+                dir.path(),
+                &[&fake(&a, Some("new a"), false), &fake(&b, None, true)]
+            )
+            .is_err()
+        );
+        assert_eq!(fs::read_to_string(&a)?, "old a");
+        assert!(!staged_path(&a).exists());
+
+        commit(
+            dir.path(),
+            &[&fake(&a, Some("new a"), false), &fake(&b, None, false)],
+        )?;
+        assert_eq!(fs::read_to_string(&a)?, "new a");
+        assert_eq!(fs::read_to_string(&b)?, "old b");
+        assert!(!dir.path().join(JOURNAL).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn writes_atomically() -> Result<()> {
+        let dir = tempdir()?;
+        let target = dir.path().join("credentials");
+        fs::write(&target, "old")?;
+        #[cfg(unix)]
+        fs::set_permissions(&target, std::os::unix::fs::PermissionsExt::from_mode(0o640))?;
+
+        write_atomically(&target, b"new")?;
+
+        assert_eq!(fs::read_to_string(&target)?, "new");
+        assert!(!staged_path(&target).exists());
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&target)?.permissions()) & 0o777,
+            0o640
+        );
+
+        // A file which doesn't exist yet is created
+        write_atomically(&dir.path().join("config"), b"[default]")?;
+        assert_eq!(fs::read_to_string(dir.path().join("config"))?, "[default]");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_symlinks() -> Result<()> {
+        let dir = tempdir()?;
+        let dotfiles = dir.path().join("dotfiles");
+        fs::create_dir(&dotfiles)?;
+        let real = dotfiles.join("credentials");
+        fs::write(&real, "old")?;
+        let link = dir.path().join("credentials");
+        std::os::unix::fs::symlink(&real, &link)?;
+
+        write_atomically(&link, b"new")?;
+
+        assert!(fs::symlink_metadata(&link)?.file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&real)?, "new");
+        assert!(!staged_path(&real).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn recovers_interrupted_commit() -> Result<()> {
+        let dir = tempdir()?;
+        let target = dir.path().join("credentials");
+        fs::write(&target, "old")?;
+        fs::write(staged_path(&target), "new")?;
+        fs::write(
+            dir.path().join(JOURNAL),
+            serde_json::to_vec(&[Replacement {
+                staged: staged_path(&target),
+                target: target.clone(),
+            }])?,
+        )?;
+
+        // Not while the run which journaled it might still be finishing it
+        let journal_lock = FileLock::acquire(&dir.path().join(JOURNAL))?;
+        let journal_dir = dir.path().to_path_buf();
+        let recovery = std::thread::spawn(move || recover(&journal_dir));
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(fs::read_to_string(&target)?, "old");
+
+        drop(journal_lock);
+        recovery
+            .join()
+            .map_err(|_| eyre::eyre!("Recovery panicked"))??;
+
+        assert_eq!(fs::read_to_string(&target)?, "new");
+        assert!(!dir.path().join(JOURNAL).exists());
+
+        Ok(())
+    }
+}
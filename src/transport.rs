@@ -0,0 +1,101 @@
+//! How requests reach Okta and the AWS SSO portal.
+//!
+//! Requests are built with reqwest, then handed to a [`Transport`] to send. That is a reqwest client by default,
+//! but library users can supply their own (e.g. with custom TLS or proxy settings),
+//! and oktaws' own tests answer with canned responses instead of going over the network.
+
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::fmt;
+#[cfg(test)]
+use std::sync::Mutex;
+
+use futures::future::BoxFuture;
+#[cfg(test)]
+use reqwest::Method;
+use reqwest::{Request, Response};
+#[cfg(test)]
+use url::Url;
+
+/// Something which sends requests, and returns their responses
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Send `request`, returning its response
+    fn execute(&self, request: Request) -> BoxFuture<'_, reqwest::Result<Response>>;
+}
+
+impl Transport for reqwest::Client {
+    fn execute(&self, request: Request) -> BoxFuture<'_, reqwest::Result<Response>> {
+        Box::pin(Self::execute(self, request))
+    }
+}
+
+/// A transport answering with canned responses, in the order they were added, which records every request sent to it.
+/// Once the responses run out, it answers 404.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct Canned {
+    responses: Mutex<VecDeque<http::Response<String>>>,
+    requests: Mutex<Vec<(Method, Url)>>,
+}
+
+#[cfg(test)]
+impl Canned {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer the next request with `status` and `body`
+    #[must_use]
+    pub fn respond(self, status: u16, body: impl Into<String>) -> Self {
+        let mut response = http::Response::new(body.into());
+        *response.status_mut() =
+            http::StatusCode::from_u16(status).unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR);
+
+        self.respond_with(response)
+    }
+
+    /// Answer the next request with `response`, e.g. to set headers
+    #[must_use]
+    pub fn respond_with(self, response: http::Response<String>) -> Self {
+        self.responses
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push_back(response);
+
+        self
+    }
+
+    /// The method and URL of every request sent so far
+    #[must_use]
+    pub fn requests(&self) -> Vec<(Method, Url)> {
+        self.requests
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[cfg(test)]
+impl Transport for Canned {
+    fn execute(&self, request: Request) -> BoxFuture<'_, reqwest::Result<Response>> {
+        let url = request.url().clone();
+        self.requests
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push((request.method().clone(), url.clone()));
+
+        let response = self
+            .responses
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .pop_front()
+            .unwrap_or_else(|| {
+                let mut response = http::Response::new(format!("No canned response for {url}"));
+                *response.status_mut() = http::StatusCode::NOT_FOUND;
+                response
+            });
+
+        Box::pin(async move { Ok(Response::from(response)) })
+    }
+}
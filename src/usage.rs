@@ -0,0 +1,256 @@
+//! Local-only tracking of how often profiles are used, to suggest pruning the configuration.
+//!
+//! Nothing here ever leaves the machine; it is a counter file in the oktaws state directory.
+
+use crate::lock::FileLock;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+/// The usage file, in the oktaws state directory
+pub const FILE_NAME: &str = "usage.json";
+
+/// How often something was used
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Hits {
+    pub count: u64,
+    /// Seconds since the epoch when it was last used
+    pub last_used: u64,
+}
+
+impl Hits {
+    fn add(&mut self, other: Self) {
+        self.count += other.count;
+        self.last_used = self.last_used.max(other.last_used);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+struct Counts {
+    /// Hits for each profile, by organization
+    #[serde(default)]
+    profiles: BTreeMap<String, BTreeMap<String, Hits>>,
+    /// Hits for each role chained into with `--assume`
+    #[serde(default)]
+    assumed: BTreeMap<String, Hits>,
+}
+
+impl Counts {
+    fn merge(&mut self, other: &Self) {
+        for (organization, profiles) in &other.profiles {
+            let entry = self.profiles.entry(organization.clone()).or_default();
+            for (profile, hits) in profiles {
+                entry.entry(profile.clone()).or_default().add(*hits);
+            }
+        }
+        for (role_arn, hits) in &other.assumed {
+            self.assumed.entry(role_arn.clone()).or_default().add(*hits);
+        }
+    }
+}
+
+/// Profile usage, as recorded by previous runs, plus whatever this run has used
+#[derive(Debug, Default)]
+pub struct Usage {
+    path: PathBuf,
+    counts: Counts,
+    recorded: Counts,
+}
+
+/// Changes to the configuration suggested by its usage
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Suggestions {
+    /// Configured profiles which have never been used, by organization
+    pub unused: Vec<(String, String)>,
+    /// Roles often chained into with `--assume` which no profile pins as its `assume_role`
+    pub unpinned: Vec<(String, Hits)>,
+}
+
+impl Usage {
+    /// Load the usage from `path`, which need not exist yet
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file exists but cannot be read or parsed
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            counts: Self::read(path)?,
+            recorded: Counts::default(),
+        })
+    }
+
+    fn read(path: &Path) -> Result<Counts> {
+        if path.exists() {
+            Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+        } else {
+            Ok(Counts::default())
+        }
+    }
+
+    fn hit() -> Hits {
+        Hits {
+            count: 1,
+            last_used: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+
+    /// Record that credentials for `profile` were used: by `exec`, `console`, `shell` or `credential_process`.
+    /// Refreshes fetch every matching profile, whether it is wanted or not, so they are not counted.
+    pub fn record_profile(&mut self, organization: &str, profile: &str) {
+        for counts in [&mut self.counts, &mut self.recorded] {
+            counts
+                .profiles
+                .entry(organization.to_string())
+                .or_default()
+                .entry(profile.to_string())
+                .or_default()
+                .add(Self::hit());
+        }
+    }
+
+    /// Record that `role_arn` was chained into with `--assume`
+    pub fn record_assumed(&mut self, role_arn: &str) {
+        for counts in [&mut self.counts, &mut self.recorded] {
+            counts
+                .assumed
+                .entry(role_arn.to_string())
+                .or_default()
+                .add(Self::hit());
+        }
+    }
+
+    /// How often `profile` has been used
+    #[must_use]
+    pub fn profile(&self, organization: &str, profile: &str) -> Hits {
+        self.counts
+            .profiles
+            .get(organization)
+            .and_then(|profiles| profiles.get(profile))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Suggest profiles to remove, and `--assume` roles (used at least `min_uses` times) to pin.
+    /// `configured` lists each organization's profile names, with the roles they chain into.
+    #[must_use]
+    pub fn suggest<'a>(
+        &self,
+        configured: impl IntoIterator<Item = (&'a str, Vec<(&'a str, Option<&'a str>)>)>,
+        min_uses: u64,
+    ) -> Suggestions {
+        let mut suggestions = Suggestions::default();
+        let mut pinned = Vec::new();
+
+        for (organization, profiles) in configured {
+            for (profile, assume_role) in profiles {
+                if self.profile(organization, profile).count == 0 {
+                    suggestions
+                        .unused
+                        .push((organization.to_string(), profile.to_string()));
+                }
+                pinned.extend(assume_role);
+            }
+        }
+
+        suggestions.unpinned = self
+            .counts
+            .assumed
+            .iter()
+            .filter(|(role_arn, hits)| {
+                hits.count >= min_uses && !pinned.contains(&role_arn.as_str())
+            })
+            .map(|(role_arn, hits)| (role_arn.clone(), *hits))
+            .collect();
+        suggestions
+            .unpinned
+            .sort_by(|(_, a), (_, b)| b.count.cmp(&a.count));
+
+        suggestions
+    }
+
+    /// Add this run's usage to the file, merging with any changes made by other processes
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the lock cannot be acquired, or if the file cannot be read or written
+    pub fn save(&self) -> Result<()> {
+        if self.recorded == Counts::default() {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let _lock = FileLock::acquire(&self.path)?;
+
+        let mut counts = Self::read(&self.path)?;
+        counts.merge(&self.recorded);
+
+        fs::write(&self.path, serde_json::to_string_pretty(&counts)?).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    const ADMIN: &str = "arn:aws:iam::123456789012:role/Admin";
+    const AUDIT: &str = "arn:aws:iam::123456789012:role/Audit";
+
+    #[test]
+    fn suggests_pruning() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("usage.json");
+
+        let mut usage = Usage::load(&path)?;
+        usage.record_profile("mock_org", "prod");
+        usage.record_assumed(ADMIN);
+        usage.record_assumed(ADMIN);
+        usage.record_assumed(AUDIT);
+        usage.save()?;
+
+        let mut usage = Usage::load(&path)?;
+        usage.record_profile("mock_org", "prod");
+        usage.save()?;
+
+        let usage = Usage::load(&path)?;
+        assert_eq!(usage.profile("mock_org", "prod").count, 2);
+
+        let suggestions = usage.suggest(
+            [(
+                "mock_org",
+                vec![("prod", None), ("dev", None), ("audit", Some(AUDIT))],
+            )],
+            2,
+        );
+
+        assert_eq!(
+            suggestions.unused,
+            vec![
+                (String::from("mock_org"), String::from("dev")),
+                (String::from("mock_org"), String::from("audit"))
+            ]
+        );
+        assert_eq!(
+            suggestions
+                .unpinned
+                .iter()
+                .map(|(role_arn, _)| role_arn.as_str())
+                .collect::<Vec<_>>(),
+            vec![ADMIN]
+        );
+
+        Ok(())
+    }
+}
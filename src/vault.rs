@@ -0,0 +1,256 @@
+//! HashiCorp Vault, for teams which keep their secrets there.
+//!
+//! Organizations can read their Okta password from a KV (version 2) secret with `[password_vault]`,
+//! and send refreshed credentials to one with a `vault` sink. Both sign in to Vault the same way:
+//! with `VAULT_TOKEN` (the default), or with an AppRole whose secret ID comes from `VAULT_SECRET_ID`.
+//! Secrets are never kept in the organization's config.
+
+use crate::tls;
+
+use std::env::var as env_var;
+
+use eyre::{Result, eyre};
+use reqwest::StatusCode;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::debug;
+use url::Url;
+
+/// How to sign in to Vault
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Auth {
+    /// The token in `VAULT_TOKEN`
+    #[default]
+    Token,
+    /// An AppRole, with its secret ID in `VAULT_SECRET_ID`
+    AppRole {
+        role_id: String,
+        /// Where the AppRole auth method is mounted
+        #[serde(default = "default_approle_mount")]
+        mount: String,
+    },
+}
+
+fn default_approle_mount() -> String {
+    String::from("approle")
+}
+
+fn default_password_key() -> String {
+    String::from("password")
+}
+
+/// Where an organization's Okta password is kept in Vault, from its `[password_vault]` config
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PasswordSource {
+    /// Defaults to `VAULT_ADDR`
+    pub address: Option<String>,
+    #[serde(default)]
+    pub auth: Auth,
+    /// Where the KV secrets engine is mounted, e.g. `secret`
+    pub mount: String,
+    /// The secret's path under the mount
+    pub path: String,
+    /// The field of the secret holding the password
+    #[serde(default = "default_password_key")]
+    pub key: String,
+}
+
+impl PasswordSource {
+    /// Read the password from Vault. This blocks, so callers run it off the reactor.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if Vault cannot be signed in to, or the secret (or its field) does not exist
+    pub fn read(&self) -> Result<String> {
+        let connection = Connection::open(self.address.as_deref(), &self.auth)?;
+
+        connection
+            .read_field(&self.mount, &self.path, &self.key)?
+            .ok_or_else(|| {
+                eyre!(
+                    "There is no {} field in Vault at {}/{}",
+                    self.key,
+                    self.mount,
+                    self.path
+                )
+            })
+    }
+}
+
+/// A Vault server, signed in to
+pub struct Connection {
+    client: Client,
+    address: Url,
+    token: String,
+}
+
+impl Connection {
+    /// Sign in to the Vault at `address` (or `VAULT_ADDR`)
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there is no address, the auth method's secret is not set,
+    /// or Vault refuses to sign in
+    pub fn open(address: Option<&str>, auth: &Auth) -> Result<Self> {
+        let address = match address {
+            Some(address) => address.to_string(),
+            None => env_var("VAULT_ADDR")
+                .map_err(|_| eyre!("Set the Vault `address`, or VAULT_ADDR"))?,
+        };
+        let address: Url = address.parse()?;
+        let client = tls::blocking_client_builder().build()?;
+
+        let token = match auth {
+            Auth::Token => env_var("VAULT_TOKEN")
+                .map_err(|_| eyre!("VAULT_TOKEN must be set to sign in to Vault"))?,
+            Auth::AppRole { role_id, mount } => {
+                let secret_id = env_var("VAULT_SECRET_ID").map_err(|_| {
+                    eyre!("VAULT_SECRET_ID must be set to sign in to Vault with an AppRole")
+                })?;
+                debug!("Signing in to Vault with the AppRole {role_id}");
+
+                let response = client
+                    .post(address.join(&format!("v1/auth/{}/login", mount.trim_matches('/')))?)
+                    .json(&json!({ "role_id": role_id, "secret_id": secret_id }))
+                    .send()?;
+                if !response.status().is_success() {
+                    return Err(eyre!(
+                        "Vault responded {} to the AppRole sign-in",
+                        response.status()
+                    ));
+                }
+
+                response
+                    .json::<Value>()?
+                    .pointer("/auth/client_token")
+                    .and_then(Value::as_str)
+                    .map(ToString::to_string)
+                    .ok_or_else(|| eyre!("Vault did not return a token for the AppRole"))?
+            }
+        };
+
+        Ok(Self {
+            client,
+            address,
+            token,
+        })
+    }
+
+    /// The API URL for `path` (under `v1/`)
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `path` does not make a valid URL
+    pub fn url(&self, path: &str) -> Result<Url> {
+        self.address
+            .join(&format!("v1/{}", path.trim_start_matches('/')))
+            .map_err(Into::into)
+    }
+
+    #[must_use]
+    pub const fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Send a request with the connection's token
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails, or Vault responds with an error (including 404)
+    pub fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let response = request.header("X-Vault-Token", &self.token).send()?;
+
+        match response.status() {
+            status if status.is_success() => Ok(response),
+            status => Err(eyre!("Vault responded {status} to {}", response.url())),
+        }
+    }
+
+    /// Send a read (or list) request with the connection's token, returning `None` if there is nothing there
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request fails, or Vault responds with an error other than 404
+    pub fn fetch(&self, request: RequestBuilder) -> Result<Option<Response>> {
+        let response = request.header("X-Vault-Token", &self.token).send()?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response)),
+            status => Err(eyre!("Vault responded {status} to {}", response.url())),
+        }
+    }
+
+    /// A field of the latest version of a KV (version 2) secret, if the secret exists
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the secret cannot be read
+    pub fn read_field(&self, mount: &str, path: &str, key: &str) -> Result<Option<String>> {
+        let url = self.url(&format!(
+            "{}/data/{}",
+            mount.trim_matches('/'),
+            path.trim_matches('/')
+        ))?;
+        let Some(response) = self.fetch(self.client.get(url))? else {
+            return Ok(None);
+        };
+
+        Ok(field(&response.json()?, key))
+    }
+}
+
+/// A string field of a KV (version 2) read response
+fn field(secret: &Value, key: &str) -> Option<String> {
+    secret
+        .pointer("/data/data")?
+        .get(key)?
+        .as_str()
+        .map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_kv_fields() {
+        let secret = json!({
+            "data": {
+                "data": { "password": "hunter2", "attempts": 3 },
+                "metadata": { "version": 2 }
+            }
+        });
+
+        assert_eq!(field(&secret, "password").as_deref(), Some("hunter2"));
+        assert_eq!(field(&secret, "attempts"), None);
+        assert_eq!(field(&secret, "username"), None);
+    }
+
+    #[test]
+    fn token_auth_by_default() -> Result<()> {
+        let source: PasswordSource = toml::from_str(
+            r"
+            mount = 'secret'
+            path = 'okta/jane'
+            ",
+        )?;
+
+        assert_eq!(source.auth, Auth::Token);
+        assert_eq!(source.key, "password");
+
+        let auth: Auth = toml::from_str("method = 'approle'\nrole_id = 'ROLE_ID'")?;
+        assert_eq!(
+            auth,
+            Auth::AppRole {
+                role_id: String::from("ROLE_ID"),
+                mount: String::from("approle")
+            }
+        );
+
+        Ok(())
+    }
+}